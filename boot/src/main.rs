@@ -26,6 +26,27 @@ const BINARY_START: *mut u8 = BINARY_START_ADDR as *mut u8;
 /// Free space between the bootloader and the loaded binary's start address.
 const MAX_BINARY_SIZE: usize = BOOTLOADER_START_ADDR - BINARY_START_ADDR;
 
+/// Where a received image is kept until it is unpacked or copied to
+/// `BINARY_START`, well past the bootloader, and its largest size.
+const STAGING_START_ADDR: usize = 0x8000000;
+const MAX_STAGED_SIZE: usize = 0x4000000;
+const STAGING_START: *mut u8 = STAGING_START_ADDR as *mut u8;
+
+/// Moves the image received in `staged` to `BINARY_START`, decompressing
+/// it if it is gzip-compressed. Returns `OutputFull` if the image does not
+/// fit below the bootloader.
+unsafe fn install(staged: &[u8]) -> Result<(), inflate::Error> {
+    let binary = slice::from_raw_parts_mut(BINARY_START, MAX_BINARY_SIZE);
+    if inflate::is_gzip(staged) {
+        inflate::gunzip(staged, binary)?;
+    } else if staged.len() > MAX_BINARY_SIZE {
+        return Err(inflate::Error::OutputFull);
+    } else {
+        binary[..staged.len()].copy_from_slice(staged);
+    }
+    Ok(())
+}
+
 /// Branches to the address `addr` unconditionally.
 unsafe fn jump_to(addr: *mut u8) -> ! {
     llvm_asm!("br $0" : : "r"(addr as usize));
@@ -41,17 +62,21 @@ fn kmain() -> ! {
     let mut led = gpio::Gpio::new(5).into_output();
     loop {
         unsafe {
-            let new_kernel = slice::from_raw_parts_mut(BINARY_START, MAX_BINARY_SIZE);
-            match Xmodem::receive(&mut uart, new_kernel) {
-                Ok(_) => jump_to(BINARY_START),
-                Err(e) => {
-                    if e.kind() != io::ErrorKind::TimedOut {
-                        led.set();
-                        timer::spin_sleep(Duration::from_millis(75));
-                        led.clear();
-                        timer::spin_sleep(Duration::from_millis(75));
-                    }
-                }
+            // Kernels may be sent gzip-compressed to cut the transfer time,
+            // so they are staged and unpacked once complete.
+            let staging = slice::from_raw_parts_mut(STAGING_START, MAX_STAGED_SIZE);
+            let failed = match Xmodem::receive(&mut uart, &mut *staging) {
+                Ok(len) => match install(&staging[..len]) {
+                    Ok(()) => jump_to(BINARY_START),
+                    Err(_) => true,
+                },
+                Err(e) => e.kind() != io::ErrorKind::TimedOut,
+            };
+            if failed {
+                led.set();
+                timer::spin_sleep(Duration::from_millis(75));
+                led.clear();
+                timer::spin_sleep(Duration::from_millis(75));
             }
         }
     }
@@ -1,3 +1,281 @@
+//! Generates the kernel symbol table embedded in the image; see `ksyms`.
+//! Also records what the image is built from for `version`: the git
+//! revision, the build time, the enabled cargo features and the compiler.
+//!
+//! A linked kernel is needed to know where its functions are, so the kernel
+//! is linked twice (see `make build`). The first link embeds an empty table.
+//! The second is built with `KSYMS_ELF` naming the first, whose function
+//! symbols are written to the table. Only the contents of the `.ksymtab`
+//! section differ between the two, and it follows the code and data, so the
+//! addresses in the table hold for the second image as well.
+//!
+//! The table is little endian: the magic `KSYM`, the number of symbols as a
+//! `u32`, then for each symbol, sorted by address, its address (`u64`), size
+//! (`u32`) and the offset of its name (`u32`) from the start of the table.
+//! Each name is a `u16` length followed by that many bytes of UTF-8.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The ELF section type of a symbol table and the symbol type of a
+/// function.
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+
 pub fn main() {
     println!("cargo:rerun-if-changed=.cargo/layout.ld");
+    println!("cargo:rerun-if-env-changed=KSYMS_ELF");
+
+    let mut symbols = Vec::new();
+    if let Some(elf) = env::var_os("KSYMS_ELF") {
+        let elf = Path::new(&elf);
+        println!("cargo:rerun-if-changed={}", elf.display());
+        let image = fs::read(elf).unwrap_or_else(|e| panic!("{}: {}", elf.display(), e));
+        symbols = functions(&image)
+            .unwrap_or_else(|| panic!("{}: not a little-endian ELF64 image", elf.display()));
+    }
+
+    let table = encode(&mut symbols);
+    let out = env::var("OUT_DIR").unwrap();
+    fs::write(Path::new(&out).join("ksymtab.bin"), &table).unwrap();
+    let len = format!("const TABLE_LEN: usize = {};\n", table.len());
+    fs::write(Path::new(&out).join("ksymtab.rs"), len).unwrap();
+
+    build_info();
+}
+
+/// Passes the build's metadata to the kernel as the `RUSTOS_*` environment
+/// variables `version` reads.
+///
+/// The build time is when this script last ran: when the git revision, the
+/// features or the compiler changed. `SOURCE_DATE_EPOCH` replaces it for
+/// reproducible builds.
+fn build_info() {
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    if let Some(git_dir) = git(&["rev-parse", "--git-dir"]) {
+        let git_dir = Path::new(&git_dir);
+        println!("cargo:rerun-if-changed={}", git_dir.join("HEAD").display());
+        println!("cargo:rerun-if-changed={}", git_dir.join("index").display());
+        if let Some(head) = git(&["symbolic-ref", "-q", "HEAD"]) {
+            println!("cargo:rerun-if-changed={}", git_dir.join(head).display());
+        }
+    }
+
+    let revision = match git(&["rev-parse", "--short=12", "HEAD"]) {
+        Some(hash) => match git(&["status", "--porcelain", "--untracked-files=no"]) {
+            Some(ref changes) if changes.is_empty() => hash,
+            _ => hash + "-dirty",
+        },
+        None => "unknown".to_string(),
+    };
+
+    let seconds = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs());
+
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|version| version.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    let mut features: Vec<String> = env::vars()
+        .filter(|(key, _)| key.starts_with("CARGO_FEATURE_"))
+        .map(|(key, _)| key["CARGO_FEATURE_".len()..].to_lowercase().replace('_', "-"))
+        .collect();
+    features.sort();
+
+    println!("cargo:rustc-env=RUSTOS_GIT_REVISION={}", revision);
+    println!("cargo:rustc-env=RUSTOS_BUILD_TIME={}", utc_time(seconds));
+    println!("cargo:rustc-env=RUSTOS_RUSTC_VERSION={}", rustc);
+    println!("cargo:rustc-env=RUSTOS_FEATURES={}", features.join(","));
+}
+
+/// Runs git with `args` in the kernel's directory, and returns its output
+/// without the trailing newline, or `None` if it fails.
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim_end().to_string())
+}
+
+/// Formats `seconds` since the Unix epoch as an ISO 8601 UTC time.
+fn utc_time(seconds: u64) -> String {
+    let (days, secs) = ((seconds / 86400) as i64, seconds % 86400);
+    // Howard Hinnant's civil_from_days, with eras of 400 years from March.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            year, month, day, secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes([*bytes.get(at)?, *bytes.get(at + 1)?]))
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> Option<u32> {
+    let mut word = [0; 4];
+    word.copy_from_slice(bytes.get(at..at + 4)?);
+    Some(u32::from_le_bytes(word))
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> Option<u64> {
+    let mut word = [0; 8];
+    word.copy_from_slice(bytes.get(at..at + 8)?);
+    Some(u64::from_le_bytes(word))
+}
+
+/// Returns the address, size and demangled name of every function in the
+/// symbol table of the little-endian ELF64 `image`.
+fn functions(image: &[u8]) -> Option<Vec<(u64, u32, String)>> {
+    if image.get(..6)? != b"\x7fELF\x02\x01" {
+        return None;
+    }
+    let shoff = u64_at(image, 0x28)? as usize;
+    let shentsize = u16_at(image, 0x3a)? as usize;
+    let shnum = u16_at(image, 0x3c)? as usize;
+    let section = |index: usize| {
+        let header = shoff + index * shentsize;
+        let kind = u32_at(image, header + 0x04)?;
+        let offset = u64_at(image, header + 0x18)? as usize;
+        let size = u64_at(image, header + 0x20)? as usize;
+        let link = u32_at(image, header + 0x28)? as usize;
+        Some((kind, image.get(offset..offset + size)?, link))
+    };
+
+    let mut functions = Vec::new();
+    for index in 0..shnum {
+        let (kind, symtab, link) = section(index)?;
+        if kind != SHT_SYMTAB {
+            continue;
+        }
+        let (_, strtab, _) = section(link)?;
+        for sym in symtab.chunks_exact(24) {
+            let name = u32_at(sym, 0)? as usize;
+            let (info, value, size) = (sym[4], u64_at(sym, 8)?, u64_at(sym, 16)?);
+            if info & 0xf != STT_FUNC || value == 0 {
+                continue;
+            }
+            let name = strtab.get(name..)?;
+            let name = &name[..name.iter().position(|&b| b == 0)?];
+            let name = String::from_utf8_lossy(name);
+            functions.push((value, size as u32, demangle(&name)));
+        }
+    }
+    Some(functions)
+}
+
+/// Demangles a Rust symbol in the legacy scheme, e.g.
+/// `_ZN6kernel5shell5shell17h0123456789abcdefE` to `kernel::shell::shell`.
+/// Other names are returned as they are.
+fn demangle(name: &str) -> String {
+    if !name.starts_with("_ZN") {
+        return name.to_string();
+    }
+    let mut rest = &name[3..];
+    let mut path: Vec<String> = Vec::new();
+    while !rest.starts_with('E') {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let len: usize = match rest[..digits].parse() {
+            Ok(len) if digits + len <= rest.len() => len,
+            _ => return name.to_string(),
+        };
+        let part = &rest[digits..digits + len];
+        rest = &rest[digits + len..];
+        // The last component is a hash of the symbol.
+        let is_hash = rest == "E"
+            && part.len() == 17
+            && part.starts_with('h')
+            && part[1..].bytes().all(|b| b.is_ascii_hexdigit());
+        if !is_hash {
+            path.push(unescape(part));
+        }
+    }
+    path.join("::")
+}
+
+/// Replaces the escapes the legacy scheme uses for characters that are not
+/// allowed in symbols.
+fn unescape(part: &str) -> String {
+    let mut rest = if part.starts_with("_$") { &part[1..] } else { part };
+    let mut out = String::new();
+    while !rest.is_empty() {
+        if rest.starts_with("..") {
+            out.push_str("::");
+            rest = &rest[2..];
+        } else if rest.starts_with('$') {
+            let end = match rest[1..].find('$') {
+                Some(end) => end + 1,
+                None => break,
+            };
+            let escape = &rest[1..end];
+            match escape {
+                "SP" => out.push('@'),
+                "BP" => out.push('*'),
+                "RF" => out.push('&'),
+                "LT" => out.push('<'),
+                "GT" => out.push('>'),
+                "LP" => out.push('('),
+                "RP" => out.push(')'),
+                "C" => out.push(','),
+                _ => {
+                    let unicode = Some(escape)
+                        .filter(|escape| escape.starts_with('u'))
+                        .and_then(|escape| u32::from_str_radix(&escape[1..], 16).ok())
+                        .and_then(std::char::from_u32);
+                    match unicode {
+                        Some(c) => out.push(c),
+                        None => out.push_str(&rest[..=end]),
+                    }
+                }
+            }
+            rest = &rest[end + 1..];
+        } else {
+            let c = rest.chars().next().unwrap();
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    out
+}
+
+/// Encodes `symbols` as a table, sorting them by address and keeping the
+/// first of those that share one.
+fn encode(symbols: &mut Vec<(u64, u32, String)>) -> Vec<u8> {
+    symbols.sort_by_key(|&(addr, _, _)| addr);
+    symbols.dedup_by_key(|&mut (addr, _, _)| addr);
+
+    let mut entries = Vec::new();
+    let mut names = Vec::new();
+    let names_start = 8 + symbols.len() * 16;
+    for (addr, size, name) in symbols.iter() {
+        let name = &name.as_bytes()[..name.len().min(u16::max_value() as usize)];
+        entries.extend_from_slice(&addr.to_le_bytes());
+        entries.extend_from_slice(&size.to_le_bytes());
+        entries.extend_from_slice(&((names_start + names.len()) as u32).to_le_bytes());
+        names.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        names.extend_from_slice(name);
+    }
+
+    let mut table = b"KSYM".to_vec();
+    table.extend_from_slice(&(symbols.len() as u32).to_le_bytes());
+    table.extend_from_slice(&entries);
+    table.extend_from_slice(&names);
+    table
 }
@@ -4,6 +4,8 @@ mod util;
 mod bin;
 mod bump;
 
+pub mod track;
+
 type AllocatorImpl = bin::Allocator;
 
 #[cfg(test)]
@@ -12,9 +14,11 @@ mod tests;
 use core::alloc::{GlobalAlloc, Layout};
 use core::fmt;
 use core::convert::TryInto;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::mutex::Mutex;
 use pi::atags::Atags;
+use pi::rng::Rng;
 use crate::allocator::util::{align_up, align_down};
 
 /// `LocalAlloc` is an analogous trait to the standard library's `GlobalAlloc`,
@@ -24,6 +28,14 @@ pub trait LocalAlloc {
     unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout);
 }
 
+/// The heap starts a random number of pages, up to this many bytes, past the
+/// kernel image, so that the addresses of kernel objects differ from boot to
+/// boot.
+const MAX_BASE_OFFSET: usize = 16 * 1024 * 1024;
+
+/// The offset the heap was placed at by `initialize()`.
+static BASE_OFFSET: AtomicUsize = AtomicUsize::new(0);
+
 /// Thread-safe (locking) wrapper around a particular memory allocator.
 pub struct Allocator(Mutex<Option<AllocatorImpl>>);
 
@@ -36,7 +48,9 @@ impl Allocator {
         Allocator(Mutex::new(None))
     }
 
-    /// Initializes the memory allocator.
+    /// Initializes the memory allocator. The start of the heap is moved a
+    /// random number of pages past the start of free memory, drawn from the
+    /// hardware random number generator.
     /// The caller should assure that the method is invoked only once during the
     /// kernel initialization.
     ///
@@ -45,20 +59,53 @@ impl Allocator {
     /// Panics if the system's memory map could not be retrieved.
     pub unsafe fn initialize(&self) {
         let (start, end) = memory_map().expect("failed to find memory map");
-        *self.0.lock() = Some(AllocatorImpl::new(start, end));
+        let page_size = 1 << 12;
+        let pages = MAX_BASE_OFFSET.min((end - start) / 2) / page_size;
+        let offset = (Rng::new().next_u32() as usize % (pages + 1)) * page_size;
+        BASE_OFFSET.store(offset, Ordering::Relaxed);
+        *self.0.lock() = Some(AllocatorImpl::new(start + offset, end));
+    }
+
+    /// Returns how far past the start of free memory the heap begins.
+    pub fn base_offset(&self) -> usize {
+        BASE_OFFSET.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of bytes of the heap not handed out, or 0 before
+    /// `initialize()`.
+    pub fn free_bytes(&self) -> usize {
+        self.0.lock().as_ref().map(|alloc| alloc.free()).unwrap_or(0)
+    }
+
+    /// Returns `true` if `initialize()` has been called and heap allocations
+    /// can be made.
+    pub fn is_initialized(&self) -> bool {
+        self.0.lock().is_some()
     }
 }
 
 unsafe impl GlobalAlloc for Allocator {
+    #[inline(never)]
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        self.0
+        #[cfg(feature = "heap-track")]
+        let caller = track::caller_address();
+        let ptr = self.0
             .lock()
             .as_mut()
             .expect("allocator uninitialized")
-            .alloc(layout)
+            .alloc(layout);
+        #[cfg(feature = "heap-track")]
+        {
+            if !ptr.is_null() {
+                track::TRACKER.lock().record(ptr as usize, layout, caller);
+            }
+        }
+        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "heap-track")]
+        track::TRACKER.lock().forget(ptr as usize);
         self.0
             .lock()
             .as_mut()
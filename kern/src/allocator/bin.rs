@@ -16,6 +16,8 @@ use crate::allocator::LocalAlloc;
 
 pub struct Allocator {
     bins: [LinkedList; 30],
+    /// The bytes not handed out, counting whole blocks.
+    free: usize,
 }
 
 fn absorb_memory(allocator: &mut Allocator, start: usize, end: usize) {
@@ -42,48 +44,39 @@ impl Allocator {
     pub fn new(start: usize, end: usize) -> Allocator {
         let mut alloc = Allocator {
             bins: [LinkedList::new(); 30],
+            free: end - start,
         };
         absorb_memory(&mut alloc, start, end);
         return alloc;
     }
+
+    /// Returns the number of bytes not handed out.
+    pub fn free(&self) -> usize {
+        self.free
+    }
 }
 
-impl LocalAlloc for Allocator {
-    /// Allocates memory. Returns a pointer meeting the size and alignment
-    /// properties of `layout.size()` and `layout.align()`.
-    ///
-    /// If this method returns an `Ok(addr)`, `addr` will be non-null address
-    /// pointing to a block of storage suitable for holding an instance of
-    /// `layout`. In particular, the block will be at least `layout.size()`
-    /// bytes large and will be aligned to `layout.align()`. The returned block
-    /// of storage may or may not have its contents initialized or zeroed.
-    ///
-    /// # Safety
-    ///
-    /// The _caller_ must ensure that `layout.size() > 0` and that
-    /// `layout.align()` is a power of two. Parameters not meeting these
-    /// conditions may result in undefined behavior.
-    ///
-    /// # Errors
-    ///
-    /// Returning null pointer (`core::ptr::null_mut`)
-    /// indicates that either memory is exhausted
-    /// or `layout` does not meet this allocator's
-    /// size or alignment constraints.
-    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
-        let mut bin = 0;
-        let mut block_size = 8;
-        let target_size = if layout.size().next_power_of_two() > layout.align() {
-            if layout.size().next_power_of_two() > 8 {
-                layout.size().next_power_of_two()
-            } else {
-                8
-            }
-        } else if layout.align() > 8 {
-            layout.align()
+/// Returns the size of the block that holds an allocation of `layout`.
+fn block_size(layout: Layout) -> usize {
+    if layout.size().next_power_of_two() > layout.align() {
+        if layout.size().next_power_of_two() > 8 {
+            layout.size().next_power_of_two()
         } else {
             8
-        };
+        }
+    } else if layout.align() > 8 {
+        layout.align()
+    } else {
+        8
+    }
+}
+
+impl Allocator {
+    /// Allocates a block of memory for `layout` from the bins.
+    unsafe fn alloc_block(&mut self, layout: Layout) -> *mut u8 {
+        let mut bin = 0;
+        let target_size = block_size(layout);
+        let mut block_size = 8;
         let target_align = if layout.align() > 8 {
             layout.align()
         } else {
@@ -98,6 +91,7 @@ impl LocalAlloc for Allocator {
                     if start == start_of_alloc {
                         node.pop();
                         absorb_memory(self, start_of_alloc + target_size, start + block_size);
+                        self.free -= target_size;
                         return start_of_alloc as *mut u8;
                     }
                     good_node = Some(node);
@@ -109,6 +103,7 @@ impl LocalAlloc for Allocator {
                 n.pop();
                 absorb_memory(self, start, start_of_alloc);
                 absorb_memory(self, start_of_alloc + target_size, start + block_size);
+                self.free -= target_size;
                 return start_of_alloc as *mut u8;
             }
             bin += 1;
@@ -117,6 +112,43 @@ impl LocalAlloc for Allocator {
         core::ptr::null_mut()
     }
 
+    /// Returns the block of memory for `layout` at `ptr` to the bins.
+    unsafe fn dealloc_block(&mut self, ptr: *mut u8, layout: Layout) {
+        let target_size = block_size(layout);
+        self.free += target_size;
+        absorb_memory(self, ptr as usize, ptr as usize + target_size);
+    }
+}
+
+impl LocalAlloc for Allocator {
+    /// Allocates memory. Returns a pointer meeting the size and alignment
+    /// properties of `layout.size()` and `layout.align()`.
+    ///
+    /// If this method returns an `Ok(addr)`, `addr` will be non-null address
+    /// pointing to a block of storage suitable for holding an instance of
+    /// `layout`. In particular, the block will be at least `layout.size()`
+    /// bytes large and will be aligned to `layout.align()`. The returned block
+    /// of storage may or may not have its contents initialized or zeroed.
+    ///
+    /// # Safety
+    ///
+    /// The _caller_ must ensure that `layout.size() > 0` and that
+    /// `layout.align()` is a power of two. Parameters not meeting these
+    /// conditions may result in undefined behavior.
+    ///
+    /// # Errors
+    ///
+    /// Returning null pointer (`core::ptr::null_mut`)
+    /// indicates that either memory is exhausted
+    /// or `layout` does not meet this allocator's
+    /// size or alignment constraints.
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        #[cfg(feature = "heap-debug")]
+        return debug::alloc(self, layout);
+        #[cfg(not(feature = "heap-debug"))]
+        return self.alloc_block(layout);
+    }
+
     /// Deallocates the memory referenced by `ptr`.
     ///
     /// # Safety
@@ -131,18 +163,86 @@ impl LocalAlloc for Allocator {
     /// Parameters not meeting these conditions may result in undefined
     /// behavior.
     unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
-        let target_size = if layout.size().next_power_of_two() > layout.align() {
-            if layout.size().next_power_of_two() > 8 {
-                layout.size().next_power_of_two()
-            } else {
-                8
-            }
-        } else if layout.align() > 8 {
-            layout.align()
-        } else {
-            8
-        };
-        absorb_memory(self, ptr as usize, ptr as usize + target_size);
+        #[cfg(feature = "heap-debug")]
+        debug::dealloc(self, ptr, layout);
+        #[cfg(not(feature = "heap-debug"))]
+        self.dealloc_block(ptr, layout);
+    }
+}
+
+/// Heap debugging support for the bin allocator.
+///
+/// Every allocation is preceded by a header holding the requested size and a
+/// canary. On deallocation the canary and size are checked, the block is
+/// poisoned with `POISON`, and the canary is replaced with `FREED` so that a
+/// second free of the same pointer is detected.
+#[cfg(feature = "heap-debug")]
+mod debug {
+    use core::alloc::Layout;
+    use core::cmp::max;
+
+    use super::Allocator;
+
+    /// The byte pattern written over freed memory.
+    pub const POISON: u8 = 0xDE;
+
+    /// Canary stored ahead of a live allocation.
+    const LIVE: usize = 0xA110_CA7E_D0CA_FE00;
+
+    /// Canary stored ahead of a freed allocation.
+    const FREED: usize = 0xF4EE_D0DE_ADBE_EF00;
+
+    /// The size of the header preceding a block with alignment `align`. The
+    /// header keeps the returned pointer aligned to `align`.
+    fn header_size(align: usize) -> usize {
+        max(2 * core::mem::size_of::<usize>(), align)
+    }
+
+    /// Returns the layout of the underlying block backing `layout`.
+    fn block_layout(layout: Layout) -> Layout {
+        let size = layout.size() + header_size(layout.align());
+        unsafe { Layout::from_size_align_unchecked(size, layout.align()) }
+    }
+
+    /// Returns pointers to the (size, canary) words of the header of `ptr`.
+    /// The canary is the word immediately before `ptr` so that it is not
+    /// overwritten by the free list link stored at the start of a free block.
+    unsafe fn header(ptr: *mut u8) -> (*mut usize, *mut usize) {
+        let canary = (ptr as *mut usize).sub(1);
+        (canary.sub(1), canary)
+    }
+
+    pub unsafe fn alloc(allocator: &mut Allocator, layout: Layout) -> *mut u8 {
+        let block = allocator.alloc_block(block_layout(layout));
+        if block.is_null() {
+            return block;
+        }
+        let ptr = block.add(header_size(layout.align()));
+        let (size, canary) = header(ptr);
+        *size = layout.size();
+        *canary = LIVE;
+        ptr
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `ptr` has already been freed, if its canary was overwritten,
+    /// or if `layout.size()` does not match the size it was allocated with.
+    pub unsafe fn dealloc(allocator: &mut Allocator, ptr: *mut u8, layout: Layout) {
+        let (size, canary) = header(ptr);
+        match *canary {
+            LIVE => {}
+            FREED => panic!("double free of {:p}", ptr),
+            other => panic!("heap corruption at {:p}: canary is {:#x}", ptr, other),
+        }
+        if *size != layout.size() {
+            panic!("dealloc of {:p} with size {} but it was allocated with size {}",
+                ptr, layout.size(), *size);
+        }
+        let block = ptr.sub(header_size(layout.align()));
+        block.write_bytes(POISON, block_layout(layout).size());
+        *canary = FREED;
+        allocator.dealloc_block(block, block_layout(layout));
     }
 }
 
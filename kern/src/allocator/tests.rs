@@ -289,6 +289,120 @@ mod allocator {
             }
         }
     });
+
+    #[cfg(not(feature = "heap-debug"))]
+    test_allocators!(@bin, bin_counts_free, 65536, |(start, end, mut a)| {
+        assert_eq!(a.free(), end - start);
+        let ptr = a.alloc(layout!(3000, 64));
+        assert!(!ptr.is_null());
+        assert_eq!(a.free(), end - start - 4096);
+        a.dealloc(ptr, layout!(3000, 64));
+        assert_eq!(a.free(), end - start);
+    });
+}
+
+#[cfg(feature = "heap-debug")]
+mod heap_debug {
+    extern crate alloc;
+    use alloc::raw_vec::RawVec;
+
+    use core::alloc::Layout;
+
+    use crate::allocator::{bin, LocalAlloc};
+
+    fn allocator(size: usize) -> (RawVec<u8>, bin::Allocator) {
+        let mem: RawVec<u8> = RawVec::with_capacity(size);
+        let start = mem.ptr() as usize;
+        let allocator = bin::Allocator::new(start, start + size);
+        (mem, allocator)
+    }
+
+    #[test]
+    fn poisons_freed_memory() {
+        let (_mem, mut a) = allocator(65536);
+        let layout = Layout::from_size_align(64, 16).unwrap();
+        unsafe {
+            let ptr = a.alloc(layout);
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % 16, 0);
+            ptr.write_bytes(0xAF, 64);
+            a.dealloc(ptr, layout);
+            for i in 0..64 {
+                assert_eq!(*ptr.add(i), 0xDE, "byte {} of freed block not poisoned", i);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "double free")]
+    fn detects_double_free() {
+        let (_mem, mut a) = allocator(65536);
+        let layout = Layout::from_size_align(64, 16).unwrap();
+        unsafe {
+            let ptr = a.alloc(layout);
+            a.dealloc(ptr, layout);
+            a.dealloc(ptr, layout);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "allocated with size")]
+    fn detects_size_mismatch() {
+        let (_mem, mut a) = allocator(65536);
+        unsafe {
+            let ptr = a.alloc(Layout::from_size_align(64, 16).unwrap());
+            a.dealloc(ptr, Layout::from_size_align(32, 16).unwrap());
+        }
+    }
+}
+
+mod track {
+    use core::alloc::Layout;
+
+    use crate::allocator::track::Tracker;
+
+    fn layout(size: usize) -> Layout {
+        Layout::from_size_align(size, 8).unwrap()
+    }
+
+    #[test]
+    fn records_and_forgets() {
+        let mut tracker = Tracker::new();
+        tracker.record(0x1000, layout(16), 0xaaaa);
+        tracker.record(0x2000, layout(32), 0xbbbb);
+        assert_eq!(tracker.live().count(), 2);
+
+        tracker.forget(0x1000);
+        let live: Vec<usize> = tracker.live().map(|a| a.addr).collect();
+        assert_eq!(live, vec![0x2000]);
+
+        tracker.forget(0x3000);
+        assert_eq!(tracker.live().count(), 1);
+    }
+
+    #[test]
+    fn leaks_are_allocations_since_mark() {
+        let mut tracker = Tracker::new();
+        tracker.record(0x1000, layout(16), 0);
+        assert_eq!(tracker.leaks().count(), 0);
+
+        assert_eq!(tracker.mark(), 1);
+        tracker.record(0x2000, layout(16), 0);
+        tracker.record(0x3000, layout(16), 0);
+        tracker.forget(0x2000);
+
+        let leaks: Vec<usize> = tracker.leaks().map(|a| a.addr).collect();
+        assert_eq!(leaks, vec![0x3000]);
+    }
+
+    #[test]
+    fn counts_dropped_records() {
+        let mut tracker = Tracker::new();
+        for i in 0..crate::allocator::track::MAX_TRACKED + 3 {
+            tracker.record(i * 16, layout(16), 0);
+        }
+        assert_eq!(tracker.dropped(), 3);
+    }
 }
 
 mod linked_list {
@@ -0,0 +1,115 @@
+use core::alloc::Layout;
+
+use crate::mutex::Mutex;
+
+/// The maximum number of live allocations that can be tracked at once.
+/// Allocations made while the table is full are counted but not recorded.
+pub const MAX_TRACKED: usize = 1024;
+
+/// A live heap allocation.
+#[derive(Copy, Clone, Debug)]
+pub struct Allocation {
+    /// The address returned to the caller.
+    pub addr: usize,
+    /// The requested size in bytes.
+    pub size: usize,
+    /// The requested alignment in bytes.
+    pub align: usize,
+    /// The return address of the call into the allocator.
+    pub caller: usize,
+    /// The epoch during which the allocation was made.
+    pub epoch: u64,
+}
+
+/// A side table of live heap allocations.
+pub struct Tracker {
+    entries: [Option<Allocation>; MAX_TRACKED],
+    epoch: u64,
+    dropped: usize,
+}
+
+impl Tracker {
+    /// Returns a new, empty `Tracker` in epoch 0.
+    pub const fn new() -> Tracker {
+        Tracker {
+            entries: [None; MAX_TRACKED],
+            epoch: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Records an allocation of `layout` at `addr` made from `caller`.
+    pub fn record(&mut self, addr: usize, layout: Layout, caller: usize) {
+        let epoch = self.epoch;
+        match self.entries.iter_mut().find(|e| e.is_none()) {
+            Some(slot) => {
+                *slot = Some(Allocation {
+                    addr,
+                    size: layout.size(),
+                    align: layout.align(),
+                    caller,
+                    epoch,
+                })
+            }
+            None => self.dropped += 1,
+        }
+    }
+
+    /// Forgets the allocation at `addr`, if it is being tracked.
+    pub fn forget(&mut self, addr: usize) {
+        for entry in self.entries.iter_mut() {
+            if let Some(a) = entry {
+                if a.addr == addr {
+                    *entry = None;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Starts a new epoch and returns it. Allocations made from now on that
+    /// are still live at the next report are flagged as leaks.
+    pub fn mark(&mut self) -> u64 {
+        self.epoch += 1;
+        self.epoch
+    }
+
+    /// Returns the current epoch.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Returns the number of allocations that could not be recorded because
+    /// the table was full.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Returns an iterator over all tracked live allocations.
+    pub fn live(&self) -> impl Iterator<Item = &Allocation> {
+        self.entries.iter().filter_map(|e| e.as_ref())
+    }
+
+    /// Returns an iterator over live allocations made since the last call to
+    /// `mark()`. Returns an empty iterator if `mark()` was never called.
+    pub fn leaks(&self) -> impl Iterator<Item = &Allocation> {
+        let epoch = self.epoch;
+        self.live().filter(move |a| epoch > 0 && a.epoch == epoch)
+    }
+}
+
+/// Global allocation tracker. Only populated when the kernel is built with
+/// the `heap-track` feature.
+pub static TRACKER: Mutex<Tracker> = Mutex::new(Tracker::new());
+
+/// Returns the link register of the caller, which is the return address of
+/// the call that the caller is servicing.
+#[cfg(feature = "heap-track")]
+#[inline(always)]
+pub fn caller_address() -> usize {
+    let lr: usize;
+    unsafe {
+        llvm_asm!("mov $0, x30" : "=r"(lr) ::: "volatile");
+    }
+    lr
+}
@@ -0,0 +1,233 @@
+use alloc::collections::VecDeque;
+use core::mem::size_of;
+
+use aarch64::clean_dcache_range;
+use pi::dma::{self, dreq, ti, Channel, ControlBlock};
+use pi::interrupt::{Controller, Interrupt};
+use pi::pcm::{self, Pcm};
+
+use crate::mutex::Mutex;
+use crate::traps::irq::IrqHandler;
+use crate::traps::TrapFrame;
+use crate::IRQ;
+
+/// The DMA channel feeding the PCM transmit FIFO. Its completion interrupt is
+/// `Interrupt::Dma0`.
+const DMA_CHANNEL: u8 = 0;
+
+/// The number of stereo frames in each half of the double buffer.
+const BUFFER_FRAMES: usize = 1024;
+
+/// Error type for audio playback failures.
+#[derive(Debug)]
+pub enum Error {
+    /// Audio output was not initialized, e.g. because it is turned off in
+    /// the configuration.
+    Unavailable,
+    /// Samples at a different rate are still playing.
+    Busy,
+    /// The sample rate is not supported by the PCM clock.
+    UnsupportedRate(u32),
+}
+
+impl From<pcm::Error> for Error {
+    fn from(error: pcm::Error) -> Error {
+        match error {
+            pcm::Error::UnsupportedRate(rate) => Error::UnsupportedRate(rate),
+        }
+    }
+}
+
+/// Memory read by the DMA engine: two control blocks looping over the two
+/// halves of the double buffer.
+#[repr(C, align(64))]
+struct DmaMemory {
+    blocks: [ControlBlock; 2],
+    buffers: [[u32; BUFFER_FRAMES]; 2],
+}
+
+static mut DMA_MEMORY: DmaMemory = DmaMemory {
+    blocks: [ControlBlock::new(), ControlBlock::new()],
+    buffers: [[0; BUFFER_FRAMES]; 2],
+};
+
+struct Player {
+    pcm: Pcm,
+    dma: Channel,
+    /// Frames waiting to be copied into the double buffer.
+    queue: VecDeque<u32>,
+    playing: bool,
+    /// The half of the double buffer to refill on the next DMA interrupt.
+    next_fill: usize,
+    /// The number of consecutive DMA interrupts that found `queue` empty.
+    idle: usize,
+}
+
+impl Player {
+    /// Copies queued frames into buffer `half`, padding with silence, and
+    /// cleans it from the data cache.
+    fn fill(&mut self, half: usize) {
+        unsafe {
+            let buffer = &mut DMA_MEMORY.buffers[half];
+            for frame in buffer.iter_mut() {
+                *frame = self.queue.pop_front().unwrap_or(0);
+            }
+            clean_dcache_range(buffer.as_ptr() as usize, size_of::<[u32; BUFFER_FRAMES]>());
+        }
+    }
+
+    fn start(&mut self) {
+        self.fill(0);
+        self.fill(1);
+        let fifo = self.pcm.fifo_bus_address();
+        unsafe {
+            let memory = &mut DMA_MEMORY;
+            for half in 0..2 {
+                let mut block = ControlBlock::transfer(
+                    ti::INTEN | ti::WAIT_RESP | ti::SRC_INC | ti::DEST_DREQ | ti::permap(dreq::PCM_TX),
+                    dma::bus_address(memory.buffers[half].as_ptr()),
+                    fifo,
+                    (BUFFER_FRAMES * size_of::<u32>()) as u32,
+                );
+                block.next = dma::bus_address(&memory.blocks[1 - half]);
+                memory.blocks[half] = block;
+            }
+            clean_dcache_range(memory.blocks.as_ptr() as usize, size_of::<[ControlBlock; 2]>());
+            self.dma.reset();
+            self.dma.start(&memory.blocks[0]);
+        }
+        self.pcm.start_dma();
+        self.playing = true;
+        self.next_fill = 0;
+        self.idle = 0;
+    }
+
+    fn stop(&mut self) {
+        self.pcm.stop();
+        self.dma.stop();
+        self.playing = false;
+    }
+
+    /// Handles a DMA completion: refills the half that just finished playing,
+    /// and stops once every queued frame has been played.
+    fn service(&mut self) {
+        if !self.dma.take_interrupt() || !self.playing {
+            return;
+        }
+        if self.queue.is_empty() {
+            self.idle += 1;
+            // The half finishing now was the last one filled with samples.
+            if self.idle == 2 {
+                return self.stop();
+            }
+        } else {
+            self.idle = 0;
+        }
+        let half = self.next_fill;
+        self.fill(half);
+        self.next_fill = 1 - half;
+    }
+}
+
+/// The kernel's audio output over the PCM/I2S interface.
+pub struct Audio(Mutex<Option<Player>>);
+
+impl Audio {
+    /// Returns an uninitialized `Audio`.
+    pub const fn uninitialized() -> Audio {
+        Audio(Mutex::new(None))
+    }
+
+    /// Initializes the PCM interface and registers the DMA interrupt handler.
+    pub fn initialize(&self) {
+        *self.0.lock() = Some(Player {
+            pcm: Pcm::new(),
+            dma: Channel::new(DMA_CHANNEL),
+            queue: VecDeque::new(),
+            playing: false,
+            next_fill: 0,
+            idle: 0,
+        });
+        IRQ.replace(Interrupt::Dma0, IrqHandler::new(service, 0));
+        Controller::new().enable(Interrupt::Dma0);
+    }
+
+    fn critical<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut Player) -> R,
+    {
+        let mut guard = self.0.lock();
+        f(guard.as_mut().expect("audio uninitialized"))
+    }
+
+    /// Queues the mono 16-bit `samples` for playback at `rate` samples per
+    /// second and starts playback if it is not already running. Returns
+    /// without waiting for playback to finish; see `wait()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Unavailable` if audio was not initialized,
+    /// `Error::Busy` if samples at a different rate are still playing and
+    /// `Error::UnsupportedRate` if `rate` cannot be produced.
+    pub fn play_pcm(&self, samples: &[i16], rate: u32) -> Result<(), Error> {
+        if self.0.lock().is_none() {
+            return Err(Error::Unavailable);
+        }
+        self.critical(|player| {
+            if player.playing && player.pcm.rate() != rate {
+                return Err(Error::Busy);
+            }
+            player.queue.extend(samples.iter().map(|&s| {
+                let s = s as u16 as u32;
+                s | (s << 16)
+            }));
+            if !player.playing {
+                if player.pcm.rate() != rate {
+                    player.pcm.configure(rate)?;
+                }
+                player.start();
+            }
+            Ok(())
+        })
+    }
+
+    /// Returns `true` while samples are playing.
+    pub fn is_playing(&self) -> bool {
+        self.critical(|player| player.playing)
+    }
+
+    /// Blocks until all queued samples have played. Polls the DMA channel
+    /// directly, so it also works while IRQs are masked.
+    pub fn wait(&self) {
+        while self.critical(|player| {
+            player.service();
+            player.playing
+        }) {}
+    }
+
+    /// Stops playback and discards queued samples.
+    pub fn stop(&self) {
+        self.critical(|player| {
+            player.queue.clear();
+            if player.playing {
+                player.stop();
+            }
+        })
+    }
+
+    /// Services a DMA interrupt.
+    fn service(&self) {
+        self.critical(|player| player.service())
+    }
+}
+
+/// The DMA interrupt handler.
+fn service(_: usize, _: &mut TrapFrame) {
+    crate::AUDIO.service()
+}
+
+/// Plays the mono 16-bit `samples` at `rate` samples per second. See
+/// `Audio::play_pcm()`.
+pub fn play_pcm(samples: &[i16], rate: u32) -> Result<(), Error> {
+    crate::AUDIO.play_pcm(samples, rate)
+}
@@ -0,0 +1,84 @@
+use alloc::string::String;
+
+use pi::atags::Atags;
+
+use crate::logger::{debug, warn};
+use crate::{config, console, fs, logger, selftest, video};
+
+/// A kernel parameter, set on the command line with `<name>=<value>`. An
+/// option without `=` is passed an empty value.
+pub struct Param {
+    /// The parameter's name. A name ending in `.` matches every option
+    /// starting with it, e.g. `feature.` matches `feature.audio`.
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Applies the option `name=value`, or returns why it is invalid.
+    pub set: fn(name: &str, value: &str) -> Result<(), String>,
+}
+
+impl Param {
+    fn matches(&self, name: &str) -> bool {
+        if self.name.ends_with('.') {
+            name.starts_with(self.name) && name.len() > self.name.len()
+        } else {
+            name == self.name
+        }
+    }
+}
+
+/// The parameters of every subsystem.
+static REGISTRY: &[&[Param]] = &[
+    console::PARAMS,
+    logger::PARAMS,
+    config::PARAMS,
+    fs::PARAMS,
+    video::PARAMS,
+    selftest::PARAMS,
+];
+
+/// Returns all registered parameters.
+pub fn params() -> impl Iterator<Item = &'static Param> {
+    REGISTRY.iter().flat_map(|params| params.iter())
+}
+
+/// Returns the kernel command line passed by the firmware, if any.
+pub fn get() -> Option<&'static str> {
+    Atags::get().filter_map(|atag| atag.cmd()).next()
+}
+
+/// Returns the `(name, value)` pairs of the options on the command line, in
+/// order.
+pub fn options() -> impl Iterator<Item = (&'static str, &'static str)> {
+    get()
+        .into_iter()
+        .flat_map(|cmdline| cmdline.split_whitespace())
+        .map(|option| {
+            let mut parts = option.splitn(2, '=');
+            (parts.next().unwrap_or(""), parts.next().unwrap_or(""))
+        })
+}
+
+/// Returns the value of the last option named `name` on the command line.
+/// Does not allocate, so it can be used before the heap is initialized.
+pub fn value(name: &str) -> Option<&'static str> {
+    options().filter(|&(option, _)| option == name).map(|(_, value)| value).last()
+}
+
+/// Applies every option on the command line to its registered parameter,
+/// overriding the settings of `config::CONFIG_FILE`.
+///
+/// Unknown options are logged as warnings, except those whose names contain
+/// a `.`: the firmware passes parameters for Linux modules in that form.
+pub fn apply() {
+    for (name, value) in options() {
+        match params().find(|param| param.matches(name)) {
+            Some(param) => {
+                if let Err(message) = (param.set)(name, value) {
+                    warn!("cmdline: {}={}: {}", name, value, message);
+                }
+            }
+            None if name.contains('.') => debug!("cmdline: ignoring {}={}", name, value),
+            None => warn!("cmdline: unknown parameter {}", name),
+        }
+    }
+}
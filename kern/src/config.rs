@@ -0,0 +1,345 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use fat32::traits::FileSystem;
+use kernel_api::{CAP_ALL, CAP_NAMES};
+use pi::tft;
+use pi::uart;
+use shim::io;
+
+use crate::cmdline::Param;
+use crate::console::{self, Uart, CONSOLE};
+use crate::display;
+use crate::fs;
+use crate::logger::{self, warn, Level};
+use crate::mutex::Mutex;
+use crate::param::TICK;
+use crate::FILESYSTEM;
+
+/// The configuration file read at boot.
+pub const CONFIG_FILE: &str = "/config.txt";
+
+/// Features that can be toggled with `feature.<name>=on|off`, and whether
+/// they are on by default.
+const FEATURES: &[(&str, bool)] = &[
+    ("audio", true),
+    ("display_console", true),
+    ("log_file", cfg!(feature = "log-file")),
+    ("writeback", true),
+    ("syscall_profile", false),
+    ("core_dumps", false),
+    ("debugger", false),
+    ("smp", false),
+    ("swap", false),
+    ("uart_mux", false),
+];
+
+/// The default size limit of a core dump.
+const CORE_LIMIT: usize = 256 * 1024;
+
+/// The shortest and longest accepted scheduler ticks.
+const MIN_TICK: Duration = Duration::from_millis(1);
+const MAX_TICK: Duration = Duration::from_secs(1);
+
+/// Kernel settings, read from `CONFIG_FILE`.
+///
+/// The file holds one `key=value` setting per line. Blank lines and lines
+/// starting with `#` are ignored. The keys are:
+///
+///   * `console`: the console UART as `<uart>[,<baud>]`, e.g. `pl011,115200`
+///   * `log_level`: `error`, `warn`, `info`, `debug` or `trace`
+///   * `sched_tick`: the scheduler time slice, e.g. `10ms`, `500us` or `1s`
+///   * `init_processes`: the number of init processes started
+///   * `core_limit`: the largest core dump written, e.g. `64k` or `1m`
+///   * `init_caps`: the capabilities of init processes, as a comma-separated
+///     list of names from `CAP_NAMES`, `all` or `none`
+///   * `display`: a TFT panel as `<panel>[,<dc>[,<reset>]]`, e.g. `st7735,24,25`;
+///     see `display::parse_config()`
+///   * `feature.<name>`: `on` or `off`, for each name in `FEATURES`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    /// The console UART, if it should differ from the command line's.
+    pub console: Option<(Uart, uart::Config)>,
+    pub log_level: Level,
+    pub sched_tick: Duration,
+    pub init_processes: usize,
+    /// The largest core dump written, in bytes.
+    pub core_limit: usize,
+    /// The capabilities of init processes, `CAP_*` bits.
+    pub init_caps: u64,
+    /// The TFT panel to draw on, if any.
+    pub display: Option<tft::Config>,
+    /// The features whose state differs from their default.
+    pub features: Vec<(String, bool)>,
+    /// Whether the settings were read from `CONFIG_FILE`.
+    pub loaded: bool,
+}
+
+impl Config {
+    /// The settings used when there is no `CONFIG_FILE`.
+    pub const fn new() -> Config {
+        Config {
+            console: None,
+            log_level: Level::Info,
+            sched_tick: TICK,
+            init_processes: 4,
+            core_limit: CORE_LIMIT,
+            init_caps: 0,
+            display: None,
+            features: Vec::new(),
+            loaded: false,
+        }
+    }
+
+    /// Parses the contents of a configuration file. Invalid lines and
+    /// unknown keys are skipped; a description of each is returned along
+    /// with the settings.
+    pub fn parse(text: &str) -> (Config, Vec<String>) {
+        let mut config = Config::new();
+        config.loaded = true;
+        let mut warnings = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = match parts.next() {
+                Some(value) => value.trim(),
+                None => {
+                    warnings.push(format!("line {}: expected key=value", number + 1));
+                    continue;
+                }
+            };
+            if let Err(message) = config.set(key, value) {
+                warnings.push(format!("line {}: {}", number + 1, message));
+            }
+        }
+        (config, warnings)
+    }
+
+    /// Applies the setting `key=value`.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let invalid = || format!("invalid value for {}: {}", key, value);
+        match key {
+            "console" => self.console = Some(console::parse_console(value).ok_or_else(invalid)?),
+            "log_level" => self.log_level = Level::from_name(value).ok_or_else(invalid)?,
+            "sched_tick" => {
+                self.sched_tick = parse_duration(value)
+                    .filter(|tick| *tick >= MIN_TICK && *tick <= MAX_TICK)
+                    .ok_or_else(invalid)?
+            }
+            "init_processes" => self.init_processes = value.parse().map_err(|_| invalid())?,
+            "core_limit" => self.core_limit = parse_size(value).ok_or_else(invalid)?,
+            "init_caps" => self.init_caps = parse_caps(value).ok_or_else(invalid)?,
+            "display" => self.display = Some(display::parse_config(value).ok_or_else(invalid)?),
+            _ if key.starts_with("feature.") => {
+                let name = &key["feature.".len()..];
+                if !FEATURES.iter().any(|&(feature, _)| feature == name) {
+                    return Err(format!("unknown feature: {}", name));
+                }
+                let on = match value {
+                    "on" | "1" | "true" => true,
+                    "off" | "0" | "false" => false,
+                    _ => return Err(invalid()),
+                };
+                self.features.retain(|(feature, _)| feature != name);
+                self.features.push((name.to_string(), on));
+            }
+            _ => return Err(format!("unknown key: {}", key)),
+        }
+        Ok(())
+    }
+
+    /// Returns whether the feature `name` is on.
+    pub fn feature(&self, name: &str) -> bool {
+        match self.features.iter().find(|(feature, _)| feature == name) {
+            Some(&(_, on)) => on,
+            None => FEATURES.iter().any(|&(feature, on)| feature == name && on),
+        }
+    }
+}
+
+/// Parses a duration with a `us`, `ms` or `s` suffix. A bare number is in
+/// milliseconds.
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let number: u64 = value[..split].parse().ok()?;
+    match &value[split..] {
+        "us" => Some(Duration::from_micros(number)),
+        "" | "ms" => Some(Duration::from_millis(number)),
+        "s" => Some(Duration::from_secs(number)),
+        _ => None,
+    }
+}
+
+/// Parses a size in bytes with an optional `k` or `m` suffix.
+pub fn parse_size(value: &str) -> Option<usize> {
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let number: usize = value[..split].parse().ok()?;
+    match &value[split..] {
+        "" => Some(number),
+        "k" | "K" => number.checked_mul(1024),
+        "m" | "M" => number.checked_mul(1024 * 1024),
+        _ => None,
+    }
+}
+
+/// Parses a comma-separated list of capability names, `all` or `none`.
+pub fn parse_caps(value: &str) -> Option<u64> {
+    match value {
+        "all" => return Some(CAP_ALL),
+        "none" => return Some(0),
+        _ => (),
+    }
+    value.split(',').try_fold(0, |caps, name| {
+        let name = name.trim();
+        CAP_NAMES.iter().find(|&&(cap, _)| cap == name).map(|&(_, bit)| caps | bit)
+    })
+}
+
+/// Returns the names of the capabilities in `caps`, comma-separated, or
+/// `none`.
+pub fn caps_names(caps: u64) -> String {
+    let names: Vec<&str> = CAP_NAMES
+        .iter()
+        .filter(|&&(_, bit)| caps & bit != 0)
+        .map(|&(name, _)| name)
+        .collect();
+    if names.is_empty() {
+        "none".to_string()
+    } else {
+        names.join(",")
+    }
+}
+
+/// The configuration's kernel parameters; see `cmdline`. They override
+/// `CONFIG_FILE`, and take the same values as its keys.
+pub static PARAMS: &[Param] = &[
+    Param { name: "sched_tick", description: "scheduler time slice, e.g. 5ms", set },
+    Param { name: "init_processes", description: "number of init processes", set },
+    Param { name: "core_limit", description: "largest core dump, e.g. 64k", set },
+    Param { name: "init_caps", description: "init process capabilities, e.g. sched,ptrace", set },
+    Param { name: "feature.", description: "feature.<name>=on|off toggles a feature", set },
+];
+
+fn set(name: &str, value: &str) -> Result<(), String> {
+    CONFIG.lock().set(name, value)
+}
+
+static CONFIG: Mutex<Config> = Mutex::new(Config::new());
+
+/// Reads `CONFIG_FILE`, if it exists, and applies the console and log level
+/// settings. The other settings take effect as their subsystems start.
+/// `cmdline::apply()` should be called afterwards, so that the command line
+/// takes precedence.
+///
+/// The caller should assure that `FILESYSTEM` has been initialized, and that
+/// buffered console output has not been enabled yet.
+pub fn load() {
+    let text = match read(CONFIG_FILE) {
+        Ok(text) => text,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return,
+        Err(e) => {
+            warn!("config: cannot read {}: {:?}", CONFIG_FILE, e);
+            return;
+        }
+    };
+    let (config, warnings) = Config::parse(&text);
+    logger::set_max_level(config.log_level);
+    if let Some((uart, uart_config)) = config.console {
+        CONSOLE.lock().select_uart(uart, uart_config);
+    }
+    for warning in warnings {
+        warn!("config: {}: {}", CONFIG_FILE, warning);
+    }
+    *CONFIG.lock() = config;
+}
+
+fn read(path: &str) -> io::Result<String> {
+    let mut file = FILESYSTEM.open_file(path)?;
+    let mut bytes = Vec::new();
+    fs::read_to_end(&mut file, &mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "not UTF-8"))
+}
+
+/// Returns the current settings.
+pub fn get() -> Config {
+    CONFIG.lock().clone()
+}
+
+/// Returns the scheduler time slice.
+pub fn sched_tick() -> Duration {
+    CONFIG.lock().sched_tick
+}
+
+/// Returns whether the feature `name` is on.
+pub fn feature(name: &str) -> bool {
+    CONFIG.lock().feature(name)
+}
+
+/// Returns the name and state of every feature.
+pub fn features() -> impl Iterator<Item = (&'static str, bool)> {
+    let config = get();
+    FEATURES.iter().map(move |&(name, _)| (name, config.feature(name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use kernel_api::{CAP_CONSOLE, CAP_PTRACE, CAP_SCHED};
+
+    use super::*;
+
+    #[test]
+    fn parses_settings() {
+        let (config, warnings) = Config::parse(
+            "# comment\n\nlog_level = debug\nsched_tick=5ms\ninit_processes=2\nfeature.audio=off\n\
+             display=ili9341\n",
+        );
+        assert!(warnings.is_empty());
+        assert_eq!(config.log_level, Level::Debug);
+        assert_eq!(config.sched_tick, Duration::from_millis(5));
+        assert_eq!(config.init_processes, 2);
+        assert!(!config.feature("audio"));
+        assert_eq!(config.console, None);
+        assert_eq!(config.display, Some(tft::Config::new(tft::Panel::Ili9341)));
+    }
+
+    #[test]
+    fn reports_invalid_lines() {
+        let (config, warnings) = Config::parse("bogus=1\nsched_tick=0ms\nfeature.x=on\nlog_level\n");
+        assert_eq!(warnings.len(), 4);
+        assert_eq!(config, Config { loaded: true, ..Config::new() });
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("250us"), Some(Duration::from_micros(250)));
+        assert_eq!(parse_duration("7"), Some(Duration::from_millis(7)));
+        assert_eq!(parse_duration("2s"), Some(Duration::from_secs(2)));
+        assert_eq!(parse_duration("2m"), None);
+        assert_eq!(parse_duration("ms"), None);
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("64k"), Some(64 * 1024));
+        assert_eq!(parse_size("2M"), Some(2 * 1024 * 1024));
+        assert_eq!(parse_size("1g"), None);
+        assert_eq!(parse_size("k"), None);
+    }
+
+    #[test]
+    fn parses_caps() {
+        assert_eq!(parse_caps("sched, ptrace"), Some(CAP_SCHED | CAP_PTRACE));
+        assert_eq!(parse_caps("all"), Some(CAP_ALL));
+        assert_eq!(parse_caps("none"), Some(0));
+        assert_eq!(parse_caps("sched,root"), None);
+        assert_eq!(caps_names(CAP_SCHED | CAP_CONSOLE), "sched,console");
+        assert_eq!(caps_names(0), "none");
+    }
+}
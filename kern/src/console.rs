@@ -1,79 +1,880 @@
+pub mod early;
+pub mod font;
+pub mod line;
+pub mod mux;
+pub mod screen;
+pub mod utf8;
+#[cfg(feature = "semihosting")]
+pub mod semihost;
+
+use alloc::string::String;
 use core::fmt;
-use pi::uart::MiniUart;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use pi::interrupt::{Controller, Interrupt};
+use pi::pl011::{self, Pl011};
+use pi::uart::{self, MiniUart};
+use ringbuf::{Mpsc, Slot, Spsc};
+use serial_mux::{Channel, Decoder, MAX_PAYLOAD};
 use shim::io;
 
-use crate::mutex::Mutex;
+use crate::cmdline::{self, Param};
+use crate::mutex::{Mutex, MutexGuard};
+use crate::process::Id;
+use crate::traps::irq::{without_irqs, IrqHandler};
+use crate::traps::TrapFrame;
+use crate::IRQ;
+
+/// The control character sent by Ctrl-C.
+pub const CTRL_C: u8 = 0x03;
+
+/// The number of input bytes that can be read ahead while polling for
+/// control characters. A power of two, as for every `Spsc`.
+const INPUT_BUFFER_SIZE: usize = 64;
+
+/// The number of output bytes that can be queued for interrupt-driven
+/// transmission before writes block.
+const OUTPUT_BUFFER_SIZE: usize = 4096;
+
+/// The number of most recently written bytes kept for crash dumps.
+const HISTORY_SIZE: usize = 8192;
+
+/// The number of bytes printed in interrupt context that can wait for the
+/// console lock.
+const DEFERRED_SIZE: usize = 2048;
+
+/// The longest message printed in interrupt context; longer ones are cut.
+const MAX_DEFERRED_MESSAGE: usize = 256;
+
+/// The most output a capture keeps; the rest is dropped.
+const MAX_CAPTURE: usize = 1 << 20;
+
+/// A UART that can back the console.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Uart {
+    Mini,
+    Pl011,
+}
+
+impl Uart {
+    /// Returns the UART named `name`: `mini` (or `ttyS0`) for the mini UART
+    /// and `pl011` (or `ttyAMA0`) for the PL011.
+    pub fn from_name(name: &str) -> Option<Uart> {
+        match name {
+            "mini" | "ttyS0" => Some(Uart::Mini),
+            "pl011" | "ttyAMA0" => Some(Uart::Pl011),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Uart::Mini => "mini",
+            Uart::Pl011 => "pl011",
+        }
+    }
+}
+
+/// Parses a console setting of the form `<uart>[,<baud>]`; see
+/// `Uart::from_name()`. The baud rate defaults to 115200 bps.
+pub fn parse_console(value: &str) -> Option<(Uart, uart::Config)> {
+    let mut value = value.splitn(2, ',');
+    let device = Uart::from_name(value.next()?)?;
+    let mut config = uart::Config::default();
+    if let Some(baud) = value.next() {
+        config.baud = baud.parse().ok()?;
+    }
+    Some((device, config))
+}
+
+/// The console's kernel parameters; see `cmdline`.
+pub static PARAMS: &[Param] = &[Param {
+    name: "console",
+    description: "console UART as <uart>[,<baud>], where <uart> is mini or pl011, or semihost",
+    set: set_console,
+}];
+
+fn set_console(_name: &str, value: &str) -> Result<(), String> {
+    if value == "semihost" {
+        #[cfg(feature = "semihosting")]
+        {
+            semihost::set_enabled(true);
+            return Ok(());
+        }
+        #[cfg(not(feature = "semihosting"))]
+        return Err(String::from("built without the semihosting feature"));
+    }
+    let (uart, config) = match parse_console(value) {
+        Some(console) => console,
+        None => return Err(String::from("expected <uart>[,<baud>] or semihost")),
+    };
+    #[cfg(feature = "semihosting")]
+    semihost::set_enabled(false);
+    CONSOLE.lock().select_uart(uart, config);
+    Ok(())
+}
+
+/// The UART backing the console.
+enum Device {
+    Mini(MiniUart),
+    Pl011(Pl011),
+}
+
+impl Device {
+    /// Opens the UART selected by the `console` option on the kernel command
+    /// line. Defaults to the mini UART at 115200 bps.
+    fn from_cmdline() -> Device {
+        let (uart, config) = cmdline::value("console")
+            .and_then(parse_console)
+            .unwrap_or((Uart::Mini, uart::Config::default()));
+        Device::open(uart, config)
+    }
+
+    /// Opens `uart` with `config`, falling back to its default settings if
+    /// `config` is not supported.
+    fn open(uart: Uart, config: uart::Config) -> Device {
+        match uart {
+            Uart::Pl011 => match Pl011::with_config(config) {
+                Ok(uart) => Device::Pl011(uart),
+                Err(_) => Device::Pl011(Pl011::new()),
+            },
+            Uart::Mini => match MiniUart::with_config(config) {
+                Ok(uart) => Device::Mini(uart),
+                Err(_) => Device::Mini(MiniUart::new()),
+            },
+        }
+    }
+
+    fn uart(&self) -> Uart {
+        match self {
+            Device::Mini(_) => Uart::Mini,
+            Device::Pl011(_) => Uart::Pl011,
+        }
+    }
+
+    fn has_byte(&self) -> bool {
+        match self {
+            Device::Mini(uart) => uart.has_byte(),
+            Device::Pl011(uart) => uart.has_byte(),
+        }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        match self {
+            Device::Mini(uart) => uart.read_byte(),
+            Device::Pl011(uart) => uart.read_byte(),
+        }
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        match self {
+            Device::Mini(uart) => uart.write_byte(byte),
+            Device::Pl011(uart) => uart.write_byte(byte),
+        }
+    }
+
+    fn can_write(&self) -> bool {
+        match self {
+            Device::Mini(uart) => uart.can_write(),
+            Device::Pl011(uart) => uart.can_write(),
+        }
+    }
+
+    /// Returns the interrupt line of the UART.
+    fn interrupt(&self) -> Interrupt {
+        match self {
+            Device::Mini(_) => Interrupt::Aux,
+            Device::Pl011(_) => Interrupt::Uart,
+        }
+    }
+
+    fn enable_tx_interrupt(&mut self, enable: bool) {
+        match self {
+            Device::Mini(uart) => uart.enable_tx_interrupt(enable),
+            Device::Pl011(uart) => uart.enable_interrupt(pl011::Interrupt::Tx, enable),
+        }
+    }
+
+    /// Enables or disables the interrupts raised when input arrives.
+    fn enable_rx_interrupt(&mut self, enable: bool) {
+        match self {
+            Device::Mini(uart) => uart.enable_rx_interrupt(enable),
+            Device::Pl011(uart) => {
+                uart.enable_interrupt(pl011::Interrupt::Rx, enable);
+                uart.enable_interrupt(pl011::Interrupt::RxTimeout, enable);
+            }
+        }
+    }
+
+    fn config(&self) -> uart::Config {
+        match self {
+            Device::Mini(uart) => uart.config(),
+            Device::Pl011(uart) => uart.config(),
+        }
+    }
+
+    fn configure(&mut self, config: uart::Config) -> Result<(), uart::Error> {
+        match self {
+            Device::Mini(uart) => uart.configure(config),
+            Device::Pl011(uart) => uart.configure(config),
+        }
+    }
+}
+
+impl io::Read for Device {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Device::Mini(uart) => io::Read::read(uart, buf),
+            Device::Pl011(uart) => io::Read::read(uart, buf),
+        }
+    }
+}
+
+impl io::Write for Device {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Device::Mini(uart) => io::Write::write(uart, buf),
+            Device::Pl011(uart) => io::Write::write(uart, buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Device::Mini(uart) => io::Write::flush(uart),
+            Device::Pl011(uart) => io::Write::flush(uart),
+        }
+    }
+}
+
+impl fmt::Write for Device {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match self {
+            Device::Mini(uart) => fmt::Write::write_str(uart, s),
+            Device::Pl011(uart) => fmt::Write::write_str(uart, s),
+        }
+    }
+}
 
 /// A global singleton allowing read/write access to the console.
 pub struct Console {
-    inner: Option<MiniUart>,
+    inner: Option<Device>,
+    /// The process group that owns the console, if any. Input and Ctrl-C are
+    /// only delivered to this group.
+    foreground: Option<Id>,
+    /// Bytes read ahead by `poll_interrupt()` that have not been consumed.
+    input: Spsc<[u8; INPUT_BUFFER_SIZE]>,
+    /// Whether output is queued in `output` and sent from the UART's
+    /// transmit interrupt. Otherwise writes go directly to the UART.
+    buffered: bool,
+    output: Spsc<[u8; OUTPUT_BUFFER_SIZE]>,
+    /// The last `HISTORY_SIZE` bytes written, ending at `history_end`.
+    history: [u8; HISTORY_SIZE],
+    history_end: usize,
+    history_len: usize,
+    /// Whether the UART is multiplexed: console I/O travels in frames on
+    /// `Channel::Console`, next to the other channels; see `mux`.
+    mux: bool,
+    decoder: Decoder,
+    /// Output being captured instead of written; see `start_capture()`.
+    capture: Option<Capture>,
+}
+
+/// The `kprint!` output of a core, kept for the caller of `start_capture()`.
+struct Capture {
+    core: usize,
+    text: String,
+    truncated: bool,
+}
+
+impl fmt::Write for Capture {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.text.len() + s.len() > MAX_CAPTURE {
+            self.truncated = true;
+            return Ok(());
+        }
+        self.text.push_str(s);
+        Ok(())
+    }
 }
 
 impl Console {
     /// Creates a new instance of `Console`.
     const fn new() -> Console {
-        Console { inner: None }
+        Console {
+            inner: None,
+            foreground: None,
+            input: Spsc::new([0; INPUT_BUFFER_SIZE]),
+            buffered: false,
+            output: Spsc::new([0; OUTPUT_BUFFER_SIZE]),
+            history: [0; HISTORY_SIZE],
+            history_end: 0,
+            history_len: 0,
+            mux: false,
+            decoder: Decoder::new(),
+            capture: None,
+        }
     }
 
     /// Initializes the console if it's not already initialized.
     #[inline]
     fn initialize(&mut self) {
         if self.inner.is_none() {
-            self.inner = Some(MiniUart::new());
+            self.inner = Some(Device::from_cmdline());
         }
     }
 
-    /// Returns a mutable borrow to the inner `MiniUart`, initializing it as
-    /// needed.
-    fn inner(&mut self) -> &mut MiniUart {
+    /// Returns a mutable borrow to the inner UART, initializing it as needed.
+    fn inner(&mut self) -> &mut Device {
         self.initialize();
         self.inner.as_mut().unwrap()
     }
 
     /// Reads a byte from the UART device, blocking until a byte is available.
+    ///
+    /// Queued output is sent before blocking so that prompts are visible even
+    /// when the transmit interrupt cannot be taken.
     pub fn read_byte(&mut self) -> u8 {
-        self.inner().read_byte()
+        if let Some(byte) = self.pop_input() {
+            return byte;
+        }
+        self.flush_output();
+        loop {
+            self.poll_input();
+            if let Some(byte) = self.pop_input() {
+                return byte;
+            }
+        }
+    }
+
+    /// Appends `byte` to the read-ahead buffer, dropping it if the buffer is
+    /// full.
+    fn push_input(&mut self, byte: u8) {
+        let _ = self.input.push(byte);
+    }
+
+    /// Handles `raw`, a byte read from the UART. Without multiplexing, it is
+    /// console input. Otherwise it is passed to the decoder: the payload of a
+    /// console frame is console input and control frames are queued for the
+    /// control thread.
+    fn receive(&mut self, raw: u8) {
+        if !self.mux {
+            return self.push_input(raw);
+        }
+        let mut payload = [0; MAX_PAYLOAD];
+        let (channel, len) = match self.decoder.push(raw) {
+            Some((channel, data)) => {
+                payload[..data.len()].copy_from_slice(data);
+                (channel, data.len())
+            }
+            None => return,
+        };
+        match channel {
+            Channel::Console => {
+                for &byte in &payload[..len] {
+                    self.push_input(byte);
+                }
+            }
+            Channel::Control => mux::queue_request(&payload[..len]),
+            Channel::Log => (),
+        }
+    }
+
+    /// Reads all bytes waiting in the UART without blocking.
+    pub fn poll_input(&mut self) {
+        while self.inner().has_byte() {
+            let raw = self.inner().read_byte();
+            self.receive(raw);
+        }
+    }
+
+    /// Removes and returns the oldest read-ahead byte, if any.
+    fn pop_input(&mut self) -> Option<u8> {
+        self.input.pop()
+    }
+
+    /// Returns `true` if read-ahead input is waiting. With buffered output
+    /// enabled, the receive interrupt reads input ahead as it arrives.
+    pub fn has_input(&self) -> bool {
+        !self.input.is_empty()
+    }
+
+    /// Moves read-ahead input into `buf` without blocking and returns the
+    /// number of bytes moved.
+    pub fn take_input(&mut self, buf: &mut [u8]) -> usize {
+        let mut i = 0;
+        while i < buf.len() {
+            match self.pop_input() {
+                Some(byte) => buf[i] = byte,
+                None => break,
+            }
+            i += 1;
+        }
+        i
+    }
+
+    /// Returns the process group that owns the console, if any.
+    pub fn foreground(&self) -> Option<Id> {
+        self.foreground
+    }
+
+    /// Makes `pgid` the process group that owns the console. `None` returns
+    /// ownership to the kernel.
+    pub fn set_foreground(&mut self, pgid: Option<Id>) {
+        self.foreground = pgid;
+    }
+
+    /// Returns `true` if input may be delivered to processes in group `pgid`.
+    pub fn is_foreground(&self, pgid: Id) -> bool {
+        self.foreground == Some(pgid)
+    }
+
+    /// Drains all bytes waiting in the UART without blocking and returns
+    /// `true` if Ctrl-C was among the input. Ctrl-C is removed; other bytes
+    /// are kept for subsequent reads, and bytes that do not fit in the
+    /// read-ahead buffer are dropped.
+    pub fn poll_interrupt(&mut self) -> bool {
+        self.poll_input();
+        let mut interrupted = false;
+        for _ in 0..self.input.len() {
+            let byte = self.input.pop().unwrap();
+            if byte == CTRL_C {
+                interrupted = true;
+            } else {
+                self.push_input(byte);
+            }
+        }
+        interrupted
     }
 
     /// Writes the byte `byte` to the UART device.
+    ///
+    /// With buffered output enabled, this only blocks if the output queue is
+    /// full. Otherwise it blocks until the UART accepts the byte.
     pub fn write_byte(&mut self, byte: u8) {
-        self.inner().write_byte(byte)
+        self.record(&[byte]);
+        self.send(Channel::Console, &[byte]);
+    }
+
+    /// Writes `bytes` to `channel`. Without multiplexing, all channels are
+    /// written to the UART as is. Not recorded in the history.
+    pub fn write_channel(&mut self, channel: Channel, bytes: &[u8]) {
+        self.send(channel, bytes);
+    }
+
+    /// Multiplexes the UART from now on; see `mux`. Pending output is sent
+    /// as is first.
+    pub fn enable_mux(&mut self) {
+        self.flush();
+        self.mux = true;
+    }
+
+    /// Returns `true` if the UART is multiplexed.
+    pub fn is_mux(&self) -> bool {
+        self.mux
+    }
+
+    /// Keeps what the calling core prints with `kprint!` from now on, outside
+    /// of interrupt handlers, instead of writing it, until `end_capture()`.
+    /// Output of other cores, log records and writes to the console itself
+    /// are not captured. Returns `false` if a capture is already running.
+    pub fn start_capture(&mut self) -> bool {
+        if self.capture.is_some() {
+            return false;
+        }
+        self.capture = Some(Capture { core: aarch64::affinity(), text: String::new(), truncated: false });
+        true
+    }
+
+    /// Ends the capture started by `start_capture()` and returns the output
+    /// it kept, and whether output past `MAX_CAPTURE` bytes was dropped.
+    pub fn end_capture(&mut self) -> Option<(String, bool)> {
+        self.capture.take().map(|capture| (capture.text, capture.truncated))
+    }
+
+    /// Writes `args` for `kprint!`: to the capture if the calling core is
+    /// captured, to the console otherwise.
+    fn print(&mut self, args: fmt::Arguments) {
+        use core::fmt::Write;
+        if let Some(capture) = self.capture.as_mut() {
+            if capture.core == aarch64::affinity() && !crate::traps::in_interrupt() {
+                let _ = capture.write_fmt(args);
+                return;
+            }
+        }
+        self.write_fmt(args).unwrap();
+    }
+
+    /// Appends `bytes` to the output history, and queues them for the
+    /// display if the console is mirrored onto it and for the HDMI console.
+    fn record(&mut self, bytes: &[u8]) {
+        crate::display::mirror(bytes);
+        crate::video::mirror(bytes);
+        for &byte in bytes {
+            self.history[self.history_end] = byte;
+            self.history_end = (self.history_end + 1) % HISTORY_SIZE;
+        }
+        self.history_len = (self.history_len + bytes.len()).min(HISTORY_SIZE);
+    }
+
+    /// Returns the most recently written output, oldest first, as two slices
+    /// that are to be read one after the other.
+    pub fn history(&self) -> (&[u8], &[u8]) {
+        if self.history_len < HISTORY_SIZE {
+            (&self.history[..self.history_end], &[])
+        } else {
+            (&self.history[self.history_end..], &self.history[..self.history_end])
+        }
+    }
+
+    /// Sends `bytes` on `channel`, in frames if the UART is multiplexed.
+    fn send(&mut self, channel: Channel, bytes: &[u8]) {
+        if self.mux {
+            serial_mux::encode(channel, bytes, |byte| self.send_byte(byte));
+        } else {
+            for &byte in bytes {
+                self.send_byte(byte);
+            }
+        }
+    }
+
+    /// Sends `s` on `channel` with each `\n` preceded by `\r`.
+    fn send_str(&mut self, channel: Channel, s: &str) {
+        for (i, line) in s.split('\n').enumerate() {
+            if i > 0 {
+                self.send(channel, b"\r\n");
+            }
+            if !line.is_empty() {
+                self.send(channel, line.as_bytes());
+            }
+        }
+    }
+
+    /// Sends raw `byte` to the UART, without recording it in the history.
+    fn send_byte(&mut self, byte: u8) {
+        if !self.buffered {
+            return self.inner().write_byte(byte);
+        }
+        // The transmit interrupt pops from `output` too.
+        without_irqs(|| {
+            if self.output.is_full() {
+                let oldest = self.pop_output().unwrap();
+                self.inner().write_byte(oldest);
+            }
+            let _ = self.output.push(byte);
+            self.transmit();
+        })
+    }
+
+    /// Removes and returns the oldest queued output byte, if any. Outside the
+    /// transmit interrupt, IRQs must be masked.
+    fn pop_output(&mut self) -> Option<u8> {
+        self.output.pop()
+    }
+
+    /// Moves queued output into the UART until its transmit FIFO is full and
+    /// leaves the transmit interrupt enabled only while output remains.
+    /// Outside the transmit interrupt, IRQs must be masked.
+    fn transmit(&mut self) {
+        while !self.output.is_empty() && self.inner().can_write() {
+            let byte = self.pop_output().unwrap();
+            self.inner().write_byte(byte);
+        }
+        let pending = !self.output.is_empty();
+        self.inner().enable_tx_interrupt(pending);
+    }
+
+    /// Blocks until all queued output has been handed to the UART.
+    fn flush_output(&mut self) {
+        without_irqs(|| {
+            while let Some(byte) = self.pop_output() {
+                self.inner().write_byte(byte);
+            }
+            if self.buffered {
+                self.inner().enable_tx_interrupt(false);
+            }
+        })
+    }
+
+    /// Blocks until all queued output has been transmitted. Does not depend on
+    /// interrupts, so it is safe to call from panic and fault paths.
+    pub fn flush(&mut self) {
+        self.flush_output();
+        let _ = io::Write::flush(self.inner());
+    }
+
+    /// Returns the line settings of the UART device.
+    pub fn uart_config(&mut self) -> uart::Config {
+        self.inner().config()
+    }
+
+    /// Returns the UART backing the console.
+    pub fn uart(&mut self) -> Uart {
+        self.inner().uart()
+    }
+
+    /// Switches the console to `uart` with `config`. Pending output is sent
+    /// on the old device first.
+    ///
+    /// Must be called before `enable_buffered_output()`, which registers the
+    /// interrupt handler of the device in use at that time.
+    pub fn select_uart(&mut self, uart: Uart, config: uart::Config) {
+        self.flush();
+        if self.uart() != uart || self.uart_config() != config {
+            self.inner = Some(Device::open(uart, config));
+        }
+    }
+
+    /// Reconfigures the UART device. Pending output is sent at the old
+    /// settings first.
+    pub fn configure_uart(&mut self, config: uart::Config) -> Result<(), uart::Error> {
+        self.flush_output();
+        self.inner().configure(config)
+    }
+}
+
+impl line::Tty for Console {
+    fn read_byte(&mut self) -> u8 {
+        Console::read_byte(self)
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        Console::write_byte(self, byte)
     }
 }
 
 impl io::Read for Console {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.inner().read(buf)
+        if self.input.is_empty() && !self.mux {
+            return self.inner().read(buf);
+        }
+        while self.input.is_empty() && !buf.is_empty() {
+            self.poll_input();
+        }
+        Ok(self.take_input(buf))
     }
 }
 
 impl io::Write for Console {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.inner().write(buf)
+        self.record(buf);
+        if !self.buffered && !self.mux {
+            return self.inner().write(buf);
+        }
+        self.send(Channel::Console, buf);
+        Ok(buf.len())
     }
 
     fn flush(&mut self) -> io::Result<()> {
+        Console::flush(self);
         Ok(())
     }
 }
 
 impl fmt::Write for Console {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        self.inner().write_str(s)
+        self.record(s.as_bytes());
+        if !self.buffered && !self.mux {
+            return self.inner().write_str(s);
+        }
+        self.send_str(Channel::Console, s);
+        Ok(())
+    }
+}
+
+/// Writes formatted text to a channel other than the console's.
+struct ChannelWriter<'a> {
+    console: &'a mut Console,
+    channel: Channel,
+}
+
+impl fmt::Write for ChannelWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.console.record(s.as_bytes());
+        self.console.send_str(self.channel, s);
+        Ok(())
     }
 }
 
 /// Global `Console` singleton.
 pub static CONSOLE: Mutex<Console> = Mutex::new(Console::new());
 
+/// Messages printed by interrupt handlers while `CONSOLE` was held by the
+/// code they interrupted. They are printed once the lock is free: by the
+/// next print outside interrupt context or when the interrupt returns.
+static DEFERRED: Mpsc<[Slot<u8>; DEFERRED_SIZE]> = Mpsc::new([Slot::new(0); DEFERRED_SIZE]);
+
+/// The messages dropped because `DEFERRED` was full.
+static DEFERRED_DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Set once a panic has taken over the console; see `take_over()`.
+static TAKEN_OVER: AtomicBool = AtomicBool::new(false);
+
+/// Formats a message into a fixed buffer, cutting it at the buffer's end.
+struct MessageBuf {
+    bytes: [u8; MAX_DEFERRED_MESSAGE],
+    len: usize,
+}
+
+impl fmt::Write for MessageBuf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(self.bytes.len() - self.len);
+        self.bytes[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// Queues `args` to be printed once `CONSOLE` is free.
+fn defer(args: fmt::Arguments) {
+    use core::fmt::Write;
+    let mut message = MessageBuf { bytes: [0; MAX_DEFERRED_MESSAGE], len: 0 };
+    let _ = message.write_fmt(args);
+    if DEFERRED.push_slice(&message.bytes[..message.len]).is_err() {
+        DEFERRED_DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Console {
+    /// Prints the messages deferred by interrupt handlers. Only called with
+    /// `CONSOLE` held, which makes this the queue's one consumer.
+    fn print_deferred(&mut self) {
+        if DEFERRED.is_empty() && DEFERRED_DROPPED.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let mut consumer = unsafe { DEFERRED.consumer() };
+        while let Some(byte) = consumer.pop() {
+            self.record(&[byte]);
+            match byte {
+                b'\n' => self.send(Channel::Console, b"\r\n"),
+                byte => self.send(Channel::Console, &[byte]),
+            }
+        }
+        let dropped = DEFERRED_DROPPED.swap(0, Ordering::Relaxed);
+        if dropped != 0 {
+            let _ = fmt::Write::write_fmt(
+                self,
+                format_args!("[{} messages from interrupt handlers dropped]\n", dropped),
+            );
+        }
+    }
+}
+
+/// Prints the messages deferred by interrupt handlers unless `CONSOLE` is
+/// held, in which case its holder prints them with its next message. Called
+/// as each interrupt returns.
+pub fn flush_deferred() {
+    if DEFERRED.is_empty() || CONSOLE.is_locked() || early::is_active() {
+        return;
+    }
+    CONSOLE.lock().print_deferred();
+}
+
+/// Gives the panicking code the console: from now on `kprint!` writes
+/// straight to the UART without waiting for the console lock, whoever holds
+/// it, after the queued output and deferred messages.
+///
+/// Only the panic handler may call it: the holder of the lock must never
+/// run again.
+pub fn take_over() {
+    TAKEN_OVER.store(true, Ordering::Relaxed);
+    if early::is_active() {
+        return;
+    }
+    let mut console = unsafe { CONSOLE.force_lock() };
+    // The panic message must reach the UART, not a capture.
+    console.capture = None;
+    console.print_deferred();
+    console.flush_output();
+    console.buffered = false;
+}
+
+/// Returns the console for printing: its lock if it can be taken without
+/// corrupting it, the console itself once a panic has taken it over, or
+/// `None` if the caller is an interrupt handler that interrupted its holder.
+fn printer() -> Option<MutexGuard<'static, Console>> {
+    if TAKEN_OVER.load(Ordering::Relaxed) {
+        return Some(unsafe { CONSOLE.force_lock() });
+    }
+    if crate::traps::in_interrupt() && CONSOLE.is_locked() {
+        return None;
+    }
+    let mut console = CONSOLE.lock();
+    console.print_deferred();
+    Some(console)
+}
+
+/// Switches console output to a queue drained by the UART's transmit
+/// interrupt, so that `kprint!` only blocks once the queue is full, and reads
+/// input ahead from the receive interrupt, so that processes waiting in
+/// `sys_read_console` need not poll the UART.
+pub fn enable_buffered_output() {
+    without_irqs(|| {
+        let int = CONSOLE.lock().inner().interrupt();
+        IRQ.replace(int, IrqHandler::new(service, 0));
+        Controller::new().enable(int);
+        let mut console = CONSOLE.lock();
+        console.buffered = true;
+        console.inner().enable_rx_interrupt(true);
+    })
+}
+
+/// The UART's interrupt handler.
+fn service(_: usize, _: &mut TrapFrame) {
+    let mut console = CONSOLE.lock();
+    console.poll_input();
+    console.transmit();
+}
+
 /// Internal function called by the `kprint[ln]!` macros.
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
+    #[cfg(not(test))]
+    {
+        #[cfg(feature = "semihosting")]
+        {
+            if semihost::is_enabled() {
+                return semihost::print(args);
+            }
+        }
+        if early::is_active() {
+            return early::print(args);
+        }
+        match printer() {
+            Some(mut console) => console.print(args),
+            None => defer(args),
+        }
+    }
+
+    #[cfg(test)]
+    {
+        print!("{}", args);
+    }
+}
+
+/// Prints a log record: on `Channel::Log` if the UART is multiplexed, like
+/// `kprint!` otherwise.
+pub fn print_log(args: fmt::Arguments) {
     #[cfg(not(test))]
     {
         use core::fmt::Write;
-        let mut console = CONSOLE.lock();
-        console.write_fmt(args).unwrap();
+        #[cfg(feature = "semihosting")]
+        {
+            if semihost::is_enabled() {
+                return semihost::print(args);
+            }
+        }
+        if early::is_active() {
+            return early::print(args);
+        }
+        let mut console = match printer() {
+            Some(console) => console,
+            None => return defer(args),
+        };
+        if console.mux {
+            let mut writer = ChannelWriter { console: &mut console, channel: Channel::Log };
+            writer.write_fmt(args).unwrap();
+        } else {
+            console.write_fmt(args).unwrap();
+        }
     }
 
     #[cfg(test)]
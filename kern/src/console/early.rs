@@ -0,0 +1,62 @@
+//! Console output for early boot, before the allocator, the kernel command
+//! line and the main `CONSOLE` are set up.
+//!
+//! The early console writes straight to the mini UART at its default
+//! settings. It takes no locks and allocates nothing, so it works from the
+//! first instruction of `kmain` and in panics that happen before the MMU is
+//! enabled, when exclusive accesses fault. Only one core runs at that point,
+//! which is what makes the unsynchronized access sound.
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use pi::uart::MiniUart;
+
+static mut UART: Option<MiniUart> = None;
+
+/// Set between `initialize()` and `hand_over()`. Only loads and stores are
+/// used, for the same reason as in the panic handler.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Opens the mini UART and routes `kprint!` output to it.
+///
+/// # Safety
+///
+/// Must be called once, by the boot core, before any other core runs.
+pub unsafe fn initialize() {
+    UART = Some(MiniUart::new());
+    ACTIVE.store(true, Ordering::Relaxed);
+}
+
+/// Returns `true` while output goes to the early console.
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Waits for pending output to leave the UART and routes `kprint!` output
+/// to `CONSOLE` from now on. The main console reopens whichever UART it is
+/// configured to use, so nothing written before the handover is cut off.
+pub fn hand_over() {
+    if !is_active() {
+        return;
+    }
+    unsafe {
+        if let Some(uart) = UART.as_mut() {
+            let _ = shim::io::Write::flush(uart);
+        }
+        ACTIVE.store(false, Ordering::Relaxed);
+        UART = None;
+    }
+}
+
+/// Writes `args` to the early console. Does nothing if it is not active.
+pub fn print(args: fmt::Arguments) {
+    if !is_active() {
+        return;
+    }
+    unsafe {
+        if let Some(uart) = UART.as_mut() {
+            let _ = uart.write_fmt(args);
+        }
+    }
+}
@@ -0,0 +1,574 @@
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::CTRL_C;
+
+const CTRL_A: u8 = 0x01;
+const CTRL_D: u8 = 0x04;
+const CTRL_E: u8 = 0x05;
+const BEL: u8 = 0x07;
+const BS: u8 = 0x08;
+const LF: u8 = 0x0a;
+const CR: u8 = 0x0d;
+const CTRL_U: u8 = 0x15;
+const CTRL_W: u8 = 0x17;
+const ESC: u8 = 0x1b;
+const DEL: u8 = 0x7f;
+
+/// A byte-oriented terminal device.
+pub trait Tty {
+    /// Reads a byte, blocking until one is available.
+    fn read_byte(&mut self) -> u8;
+
+    /// Writes the byte `byte`.
+    fn write_byte(&mut self, byte: u8);
+}
+
+/// A decoded key press.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Key {
+    Char(u8),
+    Enter,
+    Backspace,
+    Delete,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    /// Ctrl-C.
+    Interrupt,
+    /// Ctrl-D.
+    EndOfFile,
+    /// Ctrl-U.
+    KillLine,
+    /// Ctrl-W.
+    WordErase,
+    /// Any other control character.
+    Control(u8),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum State {
+    Ground,
+    /// Seen `ESC`.
+    Escape,
+    /// Seen `ESC [` followed by an optional numeric parameter.
+    Csi(u16),
+    /// Seen `ESC O`.
+    Ss3,
+}
+
+/// Decodes a stream of input bytes into keys, translating the ANSI escape
+/// sequences sent by terminals for cursor and editing keys.
+#[derive(Debug)]
+pub struct Decoder {
+    state: State,
+}
+
+impl Decoder {
+    /// Returns a new decoder that is not in the middle of a sequence.
+    pub const fn new() -> Decoder {
+        Decoder { state: State::Ground }
+    }
+
+    /// Feeds `byte` to the decoder. Returns the decoded key if `byte`
+    /// completes one, and `None` if more input is needed. Unrecognized
+    /// escape sequences are discarded.
+    pub fn decode(&mut self, byte: u8) -> Option<Key> {
+        let (state, key) = match (self.state, byte) {
+            (State::Ground, ESC) => (State::Escape, None),
+            (State::Ground, byte) => (State::Ground, Some(Decoder::control(byte))),
+            (State::Escape, b'[') => (State::Csi(0), None),
+            (State::Escape, b'O') => (State::Ss3, None),
+            (State::Escape, _) => (State::Ground, None),
+            (State::Csi(n), b'0'..=b'9') => {
+                let n = n.saturating_mul(10).saturating_add((byte - b'0') as u16);
+                (State::Csi(n), None)
+            }
+            (State::Csi(n), b'~') => (State::Ground, match n {
+                1 | 7 => Some(Key::Home),
+                3 => Some(Key::Delete),
+                4 | 8 => Some(Key::End),
+                5 => Some(Key::PageUp),
+                6 => Some(Key::PageDown),
+                _ => None,
+            }),
+            (State::Csi(_), 0x40..=0x7e) | (State::Ss3, _) => {
+                (State::Ground, Decoder::cursor(byte))
+            }
+            // Parameter separators and intermediate bytes.
+            (State::Csi(n), _) => (State::Csi(n), None),
+        };
+        self.state = state;
+        key
+    }
+
+    fn control(byte: u8) -> Key {
+        match byte {
+            CR | LF => Key::Enter,
+            BS | DEL => Key::Backspace,
+            CTRL_A => Key::Home,
+            CTRL_C => Key::Interrupt,
+            CTRL_D => Key::EndOfFile,
+            CTRL_E => Key::End,
+            CTRL_U => Key::KillLine,
+            CTRL_W => Key::WordErase,
+            0..=0x1f => Key::Control(byte),
+            _ => Key::Char(byte),
+        }
+    }
+
+    fn cursor(byte: u8) -> Option<Key> {
+        match byte {
+            b'A' => Some(Key::Up),
+            b'B' => Some(Key::Down),
+            b'C' => Some(Key::Right),
+            b'D' => Some(Key::Left),
+            b'H' => Some(Key::Home),
+            b'F' => Some(Key::End),
+            _ => None,
+        }
+    }
+}
+
+/// The input mode of a `LineDiscipline`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Input is edited a line at a time and delivered when Enter is pressed.
+    Cooked,
+    /// Input bytes are delivered as they arrive, without any processing.
+    Raw,
+}
+
+/// Reasons a cooked read ends without a line.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// Ctrl-C was pressed.
+    Interrupted,
+    /// Ctrl-D was pressed on an empty line.
+    EndOfFile,
+}
+
+/// The lines entered before, oldest first, for recall with Up and Down by
+/// `LineDiscipline::read_line_with_history()`.
+#[derive(Debug, Default)]
+pub struct History {
+    entries: VecDeque<String>,
+    max: usize,
+}
+
+impl History {
+    /// Returns an empty history that keeps the newest `max` lines.
+    pub fn new(max: usize) -> History {
+        History { entries: VecDeque::new(), max }
+    }
+
+    /// Adds `line` as the newest entry unless it is blank or repeats the
+    /// newest entry, dropping the oldest entries past `max()`. Returns whether
+    /// it was added.
+    pub fn push(&mut self, line: &str) -> bool {
+        if line.trim().is_empty() || self.entries.back().map(String::as_str) == Some(line) || self.max == 0 {
+            return false;
+        }
+        self.entries.push_back(String::from(line));
+        self.set_max(self.max);
+        true
+    }
+
+    /// Returns the most entries kept.
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// Keeps the newest `max` entries from now on, dropping older ones.
+    pub fn set_max(&mut self, max: usize) {
+        self.max = max;
+        while self.entries.len() > max {
+            self.entries.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns entry `index`, counting from the oldest.
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(String::as_str)
+    }
+
+    /// Returns the entries, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(String::as_str)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// Terminal line discipline: turns raw terminal input into lines with
+/// echo and line editing, or passes it through untouched in raw mode.
+#[derive(Debug)]
+pub struct LineDiscipline {
+    decoder: Decoder,
+    mode: Mode,
+    echo: bool,
+}
+
+impl LineDiscipline {
+    /// Returns a new line discipline in cooked mode with echo enabled.
+    pub const fn new() -> LineDiscipline {
+        LineDiscipline {
+            decoder: Decoder::new(),
+            mode: Mode::Cooked,
+            echo: true,
+        }
+    }
+
+    /// Returns the current input mode.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Sets the input mode.
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+    }
+
+    /// Returns `true` if input is echoed.
+    pub fn echo(&self) -> bool {
+        self.echo
+    }
+
+    /// Enables or disables echo.
+    pub fn set_echo(&mut self, echo: bool) {
+        self.echo = echo;
+    }
+
+    /// Reads from `tty` into `buf` according to the current mode.
+    ///
+    /// In raw mode, reads a single byte. In cooked mode, reads an edited line
+    /// of at most `buf.len() - 1` bytes followed by a newline.
+    pub fn read<T: Tty>(&mut self, tty: &mut T, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match self.mode {
+            Mode::Raw => {
+                buf[0] = tty.read_byte();
+                if self.echo {
+                    tty.write_byte(buf[0]);
+                }
+                Ok(1)
+            }
+            Mode::Cooked => {
+                let n = buf.len() - 1;
+                let len = self.read_line(tty, &mut buf[..n])?;
+                buf[len] = b'\n';
+                Ok(len + 1)
+            }
+        }
+    }
+
+    /// Reads the next decoded key from `tty` without echoing it.
+    pub fn read_key<T: Tty>(&mut self, tty: &mut T) -> Key {
+        loop {
+            if let Some(key) = self.decoder.decode(tty.read_byte()) {
+                return key;
+            }
+        }
+    }
+
+    /// Reads and edits a line from `tty` into `buf`, returning its length.
+    /// The line is not terminated by a newline. Input beyond the capacity of
+    /// `buf` is rejected with a bell.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Interrupted` if Ctrl-C is pressed and
+    /// `Error::EndOfFile` if Ctrl-D is pressed on an empty line.
+    pub fn read_line<T: Tty>(&mut self, tty: &mut T, buf: &mut [u8]) -> Result<usize, Error> {
+        self.edit_line(tty, buf, None)
+    }
+
+    /// Like `read_line()`, but Up and Down replace the line with the entries
+    /// of `history`, from the newest; Down past the newest brings back the
+    /// line being typed. The line read is not added to `history`.
+    pub fn read_line_with_history<T: Tty>(
+        &mut self,
+        tty: &mut T,
+        buf: &mut [u8],
+        history: &History,
+    ) -> Result<usize, Error> {
+        self.edit_line(tty, buf, Some(history))
+    }
+
+    fn edit_line<T: Tty>(&mut self, tty: &mut T, buf: &mut [u8], history: Option<&History>) -> Result<usize, Error> {
+        let mut line = Line { tty, buf, len: 0, cursor: 0, echo: self.echo };
+        // The entry shown, or `history.len()` for the line being typed, which
+        // is kept in `draft` while entries are shown.
+        let entries = history.map_or(0, History::len);
+        let mut shown = entries;
+        let mut draft = Vec::new();
+        loop {
+            match self.read_key(line.tty) {
+                Key::Char(byte) => line.insert(byte),
+                Key::Enter => {
+                    line.write(b"\r\n");
+                    return Ok(line.len);
+                }
+                Key::Backspace if line.cursor > 0 => line.erase(line.cursor - 1, line.cursor),
+                Key::Delete if line.cursor < line.len => line.erase(line.cursor, line.cursor + 1),
+                Key::Left if line.cursor > 0 => line.move_to(line.cursor - 1),
+                Key::Right if line.cursor < line.len => line.move_to(line.cursor + 1),
+                Key::Up if shown > 0 => {
+                    if shown == entries {
+                        draft = line.buf[..line.len].to_vec();
+                    }
+                    shown -= 1;
+                    line.replace(history.and_then(|h| h.get(shown)).unwrap_or("").as_bytes());
+                }
+                Key::Down if shown < entries => {
+                    shown += 1;
+                    match history.and_then(|h| h.get(shown)) {
+                        Some(entry) => line.replace(entry.as_bytes()),
+                        None => line.replace(&draft),
+                    }
+                }
+                Key::Home => line.move_to(0),
+                Key::End => line.move_to(line.len),
+                Key::KillLine => line.erase(0, line.len),
+                Key::WordErase => line.erase(line.word_start(), line.cursor),
+                Key::Interrupt => {
+                    line.write(b"^C\r\n");
+                    return Err(Error::Interrupted);
+                }
+                Key::EndOfFile if line.len == 0 => return Err(Error::EndOfFile),
+                _ => line.bell(),
+            }
+        }
+    }
+}
+
+/// A line being edited, and the terminal it is echoed to.
+struct Line<'a, T: Tty> {
+    tty: &'a mut T,
+    buf: &'a mut [u8],
+    len: usize,
+    cursor: usize,
+    echo: bool,
+}
+
+impl<'a, T: Tty> Line<'a, T> {
+    fn write(&mut self, bytes: &[u8]) {
+        if self.echo {
+            for &byte in bytes {
+                self.tty.write_byte(byte);
+            }
+        }
+    }
+
+    fn write_n(&mut self, byte: u8, n: usize) {
+        if self.echo {
+            for _ in 0..n {
+                self.tty.write_byte(byte);
+            }
+        }
+    }
+
+    /// Redraws the line from `from` to its end, followed by `clear` blanks,
+    /// then moves the terminal cursor back to `self.cursor`.
+    fn redraw(&mut self, from: usize, clear: usize) {
+        if self.echo {
+            for i in from..self.len {
+                self.tty.write_byte(self.buf[i]);
+            }
+        }
+        self.write_n(b' ', clear);
+        self.write_n(BS, self.len + clear - self.cursor);
+    }
+
+    fn bell(&mut self) {
+        self.write(&[BEL]);
+    }
+
+    fn move_to(&mut self, pos: usize) {
+        if pos < self.cursor {
+            self.write_n(BS, self.cursor - pos);
+        } else if self.echo {
+            for i in self.cursor..pos {
+                self.tty.write_byte(self.buf[i]);
+            }
+        }
+        self.cursor = pos;
+    }
+
+    fn insert(&mut self, byte: u8) {
+        if self.len == self.buf.len() {
+            return self.bell();
+        }
+        self.buf.copy_within(self.cursor..self.len, self.cursor + 1);
+        self.buf[self.cursor] = byte;
+        self.len += 1;
+        self.cursor += 1;
+        self.redraw(self.cursor - 1, 0);
+    }
+
+    /// Replaces the line with as much of `bytes` as fits, leaving the cursor
+    /// at its end.
+    fn replace(&mut self, bytes: &[u8]) {
+        let old = self.len;
+        self.move_to(0);
+        let len = bytes.len().min(self.buf.len());
+        self.buf[..len].copy_from_slice(&bytes[..len]);
+        self.len = len;
+        self.cursor = len;
+        self.redraw(0, old.saturating_sub(len));
+    }
+
+    /// Removes the bytes in `start..end` and leaves the cursor at `start`.
+    fn erase(&mut self, start: usize, end: usize) {
+        if start == end {
+            return self.bell();
+        }
+        self.move_to(start);
+        self.buf.copy_within(end..self.len, start);
+        self.len -= end - start;
+        self.redraw(start, end - start);
+    }
+
+    /// Returns the start of the word before the cursor, skipping any spaces
+    /// immediately before it.
+    fn word_start(&self) -> usize {
+        let mut pos = self.cursor;
+        while pos > 0 && self.buf[pos - 1] == b' ' {
+            pos -= 1;
+        }
+        while pos > 0 && self.buf[pos - 1] != b' ' {
+            pos -= 1;
+        }
+        pos
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    struct MockTty {
+        input: VecDeque<u8>,
+        output: Vec<u8>,
+    }
+
+    impl MockTty {
+        fn new(input: &[u8]) -> MockTty {
+            MockTty { input: input.iter().cloned().collect(), output: Vec::new() }
+        }
+    }
+
+    impl Tty for MockTty {
+        fn read_byte(&mut self) -> u8 {
+            self.input.pop_front().expect("mock tty out of input")
+        }
+
+        fn write_byte(&mut self, byte: u8) {
+            self.output.push(byte);
+        }
+    }
+
+    fn read_line(input: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut tty = MockTty::new(input);
+        let mut buf = [0u8; 16];
+        let len = LineDiscipline::new().read_line(&mut tty, &mut buf)?;
+        Ok(buf[..len].to_vec())
+    }
+
+    #[test]
+    fn decodes_escape_sequences() {
+        let mut decoder = Decoder::new();
+        let keys: Vec<Key> = b"a\x1b[A\x1b[D\x1bOH\x1b[3~\x1b[1;5C\r"
+            .iter()
+            .filter_map(|&b| decoder.decode(b))
+            .collect();
+        assert_eq!(keys, vec![
+            Key::Char(b'a'), Key::Up, Key::Left, Key::Home, Key::Delete, Key::Right, Key::Enter,
+        ]);
+    }
+
+    #[test]
+    fn edits_lines() {
+        assert_eq!(read_line(b"helo\x7flo\r").unwrap(), b"hello");
+        assert_eq!(read_line(b"hxllo\x1b[D\x1b[D\x1b[D\x7fe\r").unwrap(), b"hello");
+        assert_eq!(read_line(b"hello\x1b[H\x1b[3~j\r").unwrap(), b"jello");
+        assert_eq!(read_line(b"rm -rf \x15ls\r").unwrap(), b"ls");
+        assert_eq!(read_line(b"cat foo  \x17bar\r").unwrap(), b"cat bar");
+    }
+
+    #[test]
+    fn rejects_input_past_capacity() {
+        let line = read_line(b"0123456789abcdefXYZ\r").unwrap();
+        assert_eq!(line, b"0123456789abcdef");
+    }
+
+    #[test]
+    fn reports_interrupt_and_eof() {
+        assert_eq!(read_line(b"abc\x03"), Err(Error::Interrupted));
+        assert_eq!(read_line(b"\x04"), Err(Error::EndOfFile));
+        assert_eq!(read_line(b"a\x04\r").unwrap(), b"a");
+    }
+
+    #[test]
+    fn echoes_edits() {
+        let mut tty = MockTty::new(b"ab\x1b[D\x7f\r");
+        let mut buf = [0u8; 16];
+        LineDiscipline::new().read_line(&mut tty, &mut buf).unwrap();
+        assert_eq!(tty.output, b"ab\x08\x08b \x08\x08\r\n");
+    }
+
+    #[test]
+    fn recalls_history() {
+        let mut history = History::new(2);
+        for line in ["ls", "ls", "  ", "cd /", "pwd"].iter() {
+            history.push(line);
+        }
+        assert_eq!(history.iter().collect::<Vec<_>>(), vec!["cd /", "pwd"]);
+
+        let read = |input: &[u8]| {
+            let mut tty = MockTty::new(input);
+            let mut buf = [0u8; 16];
+            let len = LineDiscipline::new().read_line_with_history(&mut tty, &mut buf, &history).unwrap();
+            buf[..len].to_vec()
+        };
+        assert_eq!(read(b"\x1b[A\r"), b"pwd");
+        assert_eq!(read(b"\x1b[A\x1b[A\x1b[A\r"), b"cd /");
+        assert_eq!(read(b"\x1b[A\x1b[A\x1b[B!\r"), b"pwd!");
+        // Down past the newest entry brings back what was typed.
+        assert_eq!(read(b"ec\x1b[A\x1b[Bho\r"), b"echo");
+        assert_eq!(read(b"\x1b[B\r"), b"");
+    }
+
+    #[test]
+    fn raw_mode_passes_bytes_through() {
+        let mut tty = MockTty::new(b"\x1b\x7f");
+        let mut discipline = LineDiscipline::new();
+        discipline.set_mode(Mode::Raw);
+        discipline.set_echo(false);
+        let mut buf = [0u8; 4];
+        assert_eq!(discipline.read(&mut tty, &mut buf), Ok(1));
+        assert_eq!(buf[0], ESC);
+        assert_eq!(discipline.read(&mut tty, &mut buf), Ok(1));
+        assert_eq!(buf[0], DEL);
+        assert!(tty.output.is_empty());
+    }
+}
@@ -0,0 +1,165 @@
+//! Console multiplexing: the console, a control channel and the kernel log
+//! share the UART, framed by the `serial_mux` protocol, so that a host tool
+//! can transfer files and stream logs while the shell is in use.
+//!
+//! Once `enable()` is called, console output is sent on `Channel::Console`
+//! and log records on `Channel::Log`, and only console frames are read as
+//! console input. Requests on `Channel::Control` are queued by whoever reads
+//! the UART and served by the control thread, which also polls the UART so
+//! that requests arrive while nothing reads the console. Telemetry samples
+//! are sent on the control channel too; see `telemetry`.
+
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use fat32::traits::FileSystem as _;
+use fat32::vfat::{Entry, File};
+use serial_mux::control::{Message, MAX_DATA};
+use serial_mux::Channel;
+use shim::io::{self, Read, Write};
+use shim::newioerr;
+
+use crate::console::{kprintln, CONSOLE};
+use crate::fs::PiVFatHandle;
+use crate::mutex::Mutex;
+use crate::process::{Id, Process};
+use crate::telemetry;
+use crate::{FILESYSTEM, SCHEDULER};
+
+/// How often the control thread polls the UART.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The most control requests queued; more are dropped.
+const MAX_REQUESTS: usize = 16;
+
+/// Control frame payloads waiting for the control thread.
+static REQUESTS: Mutex<Option<VecDeque<Vec<u8>>>> = Mutex::new(None);
+
+/// Multiplexes the UART and starts the control thread.
+///
+/// The caller should assure that `FILESYSTEM` and `SCHEDULER` have been
+/// initialized. Returns the ID of the control thread.
+pub fn enable() -> Option<Id> {
+    let thread = match Process::kernel_thread(control_thread) {
+        Ok(thread) => thread,
+        Err(e) => {
+            kprintln!("mux: cannot start control thread: {:?}", e);
+            return None;
+        }
+    };
+    *REQUESTS.lock() = Some(VecDeque::new());
+    CONSOLE.lock().enable_mux();
+    SCHEDULER.add(thread)
+}
+
+/// Queues the payload of a control frame for the control thread.
+pub(super) fn queue_request(payload: &[u8]) {
+    if let Some(requests) = REQUESTS.lock().as_mut() {
+        if requests.len() < MAX_REQUESTS {
+            requests.push_back(payload.to_vec());
+        }
+    }
+}
+
+/// Removes and returns the oldest queued control request, if any.
+fn next_request() -> Option<Vec<u8>> {
+    REQUESTS.lock().as_mut()?.pop_front()
+}
+
+/// Sends `message` on the control channel.
+pub fn send(message: Message) {
+    let mut payload = Vec::with_capacity(MAX_DATA + 1);
+    message.encode(|byte| payload.push(byte));
+    CONSOLE.lock().write_channel(Channel::Control, &payload);
+}
+
+/// Sends `e` as an `Error` message.
+fn send_error(e: io::Error) {
+    send(Message::Error(&format!("{:?}", e)));
+}
+
+/// Creates the file at `path`, replacing any file there.
+fn create_file(path: &str) -> io::Result<File<PiVFatHandle>> {
+    let (parent, name) = match path.rfind('/') {
+        Some(0) => ("/", &path[1..]),
+        Some(i) => (&path[..i], &path[i + 1..]),
+        None => return Err(newioerr!(InvalidInput, "path is not absolute")),
+    };
+    let dir = FILESYSTEM.open_dir(parent)?;
+    match dir.find(name) {
+        Ok(Entry::File(_)) => dir.remove(name)?,
+        Ok(Entry::Dir(_)) => return Err(newioerr!(AlreadyExists, "path is a directory")),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e),
+    }
+    dir.create_file(name)
+}
+
+/// Sends the contents of the file at `path` in `Data` messages, then `Done`.
+fn send_file(path: &str) -> io::Result<()> {
+    let mut file = FILESYSTEM.open_file(path)?;
+    let mut buf = [0; MAX_DATA];
+    loop {
+        match file.read(&mut buf)? {
+            0 => break,
+            n => send(Message::Data(&buf[..n])),
+        }
+    }
+    send(Message::Done);
+    Ok(())
+}
+
+/// Serves the control request `payload`. `upload` is the file being written
+/// by a `Put` request, if any.
+fn serve(payload: &[u8], upload: &mut Option<File<PiVFatHandle>>) {
+    let message = match Message::parse(payload) {
+        Some(message) => message,
+        None => return send(Message::Error("malformed request")),
+    };
+    let result = match message {
+        Message::Ping => {
+            send(Message::Pong);
+            Ok(())
+        }
+        Message::Get(path) => send_file(path),
+        Message::Put(path) => create_file(path).map(|file| {
+            *upload = Some(file);
+            send(Message::Ack);
+        }),
+        Message::Data(data) => match upload.as_mut() {
+            Some(file) => file.write_all(data).map(|_| send(Message::Ack)),
+            None => Err(newioerr!(InvalidInput, "no transfer in progress")),
+        },
+        Message::Done => match upload.take() {
+            Some(mut file) => file.flush().map(|_| send(Message::Ack)),
+            None => Err(newioerr!(InvalidInput, "no transfer in progress")),
+        },
+        Message::Telemetry(0) => {
+            telemetry::stop();
+            send(Message::Ack);
+            Ok(())
+        }
+        Message::Telemetry(ms) => {
+            telemetry::start(Duration::from_millis(ms as u64)).map(|_| send(Message::Ack))
+        }
+        _ => Err(newioerr!(InvalidInput, "unexpected message")),
+    };
+    if let Err(e) = result {
+        // An error ends any transfer.
+        *upload = None;
+        send_error(e);
+    }
+}
+
+extern "C" fn control_thread() -> ! {
+    let mut upload = None;
+    loop {
+        CONSOLE.lock().poll_input();
+        while let Some(request) = next_request() {
+            serve(&request, &mut upload);
+        }
+        let _ = kernel_api::syscall::sleep(POLL_INTERVAL);
+    }
+}
@@ -0,0 +1,398 @@
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// RGB values of the 16 ANSI colors, indexed by `Color`. Indices 8-15 are the
+/// bright variants of 0-7.
+pub const PALETTE: [u32; 16] = [
+    0x000000, 0xAA0000, 0x00AA00, 0xAA5500, 0x0000AA, 0xAA00AA, 0x00AAAA, 0xAAAAAA,
+    0x555555, 0xFF5555, 0x55FF55, 0xFFFF55, 0x5555FF, 0xFF55FF, 0x55FFFF, 0xFFFFFF,
+];
+
+/// An index into `PALETTE`.
+pub type Color = u8;
+
+const DEFAULT_FG: Color = 7;
+const DEFAULT_BG: Color = 0;
+
+/// The maximum number of parameters kept from a single escape sequence.
+const MAX_PARAMS: usize = 8;
+
+const ESC: u8 = 0x1b;
+
+/// The byte of the cell of a non-ASCII character, which the font has no
+/// glyph for.
+const NON_ASCII: u8 = 0x7f;
+
+/// A character cell of the screen.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Cell {
+    pub byte: u8,
+    pub fg: Color,
+    pub bg: Color,
+}
+
+impl Cell {
+    const BLANK: Cell = Cell { byte: b' ', fg: DEFAULT_FG, bg: DEFAULT_BG };
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    Csi,
+}
+
+/// A text screen with scrollback, interpreting the ANSI SGR escape sequences
+/// used for colored output. This is the backend-independent part of a
+/// graphical console: a renderer draws `visible_lines()` whenever
+/// `take_dirty()` returns `true`.
+#[derive(Debug)]
+pub struct Screen {
+    cols: usize,
+    rows: usize,
+    scrollback: usize,
+    /// All retained lines, oldest first. The last `rows` lines are live.
+    lines: VecDeque<Vec<Cell>>,
+    row: usize,
+    col: usize,
+    fg: Color,
+    bg: Color,
+    bold: bool,
+    state: State,
+    params: [u16; MAX_PARAMS],
+    nparams: usize,
+    /// The continuation bytes still expected of a UTF-8 sequence.
+    continuation: u8,
+    /// How many lines the view is scrolled back from the live area.
+    view_offset: usize,
+    dirty: bool,
+}
+
+impl Screen {
+    /// Returns a blank `cols` x `rows` screen retaining up to `scrollback`
+    /// lines that have scrolled off the top.
+    pub fn new(cols: usize, rows: usize, scrollback: usize) -> Screen {
+        let mut lines = VecDeque::with_capacity(rows + scrollback);
+        for _ in 0..rows {
+            lines.push_back(vec![Cell::BLANK; cols]);
+        }
+        Screen {
+            cols,
+            rows,
+            scrollback,
+            lines,
+            row: 0,
+            col: 0,
+            fg: DEFAULT_FG,
+            bg: DEFAULT_BG,
+            bold: false,
+            state: State::Ground,
+            params: [0; MAX_PARAMS],
+            nparams: 0,
+            continuation: 0,
+            view_offset: 0,
+            dirty: true,
+        }
+    }
+
+    /// Returns the index in `lines` of the live row `row`.
+    fn live(&self, row: usize) -> usize {
+        self.lines.len() - self.rows + row
+    }
+
+    /// Returns the cursor position as `(row, col)`.
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.row, self.col)
+    }
+
+    /// Writes `byte`, interpreting control characters and escape sequences.
+    pub fn write_byte(&mut self, byte: u8) {
+        self.dirty = true;
+        match self.state {
+            State::Ground => self.ground(byte),
+            State::Escape => {
+                self.state = match byte {
+                    b'[' => {
+                        self.params = [0; MAX_PARAMS];
+                        self.nparams = 1;
+                        State::Csi
+                    }
+                    _ => State::Ground,
+                }
+            }
+            State::Csi => match byte {
+                b'0'..=b'9' => {
+                    let param = &mut self.params[self.nparams - 1];
+                    *param = param.saturating_mul(10).saturating_add((byte - b'0') as u16);
+                }
+                b';' => self.nparams = (self.nparams + 1).min(MAX_PARAMS),
+                0x40..=0x7e => {
+                    self.state = State::Ground;
+                    self.dispatch(byte);
+                }
+                _ => {}
+            },
+        }
+    }
+
+    fn ground(&mut self, byte: u8) {
+        // A non-ASCII character takes one cell, whatever its length in UTF-8;
+        // the bytes of an invalid sequence take one each.
+        match byte {
+            0x80..=0xbf if self.continuation > 0 => {
+                self.continuation -= 1;
+                return;
+            }
+            0x80..=0xff => {
+                self.continuation = match byte {
+                    0xc2..=0xdf => 1,
+                    0xe0..=0xef => 2,
+                    0xf0..=0xf4 => 3,
+                    _ => 0,
+                };
+                return self.put(NON_ASCII);
+            }
+            _ => self.continuation = 0,
+        }
+        match byte {
+            ESC => self.state = State::Escape,
+            b'\n' => self.newline(),
+            b'\r' => self.col = 0,
+            0x08 => self.col = self.col.saturating_sub(1),
+            b'\t' => self.col = ((self.col / 8 + 1) * 8).min(self.cols - 1),
+            0x20..=0x7e => self.put(byte),
+            _ => {}
+        }
+    }
+
+    /// Writes a cell with `byte` at the cursor, wrapping at the end of the line.
+    fn put(&mut self, byte: u8) {
+        if self.col == self.cols {
+            self.col = 0;
+            self.newline();
+        }
+        let fg = if self.bold && self.fg < 8 { self.fg + 8 } else { self.fg };
+        let index = self.live(self.row);
+        self.lines[index][self.col] = Cell { byte, fg, bg: self.bg };
+        self.col += 1;
+    }
+
+    fn newline(&mut self) {
+        if self.row + 1 < self.rows {
+            self.row += 1;
+            return;
+        }
+        self.lines.push_back(vec![Cell::BLANK; self.cols]);
+        if self.lines.len() > self.rows + self.scrollback {
+            self.lines.pop_front();
+        } else if self.view_offset > 0 {
+            // Keep a scrolled-back view on the same lines.
+            self.view_offset += 1;
+        }
+    }
+
+    /// Executes the control sequence with final byte `byte`.
+    fn dispatch(&mut self, byte: u8) {
+        let params = self.params;
+        let params = &params[..self.nparams];
+        match byte {
+            b'm' => params.iter().for_each(|&p| self.sgr(p)),
+            // Erase in line, from the cursor to the end.
+            b'K' => {
+                let (index, col) = (self.live(self.row), self.col);
+                for cell in self.lines[index][col.min(self.cols)..].iter_mut() {
+                    *cell = Cell::BLANK;
+                }
+            }
+            // Erase in display (entire screen) and home the cursor.
+            b'J' if params[0] == 2 => {
+                for row in 0..self.rows {
+                    let index = self.live(row);
+                    self.lines[index] = vec![Cell::BLANK; self.cols];
+                }
+                self.row = 0;
+                self.col = 0;
+            }
+            _ => {}
+        }
+    }
+
+    /// Applies the Select Graphic Rendition parameter `param`.
+    fn sgr(&mut self, param: u16) {
+        match param {
+            0 => {
+                self.fg = DEFAULT_FG;
+                self.bg = DEFAULT_BG;
+                self.bold = false;
+            }
+            1 => self.bold = true,
+            22 => self.bold = false,
+            30..=37 => self.fg = (param - 30) as Color,
+            39 => self.fg = DEFAULT_FG,
+            40..=47 => self.bg = (param - 40) as Color,
+            49 => self.bg = DEFAULT_BG,
+            90..=97 => self.fg = (param - 90 + 8) as Color,
+            100..=107 => self.bg = (param - 100 + 8) as Color,
+            _ => {}
+        }
+    }
+
+    /// Scrolls the view `lines` lines back into the scrollback (negative
+    /// values scroll towards the live area), clamped to the retained lines.
+    pub fn scroll_view(&mut self, lines: isize) {
+        let max = self.lines.len() - self.rows;
+        let offset = (self.view_offset as isize + lines).max(0) as usize;
+        self.view_offset = offset.min(max);
+        self.dirty = true;
+    }
+
+    /// Scrolls the view back by a page.
+    pub fn page_up(&mut self) {
+        self.scroll_view(self.rows as isize);
+    }
+
+    /// Scrolls the view forward by a page.
+    pub fn page_down(&mut self) {
+        self.scroll_view(-(self.rows as isize));
+    }
+
+    /// Returns the view to the live area.
+    pub fn reset_view(&mut self) {
+        self.scroll_view(-(self.view_offset as isize));
+    }
+
+    /// Returns the `rows` lines currently in view, top first.
+    pub fn visible_lines(&self) -> impl Iterator<Item = &[Cell]> {
+        let start = self.lines.len() - self.rows - self.view_offset;
+        self.lines.range(start..start + self.rows).map(|line| line.as_slice())
+    }
+
+    /// Returns the screen's size as `(cols, rows)`.
+    pub fn size(&self) -> (usize, usize) {
+        (self.cols, self.rows)
+    }
+
+    /// Changes the screen's size to `cols` x `rows`, keeping the retained
+    /// lines: lines are cut or padded to the new width rather than
+    /// rewrapped, and the live area stays at the bottom, taking lines from
+    /// the scrollback as it grows. Blank lines below the cursor are dropped
+    /// first as it shrinks. The view returns to the live area.
+    pub fn resize(&mut self, cols: usize, rows: usize) {
+        let mut cursor = self.live(self.row);
+        while self.lines.len() - cursor > rows {
+            self.lines.pop_back();
+        }
+        while self.lines.len() < rows {
+            self.lines.push_front(vec![Cell::BLANK; cols]);
+            cursor += 1;
+        }
+        while self.lines.len() > rows + self.scrollback {
+            self.lines.pop_front();
+            cursor -= 1;
+        }
+        for line in self.lines.iter_mut() {
+            line.resize(cols, Cell::BLANK);
+        }
+        self.cols = cols;
+        self.rows = rows;
+        self.row = cursor - (self.lines.len() - rows);
+        self.col = self.col.min(cols);
+        self.view_offset = 0;
+        self.dirty = true;
+    }
+
+    /// Returns `true` if the screen changed since the last call.
+    pub fn take_dirty(&mut self) -> bool {
+        core::mem::replace(&mut self.dirty, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(screen: &mut Screen, s: &str) {
+        s.bytes().for_each(|b| screen.write_byte(b));
+    }
+
+    fn text(line: &[Cell]) -> String {
+        line.iter().map(|c| c.byte as char).collect::<String>().trim_end().to_string()
+    }
+
+    #[test]
+    fn applies_sgr_colors() {
+        let mut screen = Screen::new(10, 2, 0);
+        write(&mut screen, "a\x1b[31;44mb\x1b[1mc\x1b[0md");
+        let line: Vec<Cell> = screen.visible_lines().next().unwrap().to_vec();
+        assert_eq!((line[0].fg, line[0].bg), (DEFAULT_FG, DEFAULT_BG));
+        assert_eq!((line[1].fg, line[1].bg), (1, 4));
+        assert_eq!((line[2].fg, line[2].bg), (9, 4));
+        assert_eq!((line[3].fg, line[3].bg), (DEFAULT_FG, DEFAULT_BG));
+    }
+
+    #[test]
+    fn gives_non_ascii_characters_one_cell() {
+        let mut screen = Screen::new(10, 1, 0);
+        write(&mut screen, "a\u{e9}\u{20ac}\u{1f600}b");
+        screen.write_byte(0xff);
+        write(&mut screen, "c");
+        let line: Vec<Cell> = screen.visible_lines().next().unwrap().to_vec();
+        let bytes: Vec<u8> = line.iter().map(|c| c.byte).collect();
+        assert_eq!(bytes[..7], [b'a', NON_ASCII, NON_ASCII, NON_ASCII, b'b', NON_ASCII, b'c']);
+    }
+
+    #[test]
+    fn keeps_scrollback() {
+        let mut screen = Screen::new(8, 2, 2);
+        write(&mut screen, "1\n2\n3\n4\n5");
+        let view: Vec<String> = screen.visible_lines().map(text).collect();
+        assert_eq!(view, vec!["4", "5"]);
+
+        screen.page_up();
+        let view: Vec<String> = screen.visible_lines().map(text).collect();
+        assert_eq!(view, vec!["2", "3"]);
+
+        screen.scroll_view(10);
+        let view: Vec<String> = screen.visible_lines().map(text).collect();
+        assert_eq!(view, vec!["2", "3"]);
+
+        screen.reset_view();
+        let view: Vec<String> = screen.visible_lines().map(text).collect();
+        assert_eq!(view, vec!["4", "5"]);
+    }
+
+    #[test]
+    fn resizes_keeping_scrollback() {
+        let mut screen = Screen::new(8, 3, 4);
+        write(&mut screen, "1\n2\n3\n4\nfifth");
+        screen.resize(3, 4);
+        let view: Vec<String> = screen.visible_lines().map(text).collect();
+        assert_eq!(view, vec!["2", "3", "4", "fif"]);
+        assert_eq!(screen.cursor(), (3, 3));
+
+        screen.resize(8, 2);
+        let view: Vec<String> = screen.visible_lines().map(text).collect();
+        assert_eq!(view, vec!["4", "fif"]);
+        screen.page_up();
+        let view: Vec<String> = screen.visible_lines().map(text).collect();
+        assert_eq!(view, vec!["2", "3"]);
+
+        // Blank lines under the cursor go before the lines above it.
+        let mut screen = Screen::new(4, 4, 0);
+        write(&mut screen, "a\nb");
+        screen.resize(4, 2);
+        let view: Vec<String> = screen.visible_lines().map(text).collect();
+        assert_eq!(view, vec!["a", "b"]);
+        assert_eq!(screen.cursor(), (1, 1));
+    }
+
+    #[test]
+    fn wraps_long_lines() {
+        let mut screen = Screen::new(4, 2, 0);
+        write(&mut screen, "abcdef");
+        let view: Vec<String> = screen.visible_lines().map(text).collect();
+        assert_eq!(view, vec!["abcd", "ef"]);
+        assert_eq!(screen.cursor(), (1, 2));
+    }
+}
@@ -0,0 +1,74 @@
+//! Console output through ARM semihosting, for runs under QEMU.
+//!
+//! With `console=semihost` on the kernel command line, or by default in
+//! `qemu` builds, `kprint!` output and log records are handed to the
+//! emulator with `SYS_WRITE0` instead of being clocked out of an emulated
+//! UART, which is much faster. Console input still comes from the UART.
+//!
+//! QEMU only services semihosting calls when started with `-semihosting`;
+//! otherwise the `HLT` instruction faults. A running kernel cannot tell, so
+//! this backend is only built with the `semihosting` feature.
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// The semihosting operation that writes a NUL-terminated string to the
+/// debug console.
+const SYS_WRITE0: u64 = 0x04;
+
+/// The most bytes handed to the emulator per call.
+const CHUNK_SIZE: usize = 256;
+
+/// Whether console output goes through semihosting. `qemu` builds always
+/// run under QEMU with semihosting enabled; see `make qemu-test`.
+static ENABLED: AtomicBool = AtomicBool::new(cfg!(feature = "qemu"));
+
+/// Routes console output through semihosting from now on, or back to the
+/// console UART.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns `true` if console output goes through semihosting.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Collects output into NUL-terminated chunks for `SYS_WRITE0`.
+struct Writer {
+    buf: [u8; CHUNK_SIZE + 1],
+    len: usize,
+}
+
+impl Writer {
+    /// Hands the collected bytes to the emulator.
+    fn flush(&mut self) {
+        if self.len == 0 {
+            return;
+        }
+        self.buf[self.len] = 0;
+        unsafe { aarch64::semihosting_call(SYS_WRITE0, self.buf.as_ptr() as u64) };
+        self.len = 0;
+    }
+}
+
+impl fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // A NUL would end the string early; it has no business on a console.
+        for byte in s.bytes().filter(|&byte| byte != 0) {
+            if self.len == CHUNK_SIZE {
+                self.flush();
+            }
+            self.buf[self.len] = byte;
+            self.len += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Writes `args` to the emulator's console.
+pub fn print(args: fmt::Arguments) {
+    let mut writer = Writer { buf: [0; CHUNK_SIZE + 1], len: 0 };
+    let _ = fmt::Write::write_fmt(&mut writer, args);
+    writer.flush();
+}
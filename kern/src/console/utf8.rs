@@ -0,0 +1,209 @@
+//! Printing bytes that should be UTF-8 text but may not be, such as file
+//! contents.
+//!
+//! `Utf8Decoder` turns a stream of bytes into text a chunk at a time, keeping
+//! a character split between chunks until it is complete. Invalid sequences
+//! become U+FFFD, one per maximal invalid prefix as other decoders do, so
+//! that they cannot corrupt the terminal's own decoding.
+
+use core::fmt;
+use core::str;
+
+use shim::io;
+
+use crate::console::kprint;
+
+/// The character invalid sequences decode to.
+pub const REPLACEMENT: char = '\u{FFFD}';
+
+const REPLACEMENT_STR: &str = "\u{FFFD}";
+
+/// The longest UTF-8 sequence.
+const MAX_SEQUENCE: usize = 4;
+
+/// A streaming UTF-8 decoder.
+#[derive(Debug, Default)]
+pub struct Utf8Decoder {
+    /// The start of a character split at the end of the last chunk.
+    pending: [u8; MAX_SEQUENCE],
+    len: usize,
+}
+
+impl Utf8Decoder {
+    pub const fn new() -> Utf8Decoder {
+        Utf8Decoder { pending: [0; MAX_SEQUENCE], len: 0 }
+    }
+
+    /// Decodes `bytes` into `out`, following the bytes of earlier calls. A
+    /// character split at the end of `bytes` is written once the next call
+    /// completes it.
+    pub fn decode<W: fmt::Write>(&mut self, mut bytes: &[u8], out: &mut W) -> fmt::Result {
+        // Complete the pending character a byte at a time: it needs at most
+        // three more.
+        while self.len > 0 && !bytes.is_empty() {
+            self.pending[self.len] = bytes[0];
+            self.len += 1;
+            bytes = &bytes[1..];
+            match str::from_utf8(&self.pending[..self.len]) {
+                Ok(s) => {
+                    out.write_str(s)?;
+                    self.len = 0;
+                }
+                Err(e) => if let Some(invalid) = e.error_len() {
+                    // The bytes after the invalid prefix start afresh.
+                    let (rest, len) = (self.pending, self.len);
+                    self.len = 0;
+                    out.write_str(REPLACEMENT_STR)?;
+                    self.decode(&rest[invalid..len], out)?;
+                }
+            }
+        }
+        loop {
+            match str::from_utf8(bytes) {
+                Ok(s) => return out.write_str(s),
+                Err(e) => {
+                    let (valid, rest) = bytes.split_at(e.valid_up_to());
+                    // `from_utf8()` checked the bytes up to `valid_up_to()`.
+                    out.write_str(unsafe { str::from_utf8_unchecked(valid) })?;
+                    match e.error_len() {
+                        Some(invalid) => {
+                            out.write_str(REPLACEMENT_STR)?;
+                            bytes = &rest[invalid..];
+                        }
+                        None => {
+                            self.pending[..rest.len()].copy_from_slice(rest);
+                            self.len = rest.len();
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Ends the stream, writing U+FFFD for a character it cut short.
+    pub fn finish<W: fmt::Write>(&mut self, out: &mut W) -> fmt::Result {
+        if self.len == 0 {
+            return Ok(());
+        }
+        self.len = 0;
+        out.write_str(REPLACEMENT_STR)
+    }
+}
+
+/// Writes text with `kprint!`.
+#[derive(Debug, Default)]
+pub struct Printer;
+
+impl fmt::Write for Printer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        kprint!("{}", s);
+        Ok(())
+    }
+}
+
+/// Writes the bytes written to it as text to `W`, the console by default,
+/// decoding them with a `Utf8Decoder`.
+#[derive(Debug)]
+pub struct TextWriter<W: fmt::Write = Printer> {
+    inner: W,
+    decoder: Utf8Decoder,
+}
+
+impl TextWriter {
+    /// Returns a writer to the console.
+    pub fn console() -> TextWriter {
+        TextWriter::new(Printer)
+    }
+}
+
+impl<W: fmt::Write> TextWriter<W> {
+    pub fn new(inner: W) -> TextWriter<W> {
+        TextWriter { inner, decoder: Utf8Decoder::new() }
+    }
+
+    /// Ends the text, writing U+FFFD for a character it cut short, and
+    /// returns the inner writer.
+    pub fn finish(mut self) -> Result<W, fmt::Error> {
+        self.decoder.finish(&mut self.inner)?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: fmt::Write> io::Write for TextWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.decoder.decode(buf, &mut self.inner) {
+            Ok(()) => Ok(buf.len()),
+            Err(_) => Err(io::Error::new(io::ErrorKind::Other, "formatter error")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns whether `sample`, the start of a file, looks like text: UTF-8,
+/// but for a character cut short at its end, with no control characters
+/// other than those of formatting and escape sequences.
+pub fn is_text(sample: &[u8]) -> bool {
+    let controls_ok = sample.iter().all(|&b| match b {
+        b'\t' | b'\n' | b'\r' | 0x08 | 0x0c | 0x1b => true,
+        0..=0x1f | 0x7f => false,
+        _ => true,
+    });
+    controls_ok && match str::from_utf8(sample) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    fn decode(chunks: &[&[u8]]) -> String {
+        let mut decoder = Utf8Decoder::new();
+        let mut out = String::new();
+        for chunk in chunks {
+            decoder.decode(chunk, &mut out).unwrap();
+        }
+        decoder.finish(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn joins_characters_split_between_chunks() {
+        let text = "h\u{e9}llo \u{20ac}\u{1f600}";
+        let bytes = text.as_bytes();
+        for i in 0..bytes.len() {
+            assert_eq!(decode(&[&bytes[..i], &bytes[i..]]), text);
+        }
+        let single: Vec<&[u8]> = bytes.chunks(1).collect();
+        assert_eq!(decode(&single), text);
+    }
+
+    #[test]
+    fn replaces_invalid_sequences() {
+        assert_eq!(decode(&[b"a\xffb"]), "a\u{fffd}b");
+        // A truncated sequence is one replacement; the byte ending it is kept.
+        assert_eq!(decode(&[b"a\xe2\x82b"]), "a\u{fffd}b");
+        assert_eq!(decode(&[b"a\xe2", b"\x82", b"b"]), "a\u{fffd}b");
+        assert_eq!(decode(&[b"\xe2", b"\xf0\x9f\x98\x80"]), "\u{fffd}\u{1f600}");
+        assert_eq!(decode(&[b"\x80\x80"]), "\u{fffd}\u{fffd}");
+        // A stream that ends mid-character.
+        assert_eq!(decode(&[b"ok\xf0\x9f"]), "ok\u{fffd}");
+    }
+
+    #[test]
+    fn detects_text() {
+        assert!(is_text(b"plain\ttext\r\n\x1b[1mbold\x1b[0m"));
+        assert!(is_text("caf\u{e9}".as_bytes()));
+        // A sample can end mid-character.
+        assert!(is_text(&"\u{20ac}".as_bytes()[..2]));
+        assert!(!is_text(b"ELF\x00\x01"));
+        assert!(!is_text(b"\xff\xfe"));
+    }
+}
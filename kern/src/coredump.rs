@@ -0,0 +1,187 @@
+//! Core dumps of user processes killed by faults.
+//!
+//! A dump is captured in the fault handler, while the process's pages are
+//! still mapped, and queued. A kernel thread writes queued dumps to
+//! `CORE_DIR/<pid>.cor`, so the fault path never waits for the SD card.
+//! Only 8.3 names can be created on the file system, hence the extension.
+//!
+//! A dump is a text header followed by the raw bytes of the stack:
+//!
+//! ```text
+//! RUSTOS CORE 1
+//! pid 3
+//! fault DataAbort { .. } elr 0x.. far 0x..
+//!
+//! [registers]
+//! ...
+//!
+//! [memory map]
+//! <one line per region>
+//!
+//! [stack 0xffffffffffff0000 65536]
+//! <65536 bytes>
+//! ```
+//!
+//! Dumps are cut off at the configured size limit; the stack header gives
+//! the number of stack bytes that follow.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write as _;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use fat32::traits::FileSystem as _;
+use fat32::vfat::{Dir, Entry};
+use shim::io::{self, Write};
+use shim::newioerr;
+
+use crate::console::kprintln;
+use crate::fs::PiVFatHandle;
+use crate::logger::{info, warn};
+use crate::mutex::Mutex;
+use crate::process::{Id, Process};
+use crate::traps::TrapFrame;
+use crate::{FILESYSTEM, SCHEDULER};
+
+/// The directory dumps are written to.
+pub const CORE_DIR: &str = "/cores";
+
+/// The first line of every dump.
+const MAGIC: &str = "RUSTOS CORE 1";
+
+/// The most dumps waiting to be written; further dumps are dropped.
+const MAX_PENDING: usize = 4;
+
+/// How often queued dumps are written.
+const WRITE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Set once dumps are enabled with `enable()`.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The largest dump written, in bytes.
+static LIMIT: AtomicUsize = AtomicUsize::new(0);
+
+static PENDING: Mutex<Vec<(Id, Vec<u8>)>> = Mutex::new(Vec::new());
+
+/// Starts the thread that writes dumps and captures a dump of each process
+/// killed by a fault from now on. Dumps are cut off at `limit` bytes.
+///
+/// The caller should assure that `FILESYSTEM` and `SCHEDULER` have been
+/// initialized. Returns the ID of the writer thread.
+pub fn enable(limit: usize) -> Option<Id> {
+    let thread = match Process::kernel_thread(writer_thread) {
+        Ok(thread) => thread,
+        Err(e) => {
+            kprintln!("coredump: cannot start writer thread: {:?}", e);
+            return None;
+        }
+    };
+    LIMIT.store(limit, Ordering::Relaxed);
+    ENABLED.store(true, Ordering::Relaxed);
+    SCHEDULER.add(thread)
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Captures a dump of `process`, whose registers are in `tf`, for a fault
+/// described by `reason`, and queues it to be written.
+pub fn capture(process: &Process, tf: &TrapFrame, reason: &str) {
+    if !enabled() {
+        return;
+    }
+    let pid = tf.tpidr;
+    let mut pending = PENDING.lock();
+    if pending.len() == MAX_PENDING {
+        warn!("coredump: dropping dump of process {}", pid);
+        return;
+    }
+    pending.push((pid, format_core(process, tf, reason, LIMIT.load(Ordering::Relaxed))));
+}
+
+/// Formats the dump of `process`, cut off at `limit` bytes.
+fn format_core(process: &Process, tf: &TrapFrame, reason: &str, limit: usize) -> Vec<u8> {
+    let mut header = String::new();
+    let _ = writeln!(header, "{}", MAGIC);
+    let _ = writeln!(header, "pid {}", tf.tpidr);
+    let _ = writeln!(header, "fault {}", reason);
+
+    let _ = writeln!(header, "\n[registers]");
+    let _ = writeln!(header, "elr   {:#018x}  spsr  {:#018x}", tf.elr, tf.spsr);
+    let _ = writeln!(header, "sp    {:#018x}  tpidr {:#018x}", tf.sp, tf.tpidr);
+    for (i, x) in tf.x_registers.iter().enumerate() {
+        let _ = writeln!(header, "x{:02}   {:#018x}", i, x);
+    }
+
+    let _ = writeln!(header, "\n[memory map]");
+    let regions = process.vmap.regions(Process::get_image_base());
+    for region in regions.iter() {
+        let _ = writeln!(header, "{}", region);
+    }
+
+    // The stack is the region holding the stack base. The kernel maps all
+    // of physical memory, so it is read through its physical address.
+    let stack_base = Process::get_stack_base();
+    let base = stack_base.as_u64();
+    let region = regions.iter().find(|r| r.start.as_u64() <= base && base < r.end.as_u64());
+    let stack: &[u8] = match region {
+        Some(region) => unsafe {
+            core::slice::from_raw_parts(
+                region.phys.as_usize() as *const u8,
+                region.end.as_usize() - region.start.as_usize(),
+            )
+        },
+        None => &[],
+    };
+    let room = limit.saturating_sub(header.len() + 64);
+    let stack = &stack[..stack.len().min(room)];
+    let _ = writeln!(header, "\n[stack {:#x} {}]", stack_base.as_usize(), stack.len());
+
+    let mut dump = header.into_bytes();
+    dump.extend_from_slice(stack);
+    dump.truncate(limit);
+    dump
+}
+
+extern "C" fn writer_thread() -> ! {
+    loop {
+        let pending = core::mem::replace(&mut *PENDING.lock(), Vec::new());
+        for (pid, dump) in pending {
+            match write_dump(pid, &dump) {
+                Ok(path) => info!("coredump: process {} dumped to {}", pid, path),
+                Err(e) => warn!("coredump: cannot write dump of process {}: {:?}", pid, e),
+            }
+        }
+        let _ = kernel_api::syscall::sleep(WRITE_INTERVAL);
+    }
+}
+
+/// Writes `dump` to `CORE_DIR/<pid>.cor`, replacing any older dump, and
+/// returns the path.
+fn write_dump(pid: Id, dump: &[u8]) -> io::Result<String> {
+    let dir = open_core_dir()?;
+    let name = format!("{}.cor", pid);
+    if let Err(e) = dir.remove(&name) {
+        if e.kind() != io::ErrorKind::NotFound {
+            return Err(e);
+        }
+    }
+    let mut file = dir.create_file(&name)?;
+    file.write_all(dump)?;
+    file.flush()?;
+    Ok(format!("{}/{}", CORE_DIR, name))
+}
+
+/// Opens `CORE_DIR`, creating it if needed.
+fn open_core_dir() -> io::Result<Dir<PiVFatHandle>> {
+    let root = FILESYSTEM.open_dir("/")?;
+    match root.find(&CORE_DIR[1..]) {
+        Ok(Entry::Dir(dir)) => Ok(dir),
+        Ok(Entry::File(_)) => Err(newioerr!(AlreadyExists, "not a directory")),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => root.create_dir(&CORE_DIR[1..]),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,203 @@
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+
+use aarch64::{frame_pointer, ELR_EL1, ESR_EL1, FAR_EL1, SPSR_EL1, SP};
+use fat32::MasterBootRecord;
+use fat32::traits::{BlockDevice, Entry, FileSystem};
+use pi::timer::current_time;
+use shim::io;
+use shim::ioerr;
+
+use crate::console::CONSOLE;
+use crate::logger::warn;
+use crate::fs::Sd;
+use crate::ksyms;
+use crate::mutex::Mutex;
+use crate::traps::frames;
+use crate::{FILESYSTEM, SCHEDULER};
+
+/// The file whose sectors receive crash dumps. It must be created with a
+/// fixed, non-zero size before boot, e.g. by copying `DUMP_SIZE` zero bytes
+/// to the boot partition; its contents are overwritten on every panic.
+pub const DUMP_FILE: &str = "/crash.dmp";
+
+/// The size of a crash dump. Longer dumps are truncated.
+const DUMP_SIZE: usize = 32 * 1024;
+
+/// The first sector of the raw dump area used when `DUMP_FILE` is missing: the
+/// gap between the MBR and the first partition.
+const RAW_START: u64 = 1;
+
+/// The number of sectors needed for a full dump.
+const DUMP_SECTORS: u64 = (DUMP_SIZE / 512) as u64;
+
+/// The first line of every dump.
+const MAGIC: &str = "RUSTOS CRASH DUMP 1";
+
+/// Where dumps are written. Resolved at boot, while the file system is
+/// known to be consistent, so that writing a dump never touches it.
+enum Location {
+    /// The sectors holding `DUMP_FILE`.
+    File(Vec<u64>),
+    /// `count` consecutive sectors starting at `start`.
+    Raw { start: u64, count: u64 },
+}
+
+impl Location {
+    /// Returns the `i`th sector of the dump, if the location has one.
+    fn sector(&self, i: usize) -> Option<u64> {
+        match *self {
+            Location::File(ref sectors) => sectors.get(i).cloned(),
+            Location::Raw { start, count } if (i as u64) < count => Some(start + i as u64),
+            Location::Raw { .. } => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            Location::File(_) => DUMP_FILE,
+            Location::Raw { .. } => "raw sectors",
+        }
+    }
+}
+
+struct Target {
+    sd: Sd,
+    location: Location,
+}
+
+static TARGET: Mutex<Option<Target>> = Mutex::new(None);
+
+/// The dump is formatted here rather than on the heap, which may be the
+/// reason for the panic.
+static mut BUFFER: [u8; DUMP_SIZE] = [0; DUMP_SIZE];
+
+/// Finds the location for crash dumps: `DUMP_FILE` if it exists, otherwise
+/// the sectors between the MBR and the first partition if there is room.
+/// Dumps are disabled if neither is available.
+///
+/// The caller should assure that `FILESYSTEM` has been initialized.
+pub fn initialize() {
+    let mut sd = match FILESYSTEM.device() {
+        Some(sd) => sd,
+        None => return,
+    };
+
+    let file_sectors = (&FILESYSTEM)
+        .open(DUMP_FILE)
+        .ok()
+        .and_then(|entry| entry.into_file())
+        .and_then(|file| file.sectors().ok())
+        .filter(|sectors| !sectors.is_empty());
+    let location = match file_sectors {
+        Some(sectors) => Location::File(sectors),
+        None => {
+            let partition_start = match MasterBootRecord::from(sd) {
+                Ok(mbr) => mbr.partition_table.iter()
+                    .filter(|p| p.partition_type != 0)
+                    .map(|p| p.sector_offset as u64)
+                    .min()
+                    .unwrap_or(0),
+                Err(_) => 0,
+            };
+            let count = partition_start.saturating_sub(RAW_START).min(DUMP_SECTORS);
+            if count == 0 {
+                warn!("crash: no {} and no room before the first partition; dumps disabled", DUMP_FILE);
+                return;
+            }
+            Location::Raw { start: RAW_START, count }
+        }
+    };
+    // Make sure the card accepts reads before relying on it in a panic.
+    let mut buf = [0; 512];
+    if sd.read_sector(0, &mut buf).is_ok() {
+        *TARGET.lock() = Some(Target { sd, location });
+    }
+}
+
+/// A `fmt::Write` sink that fills a byte buffer and silently drops whatever
+/// does not fit.
+struct Cursor<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Write for Cursor<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// Formats the crash dump for `info` into `w`.
+fn format(w: &mut dyn Write, info: &PanicInfo) -> fmt::Result {
+    writeln!(w, "{}", MAGIC)?;
+    writeln!(w, "uptime {:?}", current_time())?;
+
+    writeln!(w, "\n[panic]")?;
+    if let Some(message) = info.message() {
+        writeln!(w, "{}", message)?;
+    }
+    if let Some(location) = info.location() {
+        writeln!(w, "at {}:{}:{}", location.file(), location.line(), location.column())?;
+    }
+
+    writeln!(w, "\n[registers]")?;
+    let fp = frame_pointer();
+    unsafe {
+        writeln!(w, "sp    {:#018x}  fp    {:#018x}", SP.get(), fp)?;
+        writeln!(w, "elr   {:#018x}  spsr  {:#018x}", ELR_EL1.get(), SPSR_EL1.get())?;
+        writeln!(w, "esr   {:#018x}  far   {:#018x}", ESR_EL1.get(), FAR_EL1.get())?;
+    }
+
+    writeln!(w, "\n[backtrace]")?;
+    for (depth, lr) in frames(fp).enumerate() {
+        match ksyms::lookup(lr) {
+            Some(symbol) => writeln!(w, "#{:<2} {:#018x} {}", depth, lr, symbol)?,
+            None => writeln!(w, "#{:<2} {:#018x}", depth, lr)?,
+        }
+    }
+
+    writeln!(w, "\n[scheduler]")?;
+    SCHEDULER.summarize(w)?;
+
+    writeln!(w, "\n[log]")?;
+    let console = CONSOLE.lock();
+    let (older, newer) = console.history();
+    for &byte in older.iter().chain(newer.iter()) {
+        w.write_char(if byte.is_ascii() { byte as char } else { '?' })?;
+    }
+    Ok(())
+}
+
+/// Writes a crash dump for the panic `info` to the location found by
+/// `initialize()`. Returns a description of where the dump went.
+///
+/// Does not allocate and does not use the file system.
+pub fn write(info: &PanicInfo) -> io::Result<&'static str> {
+    let mut guard = TARGET.lock();
+    let target = match guard.as_mut() {
+        Some(target) => target,
+        None => return ioerr!(NotFound, "no crash dump location"),
+    };
+
+    let buf = unsafe { &mut BUFFER };
+    let mut cursor = Cursor { buf: &mut buf[..], len: 0 };
+    let _ = format(&mut cursor, info);
+    let len = cursor.len;
+    // Clear the rest so that no part of an older dump survives.
+    for byte in buf[len..].iter_mut() {
+        *byte = 0;
+    }
+
+    for (i, block) in buf.chunks(512).enumerate() {
+        match target.location.sector(i) {
+            Some(sector) => target.sd.write_sector(sector, block)?,
+            None => break,
+        };
+    }
+    Ok(target.location.name())
+}
@@ -0,0 +1,513 @@
+//! The debugger on the console, entered on `brk` and, with the `debugger`
+//! feature on, on faults.
+//!
+//! A session stops the core that entered it: it shows the registers saved in
+//! the trap frame, reads and writes memory, disassembles it and walks the
+//! frame pointer chain. Kernel and user code are debugged alike; addresses
+//! are those of the kernel or of the stopped process, and are checked before
+//! each access.
+//!
+//! Breakpoints are temporary: `break` replaces an instruction with
+//! `brk #BREAKPOINT`, and the instruction is put back when any core reaches
+//! it. `step` sets such breakpoints where the current instruction may lead,
+//! so it follows branches, returns and calls. A session entered on a fault
+//! cannot resume the faulting code: leaving it goes on with the kill or the
+//! panic.
+
+pub mod disasm;
+
+use alloc::vec;
+use core::fmt;
+use core::str;
+
+use crate::allocator::memory_map;
+use crate::config;
+use crate::console::line::{self, LineDiscipline};
+use crate::console::{kprint, kprintln, CONSOLE};
+use crate::ksyms;
+use crate::mutex::Mutex;
+use crate::shell::{self, parse_number};
+use crate::traps::{self, TrapFrame};
+use crate::vm::VirtualAddr;
+use crate::SCHEDULER;
+
+use self::disasm::{Flow, Instruction};
+
+/// The comment of the `brk` instructions that are breakpoints.
+pub const BREAKPOINT: u16 = 0xdb9;
+
+/// The most breakpoints set at once, across kernel and processes.
+const MAX_BREAKPOINTS: usize = 16;
+
+/// The bytes `x` shows by default, and the most it shows.
+const DUMP_DEFAULT_LEN: usize = 64;
+const DUMP_MAX_LEN: usize = 4096;
+
+/// The instructions `dis` shows by default, and the most it shows.
+const DIS_DEFAULT_COUNT: usize = 8;
+const DIS_MAX_COUNT: usize = 256;
+
+/// The most frames `bt` prints.
+const MAX_FRAMES: usize = 16;
+
+/// The longest command line.
+const MAX_LINE_LEN: usize = 128;
+
+/// The address space of the code being debugged.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Space {
+    Kernel,
+    /// The process with the ID.
+    User(u64),
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Breakpoint {
+    space: Space,
+    addr: u64,
+    /// The instruction the breakpoint replaced.
+    original: u32,
+    /// Whether `step` set the breakpoint.
+    step: bool,
+}
+
+static BREAKPOINTS: Mutex<[Option<Breakpoint>; MAX_BREAKPOINTS]> =
+    Mutex::new([None; MAX_BREAKPOINTS]);
+
+/// Returns whether faults enter the debugger.
+pub fn enabled() -> bool {
+    config::feature("debugger")
+}
+
+/// Handles `brk #comment`, executed by the kernel or, if `from_user`, by the
+/// process whose registers are in `tf`, by starting a session.
+///
+/// At one of the debugger's breakpoints, the replaced instruction is put
+/// back, along with the other breakpoints `step` set, and runs when the
+/// session ends; at any other `brk`, execution goes on after it.
+pub fn on_brk(comment: u16, tf: &mut TrapFrame, from_user: bool) {
+    let space = if from_user { Space::User(tf.tpidr) } else { Space::Kernel };
+    let hit = if comment == BREAKPOINT { remove_hit(space, tf.elr) } else { None };
+    match hit {
+        Some(breakpoint) if breakpoint.step => kprintln!("stepped to {}", Location(space, tf.elr)),
+        Some(_) => kprintln!("breakpoint at {}", Location(space, tf.elr)),
+        None => {
+            kprintln!("brk #{:#x} at {}", comment, Location(space, tf.elr));
+            tf.elr += 4;
+        }
+    }
+    Session { tf, space, post_mortem: false }.run();
+}
+
+/// Starts a session on a fault, `reason`, that the kernel or, if
+/// `from_user`, the process whose registers are in `tf` cannot recover
+/// from. Does nothing unless `enabled()`.
+pub fn on_fault(reason: &dyn fmt::Debug, tf: &mut TrapFrame, from_user: bool) {
+    if !enabled() {
+        return;
+    }
+    let space = if from_user { Space::User(tf.tpidr) } else { Space::Kernel };
+    kprintln!("{:?} at {}", reason, Location(space, tf.elr));
+    Session { tf, space, post_mortem: true }.run();
+}
+
+/// An address, with the kernel function it is in when known.
+struct Location(Space, u64);
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Location(space, addr) = *self;
+        write!(f, "{:#018x}", addr)?;
+        match (space, ksyms::lookup(addr)) {
+            (Space::Kernel, Some(symbol)) => write!(f, " {}", symbol),
+            (Space::User(pid), _) => write!(f, " in process {}", pid),
+            _ => Ok(()),
+        }
+    }
+}
+
+struct Session<'a> {
+    tf: &'a TrapFrame,
+    space: Space,
+    /// Whether the session was entered on a fault, so it cannot resume.
+    post_mortem: bool,
+}
+
+/// What a command does once it ran.
+#[derive(PartialEq)]
+enum Next {
+    Prompt,
+    Leave,
+}
+
+impl<'a> Session<'a> {
+    fn run(&self) {
+        let mut console = CONSOLE.lock();
+        let mut discipline = LineDiscipline::new();
+        let mut buf = [0u8; MAX_LINE_LEN];
+        self.disassemble(self.tf.elr, 1);
+        loop {
+            kprint!("(debug) ");
+            let line = match discipline.read_line(&mut *console, &mut buf) {
+                Ok(len) => &buf[..len],
+                Err(line::Error::Interrupted) => continue,
+                Err(line::Error::EndOfFile) => {
+                    kprintln!();
+                    continue;
+                }
+            };
+            let line = match str::from_utf8(line) {
+                Ok(line) => line,
+                Err(_) => {
+                    kprintln!("error: input is not UTF-8");
+                    continue;
+                }
+            };
+            let mut args = line.split_whitespace();
+            let command = match args.next() {
+                Some(command) => command,
+                None => continue,
+            };
+            let args: [Option<&str>; 3] = [args.next(), args.next(), args.next()];
+            if self.execute(command, args) == Next::Leave {
+                return;
+            }
+        }
+    }
+
+    fn execute(&self, command: &str, args: [Option<&str>; 3]) -> Next {
+        let result = match command {
+            "regs" => {
+                traps::dump_registers(self.tf);
+                Ok(())
+            }
+            "x" => match args[0] {
+                None => Err("x: <address> [len] arguments required"),
+                Some(addr) => self.dump(addr, args[1]),
+            },
+            "poke" => match (args[0], args[1]) {
+                (Some(addr), Some(value)) => self.poke(addr, value, args[2]),
+                _ => Err("poke: <address> <value> [size] arguments required"),
+            },
+            "dis" => {
+                let addr = args[0].map_or(Some(self.tf.elr), |arg| self.address(arg));
+                let count = args[1].map_or(Some(DIS_DEFAULT_COUNT as u64), parse_number);
+                match (addr, count) {
+                    (Some(addr), Some(count)) if count as usize <= DIS_MAX_COUNT => {
+                        self.disassemble(addr, count as usize);
+                        Ok(())
+                    }
+                    _ => Err("dis: invalid address or count"),
+                }
+            }
+            "break" => match args[0] {
+                None => {
+                    self.list_breakpoints();
+                    Ok(())
+                }
+                Some(addr) => match self.address(addr) {
+                    Some(addr) => self.set_breakpoint(addr, false),
+                    None => Err("break: invalid address"),
+                },
+            },
+            "clear" => {
+                self.clear_breakpoints();
+                Ok(())
+            }
+            "bt" => {
+                self.backtrace();
+                Ok(())
+            }
+            "shell" => {
+                shell::shell("debug$ ");
+                Ok(())
+            }
+            "c" | "continue" => return Next::Leave,
+            "s" | "step" if self.post_mortem => Err("step: cannot resume after a fault"),
+            "s" | "step" => match self.step() {
+                Ok(()) => return Next::Leave,
+                Err(e) => Err(e),
+            },
+            "help" => {
+                help();
+                Ok(())
+            }
+            _ => Err("unknown command; try help"),
+        };
+        if let Err(e) = result {
+            kprintln!("error: {}", e);
+        }
+        Next::Prompt
+    }
+
+    /// Parses a hexadecimal address, with an optional `0x` prefix, or the
+    /// name of a register holding one: `pc`, `sp`, `lr` or `x0` to `x30`.
+    fn address(&self, arg: &str) -> Option<u64> {
+        match arg {
+            "pc" => Some(self.tf.elr),
+            "sp" => Some(self.tf.sp),
+            "lr" => Some(self.tf.x_registers[30]),
+            _ if arg.starts_with('x') && arg.len() > 1 => {
+                let n: usize = arg[1..].parse().ok()?;
+                self.tf.x_registers.get(n).copied()
+            }
+            _ => u64::from_str_radix(arg.trim_start_matches("0x"), 16).ok(),
+        }
+    }
+
+    /// Returns whether the `len` bytes at `addr` can be accessed.
+    ///
+    /// Kernel addresses must be in physical memory. Those of a process must
+    /// be mapped, or be pages the process can fault in, which are mapped by
+    /// the check.
+    fn accessible(&self, addr: u64, len: usize) -> bool {
+        let end = match addr.checked_add(len as u64) {
+            Some(end) => end,
+            None => return false,
+        };
+        match self.space {
+            Space::Kernel => {
+                let mem_end = memory_map().map(|(_, end)| end as u64).unwrap_or(0);
+                end <= mem_end
+            }
+            Space::User(_) => {
+                let tf = self.tf;
+                SCHEDULER.critical(|scheduler| match scheduler.owner_mut(tf) {
+                    Some(p) => p.check_user_range(VirtualAddr::from(addr as usize), len).is_ok(),
+                    None => false,
+                })
+            }
+        }
+    }
+
+    /// Reads `buf.len()` bytes at `addr`.
+    fn read(&self, addr: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        if !self.accessible(addr, buf.len()) {
+            return Err("address not accessible");
+        }
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = unsafe { core::ptr::read_volatile((addr as *const u8).add(i)) };
+        }
+        Ok(())
+    }
+
+    /// Writes `bytes` at `addr`, making the change visible to instruction
+    /// fetches.
+    fn write(&self, addr: u64, bytes: &[u8]) -> Result<(), &'static str> {
+        if !self.accessible(addr, bytes.len()) {
+            return Err("address not accessible");
+        }
+        unsafe {
+            for (i, &byte) in bytes.iter().enumerate() {
+                core::ptr::write_volatile((addr as *mut u8).add(i), byte);
+            }
+            aarch64::sync_icache_range(addr as usize, bytes.len());
+        }
+        Ok(())
+    }
+
+    fn read_u32(&self, addr: u64) -> Result<u32, &'static str> {
+        let mut bytes = [0; 4];
+        self.read(addr, &mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn read_u64(&self, addr: u64) -> Result<u64, &'static str> {
+        let mut bytes = [0; 8];
+        self.read(addr, &mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// Prints `len` bytes at `addr`, `DUMP_DEFAULT_LEN` if `None`.
+    fn dump(&self, addr: &str, len: Option<&str>) -> Result<(), &'static str> {
+        let addr = self.address(addr).ok_or("x: invalid address")?;
+        let len = len.map_or(Some(DUMP_DEFAULT_LEN as u64), parse_number);
+        let len = match len {
+            Some(len) if len as usize <= DUMP_MAX_LEN => len as usize,
+            _ => return Err("x: invalid length"),
+        };
+        let mut bytes = vec![0; len];
+        self.read(addr, &mut bytes)?;
+        shell::print_hex(addr, &bytes);
+        Ok(())
+    }
+
+    /// Writes `value` at `addr` as a little-endian number of `size` bytes: 1,
+    /// 2, 4 or, by default, 8.
+    fn poke(&self, addr: &str, value: &str, size: Option<&str>) -> Result<(), &'static str> {
+        let addr = self.address(addr).ok_or("poke: invalid address")?;
+        let value = parse_number(value).ok_or("poke: invalid value")?;
+        let size = match size.map_or(Some(8), parse_number) {
+            Some(size @ 1) | Some(size @ 2) | Some(size @ 4) | Some(size @ 8) => size as usize,
+            _ => return Err("poke: size must be 1, 2, 4 or 8"),
+        };
+        if size < 8 && value >> (size * 8) != 0 {
+            return Err("poke: value does not fit in size");
+        }
+        self.write(addr, &value.to_le_bytes()[..size])
+    }
+
+    /// Prints `count` instructions from `addr`, marking the one at the
+    /// program counter.
+    fn disassemble(&self, addr: u64, count: usize) {
+        let mut function = None;
+        for i in 0..count as u64 {
+            let pc = addr.wrapping_add(i * 4);
+            if self.space == Space::Kernel {
+                let symbol = ksyms::lookup(pc);
+                match symbol {
+                    Some(symbol) if function != Some(symbol.addr) => kprintln!("{}:", symbol.name),
+                    _ => (),
+                }
+                function = symbol.map(|symbol| symbol.addr);
+            }
+            let marker = if pc == self.tf.elr { "=>" } else { "  " };
+            match self.read_u32(pc) {
+                Ok(word) => {
+                    kprintln!("{} {:#018x}  {:08x}  {}", marker, pc, word, Instruction::new(pc, word))
+                }
+                Err(e) => return kprintln!("{} {:#018x}  {}", marker, pc, e),
+            }
+        }
+    }
+
+    /// Prints the return addresses found by walking the frame pointer chain.
+    fn backtrace(&self) {
+        kprintln!("  #0  {}", Location(self.space, self.tf.elr));
+        let mut fp = self.tf.x_registers[29];
+        for depth in 1..=MAX_FRAMES {
+            if fp == 0 || fp % 16 != 0 {
+                return;
+            }
+            let (next, lr) = match (self.read_u64(fp), self.read_u64(fp + 8)) {
+                (Ok(next), Ok(lr)) => (next, lr),
+                _ => return,
+            };
+            if lr == 0 {
+                return;
+            }
+            kprintln!("  #{:<2} {}", depth, Location(self.space, lr));
+            // Callers' frames are at higher addresses; anything else means
+            // the chain is corrupt.
+            fp = if next > fp { next } else { 0 };
+        }
+    }
+
+    /// Sets a breakpoint at `addr`, by `step` if `step`.
+    fn set_breakpoint(&self, addr: u64, step: bool) -> Result<(), &'static str> {
+        if addr % 4 != 0 {
+            return Err("breakpoint address not aligned");
+        }
+        let mut breakpoints = BREAKPOINTS.lock();
+        let space = self.space;
+        if breakpoints.iter().flatten().any(|b| b.space == space && b.addr == addr) {
+            return Ok(());
+        }
+        let slot = breakpoints.iter_mut().find(|b| b.is_none()).ok_or("too many breakpoints")?;
+        let original = self.read_u32(addr)?;
+        let brk = 0xD420_0000 | ((BREAKPOINT as u32) << 5);
+        self.write(addr, &brk.to_le_bytes())?;
+        *slot = Some(Breakpoint { space, addr, original, step });
+        Ok(())
+    }
+
+    fn list_breakpoints(&self) {
+        for b in BREAKPOINTS.lock().iter().flatten() {
+            let kind = if b.step { "step" } else { "break" };
+            kprintln!("{:<6} {}", kind, Location(b.space, b.addr));
+        }
+    }
+
+    /// Removes the breakpoints in the current address space.
+    fn clear_breakpoints(&self) {
+        let mut breakpoints = BREAKPOINTS.lock();
+        for slot in breakpoints.iter_mut() {
+            if let Some(b) = *slot {
+                if b.space == self.space {
+                    restore(b);
+                    *slot = None;
+                }
+            }
+        }
+    }
+
+    /// Sets breakpoints where the instruction at the program counter may
+    /// lead. An exception returns to the next instruction, so `svc` is
+    /// stepped over.
+    fn step(&self) -> Result<(), &'static str> {
+        let pc = self.tf.elr;
+        let instruction = Instruction::new(pc, self.read_u32(pc)?);
+        let targets = match instruction.flow() {
+            Flow::Next | Flow::Exception => [Some(pc + 4), None],
+            Flow::Jump(target) => [Some(target), None],
+            Flow::Branch(target) => [Some(pc + 4), Some(target)],
+            Flow::Register(n) => [Some(self.tf.x_registers.get(n).copied().unwrap_or(0)), None],
+        };
+        for &target in targets.iter().flatten() {
+            if let Err(e) = self.set_breakpoint(target, true) {
+                self.remove_steps();
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the breakpoints `step` set in the current address space.
+    fn remove_steps(&self) {
+        let mut breakpoints = BREAKPOINTS.lock();
+        for slot in breakpoints.iter_mut() {
+            if let Some(b) = *slot {
+                if b.step && b.space == self.space {
+                    restore(b);
+                    *slot = None;
+                }
+            }
+        }
+    }
+}
+
+/// Removes the breakpoint at `addr` in `space` and returns it, if there is
+/// one, along with the other breakpoints `step` set there.
+fn remove_hit(space: Space, addr: u64) -> Option<Breakpoint> {
+    let mut breakpoints = BREAKPOINTS.lock();
+    let index = breakpoints.iter().position(|b| match b {
+        Some(b) => b.space == space && b.addr == addr,
+        None => false,
+    })?;
+    let hit = breakpoints[index].take()?;
+    restore(hit);
+    if hit.step {
+        for slot in breakpoints.iter_mut() {
+            if let Some(b) = *slot {
+                if b.step && b.space == space {
+                    restore(b);
+                    *slot = None;
+                }
+            }
+        }
+    }
+    Some(hit)
+}
+
+/// Puts back the instruction `breakpoint` replaced. The caller should assure
+/// that the breakpoint's address space is the current one.
+fn restore(breakpoint: Breakpoint) {
+    unsafe {
+        core::ptr::write_volatile(breakpoint.addr as *mut u32, breakpoint.original);
+        aarch64::sync_icache_range(breakpoint.addr as usize, 4);
+    }
+}
+
+fn help() {
+    kprintln!("regs                      show the saved registers");
+    kprintln!("x <addr> [len]            show memory");
+    kprintln!("poke <addr> <val> [size]  write a 1, 2, 4 or 8 byte value");
+    kprintln!("dis [addr] [count]        disassemble, from pc by default");
+    kprintln!("break [addr]              set a temporary breakpoint, or list them");
+    kprintln!("clear                     remove this address space's breakpoints");
+    kprintln!("bt                        show a backtrace");
+    kprintln!("s, step                   run one instruction");
+    kprintln!("c, continue               leave the debugger");
+    kprintln!("shell                     start the kernel shell");
+    kprintln!("addresses are hex, or pc, sp, lr or x0 to x30 for a register's value");
+}
@@ -0,0 +1,315 @@
+//! A small AArch64 disassembler for the debugger.
+//!
+//! It knows the branches, which the debugger follows to single-step, and the
+//! instructions most common in function prologues, epilogues and address
+//! computations. Anything else is shown as `.word`.
+
+use core::fmt;
+
+/// An instruction at a given address.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub pc: u64,
+    pub word: u32,
+}
+
+/// Where execution may go after an instruction.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Flow {
+    /// To the next instruction.
+    Next,
+    /// To the address, unconditionally.
+    Jump(u64),
+    /// To the address or to the next instruction.
+    Branch(u64),
+    /// To the address held in the register.
+    Register(usize),
+    /// Into an exception or back from one: `svc`, `hvc`, `smc`, `brk` and
+    /// `eret`, which cannot be stepped over.
+    Exception,
+}
+
+/// Returns bits `lo` to `lo + len - 1` of `word`.
+fn bits(word: u32, lo: u32, len: u32) -> u32 {
+    (word >> lo) & ((1 << len) - 1)
+}
+
+/// Sign-extends the `len`-bit value `value`.
+fn sext(value: u32, len: u32) -> i64 {
+    ((value as i64) << (64 - len)) >> (64 - len)
+}
+
+/// The name of register `n`, 64-bit if `wide`. Register 31 is the stack
+/// pointer if `sp`, the zero register otherwise.
+struct Reg {
+    n: u32,
+    wide: bool,
+    sp: bool,
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.n, self.wide, self.sp) {
+            (31, true, true) => write!(f, "sp"),
+            (31, false, true) => write!(f, "wsp"),
+            (31, true, false) => write!(f, "xzr"),
+            (31, false, false) => write!(f, "wzr"),
+            (n, true, _) => write!(f, "x{}", n),
+            (n, false, _) => write!(f, "w{}", n),
+        }
+    }
+}
+
+fn x(n: u32) -> Reg {
+    Reg { n, wide: true, sp: false }
+}
+
+fn reg(n: u32, wide: bool) -> Reg {
+    Reg { n, wide, sp: false }
+}
+
+fn reg_sp(n: u32, wide: bool) -> Reg {
+    Reg { n, wide, sp: true }
+}
+
+/// A system register by its encoding, `op0:op1:CRn:CRm:op2`, in the
+/// generic `s<op0>_<op1>_c<n>_c<m>_<op2>` syntax.
+struct SysReg(u32);
+
+impl fmt::Display for SysReg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let r = self.0;
+        let (op0, op1, crn, crm, op2) =
+            (2 + bits(r, 14, 1), bits(r, 11, 3), bits(r, 7, 4), bits(r, 3, 4), bits(r, 0, 3));
+        write!(f, "s{}_{}_c{}_c{}_{}", op0, op1, crn, crm, op2)
+    }
+}
+
+const CONDITIONS: [&str; 16] =
+    ["eq", "ne", "hs", "lo", "mi", "pl", "vs", "vc", "hi", "ls", "ge", "lt", "gt", "le", "al", "nv"];
+
+impl Instruction {
+    pub fn new(pc: u64, word: u32) -> Instruction {
+        Instruction { pc, word }
+    }
+
+    /// Returns the address `offset` instructions from this one.
+    fn target(&self, offset: i64) -> u64 {
+        self.pc.wrapping_add((offset * 4) as u64)
+    }
+
+    /// Returns where execution may go after this instruction.
+    pub fn flow(&self) -> Flow {
+        let w = self.word;
+        if w & 0x7C00_0000 == 0x1400_0000 {
+            // B and BL.
+            return Flow::Jump(self.target(sext(bits(w, 0, 26), 26)));
+        }
+        if w & 0xFF00_0010 == 0x5400_0000 {
+            return match bits(w, 0, 4) {
+                14 | 15 => Flow::Jump(self.target(sext(bits(w, 5, 19), 19))),
+                _ => Flow::Branch(self.target(sext(bits(w, 5, 19), 19))),
+            };
+        }
+        if w & 0x7E00_0000 == 0x3400_0000 {
+            // CBZ and CBNZ.
+            return Flow::Branch(self.target(sext(bits(w, 5, 19), 19)));
+        }
+        if w & 0x7E00_0000 == 0x3600_0000 {
+            // TBZ and TBNZ.
+            return Flow::Branch(self.target(sext(bits(w, 5, 14), 14)));
+        }
+        match w & 0xFFFF_FC1F {
+            0xD61F_0000 | 0xD63F_0000 | 0xD65F_0000 => return Flow::Register(bits(w, 5, 5) as usize),
+            _ => (),
+        }
+        let exception = w & 0xFFE0_001C == 0xD400_0000 && bits(w, 0, 2) != 0;
+        if exception || w == 0xD69F_03E0 || w & 0xFFE0_001F == 0xD420_0000 {
+            return Flow::Exception;
+        }
+        Flow::Next
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let w = self.word;
+        let (rd, rn) = (bits(w, 0, 5), bits(w, 5, 5));
+        let wide = bits(w, 31, 1) == 1;
+
+        if w == 0xD503_201F {
+            return write!(f, "nop");
+        }
+        if w == 0xD69F_03E0 {
+            return write!(f, "eret");
+        }
+        if w & 0x7C00_0000 == 0x1400_0000 {
+            let name = if bits(w, 31, 1) == 1 { "bl" } else { "b" };
+            return write!(f, "{} {:#x}", name, self.target(sext(bits(w, 0, 26), 26)));
+        }
+        if w & 0xFF00_0010 == 0x5400_0000 {
+            let cond = CONDITIONS[bits(w, 0, 4) as usize];
+            return write!(f, "b.{} {:#x}", cond, self.target(sext(bits(w, 5, 19), 19)));
+        }
+        if w & 0x7E00_0000 == 0x3400_0000 {
+            let name = if bits(w, 24, 1) == 1 { "cbnz" } else { "cbz" };
+            let target = self.target(sext(bits(w, 5, 19), 19));
+            return write!(f, "{} {}, {:#x}", name, reg(rd, wide), target);
+        }
+        if w & 0x7E00_0000 == 0x3600_0000 {
+            let name = if bits(w, 24, 1) == 1 { "tbnz" } else { "tbz" };
+            let bit = (bits(w, 31, 1) << 5) | bits(w, 19, 5);
+            let target = self.target(sext(bits(w, 5, 14), 14));
+            return write!(f, "{} {}, #{}, {:#x}", name, reg(rd, bit >= 32), bit, target);
+        }
+        match w & 0xFFFF_FC1F {
+            0xD61F_0000 => return write!(f, "br {}", x(rn)),
+            0xD63F_0000 => return write!(f, "blr {}", x(rn)),
+            0xD65F_0000 if rn == 30 => return write!(f, "ret"),
+            0xD65F_0000 => return write!(f, "ret {}", x(rn)),
+            _ => (),
+        }
+        if w & 0xFFE0_001C == 0xD400_0000 {
+            let name = match bits(w, 0, 2) {
+                1 => "svc",
+                2 => "hvc",
+                3 => "smc",
+                _ => return write!(f, ".word {:#010x}", w),
+            };
+            return write!(f, "{} #{:#x}", name, bits(w, 5, 16));
+        }
+        if w & 0xFFE0_001F == 0xD420_0000 {
+            return write!(f, "brk #{:#x}", bits(w, 5, 16));
+        }
+        if w & 0xFFF0_0000 == 0xD530_0000 || w & 0xFFF0_0000 == 0xD510_0000 {
+            let sysreg = SysReg(bits(w, 5, 16));
+            return if bits(w, 21, 1) == 1 {
+                write!(f, "mrs {}, {}", x(rd), sysreg)
+            } else {
+                write!(f, "msr {}, {}", sysreg, x(rd))
+            };
+        }
+        if w & 0x1F80_0000 == 0x1280_0000 {
+            let name = match bits(w, 29, 2) {
+                0 => "movn",
+                2 => "movz",
+                3 => "movk",
+                _ => return write!(f, ".word {:#010x}", w),
+            };
+            let (imm, shift) = (bits(w, 5, 16), bits(w, 21, 2) * 16);
+            write!(f, "{} {}, #{:#x}", name, reg(rd, wide), imm)?;
+            return if shift != 0 { write!(f, ", lsl #{}", shift) } else { Ok(()) };
+        }
+        if w & 0x1F00_0000 == 0x1100_0000 {
+            let (sub, set_flags) = (bits(w, 30, 1) == 1, bits(w, 29, 1) == 1);
+            let imm = bits(w, 10, 12) << (bits(w, 22, 1) * 12);
+            let src = reg_sp(rn, wide);
+            if !sub && !set_flags && imm == 0 && (rd == 31 || rn == 31) {
+                return write!(f, "mov {}, {}", reg_sp(rd, wide), src);
+            }
+            if set_flags && rd == 31 {
+                let name = if sub { "cmp" } else { "cmn" };
+                return write!(f, "{} {}, #{:#x}", name, src, imm);
+            }
+            let name = match (sub, set_flags) {
+                (false, false) => "add",
+                (false, true) => "adds",
+                (true, false) => "sub",
+                (true, true) => "subs",
+            };
+            let dst = if set_flags { reg(rd, wide) } else { reg_sp(rd, wide) };
+            return write!(f, "{} {}, {}, #{:#x}", name, dst, src, imm);
+        }
+        if w & 0x1F00_0000 == 0x1000_0000 {
+            let imm = sext((bits(w, 5, 19) << 2) | bits(w, 29, 2), 21);
+            return if bits(w, 31, 1) == 1 {
+                let page = (self.pc & !0xFFF).wrapping_add((imm << 12) as u64);
+                write!(f, "adrp {}, {:#x}", x(rd), page)
+            } else {
+                write!(f, "adr {}, {:#x}", x(rd), self.pc.wrapping_add(imm as u64))
+            };
+        }
+        if w & 0x7FE0_FFE0 == 0x2A00_03E0 {
+            return write!(f, "mov {}, {}", reg(rd, wide), reg(bits(w, 16, 5), wide));
+        }
+        if w & 0x3F80_0000 == 0x3900_0000 {
+            let size = bits(w, 30, 2);
+            let load = bits(w, 22, 1) == 1;
+            let name = match (load, size) {
+                (false, 0) => "strb",
+                (false, 1) => "strh",
+                (false, _) => "str",
+                (true, 0) => "ldrb",
+                (true, 1) => "ldrh",
+                (true, _) => "ldr",
+            };
+            let offset = bits(w, 10, 12) << size;
+            return write!(f, "{} {}, [{}, #{:#x}]", name, reg(rd, size == 3), reg_sp(rn, true), offset);
+        }
+        if w & 0x3A00_0000 == 0x2800_0000 && bits(w, 30, 2) & 1 == 0 && bits(w, 23, 2) != 0 {
+            let wide = bits(w, 31, 1) == 1;
+            let name = if bits(w, 22, 1) == 1 { "ldp" } else { "stp" };
+            let offset = sext(bits(w, 15, 7), 7) * if wide { 8 } else { 4 };
+            let (rt, rt2, base) = (reg(rd, wide), reg(bits(w, 10, 5), wide), reg_sp(rn, true));
+            return match bits(w, 23, 2) {
+                1 => write!(f, "{} {}, {}, [{}], #{}", name, rt, rt2, base, offset),
+                2 => write!(f, "{} {}, {}, [{}, #{}]", name, rt, rt2, base, offset),
+                _ => write!(f, "{} {}, {}, [{}, #{}]!", name, rt, rt2, base, offset),
+            };
+        }
+        write!(f, ".word {:#010x}", w)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::*;
+
+    fn text(pc: u64, word: u32) -> alloc::string::String {
+        Instruction::new(pc, word).to_string()
+    }
+
+    #[test]
+    fn decodes_branches() {
+        let pc = 0x80000;
+        assert_eq!(text(pc, 0x1400_0004), "b 0x80010");
+        assert_eq!(text(pc, 0x97FF_FFFF), "bl 0x7fffc");
+        assert_eq!(text(pc, 0x5400_0041), "b.ne 0x80008");
+        assert_eq!(text(pc, 0xB400_0060), "cbz x0, 0x8000c");
+        assert_eq!(text(pc, 0x3708_0041), "tbnz w1, #1, 0x80008");
+        assert_eq!(text(pc, 0xD65F_03C0), "ret");
+        assert_eq!(text(pc, 0xD63F_0100), "blr x8");
+
+        let at = |word| Instruction::new(pc, word).flow();
+        assert_eq!(at(0x1400_0004), Flow::Jump(0x80010));
+        assert_eq!(at(0x5400_0041), Flow::Branch(0x80008));
+        assert_eq!(at(0xB400_0060), Flow::Branch(0x8000c));
+        assert_eq!(at(0xD65F_03C0), Flow::Register(30));
+        assert_eq!(at(0xD400_0001), Flow::Exception);
+        assert_eq!(at(0xD420_0000), Flow::Exception);
+        assert_eq!(at(0xD503_201F), Flow::Next);
+    }
+
+    #[test]
+    fn decodes_common_instructions() {
+        let pc = 0x80000;
+        assert_eq!(text(pc, 0xD503_201F), "nop");
+        assert_eq!(text(pc, 0xD400_0001), "svc #0x0");
+        assert_eq!(text(pc, 0xD420_0020), "brk #0x1");
+        assert_eq!(text(pc, 0xA9BF_7BFD), "stp x29, x30, [sp, #-16]!");
+        assert_eq!(text(pc, 0xA8C1_7BFD), "ldp x29, x30, [sp], #16");
+        assert_eq!(text(pc, 0x9100_03FD), "mov x29, sp");
+        assert_eq!(text(pc, 0xD100_43FF), "sub sp, sp, #0x10");
+        assert_eq!(text(pc, 0xF100_041F), "cmp x0, #0x1");
+        assert_eq!(text(pc, 0xD280_0540), "movz x0, #0x2a");
+        assert_eq!(text(pc, 0xF2A0_0020), "movk x0, #0x1, lsl #16");
+        assert_eq!(text(pc, 0xAA01_03E0), "mov x0, x1");
+        assert_eq!(text(pc, 0xF940_0420), "ldr x0, [x1, #0x8]");
+        assert_eq!(text(pc, 0xB900_0BE1), "str w1, [sp, #0x8]");
+        assert_eq!(text(pc, 0x9000_0000), "adrp x0, 0x80000");
+        assert_eq!(text(pc, 0xD538_0000), "mrs x0, s3_0_c0_c0_0");
+        assert_eq!(text(pc, 0x1E20_1000), ".word 0x1e201000");
+    }
+}
@@ -0,0 +1,479 @@
+//! Drawing on a small TFT panel, and mirroring the console onto it.
+//!
+//! Drawing goes to a `Framebuffer` in memory, which tracks the rectangle
+//! changed since it was last sent; `draw()` sends only that rectangle to the
+//! panel afterwards, since a full frame takes around 100ms over SPI. The
+//! panel is set with the `display` configuration key.
+//!
+//! While the console is mirrored, everything it prints is also queued here,
+//! and a kernel thread renders it into a `Screen` the size of the panel,
+//! redrawing the cells that changed a few times a second. Output that does
+//! not fit in the queue between redraws is dropped from the mirror.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use pi::tft::{self, Panel, Tft};
+use ringbuf::{Mpsc, Slot};
+use shim::io;
+use shim::newioerr;
+
+use crate::console::font;
+use crate::console::screen::{Cell, Screen, PALETTE};
+use crate::console::CONSOLE;
+use crate::mutex::Mutex;
+use crate::process::{Id, Process};
+use crate::SCHEDULER;
+
+/// How often the mirror thread redraws the console.
+const REFRESH: Duration = Duration::from_millis(100);
+
+/// The most console output queued for the mirror between redraws.
+const MAX_PENDING: usize = 4096;
+
+/// The highest GPIO pin number.
+const MAX_PIN: u8 = 53;
+
+/// Returns the RGB565 color nearest to the 8-bit components `r`, `g`, `b`.
+pub const fn rgb565(r: u8, g: u8, b: u8) -> u16 {
+    ((r as u16 >> 3) << 11) | ((g as u16 >> 2) << 5) | (b as u16 >> 3)
+}
+
+/// Returns the RGB565 color of `0xRRGGBB`.
+pub fn rgb565_of(rgb: u32) -> u16 {
+    rgb565((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+}
+
+pub const BLACK: u16 = rgb565(0, 0, 0);
+pub const WHITE: u16 = rgb565(255, 255, 255);
+
+/// A rectangle of pixels.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Rect {
+        Rect { x, y, width, height }
+    }
+
+    /// Returns the smallest rectangle containing `self` and `other`.
+    fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = (self.x + self.width).max(other.x + other.width);
+        let bottom = (self.y + self.height).max(other.y + other.height);
+        Rect::new(x, y, right - x, bottom - y)
+    }
+}
+
+/// An image of RGB565 pixels, row by row, remembering which part of it
+/// changed. Drawing is clipped to the image.
+#[derive(Debug)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<u16>,
+    dirty: Option<Rect>,
+}
+
+impl Framebuffer {
+    /// Returns a black `width` x `height` image, all of it changed.
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![BLACK; width * height],
+            dirty: Some(Rect::new(0, 0, width, height)),
+        }
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Returns the color of the pixel at (`x`, `y`), or `None` if it is
+    /// outside the image.
+    pub fn pixel(&self, x: usize, y: usize) -> Option<u16> {
+        if x < self.width && y < self.height {
+            Some(self.pixels[y * self.width + x])
+        } else {
+            None
+        }
+    }
+
+    /// Returns `rect` clipped to the image, or `None` if nothing is left.
+    fn clip(&self, rect: Rect) -> Option<Rect> {
+        let width = rect.width.min(self.width.saturating_sub(rect.x));
+        let height = rect.height.min(self.height.saturating_sub(rect.y));
+        if width == 0 || height == 0 {
+            None
+        } else {
+            Some(Rect::new(rect.x, rect.y, width, height))
+        }
+    }
+
+    fn mark(&mut self, rect: Rect) {
+        self.dirty = Some(match self.dirty {
+            Some(dirty) => dirty.union(&rect),
+            None => rect,
+        });
+    }
+
+    /// Returns the rectangle changed since the last call, if any.
+    pub fn take_dirty(&mut self) -> Option<Rect> {
+        self.dirty.take()
+    }
+
+    /// Fills the whole image with `color`.
+    pub fn clear(&mut self, color: u16) {
+        self.fill_rect(Rect::new(0, 0, self.width, self.height), color);
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: u16) {
+        self.fill_rect(Rect::new(x, y, 1, 1), color);
+    }
+
+    pub fn fill_rect(&mut self, rect: Rect, color: u16) {
+        let rect = match self.clip(rect) {
+            Some(rect) => rect,
+            None => return,
+        };
+        for y in rect.y..rect.y + rect.height {
+            let start = y * self.width + rect.x;
+            self.pixels[start..start + rect.width].iter_mut().for_each(|p| *p = color);
+        }
+        self.mark(rect);
+    }
+
+    /// Draws the one pixel wide outline of `rect`.
+    pub fn draw_rect(&mut self, rect: Rect, color: u16) {
+        if rect.width == 0 || rect.height == 0 {
+            return;
+        }
+        let Rect { x, y, width, height } = rect;
+        self.fill_rect(Rect::new(x, y, width, 1), color);
+        self.fill_rect(Rect::new(x, y + height - 1, width, 1), color);
+        self.fill_rect(Rect::new(x, y, 1, height), color);
+        self.fill_rect(Rect::new(x + width - 1, y, 1, height), color);
+    }
+
+    /// Copies the `rect.width` x `rect.height` image `pixels`, given row by
+    /// row, to `rect`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pixels` is shorter than `rect.width * rect.height`.
+    pub fn blit(&mut self, rect: Rect, pixels: &[u16]) {
+        assert!(pixels.len() >= rect.width * rect.height);
+        let clipped = match self.clip(rect) {
+            Some(clipped) => clipped,
+            None => return,
+        };
+        for row in 0..clipped.height {
+            let from = row * rect.width;
+            let to = (rect.y + row) * self.width + rect.x;
+            self.pixels[to..to + clipped.width]
+                .copy_from_slice(&pixels[from..from + clipped.width]);
+        }
+        self.mark(clipped);
+    }
+
+    /// Draws the glyph of `byte` with its top left corner at (`x`, `y`).
+    pub fn draw_char(&mut self, x: usize, y: usize, byte: u8, fg: u16, bg: u16) {
+        let mut pixels = [bg; font::WIDTH * font::HEIGHT];
+        for (row, bits) in font::glyph(byte).iter().enumerate() {
+            for col in 0..font::WIDTH {
+                if bits & (1 << col) != 0 {
+                    pixels[row * font::WIDTH + col] = fg;
+                }
+            }
+        }
+        self.blit(Rect::new(x, y, font::WIDTH, font::HEIGHT), &pixels);
+    }
+
+    /// Draws `text` on one line starting at (`x`, `y`) and returns the `x`
+    /// just past it. Characters other than printable ASCII are drawn as `?`.
+    pub fn draw_text(&mut self, x: usize, y: usize, text: &str, fg: u16, bg: u16) -> usize {
+        let mut x = x;
+        for c in text.chars() {
+            let byte = if c.is_ascii() { c as u8 } else { b'?' };
+            self.draw_char(x, y, byte, fg, bg);
+            x += font::WIDTH;
+        }
+        x
+    }
+}
+
+struct Display {
+    tft: Tft,
+    framebuffer: Framebuffer,
+}
+
+impl Display {
+    /// Sends the changed part of the framebuffer to the panel.
+    fn flush(&mut self) {
+        let rect = match self.framebuffer.take_dirty() {
+            Some(rect) => rect,
+            None => return,
+        };
+        let width = self.framebuffer.width;
+        let mut pixels = Vec::with_capacity(rect.width * rect.height);
+        for y in rect.y..rect.y + rect.height {
+            let start = y * width + rect.x;
+            pixels.extend_from_slice(&self.framebuffer.pixels[start..start + rect.width]);
+        }
+        self.tft.draw(rect.x, rect.y, rect.width, rect.height, &pixels);
+    }
+}
+
+static DISPLAY: Mutex<Option<Display>> = Mutex::new(None);
+
+/// Parses a panel's wiring as `<panel>[,<dc>[,<reset>]]`, where `<panel>` is
+/// `st7735` or `ili9341` and the pins are GPIO numbers, or `none` for no
+/// reset pin; e.g. `ili9341,24,25`. Pins that are left out are as in
+/// `tft::Config::new()`.
+pub fn parse_config(value: &str) -> Option<tft::Config> {
+    let mut parts = value.split(',').map(str::trim);
+    let mut config = tft::Config::new(Panel::from_name(parts.next()?)?);
+    if let Some(dc) = parts.next() {
+        config.dc = dc.parse().ok().filter(|&pin| pin <= MAX_PIN)?;
+    }
+    if let Some(reset) = parts.next() {
+        config.reset = match reset {
+            "none" => None,
+            pin => Some(pin.parse().ok().filter(|&pin| pin <= MAX_PIN)?),
+        };
+    }
+    match parts.next() {
+        Some(_) => None,
+        None => Some(config),
+    }
+}
+
+/// Formats a panel's wiring as `parse_config()` takes it.
+pub fn config_name(config: &tft::Config) -> String {
+    match config.reset {
+        Some(reset) => format!("{},{},{}", config.panel.name(), config.dc, reset),
+        None => format!("{},{},none", config.panel.name(), config.dc),
+    }
+}
+
+/// Initializes the panel wired as in `config` and clears it. Any panel
+/// initialized before is replaced.
+pub fn initialize(config: &tft::Config) {
+    let tft = Tft::new(config);
+    let (width, height) = tft.size();
+    let mut display = Display { tft, framebuffer: Framebuffer::new(width, height) };
+    display.flush();
+    *DISPLAY.lock() = Some(display);
+}
+
+/// Returns the panel and its size, or `None` if no panel was initialized.
+pub fn panel() -> Option<(Panel, usize, usize)> {
+    DISPLAY.lock().as_ref().map(|display| {
+        let (width, height) = display.tft.size();
+        (display.tft.panel(), width, height)
+    })
+}
+
+/// Runs `f` on the framebuffer and then shows what it drew. Returns `None`
+/// without calling `f` if no panel was initialized.
+pub fn draw<R>(f: impl FnOnce(&mut Framebuffer) -> R) -> Option<R> {
+    let mut guard = DISPLAY.lock();
+    let display = guard.as_mut()?;
+    let result = f(&mut display.framebuffer);
+    display.flush();
+    Some(result)
+}
+
+/// Console output waiting to be rendered by the mirror thread, its only
+/// consumer.
+static PENDING: Mpsc<[Slot<u8>; MAX_PENDING]> = Mpsc::new([Slot::new(0); MAX_PENDING]);
+
+/// Whether console output is mirrored.
+static MIRRORING: AtomicBool = AtomicBool::new(false);
+
+/// Counts the calls to `start_mirror()`, so that the mirror thread starts
+/// over from the console's history when mirroring restarts.
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// The mirror thread, once started. It idles while mirroring is off.
+static THREAD: Mutex<Option<Id>> = Mutex::new(None);
+
+/// Queues console output for the mirror, if it is on. Called by the console
+/// with its lock held for everything it prints.
+pub fn mirror(bytes: &[u8]) {
+    if MIRRORING.load(Ordering::Relaxed) {
+        let _ = PENDING.push_slice(bytes);
+    }
+}
+
+/// Starts mirroring the console onto the panel, beginning with the output
+/// it still has in its history.
+///
+/// Returns `Other` if no panel was initialized or the mirror thread cannot
+/// be started.
+pub fn start_mirror() -> io::Result<()> {
+    if panel().is_none() {
+        return Err(newioerr!(Other, "no display"));
+    }
+    let mut thread = THREAD.lock();
+    if thread.is_none() {
+        let process = Process::kernel_thread(mirror_thread)
+            .map_err(|_| newioerr!(Other, "cannot start mirror thread"))?;
+        let pid = SCHEDULER.add(process).ok_or_else(|| newioerr!(Other, "no process ID"))?;
+        *thread = Some(pid);
+    }
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+    MIRRORING.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Stops mirroring the console. What was shown stays on the panel.
+pub fn stop_mirror() {
+    MIRRORING.store(false, Ordering::Relaxed);
+}
+
+/// Returns `true` while the console is mirrored.
+pub fn is_mirroring() -> bool {
+    MIRRORING.load(Ordering::Relaxed)
+}
+
+/// A console rendered on the panel.
+struct Mirror {
+    screen: Screen,
+    /// The cells drawn on the panel, row by row.
+    drawn: Vec<Cell>,
+    generation: usize,
+}
+
+impl Mirror {
+    /// Returns a mirror filling the panel and holding the console's history.
+    fn new(width: usize, height: usize, generation: usize) -> Mirror {
+        let (cols, rows) = (width / font::WIDTH, height / font::HEIGHT);
+        let mut screen = Screen::new(cols, rows, 0);
+        // Output recorded after the history is queued in `PENDING`; that
+        // queued before is in the history, so drop it.
+        let history = {
+            let console = CONSOLE.lock();
+            let mut consumer = unsafe { PENDING.consumer() };
+            while consumer.pop().is_some() {}
+            let (older, newer) = console.history();
+            let mut history = Vec::with_capacity(older.len() + newer.len());
+            history.extend_from_slice(older);
+            history.extend_from_slice(newer);
+            history
+        };
+        history.iter().for_each(|&byte| screen.write_byte(byte));
+        // No drawn cell matches, so the first redraw draws every cell.
+        let unknown = Cell { byte: 0, fg: 0, bg: 0 };
+        Mirror { screen, drawn: vec![unknown; cols * rows], generation }
+    }
+
+    /// Renders the queued output and draws the cells that changed.
+    fn refresh(&mut self) {
+        // Only the mirror thread calls `refresh()`, so there is one consumer.
+        let mut consumer = unsafe { PENDING.consumer() };
+        while let Some(byte) = consumer.pop() {
+            self.screen.write_byte(byte);
+        }
+        if !self.screen.take_dirty() {
+            return;
+        }
+        let (screen, drawn) = (&self.screen, &mut self.drawn);
+        draw(|framebuffer| {
+            for (row, line) in screen.visible_lines().enumerate() {
+                for (col, &cell) in line.iter().enumerate() {
+                    let index = row * line.len() + col;
+                    if drawn[index] == cell {
+                        continue;
+                    }
+                    drawn[index] = cell;
+                    let (fg, bg) = (PALETTE[cell.fg as usize], PALETTE[cell.bg as usize]);
+                    let (x, y) = (col * font::WIDTH, row * font::HEIGHT);
+                    framebuffer.draw_char(x, y, cell.byte, rgb565_of(fg), rgb565_of(bg));
+                }
+            }
+        });
+    }
+}
+
+extern "C" fn mirror_thread() -> ! {
+    let mut mirror: Option<Mirror> = None;
+    loop {
+        let _ = kernel_api::syscall::sleep(REFRESH);
+        if !is_mirroring() {
+            continue;
+        }
+        let generation = GENERATION.load(Ordering::Relaxed);
+        if mirror.as_ref().map(|mirror| mirror.generation) != Some(generation) {
+            let (_, width, height) = match panel() {
+                Some(panel) => panel,
+                None => continue,
+            };
+            mirror = Some(Mirror::new(width, height, generation));
+        }
+        if let Some(mirror) = mirror.as_mut() {
+            mirror.refresh();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_colors() {
+        assert_eq!(rgb565(255, 255, 255), 0xFFFF);
+        assert_eq!(rgb565(255, 0, 0), 0xF800);
+        assert_eq!(rgb565(0, 255, 0), 0x07E0);
+        assert_eq!(rgb565_of(0x0000FF), 0x001F);
+    }
+
+    #[test]
+    fn tracks_changed_rect() {
+        let mut fb = Framebuffer::new(16, 8);
+        assert_eq!(fb.take_dirty(), Some(Rect::new(0, 0, 16, 8)));
+        assert_eq!(fb.take_dirty(), None);
+        fb.set_pixel(2, 1, WHITE);
+        fb.fill_rect(Rect::new(10, 5, 10, 10), WHITE);
+        assert_eq!(fb.take_dirty(), Some(Rect::new(2, 1, 14, 7)));
+        assert_eq!(fb.pixel(15, 7), Some(WHITE));
+        assert_eq!(fb.pixel(16, 7), None);
+        fb.fill_rect(Rect::new(20, 0, 4, 4), WHITE);
+        assert_eq!(fb.take_dirty(), None);
+    }
+
+    #[test]
+    fn draws_text() {
+        let mut fb = Framebuffer::new(20, 8);
+        fb.take_dirty();
+        assert_eq!(fb.draw_text(0, 0, "|-x", WHITE, BLACK), 24);
+        // `|` is a vertical bar in columns 3 and 4; the `x` is clipped.
+        assert_eq!(fb.pixel(3, 0), Some(WHITE));
+        assert_eq!(fb.pixel(2, 0), Some(BLACK));
+        assert_eq!(fb.take_dirty(), Some(Rect::new(0, 0, 20, 8)));
+    }
+
+    #[test]
+    fn parses_config() {
+        let config = parse_config("st7735").unwrap();
+        assert_eq!(config, tft::Config::new(Panel::St7735));
+        let config = parse_config("ili9341, 22, none").unwrap();
+        assert_eq!((config.panel, config.dc, config.reset), (Panel::Ili9341, 22, None));
+        assert_eq!(config_name(&config), "ili9341,22,none");
+        assert_eq!(parse_config("ssd1306"), None);
+        assert_eq!(parse_config("st7735,54"), None);
+        assert_eq!(parse_config("st7735,24,25,1"), None);
+    }
+}
@@ -0,0 +1,279 @@
+//! An interactive editor for the SD card's partition table, so that data
+//! partitions can be made on a blank card from the device itself.
+//!
+//! Edits are made to a copy of the MBR and only reach the card on `w`, once
+//! confirmed. Partitions are created at 1MiB boundaries by default, and get
+//! CHS addresses that match their LBA ones. The partition the root file
+//! system is mounted from cannot be deleted or resized.
+
+use core::str;
+
+use fat32::traits::BlockDevice;
+use fat32::{self, MasterBootRecord};
+
+use crate::console::line::{self, LineDiscipline};
+use crate::console::{kprint, kprintln, CONSOLE};
+use crate::fs::sd::Sd;
+use crate::shell::parse_number;
+use crate::FILESYSTEM;
+
+/// New partitions start on multiples of this many sectors, 1MiB.
+const ALIGN: u64 = 2048;
+
+/// The longest command line.
+const MAX_LINE_LEN: usize = 128;
+
+struct Session {
+    sd: Sd,
+    mbr: MasterBootRecord,
+    /// The card's size in sectors.
+    disk_sectors: u64,
+    /// The slot of the partition the root file system is mounted from.
+    root: Option<usize>,
+    /// Whether the table has edits not written to the card.
+    changed: bool,
+}
+
+/// What a command does once it ran.
+#[derive(PartialEq)]
+enum Next {
+    Prompt,
+    Leave,
+}
+
+/// Edits the SD card's partition table until `q` or end of input. A card
+/// without a valid MBR starts with an empty table.
+pub fn run() {
+    let sd = match FILESYSTEM.device() {
+        Some(sd) => sd,
+        None => return kprintln!("fdisk: no SD card"),
+    };
+    // A card that reports no size may still be addressed to the MBR's limit.
+    let disk_sectors = sd.num_sectors().unwrap_or(u32::max_value() as u64);
+    let mbr = match MasterBootRecord::from(sd) {
+        Ok(mbr) => mbr,
+        Err(fat32::Error::Io(e)) => return kprintln!("fdisk: cannot read the partition table: {:?}", e),
+        Err(e) => {
+            kprintln!("fdisk: no valid partition table ({:?}); starting an empty one", e);
+            MasterBootRecord::new(pi::timer::current_time().as_micros() as u32)
+        }
+    };
+    // `VFat::from()` mounts the first FAT32 partition.
+    let root = match FILESYSTEM.options() {
+        Some(_) => mbr.partition_table.iter().position(|p| {
+            p.partition_type == fat32::TYPE_FAT32 || p.partition_type == fat32::TYPE_FAT32_LBA
+        }),
+        None => None,
+    };
+    kprintln!("disk: {} sectors ({} MiB); try help", disk_sectors, disk_sectors >> 11);
+    Session { sd, mbr, disk_sectors, root, changed: false }.run();
+}
+
+impl Session {
+    fn run(&mut self) {
+        let mut discipline = LineDiscipline::new();
+        let mut buf = [0u8; MAX_LINE_LEN];
+        loop {
+            kprint!("(fdisk) ");
+            let line = match read_line(&mut discipline, &mut buf) {
+                Some(line) => line,
+                None => {
+                    kprintln!();
+                    self.quit();
+                    return;
+                }
+            };
+            let mut args = line.split_whitespace();
+            let command = match args.next() {
+                Some(command) => command,
+                None => continue,
+            };
+            let args: [Option<&str>; 4] = [args.next(), args.next(), args.next(), args.next()];
+            if self.execute(command, args, &mut discipline) == Next::Leave {
+                return;
+            }
+        }
+    }
+
+    fn execute(&mut self, command: &str, args: [Option<&str>; 4], discipline: &mut LineDiscipline) -> Next {
+        let result = match command {
+            "p" | "print" => {
+                self.print();
+                Ok(())
+            }
+            "n" | "new" => match (args[0], args[1]) {
+                (Some(slot), Some(kind)) => self.create(slot, kind, args[2], args[3]),
+                _ => Err("n: <slot> <type> [start] [size] arguments required"),
+            },
+            "d" | "delete" => match args[0] {
+                Some(slot) => self.delete(slot),
+                None => Err("d: <slot> argument required"),
+            },
+            "r" | "resize" => match (args[0], args[1]) {
+                (Some(slot), Some(size)) => self.resize(slot, size),
+                _ => Err("r: <slot> <size> arguments required"),
+            },
+            "w" | "write" => self.write(discipline),
+            "q" | "quit" => {
+                self.quit();
+                return Next::Leave;
+            }
+            "help" => {
+                help();
+                Ok(())
+            }
+            _ => Err("unknown command; try help"),
+        };
+        if let Err(e) = result {
+            kprintln!("error: {}", e);
+        }
+        Next::Prompt
+    }
+
+    fn print(&self) {
+        kprintln!("slot  boot  type  start       end         sectors     size");
+        for (i, partition) in self.mbr.partition_table.iter().enumerate() {
+            if !partition.is_used() {
+                continue;
+            }
+            let (start, count) = (partition.sector_offset as u64, partition.num_sectors as u64);
+            kprint!("{:<4}  {:<4}  {:#04x}  {:<10}  {:<10}  {:<10}  {} MiB",
+                i + 1, if partition.is_bootable() { "*" } else { "" }, partition.partition_type,
+                start, partition.end_sector() - 1, count, count >> 11);
+            if !partition.chs_matches_lba() {
+                kprint!("  (CHS does not match LBA)");
+            }
+            if self.root == Some(i) {
+                kprint!("  (mounted at /)");
+            }
+            kprintln!();
+        }
+        match self.mbr.largest_free(self.disk_sectors, ALIGN) {
+            Some((start, count)) => kprintln!("largest free: {} sectors from {} ({} MiB)", count, start, count >> 11),
+            None => kprintln!("largest free: none"),
+        }
+    }
+
+    /// Creates partition `slot` of type `kind`, in hex. It starts at `start`,
+    /// or the largest free space, and has `size` sectors, or the rest of the
+    /// free space from `start`.
+    fn create(&mut self, slot: &str, kind: &str, start: Option<&str>, size: Option<&str>) -> Result<(), &'static str> {
+        let index = parse_slot(slot)?;
+        let kind = u8::from_str_radix(kind.trim_start_matches("0x"), 16).map_err(|_| "n: invalid type")?;
+        let free = self.mbr.largest_free(self.disk_sectors, ALIGN);
+        let start = match start {
+            Some(start) => parse_number(start).ok_or("n: invalid start")?,
+            None => free.ok_or("n: no free space")?.0,
+        };
+        let count = match (size, free) {
+            (Some(size), _) => parse_size(size).ok_or("n: invalid size")?,
+            (None, Some((free_start, free_count))) if start >= free_start && start < free_start + free_count => {
+                free_start + free_count - start
+            }
+            (None, _) => return Err("n: start is not in the largest free space; give a size"),
+        };
+        self.mbr.create(index, kind, start, count, self.disk_sectors).map_err(error)?;
+        self.changed = true;
+        Ok(())
+    }
+
+    fn delete(&mut self, slot: &str) -> Result<(), &'static str> {
+        let index = parse_slot(slot)?;
+        if self.root == Some(index) {
+            return Err("d: the partition is mounted at /");
+        }
+        self.mbr.delete(index).map_err(error)?;
+        self.changed = true;
+        Ok(())
+    }
+
+    fn resize(&mut self, slot: &str, size: &str) -> Result<(), &'static str> {
+        let index = parse_slot(slot)?;
+        if self.root == Some(index) {
+            return Err("r: the partition is mounted at /");
+        }
+        let count = parse_size(size).ok_or("r: invalid size")?;
+        self.mbr.resize(index, count, self.disk_sectors).map_err(error)?;
+        self.changed = true;
+        Ok(())
+    }
+
+    /// Writes the table to the card once the user types `yes`.
+    fn write(&mut self, discipline: &mut LineDiscipline) -> Result<(), &'static str> {
+        if !self.changed {
+            return Err("w: no changes");
+        }
+        kprint!("write the partition table to the SD card? type yes: ");
+        let mut buf = [0u8; MAX_LINE_LEN];
+        match read_line(discipline, &mut buf) {
+            Some(line) if line.trim() == "yes" => {}
+            _ => return Err("w: not written"),
+        }
+        self.mbr.write_to(self.sd).map_err(|_| "w: writing the partition table failed")?;
+        self.changed = false;
+        kprintln!("partition table written");
+        Ok(())
+    }
+
+    fn quit(&self) {
+        if self.changed {
+            kprintln!("changes discarded");
+        }
+    }
+}
+
+/// Reads a line from the console. Returns `None` at end of input, and an
+/// empty line if reading was interrupted or the line is not UTF-8.
+fn read_line<'a>(discipline: &mut LineDiscipline, buf: &'a mut [u8]) -> Option<&'a str> {
+    let mut console = CONSOLE.lock();
+    let len = match discipline.read_line(&mut *console, buf) {
+        Ok(len) => len,
+        Err(line::Error::Interrupted) => 0,
+        Err(line::Error::EndOfFile) => return None,
+    };
+    Some(str::from_utf8(&buf[..len]).unwrap_or(""))
+}
+
+/// Returns the message for an error editing the table.
+fn error(e: fat32::Error) -> &'static str {
+    match e {
+        fat32::Error::InUse(_) => "the slot is in use",
+        fat32::Error::Unused(_) => "the slot is empty",
+        fat32::Error::BadType => "type 0 marks empty slots",
+        fat32::Error::BadExtent => "the sectors are not on the disk past the MBR",
+        fat32::Error::Overlaps(_) => "the sectors overlap another partition",
+        _ => "invalid partition",
+    }
+}
+
+/// Parses a slot number, 1 to 4, to an index into the table.
+fn parse_slot(arg: &str) -> Result<usize, &'static str> {
+    match arg.parse::<usize>() {
+        Ok(n) if n >= 1 && n <= 4 => Ok(n - 1),
+        _ => Err("slots are 1 to 4"),
+    }
+}
+
+/// Parses a number of sectors, or of MiB or GiB with an `M` or `G` suffix.
+fn parse_size(arg: &str) -> Option<u64> {
+    let (number, shift) = match arg.as_bytes().last()? {
+        b'M' | b'm' => (&arg[..arg.len() - 1], 11),
+        b'G' | b'g' => (&arg[..arg.len() - 1], 21),
+        _ => (arg, 0),
+    };
+    let count = parse_number(number)?;
+    if count.leading_zeros() < shift {
+        return None;
+    }
+    Some(count << shift)
+}
+
+fn help() {
+    kprintln!("p, print                        show the partitions and free space");
+    kprintln!("n <slot> <type> [start] [size]  create a partition; type is hex, e.g. c for FAT32");
+    kprintln!("d <slot>                        delete a partition");
+    kprintln!("r <slot> <size>                 resize a partition, keeping its start");
+    kprintln!("w, write                        write the table to the SD card");
+    kprintln!("q, quit                         leave, discarding unwritten changes");
+    kprintln!("slots are 1 to 4; sizes are in sectors, or MiB or GiB with M or G");
+}
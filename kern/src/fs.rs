@@ -1,18 +1,46 @@
 pub mod sd;
 
+use alloc::format;
 use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec::Vec;
 use core::fmt::{self, Debug};
+use core::time::Duration;
+use shim::canonical;
 use shim::io;
-use shim::ioerr;
+use shim::{ioerr, newioerr};
 use shim::path::Path;
 
-use fat32::vfat::{Dir, Entry, File, VFat, VFatHandle};
+use fat32::traits::{FileSystem as _, FsError, MemDisk};
+use fat32::vfat::{CacheStats, Dir, Entry, Error, File, Lock, MountOptions, VFat, VFatHandle};
 
 pub use self::sd::Sd;
+use crate::cmdline::Param;
+use crate::console::kprintln;
 use crate::mutex::Mutex;
+use crate::process::{Id, Process};
+use crate::{FILESYSTEM, SCHEDULER};
+
+/// How often the writeback thread writes modified sectors to the SD card.
+const WRITEBACK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Where a ramdisk is mounted. While one is, it hides whatever the SD card
+/// has at this path.
+pub const RAMDISK_PATH: &str = "/ram";
+
+/// The file system's kernel parameters; see `cmdline`.
+pub static PARAMS: &[Param] = &[Param {
+    name: "rootflags",
+    description: "file system mount options, e.g. ro,noatime,codepage=437",
+    set: set_rootflags,
+}];
+
+fn set_rootflags(_name: &str, value: &str) -> Result<(), String> {
+    FILESYSTEM.mount(value).map_err(|e| format!("{:?}", e))
+}
 
 #[derive(Clone)]
-pub struct PiVFatHandle(Rc<Mutex<VFat<Self>>>);
+pub struct PiVFatHandle(Rc<VFat<Self>>);
 
 // These impls are *unsound*. We should use `Arc` instead of `Rc` to implement
 // `Sync` and `Send` trait for `PiVFatHandle`. However, `Arc` uses atomic memory
@@ -28,16 +56,34 @@ impl Debug for PiVFatHandle {
     }
 }
 
+/// Not exclusive: the kernel `Mutex` never waits, so `critical()` does not
+/// keep a second caller out while the first runs. Until `Mutex` excludes
+/// other callers, the file system must not be entered by two at once.
+impl Lock for Mutex<()> {
+    fn new() -> Self {
+        Mutex::new(())
+    }
+
+    fn critical<R>(&self, f: impl FnOnce() -> R) -> R {
+        let _guard = self.lock();
+        f()
+    }
+}
+
 impl VFatHandle for PiVFatHandle {
+    type Lock = Mutex<()>;
+
     fn new(val: VFat<PiVFatHandle>) -> Self {
-        PiVFatHandle(Rc::new(Mutex::new(val)))
+        PiVFatHandle(Rc::new(val))
     }
 
-    fn lock<R>(&self, f: impl FnOnce(&mut VFat<PiVFatHandle>) -> R) -> R {
-        f(&mut self.0.lock())
+    fn with<R>(&self, f: impl FnOnce(&VFat<PiVFatHandle>) -> R) -> R {
+        f(&self.0)
     }
 }
-pub struct FileSystem(Mutex<Option<PiVFatHandle>>);
+/// The file system on the SD card, the card itself and the ramdisk mounted at
+/// `RAMDISK_PATH`, if any.
+pub struct FileSystem(Mutex<Option<PiVFatHandle>>, Mutex<Option<Sd>>, Mutex<Option<PiVFatHandle>>);
 
 impl FileSystem {
     /// Returns an uninitialized `FileSystem`.
@@ -45,7 +91,7 @@ impl FileSystem {
     /// The file system must be initialized by calling `initialize()` before the
     /// first memory allocation. Failure to do will result in panics.
     pub const fn uninitialized() -> Self {
-        FileSystem(Mutex::new(None))
+        FileSystem(Mutex::new(None), Mutex::new(None), Mutex::new(None))
     }
 
     /// Initializes the file system.
@@ -58,12 +104,166 @@ impl FileSystem {
     pub unsafe fn initialize(&self) {
         match Sd::new() {
             Ok(sd) => match VFat::from(sd) {
-                Ok(vfat) => *self.0.lock() = Some(vfat),
+                Ok(vfat) => {
+                    *self.0.lock() = Some(vfat);
+                    *self.1.lock() = Some(sd);
+                }
                 Err(e) => panic!("error initializing file system {:?}", e),
             }
             Err(e) => panic!("error initializing SD card {:?}", e),
         };
     }
+
+    /// Returns a handle to the SD card backing the file system, for raw
+    /// sector access that bypasses the file system. Returns `None` if the file
+    /// system is uninitialized.
+    pub fn device(&self) -> Option<Sd> {
+        *self.1.lock()
+    }
+
+    /// Returns the volume label, or `None` if the volume has none or the file
+    /// system is uninitialized.
+    pub fn volume_label(&self) -> Option<String> {
+        self.0.lock().as_ref()?.with(|vfat| vfat.volume_label().map(String::from))
+    }
+
+    /// Writes every modified sector in the file system's cache back to the
+    /// SD card.
+    pub fn sync(&self) -> io::Result<()> {
+        match self.0.lock().as_ref() {
+            Some(vfat) => vfat.with(|vfat| vfat.sync()),
+            None => ioerr!(Other, "uninitialized filesystem"),
+        }
+    }
+
+    /// Returns how the file system's sector cache has been used, or `None`
+    /// if it is not mounted.
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        Some(self.0.lock().as_ref()?.with(|vfat| vfat.cache_stats()))
+    }
+
+    /// Returns the options the file system is mounted with, or `None` if it
+    /// is not mounted.
+    pub fn options(&self) -> Option<MountOptions> {
+        Some(self.0.lock().as_ref()?.with(|vfat| vfat.options()))
+    }
+
+    /// Changes the options of the mounted file system to the comma-separated
+    /// `options`, which are parsed by `MountOptions::parse()`; options not
+    /// mentioned keep their values. If the file system is unmounted, mounts
+    /// it from the SD card with `options` instead. Remounting read-only
+    /// writes modified sectors back first.
+    pub fn mount(&self, options: &str) -> io::Result<()> {
+        let mut guard = self.0.lock();
+        if let Some(vfat) = guard.as_ref() {
+            return vfat.with(|vfat| {
+                let options = vfat.options().parse(options)?;
+                vfat.remount(options)
+            });
+        }
+        let options = MountOptions::default().parse(options)?;
+        let sd = match *self.1.lock() {
+            Some(sd) => sd,
+            None => return ioerr!(Other, "uninitialized filesystem"),
+        };
+        let vfat: PiVFatHandle = match VFat::from(sd) {
+            Ok(vfat) => vfat,
+            Err(Error::Io(e)) => return Err(e),
+            Err(_) => return ioerr!(InvalidData, "no FAT32 file system on the SD card"),
+        };
+        vfat.with(|vfat| vfat.remount(options))?;
+        *guard = Some(vfat);
+        Ok(())
+    }
+
+    /// Writes modified sectors back and unmounts the file system. Files that
+    /// are still open keep the volume, read-only, until they are dropped.
+    pub fn umount(&self) -> io::Result<()> {
+        let mut guard = self.0.lock();
+        match guard.as_ref() {
+            Some(vfat) => vfat.with(|vfat| {
+                let options = MountOptions { read_only: true, ..vfat.options() };
+                vfat.remount(options)
+            })?,
+            None => return ioerr!(NotFound, "file system not mounted"),
+        }
+        *guard = None;
+        Ok(())
+    }
+
+    /// Mounts the FAT32 file system on `disk` at `RAMDISK_PATH`, as scratch
+    /// space that is lost at reboot.
+    pub fn mount_ramdisk(&self, disk: MemDisk) -> io::Result<()> {
+        let mut guard = self.2.lock();
+        if guard.is_some() {
+            return ioerr!(AlreadyExists, "a ramdisk is already mounted");
+        }
+        match VFat::from(disk) {
+            Ok(vfat) => *guard = Some(vfat),
+            Err(Error::Io(e)) => return Err(e),
+            Err(_) => return ioerr!(InvalidData, "no FAT32 file system on the ramdisk"),
+        }
+        Ok(())
+    }
+
+    /// Unmounts the ramdisk, freeing its memory once files still open on it
+    /// are dropped.
+    pub fn umount_ramdisk(&self) -> io::Result<()> {
+        match self.2.lock().take() {
+            Some(_) => Ok(()),
+            None => ioerr!(NotFound, "no ramdisk mounted"),
+        }
+    }
+
+    /// Returns the options the ramdisk is mounted with, or `None` if none is
+    /// mounted.
+    pub fn ramdisk_options(&self) -> Option<MountOptions> {
+        Some(self.2.lock().as_ref()?.with(|vfat| vfat.options()))
+    }
+}
+
+/// Starts a kernel thread that calls `FILESYSTEM.sync()` every
+/// `WRITEBACK_INTERVAL`, so that writes reach the SD card without explicit
+/// flushes and without blocking the writer.
+///
+/// The caller should assure that `FILESYSTEM` and `SCHEDULER` have been
+/// initialized. Returns the ID of the thread.
+pub fn start_writeback() -> Option<Id> {
+    match Process::kernel_thread(writeback_thread) {
+        Ok(thread) => SCHEDULER.add(thread),
+        Err(e) => {
+            kprintln!("fs: cannot start writeback thread: {:?}", e);
+            None
+        }
+    }
+}
+
+extern "C" fn writeback_thread() -> ! {
+    loop {
+        let _ = kernel_api::syscall::sleep(WRITEBACK_INTERVAL);
+        // Unmounting wrote everything back.
+        if FILESYSTEM.options().is_none() {
+            continue;
+        }
+        if let Err(e) = FILESYSTEM.sync() {
+            kprintln!("fs: writeback failed: {:?}", e);
+        }
+    }
+}
+
+/// Reads `reader` to its end, appending what it reads to `buf`, and returns
+/// the number of bytes read: core_io's `Read` has no `read_to_end()`.
+pub fn read_to_end<R: io::Read>(reader: &mut R, buf: &mut Vec<u8>) -> io::Result<usize> {
+    let start = buf.len();
+    let mut chunk = [0; 512];
+    loop {
+        match reader.read(&mut chunk) {
+            Ok(0) => return Ok(buf.len() - start),
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
 }
 
 impl fat32::traits::FileSystem for &FileSystem {
@@ -71,10 +271,16 @@ impl fat32::traits::FileSystem for &FileSystem {
     type Dir = Dir<PiVFatHandle>;
     type Entry = Entry<PiVFatHandle>;
 
-    fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Entry> {
+    fn lookup<P: AsRef<Path>>(self, path: P) -> Result<Self::Entry, FsError> {
+        let path = canonical::normalize(path);
+        if let Ok(rest) = path.strip_prefix(RAMDISK_PATH) {
+            if let Some(ramdisk) = self.2.lock().as_ref() {
+                return ramdisk.lookup(Path::new("/").join(rest));
+            }
+        }
         match self.0.lock().as_ref() {
-            Some(ref vfat) => vfat.open(path),
-            None => ioerr!(Other, "uninitialized filesystem"),
+            Some(ref vfat) => vfat.lookup(path),
+            None => Err(FsError::Io(newioerr!(Other, "uninitialized filesystem"))),
         }
     }
 }
@@ -1,46 +1,46 @@
-use core::time::Duration;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::slice;
+
 use shim::io;
-use shim::ioerr;
+use shim::{ioerr, newioerr};
 
-use pi::timer::spin_sleep;
+use aarch64::{clean_invalidate_dcache_range, CACHE_LINE_SIZE};
+use pi::dma::{Channel, ControlBlock};
+use pi::emmc::{self, Emmc, BLOCK_SIZE};
 
-use fat32::traits::BlockDevice;
+use fat32::traits::{AsyncRead, BlockDevice};
 
-extern "C" {
-    /// A global representing the last SD controller error that occured.
-    static sd_err: i64;
+use crate::mutex::Mutex;
 
-    /// Initializes the SD card controller.
-    ///
-    /// Returns 0 if initialization is successful. If initialization fails,
-    /// returns -1 if a timeout occured, or -2 if an error sending commands to
-    /// the SD controller occured.
-    fn sd_init() -> i32;
-
-    /// Reads sector `n` (512 bytes) from the SD card and writes it to `buffer`.
-    /// It is undefined behavior if `buffer` does not point to at least 512
-    /// bytes of memory. Also, the caller of this function should make sure that
-    /// `buffer` is at least 4-byte aligned.
-    ///
-    /// On success, returns the number of bytes read: a positive number.
-    ///
-    /// On error, returns 0. The true error code is stored in the `sd_err`
-    /// global. `sd_err` will be set to -1 if a timeout occured or -2 if an
-    /// error sending commands to the SD controller occured. Other error codes
-    /// are also possible but defined only as being less than zero.
-    fn sd_readsector(n: i32, buffer: *mut u8) -> i32;
+/// The DMA channel that asynchronous reads are transferred with.
+const DMA_CHANNEL: u8 = 4;
+
+/// An asynchronous read, identified by the address of its buffer.
+struct Transfer {
+    sector: u32,
+    buf: usize,
 }
 
-// FIXME: Define a `#[no_mangle]` `wait_micros` function for use by `libsd`.
-// The `wait_micros` C signature is: `void wait_micros(unsigned int);`
-#[no_mangle]
-fn wait_micros(micros: u32) {
-    spin_sleep(Duration::from_micros(micros as u64 * 100));
+/// The asynchronous reads in flight. The card serves one at a time; the
+/// rest wait their turn.
+struct Queue {
+    dma: Channel,
+    active: Option<Transfer>,
+    waiting: VecDeque<Transfer>,
+    /// The outcomes of completed reads not yet collected by `poll_read()`.
+    done: Vec<(usize, io::Result<usize>)>,
 }
 
+static QUEUE: Mutex<Option<Queue>> = Mutex::new(None);
+
+/// The control block of the active transfer. It is read by the DMA engine,
+/// so it must not move.
+static mut BLOCK: ControlBlock = ControlBlock::new();
+
 /// A handle to an SD card controller.
-#[derive(Debug)]
-pub struct Sd;
+#[derive(Copy, Clone, Debug)]
+pub struct Sd(Emmc);
 
 impl Sd {
     /// Initializes the SD card controller and returns a handle to it.
@@ -48,16 +48,108 @@ impl Sd {
     /// kernel initialization. We can enforce the requirement in safe Rust code
     /// with atomic memory access, but we can't use it yet since we haven't
     /// written the memory management unit (MMU).
+    ///
+    /// The handle is `Copy`; copies access the same card and may be used where
+    /// the file system's lock cannot be taken.
     pub unsafe fn new() -> Result<Sd, io::Error> {
-        match sd_init() {
-            0 => Ok(Sd),
-            -1 => ioerr!(TimedOut, "sd init timeout"),
-            _ => ioerr!(Other, "sd error"),
+        match Emmc::new() {
+            Ok(emmc) => {
+                *QUEUE.lock() = Some(Queue {
+                    dma: Channel::new(DMA_CHANNEL),
+                    active: None,
+                    waiting: VecDeque::new(),
+                    done: Vec::new(),
+                });
+                Ok(Sd(emmc))
+            }
+            Err(emmc::Error::Timeout) => ioerr!(TimedOut, "sd init timeout"),
+            Err(_) => ioerr!(Other, "sd error"),
+        }
+    }
+}
+
+impl Sd {
+    /// Completes the active transfer if the card is done with it, and then
+    /// starts the next waiting one. Never blocks.
+    fn advance(&mut self, queue: &mut Queue) {
+        loop {
+            if let Some(ref transfer) = queue.active {
+                let result = match self.0.poll_transfer(&mut queue.dma) {
+                    Some(result) => result,
+                    None => return,
+                };
+                unsafe { clean_invalidate_dcache_range(transfer.buf, BLOCK_SIZE) };
+                queue.done.push((transfer.buf, result.map(|_| BLOCK_SIZE).map_err(io_error)));
+                queue.active = None;
+            }
+            let transfer = match queue.waiting.pop_front() {
+                Some(transfer) => transfer,
+                None => return,
+            };
+            let started = unsafe {
+                let buf = slice::from_raw_parts_mut(transfer.buf as *mut u8, BLOCK_SIZE);
+                self.0.start_read_dma(transfer.sector, buf, &mut queue.dma, &mut BLOCK)
+            };
+            match started {
+                Ok(()) => queue.active = Some(transfer),
+                Err(e) => queue.done.push((transfer.buf, Err(io_error(e)))),
+            }
+        }
+    }
+
+    /// Reads block `n` into `buf` with DMA and waits for it, once the queued
+    /// asynchronous reads are done. Returns `None`, reading nothing, before
+    /// `new()` has set up DMA.
+    ///
+    /// `buf` must start on a cache line, so that cleaning and invalidating
+    /// the lines it covers touches nothing else.
+    fn read_dma(&mut self, n: u32, buf: &mut [u8]) -> Option<Result<(), emmc::Error>> {
+        let mut guard = QUEUE.lock();
+        let queue = guard.as_mut()?;
+        while queue.active.is_some() || !queue.waiting.is_empty() {
+            self.advance(queue);
+        }
+        let addr = buf.as_ptr() as usize;
+        unsafe {
+            clean_invalidate_dcache_range(addr, BLOCK_SIZE);
+            if let Err(e) = self.0.start_read_dma(n, buf, &mut queue.dma, &mut BLOCK) {
+                return Some(Err(e));
+            }
+        }
+        let result = loop {
+            if let Some(result) = self.0.poll_transfer(&mut queue.dma) {
+                break result;
+            }
+        };
+        unsafe { clean_invalidate_dcache_range(addr, BLOCK_SIZE) };
+        Some(result)
+    }
+
+    /// Waits until no asynchronous read is using the card, so that a
+    /// synchronous command can be issued.
+    fn drain(&mut self) {
+        if let Some(queue) = QUEUE.lock().as_mut() {
+            while queue.active.is_some() || !queue.waiting.is_empty() {
+                self.advance(queue);
+            }
         }
     }
 }
 
+/// Converts an SD controller error into an I/O error.
+fn io_error(error: emmc::Error) -> io::Error {
+    match error {
+        emmc::Error::Timeout => io::Error::new(io::ErrorKind::TimedOut, "sd timed out"),
+        _ => io::Error::new(io::ErrorKind::Other, "sd error"),
+    }
+}
+
 impl BlockDevice for Sd {
+    /// Returns the capacity the card reports, in 512-byte sectors.
+    fn num_sectors(&self) -> Option<u64> {
+        Some(self.0.blocks())
+    }
+
     /// Reads sector `n` from the SD card into `buf`. On success, the number of
     /// bytes read is returned.
     ///
@@ -77,17 +169,79 @@ impl BlockDevice for Sd {
         if n > i32::max_value() as u64 {
             return ioerr!(InvalidInput, "n too large");
         }
-        let result = unsafe { sd_readsector(n as i32, buf.as_mut_ptr()) };
-        if result > 0 {
-            Ok(result as usize)
-        } else if unsafe { sd_err } == -1 {
-            ioerr!(TimedOut, "read timed out")
-        } else {
-            ioerr!(Other, "sd error")
+        // A buffer on a cache line is filled by the DMA engine directly,
+        // e.g. a large file read that bypasses the sector cache.
+        if buf.as_ptr() as usize % CACHE_LINE_SIZE == 0 {
+            if let Some(result) = self.read_dma(n as u32, buf) {
+                return result.map(|_| 512).map_err(io_error);
+            }
         }
+        self.drain();
+        self.0.read_block(n as u32, buf).map_err(io_error)?;
+        Ok(512)
     }
 
-    fn write_sector(&mut self, _n: u64, _buf: &[u8]) -> io::Result<usize> {
-        unimplemented!("SD card and file system are read only")
+    /// Writes the first 512 bytes of `buf` to sector `n`. On success, the
+    /// number of bytes written is returned.
+    ///
+    /// # Errors
+    ///
+    /// Errors are returned in the same cases as for `read_sector()`.
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() < 512 {
+            return ioerr!(InvalidInput, "buf too smol");
+        }
+        if n > i32::max_value() as u64 {
+            return ioerr!(InvalidInput, "n too large");
+        }
+        self.drain();
+        self.0.write_block(n as u32, buf).map_err(io_error)?;
+        Ok(512)
+    }
+
+    /// Queues a read of sector `n` into `buf`, which the DMA engine fills
+    /// once the reads queued before it have completed. Falls back to a
+    /// synchronous read before `new()` has set up DMA.
+    fn read_sector_async(&mut self, n: u64, mut buf: Vec<u8>) -> AsyncRead {
+        buf.resize(BLOCK_SIZE, 0);
+        if n > i32::max_value() as u64 {
+            return AsyncRead::completed(n, buf, Err(newioerr!(InvalidInput, "n too large")));
+        }
+        let mut guard = QUEUE.lock();
+        let queue = match guard.as_mut() {
+            Some(queue) => queue,
+            None => {
+                drop(guard);
+                let result = self.read_sector(n, &mut buf);
+                return AsyncRead::completed(n, buf, result);
+            }
+        };
+        // Discard cached lines now, so that none is written back over the
+        // data while the DMA engine fills the buffer.
+        unsafe { clean_invalidate_dcache_range(buf.as_ptr() as usize, BLOCK_SIZE) };
+        queue.waiting.push_back(Transfer { sector: n as u32, buf: buf.as_ptr() as usize });
+        self.advance(queue);
+        AsyncRead::pending(n, buf)
+    }
+
+    fn poll_read(&mut self, read: &mut AsyncRead) -> bool {
+        if read.is_complete() {
+            return true;
+        }
+        let key = read.buffer().as_ptr() as usize;
+        let mut guard = QUEUE.lock();
+        let queue = match guard.as_mut() {
+            Some(queue) => queue,
+            None => return false,
+        };
+        self.advance(queue);
+        match queue.done.iter().position(|&(buf, _)| buf == key) {
+            Some(i) => {
+                let (_, result) = queue.done.swap_remove(i);
+                read.complete(result);
+                true
+            }
+            None => false,
+        }
     }
 }
@@ -28,6 +28,18 @@ pub unsafe extern "C" fn _start() -> ! {
     unreachable!()
 }
 
+/// Where the secondary cores start once `smp::start_cores()` wakes them,
+/// with the MMU off and on the stack it set up for them.
+#[no_mangle]
+pub unsafe extern "C" fn _start_secondary() -> ! {
+    let core = MPIDR_EL1.get_value(MPIDR_EL1::Aff0) as usize;
+    SP.set(crate::smp::STACK_TOPS[core].load(core::sync::atomic::Ordering::Relaxed) as usize);
+    switch_to_el2();
+    switch_to_el1();
+    setup_el1();
+    crate::smp::secondary_main(core)
+}
+
 unsafe fn zeros_bss() {
     extern "C" {
         static mut __bss_beg: u64;
@@ -61,32 +73,25 @@ unsafe fn switch_to_el2() {
 
 #[no_mangle]
 unsafe fn switch_to_el1() {
-    extern "C" {
-        static mut vectors: u64;
-    }
-
     if current_el() == 2 {
         // set the stack-pointer for EL1
         SP_EL1.set(SP.get() as u64);
 
         // enable CNTP for EL1/EL0 (ref: D7.5.2, D7.5.13)
         // NOTE: This doesn't actually enable the counter stream.
-        CNTHCTL_EL2.set(CNTHCTL_EL2.get() | CNTHCTL_EL2::EL0VCTEN | CNTHCTL_EL2::EL0PCTEN);
+        CNTHCTL_EL2.set(CNTHCTL_EL2.get() | CNTHCTL_EL2::EL1PCEN | CNTHCTL_EL2::EL1PCTEN);
         CNTVOFF_EL2.set(0);
 
-        // enable AArch64 in EL1 (A53: 4.3.36)
+        // enable AArch64 in EL1 (A53: 4.3.36). This also clears E2H and the
+        // trap bits firmware may have left set for a hypervisor.
         HCR_EL2.set(HCR_EL2::RW | HCR_EL2::RES1);
 
-        // enable floating point and SVE (SIMD) (A53: 4.3.38, 4.3.34)
+        // don't trap floating point and SVE (SIMD) accesses to EL2 (A53: 4.3.38)
         CPTR_EL2.set(0);
-        CPACR_EL1.set(CPACR_EL1.get() | (0b11 << 20));
 
         // Set SCTLR to known state (A53: 4.3.30)
         SCTLR_EL1.set(SCTLR_EL1::RES1);
 
-        // set up exception handlers
-        VBAR_EL1.set((&mut vectors as *mut u64) as u64);
-
         // change execution level to EL1 (ref: C5.2.19)
         SPSR_EL2.set(
             (SPSR_EL2::M & 0b0101) // EL1h
@@ -102,10 +107,33 @@ unsafe fn switch_to_el1() {
     }
 }
 
+/// Sets up what EL1 needs whichever level the firmware started the kernel
+/// at: EL2 sets it up before dropping to EL1, but firmware that starts the
+/// kernel at EL1 does not.
+#[no_mangle]
+unsafe fn setup_el1() {
+    extern "C" {
+        static mut vectors: u64;
+    }
+
+    // enable floating point and SVE (SIMD) (A53: 4.3.34)
+    CPACR_EL1.set(CPACR_EL1.get() | (0b11 << 20));
+
+    // set up exception handlers
+    VBAR_EL1.set((&mut vectors as *mut u64) as u64);
+
+    // clear the OS lock, set at cold reset, so that debug exceptions for
+    // user processes are taken (D2.5)
+    aarch64::debug::OSLAR_EL1.set(0);
+    isb();
+}
+
 #[no_mangle]
 unsafe fn kinit() -> ! {
     zeros_bss();
+    let boot_el = current_el();
     switch_to_el2();
     switch_to_el1();
-    kmain();
+    setup_el1();
+    kmain(boot_el);
 }
@@ -1,8 +1,17 @@
 use core::panic::PanicInfo;
-use crate::console::kprintln;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::console::{self, early, kprintln, CONSOLE};
+use crate::{crash, traps};
+
+/// Set once a panic starts, so that a panic while writing the crash dump
+/// does not try to write another one. Only loads and stores are used, since
+/// exclusive accesses fault until the MMU is enabled.
+static PANICKING: AtomicBool = AtomicBool::new(false);
 
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
+    console::take_over();
     kprintln!("Kernel Panic (-.-):");
     if let Some(msg) = _info.message() {
         kprintln!("{:?}", msg);
@@ -10,5 +19,17 @@ fn panic(_info: &PanicInfo) -> ! {
     if let Some(loc) = _info.location() {
         kprintln!("  at {}:{}:{}", loc.file(), loc.line(), loc.column());
     }
+    traps::backtrace(aarch64::frame_pointer());
+    if !PANICKING.load(Ordering::Relaxed) {
+        PANICKING.store(true, Ordering::Relaxed);
+        match crash::write(_info) {
+            Ok(location) => kprintln!("crash dump written to {}", location),
+            Err(e) => kprintln!("no crash dump: {:?}", e),
+        }
+    }
+    // Before the handover, `kprintln!` wrote straight to the UART.
+    if !early::is_active() {
+        unsafe { CONSOLE.force_lock() }.flush();
+    }
     loop {}
 }
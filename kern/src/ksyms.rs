@@ -0,0 +1,176 @@
+//! The kernel's symbol table, for naming code addresses in backtraces.
+//!
+//! `build.rs` generates the table from a first link of the kernel and it is
+//! embedded in the `.ksymtab` section of the image; see there for how and
+//! for its format. Images linked only once carry an empty table, and every
+//! lookup fails.
+
+use core::fmt;
+use core::str;
+
+include!(concat!(env!("OUT_DIR"), "/ksymtab.rs"));
+
+/// The table as generated. Never read through this static: its length
+/// changes with the table, and code that depended on it would move between
+/// the two links. The table is found through the linker's symbols instead.
+#[cfg(not(test))]
+#[used]
+#[link_section = ".ksymtab"]
+static TABLE: [u8; TABLE_LEN] = *include_bytes!(concat!(env!("OUT_DIR"), "/ksymtab.bin"));
+
+/// The size of a table entry: an address, a size and a name offset.
+const ENTRY_SIZE: usize = 16;
+
+/// The function containing an address.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    /// The demangled name of the function.
+    pub name: &'static str,
+    /// The address of its first instruction.
+    pub addr: u64,
+    /// How far into it the address is.
+    pub offset: u64,
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}+{:#x}", self.name, self.offset)
+    }
+}
+
+/// A symbol table in the format written by `build.rs`.
+struct Table<'a>(&'a [u8]);
+
+impl<'a> Table<'a> {
+    fn u16_at(&self, at: usize) -> Option<u16> {
+        Some(u16::from_le_bytes([*self.0.get(at)?, *self.0.get(at + 1)?]))
+    }
+
+    fn u32_at(&self, at: usize) -> Option<u32> {
+        let mut word = [0; 4];
+        word.copy_from_slice(self.0.get(at..at + 4)?);
+        Some(u32::from_le_bytes(word))
+    }
+
+    fn u64_at(&self, at: usize) -> Option<u64> {
+        let mut word = [0; 8];
+        word.copy_from_slice(self.0.get(at..at + 8)?);
+        Some(u64::from_le_bytes(word))
+    }
+
+    /// Returns the number of symbols in the table, or 0 if it is malformed.
+    fn len(&self) -> usize {
+        match self.0.get(..4) {
+            Some(b"KSYM") => self.u32_at(4).unwrap_or(0) as usize,
+            _ => 0,
+        }
+    }
+
+    /// Returns the address, size and name of the `index`th symbol.
+    fn entry(&self, index: usize) -> Option<(u64, u64, &'a str)> {
+        let at = 8 + index * ENTRY_SIZE;
+        let (addr, size) = (self.u64_at(at)?, self.u32_at(at + 8)? as u64);
+        let name = self.u32_at(at + 12)? as usize;
+        let len = self.u16_at(name)? as usize;
+        let name = str::from_utf8(self.0.get(name + 2..name + 2 + len)?).ok()?;
+        Some((addr, size, name))
+    }
+}
+
+impl Table<'static> {
+    /// Returns the symbol containing `addr`, if any.
+    fn lookup(&self, addr: u64) -> Option<Symbol> {
+        // Find the last symbol starting at or before `addr`.
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.entry(mid)?.0 <= addr {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        let (start, size, name) = self.entry(low.checked_sub(1)?)?;
+        // Symbols without a size are taken to reach up to the next one.
+        if size != 0 && addr >= start + size {
+            return None;
+        }
+        Some(Symbol { name, addr: start, offset: addr - start })
+    }
+}
+
+/// Returns the table embedded in the image.
+#[cfg(not(test))]
+fn table() -> Table<'static> {
+    extern "C" {
+        static __ksymtab_beg: u8;
+        static __ksymtab_end: u8;
+    }
+    unsafe {
+        let beg = &__ksymtab_beg as *const u8;
+        let len = &__ksymtab_end as *const u8 as usize - beg as usize;
+        Table(core::slice::from_raw_parts(beg, len))
+    }
+}
+
+#[cfg(test)]
+fn table() -> Table<'static> {
+    Table(&[])
+}
+
+/// Returns the number of symbols in the kernel's table.
+pub fn count() -> usize {
+    table().len()
+}
+
+/// Returns the kernel function containing `addr`, if it is known.
+pub fn lookup(addr: u64) -> Option<Symbol> {
+    table().lookup(addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use alloc::boxed::Box;
+
+    /// Encodes `symbols`, sorted by address, as `build.rs` does.
+    fn encode(symbols: &[(u64, u32, &str)]) -> &'static [u8] {
+        let mut table = b"KSYM".to_vec();
+        table.extend_from_slice(&(symbols.len() as u32).to_le_bytes());
+        let mut name = 8 + symbols.len() * ENTRY_SIZE;
+        for &(addr, size, symbol) in symbols {
+            table.extend_from_slice(&addr.to_le_bytes());
+            table.extend_from_slice(&size.to_le_bytes());
+            table.extend_from_slice(&(name as u32).to_le_bytes());
+            name += 2 + symbol.len();
+        }
+        for &(_, _, symbol) in symbols {
+            table.extend_from_slice(&(symbol.len() as u16).to_le_bytes());
+            table.extend_from_slice(symbol.as_bytes());
+        }
+        Box::leak(table.into_boxed_slice())
+    }
+
+    #[test]
+    fn lookup_finds_the_containing_function() {
+        let table = Table(encode(&[
+            (0x80000, 0x40, "_start"),
+            (0x80100, 0x20, "kernel::kmain"),
+            (0x80200, 0, "kernel::shell::shell"),
+        ]));
+        assert_eq!(table.len(), 3);
+
+        assert_eq!(table.lookup(0x7fffc), None);
+        let start = table.lookup(0x80000).unwrap();
+        assert_eq!((start.name, start.offset), ("_start", 0));
+        assert_eq!(table.lookup(0x80040), None);
+        let kmain = table.lookup(0x8011c).unwrap();
+        assert_eq!(format!("{}", kmain), "kernel::kmain+0x1c");
+        // The last symbol has no size, so it covers everything after it.
+        assert_eq!(table.lookup(0x90000).unwrap().name, "kernel::shell::shell");
+
+        assert_eq!(Table(b"").lookup(0x80000), None);
+        assert_eq!(Table(b"KSYM\x05\0\0\0").lookup(0x80000), None);
+    }
+}
@@ -0,0 +1,298 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use fat32::traits::{File as _, FileSystem};
+use fat32::vfat::{Dir, Entry, File};
+use pi::timer::current_time;
+use ringbuf::{Mpsc, Slot};
+use shim::io::{self, Seek, SeekFrom, Write};
+use shim::newioerr;
+
+use crate::cmdline::Param;
+use crate::console::{kprintln, print_log};
+use crate::fs::PiVFatHandle;
+use crate::process::{Id, Process};
+use crate::{FILESYSTEM, SCHEDULER};
+
+/// The severity of a log record. Records above the maximum level set with
+/// `set_max_level()` are discarded.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    /// Returns the level named `name`: `error`, `warn`, `info`, `debug` or
+    /// `trace`, ignoring case.
+    pub fn from_name(name: &str) -> Option<Level> {
+        [Level::Error, Level::Warn, Level::Info, Level::Debug, Level::Trace]
+            .iter()
+            .cloned()
+            .find(|level| level.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    fn from_usize(level: usize) -> Level {
+        match level {
+            1 => Level::Error,
+            2 => Level::Warn,
+            3 => Level::Info,
+            4 => Level::Debug,
+            _ => Level::Trace,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        })
+    }
+}
+
+/// The logger's kernel parameters; see `cmdline`.
+pub static PARAMS: &[Param] = &[Param {
+    name: "log_level",
+    description: "most verbose level logged: error, warn, info, debug or trace",
+    set: set_log_level,
+}];
+
+fn set_log_level(_name: &str, value: &str) -> Result<(), String> {
+    let level = match Level::from_name(value) {
+        Some(level) => level,
+        None => return Err(String::from("unknown level")),
+    };
+    set_max_level(level);
+    Ok(())
+}
+
+static MAX_LEVEL: AtomicUsize = AtomicUsize::new(Level::Info as usize);
+
+/// Sets the most verbose level that is logged.
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+/// Returns the most verbose level that is logged.
+pub fn max_level() -> Level {
+    Level::from_usize(MAX_LEVEL.load(Ordering::Relaxed))
+}
+
+/// The directory holding the log files. Created if it does not exist.
+pub const LOG_DIR: &str = "/var/log";
+
+/// The file in `LOG_DIR` that records are appended to. Rotated files are
+/// named `kernel.1` (the most recent) through `kernel.<KEEP>`.
+const LOG_NAME: &str = "kernel.log";
+
+/// The size at which `LOG_NAME` is rotated.
+const MAX_SIZE: u64 = 64 * 1024;
+
+/// The number of rotated files kept besides `LOG_NAME`.
+const KEEP: usize = 4;
+
+/// How often the flush thread writes pending records to the file.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The most bytes buffered between flushes. Records that do not fit are
+/// dropped and counted.
+const MAX_PENDING: usize = 16 * 1024;
+
+/// Records waiting to be written to the log file. Any core or interrupt
+/// handler appends whole records; the flush thread is the only consumer.
+static PENDING: Mpsc<[Slot<u8>; MAX_PENDING]> = Mpsc::new([Slot::new(0); MAX_PENDING]);
+
+/// Whether records are appended to `PENDING`.
+static SINK: AtomicBool = AtomicBool::new(false);
+
+/// The number of records dropped because `PENDING` was full.
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Set while a record is being written, so that logging from within the
+/// console or the file system cannot recurse.
+static LOGGING: AtomicBool = AtomicBool::new(false);
+
+/// Internal function called by the logging macros.
+#[doc(hidden)]
+pub fn _log(level: Level, args: fmt::Arguments) {
+    if level > max_level() || LOGGING.load(Ordering::Relaxed) {
+        return;
+    }
+    LOGGING.store(true, Ordering::Relaxed);
+    let now = current_time();
+    let (secs, millis) = (now.as_secs(), now.subsec_millis());
+    print_log(format_args!("[{:>5}.{:03}] {:<5} {}\n", secs, millis, level, args));
+    if SINK.load(Ordering::Acquire) {
+        let record = format!("[{:>5}.{:03}] {:<5} {}\n", secs, millis, level, args);
+        if PENDING.push_slice(record.as_bytes()).is_err() {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    LOGGING.store(false, Ordering::Relaxed);
+}
+
+/// Logs a record at `level` to the console and, if enabled, the log file.
+pub macro log($level:expr, $($arg:tt)*) {
+    _log($level, format_args!($($arg)*))
+}
+
+/// Logs a record at `Level::Error`.
+pub macro error($($arg:tt)*) {
+    _log(Level::Error, format_args!($($arg)*))
+}
+
+/// Logs a record at `Level::Warn`.
+pub macro warn($($arg:tt)*) {
+    _log(Level::Warn, format_args!($($arg)*))
+}
+
+/// Logs a record at `Level::Info`.
+pub macro info($($arg:tt)*) {
+    _log(Level::Info, format_args!($($arg)*))
+}
+
+/// Logs a record at `Level::Debug`.
+pub macro debug($($arg:tt)*) {
+    _log(Level::Debug, format_args!($($arg)*))
+}
+
+/// Logs a record at `Level::Trace`.
+pub macro trace($($arg:tt)*) {
+    _log(Level::Trace, format_args!($($arg)*))
+}
+
+/// Starts appending log records to `LOG_DIR/kernel.log`. Records are
+/// buffered in memory and written by a kernel thread every
+/// `FLUSH_INTERVAL`, so logging never blocks on the SD card.
+///
+/// The caller should assure that `FILESYSTEM` and `SCHEDULER` have been
+/// initialized. Returns the ID of the flush thread.
+pub fn enable_file_sink() -> Option<Id> {
+    let thread = match Process::kernel_thread(flush_thread) {
+        Ok(thread) => thread,
+        Err(e) => {
+            kprintln!("logger: cannot start flush thread: {:?}", e);
+            return None;
+        }
+    };
+    SINK.store(true, Ordering::Release);
+    SCHEDULER.add(thread)
+}
+
+extern "C" fn flush_thread() -> ! {
+    loop {
+        if let Err(e) = flush() {
+            kprintln!("logger: cannot write {}/{}: {:?}", LOG_DIR, LOG_NAME, e);
+        }
+        let _ = kernel_api::syscall::sleep(FLUSH_INTERVAL);
+    }
+}
+
+/// Writes the pending records to the log file, rotating it first if they
+/// would grow it past `MAX_SIZE`.
+fn flush() -> io::Result<()> {
+    // Records wait in memory while the file system cannot be written.
+    match FILESYSTEM.options() {
+        Some(options) if !options.read_only => (),
+        _ => return Ok(()),
+    }
+    // Only the flush thread calls `flush()`, so there is one consumer.
+    let mut consumer = unsafe { PENDING.consumer() };
+    let mut pending = Vec::new();
+    while let Some(byte) = consumer.pop() {
+        pending.push(byte);
+    }
+    let dropped = DROPPED.swap(0, Ordering::Relaxed);
+    if dropped > 0 {
+        let note = format!("[logger] {} records dropped\n", dropped);
+        pending.extend_from_slice(note.as_bytes());
+    }
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    let dir = open_log_dir()?;
+    let mut file = open_log_file(&dir)?;
+    if file.size() > 0 && file.size() + pending.len() as u64 > MAX_SIZE {
+        drop(file);
+        rotate(&dir)?;
+        file = open_log_file(&dir)?;
+    }
+    file.seek(SeekFrom::End(0))?;
+    file.write_all(&pending)?;
+    file.flush()
+}
+
+/// Returns the directory `name` in `parent`, creating it if needed.
+fn find_or_create_dir(parent: &Dir<PiVFatHandle>, name: &str) -> io::Result<Dir<PiVFatHandle>> {
+    match parent.find(name) {
+        Ok(Entry::Dir(dir)) => Ok(dir),
+        Ok(Entry::File(_)) => Err(newioerr!(AlreadyExists, "not a directory")),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => parent.create_dir(name),
+        Err(e) => Err(e),
+    }
+}
+
+/// Opens `LOG_DIR`, creating it and its parents if needed.
+fn open_log_dir() -> io::Result<Dir<PiVFatHandle>> {
+    let mut dir = FILESYSTEM.open_dir("/")?;
+    for name in LOG_DIR.split('/').filter(|name| !name.is_empty()) {
+        dir = find_or_create_dir(&dir, name)?;
+    }
+    Ok(dir)
+}
+
+/// Opens `LOG_NAME` in `dir`, creating it if needed.
+fn open_log_file(dir: &Dir<PiVFatHandle>) -> io::Result<File<PiVFatHandle>> {
+    match dir.find(LOG_NAME) {
+        Ok(Entry::File(file)) => Ok(file),
+        Ok(Entry::Dir(_)) => Err(newioerr!(AlreadyExists, "log file is a directory")),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => dir.create_file(LOG_NAME),
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns the name of the `n`th rotated log file.
+fn rotated_name(n: usize) -> String {
+    format!("kernel.{}", n)
+}
+
+/// Ignores `NotFound` errors from `result`.
+fn ignore_missing(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        other => other,
+    }
+}
+
+/// Shifts `kernel.<n>` to `kernel.<n + 1>`, dropping `kernel.<KEEP>`, and
+/// moves `LOG_NAME` to `kernel.1`.
+fn rotate(dir: &Dir<PiVFatHandle>) -> io::Result<()> {
+    ignore_missing(dir.remove(&rotated_name(KEEP)))?;
+    for n in (1..KEEP).rev() {
+        ignore_missing(dir.rename(&rotated_name(n), &rotated_name(n + 1)))?;
+    }
+    dir.rename(LOG_NAME, &rotated_name(1))
+}
@@ -18,16 +18,36 @@ mod init;
 extern crate alloc;
 
 pub mod allocator;
+pub mod audio;
+pub mod cmdline;
+pub mod config;
 pub mod console;
+pub mod coredump;
+pub mod crash;
+pub mod debugger;
+pub mod display;
+pub mod fdisk;
 pub mod fs;
+pub mod ksyms;
+pub mod logger;
 pub mod mutex;
 pub mod shell;
 pub mod param;
 pub mod process;
+pub mod rc;
+pub mod selftest;
+pub mod settings;
+pub mod smp;
+pub mod swap;
+pub mod systimer;
+pub mod telemetry;
 pub mod traps;
+pub mod version;
+pub mod video;
 pub mod vm;
 
 use allocator::Allocator;
+use audio::Audio;
 use fs::FileSystem;
 use process::GlobalScheduler;
 use traps::irq::Irq;
@@ -39,14 +59,65 @@ pub static FILESYSTEM: FileSystem = FileSystem::uninitialized();
 pub static SCHEDULER: GlobalScheduler = GlobalScheduler::uninitialized();
 pub static VMM: VMManager = VMManager::uninitialized();
 pub static IRQ: Irq = Irq::uninitialized();
+pub static AUDIO: Audio = Audio::uninitialized();
 
-fn kmain() -> ! {
+/// Brings up the kernel. `boot_el` is the exception level the firmware
+/// started it at; it always runs at EL1 by now.
+fn kmain(boot_el: u8) -> ! {
     unsafe {
+        console::early::initialize();
+        let el = aarch64::current_el();
+        console::kprintln!("{} {} ({}), built {}",
+                           version::SYSNAME, version::RELEASE, version::GIT_REVISION, version::BUILD_TIME);
+        console::kprintln!("rustos: started at EL{}, running at EL{}", boot_el, el);
         ALLOCATOR.initialize();
         FILESYSTEM.initialize();
-        IRQ.initialize();
+        settings::initialize();
+        config::load();
+        cmdline::apply();
+        console::early::hand_over();
+        crash::initialize();
+        console::enable_buffered_output();
+        if config::feature("audio") {
+            AUDIO.initialize();
+        }
         VMM.initialize();
+        if config::feature("smp") {
+            traps::ipi::initialize();
+            smp::start_cores();
+        }
         SCHEDULER.initialize();
+        rc::start();
+        if selftest::enabled() {
+            selftest::spawn();
+        }
+        if config::feature("log_file") {
+            logger::enable_file_sink();
+        }
+        if config::feature("writeback") {
+            fs::start_writeback();
+        }
+        if config::feature("uart_mux") {
+            console::mux::enable();
+        }
+        if config::feature("core_dumps") {
+            coredump::enable(config::get().core_limit);
+        }
+        if config::feature("swap") {
+            swap::enable();
+        }
+        if config::feature("syscall_profile") {
+            traps::profile::set_enabled(true);
+        }
+        if let Some(display) = config::get().display {
+            display::initialize(&display);
+            if config::feature("display_console") {
+                if let Err(e) = display::start_mirror() {
+                    logger::warn!("display: cannot mirror the console: {:?}", e);
+                }
+            }
+        }
+        video::initialize();
         SCHEDULER.start();
     }
 }
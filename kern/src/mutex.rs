@@ -1,30 +1,71 @@
 use core::fmt;
-use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU16, AtomicU64, AtomicUsize, Ordering};
 use core::cell::UnsafeCell;
 use core::ops::{DerefMut, Deref, Drop};
 
+#[cfg(not(test))]
+use aarch64::atomic;
+
+use crate::param::NCORES;
+
+/// The holder of a lock taken outside any process, such as during boot.
+pub const NO_PROCESS: u64 = u64::max_value();
+
+/// A kernel lock.
+///
+/// Acquiring it is not yet exclusive: every core and process shares one
+/// owner, so `lock()` never waits. It does record which process took it, so
+/// that a process of higher priority that finds it held lends the holder its
+/// priority until the lock is released; see `inherited()`. Each lock counts
+/// how often it was taken and how often it was found held by another
+/// process; see `stats()`.
 #[repr(align(32))]
 pub struct Mutex<T> {
     data: UnsafeCell<T>,
     lock: AtomicBool,
-    owner: AtomicUsize
+    owner: AtomicUsize,
+    /// The process that took the lock while it was free, or `NO_PROCESS`.
+    holder: AtomicU64,
+    /// The highest priority lent to the holder while it holds the lock, or
+    /// 0 if none has been.
+    ceiling: AtomicU16,
+    acquisitions: AtomicUsize,
+    contentions: AtomicUsize,
 }
 
 unsafe impl<T: Send> Send for Mutex<T> { }
 unsafe impl<T: Send> Sync for Mutex<T> { }
 
 pub struct MutexGuard<'a, T: 'a> {
-    lock: &'a Mutex<T>
+    lock: &'a Mutex<T>,
+    /// Whether this guard took the lock while it was free, and so releases
+    /// it when dropped.
+    outer: bool,
 }
 
 impl<'a, T> !Send for MutexGuard<'a, T> { }
 unsafe impl<'a, T: Sync> Sync for MutexGuard<'a, T> { }
 
+/// How a lock has been used, for diagnostics.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LockStats {
+    /// The times the lock was taken.
+    pub acquisitions: usize,
+    /// The times it was taken while another process held it.
+    pub contentions: usize,
+    /// The process holding it, if any.
+    pub holder: Option<u64>,
+}
+
 impl<T> Mutex<T> {
     pub const fn new(val: T) -> Mutex<T> {
         Mutex {
             lock: AtomicBool::new(false),
             owner: AtomicUsize::new(usize::max_value()),
+            holder: AtomicU64::new(NO_PROCESS),
+            ceiling: AtomicU16::new(0),
+            acquisitions: AtomicUsize::new(0),
+            contentions: AtomicUsize::new(0),
             data: UnsafeCell::new(val)
         }
     }
@@ -34,11 +75,28 @@ impl<T> Mutex<T> {
     // Once MMU/cache is enabled, do the right thing here. For now, we don't
     // need any real synchronization.
     pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        let (pid, priority) = running();
+        self.try_lock_as(pid, priority)
+    }
+
+    /// Like `try_lock()`, on behalf of process `pid` running at `priority`.
+    fn try_lock_as(&self, pid: u64, priority: u16) -> Option<MutexGuard<T>> {
         let this = 0;
-        if !self.lock.load(Ordering::Relaxed) || self.owner.load(Ordering::Relaxed) == this {
+        let held = self.lock.load(Ordering::Relaxed);
+        if !held || self.owner.load(Ordering::Relaxed) == this {
             self.lock.store(true, Ordering::Relaxed);
             self.owner.store(this, Ordering::Relaxed);
-            Some(MutexGuard { lock: &self })
+            count(&self.acquisitions);
+            if !held {
+                self.holder.store(pid, Ordering::Relaxed);
+            } else {
+                let holder = self.holder.load(Ordering::Relaxed);
+                if holder != pid {
+                    count(&self.contentions);
+                    self.lend_to(holder, priority);
+                }
+            }
+            Some(MutexGuard { lock: &self, outer: !held })
         } else {
             None
         }
@@ -57,7 +115,56 @@ impl<T> Mutex<T> {
         }
     }
 
+    /// Returns a guard for the lock whether or not it is held, without
+    /// taking it: the guard does not release it when dropped.
+    ///
+    /// # Safety
+    ///
+    /// The holder, if any, must never run again, as when the kernel panics,
+    /// since both would then access the data at once.
+    pub unsafe fn force_lock(&self) -> MutexGuard<T> {
+        MutexGuard { lock: &self, outer: false }
+    }
+
+    /// Returns `true` if the lock is held.
+    pub fn is_locked(&self) -> bool {
+        self.lock.load(Ordering::Relaxed)
+    }
+
+    /// Returns how the lock has been used.
+    pub fn stats(&self) -> LockStats {
+        let holder = if self.lock.load(Ordering::Relaxed) {
+            Some(self.holder.load(Ordering::Relaxed)).filter(|&pid| pid != NO_PROCESS)
+        } else {
+            None
+        };
+        LockStats {
+            acquisitions: self.acquisitions.load(Ordering::Relaxed),
+            contentions: self.contentions.load(Ordering::Relaxed),
+            holder,
+        }
+    }
+
+    /// Lends `priority` to `holder` until the lock is released, unless it
+    /// has already been lent as much.
+    fn lend_to(&self, holder: u64, priority: u16) {
+        if holder == NO_PROCESS || priority <= self.ceiling.load(Ordering::Relaxed) {
+            return;
+        }
+        self.ceiling.store(priority, Ordering::Relaxed);
+        boost(self.key(), holder, priority);
+    }
+
+    /// Returns a value identifying the lock in `BOOSTS`.
+    fn key(&self) -> usize {
+        self as *const Mutex<T> as usize
+    }
+
     fn unlock(&self) {
+        if self.ceiling.swap(0, Ordering::Relaxed) != 0 {
+            unboost(self.key());
+        }
+        self.holder.store(NO_PROCESS, Ordering::Relaxed);
         self.lock.store(false, Ordering::Relaxed);
     }
 }
@@ -78,7 +185,9 @@ impl<'a, T: 'a> DerefMut for MutexGuard<'a, T> {
 
 impl<'a, T: 'a> Drop for MutexGuard<'a, T> {
     fn drop(&mut self) {
-        self.lock.unlock()
+        if self.outer {
+            self.lock.unlock()
+        }
     }
 }
 
@@ -90,3 +199,140 @@ impl<T: fmt::Debug> fmt::Debug for Mutex<T> {
         }
     }
 }
+
+/// Adds one to `counter`. Counted with the kernel's atomics, which host tests
+/// cannot run.
+fn count(counter: &AtomicUsize) {
+    #[cfg(not(test))]
+    atomic::fetch_add(counter, 1);
+    #[cfg(test)]
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Stores `lock` in `slot` if it is 0, and returns whether it did.
+fn claim(slot: &AtomicUsize, lock: usize) -> bool {
+    #[cfg(not(test))]
+    let claimed = atomic::compare_exchange(slot, 0, lock);
+    #[cfg(test)]
+    let claimed = slot.compare_exchange(0, lock, Ordering::AcqRel, Ordering::Relaxed);
+    claimed.is_ok()
+}
+
+/// The process each core runs and the priority it lends, published by the
+/// scheduler on dispatch. Locks are taken on behalf of this process.
+static RUNNING: [AtomicU64; NCORES] = [
+    AtomicU64::new(NO_PROCESS),
+    AtomicU64::new(NO_PROCESS),
+    AtomicU64::new(NO_PROCESS),
+    AtomicU64::new(NO_PROCESS),
+];
+static RUNNING_PRIORITY: [AtomicU16; NCORES] =
+    [AtomicU16::new(0), AtomicU16::new(0), AtomicU16::new(0), AtomicU16::new(0)];
+
+/// Records that core `core` runs process `pid`, which lends `priority` to
+/// the holders of locks it finds held. `pid` is `NO_PROCESS` while the core
+/// runs none.
+pub fn set_running(core: usize, pid: u64, priority: u16) {
+    RUNNING[core].store(pid, Ordering::Relaxed);
+    RUNNING_PRIORITY[core].store(priority, Ordering::Relaxed);
+}
+
+/// Returns the process the calling core runs and its priority.
+fn running() -> (u64, u16) {
+    #[cfg(not(test))]
+    let core = aarch64::affinity();
+    #[cfg(test)]
+    let core = 0;
+    (RUNNING[core].load(Ordering::Relaxed), RUNNING_PRIORITY[core].load(Ordering::Relaxed))
+}
+
+/// A priority lent to the holder of a lock.
+struct Boost {
+    /// The lock's `key()`, or 0 if the slot is free.
+    lock: AtomicUsize,
+    holder: AtomicU64,
+    priority: AtomicU16,
+}
+
+impl Boost {
+    const fn new() -> Boost {
+        Boost { lock: AtomicUsize::new(0), holder: AtomicU64::new(0), priority: AtomicU16::new(0) }
+    }
+}
+
+/// The priorities lent to lock holders. A lock has at most one slot; one
+/// that finds the table full lends nothing.
+static BOOSTS: [Boost; 8] = [
+    Boost::new(),
+    Boost::new(),
+    Boost::new(),
+    Boost::new(),
+    Boost::new(),
+    Boost::new(),
+    Boost::new(),
+    Boost::new(),
+];
+
+/// Lends `priority` to `holder` on behalf of the lock `lock`, replacing what
+/// the lock lent before.
+fn boost(lock: usize, holder: u64, priority: u16) {
+    let slot = BOOSTS
+        .iter()
+        .find(|boost| boost.lock.load(Ordering::Acquire) == lock)
+        .or_else(|| BOOSTS.iter().find(|boost| claim(&boost.lock, lock)));
+    if let Some(boost) = slot {
+        boost.holder.store(holder, Ordering::Relaxed);
+        boost.priority.store(priority, Ordering::Release);
+    }
+}
+
+/// Takes back what the lock `lock` lent.
+fn unboost(lock: usize) {
+    for boost in BOOSTS.iter().filter(|boost| boost.lock.load(Ordering::Acquire) == lock) {
+        boost.priority.store(0, Ordering::Relaxed);
+        boost.lock.store(0, Ordering::Release);
+    }
+}
+
+/// Returns the highest priority lent to process `pid` by the locks it
+/// holds, or 0 if none has been.
+pub fn inherited(pid: u64) -> u16 {
+    BOOSTS
+        .iter()
+        .filter(|boost| boost.lock.load(Ordering::Acquire) != 0)
+        .filter(|boost| boost.holder.load(Ordering::Relaxed) == pid)
+        .map(|boost| boost.priority.load(Ordering::Acquire))
+        .max()
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holder_inherits_priority_until_release() {
+        let lock = Mutex::new(0);
+        let (low, high, higher) = (1001, 1002, 1003);
+
+        let outer = lock.try_lock_as(low, 0x0001).unwrap();
+        assert_eq!(lock.stats(), LockStats { acquisitions: 1, contentions: 0, holder: Some(low) });
+        assert_eq!(inherited(low), 0);
+
+        // Taking it again as the holder is not contention.
+        drop(lock.try_lock_as(low, 0x0001).unwrap());
+        assert_eq!(lock.stats().holder, Some(low));
+
+        let inner = lock.try_lock_as(high, 0x0105).unwrap();
+        assert_eq!(inherited(low), 0x0105);
+        drop(lock.try_lock_as(higher, 0x0200).unwrap());
+        drop(lock.try_lock_as(high, 0x0105).unwrap());
+        assert_eq!(inherited(low), 0x0200);
+        drop(inner);
+        assert_eq!(lock.stats(), LockStats { acquisitions: 5, contentions: 3, holder: Some(low) });
+
+        drop(outer);
+        assert_eq!(inherited(low), 0);
+        assert_eq!(lock.stats().holder, None);
+    }
+}
@@ -23,6 +23,15 @@ pub const USER_MAX_VM_SIZE: usize = 0x4000_0000;
 const_assert_eq!(USER_IMG_BASE.wrapping_add(USER_MAX_VM_SIZE), 0);
 pub const KERN_STACK_BASE: usize = 0x80_000;
 
+/// The affinity mask that lets a process run on every core.
+pub const ALL_CORES: u64 = (1 << NCORES) - 1;
+
+/// The number of file descriptors a process may have open at once.
+pub const MAX_FDS: usize = 32;
+
+/// The number of interval timers a process may have at once.
+pub const MAX_TIMERS: usize = 8;
+
 /// The `tick` time.
 // FIXME: When you're ready, change this to something more reasonable.
 pub const TICK: Duration = Duration::from_millis(10);
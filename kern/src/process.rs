@@ -1,10 +1,24 @@
+mod clock;
+mod debug;
+mod fd;
+mod policy;
 mod process;
+mod resources;
 mod scheduler;
 mod stack;
 mod state;
+mod timer;
+mod wheel;
 
-pub use self::process::{Id, Process};
-pub use self::scheduler::GlobalScheduler;
+pub use self::clock::{Clock, SystemClock};
+pub use self::debug::{Action, DebugState, Event};
+pub use self::fd::{FdTable, OpenFile, Poll, SharedFile};
+pub use self::policy::{Policy, MAX_FIFO_PRIORITY};
+pub use self::process::{ExitFlag, Id, MemUsage, PageKind, Process};
+pub use self::resources::Resources;
+pub use self::scheduler::{CoreStats, GlobalScheduler, Scheduler};
 pub use self::stack::Stack;
 pub use self::state::State;
+pub use self::timer::Timers;
+pub use self::wheel::TimerWheel;
 pub use crate::param::TICK;
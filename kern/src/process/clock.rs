@@ -0,0 +1,21 @@
+use core::time::Duration;
+
+use pi::timer::current_time;
+
+/// A source of the current time for scheduling decisions. The scheduler is
+/// generic over its clock so that its queue logic can be driven by a mock
+/// clock in host tests.
+pub trait Clock {
+    /// Returns the time elapsed since an arbitrary, fixed point.
+    fn now(&self) -> Duration;
+}
+
+/// The system timer.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        current_time()
+    }
+}
@@ -0,0 +1,295 @@
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use aarch64::debug::{MAX_BREAKPOINTS, MAX_WATCHPOINTS};
+use kernel_api::{OsError, OsResult, Stop};
+use kernel_api::{STOP_BREAKPOINT, STOP_BRK, STOP_STEP, STOP_WATCHPOINT, WATCH_LOAD, WATCH_STORE};
+
+use crate::traps::TrapFrame;
+
+/// `SPSR_EL1.SS`: once the exception returns, run one instruction and take
+/// a software step exception.
+const SPSR_SS: u64 = 1 << 21;
+
+/// A debug exception taken from a process.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Event {
+    Breakpoint,
+    Step,
+    /// A watchpoint hit by an access to the address.
+    Watchpoint(u64),
+    Brk,
+}
+
+/// What to do with a process after a debug exception.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Action {
+    /// Return to it.
+    Resume,
+    /// Stop it until its debugger resumes it.
+    Stop,
+    /// It is not being debugged; handle the exception as usual.
+    Ignore,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Watchpoint {
+    /// The doubleword holding the watched bytes.
+    addr: u64,
+    /// The watched bytes of the doubleword, a bit each.
+    bytes: u8,
+    /// `WATCH_*` bits.
+    accesses: u64,
+}
+
+/// A process's breakpoints, watchpoints and stepping, as set by its debugger
+/// through `sys_ptrace_lite`, and whether it is stopped.
+///
+/// The debug registers belong to the core, so `install()` loads them with a
+/// process's state whenever it is dispatched. To resume from a breakpoint or
+/// a watchpoint, which is taken before its instruction runs, the process
+/// steps over the instruction with them off, then runs on.
+#[derive(Debug, Default)]
+pub struct DebugState {
+    /// Whether a debugger has set anything, so `brk` stops the process.
+    pub attached: bool,
+    breakpoints: [Option<u64>; MAX_BREAKPOINTS],
+    watchpoints: [Option<Watchpoint>; MAX_WATCHPOINTS],
+    /// Whether the process stops after its next instruction.
+    stepping: bool,
+    /// Whether the process is stepping over a breakpoint or watchpoint.
+    stepping_over: bool,
+    stop: Option<Stop>,
+    /// Raised while the process is stopped, for its debugger to wait on.
+    stopped: Arc<AtomicBool>,
+}
+
+impl DebugState {
+    pub fn new() -> DebugState {
+        DebugState::default()
+    }
+
+    /// Sets breakpoint `slot` at the instruction at `addr`, or clears it if
+    /// `addr` is 0.
+    ///
+    /// Returns `InvalidArgument` if there is no such slot or `addr` is not
+    /// aligned to an instruction.
+    pub fn set_breakpoint(&mut self, slot: usize, addr: u64) -> OsResult<()> {
+        if addr % 4 != 0 {
+            return Err(OsError::InvalidArgument);
+        }
+        let breakpoint = self.breakpoints.get_mut(slot).ok_or(OsError::InvalidArgument)?;
+        *breakpoint = if addr == 0 { None } else { Some(addr) };
+        self.attached = true;
+        Ok(())
+    }
+
+    /// Sets watchpoint `slot` on the `len` bytes at `addr`, for the `WATCH_*`
+    /// `accesses`, or clears it if `addr` is 0.
+    ///
+    /// Returns `InvalidArgument` if there is no such slot, if there are no
+    /// accesses to watch, or if the bytes are not within a doubleword.
+    pub fn set_watchpoint(&mut self, slot: usize, addr: u64, len: u64, accesses: u64) -> OsResult<()> {
+        let watchpoint = self.watchpoints.get_mut(slot).ok_or(OsError::InvalidArgument)?;
+        if addr == 0 {
+            *watchpoint = None;
+            return Ok(());
+        }
+        let all = WATCH_LOAD | WATCH_STORE;
+        let offset = addr % 8;
+        if accesses == 0 || accesses & !all != 0 || len == 0 || offset + len > 8 {
+            return Err(OsError::InvalidArgument);
+        }
+        let bytes = (((1u16 << len) - 1) << offset) as u8;
+        *watchpoint = Some(Watchpoint { addr: addr - offset, bytes, accesses });
+        self.attached = true;
+        Ok(())
+    }
+
+    /// Returns why the process is stopped, if it is.
+    pub fn stop(&self) -> Option<Stop> {
+        self.stop
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stop.is_some()
+    }
+
+    /// Returns a flag raised while the process is stopped.
+    pub fn watch_stop(&self) -> Arc<AtomicBool> {
+        self.stopped.clone()
+    }
+
+    /// Handles `event`, taken with the process's registers in `tf`. Returns
+    /// `Action::Stop` after recording why the process stopped.
+    pub fn on_event(&mut self, event: Event, tf: &mut TrapFrame) -> Action {
+        let reason = match event {
+            Event::Step if self.stepping_over && !self.stepping => {
+                self.stepping_over = false;
+                tf.spsr &= !SPSR_SS;
+                return Action::Resume;
+            }
+            Event::Step if !self.stepping => {
+                tf.spsr &= !SPSR_SS;
+                return Action::Resume;
+            }
+            Event::Brk if !self.attached => return Action::Ignore,
+            Event::Breakpoint => STOP_BREAKPOINT,
+            Event::Step => STOP_STEP,
+            Event::Watchpoint(_) => STOP_WATCHPOINT,
+            Event::Brk => STOP_BRK,
+        };
+        let addr = match event {
+            Event::Watchpoint(addr) => addr,
+            _ => 0,
+        };
+        self.stepping = false;
+        self.stepping_over = false;
+        tf.spsr &= !SPSR_SS;
+        self.stop = Some(Stop { reason, pc: tf.elr, addr });
+        self.stopped.store(true, Ordering::Release);
+        Action::Stop
+    }
+
+    /// Resumes the stopped process whose registers are in `tf`, for one
+    /// instruction if `step`. The process goes on after a `brk`.
+    ///
+    /// Returns `InvalidArgument` if the process is not stopped.
+    pub fn resume(&mut self, step: bool, tf: &mut TrapFrame) -> OsResult<()> {
+        let stop = self.stop.take().ok_or(OsError::InvalidArgument)?;
+        self.stopped.store(false, Ordering::Release);
+        if stop.reason == STOP_BRK {
+            tf.elr += 4;
+        }
+        self.stepping = step;
+        self.stepping_over = stop.reason == STOP_BREAKPOINT || stop.reason == STOP_WATCHPOINT;
+        if self.stepping || self.stepping_over {
+            tf.spsr |= SPSR_SS;
+        }
+        Ok(())
+    }
+
+    /// Clears the breakpoints and watchpoints and resumes the process, whose
+    /// registers are in `tf`, if it is stopped.
+    pub fn detach(&mut self, tf: &mut TrapFrame) {
+        self.breakpoints = [None; MAX_BREAKPOINTS];
+        self.watchpoints = [None; MAX_WATCHPOINTS];
+        self.attached = false;
+        if self.is_stopped() {
+            let _ = self.resume(false, tf);
+        }
+    }
+
+    /// Loads the calling core's debug registers with the process's state.
+    /// Does nothing for a process that was never debugged when the core's
+    /// registers are clear.
+    pub fn install(&self) {
+        #[cfg(not(test))]
+        unsafe {
+            use aarch64::debug::*;
+            use crate::param::NCORES;
+
+            /// Whether each core's debug registers may hold a process's state.
+            static LOADED: [AtomicBool; NCORES] = [
+                AtomicBool::new(false),
+                AtomicBool::new(false),
+                AtomicBool::new(false),
+                AtomicBool::new(false),
+            ];
+
+            let loaded = &LOADED[aarch64::affinity()];
+            let idle = !self.attached && !self.stepping && !self.stepping_over;
+            if idle && !loaded.load(Ordering::Relaxed) {
+                return;
+            }
+            let armed = !self.stepping_over;
+            for n in 0..breakpoints() {
+                match self.breakpoints[n] {
+                    Some(addr) if armed => set_breakpoint(n, addr, BREAKPOINT_EL0),
+                    _ => set_breakpoint(n, 0, 0),
+                }
+            }
+            for n in 0..watchpoints() {
+                match self.watchpoints[n] {
+                    Some(w) if armed => {
+                        let control = ((w.bytes as u64) << 5) | (w.accesses << 3) | (0b10 << 1) | 1;
+                        set_watchpoint(n, w.addr, control)
+                    }
+                    _ => set_watchpoint(n, 0, 0),
+                }
+            }
+            let mut mdscr = 0;
+            if self.attached {
+                mdscr |= MDSCR_EL1::MDE;
+            }
+            if self.stepping || self.stepping_over {
+                mdscr |= MDSCR_EL1::SS;
+            }
+            MDSCR_EL1.set(mdscr);
+            aarch64::isb();
+            loaded.store(mdscr != 0, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_breakpoints_and_watchpoints() {
+        let mut debug = DebugState::new();
+        assert_eq!(debug.set_breakpoint(0, 0x1_0002), Err(OsError::InvalidArgument));
+        assert_eq!(debug.set_breakpoint(MAX_BREAKPOINTS, 0x1_0000), Err(OsError::InvalidArgument));
+        assert!(!debug.attached);
+        assert_eq!(debug.set_breakpoint(0, 0x1_0000), Ok(()));
+        assert!(debug.attached);
+
+        assert_eq!(debug.set_watchpoint(0, 0x2_0006, 4, WATCH_STORE), Err(OsError::InvalidArgument));
+        assert_eq!(debug.set_watchpoint(0, 0x2_0004, 4, 0), Err(OsError::InvalidArgument));
+        assert_eq!(debug.set_watchpoint(0, 0x2_0004, 4, WATCH_STORE), Ok(()));
+        let expected = Watchpoint { addr: 0x2_0000, bytes: 0xf0, accesses: WATCH_STORE };
+        assert_eq!(debug.watchpoints[0], Some(expected));
+        assert_eq!(debug.set_watchpoint(0, 0, 0, 0), Ok(()));
+        assert_eq!(debug.watchpoints[0], None);
+    }
+
+    #[test]
+    fn steps_over_breakpoints_to_resume() {
+        let mut debug = DebugState::new();
+        let mut tf = TrapFrame::default();
+        debug.set_breakpoint(0, 0x1_0000).unwrap();
+        tf.elr = 0x1_0000;
+        assert_eq!(debug.on_event(Event::Breakpoint, &mut tf), Action::Stop);
+        assert!(debug.watch_stop().load(Ordering::Relaxed));
+        assert_eq!(debug.stop().map(|stop| (stop.reason, stop.pc)), Some((STOP_BREAKPOINT, 0x1_0000)));
+
+        // Continuing steps over the breakpoint without stopping.
+        debug.resume(false, &mut tf).unwrap();
+        assert!(!debug.is_stopped() && tf.spsr & SPSR_SS != 0);
+        tf.elr += 4;
+        assert_eq!(debug.on_event(Event::Step, &mut tf), Action::Resume);
+        assert_eq!(tf.spsr & SPSR_SS, 0);
+
+        // Stepping stops after one instruction.
+        assert_eq!(debug.on_event(Event::Breakpoint, &mut tf), Action::Stop);
+        debug.resume(true, &mut tf).unwrap();
+        assert_eq!(debug.on_event(Event::Step, &mut tf), Action::Stop);
+        assert_eq!(debug.stop().map(|stop| stop.reason), Some(STOP_STEP));
+        debug.resume(false, &mut tf).unwrap();
+        assert_eq!(debug.resume(false, &mut tf), Err(OsError::InvalidArgument));
+    }
+
+    #[test]
+    fn brk_stops_only_debugged_processes() {
+        let mut debug = DebugState::new();
+        let mut tf = TrapFrame::default();
+        tf.elr = 0x1_0000;
+        assert_eq!(debug.on_event(Event::Brk, &mut tf), Action::Ignore);
+        debug.set_breakpoint(1, 0x1_0100).unwrap();
+        assert_eq!(debug.on_event(Event::Brk, &mut tf), Action::Stop);
+        debug.detach(&mut tf);
+        assert!(!debug.is_stopped() && !debug.attached);
+        assert_eq!(tf.elr, 0x1_0004);
+    }
+}
@@ -0,0 +1,296 @@
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::fmt;
+
+use shim::io::{self, Read, Seek, SeekFrom, Write};
+
+use fat32::vfat::File;
+use kernel_api::{OsError, OsResult};
+
+use crate::fs::PiVFatHandle;
+use crate::mutex::{Mutex, MutexGuard};
+use crate::param::MAX_FDS;
+
+/// A file opened by a process, with the position of its next read or write.
+///
+/// Descriptors duplicated from one another, by `dup` or by copying a
+/// process's `FdTable`, share one `OpenFile` and so move the same position.
+/// Opening a file again creates a new `OpenFile` with a position of its own.
+#[derive(Debug)]
+pub struct OpenFile {
+    file: File<PiVFatHandle>,
+    position: u64,
+}
+
+impl OpenFile {
+    pub fn new(file: File<PiVFatHandle>) -> OpenFile {
+        OpenFile { file, position: 0 }
+    }
+
+    /// Returns the position of the next read or write.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.seek(SeekFrom::Start(self.position))?;
+        let n = self.file.read(buf)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+
+    pub fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.seek(SeekFrom::Start(self.position))?;
+        let n = self.file.write(buf)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+
+    /// Moves the position as `io::Seek` does and returns the new position.
+    pub fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(SeekFrom::Start(self.position))?;
+        self.position = self.file.seek(pos)?;
+        Ok(self.position)
+    }
+}
+
+/// A reference-counted `OpenFile`; the file is closed when the last
+/// descriptor referring to it is.
+#[derive(Clone)]
+pub struct SharedFile(Rc<Mutex<OpenFile>>);
+
+// As with `PiVFatHandle`, these impls are unsound but harmless while only one
+// core runs.
+unsafe impl Send for SharedFile {}
+unsafe impl Sync for SharedFile {}
+
+impl SharedFile {
+    pub fn new(file: File<PiVFatHandle>) -> SharedFile {
+        SharedFile(Rc::new(Mutex::new(OpenFile::new(file))))
+    }
+
+    pub fn lock(&self) -> MutexGuard<OpenFile> {
+        self.0.lock()
+    }
+}
+
+/// Reports whether a descriptor's file can be read or written without
+/// blocking, for `sys_poll`.
+pub trait Poll {
+    /// Returns whether a read and whether a write would complete now.
+    fn poll(&self) -> (bool, bool);
+}
+
+impl Poll for SharedFile {
+    /// A file on disk is always ready.
+    fn poll(&self) -> (bool, bool) {
+        (true, true)
+    }
+}
+
+impl fmt::Debug for SharedFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SharedFile({} refs)", Rc::strong_count(&self.0))
+    }
+}
+
+/// A process's file descriptors: small integers naming `F`s, which are
+/// `SharedFile`s except in tests.
+///
+/// Cloning a table, as `fork` does, gives the copy descriptors that share
+/// each open file, and its position, with the original.
+#[derive(Debug, Clone)]
+pub struct FdTable<F: Clone = SharedFile> {
+    files: Vec<Option<F>>,
+}
+
+impl<F: Clone> FdTable<F> {
+    /// Returns a table without descriptors.
+    pub fn new() -> FdTable<F> {
+        FdTable { files: Vec::new() }
+    }
+
+    /// Installs `file` at the lowest free descriptor and returns it.
+    ///
+    /// Returns `TooManyFiles` if all `MAX_FDS` descriptors are in use.
+    pub fn insert(&mut self, file: F) -> OsResult<usize> {
+        match self.files.iter().position(Option::is_none) {
+            Some(fd) => {
+                self.files[fd] = Some(file);
+                Ok(fd)
+            }
+            None if self.files.len() < MAX_FDS => {
+                self.files.push(Some(file));
+                Ok(self.files.len() - 1)
+            }
+            None => Err(OsError::TooManyFiles),
+        }
+    }
+
+    /// Returns the file that `fd` names, or `BadDescriptor` if it names none.
+    pub fn get(&self, fd: usize) -> OsResult<&F> {
+        match self.files.get(fd) {
+            Some(Some(file)) => Ok(file),
+            _ => Err(OsError::BadDescriptor),
+        }
+    }
+
+    /// Frees `fd` and returns the file it named.
+    pub fn remove(&mut self, fd: usize) -> OsResult<F> {
+        match self.files.get_mut(fd).and_then(Option::take) {
+            Some(file) => Ok(file),
+            None => Err(OsError::BadDescriptor),
+        }
+    }
+
+    /// Installs the file that `fd` names at the lowest free descriptor, as
+    /// well, and returns the new descriptor.
+    pub fn dup(&mut self, fd: usize) -> OsResult<usize> {
+        let file = self.get(fd)?.clone();
+        self.insert(file)
+    }
+
+    /// Makes `new_fd` name the file that `old_fd` names, closing whatever
+    /// `new_fd` named before, and returns `new_fd`. Nothing changes if the
+    /// two are equal.
+    pub fn dup2(&mut self, old_fd: usize, new_fd: usize) -> OsResult<usize> {
+        let file = self.get(old_fd)?.clone();
+        if new_fd >= MAX_FDS {
+            return Err(OsError::BadDescriptor);
+        }
+        if new_fd >= self.files.len() {
+            self.files.resize(new_fd + 1, None);
+        }
+        self.files[new_fd] = Some(file);
+        Ok(new_fd)
+    }
+}
+
+impl<F: Clone + Poll> FdTable<F> {
+    /// Returns which of the descriptors whose bits are set in `read` can be
+    /// read without blocking, and which of those in `write` can be written,
+    /// as masks of the same form.
+    ///
+    /// Returns `BadDescriptor` if a bit names a descriptor that is not open.
+    pub fn poll(&self, read: u64, write: u64) -> OsResult<(u64, u64)> {
+        if (read | write).checked_shr(MAX_FDS as u32).unwrap_or(0) != 0 {
+            return Err(OsError::BadDescriptor);
+        }
+        let (mut readable, mut writable) = (0, 0);
+        for fd in 0..MAX_FDS {
+            let bit = 1u64 << fd;
+            if (read | write) & bit == 0 {
+                continue;
+            }
+            let (r, w) = self.get(fd)?.poll();
+            if r && read & bit != 0 {
+                readable |= bit;
+            }
+            if w && write & bit != 0 {
+                writable |= bit;
+            }
+        }
+        Ok((readable, writable))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::rc::Rc;
+    use core::cell::Cell;
+
+    use super::*;
+
+    /// Stands in for a `SharedFile`: a shared position.
+    type Position = Rc<Cell<u64>>;
+
+    #[test]
+    fn allocates_lowest_free_descriptor() {
+        let mut table = FdTable::<Position>::new();
+        assert_eq!(table.insert(Position::default()), Ok(0));
+        assert_eq!(table.insert(Position::default()), Ok(1));
+        assert_eq!(table.insert(Position::default()), Ok(2));
+        assert!(table.remove(1).is_ok());
+        assert_eq!(table.remove(1).err(), Some(OsError::BadDescriptor));
+        assert_eq!(table.insert(Position::default()), Ok(1));
+        assert_eq!(table.get(3).err(), Some(OsError::BadDescriptor));
+    }
+
+    #[test]
+    fn limits_descriptors() {
+        let mut table = FdTable::<Position>::new();
+        for fd in 0..MAX_FDS {
+            assert_eq!(table.insert(Position::default()), Ok(fd));
+        }
+        assert_eq!(table.insert(Position::default()), Err(OsError::TooManyFiles));
+        assert_eq!(table.dup(0), Err(OsError::TooManyFiles));
+        assert_eq!(table.dup2(0, MAX_FDS), Err(OsError::BadDescriptor));
+    }
+
+    #[test]
+    fn dup_shares_position() {
+        let mut table = FdTable::<Position>::new();
+        let fd = table.insert(Position::default()).unwrap();
+        let copy = table.dup(fd).unwrap();
+        assert_eq!(copy, 1);
+        table.get(fd).unwrap().set(10);
+        assert_eq!(table.get(copy).unwrap().get(), 10);
+
+        // Closing one descriptor leaves the other open.
+        table.remove(fd).unwrap();
+        assert_eq!(table.get(copy).unwrap().get(), 10);
+        assert_eq!(table.dup(fd).err(), Some(OsError::BadDescriptor));
+    }
+
+    #[test]
+    fn dup2_replaces_target() {
+        let mut table = FdTable::<Position>::new();
+        let a = table.insert(Rc::new(Cell::new(1))).unwrap();
+        let b = table.insert(Rc::new(Cell::new(2))).unwrap();
+        assert_eq!(table.dup2(a, b), Ok(b));
+        assert_eq!(table.get(b).unwrap().get(), 1);
+        assert_eq!(table.dup2(a, a), Ok(a));
+        assert_eq!(table.get(a).unwrap().get(), 1);
+
+        // Descriptors past the end of the table are created.
+        assert_eq!(table.dup2(a, 7), Ok(7));
+        assert_eq!(table.get(5).err(), Some(OsError::BadDescriptor));
+        assert_eq!(table.insert(Position::default()), Ok(2));
+        assert_eq!(table.dup2(4, 0).err(), Some(OsError::BadDescriptor));
+    }
+
+    /// Stands in for a file that is ready to be read and written or not.
+    #[derive(Clone)]
+    struct Ready(bool, bool);
+
+    impl Poll for Ready {
+        fn poll(&self) -> (bool, bool) {
+            (self.0, self.1)
+        }
+    }
+
+    #[test]
+    fn poll_reports_ready_descriptors() {
+        let mut table = FdTable::<Ready>::new();
+        table.insert(Ready(true, false)).unwrap();
+        table.insert(Ready(false, true)).unwrap();
+        table.insert(Ready(true, true)).unwrap();
+        assert_eq!(table.poll(0b111, 0b111), Ok((0b101, 0b110)));
+        assert_eq!(table.poll(0b010, 0b001), Ok((0, 0)));
+        assert_eq!(table.poll(0b1000, 0), Err(OsError::BadDescriptor));
+        assert_eq!(table.poll(0, 1 << MAX_FDS), Err(OsError::BadDescriptor));
+    }
+
+    #[test]
+    fn clone_shares_positions() {
+        let mut parent = FdTable::<Position>::new();
+        let fd = parent.insert(Position::default()).unwrap();
+        let mut child = parent.clone();
+        child.get(fd).unwrap().set(42);
+        assert_eq!(parent.get(fd).unwrap().get(), 42);
+
+        // The tables themselves are independent.
+        child.remove(fd).unwrap();
+        assert!(parent.get(fd).is_ok());
+    }
+}
@@ -0,0 +1,70 @@
+use core::cmp::Reverse;
+use core::time::Duration;
+
+use kernel_api::{OsError, OsResult, SCHED_DEADLINE, SCHED_FIFO, SCHED_NORMAL};
+
+/// The highest priority of a `Fifo` process.
+pub const MAX_FIFO_PRIORITY: u8 = 99;
+
+/// How a process is scheduled. Ready processes are dispatched by class:
+/// `Deadline` processes first, earliest deadline first, then `Fifo`
+/// processes by priority, then `Normal` processes in round-robin order.
+///
+/// A process holding a kernel `Mutex` that a process of a higher class or
+/// priority found held is dispatched as that process until it releases the
+/// lock; see `rank_with()`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// Time-sliced with the other normal processes.
+    Normal,
+    /// Runs until it blocks or a process of a higher class or priority is
+    /// ready; the timer tick does not move it behind processes of its own
+    /// priority. Priorities go from 1 to `MAX_FIFO_PRIORITY`.
+    Fifo(u8),
+    /// Each time the process wakes, it is given a deadline this far in the
+    /// future, and the ready process with the earliest deadline runs first.
+    Deadline(Duration),
+}
+
+impl Policy {
+    /// Decodes the `policy` and `param` arguments of `sys_setscheduler`:
+    /// `SCHED_NORMAL` ignores `param`, `SCHED_FIFO` takes a priority and
+    /// `SCHED_DEADLINE` a relative deadline in milliseconds.
+    pub fn from_raw(policy: u64, param: u64) -> OsResult<Policy> {
+        match policy {
+            SCHED_NORMAL => Ok(Policy::Normal),
+            SCHED_FIFO if param >= 1 && param <= MAX_FIFO_PRIORITY as u64 => {
+                Ok(Policy::Fifo(param as u8))
+            }
+            SCHED_DEADLINE if param > 0 => Ok(Policy::Deadline(Duration::from_millis(param))),
+            _ => Err(OsError::InvalidArgument),
+        }
+    }
+
+    /// Returns a key that orders ready processes for dispatch: the process
+    /// with the greatest key runs. `deadline` is the process's current
+    /// absolute deadline.
+    pub fn rank(&self, deadline: Duration) -> (u8, u8, Reverse<Duration>) {
+        match *self {
+            Policy::Normal => (0, 0, Reverse(Duration::from_secs(0))),
+            Policy::Fifo(priority) => (1, priority, Reverse(Duration::from_secs(0))),
+            Policy::Deadline(_) => (2, 0, Reverse(deadline)),
+        }
+    }
+
+    /// Returns the class and priority of the policy packed so that a greater
+    /// value ranks higher, ignoring deadlines. A process lends this to the
+    /// holder of a lock it finds held.
+    pub fn priority(&self) -> u16 {
+        let (class, priority, _) = self.rank(Duration::from_secs(0));
+        (class as u16) << 8 | priority as u16
+    }
+
+    /// Like `rank()`, for a process that has been lent `inherited`, a
+    /// `priority()` of another process, by the locks it holds. It ranks as
+    /// the higher of its own policy and the lent class and priority.
+    pub fn rank_with(&self, deadline: Duration, inherited: u16) -> (u8, u8, Reverse<Duration>) {
+        let lent = ((inherited >> 8) as u8, inherited as u8, Reverse(deadline));
+        self.rank(deadline).max(lent)
+    }
+}
@@ -1,18 +1,80 @@
 use alloc::boxed::Box;
+use alloc::collections::vec_deque::VecDeque;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
 use shim::path::Path;
 
 use crate::FILESYSTEM;
-use shim::io::Read;
+use shim::io::{self, Read};
 use fat32::traits::{File, FileSystem};
 use crate::param::*;
-use crate::process::{Stack, State};
+use crate::process::{DebugState, FdTable, Policy, Resources, Stack, State, Timers};
+use crate::swap;
 use crate::traps::TrapFrame;
 use crate::vm::*;
-use kernel_api::{OsError, OsResult};
+use kernel_api::{OsError, OsResult, CAP_ALL};
 
 /// Type alias for the type of a process ID.
 pub type Id = u64;
 
+/// What a page mapped in a process's address space holds.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PageKind {
+    Stack,
+    Image,
+    Heap,
+}
+
+/// The pages mapped in a process's address space, by what they hold.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemUsage {
+    pub stack: usize,
+    pub image: usize,
+    pub heap: usize,
+}
+
+impl MemUsage {
+    /// Returns the number of pages mapped.
+    pub fn total(&self) -> usize {
+        self.stack + self.image + self.heap
+    }
+
+    fn count(&mut self, kind: PageKind) {
+        match kind {
+            PageKind::Stack => self.stack += 1,
+            PageKind::Image => self.image += 1,
+            PageKind::Heap => self.heap += 1,
+        }
+    }
+
+    fn uncount(&mut self, kind: PageKind) {
+        match kind {
+            PageKind::Stack => self.stack -= 1,
+            PageKind::Image => self.image -= 1,
+            PageKind::Heap => self.heap -= 1,
+        }
+    }
+}
+
+/// Raised when the process holding it is dropped, however it dies, so that
+/// threads joining it can tell it has exited.
+#[derive(Debug, Default)]
+pub struct ExitFlag(Arc<AtomicBool>);
+
+impl ExitFlag {
+    /// Returns a flag that reads `true` once the process has exited.
+    pub fn watch(&self) -> Arc<AtomicBool> {
+        self.0.clone()
+    }
+}
+
+impl Drop for ExitFlag {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
 /// A structure that represents the complete state of a process.
 #[derive(Debug)]
 pub struct Process {
@@ -24,6 +86,63 @@ pub struct Process {
     pub vmap: Box<UserPageTable>,
     /// The scheduling state of the process.
     pub state: State,
+    /// The ID of the process group this process belongs to.
+    pub group: Id,
+    /// The total CPU time consumed by the process, excluding the current time
+    /// slice if it is running.
+    pub cpu_time: Duration,
+    /// The CPU time after which the process is killed, if any.
+    pub cpu_limit: Option<Duration>,
+    /// The time at which the process's current time slice began.
+    pub slice_start: Duration,
+    /// The process's open file descriptors.
+    pub files: FdTable,
+    /// The start of the heap, just past the loaded image.
+    pub heap_start: VirtualAddr,
+    /// The end of the heap, moved by `sbrk`. Heap pages are mapped when they
+    /// are first touched.
+    pub heap_end: VirtualAddr,
+    /// The pages mapped in the process's address space. Pages swapped out
+    /// are not counted.
+    pub usage: MemUsage,
+    /// The mapped pages that may be swapped out, least recently used first.
+    /// Only kept while swapping is enabled.
+    pub lru: VecDeque<VirtualAddr>,
+    /// The most pages that have been mapped at once.
+    pub peak_pages: usize,
+    /// The most pages the process may have mapped, if limited. `sbrk` fails
+    /// with `NoMemory` rather than grow the heap past it.
+    pub mem_limit: Option<usize>,
+    /// Whether the process's syscalls are logged.
+    pub traced: bool,
+    /// The cores the process may run on: bit `n` is set if it may run on
+    /// core `n`.
+    pub affinity: u64,
+    /// The times an idle core took the process from another core's run
+    /// queue.
+    pub migrations: u64,
+    /// How the process is scheduled.
+    pub policy: Policy,
+    /// The absolute deadline of a `Policy::Deadline` process.
+    pub deadline: Duration,
+    /// The process's interval timers.
+    pub timers: Timers,
+    /// The capabilities the process holds, `CAP_*` bits.
+    pub caps: u64,
+    /// Set once the time passed to `Scheduler::sleep_until()` has come.
+    pub alarm: bool,
+    /// For a thread started by `sys_clone`, the process whose address space
+    /// and file descriptors it shares; its own `vmap`, `files` and memory
+    /// accounting are unused. `None` for a process.
+    pub leader: Option<Id>,
+    /// The process's breakpoints and watchpoints, and whether it is stopped
+    /// by its debugger.
+    pub debug: DebugState,
+    /// State attached by other subsystems, released when the process is
+    /// dropped, as a killed or exited process is.
+    pub resources: Resources,
+    /// Raised when the process exits.
+    pub exit: ExitFlag,
 }
 
 impl Process {
@@ -39,6 +158,29 @@ impl Process {
                 stack: stacc,
                 vmap: Box::new(UserPageTable::new()),
                 state: State::Ready,
+                group: 0,
+                cpu_time: Duration::from_secs(0),
+                cpu_limit: None,
+                slice_start: Duration::from_secs(0),
+                files: FdTable::new(),
+                heap_start: VirtualAddr::from(USER_IMG_BASE),
+                heap_end: VirtualAddr::from(USER_IMG_BASE),
+                usage: MemUsage::default(),
+                lru: VecDeque::new(),
+                peak_pages: 0,
+                mem_limit: None,
+                traced: false,
+                affinity: ALL_CORES,
+                migrations: 0,
+                policy: Policy::Normal,
+                deadline: Duration::from_secs(0),
+                timers: Timers::new(),
+                caps: 0,
+                alarm: false,
+                leader: None,
+                debug: DebugState::new(),
+                resources: Resources::new(),
+                exit: ExitFlag::default(),
             })
         } else {
             Err(OsError::NoMemory)
@@ -55,10 +197,19 @@ impl Process {
     ///
     /// Returns Os Error if do_load fails.
     pub fn load<P: AsRef<Path>>(pn: P) -> OsResult<Process> {
+        Process::load_with_args(pn, &[])
+    }
+
+    /// Like `load()`, and passes `args` to the program as C's `main()`
+    /// receives them: `x0` holds their number and `x1` the address of an
+    /// array of pointers to them, NUL-terminated, followed by a null
+    /// pointer. They are copied to the top of the stack.
+    ///
+    /// Returns `NoVmSpace` if the arguments do not fit in the stack page.
+    pub fn load_with_args<P: AsRef<Path>>(pn: P, args: &[&str]) -> OsResult<Process> {
         use crate::VMM;
 
-        let mut p = Process::do_load(pn)?;
-        p.context.sp = Process::get_stack_top().as_u64();
+        let mut p = Process::do_load(pn, args)?;
         p.context.spsr = (1 << 6) | (1 << 8) | (1 << 9);
         p.context.elr = Process::get_image_base().as_u64();
         p.context.ttbr0 = VMM.get_baddr().as_u64();
@@ -66,24 +217,284 @@ impl Process {
         Ok(p)
     }
 
+    /// Creates a process that runs `entry` at EL1 on its own stack, sharing
+    /// the kernel's address space. Kernel threads run with IRQs masked, so
+    /// they are never preempted while holding a lock and give up the CPU only
+    /// through system calls such as `sleep`.
+    pub fn kernel_thread(entry: extern "C" fn() -> !) -> OsResult<Process> {
+        use crate::VMM;
+
+        let mut p = Process::new()?;
+        p.stack.install_guard();
+        p.context.sp = p.stack.top().as_u64();
+        // EL1t: the thread runs on SP_EL0 and leaves SP_EL1 to exceptions.
+        p.context.spsr = 0b0100 | (1 << 6) | (1 << 7) | (1 << 8) | (1 << 9);
+        p.context.elr = entry as u64;
+        p.context.ttbr0 = VMM.get_baddr().as_u64();
+        p.context.ttbr1 = p.vmap.get_baddr().as_u64();
+        p.caps = CAP_ALL;
+        Ok(p)
+    }
+
+    /// Creates a thread of `leader` that starts at `entry` with `arg` in `x0`
+    /// and its stack pointer at `stack`. The thread runs in the leader's
+    /// address space with the leader's capabilities, affinity and policy.
+    pub fn thread(
+        leader: &Process,
+        entry: VirtualAddr,
+        stack: VirtualAddr,
+        arg: u64,
+    ) -> OsResult<Process> {
+        let mut p = Process::new()?;
+        p.context.sp = stack.as_u64();
+        p.context.spsr = (1 << 6) | (1 << 8) | (1 << 9);
+        p.context.elr = entry.as_u64();
+        p.context.x_registers[0] = arg;
+        p.context.ttbr0 = leader.context.ttbr0;
+        p.context.ttbr1 = leader.vmap.get_baddr().as_u64();
+        p.leader = Some(leader.context.tpidr);
+        p.caps = leader.caps;
+        p.affinity = leader.affinity;
+        p.policy = leader.policy;
+        p.traced = leader.traced;
+        Ok(p)
+    }
+
+    /// Returns the ID of the process whose address space this process runs
+    /// in: its leader if it is a thread, and its own ID otherwise.
+    pub fn owner(&self) -> Id {
+        self.leader.unwrap_or(self.context.tpidr)
+    }
+
     /// Creates a process and open a file with given path.
     /// Allocates one page for stack with read/write permission, and N pages with read/write/execute
     /// permission to load file's contents.
-    fn do_load<P: AsRef<Path>>(pn: P) -> OsResult<Process> {
-        let mut p = Process::new()?;
-        let _stack = p.vmap.alloc(Process::get_stack_base(), PagePerm::RW);
+    ///
+    /// Returns `NoVmSpace` if the image does not fit below the stack, and
+    /// `IoErrorEof` if the file ends before its recorded size.
+    fn do_load<P: AsRef<Path>>(pn: P, args: &[&str]) -> OsResult<Process> {
         let mut program = FILESYSTEM.open_file(pn)?;
+        let size = program.size();
+        let budget = Process::get_stack_base().as_usize() - Process::get_image_base().as_usize();
+        if size > budget as u64 {
+            return Err(OsError::NoVmSpace);
+        }
+
+        let mut p = Process::new()?;
+        let stack = p.map_page(Process::get_stack_base(), PagePerm::RW, PageKind::Stack)?;
+        let base = Process::get_stack_base().as_u64();
+        let argv = push_args(stack, base, Process::get_stack_top().as_u64(), args)?;
+        p.context.sp = argv;
+        p.context.x_registers[0] = args.len() as u64;
+        p.context.x_registers[1] = argv;
         let mut code_allocated = 0;
         let mut code_page_addr = Process::get_image_base();
-        while code_allocated < program.size() {
-            let code_page = p.vmap.alloc(code_page_addr, PagePerm::RWX);
-            program.read(code_page)?;
+        // Keep the SD card busy with the next page while the current one is
+        // allocated and copied out of the sector cache.
+        program.prefetch(PAGE_SIZE)?;
+        while code_allocated < size {
+            let code_page = p.map_page(code_page_addr, PagePerm::RWX, PageKind::Image)?;
+            program.prefetch(2 * PAGE_SIZE)?;
+            let wanted = core::cmp::min(size - code_allocated, PAGE_SIZE as u64) as usize;
+            let read = fill_page(&mut program, code_page)?;
+            if read < wanted {
+                return Err(OsError::IoErrorEof);
+            }
+            unsafe { aarch64::sync_icache_range(code_page.as_ptr() as usize, PAGE_SIZE) };
             code_allocated += PAGE_SIZE as u64;
             code_page_addr += VirtualAddr::from(PAGE_SIZE);
         }
+        p.heap_start = code_page_addr;
+        p.heap_end = code_page_addr;
         Ok(p)
     }
 
+    /// Maps a new page at `va` and returns it, counting it in `usage` as
+    /// holding `kind`.
+    ///
+    /// Returns the error of `UserPageTable::alloc()` if the page cannot be
+    /// mapped, in which case nothing is counted.
+    pub fn map_page(
+        &mut self,
+        va: VirtualAddr,
+        perm: PagePerm,
+        kind: PageKind,
+    ) -> OsResult<&mut [u8]> {
+        let page = self.vmap.alloc(va, perm)?;
+        // The entry must reach the table walker before the page is used.
+        aarch64::dsb_ishst();
+        aarch64::isb();
+        self.usage.count(kind);
+        self.peak_pages = self.peak_pages.max(self.usage.total());
+        if swap::enabled() {
+            self.lru.push_back(va);
+        }
+        Ok(page)
+    }
+
+    /// Moves the end of the heap by `increment` bytes and returns the old
+    /// end. Shrinking the heap does not unmap its pages; growing it again
+    /// reuses them.
+    ///
+    /// Returns `NoVmSpace` if the heap would reach the stack or shrink below
+    /// its start, and `NoMemory` if touching all of it would map more pages
+    /// than `mem_limit` allows.
+    pub fn sbrk(&mut self, increment: i64) -> OsResult<VirtualAddr> {
+        let old_end = self.heap_end.as_usize();
+        let new_end = if increment >= 0 {
+            old_end.checked_add(increment as usize)
+        } else {
+            old_end.checked_sub(increment.wrapping_neg() as usize)
+        };
+        match new_end {
+            Some(end) if end >= self.heap_start.as_usize() && end <= USER_STACK_BASE => {
+                if end > old_end && !self.heap_fits(end) {
+                    return Err(OsError::NoMemory);
+                }
+                self.heap_end = VirtualAddr::from(end);
+                Ok(VirtualAddr::from(old_end))
+            }
+            _ => Err(OsError::NoVmSpace),
+        }
+    }
+
+    /// Returns what the page at `va` holds.
+    fn page_kind(&self, va: VirtualAddr) -> PageKind {
+        if va.as_usize() >= USER_STACK_BASE {
+            PageKind::Stack
+        } else if va.as_usize() >= self.heap_start.as_usize() {
+            PageKind::Heap
+        } else {
+            PageKind::Image
+        }
+    }
+
+    /// Puts every page mapped in the process's address space on `lru`, for
+    /// a process loaded before swapping was enabled.
+    pub fn track_pages(&mut self) {
+        self.lru.clear();
+        for region in self.vmap.regions(Process::get_image_base()) {
+            let mut va = region.start;
+            while va.as_usize() < region.end.as_usize() {
+                self.lru.push_back(va);
+                va += VirtualAddr::from(PAGE_SIZE);
+            }
+        }
+    }
+
+    /// Takes the least recently used page off `lru` and returns it. A page
+    /// accessed since it was last looked at gets a second chance instead:
+    /// its access flag is cleared and it goes to the back of the list.
+    /// Returns `None` if the process has no pages to give up.
+    ///
+    /// The caller should flush the TLB before relying on cleared access
+    /// flags.
+    pub fn next_victim(&mut self) -> Option<VirtualAddr> {
+        let mut chances = self.lru.len();
+        while let Some(va) = self.lru.pop_front() {
+            if chances > 0 && self.vmap.accessed(va) {
+                self.vmap.set_accessed(va, false);
+                self.lru.push_back(va);
+                chances -= 1;
+                continue;
+            }
+            return Some(va);
+        }
+        None
+    }
+
+    /// Unmaps the page at `va`, whose contents have been saved to swap slot
+    /// `slot`, freeing it and taking it out of `usage`.
+    pub fn swap_out(&mut self, va: VirtualAddr, slot: usize) {
+        let kind = self.page_kind(va);
+        self.vmap.swap_out(va, slot);
+        self.usage.uncount(kind);
+    }
+
+    /// Makes the page containing `va` accessible if it belongs to the
+    /// process: sets its access flag if it is mapped, reads it back if it
+    /// was swapped out, or maps a zeroed page if it is in the heap. Returns
+    /// `false` if `va` is outside the process or the page could not be mapped
+    /// or read back.
+    pub fn fault_in(&mut self, va: VirtualAddr) -> bool {
+        let page = VirtualAddr::from(va.as_usize() & PAGE_MASK);
+        if self.vmap.is_valid(page) {
+            self.vmap.set_accessed(page, true);
+            aarch64::dsb_ishst();
+            aarch64::isb();
+            return true;
+        }
+        let slot = match self.vmap.swap_slot(page) {
+            Some(slot) => slot,
+            None => return self.fault_in_heap_page(va),
+        };
+        let kind = self.page_kind(page);
+        let frame = match self.map_page(page, PagePerm::RWX, kind) {
+            Ok(frame) => frame,
+            Err(_) => return false,
+        };
+        if swap::swap_in(slot, frame).is_err() {
+            return false;
+        }
+        if kind == PageKind::Image {
+            unsafe { aarch64::sync_icache_range(frame.as_ptr() as usize, PAGE_SIZE) };
+        }
+        true
+    }
+
+    /// Returns `true` if a heap ending at `end` fits in `mem_limit` once all
+    /// of it is mapped. Pages left mapped past the end by shrinking the heap
+    /// still count.
+    fn heap_fits(&self, end: usize) -> bool {
+        let limit = match self.mem_limit {
+            Some(limit) => limit,
+            None => return true,
+        };
+        let span = (end - self.heap_start.as_usize() + PAGE_SIZE - 1) / PAGE_SIZE;
+        self.usage.stack + self.usage.image + span.max(self.usage.heap) <= limit
+    }
+
+    /// Maps a zeroed page at the page containing `va` if `va` is in the heap
+    /// but not yet mapped. Returns `false` if `va` is outside the heap or no
+    /// page could be mapped.
+    pub fn fault_in_heap_page(&mut self, va: VirtualAddr) -> bool {
+        let addr = va.as_usize();
+        if addr < self.heap_start.as_usize() || addr >= self.heap_end.as_usize() {
+            return false;
+        }
+        let page = VirtualAddr::from(addr & PAGE_MASK);
+        if !self.vmap.is_valid(page) {
+            match self.map_page(page, PagePerm::RW, PageKind::Heap) {
+                Ok(frame) => frame.iter_mut().for_each(|byte| *byte = 0),
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+
+    /// Returns `BadAddress` unless the `len` bytes at `va` are mapped user
+    /// memory. Pages in the range are made accessible with `fault_in()`, so
+    /// that the kernel can read the range without faulting.
+    pub fn check_user_range(&mut self, va: VirtualAddr, len: usize) -> OsResult<()> {
+        let start = va.as_usize();
+        let end = start.checked_add(len).ok_or(OsError::BadAddress)?;
+        if start < USER_IMG_BASE {
+            return Err(OsError::BadAddress);
+        }
+        let mut page = start & PAGE_MASK;
+        while page < end {
+            let first = VirtualAddr::from(page.max(start));
+            if !self.fault_in(first) {
+                return Err(OsError::BadAddress);
+            }
+            page = match page.checked_add(PAGE_SIZE) {
+                Some(next) => next,
+                None => break,
+            };
+        }
+        Ok(())
+    }
+
     /// Returns the highest `VirtualAddr` that is supported by this system.
     pub fn get_max_va() -> VirtualAddr {
         VirtualAddr::from(core::usize::MAX)
@@ -107,6 +518,30 @@ impl Process {
         VirtualAddr::from(core::usize::MAX & !(PAGE_ALIGN - 1))
     }
 
+    /// Returns the total CPU time consumed by this process as of `now`,
+    /// including the current time slice if the process is running.
+    pub fn cpu_time_at(&self, now: Duration) -> Duration {
+        if let State::Running = self.state {
+            self.cpu_time + (now - self.slice_start)
+        } else {
+            self.cpu_time
+        }
+    }
+
+    /// Returns `true` if this process has consumed more CPU time than its
+    /// limit allows as of `now`.
+    pub fn exceeds_cpu_limit(&self, now: Duration) -> bool {
+        match self.cpu_limit {
+            Some(limit) => self.cpu_time_at(now) > limit,
+            None => false,
+        }
+    }
+
+    /// Returns `true` if the process may run on core `core`.
+    pub fn runs_on(&self, core: usize) -> bool {
+        self.affinity & (1 << core) != 0
+    }
+
     /// Returns `true` if this process is ready to be scheduled.
     ///
     /// This functions returns `true` only if one of the following holds:
@@ -130,8 +565,181 @@ impl Process {
             if func(self) {
                 return true;
             }
-            self.state = s;
         }
+        self.state = s;
         false
     }
 }
+
+/// Reads from `reader` until `page` is full or the reader reaches its end,
+/// retrying interrupted reads, and zeroes the rest of the page. Returns the
+/// number of bytes read.
+///
+/// A single `read` may stop short of the end of a page, for instance where
+/// a fragmented file moves to another cluster.
+fn fill_page<R: Read>(reader: &mut R, page: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < page.len() {
+        match reader.read(&mut page[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    for byte in page[filled..].iter_mut() {
+        *byte = 0;
+    }
+    Ok(filled)
+}
+
+/// Copies `args` to the top of the stack page `page`, which is mapped at
+/// `base`, below `top`: the strings, NUL-terminated, and below them an array
+/// of pointers to them ending with a null pointer. Returns the address of
+/// the array, which is 16-byte aligned to serve as the stack pointer.
+///
+/// Returns `NoVmSpace` if they do not fit in the page.
+fn push_args(page: &mut [u8], base: u64, top: u64, args: &[&str]) -> OsResult<u64> {
+    let strings: usize = args.iter().map(|arg| arg.len() + 1).sum();
+    let pointers = (args.len() + 1) * 8;
+    let mut offset = (top - base) as usize;
+    if strings + pointers + 16 > offset {
+        return Err(OsError::NoVmSpace);
+    }
+    let argv = (offset - strings - pointers) & !15;
+    let mut slot = argv;
+    for arg in args {
+        offset -= arg.len() + 1;
+        page[offset..offset + arg.len()].copy_from_slice(arg.as_bytes());
+        page[offset + arg.len()] = 0;
+        page[slot..slot + 8].copy_from_slice(&(base + offset as u64).to_le_bytes());
+        slot += 8;
+    }
+    page[slot..slot + 8].copy_from_slice(&0u64.to_le_bytes());
+    Ok(base + argv as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    /// Hands out at most `chunk` bytes of `data` per read.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        chunk: usize,
+    }
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.chunk.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn pushes_args_below_top() {
+        let mut page = [0xAA; 256];
+        let (base, top) = (0x1000, 0x1000 + 256 - 16);
+        let argv = push_args(&mut page, base, top, &["netd", "-p", "80"]).unwrap();
+        assert_eq!(argv % 16, 0);
+        let word = |addr: u64| {
+            let i = (addr - base) as usize;
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&page[i..i + 8]);
+            u64::from_le_bytes(bytes)
+        };
+        let string = |addr: u64| {
+            let i = (addr - base) as usize;
+            let len = page[i..].iter().position(|&b| b == 0).unwrap();
+            core::str::from_utf8(&page[i..i + len]).unwrap()
+        };
+        assert_eq!(string(word(argv)), "netd");
+        assert_eq!(string(word(argv + 8)), "-p");
+        assert_eq!(string(word(argv + 16)), "80");
+        assert_eq!(word(argv + 24), 0);
+        assert!(word(argv) < top);
+        // Nothing at or above `top` is touched.
+        assert!(page[240..].iter().all(|&b| b == 0xAA));
+
+        let argv = push_args(&mut page, base, top, &[]).unwrap();
+        assert_eq!((argv, word(argv)), (top - 16, 0));
+        let long = [core::str::from_utf8(&[b'x'; 200]).unwrap(); 2];
+        assert_eq!(push_args(&mut page, base, top, &long), Err(OsError::NoVmSpace));
+    }
+
+    #[test]
+    fn fill_page_loops_over_short_reads() {
+        let data: Vec<u8> = (0..100).collect();
+        let mut reader = Trickle { data: &data, chunk: 7 };
+        let mut page = [0xAA; 64];
+        assert_eq!(fill_page(&mut reader, &mut page).unwrap(), 64);
+        assert_eq!(&page[..], &data[..64]);
+
+        // The last page is zero-filled past the end of the file.
+        let mut page = [0xAA; 64];
+        assert_eq!(fill_page(&mut reader, &mut page).unwrap(), 36);
+        assert_eq!(&page[..36], &data[64..]);
+        assert!(page[36..].iter().all(|&b| b == 0));
+        assert_eq!(fill_page(&mut reader, &mut page).unwrap(), 0);
+    }
+
+    #[test]
+    fn sbrk_honors_mem_limit() {
+        let mut p = Process::new().unwrap();
+        p.heap_start = VirtualAddr::from(USER_IMG_BASE + PAGE_SIZE);
+        p.heap_end = p.heap_start;
+        p.usage = MemUsage { stack: 1, image: 1, heap: 0 };
+        p.mem_limit = Some(4);
+
+        assert_eq!(p.sbrk(2 * PAGE_SIZE as i64), Ok(p.heap_start));
+        let end = p.heap_end;
+        assert_eq!(p.sbrk(1), Err(OsError::NoMemory));
+        assert_eq!(p.heap_end, end);
+
+        // Shrinking is always allowed, and so is growing back over pages
+        // that are still mapped, but not past them.
+        p.usage.heap = 2;
+        assert_eq!(p.sbrk(-(2 * PAGE_SIZE as i64)), Ok(end));
+        assert_eq!(p.sbrk(2 * PAGE_SIZE as i64), Ok(p.heap_start));
+        assert_eq!(p.sbrk(1), Err(OsError::NoMemory));
+        p.mem_limit = None;
+        assert_eq!(p.sbrk(1), Ok(end));
+    }
+
+    #[test]
+    fn next_victim_gives_accessed_pages_a_second_chance() {
+        let mut p = Process::new().unwrap();
+        let pages: Vec<VirtualAddr> =
+            (0..3).map(|i| VirtualAddr::from(USER_IMG_BASE + i * PAGE_SIZE)).collect();
+        for &va in pages.iter() {
+            p.vmap.alloc(va, PagePerm::RW).unwrap();
+            p.lru.push_back(va);
+        }
+
+        // Pages are mapped accessed, so only page 1 goes at once; page 0
+        // loses its flag on the way.
+        p.vmap.set_accessed(pages[1], false);
+        assert_eq!(p.next_victim(), Some(pages[1]));
+        assert!(!p.vmap.accessed(pages[0]));
+        assert_eq!(p.next_victim(), Some(pages[0]));
+
+        // An access after the flag was cleared earns another chance, but
+        // a process whose every page is in use still gives one up.
+        p.vmap.set_accessed(pages[2], true);
+        assert_eq!(p.next_victim(), Some(pages[2]));
+        assert_eq!(p.next_victim(), None);
+    }
+
+    #[test]
+    fn stack_canary_detects_overflow() {
+        let p = Process::new().unwrap();
+        assert!(p.stack.canary_intact());
+        let bottom = p.stack.bottom().as_u64() as *mut u8;
+        unsafe { bottom.add(PAGE_SIZE + 3).write(0x41) };
+        assert!(!p.stack.canary_intact());
+    }
+}
@@ -0,0 +1,146 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::fmt;
+
+use kernel_api::{OsError, OsResult};
+
+/// State that subsystems outside `process` attach to a process, each entry
+/// under the name of its subsystem, and that is released when the process
+/// exits or is killed.
+///
+/// Release is left to each value's `Drop` impl: the table only guarantees
+/// that every value is dropped with the process, latest attached first, so
+/// a resource may rely on those attached before it. A subsystem whose state
+/// needs no more than that does not have to be known to the scheduler.
+#[derive(Default)]
+pub struct Resources {
+    entries: Vec<(&'static str, Box<dyn Any + Send>)>,
+}
+
+impl Resources {
+    /// Returns a table without resources.
+    pub fn new() -> Resources {
+        Resources { entries: Vec::new() }
+    }
+
+    /// Attaches `value` under `name`.
+    ///
+    /// Returns `FileExists` if `name` already has a value.
+    pub fn attach<T: Any + Send>(&mut self, name: &'static str, value: T) -> OsResult<()> {
+        if self.position(name).is_some() {
+            return Err(OsError::FileExists);
+        }
+        self.entries.push((name, Box::new(value)));
+        Ok(())
+    }
+
+    /// Returns the value attached under `name`, if there is one of type `T`.
+    pub fn get<T: Any + Send>(&self, name: &str) -> Option<&T> {
+        self.entries[self.position(name)?].1.downcast_ref()
+    }
+
+    pub fn get_mut<T: Any + Send>(&mut self, name: &str) -> Option<&mut T> {
+        let index = self.position(name)?;
+        self.entries[index].1.downcast_mut()
+    }
+
+    /// Detaches the value attached under `name` and returns it, if it is of
+    /// type `T`, so that the caller decides when it is released.
+    pub fn detach<T: Any + Send>(&mut self, name: &str) -> Option<T> {
+        let index = self.position(name)?;
+        if !self.entries[index].1.is::<T>() {
+            return None;
+        }
+        let (_, value) = self.entries.remove(index);
+        value.downcast().ok().map(|value| *value)
+    }
+
+    /// Returns the names with a value attached, in the order attached.
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.entries.iter().map(|&(name, _)| name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|&(entry, _)| entry == name)
+    }
+}
+
+impl Drop for Resources {
+    fn drop(&mut self) {
+        while let Some(entry) = self.entries.pop() {
+            drop(entry);
+        }
+    }
+}
+
+impl fmt::Debug for Resources {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use crate::mutex::Mutex;
+
+    use super::*;
+
+    /// Records its name in a shared log when dropped.
+    struct Logged(&'static str, Arc<Mutex<Vec<&'static str>>>);
+
+    impl Drop for Logged {
+        fn drop(&mut self) {
+            self.1.lock().push(self.0);
+        }
+    }
+
+    #[test]
+    fn drops_latest_attached_first() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut resources = Resources::new();
+        for &name in &["fds", "shm", "sockets"] {
+            resources.attach(name, Logged(name, log.clone())).unwrap();
+        }
+        assert_eq!(resources.names().collect::<Vec<_>>(), vec!["fds", "shm", "sockets"]);
+        drop(resources);
+        assert_eq!(*log.lock(), vec!["sockets", "shm", "fds"]);
+    }
+
+    #[test]
+    fn attaches_each_name_once() {
+        let mut resources = Resources::new();
+        resources.attach("timers", 3u32).unwrap();
+        assert_eq!(resources.attach("timers", 4u32), Err(OsError::FileExists));
+        assert_eq!(resources.get::<u32>("timers"), Some(&3));
+        *resources.get_mut::<u32>("timers").unwrap() += 1;
+        // The wrong type neither reads nor detaches the value.
+        assert_eq!(resources.get::<u64>("timers"), None);
+        assert_eq!(resources.detach::<u64>("timers"), None);
+        assert_eq!(resources.detach::<u32>("timers"), Some(4));
+        assert!(resources.is_empty());
+        assert_eq!(resources.get::<u32>("timers"), None);
+    }
+
+    #[test]
+    fn detached_values_outlive_the_table() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut resources = Resources::new();
+        resources.attach("shm", Logged("shm", log.clone())).unwrap();
+        let shm = resources.detach::<Logged>("shm").unwrap();
+        drop(resources);
+        assert!(log.lock().is_empty());
+        drop(shm);
+        assert_eq!(*log.lock(), vec!["shm"]);
+    }
+}
@@ -1,13 +1,23 @@
-use alloc::boxed::Box;
 use alloc::collections::vec_deque::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::fmt;
+use core::sync::atomic::AtomicBool;
+use core::time::Duration;
 
-use pi::timer::Timer;
-use pi::interrupt::{Controller, Interrupt};
+use pi::local_interrupt::{CoreTimer, LocalController, LocalInterrupt};
 
-use crate::console::kprintln;
-use crate::mutex::Mutex;
-use crate::param::{PAGE_SIZE, TICK, USER_IMG_BASE};
-use crate::process::{Id, Process, State};
+use crate::console::{kprintln, CONSOLE};
+use crate::mutex::{self, LockStats, Mutex, NO_PROCESS};
+use crate::config;
+use crate::param::{ALL_CORES, NCORES, PAGE_SIZE, USER_IMG_BASE};
+use crate::process::{Clock, Id, Policy, Process, State, SystemClock, TimerWheel};
+use crate::systimer;
+use crate::vm::VirtualAddr;
+use kernel_api::{OsError, OsResult};
+use crate::traps::ipi::{self, Ipi};
+use crate::traps::irq::IrqHandler;
 use crate::traps::TrapFrame;
 use crate::IRQ;
 
@@ -35,7 +45,23 @@ impl GlobalScheduler {
     /// Adds a process to the scheduler's queue and returns that process's ID.
     /// For more details, see the documentation on `Scheduler::add()`.
     pub fn add(&self, process: Process) -> Option<Id> {
-        self.critical(move |scheduler| scheduler.add(process))
+        let pid = self.critical(move |scheduler| scheduler.add(process))?;
+        ipi::broadcast(Ipi::Reschedule);
+        Some(pid)
+    }
+
+    /// Starts a thread of the currently running process and returns its ID.
+    /// For more details, see the documentation on `Scheduler::spawn_thread()`.
+    pub fn spawn_thread(
+        &self,
+        tf: &TrapFrame,
+        entry: VirtualAddr,
+        stack: VirtualAddr,
+        arg: u64,
+    ) -> OsResult<Id> {
+        let tid = self.critical(|scheduler| scheduler.spawn_thread(tf, entry, stack, arg))?;
+        ipi::broadcast(Ipi::Reschedule);
+        Ok(tid)
     }
 
     /// Performs a context switch using `tf` by setting the state of the current
@@ -47,6 +73,15 @@ impl GlobalScheduler {
         self.switch_to(tf)
     }
 
+    /// Gives up the rest of the current process's time slice: schedules it
+    /// out as `Ready` and switches to the next process, which is the same
+    /// one if no other process of its rank is ready. See
+    /// `Scheduler::yield_out()`.
+    pub fn yield_now(&self, tf: &mut TrapFrame) -> Id {
+        self.critical(|scheduler| scheduler.yield_out(tf));
+        self.switch_to(tf)
+    }
+
     pub fn switch_to(&self, tf: &mut TrapFrame) -> Id {
         loop {
             let rtn = self.critical(|scheduler| scheduler.switch_to(tf));
@@ -57,27 +92,189 @@ impl GlobalScheduler {
         }
     }
 
-    /// Kills currently running process and returns that process's ID.
-    /// For more details, see the documentaion on `Scheduler::kill()`.
+    /// Sets whether the syscalls of process `pid` are logged. Returns `false`
+    /// if there is no such process.
+    pub fn set_traced(&self, pid: Id, traced: bool) -> bool {
+        self.critical(|scheduler| match scheduler.find_mut(pid) {
+            Some(p) => {
+                p.traced = traced;
+                true
+            }
+            None => false,
+        })
+    }
+
+    /// Restricts process `pid` to the cores in `mask`: bit `n` is set if it
+    /// may run on core `n`. A process whose cores are not scheduling yet,
+    /// such as cores other than 0 while they are parked, waits until one
+    /// starts. See `Scheduler::set_affinity()`.
+    ///
+    /// Returns `InvalidArgument` if `mask` names no core and `NoEntry` if
+    /// there is no such process.
+    pub fn set_affinity(&self, pid: Id, mask: u64) -> OsResult<()> {
+        if mask & ALL_CORES == 0 {
+            return Err(OsError::InvalidArgument);
+        }
+        self.critical(|scheduler| scheduler.set_affinity(pid, mask & ALL_CORES))
+    }
+
+    /// Sets the scheduling policy of process `pid`. Returns `NoEntry` if
+    /// there is no such process.
+    pub fn set_policy(&self, pid: Id, policy: Policy) -> OsResult<()> {
+        self.critical(|scheduler| scheduler.set_policy(pid, policy))
+    }
+
+    /// Sets the `alarm` of process `pid` at time `at`; see
+    /// `Scheduler::sleep_until()`. A system timer deadline sets it on time,
+    /// so an idle core need not wait for its tick to run the process; should
+    /// no deadline be available, the tick sets it, a tick late at most.
+    pub fn sleep_until(&self, pid: Id, at: Duration) -> OsResult<()> {
+        self.critical(|scheduler| scheduler.sleep_until(pid, at))?;
+        let _ = systimer::schedule(at, GlobalScheduler::wake_sleepers, 0);
+        Ok(())
+    }
+
+    /// The system timer callback of `sleep_until()`.
+    fn wake_sleepers(_: usize) {
+        crate::SCHEDULER.critical(|scheduler| scheduler.expire_sleepers());
+    }
+
+    /// Returns the affinity mask of process `pid`, if it exists.
+    pub fn affinity(&self, pid: Id) -> Option<u64> {
+        self.critical(|scheduler| scheduler.find(pid).map(|p| p.affinity))
+    }
+
+    /// Returns the number of times a process has been dispatched since boot.
+    pub fn dispatches(&self) -> u64 {
+        self.critical(|scheduler| scheduler.dispatches)
+    }
+
+    /// Returns the dispatch statistics of every core.
+    pub fn stats(&self) -> [CoreStats; NCORES] {
+        self.critical(|scheduler| scheduler.stats)
+    }
+
+    /// Zeroes the dispatch statistics of every core.
+    pub fn reset_stats(&self) {
+        self.critical(|scheduler| scheduler.stats = [CoreStats::default(); NCORES]);
+    }
+
+    /// Returns how the scheduler's lock has been used.
+    pub fn lock_stats(&self) -> LockStats {
+        self.0.stats()
+    }
+
+    /// Kills currently running process, switches to the next ready process,
+    /// and returns the killed process's ID. If no process is ready, waits for
+    /// one to become ready. For more details, see the documentaion on
+    /// `Scheduler::kill()`.
     #[must_use]
     pub fn kill(&self, tf: &mut TrapFrame) -> Option<Id> {
-        self.critical(|scheduler| scheduler.kill(tf))
+        let pid = self.critical(|scheduler| scheduler.kill(tf))?;
+        self.switch_to(tf);
+        Some(pid)
+    }
+
+    /// Starts the scheduler tick on the calling core, from its own core
+    /// timer, so that each core can keep its own tick. Each core calls this
+    /// once, the boot core as the scheduler starts and the others as they
+    /// come online.
+    pub fn start_tick(&self) {
+        IRQ.replace_local(LocalInterrupt::CntPns, IrqHandler::new(GlobalScheduler::ticc, 0));
+        LocalController::current().enable(LocalInterrupt::CntPns);
+        CoreTimer::new().tick_in(config::sched_tick());
     }
 
-    fn ticc(tf: &mut TrapFrame) {
-        Timer::new().tick_in(TICK);
+    /// Takes the tick due on the calling core while it idles with IRQs
+    /// masked. There is nothing to preempt, so it is only set up again.
+    pub(crate) fn poll_tick(&self) {
+        let mut timer = CoreTimer::new();
+        if timer.check_deadline() {
+            timer.tick_in(config::sched_tick());
+        }
+    }
+
+    fn ticc(_: usize, tf: &mut TrapFrame) {
+        let mut timer = CoreTimer::new();
+        if !timer.check_deadline() {
+            return;
+        }
+        timer.tick_in(config::sched_tick());
+        // Kernel threads run with IRQs masked, so the tick never lands in
+        // one; every stack is checked instead of only the current one.
+        let (over_limit, overflowed) = crate::SCHEDULER.critical(|scheduler| {
+            scheduler.expire_sleepers();
+            let now = scheduler.clock.now();
+            let overflowed = scheduler
+                .processes()
+                .find(|p| !p.stack.canary_intact())
+                .map(|p| p.context.tpidr);
+            match scheduler.current_mut(tf) {
+                Some(p) => (p.exceeds_cpu_limit(now), overflowed),
+                None => (false, overflowed),
+            }
+        });
+        if let Some(pid) = overflowed {
+            panic!("stack overflow: canary of process {} overwritten", pid);
+        }
+        if over_limit {
+            kprintln!("killing process {}: CPU time limit exceeded", tf.tpidr);
+            let _ = crate::SCHEDULER.kill(tf);
+            return;
+        }
+        let interrupted = {
+            let mut console = CONSOLE.lock();
+            match console.foreground() {
+                Some(pgid) if console.poll_interrupt() => Some(pgid),
+                _ => None,
+            }
+        };
+        if let Some(pgid) = interrupted {
+            kprintln!("^C");
+            if crate::SCHEDULER.kill_group(pgid, tf) {
+                return;
+            }
+        }
         crate::SCHEDULER.switch(State::Ready, tf);
     }
 
+    /// Kills every process in the process group `pgid`. If the currently
+    /// running process is a member of the group, switches to the next process
+    /// and returns `true`. Otherwise, returns `false`.
+    pub fn kill_group(&self, pgid: Id, tf: &mut TrapFrame) -> bool {
+        let kill_current = self.critical(|scheduler| scheduler.kill_group(pgid, tf));
+        if kill_current {
+            let _ = self.kill(tf);
+        }
+        kill_current
+    }
+
+    /// Writes one line per process to `w`: its ID, group, state, saved
+    /// program counter, and CPU time. Does not allocate, so it is usable from
+    /// the panic handler.
+    pub fn summarize(&self, w: &mut dyn fmt::Write) -> fmt::Result {
+        let guard = match self.0.try_lock() {
+            Some(guard) => guard,
+            None => return writeln!(w, "<locked>"),
+        };
+        let scheduler = match guard.as_ref() {
+            Some(scheduler) => scheduler,
+            None => return writeln!(w, "<uninitialized>"),
+        };
+        for p in scheduler.processes() {
+            writeln!(w, "pid {:<4} group {:<4} {:?} elr {:#018x} cpu {:?}",
+                p.context.tpidr, p.group, p.state, p.context.elr, p.cpu_time)?;
+        }
+        Ok(())
+    }
+
     /// Starts executing processes in user space using timer interrupt based
     /// preemptive scheduling. This method should not return under normal conditions.
     pub fn start(&self) -> ! {
         let mut tf = Default::default();
         let _pid = crate::SCHEDULER.switch_to(&mut tf);
         // crate::console::kprintln!("Starting PID {}", _pid);
-        IRQ.register(Interrupt::Timer1, Box::new(GlobalScheduler::ticc));
-        Controller::new().enable(Interrupt::Timer1);
-        Timer::new().tick_in(TICK);
+        self.start_tick();
         unsafe {
             llvm_asm!("mov SP, $0
                   bl context_restore
@@ -97,8 +294,9 @@ impl GlobalScheduler {
     /// Initializes the scheduler and add userspace processes to the Scheduler
     pub unsafe fn initialize(&self) {
         *self.0.lock() = Some(Scheduler::new());
-        for _ in 0..4 {
-            let p = Process::load("/fib.bin").expect("could not load process");
+        for _ in 0..config::get().init_processes {
+            let mut p = Process::load("/fib.bin").expect("could not load process");
+            p.caps = config::get().init_caps;
             self.add(p);
         }
     }
@@ -109,7 +307,7 @@ impl GlobalScheduler {
         use crate::vm::{VirtualAddr, PagePerm};
 
         let page = proc.vmap.alloc(
-            VirtualAddr::from(USER_IMG_BASE as u64), PagePerm::RWX);
+            VirtualAddr::from(USER_IMG_BASE as u64), PagePerm::RWX).expect("could not map page");
 
         let text = unsafe {
             core::slice::from_raw_parts(test_user_process as *const u8, 24)
@@ -119,25 +317,95 @@ impl GlobalScheduler {
     }
 }
 
+/// Returns the core the scheduler is running on. Host tests run as core 0.
+fn current_core() -> usize {
+    #[cfg(not(test))]
+    {
+        aarch64::affinity()
+    }
+
+    #[cfg(test)]
+    {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests;
+
+/// What each core's dispatcher has done since boot or the last
+/// `reset_stats()`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CoreStats {
+    /// The processes the core dispatched.
+    pub dispatches: u64,
+    /// The processes the core took from another core's run queue.
+    pub migrations: u64,
+}
+
+/// How a ready process ranks for dispatch; see `Policy::rank()`.
+type Rank = (u8, u8, Reverse<Duration>);
+
+/// The per-core run queues. Time is read from `C`, which is the system timer
+/// except in tests.
+///
+/// A process is queued on one core its affinity allows: the core that added
+/// it, unless its affinity rules that core out, and then the core that last
+/// dispatched it. A core dispatches from its own queue, and only once nothing
+/// there is ready does it take the best ready process that may run on it
+/// from a sibling's queue. Each such migration is counted in the process and in
+/// the core's `CoreStats`.
 #[derive(Debug)]
-pub struct Scheduler {
-    processes: VecDeque<Process>,
+pub struct Scheduler<C: Clock = SystemClock> {
+    /// The processes queued on each core, in queue order.
+    queues: Vec<VecDeque<Process>>,
     last_id: Option<Id>,
+    clock: C,
+    /// The processes dispatched since boot.
+    dispatches: u64,
+    /// What each core has done; see `CoreStats`.
+    stats: [CoreStats; NCORES],
+    /// The processes waiting for their `alarm`, by the time it is due.
+    sleepers: TimerWheel<Id>,
 }
 
 impl Scheduler {
-    /// Returns a new `Scheduler` with an empty queue.
+    /// Returns a new `Scheduler` with empty queues.
     fn new() -> Scheduler {
+        Scheduler::with_clock(SystemClock)
+    }
+}
+
+/// Returns the core whose queue process `p` joins when it would join `core`'s:
+/// `core` itself if `p` may run there, and otherwise the first core it may
+/// run on.
+fn home(p: &Process, core: usize) -> usize {
+    if p.runs_on(core) {
+        core
+    } else {
+        (0..NCORES).find(|&c| p.runs_on(c)).unwrap_or(core)
+    }
+}
+
+impl<C: Clock> Scheduler<C> {
+    /// Returns a new `Scheduler` with empty queues that reads the time from
+    /// `clock`.
+    fn with_clock(clock: C) -> Scheduler<C> {
         Scheduler {
-            processes: VecDeque::new(),
+            queues: (0..NCORES).map(|_| VecDeque::new()).collect(),
             last_id: None,
+            clock,
+            dispatches: 0,
+            stats: [CoreStats::default(); NCORES],
+            sleepers: TimerWheel::new(),
         }
     }
 
     /// Adds a process to the scheduler's queue and returns that process's ID if
     /// a new process can be scheduled. The process ID is newly allocated for
-    /// the process and saved in its `trap_frame`. If no further processes can
-    /// be scheduled, returns `None`.
+    /// the process and saved in its `trap_frame`. The process starts out in a
+    /// new process group whose ID is its process ID. If no further processes
+    /// can be scheduled, returns `None`.
     ///
     /// It is the caller's responsibility to ensure that the first time `switch`
     /// is called, that process is executing on the CPU.
@@ -149,7 +417,9 @@ impl Scheduler {
         };
         if let Some(pid) = new_pid {
             process.context.tpidr = pid;
-            self.processes.push_back(process);
+            process.group = pid;
+            let core = home(&process, current_core());
+            self.queues[core].push_back(process);
             self.last_id = new_pid;
             new_pid
         } else {
@@ -157,37 +427,220 @@ impl Scheduler {
         }
     }
 
+    /// Returns an iterator over the processes, core by core and in queue
+    /// order.
+    pub fn processes(&self) -> impl Iterator<Item = &Process> {
+        self.queues.iter().flat_map(|queue| queue.iter())
+    }
+
+    /// Returns a mutable iterator over the processes, core by core and in
+    /// queue order.
+    pub fn processes_mut(&mut self) -> impl Iterator<Item = &mut Process> {
+        self.queues.iter_mut().flat_map(|queue| queue.iter_mut())
+    }
+
+    /// Returns the number of processes queued on core `core`.
+    pub fn queued(&self, core: usize) -> usize {
+        self.queues[core].len()
+    }
+
+    /// Returns a reference to the process with ID `pid`, if it exists.
+    pub fn find(&self, pid: Id) -> Option<&Process> {
+        self.processes().find(|p| p.context.tpidr == pid)
+    }
+
+    /// Returns a mutable reference to the process with ID `pid`, if it exists.
+    pub fn find_mut(&mut self, pid: Id) -> Option<&mut Process> {
+        self.processes_mut().find(|p| p.context.tpidr == pid)
+    }
+
+    /// Returns the core whose queue holds the process with ID `pid` and its
+    /// index there. Only a `Running` process is found if `running` is set.
+    fn position(&self, pid: Id, running: bool) -> Option<(usize, usize)> {
+        self.queues.iter().enumerate().find_map(|(core, queue)| {
+            let i = queue.iter().position(|p| {
+                p.context.tpidr == pid && (!running || match p.state {
+                    State::Running => true,
+                    _ => false,
+                })
+            })?;
+            Some((core, i))
+        })
+    }
+
+    /// Sets the affinity mask of process `pid` to `mask`. Unless it is
+    /// running, the process moves to the queue of the calling core if it
+    /// may run there and to that of the first core it may run on otherwise;
+    /// a running process moves when it is next scheduled out.
+    fn set_affinity(&mut self, pid: Id, mask: u64) -> OsResult<()> {
+        let (core, i) = self.position(pid, false).ok_or(OsError::NoEntry)?;
+        let p = &mut self.queues[core][i];
+        p.affinity = mask;
+        if let State::Running = p.state {
+            return Ok(());
+        }
+        let to = home(p, current_core());
+        if to != core {
+            if let Some(p) = self.queues[core].remove(i) {
+                self.queues[to].push_back(p);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the scheduling policy of process `pid`. A `Deadline` process's
+    /// first deadline is counted from now.
+    fn set_policy(&mut self, pid: Id, policy: Policy) -> OsResult<()> {
+        let now = self.clock.now();
+        let p = self.find_mut(pid).ok_or(OsError::NoEntry)?;
+        p.policy = policy;
+        if let Policy::Deadline(relative) = policy {
+            p.deadline = now + relative;
+        }
+        Ok(())
+    }
+
+    /// Clears the `alarm` of process `pid` and sets it again at time `at`,
+    /// or sets it now if `at` has passed. A process waiting on its alarm
+    /// costs its wait function a flag check, however many others sleep.
+    pub fn sleep_until(&mut self, pid: Id, at: Duration) -> OsResult<()> {
+        let now = self.clock.now();
+        let p = self.find_mut(pid).ok_or(OsError::NoEntry)?;
+        p.alarm = at <= now;
+        if !p.alarm {
+            self.sleepers.insert(at, pid);
+        }
+        Ok(())
+    }
+
+    /// Sets the `alarm` of every process whose time has come, in one pass.
+    /// Alarms of processes that have since died are dropped.
+    fn expire_sleepers(&mut self) {
+        let mut due = self.sleepers.advance(self.clock.now());
+        if due.is_empty() {
+            return;
+        }
+        due.sort_unstable();
+        for p in self.processes_mut() {
+            if due.binary_search(&p.context.tpidr).is_ok() {
+                p.alarm = true;
+            }
+        }
+    }
+
+    /// Returns a mutable reference to the currently running process, the
+    /// process whose ID is saved in `tf`, if there is one.
+    pub fn current_mut(&mut self, tf: &TrapFrame) -> Option<&mut Process> {
+        self.processes_mut().find(|p| {
+            p.context.tpidr == tf.tpidr && match p.state {
+                State::Running => true,
+                _ => false,
+            }
+        })
+    }
+
+    /// Returns a mutable reference to the process whose address space and
+    /// file descriptors the currently running process uses: its leader if it
+    /// is a thread, and itself otherwise.
+    pub fn owner_mut(&mut self, tf: &TrapFrame) -> Option<&mut Process> {
+        let leader = self.current_mut(tf)?.leader;
+        match leader {
+            Some(leader) => self.find_mut(leader),
+            None => self.current_mut(tf),
+        }
+    }
+
+    /// Adds a thread of the currently running process that starts at
+    /// `entry` with `arg` in `x0` and its stack pointer at `stack`, in the
+    /// caller's process group. Threads started by a thread belong to its
+    /// leader. Returns the thread's ID.
+    ///
+    /// Returns `NoEntry` if there is no current process and `NoMemory` if
+    /// the thread could not be created or given an ID.
+    fn spawn_thread(
+        &mut self,
+        tf: &TrapFrame,
+        entry: VirtualAddr,
+        stack: VirtualAddr,
+        arg: u64,
+    ) -> OsResult<Id> {
+        let group = self.current_mut(tf).ok_or(OsError::NoEntry)?.group;
+        let owner = self.owner_mut(tf).ok_or(OsError::NoEntry)?;
+        let thread = Process::thread(owner, entry, stack, arg)?;
+        let tid = self.add(thread).ok_or(OsError::NoMemory)?;
+        if let Some(thread) = self.find_mut(tid) {
+            thread.group = group;
+        }
+        Ok(tid)
+    }
+
+    /// Returns a flag raised when thread `tid` exits, for a thread of the
+    /// same process as the currently running one to wait on.
+    ///
+    /// Returns `InvalidArgument` if `tid` is the current thread and `NoEntry`
+    /// if it is not a live thread of the current process.
+    pub fn watch_thread(&mut self, tf: &TrapFrame, tid: Id) -> OsResult<Arc<AtomicBool>> {
+        if tid == tf.tpidr {
+            return Err(OsError::InvalidArgument);
+        }
+        let owner = self.current_mut(tf).ok_or(OsError::NoEntry)?.owner();
+        match self.find(tid) {
+            Some(thread) if thread.leader == Some(owner) => Ok(thread.exit.watch()),
+            _ => Err(OsError::NoEntry),
+        }
+    }
+
+    /// Removes the threads whose leader has exited, except `current`, which
+    /// the caller kills itself. Threads cannot outlive the address space
+    /// they run in.
+    fn reap_threads(&mut self, current: Id) {
+        let leaders: Vec<Id> = self
+            .processes()
+            .filter(|p| p.leader.is_none())
+            .map(|p| p.context.tpidr)
+            .collect();
+        for queue in self.queues.iter_mut() {
+            queue.retain(|p| {
+                p.context.tpidr == current || p.leader.map_or(true, |l| leaders.contains(&l))
+            });
+        }
+    }
+
     /// Finds the currently running process, sets the current process's state
     /// to `new_state`, prepares the context switch on `tf` by saving `tf`
     /// into the current process, and push the current process back to the
-    /// end of `processes` queue.
+    /// end of its core's queue, or of the first queue its affinity allows if
+    /// it may no longer run on that core.
     ///
-    /// If the `processes` queue is empty or there is no current process,
-    /// returns `false`. Otherwise, returns `true`.
+    /// If there is no current process, returns `false`. Otherwise, returns
+    /// `true`.
     fn schedule_out(&mut self, new_state: State, tf: &mut TrapFrame) -> bool {
-        let mut ind = None;
-        for i in 0..self.processes.len() {
-            if let Some(p) = self.processes.get_mut(i) {
-                if p.context.tpidr == tf.tpidr {
-                    if let State::Running = p.state {
-                        ind = Some(i);
-                        break;
-                    }
-                }
-            }
-        }
-        if let Some(i) = ind {
-            if let Some(mut p) = self.processes.remove(i) {
+        if let Some((core, i)) = self.position(tf.tpidr, true) {
+            if let Some(mut p) = self.queues[core].remove(i) {
                 let should_requeue = if let State::Dead = new_state {
                     false
                 } else {
                     true
                 };
+                p.cpu_time += self.clock.now() - p.slice_start;
+                mutex::set_running(current_core(), NO_PROCESS, 0);
+                // A preempted FIFO process keeps its place ahead of the
+                // processes of its own priority.
+                let keep_place = match (&new_state, p.policy) {
+                    (State::Ready, Policy::Fifo(_)) => true,
+                    _ => false,
+                };
                 p.state = new_state;
                 *p.context = *tf;
                 // kprintln!("schedule_out");
-                if should_requeue {
-                    self.processes.push_back(p);
+                let core = home(&p, core);
+                if keep_place {
+                    self.queues[core].push_front(p);
+                } else if should_requeue {
+                    self.queues[core].push_back(p);
+                } else {
+                    drop(p);
+                    self.reap_threads(tf.tpidr);
                 }
                 return true;
             }
@@ -195,57 +648,126 @@ impl Scheduler {
         false
     }
 
+    /// Like `schedule_out(State::Ready, tf)`, but the current process goes
+    /// behind the processes of its rank even if it is a FIFO process, which
+    /// otherwise keeps its place when preempted, since it gives up the core.
+    fn yield_out(&mut self, tf: &mut TrapFrame) -> bool {
+        if !self.schedule_out(State::Ready, tf) {
+            return false;
+        }
+        for queue in self.queues.iter_mut() {
+            if queue.front().map_or(false, |p| p.context.tpidr == tf.tpidr) {
+                if let Some(p) = queue.pop_front() {
+                    queue.push_back(p);
+                }
+            }
+        }
+        true
+    }
+
     /// Finds the next process to switch to, brings the next process to the
-    /// front of the `processes` queue, changes the next process's state to
+    /// front of the calling core's queue, changes the next process's state to
     /// `Running`, and performs context switch by restoring the next process`s
     /// trap frame into `tf`.
     ///
     /// If there is no process to switch to, returns `None`. Otherwise, returns
     /// `Some` of the next process`s process ID.
     fn switch_to(&mut self, tf: &mut TrapFrame) -> Option<Id> {
-        let mut ind = None;
-        for i in 0..self.processes.len() {
-            if let Some(p) = self.processes.get_mut(i) {
-                if p.is_ready() {
-                    ind = Some(i);
-                    break;
+        self.switch_to_on(current_core(), tf)
+    }
+
+    /// Like `switch_to()`, but for core `core`. The ready process in the
+    /// core's own queue whose policy ranks highest is chosen; among equals,
+    /// the one nearest the front of the queue. If none is ready, the core
+    /// steals the highest ranked ready process that may run on it from its
+    /// siblings' queues, the nearest sibling first among equals, and moves it
+    /// to its own queue.
+    fn switch_to_on(&mut self, core: usize, tf: &mut TrapFrame) -> Option<Id> {
+        self.expire_sleepers();
+        let mut found = self.best_ready(core, core).map(|(i, _)| (core, i));
+        if found.is_none() {
+            let mut best = None;
+            for sibling in (1..NCORES).map(|n| (core + n) % NCORES) {
+                if let Some((i, rank)) = self.best_ready(sibling, core) {
+                    if best.map_or(true, |best| rank > best) {
+                        found = Some((sibling, i));
+                        best = Some(rank);
+                    }
                 }
             }
         }
-        if let Some(i) = ind {
-            if let Some(mut p) = self.processes.remove(i) {
-                let pid = p.context.tpidr;
-                p.state = State::Running;
-                *tf = *p.context;
-                self.processes.push_front(p);
-                // kprintln!("switch_to {}", pid);
-                return Some(pid);
+        let (from, i) = found?;
+        let mut p = self.queues[from].remove(i)?;
+        let pid = p.context.tpidr;
+        if from != core {
+            p.migrations += 1;
+            self.stats[core].migrations += 1;
+        }
+        p.state = State::Running;
+        p.slice_start = self.clock.now();
+        self.dispatches += 1;
+        self.stats[core].dispatches += 1;
+        mutex::set_running(core, pid, p.policy.priority());
+        *tf = *p.context;
+        p.debug.install();
+        self.queues[core].push_front(p);
+        // kprintln!("switch_to {}", pid);
+        Some(pid)
+    }
+
+    /// Returns the index in core `queue`'s queue of the ready process that
+    /// may run on core `core` whose policy ranks highest, the one nearest the
+    /// front among equals, along with its rank.
+    fn best_ready(&mut self, queue: usize, core: usize) -> Option<(usize, Rank)> {
+        let now = self.clock.now();
+        let mut best: Option<(usize, Rank)> = None;
+        for (i, p) in self.queues[queue].iter_mut().enumerate() {
+            if !p.runs_on(core) {
+                continue;
+            }
+            let waking = match p.state {
+                State::Waiting(_) => true,
+                _ => false,
+            };
+            if !p.is_ready() {
+                continue;
+            }
+            if let (true, Policy::Deadline(relative)) = (waking, p.policy) {
+                p.deadline = now + relative;
+            }
+            let rank = p.policy.rank_with(p.deadline, mutex::inherited(p.context.tpidr));
+            if best.map_or(true, |(_, best)| rank > best) {
+                best = Some((i, rank));
             }
         }
-        None
+        best
+    }
+
+    /// Removes every process in the process group `pgid` except the currently
+    /// running one. Returns `true` if the currently running process is a
+    /// member of the group and still needs to be killed.
+    fn kill_group(&mut self, pgid: Id, tf: &TrapFrame) -> bool {
+        let current = tf.tpidr;
+        for queue in self.queues.iter_mut() {
+            queue.retain(|p| p.group != pgid || p.context.tpidr == current);
+        }
+        self.reap_threads(current);
+        self.processes().any(|p| p.group == pgid)
     }
 
     /// Kills currently running process by scheduling out the current process
     /// as `Dead` state. Removes the dead process from the queue, drop the
     /// dead process's instance, and returns the dead process's process ID.
+    ///
+    /// The caller is responsible for switching to the next process.
     fn kill(&mut self, tf: &mut TrapFrame) -> Option<Id> {
-        let mut ind = None;
-        for i in 0..self.processes.len() {
-            if let Some(p) = self.processes.get_mut(i) {
-                if p.context.tpidr == tf.tpidr {
-                    if let State::Running = p.state {
-                        ind = Some(i);
-                        break;
-                    }
-                }
-            }
-        }
-        if let Some(i) = ind {
-            if let Some(mut p) = self.processes.remove(i) {
+        if let Some((core, i)) = self.position(tf.tpidr, true) {
+            if let Some(mut p) = self.queues[core].remove(i) {
                 let pid = p.context.tpidr;
                 p.state = State::Dead;
+                mutex::set_running(current_core(), NO_PROCESS, 0);
                 drop(p);
-                self.switch_to(tf);
+                self.reap_threads(pid);
                 return Some(pid);
             }
         }
@@ -0,0 +1,438 @@
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::Cell;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use kernel_api::OsError;
+
+use crate::param::{PAGE_SIZE, USER_IMG_BASE};
+use crate::process::{Clock, Id, Policy, Process, State};
+use crate::traps::TrapFrame;
+use crate::vm::VirtualAddr;
+
+use super::{CoreStats, Scheduler};
+
+/// A clock that only moves when told to.
+#[derive(Clone, Default)]
+struct MockClock(Rc<Cell<Duration>>);
+
+impl MockClock {
+    fn advance(&self, ms: u64) {
+        self.0.set(self.0.get() + Duration::from_millis(ms));
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Duration {
+        self.0.get()
+    }
+}
+
+fn scheduler(processes: usize) -> (Scheduler<MockClock>, MockClock) {
+    let clock = MockClock::default();
+    let mut scheduler = Scheduler::with_clock(clock.clone());
+    for _ in 0..processes {
+        scheduler.add(Process::new().expect("process")).expect("pid");
+    }
+    (scheduler, clock)
+}
+
+/// Returns the IDs of the processes in queue order.
+fn queue(scheduler: &Scheduler<MockClock>) -> Vec<Id> {
+    scheduler.processes().map(|p| p.context.tpidr).collect()
+}
+
+#[test]
+fn add_assigns_ids_and_groups() {
+    let (scheduler, _) = scheduler(3);
+    assert_eq!(queue(&scheduler), vec![0, 1, 2]);
+    assert!(scheduler.processes().all(|p| p.group == p.context.tpidr));
+}
+
+#[test]
+fn round_robin() {
+    let (mut scheduler, _) = scheduler(3);
+    let mut tf = TrapFrame::default();
+    let mut order = Vec::new();
+    for _ in 0..6 {
+        order.push(scheduler.switch_to(&mut tf).expect("ready process"));
+        assert_eq!(tf.tpidr, *order.last().unwrap());
+        assert!(scheduler.schedule_out(State::Ready, &mut tf));
+    }
+    assert_eq!(order, vec![0, 1, 2, 0, 1, 2]);
+}
+
+#[test]
+fn schedule_out_saves_frame() {
+    let (mut scheduler, _) = scheduler(2);
+    let mut tf = TrapFrame::default();
+    scheduler.switch_to(&mut tf);
+    tf.elr = 0x1234;
+    scheduler.schedule_out(State::Ready, &mut tf);
+    assert_eq!(scheduler.find(0).unwrap().context.elr, 0x1234);
+
+    assert_eq!(scheduler.switch_to(&mut tf), Some(1));
+    assert_eq!(tf.elr, 0);
+}
+
+#[test]
+fn accounts_cpu_time() {
+    let (mut scheduler, clock) = scheduler(2);
+    let mut tf = TrapFrame::default();
+    scheduler.switch_to(&mut tf);
+    clock.advance(7);
+    assert_eq!(scheduler.current_mut(&tf).unwrap().cpu_time_at(clock.now()), Duration::from_millis(7));
+    scheduler.schedule_out(State::Ready, &mut tf);
+
+    scheduler.switch_to(&mut tf);
+    clock.advance(3);
+    scheduler.schedule_out(State::Ready, &mut tf);
+
+    assert_eq!(scheduler.find(0).unwrap().cpu_time, Duration::from_millis(7));
+    assert_eq!(scheduler.find(1).unwrap().cpu_time, Duration::from_millis(3));
+}
+
+#[test]
+fn cpu_limit() {
+    let (mut scheduler, clock) = scheduler(1);
+    let mut tf = TrapFrame::default();
+    scheduler.find_mut(0).unwrap().cpu_limit = Some(Duration::from_millis(5));
+    scheduler.switch_to(&mut tf);
+    clock.advance(5);
+    assert!(!scheduler.current_mut(&tf).unwrap().exceeds_cpu_limit(clock.now()));
+    clock.advance(1);
+    assert!(scheduler.current_mut(&tf).unwrap().exceeds_cpu_limit(clock.now()));
+}
+
+#[test]
+fn skips_waiting_processes() {
+    let (mut scheduler, _) = scheduler(2);
+    let mut tf = TrapFrame::default();
+    let event = Arc::new(AtomicBool::new(false));
+    let flag = event.clone();
+
+    assert_eq!(scheduler.switch_to(&mut tf), Some(0));
+    let poll = Box::new(move |_: &mut Process| flag.load(Ordering::Relaxed));
+    scheduler.schedule_out(State::Waiting(poll), &mut tf);
+    assert_eq!(scheduler.switch_to(&mut tf), Some(1));
+    scheduler.schedule_out(State::Ready, &mut tf);
+    assert_eq!(scheduler.switch_to(&mut tf), Some(1));
+    scheduler.schedule_out(State::Ready, &mut tf);
+
+    event.store(true, Ordering::Relaxed);
+    assert_eq!(scheduler.switch_to(&mut tf), Some(0));
+}
+
+#[test]
+fn nothing_ready() {
+    let (mut scheduler, _) = scheduler(1);
+    let mut tf = TrapFrame::default();
+    scheduler.switch_to(&mut tf);
+    scheduler.schedule_out(State::Waiting(Box::new(|_: &mut Process| false)), &mut tf);
+    assert_eq!(scheduler.switch_to(&mut tf), None);
+}
+
+#[test]
+fn kill() {
+    let (mut scheduler, _) = scheduler(3);
+    let mut tf = TrapFrame::default();
+    scheduler.switch_to(&mut tf);
+    assert_eq!(scheduler.kill(&mut tf), Some(0));
+    assert_eq!(queue(&scheduler), vec![1, 2]);
+    // Only the running process can be killed.
+    assert_eq!(scheduler.kill(&mut tf), None);
+}
+
+#[test]
+fn kill_group() {
+    let (mut scheduler, _) = scheduler(4);
+    for pid in 1..4 {
+        scheduler.find_mut(pid).unwrap().group = 1;
+    }
+    let mut tf = TrapFrame::default();
+    scheduler.switch_to(&mut tf);
+    scheduler.schedule_out(State::Ready, &mut tf);
+    assert_eq!(scheduler.switch_to(&mut tf), Some(1));
+
+    // The running member is left for the caller to kill.
+    assert!(scheduler.kill_group(1, &tf));
+    assert_eq!(queue(&scheduler), vec![1, 0]);
+    assert!(!scheduler.kill_group(0, &tf));
+    assert_eq!(queue(&scheduler), vec![1]);
+}
+
+#[test]
+fn respects_affinity() {
+    let (mut scheduler, _) = scheduler(3);
+    scheduler.find_mut(0).unwrap().affinity = 0b10;
+    scheduler.find_mut(2).unwrap().affinity = 0b11;
+    let mut tf = TrapFrame::default();
+    let mut order = Vec::new();
+    for _ in 0..4 {
+        order.push(scheduler.switch_to_on(0, &mut tf).expect("ready process"));
+        scheduler.schedule_out(State::Ready, &mut tf);
+    }
+    // Process 0 is pinned to core 1 and never runs on core 0.
+    assert_eq!(order, vec![1, 2, 1, 2]);
+    assert_eq!(scheduler.switch_to_on(1, &mut tf), Some(0));
+}
+
+#[test]
+fn fifo_runs_before_normal_and_keeps_its_place() {
+    let (mut scheduler, _) = scheduler(4);
+    scheduler.set_policy(2, Policy::Fifo(10)).unwrap();
+    scheduler.set_policy(3, Policy::Fifo(20)).unwrap();
+    let mut tf = TrapFrame::default();
+    for _ in 0..3 {
+        assert_eq!(scheduler.switch_to_on(0, &mut tf), Some(3));
+        scheduler.schedule_out(State::Ready, &mut tf);
+    }
+
+    // Once the highest priority process blocks, the next one runs, and
+    // normal processes only run once no FIFO process is ready.
+    scheduler.switch_to_on(0, &mut tf);
+    scheduler.schedule_out(State::Waiting(Box::new(|_| false)), &mut tf);
+    assert_eq!(scheduler.switch_to_on(0, &mut tf), Some(2));
+    scheduler.schedule_out(State::Waiting(Box::new(|_| false)), &mut tf);
+    assert_eq!(scheduler.switch_to_on(0, &mut tf), Some(0));
+    scheduler.schedule_out(State::Ready, &mut tf);
+    assert_eq!(scheduler.switch_to_on(0, &mut tf), Some(1));
+}
+
+#[test]
+fn yielding_gives_way_to_processes_of_the_same_rank() {
+    let (mut scheduler, _) = scheduler(3);
+    scheduler.set_policy(1, Policy::Fifo(10)).unwrap();
+    scheduler.set_policy(2, Policy::Fifo(10)).unwrap();
+    let mut tf = TrapFrame::default();
+    assert_eq!(scheduler.switch_to_on(0, &mut tf), Some(1));
+    assert!(scheduler.yield_out(&mut tf));
+    assert_eq!(scheduler.switch_to_on(0, &mut tf), Some(2));
+    assert!(scheduler.yield_out(&mut tf));
+    assert_eq!(scheduler.switch_to_on(0, &mut tf), Some(1));
+
+    // A process alone at its rank runs again.
+    scheduler.set_policy(2, Policy::Normal).unwrap();
+    assert!(scheduler.yield_out(&mut tf));
+    assert_eq!(scheduler.switch_to_on(0, &mut tf), Some(1));
+}
+
+#[test]
+fn lent_priority_ranks_lock_holders() {
+    let normal = Policy::Normal;
+    let fifo = Policy::Fifo(10);
+    let deadline = Duration::from_millis(5);
+
+    // A normal holder lent a FIFO priority outranks lower FIFO processes and
+    // ranks with its lender; lending less than it has changes nothing.
+    let lent = normal.rank_with(deadline, Policy::Fifo(20).priority());
+    assert!(lent > fifo.rank(deadline));
+    assert_eq!(lent.0, Policy::Fifo(20).rank(deadline).0);
+    assert_eq!(lent.1, 20);
+    assert_eq!(fifo.rank_with(deadline, normal.priority()), fifo.rank(deadline));
+    assert_eq!(normal.rank_with(deadline, 0), normal.rank(deadline));
+    assert!(Policy::Deadline(deadline).priority() > Policy::Fifo(99).priority());
+}
+
+#[test]
+fn earliest_deadline_first() {
+    let (mut scheduler, clock) = scheduler(3);
+    scheduler.set_policy(0, Policy::Fifo(50)).unwrap();
+    scheduler.set_policy(1, Policy::Deadline(Duration::from_millis(30))).unwrap();
+    scheduler.set_policy(2, Policy::Deadline(Duration::from_millis(20))).unwrap();
+    let mut tf = TrapFrame::default();
+    assert_eq!(scheduler.switch_to_on(0, &mut tf), Some(2));
+
+    // Process 2 waits; when it wakes its new deadline falls after process
+    // 1's, which runs first.
+    let wake = Arc::new(AtomicBool::new(false));
+    let flag = wake.clone();
+    let woken = Box::new(move |_: &mut Process| flag.load(Ordering::Relaxed));
+    scheduler.schedule_out(State::Waiting(woken), &mut tf);
+    assert_eq!(scheduler.switch_to_on(0, &mut tf), Some(1));
+    clock.advance(15);
+    wake.store(true, Ordering::Relaxed);
+    scheduler.schedule_out(State::Ready, &mut tf);
+    assert_eq!(scheduler.switch_to_on(0, &mut tf), Some(1));
+    assert_eq!(scheduler.find(2).unwrap().deadline, Duration::from_millis(35));
+
+    assert_eq!(scheduler.set_policy(7, Policy::Normal), Err(OsError::NoEntry));
+}
+
+#[test]
+fn counts_dispatches() {
+    let (mut scheduler, _) = scheduler(2);
+    let mut tf = TrapFrame::default();
+    assert_eq!(scheduler.switch_to_on(0, &mut tf), Some(0));
+    scheduler.schedule_out(State::Ready, &mut tf);
+    assert_eq!(scheduler.switch_to_on(0, &mut tf), Some(1));
+    assert_eq!(scheduler.dispatches, 2);
+}
+
+#[test]
+fn idle_cores_steal_what_their_affinity_allows() {
+    let (mut scheduler, _) = scheduler(3);
+    scheduler.find_mut(1).unwrap().affinity = 0b1;
+    let never = || Box::new(|_: &mut Process| false);
+    let (mut tf0, mut tf1) = (TrapFrame::default(), TrapFrame::default());
+
+    // Every process was added on core 0, so core 1 has to steal.
+    assert_eq!(scheduler.switch_to_on(1, &mut tf1), Some(0));
+    assert_eq!(scheduler.switch_to_on(0, &mut tf0), Some(1));
+    scheduler.schedule_out(State::Waiting(never()), &mut tf1);
+    assert_eq!(scheduler.switch_to_on(1, &mut tf1), Some(2));
+    scheduler.schedule_out(State::Ready, &mut tf1);
+    // Core 1 looked at process 1 while it ran on core 0 and left it running.
+    assert!(scheduler.schedule_out(State::Ready, &mut tf0));
+    assert_eq!((scheduler.queued(0), scheduler.queued(1)), (1, 2));
+
+    // A stolen process stays on its new core, and process 1, pinned to core
+    // 0, is never stolen.
+    assert_eq!(scheduler.switch_to_on(1, &mut tf1), Some(2));
+    scheduler.schedule_out(State::Waiting(never()), &mut tf1);
+    assert_eq!(scheduler.switch_to_on(1, &mut tf1), None);
+    assert_eq!(scheduler.switch_to_on(0, &mut tf0), Some(1));
+
+    let migrations: Vec<u64> = scheduler.processes().map(|p| p.migrations).collect();
+    assert_eq!(migrations, vec![0, 1, 1]);
+    assert_eq!(scheduler.stats[0], CoreStats { dispatches: 2, migrations: 0 });
+    assert_eq!(scheduler.stats[1], CoreStats { dispatches: 3, migrations: 2 });
+}
+
+#[test]
+fn affinity_moves_processes_between_queues() {
+    let (mut scheduler, _) = scheduler(2);
+    let mut tf = TrapFrame::default();
+    assert_eq!(scheduler.switch_to_on(0, &mut tf), Some(0));
+
+    // A queued process moves at once, a running one once scheduled out.
+    scheduler.set_affinity(1, 0b10).unwrap();
+    scheduler.set_affinity(0, 0b100).unwrap();
+    assert_eq!((scheduler.queued(0), scheduler.queued(1)), (1, 1));
+    scheduler.schedule_out(State::Ready, &mut tf);
+    assert_eq!((scheduler.queued(0), scheduler.queued(2)), (0, 1));
+    assert_eq!(scheduler.switch_to_on(1, &mut tf), Some(1));
+    assert_eq!(scheduler.find(1).unwrap().migrations, 0);
+    assert_eq!(scheduler.set_affinity(9, 0b1), Err(OsError::NoEntry));
+}
+
+#[test]
+fn sleepers_wake_on_their_alarm() {
+    let (mut scheduler, clock) = scheduler(2);
+    let mut tf = TrapFrame::default();
+    assert_eq!(scheduler.switch_to_on(0, &mut tf), Some(0));
+    scheduler.sleep_until(0, Duration::from_millis(20)).unwrap();
+    scheduler.schedule_out(State::Waiting(Box::new(|p: &mut Process| p.alarm)), &mut tf);
+
+    clock.advance(10);
+    assert_eq!(scheduler.switch_to_on(0, &mut tf), Some(1));
+    scheduler.schedule_out(State::Ready, &mut tf);
+    assert_eq!(scheduler.switch_to_on(0, &mut tf), Some(1));
+    scheduler.schedule_out(State::Ready, &mut tf);
+    clock.advance(10);
+    assert_eq!(scheduler.switch_to_on(0, &mut tf), Some(0));
+
+    // An alarm already due is set at once.
+    scheduler.sleep_until(1, Duration::from_millis(5)).unwrap();
+    assert!(scheduler.find(1).unwrap().alarm);
+    assert_eq!(scheduler.sleep_until(9, Duration::from_millis(5)), Err(OsError::NoEntry));
+}
+
+#[test]
+fn threads_share_their_leader_and_die_with_it() {
+    let (mut scheduler, _) = scheduler(2);
+    let mut tf = TrapFrame::default();
+    assert_eq!(scheduler.switch_to(&mut tf), Some(0));
+    let entry = VirtualAddr::from(USER_IMG_BASE);
+    let stack = VirtualAddr::from(USER_IMG_BASE + 2 * PAGE_SIZE);
+    let first = scheduler.spawn_thread(&tf, entry, stack, 7).unwrap();
+    let second = scheduler.spawn_thread(&tf, entry, stack, 8).unwrap();
+    let thread = scheduler.find(first).unwrap();
+    assert_eq!((thread.leader, thread.group), (Some(0), 0));
+    assert_eq!(thread.context.x_registers[0], 7);
+    assert_eq!(thread.context.ttbr1, scheduler.find(0).unwrap().vmap.get_baddr().as_u64());
+
+    // A thread's memory and files are its leader's, and threads it starts
+    // belong to the leader too.
+    scheduler.schedule_out(State::Ready, &mut tf);
+    assert_eq!(scheduler.switch_to(&mut tf), Some(1));
+    scheduler.schedule_out(State::Ready, &mut tf);
+    assert_eq!(scheduler.switch_to(&mut tf), Some(first));
+    assert_eq!(scheduler.owner_mut(&tf).unwrap().context.tpidr, 0);
+    let third = scheduler.spawn_thread(&tf, entry, stack, 9).unwrap();
+    assert_eq!(scheduler.find(third).unwrap().leader, Some(0));
+    assert_eq!(scheduler.watch_thread(&tf, first), Err(OsError::InvalidArgument));
+    assert_eq!(scheduler.watch_thread(&tf, 1), Err(OsError::NoEntry));
+
+    // Joining sees a thread exit; the rest die with their leader.
+    let exited = scheduler.watch_thread(&tf, second).unwrap();
+    scheduler.schedule_out(State::Ready, &mut tf);
+    assert_eq!(scheduler.switch_to(&mut tf), Some(second));
+    assert!(!exited.load(Ordering::Acquire));
+    scheduler.schedule_out(State::Dead, &mut tf);
+    assert!(exited.load(Ordering::Acquire));
+    assert!(scheduler.find(first).is_some());
+
+    while scheduler.switch_to(&mut tf) != Some(0) {
+        scheduler.schedule_out(State::Ready, &mut tf);
+    }
+    assert_eq!(scheduler.kill(&mut tf), Some(0));
+    assert_eq!(queue(&scheduler), vec![1]);
+}
+
+/// Counts the live copies of a resource attached to processes.
+struct Counted(Arc<AtomicUsize>);
+
+impl Counted {
+    fn new(live: &Arc<AtomicUsize>) -> Counted {
+        live.fetch_add(1, Ordering::Relaxed);
+        Counted(live.clone())
+    }
+}
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn exiting_or_killed_processes_release_their_resources() {
+    let (mut scheduler, _) = scheduler(5);
+    let live = Arc::new(AtomicUsize::new(0));
+    for p in scheduler.processes_mut() {
+        p.resources.attach("test", Counted::new(&live)).unwrap();
+    }
+    for pid in 3..5 {
+        scheduler.find_mut(pid).unwrap().group = 3;
+    }
+    let mut tf = TrapFrame::default();
+
+    assert_eq!(scheduler.switch_to(&mut tf), Some(0));
+    scheduler.schedule_out(State::Dead, &mut tf);
+    assert_eq!(live.load(Ordering::Relaxed), 4);
+
+    assert_eq!(scheduler.switch_to(&mut tf), Some(1));
+    assert_eq!(scheduler.kill(&mut tf), Some(1));
+    assert_eq!(live.load(Ordering::Relaxed), 3);
+
+    assert!(!scheduler.kill_group(3, &tf));
+    assert_eq!(queue(&scheduler), vec![2]);
+    assert_eq!(live.load(Ordering::Relaxed), 1);
+
+    // A dead leader's threads are reaped with whatever they hold.
+    let entry = VirtualAddr::from(USER_IMG_BASE);
+    let stack = VirtualAddr::from(USER_IMG_BASE + PAGE_SIZE);
+    assert_eq!(scheduler.switch_to(&mut tf), Some(2));
+    let thread = scheduler.spawn_thread(&tf, entry, stack, 0).unwrap();
+    let p = scheduler.find_mut(thread).unwrap();
+    p.resources.attach("test", Counted::new(&live)).unwrap();
+    assert_eq!(live.load(Ordering::Relaxed), 2);
+    assert_eq!(scheduler.kill(&mut tf), Some(2));
+    assert_eq!(scheduler.processes().count(), 0);
+    assert_eq!(live.load(Ordering::Relaxed), 0);
+}
@@ -1,40 +1,67 @@
-use core::alloc::{GlobalAlloc, Layout};
+use alloc::alloc::{alloc, dealloc};
+use core::alloc::Layout;
 use core::fmt;
 use core::ptr::Unique;
 
+use crate::param::PAGE_SIZE;
 use crate::vm::PhysicalAddr;
-use crate::ALLOCATOR;
+use crate::VMM;
 
-/// A process stack. The default size is 1MiB with an alignment of 16 bytes.
+/// A process stack. The default size is 1MiB, aligned to a page.
+///
+/// The lowest page of the stack is left unused so that `install_guard()` can
+/// unmap it: a kernel thread that overflows its stack then faults. The lowest
+/// word above it holds a random canary, which `canary_intact()` checks, for
+/// overflows of stacks without a guard page.
 pub struct Stack {
     ptr: Unique<[u8; Stack::SIZE]>,
+    canary: u64,
+    guarded: bool,
 }
 
 impl Stack {
     /// The default stack size is 1MiB.
     pub const SIZE: usize = 1 << 20;
 
-    /// The default stack alignment is 16 bytes.
-    pub const ALIGN: usize = 16;
+    /// The default stack alignment is a page, for the guard page.
+    pub const ALIGN: usize = PAGE_SIZE;
 
     /// The default layout for a stack.
     fn layout() -> Layout {
         unsafe { Layout::from_size_align_unchecked(Self::SIZE, Self::ALIGN) }
     }
 
-    /// Returns a newly allocated process stack, zeroed out, if one could be
-    /// successfully allocated. If there is no memory, or memory allocation
+    /// Returns a newly allocated process stack, zeroed out but for the canary,
+    /// if one could be successfully allocated. If there is no memory, or memory allocation
     /// fails for some other reason, returns `None`.
     pub fn new() -> Option<Stack> {
-        let raw_ptr = unsafe {
-            let raw_ptr: *mut u8 = ALLOCATOR.alloc(Stack::layout());
-            assert!(!raw_ptr.is_null());
-            raw_ptr.write_bytes(0, Self::SIZE);
-            raw_ptr
-        };
+        let raw_ptr: *mut u8 = unsafe { alloc(Stack::layout()) };
+        let ptr = Unique::new(raw_ptr as *mut _)?;
+        unsafe { raw_ptr.write_bytes(0, Self::SIZE) };
+        let canary = random_canary();
+        unsafe { (raw_ptr.add(PAGE_SIZE) as *mut u64).write_volatile(canary) };
+        Some(Stack { ptr, canary, guarded: false })
+    }
+
+    /// Returns `false` if the canary at the bottom of the stack has been
+    /// overwritten.
+    pub fn canary_intact(&self) -> bool {
+        unsafe { (self.as_mut_ptr().add(PAGE_SIZE) as *const u64).read_volatile() == self.canary }
+    }
 
-        let ptr = Unique::new(raw_ptr as *mut _).expect("non-null");
-        Some(Stack { ptr })
+    /// Returns the physical address of the guard page at the bottom of the
+    /// stack.
+    pub fn guard_page(&self) -> PhysicalAddr {
+        self.bottom()
+    }
+
+    /// Unmaps the guard page from the kernel's identity map. It is mapped
+    /// again when the stack is dropped.
+    ///
+    /// The caller should assure that `VMM` has been initialized.
+    pub fn install_guard(&mut self) {
+        VMM.guard(self.guard_page());
+        self.guarded = true;
     }
 
     /// Internal method to cast to a `*mut u8`.
@@ -53,9 +80,23 @@ impl Stack {
     }
 }
 
+/// Returns a canary value from the hardware random number generator. Host
+/// tests have no generator and use a fixed value.
+fn random_canary() -> u64 {
+    #[cfg(not(test))]
+    let canary = pi::rng::Rng::new().next_u64();
+    #[cfg(test)]
+    let canary = 0x5afe_57ac_c0de_f00d;
+    // A zero byte at the bottom stops string overflows short of the rest.
+    canary & !0xff
+}
+
 impl Drop for Stack {
     fn drop(&mut self) {
-        unsafe { ALLOCATOR.dealloc(self.as_mut_ptr(), Self::layout()) }
+        if self.guarded {
+            VMM.unguard(self.guard_page());
+        }
+        unsafe { dealloc(self.as_mut_ptr(), Self::layout()) }
     }
 }
 
@@ -65,6 +106,7 @@ impl fmt::Debug for Stack {
             .field("top", &self.top())
             .field("bottom", &self.bottom())
             .field("size", &Self::SIZE)
+            .field("canary_intact", &self.canary_intact())
             .finish()
     }
 }
@@ -0,0 +1,163 @@
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use kernel_api::{OsError, OsResult};
+
+use crate::param::MAX_TIMERS;
+
+/// An interval timer. It expires at `next`, if armed, and then every
+/// `interval` after that unless `interval` is zero.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+struct IntervalTimer {
+    next: Option<Duration>,
+    interval: Duration,
+}
+
+/// A process's interval timers, named by small integers as file descriptors
+/// are.
+///
+/// Timers are not driven by interrupts: expirations are counted from the
+/// current time whenever a timer is read, so an armed timer costs nothing
+/// until its owner waits on it.
+#[derive(Debug, Clone, Default)]
+pub struct Timers {
+    timers: Vec<Option<IntervalTimer>>,
+}
+
+impl Timers {
+    /// Returns a table without timers.
+    pub fn new() -> Timers {
+        Timers { timers: Vec::new() }
+    }
+
+    /// Creates a disarmed timer with the lowest free ID and returns the ID.
+    ///
+    /// Returns `NoMemory` if all `MAX_TIMERS` timers are in use.
+    pub fn create(&mut self) -> OsResult<usize> {
+        let timer = Some(IntervalTimer::default());
+        match self.timers.iter().position(Option::is_none) {
+            Some(id) => {
+                self.timers[id] = timer;
+                Ok(id)
+            }
+            None if self.timers.len() < MAX_TIMERS => {
+                self.timers.push(timer);
+                Ok(self.timers.len() - 1)
+            }
+            None => Err(OsError::NoMemory),
+        }
+    }
+
+    /// Removes timer `id`.
+    pub fn delete(&mut self, id: usize) -> OsResult<()> {
+        match self.timers.get_mut(id).and_then(Option::take) {
+            Some(_) => Ok(()),
+            None => Err(OsError::InvalidArgument),
+        }
+    }
+
+    /// Arms timer `id` to expire `initial` after `now` and then every
+    /// `interval`, or only once if `interval` is zero. Expirations that were
+    /// not yet read are discarded.
+    pub fn arm(
+        &mut self,
+        id: usize,
+        now: Duration,
+        initial: Duration,
+        interval: Duration,
+    ) -> OsResult<()> {
+        let timer = self.get_mut(id)?;
+        timer.next = Some(now + initial);
+        timer.interval = interval;
+        Ok(())
+    }
+
+    /// Disarms timer `id`, discarding expirations that were not yet read.
+    pub fn disarm(&mut self, id: usize) -> OsResult<()> {
+        self.get_mut(id)?.next = None;
+        Ok(())
+    }
+
+    /// Returns whether timer `id` is armed or has expirations to be read.
+    pub fn is_armed(&self, id: usize) -> OsResult<bool> {
+        match self.timers.get(id) {
+            Some(Some(timer)) => Ok(timer.next.is_some()),
+            _ => Err(OsError::InvalidArgument),
+        }
+    }
+
+    /// Returns the number of times timer `id` has expired by `now` since it
+    /// was armed or last read. A one-shot timer is disarmed once read.
+    pub fn expirations(&mut self, id: usize, now: Duration) -> OsResult<u64> {
+        let timer = self.get_mut(id)?;
+        let next = match timer.next {
+            Some(next) if next <= now => next,
+            _ => return Ok(0),
+        };
+        if timer.interval == Duration::from_secs(0) {
+            timer.next = None;
+            return Ok(1);
+        }
+        let interval = timer.interval.as_nanos();
+        let expired = (now - next).as_nanos() / interval + 1;
+        timer.next = Some(next + Duration::from_nanos((expired * interval) as u64));
+        Ok(expired as u64)
+    }
+
+    fn get_mut(&mut self, id: usize) -> OsResult<&mut IntervalTimer> {
+        match self.timers.get_mut(id) {
+            Some(Some(timer)) => Ok(timer),
+            _ => Err(OsError::InvalidArgument),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn allocates_lowest_free_id() {
+        let mut timers = Timers::new();
+        assert_eq!(timers.create(), Ok(0));
+        assert_eq!(timers.create(), Ok(1));
+        assert_eq!(timers.delete(0), Ok(()));
+        assert_eq!(timers.delete(0), Err(OsError::InvalidArgument));
+        assert_eq!(timers.create(), Ok(0));
+        for id in 2..MAX_TIMERS {
+            assert_eq!(timers.create(), Ok(id));
+        }
+        assert_eq!(timers.create(), Err(OsError::NoMemory));
+    }
+
+    #[test]
+    fn one_shot_expires_once() {
+        let mut timers = Timers::new();
+        let id = timers.create().unwrap();
+        assert_eq!(timers.expirations(id, ms(100)), Ok(0));
+        timers.arm(id, ms(100), ms(50), ms(0)).unwrap();
+        assert_eq!(timers.expirations(id, ms(149)), Ok(0));
+        assert_eq!(timers.expirations(id, ms(500)), Ok(1));
+        assert_eq!(timers.is_armed(id), Ok(false));
+        assert_eq!(timers.expirations(id, ms(1000)), Ok(0));
+    }
+
+    #[test]
+    fn periodic_counts_missed_expirations() {
+        let mut timers = Timers::new();
+        let id = timers.create().unwrap();
+        timers.arm(id, ms(0), ms(10), ms(10)).unwrap();
+        assert_eq!(timers.expirations(id, ms(10)), Ok(1));
+        assert_eq!(timers.expirations(id, ms(19)), Ok(0));
+        assert_eq!(timers.expirations(id, ms(55)), Ok(4));
+        assert_eq!(timers.expirations(id, ms(60)), Ok(1));
+
+        timers.disarm(id).unwrap();
+        assert_eq!(timers.expirations(id, ms(1000)), Ok(0));
+        assert_eq!(timers.arm(7, ms(0), ms(1), ms(1)), Err(OsError::InvalidArgument));
+    }
+}
@@ -0,0 +1,179 @@
+use alloc::vec::Vec;
+use core::time::Duration;
+
+/// The time one slot of the lowest level spans.
+pub const RESOLUTION: Duration = Duration::from_millis(1);
+
+/// Each level has `1 << SLOT_BITS` slots.
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+const LEVELS: usize = 4;
+
+/// Hierarchical timing wheels: items that are due at some time, handed back
+/// in batches once the time has come.
+///
+/// Level `l` has 64 slots of `64^l` ticks of `RESOLUTION` each, so it holds
+/// items due in under `64^(l+1)` ticks:
+///
+/// | level | slot   | holds delays under |
+/// |-------|--------|--------------------|
+/// | 0     | 1 ms   | 64 ms              |
+/// | 1     | 64 ms  | 4.1 s              |
+/// | 2     | 4.1 s  | 4.4 min            |
+/// | 3     | 4.4 min| 4.7 h              |
+///
+/// Inserting an item and handing it back are O(1). When the lowest level
+/// comes round to the start of a slot of a higher level, the items in that
+/// slot are moved down (cascaded), so each item moves at most once per level
+/// and every level is as accurate as the lowest: an item is handed back by
+/// the first `advance()` to a time at or past its due time rounded up to
+/// `RESOLUTION`. It is never early, and late by no more than the time
+/// between calls to `advance()`. Items due beyond the top level's range are
+/// parked in its last slot and placed again when that slot comes round.
+#[derive(Debug)]
+pub struct TimerWheel<T> {
+    /// `LEVELS` levels of `SLOTS` slots, each holding items with the tick
+    /// they are due at.
+    slots: Vec<Vec<(u64, T)>>,
+    /// The last tick handed out; everything due by then has been.
+    now: u64,
+    len: usize,
+}
+
+impl<T> TimerWheel<T> {
+    /// Returns empty wheels starting at time zero.
+    pub fn new() -> TimerWheel<T> {
+        let mut slots = Vec::with_capacity(LEVELS * SLOTS);
+        slots.resize_with(LEVELS * SLOTS, Vec::new);
+        TimerWheel { slots, now: 0, len: 0 }
+    }
+
+    /// Returns the number of items waiting.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Adds `item`, due at time `at`. An item due by the last time passed to
+    /// `advance()` is handed back by the next call.
+    pub fn insert(&mut self, at: Duration, item: T) {
+        let tick = core::cmp::max(ticks(at, true), self.now + 1);
+        self.place(tick, item);
+        self.len += 1;
+    }
+
+    /// Moves the wheels on to time `now` and returns the items that have
+    /// come due, in the order they fell due.
+    pub fn advance(&mut self, now: Duration) -> Vec<T> {
+        let target = ticks(now, false);
+        let mut due = Vec::new();
+        while self.now < target {
+            if self.len == 0 {
+                self.now = target;
+                break;
+            }
+            self.now += 1;
+            let tick = self.now;
+            // Higher levels first, so that an item moved down lands in a
+            // slot cascaded or handed out during this same tick.
+            for level in (1..LEVELS).rev() {
+                if tick & ((1 << (SLOT_BITS * level as u32)) - 1) == 0 {
+                    let slot = core::mem::replace(&mut self.slots[index(level, tick)], Vec::new());
+                    for (at, item) in slot {
+                        self.place(at, item);
+                    }
+                }
+            }
+            let slot = core::mem::replace(&mut self.slots[index(0, tick)], Vec::new());
+            for (at, item) in slot {
+                if at <= tick {
+                    due.push(item);
+                    self.len -= 1;
+                } else {
+                    // Parked beyond the top level's range.
+                    self.place(at, item);
+                }
+            }
+        }
+        due
+    }
+
+    /// Puts `item`, due at `tick`, in the lowest level whose range reaches
+    /// it, or parks it at the end of the top level.
+    fn place(&mut self, tick: u64, item: T) {
+        let delta = tick.saturating_sub(self.now);
+        let mut level = 0;
+        while level + 1 < LEVELS && delta >= 1 << (SLOT_BITS * (level as u32 + 1)) {
+            level += 1;
+        }
+        let last = self.now + (1 << (SLOT_BITS * LEVELS as u32)) - 1;
+        let slot = index(level, core::cmp::min(tick, last));
+        self.slots[slot].push((tick, item));
+    }
+}
+
+/// Returns the index of the slot of level `level` that holds `tick`.
+fn index(level: usize, tick: u64) -> usize {
+    level * SLOTS + ((tick >> (SLOT_BITS * level as u32)) as usize & (SLOTS - 1))
+}
+
+/// Converts `time` to ticks of `RESOLUTION`, rounding up if `up` is set and
+/// down otherwise.
+fn ticks(time: Duration, up: bool) -> u64 {
+    let resolution = RESOLUTION.as_nanos();
+    let round = if up { resolution - 1 } else { 0 };
+    ((time.as_nanos() + round) / resolution) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn hands_items_back_when_due() {
+        let mut wheel = TimerWheel::new();
+        wheel.insert(ms(30), 'b');
+        wheel.insert(ms(10), 'a');
+        wheel.insert(ms(30), 'c');
+        assert_eq!(wheel.advance(ms(9)), vec![]);
+        assert_eq!(wheel.advance(ms(10)), vec!['a']);
+        assert_eq!(wheel.advance(ms(100)), vec!['b', 'c']);
+        assert_eq!(wheel.len(), 0);
+
+        // Items already due come back on the next call; fractions of a
+        // tick round up.
+        wheel.insert(ms(50), 'd');
+        wheel.insert(Duration::from_micros(100_500), 'e');
+        assert_eq!(wheel.advance(ms(100)), vec!['d']);
+        assert_eq!(wheel.advance(ms(101)), vec!['e']);
+    }
+
+    #[test]
+    fn cascades_without_losing_accuracy() {
+        let mut wheel = TimerWheel::new();
+        let due = [63, 64, 65, 4095, 4096, 4097, 300_000, 262_144];
+        for &at in due.iter() {
+            wheel.insert(ms(at), at);
+        }
+        let mut fired = Vec::new();
+        for now in 0..=300_000 {
+            for at in wheel.advance(ms(now)) {
+                assert_eq!(at, now);
+                fired.push(at);
+            }
+        }
+        assert_eq!(fired, vec![63, 64, 65, 4095, 4096, 4097, 262_144, 300_000]);
+    }
+
+    #[test]
+    fn parks_items_beyond_range() {
+        let mut wheel = TimerWheel::new();
+        let far = 1 << 25;
+        wheel.insert(ms(far), ());
+        assert_eq!(wheel.advance(ms(far - 1)).len(), 0);
+        assert_eq!(wheel.advance(ms(far)).len(), 1);
+    }
+}
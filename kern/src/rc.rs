@@ -0,0 +1,245 @@
+//! Programs started at boot, listed in `RC_FILE`.
+//!
+//! Each line of the file names a program and its arguments, optionally
+//! preceded by its restart policy:
+//!
+//! ```text
+//! # <policy> <path> [args...]
+//! respawn /bin/netd -p 80
+//! once /bin/logger
+//! /bin/shell
+//! ```
+//!
+//! `once`, the default, starts the program once; `respawn` starts it again
+//! whenever it exits. A program that keeps exiting right after it starts is
+//! given up on, so that a broken one does not take over the CPU. The programs
+//! run with the init processes' capabilities, `init_caps` in the config file.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use fat32::traits::FileSystem;
+use pi::timer::current_time;
+use shim::io;
+
+use crate::config;
+use crate::fs;
+use crate::logger::{info, warn};
+use crate::mutex::Mutex;
+use crate::process::Process;
+use crate::{FILESYSTEM, SCHEDULER};
+
+/// The file listing the programs started at boot.
+pub const RC_FILE: &str = "/etc/rc";
+
+/// How often the supervisor checks whether respawned programs exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A program that exits this soon after it starts has failed to start.
+const FAST_EXIT: Duration = Duration::from_secs(1);
+
+/// The failed starts in a row after which a program is no longer respawned.
+const MAX_FAST_EXITS: u32 = 5;
+
+/// What to do when a program exits.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Restart {
+    Once,
+    Respawn,
+}
+
+/// A program listed in `RC_FILE`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Service {
+    pub restart: Restart,
+    pub path: String,
+    /// The program's arguments, starting with its path.
+    pub args: Vec<String>,
+}
+
+/// Parses the contents of an rc file. Invalid lines are skipped; a
+/// description of each is returned along with the services.
+pub fn parse(text: &str) -> (Vec<Service>, Vec<String>) {
+    let mut services = Vec::new();
+    let mut warnings = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words: Vec<&str> = line.split_whitespace().collect();
+        let restart = match words[0] {
+            "once" => Some(Restart::Once),
+            "respawn" => Some(Restart::Respawn),
+            _ => None,
+        };
+        if restart.is_some() {
+            words.remove(0);
+        }
+        if words.is_empty() {
+            warnings.push(format!("line {}: expected a program", number + 1));
+            continue;
+        }
+        let args: Vec<String> = words.iter().map(|word| word.to_string()).collect();
+        let restart = restart.unwrap_or(Restart::Once);
+        services.push(Service { restart, path: args[0].clone(), args });
+    }
+    (services, warnings)
+}
+
+/// A respawned program the supervisor watches.
+struct Running {
+    service: Service,
+    /// Set once the current instance exits.
+    exited: Arc<AtomicBool>,
+    /// When the current instance started.
+    started: Duration,
+    fast_exits: u32,
+}
+
+/// The respawned programs.
+static RUNNING: Mutex<Vec<Running>> = Mutex::new(Vec::new());
+
+/// Starts the programs listed in `RC_FILE`, if it exists, logging those that
+/// cannot be started. Starts the supervisor thread if any is respawned.
+///
+/// The caller should assure that `FILESYSTEM` and `SCHEDULER` have been
+/// initialized.
+pub fn start() {
+    let text = match read(RC_FILE) {
+        Ok(text) => text,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return,
+        Err(e) => {
+            warn!("rc: cannot read {}: {:?}", RC_FILE, e);
+            return;
+        }
+    };
+    let (services, warnings) = parse(&text);
+    for warning in warnings {
+        warn!("rc: {}: {}", RC_FILE, warning);
+    }
+    let mut running = RUNNING.lock();
+    for service in services {
+        let exited = match spawn(&service) {
+            Some(exited) => exited,
+            None => continue,
+        };
+        if service.restart == Restart::Respawn {
+            running.push(Running { service, exited, started: current_time(), fast_exits: 0 });
+        }
+    }
+    if running.is_empty() {
+        return;
+    }
+    drop(running);
+    match Process::kernel_thread(supervisor_thread) {
+        Ok(thread) => {
+            SCHEDULER.add(thread);
+        }
+        Err(e) => warn!("rc: cannot start the supervisor: {:?}", e),
+    }
+}
+
+/// Starts `service`. Returns the flag set when it exits, or `None`, after
+/// logging why, if it cannot be started.
+fn spawn(service: &Service) -> Option<Arc<AtomicBool>> {
+    let args: Vec<&str> = service.args.iter().map(|arg| arg.as_str()).collect();
+    let mut process = match Process::load_with_args(&service.path, &args) {
+        Ok(process) => process,
+        Err(e) => {
+            warn!("rc: cannot load {}: {:?}", service.path, e);
+            return None;
+        }
+    };
+    process.caps = config::get().init_caps;
+    let exited = process.exit.watch();
+    let id = match SCHEDULER.add(process) {
+        Some(id) => id,
+        None => {
+            warn!("rc: no process ID for {}", service.path);
+            return None;
+        }
+    };
+    info!("rc: started {} as process {}", service.path, id);
+    Some(exited)
+}
+
+extern "C" fn supervisor_thread() -> ! {
+    loop {
+        let _ = kernel_api::syscall::sleep(POLL_INTERVAL);
+        let now = current_time();
+        let mut list = RUNNING.lock();
+        let mut i = 0;
+        while i < list.len() {
+            let running = &mut list[i];
+            if !running.exited.load(Ordering::Relaxed) {
+                i += 1;
+                continue;
+            }
+            if now - running.started < FAST_EXIT {
+                running.fast_exits += 1;
+            } else {
+                running.fast_exits = 0;
+            }
+            if running.fast_exits >= MAX_FAST_EXITS {
+                warn!("rc: {} keeps exiting; not respawning it", running.service.path);
+                list.remove(i);
+                continue;
+            }
+            info!("rc: {} exited; respawning it", running.service.path);
+            match spawn(&running.service) {
+                Some(exited) => {
+                    running.exited = exited;
+                    running.started = current_time();
+                    i += 1;
+                }
+                None => {
+                    list.remove(i);
+                }
+            }
+        }
+    }
+}
+
+fn read(path: &str) -> io::Result<String> {
+    let mut file = FILESYSTEM.open_file(path)?;
+    let mut bytes = Vec::new();
+    fs::read_to_end(&mut file, &mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "not UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(restart: Restart, args: &[&str]) -> Service {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Service { restart, path: args[0].clone(), args }
+    }
+
+    #[test]
+    fn parses_services() {
+        let (services, warnings) =
+            parse("# daemons\n\nrespawn /bin/netd -p 80\nonce /bin/logger\n  /bin/shell  \n");
+        assert!(warnings.is_empty());
+        assert_eq!(
+            services,
+            [
+                service(Restart::Respawn, &["/bin/netd", "-p", "80"]),
+                service(Restart::Once, &["/bin/logger"]),
+                service(Restart::Once, &["/bin/shell"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_missing_programs() {
+        let (services, warnings) = parse("respawn\nonce   \n/fib.bin\n");
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(services, [service(Restart::Once, &["/fib.bin"])]);
+    }
+}
@@ -0,0 +1,215 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use fat32::traits::{Dir, Entry, File, FileSystem};
+use kernel_api::syscall;
+
+use crate::cmdline::Param;
+use crate::console::{kprintln, CONSOLE};
+use crate::fs;
+use crate::process::Process;
+use crate::{FILESYSTEM, SCHEDULER};
+
+/// The outcome of a single test.
+enum Outcome {
+    Pass,
+    Fail(&'static str),
+    Skip(&'static str),
+}
+
+use self::Outcome::*;
+
+/// Fails with `message` unless `condition` holds.
+fn check(condition: bool, message: &'static str) -> Result<(), Outcome> {
+    if condition {
+        Ok(())
+    } else {
+        Err(Fail(message))
+    }
+}
+
+/// Returns `Pass` if `test` finishes, and the outcome it stops with
+/// otherwise.
+fn outcome(test: fn() -> Result<(), Outcome>) -> Outcome {
+    match test() {
+        Ok(()) => Pass,
+        Err(outcome) => outcome,
+    }
+}
+
+const TESTS: &[(&str, fn() -> Result<(), Outcome>)] = &[
+    ("allocator: small and large allocations", allocations),
+    ("allocator: page-aligned allocation", aligned_allocation),
+    ("fs: list the root directory", list_root),
+    ("fs: read a file", read_file),
+    ("process: load /fib.bin", load_process),
+    ("process: spawn and schedule a thread", spawn_thread),
+    ("syscall: time is monotonic", time),
+    ("syscall: sleep", sleep),
+    ("syscall: getpid", getpid),
+];
+
+/// The self-test suite's kernel parameters; see `cmdline`.
+pub static PARAMS: &[Param] = &[Param {
+    name: "selftest",
+    description: "run the self-test suite and exit QEMU with its result",
+    set: set_selftest,
+}];
+
+/// Whether `selftest` is on the kernel command line.
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+fn set_selftest(_name: &str, value: &str) -> Result<(), String> {
+    if !value.is_empty() {
+        return Err(String::from("takes no value"));
+    }
+    REQUESTED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Returns `true` if the self-test suite should run: always in `qemu`
+/// builds, and otherwise when `selftest` is on the kernel command line. It
+/// runs next to a normal boot, init processes included, and ends it.
+///
+/// The caller should assure that `cmdline::apply()` has been called.
+pub fn enabled() -> bool {
+    cfg!(feature = "qemu") || REQUESTED.load(Ordering::Relaxed)
+}
+
+/// Adds a kernel thread that runs the self-test suite, printing the results
+/// to the console in TAP format, and then exits QEMU with status 0 if every
+/// test passed and 1 otherwise.
+///
+/// The caller should assure that `SCHEDULER` has been initialized.
+pub fn spawn() {
+    let thread = Process::kernel_thread(run).expect("self-test thread");
+    SCHEDULER.add(thread).expect("self-test thread");
+}
+
+extern "C" fn run() -> ! {
+    kprintln!("TAP version 13");
+    kprintln!("1..{}", TESTS.len());
+    let mut failed = 0;
+    for (number, &(name, test)) in TESTS.iter().enumerate() {
+        match outcome(test) {
+            Pass => kprintln!("ok {} - {}", number + 1, name),
+            Skip(reason) => kprintln!("ok {} - {} # SKIP {}", number + 1, name, reason),
+            Fail(message) => {
+                failed += 1;
+                kprintln!("not ok {} - {}", number + 1, name);
+                kprintln!("  ---");
+                kprintln!("  message: {}", message);
+                kprintln!("  ...");
+            }
+        }
+    }
+    kprintln!("# {} of {} tests failed", failed, TESTS.len());
+    exit(if failed == 0 { 0 } else { 1 })
+}
+
+/// The semihosting `SYS_EXIT` operation and its reason code for a normal
+/// application exit.
+const SYS_EXIT: u64 = 0x18;
+const ADP_STOPPED_APPLICATION_EXIT: u64 = 0x20026;
+
+/// Exits QEMU with status `code` through semihosting. Outside of `qemu`
+/// builds semihosting may be unavailable, so the kernel halts instead.
+fn exit(code: u64) -> ! {
+    CONSOLE.lock().flush();
+    if cfg!(feature = "qemu") {
+        let block = [ADP_STOPPED_APPLICATION_EXIT, code];
+        unsafe { aarch64::semihosting_call(SYS_EXIT, block.as_ptr() as u64) };
+    }
+    kprintln!("self-test finished with status {}; halting", code);
+    loop {
+        aarch64::wfe();
+    }
+}
+
+fn allocations() -> Result<(), Outcome> {
+    let small: Vec<u64> = (0..64).collect();
+    check(small.iter().sum::<u64>() == 63 * 64 / 2, "small vector corrupted")?;
+    let mut large = Vec::new();
+    large.resize(1 << 20, 0xA5u8);
+    check(large.iter().all(|&b| b == 0xA5), "large vector corrupted")?;
+    drop(large);
+    let boxes: Vec<Box<[u8; 512]>> = (0..32).map(|i| Box::new([i as u8; 512])).collect();
+    check(boxes.iter().enumerate().all(|(i, b)| b[511] == i as u8), "boxes corrupted")
+}
+
+fn aligned_allocation() -> Result<(), Outcome> {
+    let layout = Layout::from_size_align(4096, 4096).unwrap();
+    let ptr = unsafe { alloc::alloc::alloc(layout) };
+    check(!ptr.is_null(), "allocation failed")?;
+    let aligned = ptr as usize % 4096 == 0;
+    unsafe { alloc::alloc::dealloc(ptr, layout) };
+    check(aligned, "allocation is not page-aligned")
+}
+
+fn list_root() -> Result<(), Outcome> {
+    let root = FILESYSTEM.open_dir("/").map_err(|_| Fail("cannot open /"))?;
+    let entries = root.entries().map_err(|_| Fail("cannot read /"))?;
+    check(entries.count() > 0, "/ is empty")
+}
+
+fn read_file() -> Result<(), Outcome> {
+    let root = FILESYSTEM.open_dir("/").map_err(|_| Fail("cannot open /"))?;
+    let entries = root.entries().map_err(|_| Fail("cannot read /"))?;
+    let mut file = match entries.filter_map(|entry| entry.into_file()).find(|file| file.size() > 0) {
+        Some(file) => file,
+        None => return Err(Skip("no non-empty file in /")),
+    };
+    let mut contents = Vec::new();
+    fs::read_to_end(&mut file, &mut contents).map_err(|_| Fail("read failed"))?;
+    check(contents.len() as u64 == file.size(), "read returned the wrong length")
+}
+
+fn load_process() -> Result<(), Outcome> {
+    if FILESYSTEM.open("/fib.bin").is_err() {
+        return Err(Skip("no /fib.bin"));
+    }
+    let process = Process::load("/fib.bin").map_err(|_| Fail("load failed"))?;
+    check(process.context.elr == Process::get_image_base().as_u64(), "wrong entry point")
+}
+
+static CHILD_RAN: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn child() -> ! {
+    CHILD_RAN.store(true, Ordering::Relaxed);
+    syscall::exit()
+}
+
+fn spawn_thread() -> Result<(), Outcome> {
+    let thread = Process::kernel_thread(child).map_err(|_| Fail("cannot create thread"))?;
+    SCHEDULER.add(thread).ok_or(Fail("cannot add thread"))?;
+    for _ in 0..100 {
+        if CHILD_RAN.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let _ = syscall::sleep(Duration::from_millis(10));
+    }
+    Err(Fail("thread did not run within 1s"))
+}
+
+fn time() -> Result<(), Outcome> {
+    let first = syscall::time();
+    let second = syscall::time();
+    check(second >= first, "time went backwards")
+}
+
+fn sleep() -> Result<(), Outcome> {
+    let start = syscall::time();
+    let elapsed = syscall::sleep(Duration::from_millis(20)).map_err(|_| Fail("sleep failed"))?;
+    check(elapsed >= Duration::from_millis(20), "reported elapsed time too short")?;
+    check(syscall::time() - start >= Duration::from_millis(20), "woke up too early")
+}
+
+fn getpid() -> Result<(), Outcome> {
+    let pid = syscall::getpid();
+    let running = SCHEDULER.critical(|scheduler| scheduler.find(pid).is_some());
+    check(running, "getpid returned an unknown process")
+}
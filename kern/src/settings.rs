@@ -0,0 +1,525 @@
+//! Persistent settings: a small key-value store that survives reboots and
+//! power loss.
+//!
+//! The store lives in `SETTINGS_FILE`, whose sectors are written directly,
+//! like crash dumps. The file is split into two areas. Each area is a log of
+//! records, one per sector, each carrying the area's generation and a CRC.
+//! Changes are appended to the active area, so a torn write loses at most the
+//! record being written. When the active area fills up, the live settings are
+//! written to the other area under the next generation and committed with a
+//! final `Commit` record. Until that record is on the card, the old area
+//! stays current.
+//!
+//! At boot the committed area with the highest generation is replayed, up to
+//! the first sector that is not a valid record of that generation.
+//!
+//! Known keys:
+//!
+//!   * `hostname`: the system's name, shown in the shell prompt and returned
+//!     by `sys_gethostname`; `DEFAULT_HOSTNAME` if unset
+//!   * `console.baud`: the console's baud rate, applied at boot; `console=`
+//!     on the command line or in `CONFIG_FILE` takes precedence
+//!   * `console.rows`, `console.cols`: the size of the terminal on the
+//!     console, for `more`; asked of the terminal if either is unset
+//!   * `history.size`: the most shell history entries kept in
+//!     `/.history`; 100 if unset
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use checksum::crc32;
+use fat32::traits::{BlockDevice, Entry, FileSystem};
+use kernel_api::HOST_NAME_MAX;
+use shim::io;
+use shim::ioerr;
+
+use crate::console::CONSOLE;
+use crate::fs::Sd;
+use crate::logger::{info, warn};
+use crate::mutex::Mutex;
+use crate::FILESYSTEM;
+
+/// The file holding the store. It must be created with a fixed size of at
+/// least `4 * SECTOR_SIZE` bytes before boot.
+pub const SETTINGS_FILE: &str = "/settings.dat";
+
+/// The hostname used while `hostname` is unset.
+pub const DEFAULT_HOSTNAME: &str = "rustos";
+
+const SECTOR_SIZE: usize = 512;
+
+/// The longest key and value.
+pub const MAX_KEY: usize = 64;
+pub const MAX_VALUE: usize = 400;
+
+/// The first word of every record.
+const MAGIC: u32 = 0x5354_4731;
+
+/// The size of a record's header: magic, generation, kind, key length and
+/// value length.
+const HEADER_SIZE: usize = 12;
+
+/// Where a record's CRC is stored. It covers the bytes before it.
+const CRC_OFFSET: usize = SECTOR_SIZE - 4;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Kind {
+    Set = 1,
+    Delete = 2,
+    /// Ends the snapshot written when an area is started.
+    Commit = 3,
+}
+
+/// A decoded record.
+#[derive(Debug, PartialEq)]
+struct Record<'a> {
+    generation: u32,
+    kind: Kind,
+    key: &'a str,
+    value: &'a str,
+}
+
+impl<'a> Record<'a> {
+    /// Returns the sector holding `self`.
+    fn encode(&self) -> [u8; SECTOR_SIZE] {
+        let mut sector = [0; SECTOR_SIZE];
+        let (key, value) = (self.key.as_bytes(), self.value.as_bytes());
+        sector[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        sector[4..8].copy_from_slice(&self.generation.to_le_bytes());
+        sector[8] = self.kind as u8;
+        sector[9] = key.len() as u8;
+        sector[10..12].copy_from_slice(&(value.len() as u16).to_le_bytes());
+        sector[HEADER_SIZE..HEADER_SIZE + key.len()].copy_from_slice(key);
+        let start = HEADER_SIZE + key.len();
+        sector[start..start + value.len()].copy_from_slice(value);
+        let crc = crc32(&sector[..CRC_OFFSET]);
+        sector[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+        sector
+    }
+
+    /// Decodes the record in `sector`, or returns `None` if it holds none.
+    fn decode(sector: &'a [u8]) -> Option<Record<'a>> {
+        let word = |i: usize| {
+            u32::from_le_bytes([sector[i], sector[i + 1], sector[i + 2], sector[i + 3]])
+        };
+        if word(0) != MAGIC || word(CRC_OFFSET) != crc32(&sector[..CRC_OFFSET]) {
+            return None;
+        }
+        let kind = match sector[8] {
+            1 => Kind::Set,
+            2 => Kind::Delete,
+            3 => Kind::Commit,
+            _ => return None,
+        };
+        let key_len = sector[9] as usize;
+        let value_len = u16::from_le_bytes([sector[10], sector[11]]) as usize;
+        if key_len > MAX_KEY || value_len > MAX_VALUE {
+            return None;
+        }
+        let key = &sector[HEADER_SIZE..HEADER_SIZE + key_len];
+        let value = &sector[HEADER_SIZE + key_len..HEADER_SIZE + key_len + value_len];
+        Some(Record {
+            generation: word(4),
+            kind,
+            key: core::str::from_utf8(key).ok()?,
+            value: core::str::from_utf8(value).ok()?,
+        })
+    }
+}
+
+/// The state of an area found by `Store::replay()`.
+struct Replay {
+    area: usize,
+    generation: u32,
+    values: Vec<(String, String)>,
+    /// The index within the area of the next record.
+    next: usize,
+}
+
+/// A settings store in the sectors `sectors` of `device`.
+pub struct Store<D: BlockDevice> {
+    device: D,
+    sectors: Vec<u64>,
+    /// The live settings, in the order they were first set.
+    values: Vec<(String, String)>,
+    /// The area appended to, 0 or 1.
+    area: usize,
+    generation: u32,
+    /// The index within the area of the next record.
+    next: usize,
+}
+
+impl<D: BlockDevice> Store<D> {
+    /// Opens the store in `sectors`, starting an empty one if neither area
+    /// holds a committed store.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidInput` if there are fewer than four sectors, and any
+    /// error of the device.
+    pub fn open(device: D, sectors: Vec<u64>) -> io::Result<Store<D>> {
+        if sectors.len() < 4 {
+            return ioerr!(InvalidInput, "settings store needs at least four sectors");
+        }
+        let mut store =
+            Store { device, sectors, values: Vec::new(), area: 0, generation: 0, next: 0 };
+        let mut best: Option<Replay> = None;
+        for area in 0..2 {
+            if let Some(replay) = store.replay(area)? {
+                match best {
+                    Some(ref best) if best.generation >= replay.generation => (),
+                    _ => best = Some(replay),
+                }
+            }
+        }
+        match best {
+            Some(replay) => {
+                store.area = replay.area;
+                store.generation = replay.generation;
+                store.values = replay.values;
+                store.next = replay.next;
+            }
+            None => store.start_area(0, 1)?,
+        }
+        Ok(store)
+    }
+
+    /// Returns the number of records an area holds.
+    fn area_len(&self) -> usize {
+        self.sectors.len() / 2
+    }
+
+    /// Returns the sector of record `i` of `area`.
+    fn sector(&self, area: usize, i: usize) -> u64 {
+        self.sectors[area * self.area_len() + i]
+    }
+
+    /// Replays the records of `area`, or returns `None` if it holds no
+    /// committed store.
+    fn replay(&mut self, area: usize) -> io::Result<Option<Replay>> {
+        let mut buf = [0; SECTOR_SIZE];
+        let mut values = Vec::new();
+        let mut generation = None;
+        let mut committed = false;
+        let mut next = 0;
+        while next < self.area_len() {
+            let sector = self.sector(area, next);
+            self.device.read_sector(sector, &mut buf)?;
+            let record = match Record::decode(&buf) {
+                Some(record) if generation.map_or(true, |g| g == record.generation) => record,
+                _ => break,
+            };
+            generation = Some(record.generation);
+            match record.kind {
+                Kind::Set => apply_set(&mut values, record.key, record.value),
+                Kind::Delete => values.retain(|(key, _)| key != record.key),
+                Kind::Commit => committed = true,
+            }
+            next += 1;
+        }
+        Ok(match generation {
+            Some(generation) if committed => Some(Replay { area, generation, values, next }),
+            _ => None,
+        })
+    }
+
+    /// Writes the live settings to `area` under `generation`, followed by a
+    /// `Commit` record, and makes it the area appended to.
+    fn start_area(&mut self, area: usize, generation: u32) -> io::Result<()> {
+        if self.values.len() + 1 > self.area_len() {
+            return ioerr!(Other, "settings store full");
+        }
+        let mut i = 0;
+        let values = core::mem::take(&mut self.values);
+        let mut result = Ok(());
+        for (key, value) in values.iter() {
+            let record = Record { generation, kind: Kind::Set, key, value };
+            result = self.write_record(area, i, &record);
+            if result.is_err() {
+                break;
+            }
+            i += 1;
+        }
+        self.values = values;
+        result?;
+        let commit = Record { generation, kind: Kind::Commit, key: "", value: "" };
+        self.write_record(area, i, &commit)?;
+        self.area = area;
+        self.generation = generation;
+        self.next = i + 1;
+        Ok(())
+    }
+
+    fn write_record(&mut self, area: usize, i: usize, record: &Record) -> io::Result<()> {
+        let sector = self.sector(area, i);
+        self.device.write_sector(sector, &record.encode())?;
+        Ok(())
+    }
+
+    /// Appends a record of `kind` for `key` and `value` to the active area,
+    /// moving the live settings to the other area first if it is full.
+    fn append(&mut self, kind: Kind, key: &str, value: &str) -> io::Result<()> {
+        if self.next == self.area_len() {
+            self.start_area(1 - self.area, self.generation.wrapping_add(1))?;
+            if self.next == self.area_len() {
+                return ioerr!(Other, "settings store full");
+            }
+        }
+        let record = Record { generation: self.generation, kind, key, value };
+        self.write_record(self.area, self.next, &record)?;
+        self.next += 1;
+        Ok(())
+    }
+
+    /// Returns the value of `key`, if it is set.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.iter().find(|(k, _)| k == key).map(|(_, value)| value.as_str())
+    }
+
+    /// Returns every setting, in the order they were first set.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// Sets `key` to `value` and writes the change to the device.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidInput` if `key` is empty or longer than `MAX_KEY`
+    /// bytes or `value` is longer than `MAX_VALUE` bytes, and `Other` if the
+    /// store is full.
+    pub fn set(&mut self, key: &str, value: &str) -> io::Result<()> {
+        if key.is_empty() || key.len() > MAX_KEY || value.len() > MAX_VALUE {
+            return ioerr!(InvalidInput, "setting too long");
+        }
+        if self.get(key) == Some(value) {
+            return Ok(());
+        }
+        self.append(Kind::Set, key, value)?;
+        apply_set(&mut self.values, key, value);
+        Ok(())
+    }
+
+    /// Removes `key` and writes the change to the device. Returns `false` if
+    /// it was not set.
+    pub fn delete(&mut self, key: &str) -> io::Result<bool> {
+        if self.get(key).is_none() {
+            return Ok(false);
+        }
+        self.append(Kind::Delete, key, "")?;
+        self.values.retain(|(k, _)| k != key);
+        Ok(true)
+    }
+}
+
+/// Sets `key` to `value` in `values`.
+fn apply_set(values: &mut Vec<(String, String)>, key: &str, value: &str) {
+    match values.iter_mut().find(|(k, _)| k == key) {
+        Some((_, v)) => *v = String::from(value),
+        None => values.push((String::from(key), String::from(value))),
+    }
+}
+
+static STORE: Mutex<Option<Store<Sd>>> = Mutex::new(None);
+
+/// Opens the store in `SETTINGS_FILE` and applies `console.baud`. Settings
+/// are unavailable if the file is missing or too small.
+///
+/// The caller should assure that `FILESYSTEM` has been initialized.
+pub fn initialize() {
+    let sd = match FILESYSTEM.device() {
+        Some(sd) => sd,
+        None => return,
+    };
+    let sectors = (&FILESYSTEM)
+        .open(SETTINGS_FILE)
+        .ok()
+        .and_then(|entry| entry.into_file())
+        .and_then(|file| file.sectors().ok());
+    let store = match sectors.map(|sectors| Store::open(sd, sectors)) {
+        Some(Ok(store)) => store,
+        Some(Err(e)) => return warn!("settings: cannot open {}: {:?}", SETTINGS_FILE, e),
+        None => return warn!("settings: no {}; settings are not saved", SETTINGS_FILE),
+    };
+    if let Some(baud) = store.get("console.baud") {
+        apply_baud(baud);
+    }
+    info!("settings: {} in {}", store.values.len(), SETTINGS_FILE);
+    *STORE.lock() = Some(store);
+}
+
+/// Sets the console's baud rate to `baud`.
+fn apply_baud(baud: &str) {
+    let mut console = CONSOLE.lock();
+    let mut config = console.uart_config();
+    match baud.parse() {
+        Ok(baud) => config.baud = baud,
+        Err(_) => return warn!("settings: invalid console.baud {}", baud),
+    }
+    if console.configure_uart(config).is_err() {
+        warn!("settings: unsupported console.baud {}", baud);
+    }
+}
+
+/// Returns an error if `value` is not valid for the known key `key`.
+fn validate(key: &str, value: &str) -> io::Result<()> {
+    match key {
+        "console.baud" if value.parse::<u32>().map_or(true, |baud| baud == 0) => {
+            ioerr!(InvalidInput, "console.baud must be a baud rate")
+        }
+        "console.rows" | "console.cols" if value.parse::<usize>().map_or(true, |n| n == 0) => {
+            ioerr!(InvalidInput, "console.rows and console.cols must be positive numbers")
+        }
+        "history.size" if value.parse::<usize>().is_err() => {
+            ioerr!(InvalidInput, "history.size must be a number")
+        }
+        "hostname" if value.is_empty() || value.contains(char::is_whitespace) => {
+            ioerr!(InvalidInput, "hostname must be one word")
+        }
+        "hostname" if value.len() > HOST_NAME_MAX => ioerr!(InvalidInput, "hostname too long"),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the system's hostname.
+pub fn hostname() -> String {
+    get("hostname").unwrap_or_else(|| String::from(DEFAULT_HOSTNAME))
+}
+
+/// Returns the value of setting `key`, if it is set.
+pub fn get(key: &str) -> Option<String> {
+    STORE.lock().as_ref()?.get(key).map(String::from)
+}
+
+/// Returns every setting, in the order they were first set.
+pub fn list() -> Vec<(String, String)> {
+    match STORE.lock().as_ref() {
+        Some(store) => store.values.clone(),
+        None => vec![],
+    }
+}
+
+/// Sets `key` to `value` and saves it. Known keys are validated.
+pub fn set(key: &str, value: &str) -> io::Result<()> {
+    validate(key, value)?;
+    match STORE.lock().as_mut() {
+        Some(store) => store.set(key, value),
+        None => ioerr!(NotFound, "settings store unavailable"),
+    }
+}
+
+/// Removes setting `key` and saves the change. Returns `false` if it was not
+/// set.
+pub fn delete(key: &str) -> io::Result<bool> {
+    match STORE.lock().as_mut() {
+        Some(store) => store.delete(key),
+        None => ioerr!(NotFound, "settings store unavailable"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A device of `n` sectors in memory.
+    struct MemDevice(Vec<[u8; SECTOR_SIZE]>);
+
+    impl BlockDevice for MemDevice {
+        fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+            buf[..SECTOR_SIZE].copy_from_slice(&self.0[n as usize]);
+            Ok(SECTOR_SIZE)
+        }
+
+        fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+            self.0[n as usize].copy_from_slice(&buf[..SECTOR_SIZE]);
+            Ok(SECTOR_SIZE)
+        }
+    }
+
+    fn reopen(store: Store<MemDevice>) -> Store<MemDevice> {
+        Store::open(store.device, store.sectors).expect("reopened")
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let record = Record { generation: 7, kind: Kind::Set, key: "hostname", value: "pi" };
+        let mut sector = record.encode();
+        assert_eq!(Record::decode(&sector), Some(record));
+        sector[20] ^= 1;
+        assert_eq!(Record::decode(&sector), None);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn settings_persist() {
+        let device = MemDevice(vec![[0; SECTOR_SIZE]; 8]);
+        let mut store = Store::open(device, (0..8).collect()).expect("opened");
+        store.set("hostname", "pi").unwrap();
+        store.set("console.baud", "115200").unwrap();
+        store.set("hostname", "raspberry").unwrap();
+        assert!(store.delete("console.baud").unwrap());
+        assert!(!store.delete("console.baud").unwrap());
+
+        let store = reopen(store);
+        assert_eq!(store.get("hostname"), Some("raspberry"));
+        assert_eq!(store.get("console.baud"), None);
+        assert_eq!(store.iter().count(), 1);
+    }
+
+    #[test]
+    fn full_area_moves_to_the_other() {
+        let device = MemDevice(vec![[0; SECTOR_SIZE]; 8]);
+        let mut store = Store::open(device, (0..8).collect()).expect("opened");
+        for i in 0..20 {
+            store.set("counter", &format!("{}", i)).unwrap();
+            store.set("hostname", "pi").unwrap();
+        }
+        assert!(store.generation > 1);
+        let store = reopen(store);
+        assert_eq!(store.get("counter"), Some("19"));
+        assert_eq!(store.get("hostname"), Some("pi"));
+    }
+
+    #[test]
+    fn torn_writes_keep_the_last_commit() {
+        let device = MemDevice(vec![[0; SECTOR_SIZE]; 8]);
+        let mut store = Store::open(device, (0..8).collect()).expect("opened");
+        store.set("a", "1").unwrap();
+        store.set("b", "2").unwrap();
+
+        // A torn append loses only that record.
+        store.set("c", "3").unwrap();
+        let last = store.sector(store.area, store.next - 1) as usize;
+        store.device.0[last][30] ^= 0xFF;
+        let mut store = reopen(store);
+        assert_eq!(store.get("b"), Some("2"));
+        assert_eq!(store.get("c"), None);
+
+        // A move to the other area that stops before its commit record
+        // leaves the old area current.
+        store.set("c", "3").unwrap();
+        let (area, generation) = (store.area, store.generation);
+        let snapshot = Record { generation: generation + 1, kind: Kind::Set, key: "a", value: "0" };
+        let sector = store.sector(1 - area, 0) as usize;
+        store.device.0[sector] = snapshot.encode();
+        let store = reopen(store);
+        assert_eq!((store.area, store.generation), (area, generation));
+        assert_eq!(store.get("a"), Some("1"));
+        assert_eq!(store.get("c"), Some("3"));
+    }
+
+    #[test]
+    fn rejects_oversized_settings() {
+        let device = MemDevice(vec![[0; SECTOR_SIZE]; 4]);
+        let mut store = Store::open(device, (0..4).collect()).expect("opened");
+        let long = "x".repeat(MAX_VALUE + 1);
+        assert!(store.set("key", &long).is_err());
+        assert!(store.set("", "value").is_err());
+        store.set("a", "1").unwrap();
+        // An area of two records holds one setting and its commit.
+        assert!(store.set("b", "2").is_err());
+        assert_eq!(store.get("b"), None);
+        assert!(Store::open(MemDevice(vec![[0; SECTOR_SIZE]; 3]), (0..3).collect()).is_err());
+    }
+}
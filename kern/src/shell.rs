@@ -1,40 +1,202 @@
+mod history;
+mod pager;
+
+use shim::canonical;
 use shim::path::PathBuf;
 
 use stack_vec::StackVec;
 
-use fat32::traits::{Dir, Entry, File, FileSystem, Metadata, Timestamp};
+use fat32::traits::{BlockDevice, Dir, Entry, File, FileSystem, MemDisk, Metadata, Timestamp};
+use fat32::vfat::{self, FormatOptions, Layout};
+use fat32::MasterBootRecord;
+use pi::uart;
 
+use crate::console::line::{self, LineDiscipline};
+use crate::console::utf8::{self, TextWriter};
 use crate::console::{kprint, kprintln, CONSOLE};
-use shim::io::{Read};
+use shim::io::{self, Read, Seek, SeekFrom};
+use core::mem;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::ops::Deref;
 use core::str;
 use core::time::Duration;
-use crate::FILESYSTEM;
+use crate::process::Process;
+use crate::vm::TranslationConfig;
+use crate::{cmdline, config, display, fdisk, fs, ksyms, logger, settings, systimer, telemetry};
+use crate::{traps, version, video};
+use crate::{ALLOCATOR, FILESYSTEM, IRQ, SCHEDULER, VMM};
+use alloc::format;
+use alloc::vec;
 use alloc::vec::Vec;
 use alloc::string::String;
 
+/// The GPIO pin of the 1-Wire bus `temp` reads by default, as for Linux's
+/// `w1-gpio` overlay.
+const TEMP_PIN: u8 = 4;
+
+/// Maximum length of a line when the heap is available.
+const MAX_LINE_LEN: usize = 4096;
+
+/// Maximum length of a line and number of arguments before the allocator has
+/// been initialized.
+const FALLBACK_LINE_LEN: usize = 512;
+const FALLBACK_MAX_ARGS: usize = 64;
+
+/// The bytes shown on each line of a hex dump.
+const HEXDUMP_WIDTH: usize = 16;
+
+/// The bytes `cat` reads at a time; a multiple of `HEXDUMP_WIDTH`.
+const CAT_CHUNK_SIZE: usize = 4096;
+
+/// The most bytes `hexdump` reads when no length is given.
+const HEXDUMP_DEFAULT_LEN: u64 = 4096;
+
+/// The most sectors `sector` dumps at once.
+const MAX_DUMP_SECTORS: u64 = 64;
+
+/// How deeply `run` scripts may run one another.
+const MAX_SCRIPT_DEPTH: usize = 8;
+
+/// Whether the command being run failed, which stops a script run with
+/// `run -e`. Set by `fail!`.
+static FAILED: AtomicBool = AtomicBool::new(false);
+
+/// The number of `run` scripts being run.
+static SCRIPT_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Prints an error of the command being run and marks it as failed.
+macro fail($($arg:tt)*) {{
+  FAILED.store(true, Ordering::Relaxed);
+  kprintln!($($arg)*)
+}}
+
+/// What the shell does after a command.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Flow {
+  Continue,
+  Exit,
+}
+
 /// Error type for `Command` parse failures.
 #[derive(Debug)]
 enum Error {
   Empty,
   TooManyArgs,
+  UnterminatedQuote,
+  DanglingEscape,
+}
+
+/// A buffer backed by the heap when it is available and by caller-provided
+/// stack storage otherwise.
+enum Buf<'a, T: 'a> {
+  Stack(StackVec<'a, T>),
+  Heap(Vec<T>, usize),
+}
+
+impl<'a, T: 'a> Buf<'a, T> {
+  /// Returns a heap-backed buffer holding at most `limit` elements.
+  fn heap(limit: usize) -> Buf<'a, T> {
+    Buf::Heap(Vec::new(), limit)
+  }
+
+  /// Returns a buffer backed by `storage`.
+  fn stack(storage: &'a mut [T]) -> Buf<'a, T> {
+    Buf::Stack(StackVec::new(storage))
+  }
+
+  /// Appends `value`, returning `Err(())` if the buffer is full.
+  fn push(&mut self, value: T) -> Result<(), ()> {
+    match self {
+      Buf::Stack(v) => v.push(value),
+      Buf::Heap(v, limit) if v.len() < *limit => Ok(v.push(value)),
+      Buf::Heap(..) => Err(()),
+    }
+  }
+}
+
+impl<'a, T: 'a> Deref for Buf<'a, T> {
+  type Target = [T];
+
+  fn deref(&self) -> &[T] {
+    match self {
+      Buf::Stack(v) => v.as_slice(),
+      Buf::Heap(v, _) => v.as_slice(),
+    }
+  }
 }
 
 /// A structure representing a single shell command.
 struct Command<'a> {
-  args: StackVec<'a, &'a str>,
+  args: Buf<'a, &'a str>,
 }
 
 impl<'a> Command<'a> {
-  /// Parse a command from a string `s` using `buf` as storage for the
-  /// arguments.
+  /// Parse a command from a string `s`, writing the unquoted arguments to
+  /// `out` and collecting them in `args`.
+  ///
+  /// Arguments are separated by spaces. Text inside double quotes is taken
+  /// literally except for backslash escapes; text inside single quotes is
+  /// taken literally. Outside of single quotes, a backslash escapes the
+  /// following character.
   ///
   /// # Errors
   ///
   /// If `s` contains no arguments, returns `Error::Empty`. If there are more
-  /// arguments than `buf` can hold, returns `Error::TooManyArgs`.
-  fn parse(s: &'a str, buf: &'a mut [&'a str]) -> Result<Command<'a>, Error> {
-    let mut args = StackVec::new(buf);
-    for arg in s.split(' ').filter(|a| !a.is_empty()) {
+  /// arguments than `args` can hold, returns `Error::TooManyArgs`. If a quote
+  /// is not closed, returns `Error::UnterminatedQuote`, and if `s` ends with
+  /// an unescaped backslash, returns `Error::DanglingEscape`.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `out` is shorter than `s`.
+  fn parse(s: &str, out: &'a mut [u8], mut args: Buf<'a, &'a str>) -> Result<Command<'a>, Error> {
+    let bytes = s.as_bytes();
+    let mut rest = out;
+    let mut i = 0;
+    loop {
+      while i < bytes.len() && bytes[i] == b' ' {
+        i += 1;
+      }
+      if i == bytes.len() {
+        break;
+      }
+
+      let mut len = 0;
+      let mut quote = None;
+      while i < bytes.len() {
+        let byte = bytes[i];
+        i += 1;
+        let literal = match (quote, byte) {
+          (Some(q), _) if byte == q => {
+            quote = None;
+            continue;
+          }
+          (Some(b'\''), _) => byte,
+          (_, b'\\') => {
+            let escaped = *bytes.get(i).ok_or(Error::DanglingEscape)?;
+            i += 1;
+            escaped
+          }
+          (None, b'"') | (None, b'\'') => {
+            quote = Some(byte);
+            continue;
+          }
+          (None, b' ') => break,
+          _ => byte,
+        };
+        rest[len] = literal;
+        len += 1;
+      }
+      if quote.is_some() {
+        return Err(Error::UnterminatedQuote);
+      }
+
+      let (arg, tail) = mem::take(&mut rest).split_at_mut(len);
+      rest = tail;
+      // Only ASCII quotes and backslashes are removed, so `arg` is still
+      // valid UTF-8.
+      let arg: &'a [u8] = arg;
+      let arg = str::from_utf8(arg).expect("unquoted argument is valid utf-8");
       args.push(arg).map_err(|_| Error::TooManyArgs)?;
     }
 
@@ -54,231 +216,853 @@ impl<'a> Command<'a> {
 /// Starts a shell using `prefix` as the prefix for each line. This function
 /// never returns.
 pub fn shell(prefix: &str) {
-  const BEL: u8 = 7;
-  const BS: u8 = 8;
-  const LF: u8 = 10;
-  const CR: u8 = 13;
-  const DEL: u8 = 127;
   let mut console = CONSOLE.lock();
+  let mut discipline = LineDiscipline::new();
   let mut work_dir = PathBuf::from("/");
+  if ALLOCATOR.is_initialized() {
+    history::load();
+  }
   loop {
-    let mut line_storage: [u8; 512] = [0; 512];
-    let mut line = StackVec::new(&mut line_storage);
-    let mut arg_storage: [&str; 64] = [&""; 64];
-    kprint!("{}", prefix);
-    let mut cmd_ready = false;
-    while !cmd_ready {
-      let byte = console.read_byte();
-      match byte {
-        BS | DEL => {
-          if line.is_empty() {
-            console.write_byte(BEL);
-          } else {
-            line.pop();
-            console.write_byte(BS);
-            console.write_byte(b' ');
-            console.write_byte(BS);
-          }
-        }
-        CR | LF => {
-          cmd_ready = true;
-          kprint!("\r\n");
-        }
-        0..=0x1f => {
-          console.write_byte(BEL);
-        }
-        _ => {
-          if line.is_full() {
-            console.write_byte(BEL);
-          } else {
-            line.push(byte).expect("error buffering input");
-            console.write_byte(byte);
-          }
-        }
+    // Parsing falls back to fixed stack buffers if the shell is entered
+    // before the allocator is up.
+    let heap = ALLOCATOR.is_initialized();
+    let mut line_storage = [0u8; FALLBACK_LINE_LEN];
+    let mut line_heap: Vec<u8>;
+    let line_buf = if heap {
+      line_heap = vec![0; MAX_LINE_LEN];
+      &mut line_heap[..]
+    } else {
+      &mut line_storage[..]
+    };
+    if heap {
+      kprint!("{}:{}", settings::hostname(), prefix);
+    } else {
+      kprint!("{}", prefix);
+    }
+    let read = if heap {
+      history::read_line(&mut discipline, &mut *console, line_buf)
+    } else {
+      discipline.read_line(&mut *console, line_buf)
+    };
+    let line = match read {
+      Ok(len) => &line_buf[..len],
+      Err(line::Error::Interrupted) => continue,
+      Err(line::Error::EndOfFile) => {
+        kprintln!();
+        continue;
       }
+    };
+    if let (true, Ok(text)) = (heap, str::from_utf8(line)) {
+      history::add(text);
     }
-    match str::from_utf8(line.as_slice()) {
-      Ok(utf8) => {
-        match Command::parse(utf8, &mut arg_storage) {
-          Err(Error::TooManyArgs) => kprintln!("error: too many arguments"),
-          Err(Error::Empty) => {}
-          Ok(command) => {
-            match command.path() {
-              "cat" => for file_name in command.args[1..].iter() {
-                if file_name.chars().nth(0) == Some('/') {
-                  cat(PathBuf::from(file_name));
-                } else {
-                  let mut path = work_dir.clone();
-                  path.push(file_name);
-                  cat(path);
-                }
-              }
-              "cd" => {
-                match command.args.len() {
-                  1 => kprintln!("cd: <directory> argument required"),
-                  2 => {
-                    match command.args[1] {
-                      "." => {},
-                      ".." => if let Some(_) = work_dir.parent() {
-                        work_dir.pop();
-                      }
-                      other_dir => {
-                        if other_dir.len() > 0 && other_dir.chars().nth(0) == Some('/') {
-                          let new_work_dir = PathBuf::from(other_dir);
-                          match FILESYSTEM.open(new_work_dir.clone()) {
-                            Ok(wd) => if let Some(_) = wd.as_dir() {
-                              work_dir = new_work_dir;
-                            } else {
-                              kprintln!("cd: {}: not a directory", other_dir);
-                            }
-                            Err(e) => kprintln!("cd: error: {:?}", e),
-                          }
-                        } else {
-                          let mut new_work_dir = work_dir.clone();
-                          new_work_dir.push(other_dir);
-                          match FILESYSTEM.open(new_work_dir) {
-                            Ok(wd) => if let Some(_) = wd.as_dir() {
-                              work_dir.push(other_dir);
-                            } else {
-                              kprintln!("cd: {}: not a directory", other_dir);
-                            }
-                            Err(e) => kprintln!("cd: error: {:?}", e),
-                          }
-                        }
-                      }
+    if execute(line, &mut work_dir, heap) == Flow::Exit {
+      if let Err(e) = history::save() {
+        kprintln!("history: cannot save: {:?}", e);
+      }
+      break;
+    }
+  }
+}
+
+/// Parses and runs the command `line`, with `work_dir` as the working
+/// directory. Parsing uses the heap if `heap` is `true`.
+fn execute(line: &[u8], work_dir: &mut PathBuf, heap: bool) -> Flow {
+  FAILED.store(false, Ordering::Relaxed);
+  let mut out_storage = [0u8; FALLBACK_LINE_LEN];
+  let mut out_heap: Vec<u8>;
+  let mut arg_storage: [&str; FALLBACK_MAX_ARGS] = [&""; FALLBACK_MAX_ARGS];
+  let (out, args) = if heap {
+    out_heap = vec![0; line.len()];
+    (&mut out_heap[..], Buf::heap(usize::MAX))
+  } else {
+    (&mut out_storage[..], Buf::stack(&mut arg_storage))
+  };
+  match str::from_utf8(line) {
+    Ok(utf8) => {
+      match Command::parse(utf8, out, args) {
+        Err(Error::TooManyArgs) => fail!("error: too many arguments"),
+        Err(Error::UnterminatedQuote) => fail!("error: unterminated quote"),
+        Err(Error::DanglingEscape) => fail!("error: trailing backslash"),
+        Err(Error::Empty) => {}
+        Ok(command) => {
+          match command.path() {
+            "cat" => {
+              let binary = command.args[1..].contains(&"--binary");
+              for file_name in command.args[1..].iter().filter(|&&arg| arg != "--binary") {
+                cat(canonical::resolve(&*work_dir, file_name), file_name, binary);
+              }
+            }
+            "cd" => {
+              match command.args.len() {
+                1 => fail!("cd: <directory> argument required"),
+                2 => {
+                  let new_work_dir = canonical::resolve(&*work_dir, command.args[1]);
+                  match FILESYSTEM.open_dir(&new_work_dir) {
+                    Ok(_) => *work_dir = new_work_dir,
+                    Err(e) => fail!("cd: {}: {}", command.args[1], e),
+                  }
+                }
+                _ => fail!("cd: too many arguments"),
+              }
+            }
+            "echo" => {
+              for arg in command.args[1..].iter() {
+                kprint!("{} ", arg);
+              }
+              kprintln!();
+            }
+            "exit" => return Flow::Exit,
+            "hexdump" => {
+              match command.args.len() {
+                1 => fail!("hexdump: <path> [offset] [len] arguments required"),
+                2..=4 => {
+                  let offset = command.args.get(2).map_or(Some(0), |arg| parse_number(arg));
+                  let len = command.args.get(3)
+                    .map_or(Some(HEXDUMP_DEFAULT_LEN), |arg| parse_number(arg));
+                  match (offset, len) {
+                    (Some(offset), Some(len)) => {
+                      hexdump(canonical::resolve(&*work_dir, command.args[1]), offset, len)
                     }
+                    _ => fail!("hexdump: invalid offset or length"),
                   }
-                  _ => kprintln!("cd: too many arguments"),
-                }
-              }
-              "echo" => {
-                for arg in command.args[1..].iter() {
-                  kprint!("{} ", arg);
-                }
-                kprintln!();
-              }
-              "exit" => break,
-              "ls" => {
-                match command.args.len() {
-                  1 => ls(&work_dir, false),
-                  2 => if command.args[1] == "-a" {
-                    ls(&work_dir, true);
-                  } else if command.args[1].chars().nth(0) == Some('/') {
-                    ls(&PathBuf::from(command.args[1]), false);
-                  } else {
-                    let mut path = work_dir.clone();
-                    path.push(command.args[1]);
-                    ls(&path, false);
+                }
+                _ => fail!("hexdump: too many arguments"),
+              }
+            }
+            "sector" => {
+              match command.args.len() {
+                1 => fail!("sector: <n> [count] arguments required"),
+                2 | 3 => {
+                  let n = parse_number(command.args[1]);
+                  let count = command.args.get(2).map_or(Some(1), |arg| parse_number(arg));
+                  match (n, count) {
+                    (Some(n), Some(count)) if count <= MAX_DUMP_SECTORS => sector(n, count),
+                    (Some(_), Some(_)) => fail!("sector: at most {} sectors", MAX_DUMP_SECTORS),
+                    _ => fail!("sector: invalid sector number or count"),
                   }
-                  3 => if command.args[1] == "-a" {
-                    if command.args[2].chars().nth(0) == Some('/') {
-                      ls(&PathBuf::from(command.args[2]), true);
-                    } else {
-                      let mut path = work_dir.clone();
-                      path.push(command.args[2]);
-                      ls(&path, true);
+                }
+                _ => fail!("sector: too many arguments"),
+              }
+            }
+            "run" => {
+              match (command.args.len(), command.args.get(1).copied()) {
+                (3, Some("-e")) => run_script(command.args[2], true, work_dir),
+                (2, Some(script)) if script != "-e" => run_script(script, false, work_dir),
+                _ => fail!("run: usage: run [-e] <script>"),
+              }
+            }
+            "history" => {
+              match (command.args.len(), command.args.get(1).copied()) {
+                (1, _) => history::print(),
+                (2, Some("-c")) => if let Err(e) = history::clear() {
+                  fail!("history: {:?}", e);
+                }
+                (2, Some("-w")) => if let Err(e) = history::save() {
+                  fail!("history: {:?}", e);
+                }
+                _ => fail!("history: usage: history [-c | -w]"),
+              }
+            }
+            "time" => {
+              match command.args.len() {
+                1 => fail!("time: <command> [args] arguments required"),
+                _ if !heap => fail!("time: no heap to run the command"),
+                _ => if time(&command.args[1..], work_dir) == Flow::Exit {
+                  return Flow::Exit;
+                }
+              }
+            }
+            "more" | "less" => {
+              match command.args.len() {
+                1 => fail!("{}: <command> [args] arguments required", command.path()),
+                _ if !heap => fail!("{}: no heap to hold the output", command.path()),
+                _ => if more(&command.args[1..], work_dir) == Flow::Exit {
+                  return Flow::Exit;
+                }
+              }
+            }
+            "ls" => {
+              match command.args.len() {
+                1 => ls(work_dir, false),
+                2 => if command.args[1] == "-a" {
+                  ls(work_dir, true);
+                } else {
+                  ls(&canonical::resolve(&*work_dir, command.args[1]), false);
+                }
+                3 => if command.args[1] == "-a" {
+                  ls(&canonical::resolve(&*work_dir, command.args[2]), true);
+                } else {
+                  fail!("ls: invalid argument {}", command.args[1]);
+                }
+                _ => fail!("ls: too many arguments"),
+              }
+            }
+            "pwd" => {
+              kprintln!("{}", work_dir.to_string_lossy());
+            }
+            "sleep" => {
+              match command.args.len() {
+                1 => fail!("sleep: <ms> argument required"),
+                2 => {
+                  match command.args[1].parse::<u32>() {
+                    Ok(ms) => {
+                      match kernel_api::syscall::sleep(Duration::from_millis(ms as u64)) {
+                        Ok(elapsed) => kprintln!("slept for {:?}", elapsed),
+                        Err(e) => fail!("sleep: {}", e),
+                      }
                     }
-                  } else {
-                    kprintln!("ls: invalid argument {}", command.args[1]);
+                    Err(e) => fail!("sleep: error: {:?}", e),
                   }
-                  _ => kprintln!("ls: too many arguments"),
-                }
-              }
-              "pwd" => {
-                kprintln!("{}", work_dir.to_string_lossy());
-              }
-              "sleep" => {
-                match command.args.len() {
-                  1 => kprintln!("sleep: <ms> argument required"),
-                  2 => {
-                    match command.args[1].parse::<u32>() {
-                      Ok(ms) => {
-                        match kernel_api::syscall::sleep(Duration::from_millis(ms as u64)) {
-                          Ok(elapsed) => kprintln!("slept for {:?}", elapsed),
-                          Err(e) => kprintln!("sleep: error: {:?}", e),
-                        }
-                      }
-                      Err(e) => kprintln!("sleep: error: {:?}", e),
+                }
+                _ => fail!("sleep: too many arguments"),
+              }
+            }
+            "baud" => {
+              let mut console = CONSOLE.lock();
+              match command.args.len() {
+                1 => kprintln!("{} bps", console.uart_config().baud),
+                2 => match command.args[1].parse::<u32>() {
+                  Ok(baud) => {
+                    kprintln!("switching console to {} bps", baud);
+                    let config = uart::Config { baud, ..console.uart_config() };
+                    if let Err(e) = console.configure_uart(config) {
+                      fail!("baud: error: {:?}", e);
                     }
                   }
-                  _ => kprintln!("sleep: too many arguments"),
-                }
-              }
-              // For debugging purposes
-              //
-              // "atags" => {
-              //   for atag in Atags::get() {
-              //     kprint!("{:#?} ", atag);
-              //   }
-              //   kprintln!();
-              // }
-              // "memmap" => {
-              //   kprintln!("{:#?}", memory_map());
-              // }
-              // "memtest" => {
-              // let mut v = Vec::new();
-              //   for i in 0..50 {
-              //     v.push(i);
-              //   }
-              //   kprintln!("{:?}", v);
-              // }
-              // "fsinit" => {
-              //   unsafe { FILESYSTEM.initialize() };
-              // }
-              // "print_root" => {
-              //   let ent = FILESYSTEM.open(Path::new("/"));
-              //   match ent {
-              //     Ok(root) => {
-              //       if let Some(d) = root.as_dir() {
-              //         match d.entries() {
-              //           Ok(it) => {
-              //             for entry in it {
-              //               kprint!("{}\t", entry.name());
-              //             }
-              //             kprintln!();
-              //           }
-              //           Err(e) => kprintln!("error iterating directory: {:?}", e),
-              //         }
-              //       } else {
-              //         kprintln!("root dir is not dir...");
-              //       }
-              //     }
-              //     Err(e) => kprintln!("error: {:?}", e),
-              //   }
-              // }
-              other => {
-                kprintln!("unknown command: {}", other);
+                  Err(e) => fail!("baud: error: {:?}", e),
+                }
+                _ => fail!("baud: too many arguments"),
+              }
+            }
+            "temp" => {
+              match command.args.len() {
+                1 => temp(TEMP_PIN),
+                2 => match command.args[1].parse::<u8>() {
+                  Ok(pin) if pin <= 53 => temp(pin),
+                  _ => fail!("temp: invalid GPIO pin {}", command.args[1]),
+                },
+                _ => fail!("temp: too many arguments"),
+              }
+            }
+            "display" => {
+              match command.args.len() {
+                1 => display_status(),
+                2 if command.args[1] == "test" => display_test(),
+                2 if command.args[1] == "clear" => {
+                  if display::draw(|fb| fb.clear(display::BLACK)).is_none() {
+                    fail!("display: no display");
+                  }
+                }
+                3 if command.args[1] == "init" => match display::parse_config(command.args[2]) {
+                  Some(config) => display::initialize(&config),
+                  None => fail!("display: invalid panel {}", command.args[2]),
+                },
+                3 if command.args[1] == "mirror" => match command.args[2] {
+                  "on" => if let Err(e) = display::start_mirror() {
+                    fail!("display: {:?}", e);
+                  },
+                  "off" => display::stop_mirror(),
+                  arg => fail!("display: expected on or off, not {}", arg),
+                },
+                n if n > 2 && command.args[1] == "text" => {
+                  display_text(&command.args[2..].join(" "))
+                }
+                _ => {
+                  fail!("display: usage: display [init <panel>|test|clear|text <t>|mirror on|off]")
+                }
+              }
+            }
+            "vidmode" => {
+              match command.args.len() {
+                1 => vidmode_list(),
+                2 => {
+                  let size = match command.args[1] {
+                    "auto" => Some(None),
+                    arg => video::parse_size(arg).map(Some),
+                  };
+                  match size {
+                    Some(size) => if let Err(e) = video::set_mode(size) {
+                      fail!("vidmode: {:?}", e);
+                    },
+                    None => fail!("vidmode: expected <width>x<height> or auto"),
+                  }
+                }
+                _ => fail!("vidmode: usage: vidmode [<width>x<height>|auto]"),
+              }
+            }
+            "tone" => {
+              match command.args.len() {
+                1 => fail!("tone: <hz> [ms] arguments required"),
+                2 | 3 => {
+                  let hz = command.args[1].parse::<u32>();
+                  let ms = command.args.get(2).map_or(Ok(500), |ms| ms.parse::<u32>());
+                  match (hz, ms) {
+                    (Ok(hz), Ok(ms)) => tone(hz, ms),
+                    (Err(e), _) | (_, Err(e)) => fail!("tone: error: {:?}", e),
+                  }
+                }
+                _ => fail!("tone: too many arguments"),
+              }
+            }
+            "config" => {
+              match command.args.len() {
+                1 => show_config(),
+                _ => fail!("config: too many arguments"),
+              }
+            }
+            "sync" => {
+              match command.args.len() {
+                1 => if let Err(e) = FILESYSTEM.sync() {
+                  fail!("sync: {:?}", e);
+                }
+                _ => fail!("sync: too many arguments"),
+              }
+            }
+            "mount" => {
+              match command.args.len() {
+                1 => {
+                  match FILESYSTEM.options() {
+                    Some(options) => kprintln!("/ vfat {}", options),
+                    None => fail!("mount: not mounted"),
+                  }
+                  if let Some(options) = FILESYSTEM.ramdisk_options() {
+                    kprintln!("{} vfat {}", fs::RAMDISK_PATH, options);
+                  }
+                }
+                3 if command.args[1] == "-o" => {
+                  if let Err(e) = FILESYSTEM.mount(command.args[2]) {
+                    fail!("mount: {:?}", e);
+                  }
+                }
+                _ => fail!("mount: usage: mount [-o <options>]"),
+              }
+            }
+            "umount" => {
+              match command.args.len() {
+                1 => if let Err(e) = FILESYSTEM.umount() {
+                  fail!("umount: {:?}", e);
+                }
+                _ => fail!("umount: too many arguments"),
+              }
+            }
+            "fdisk" => {
+              match command.args.len() {
+                1 => fdisk::run(),
+                _ => fail!("fdisk: too many arguments"),
+              }
+            }
+            "mkfs" => {
+              let confirmed = command.args.get(1) == Some(&"-y");
+              let args = &command.args[if confirmed { 2 } else { 1 }..];
+              match args.len() {
+                1 => mkfs(args[0], 0, confirmed),
+                2 => match parse_number(args[1]) {
+                  Some(cluster_size) if cluster_size <= u32::max_value() as u64 => {
+                    mkfs(args[0], cluster_size as u32, confirmed)
+                  }
+                  _ => fail!("mkfs: invalid cluster size"),
+                },
+                _ => fail!("mkfs: usage: mkfs [-y] <sd1-sd4> [cluster bytes]"),
+              }
+            }
+            "ramdisk" => {
+              match command.args.len() {
+                2 if command.args[1] == "-u" => if let Err(e) = FILESYSTEM.umount_ramdisk() {
+                  fail!("ramdisk: {:?}", e);
+                }
+                2 => if let Err(e) = ramdisk(canonical::resolve(&*work_dir, command.args[1])) {
+                  fail!("ramdisk: {:?}", e);
+                }
+                _ => fail!("ramdisk: usage: ramdisk <image> | ramdisk -u"),
+              }
+            }
+            "setting" => {
+              match (command.args.len(), command.args.get(1).copied()) {
+                (1, _) => for (key, value) in settings::list() {
+                  kprintln!("{}={}", key, value);
+                },
+                (3, Some("get")) => match settings::get(command.args[2]) {
+                  Some(value) => kprintln!("{}", value),
+                  None => fail!("setting: {} is not set", command.args[2]),
+                },
+                (4, Some("set")) => {
+                  if let Err(e) = settings::set(command.args[2], command.args[3]) {
+                    fail!("setting: {:?}", e);
+                  }
+                }
+                (3, Some("delete")) => match settings::delete(command.args[2]) {
+                  Ok(true) => (),
+                  Ok(false) => fail!("setting: {} is not set", command.args[2]),
+                  Err(e) => fail!("setting: {:?}", e),
+                },
+                _ => fail!("setting: usage: setting [get <key> | set <key> <value> | \
+                                delete <key>]"),
+              }
+            }
+            "hostname" => {
+              match command.args.len() {
+                1 => kprintln!("{}", settings::hostname()),
+                2 => if let Err(e) = settings::set("hostname", command.args[1]) {
+                  fail!("hostname: {:?}", e);
+                }
+                _ => fail!("hostname: too many arguments"),
+              }
+            }
+            "version" => {
+              match command.args.len() {
+                1 => version::print(),
+                _ => fail!("version: too many arguments"),
+              }
+            }
+            "uname" => {
+              match command.args.len() {
+                1 => kprintln!("{}", version::SYSNAME),
+                2 if command.args[1] == "-a" => kprintln!("{} {} {} {} {} aarch64",
+                    version::SYSNAME, settings::hostname(), version::RELEASE,
+                    version::GIT_REVISION, version::BUILD_TIME),
+                2 => fail!("uname: invalid argument {}", command.args[1]),
+                _ => fail!("uname: too many arguments"),
+              }
+            }
+            "volinfo" => {
+              match command.args.len() {
+                1 => volinfo(),
+                _ => fail!("volinfo: too many arguments"),
+              }
+            }
+            "syslat" => {
+              match command.args.len() {
+                1 => traps::profile::dump(),
+                2 => match command.args[1] {
+                  "on" => traps::profile::set_enabled(true),
+                  "off" => traps::profile::set_enabled(false),
+                  "reset" => traps::profile::reset(),
+                  other => fail!("syslat: invalid argument {}", other),
+                }
+                _ => fail!("syslat: too many arguments"),
+              }
+            }
+            "irqstat" => {
+              match command.args.len() {
+                1 => irqstat(),
+                2 if command.args[1] == "reset" => IRQ.reset_stats(),
+                2 => fail!("irqstat: invalid argument {}", command.args[1]),
+                _ => fail!("irqstat: too many arguments"),
+              }
+            }
+            "systimer" => {
+              match command.args.len() {
+                1 => systimer(),
+                _ => fail!("systimer: too many arguments"),
+              }
+            }
+            "heapdump" => {
+              match command.args.len() {
+                1 => heapdump(false),
+                2 if command.args[1] == "mark" => heapdump(true),
+                2 => fail!("heapdump: invalid argument {}", command.args[1]),
+                _ => fail!("heapdump: too many arguments"),
+              }
+            }
+            "strace" => {
+              let enable = match command.args.len() {
+                2 => Some(true),
+                3 if command.args[2] == "off" => Some(false),
+                3 => {
+                  fail!("strace: invalid argument {}", command.args[2]);
+                  None
+                }
+                1 => {
+                  fail!("strace: <pid> argument required");
+                  None
+                }
+                _ => {
+                  fail!("strace: too many arguments");
+                  None
+                }
+              };
+              if let Some(enable) = enable {
+                match command.args[1].parse::<u64>() {
+                  Ok(pid) => if !SCHEDULER.set_traced(pid, enable) {
+                    fail!("strace: no process with pid {}", pid);
+                  }
+                  Err(e) => fail!("strace: error: {:?}", e),
+                }
+              }
+            }
+            "taskset" => {
+              let pid = match command.args.len() {
+                1 => {
+                  fail!("taskset: <pid> [mask] arguments required");
+                  None
+                }
+                2 | 3 => match command.args[1].parse::<u64>() {
+                  Ok(pid) => Some(pid),
+                  Err(e) => {
+                    fail!("taskset: error: {:?}", e);
+                    None
+                  }
+                }
+                _ => {
+                  fail!("taskset: too many arguments");
+                  None
+                }
+              };
+              if let Some(pid) = pid {
+                taskset(pid, command.args.get(2).cloned());
+              }
+            }
+            "schedstat" => {
+              match command.args.len() {
+                1 => schedstat(),
+                2 if command.args[1] == "reset" => SCHEDULER.reset_stats(),
+                2 => fail!("schedstat: invalid argument {}", command.args[1]),
+                _ => fail!("schedstat: too many arguments"),
+              }
+            }
+            "sym" => {
+              match command.args.len() {
+                1 => fail!("sym: <address> argument required"),
+                _ => for addr in command.args[1..].iter() {
+                  sym(addr);
+                }
+              }
+            }
+            "telemetry" => {
+              match command.args.len() {
+                1 => telemetry_status(),
+                2 if command.args[1] == "stop" => telemetry::stop(),
+                2 | 3 if command.args[1] == "start" => {
+                  match command.args.get(2).map_or(Ok(1000), |ms| ms.parse::<u64>()) {
+                    Ok(ms) => telemetry_start(ms),
+                    Err(e) => fail!("telemetry: error: {:?}", e),
+                  }
+                }
+                2 | 3 => fail!("telemetry: invalid argument {}", command.args[1]),
+                _ => fail!("telemetry: too many arguments"),
+              }
+            }
+            "lockstat" => {
+              match command.args.len() {
+                1 => lockstat(),
+                _ => fail!("lockstat: too many arguments"),
+              }
+            }
+            "ps" => {
+              match command.args.len() {
+                1 => ps(),
+                _ => fail!("ps: too many arguments"),
+              }
+            }
+            "vminfo" => {
+              match command.args.len() {
+                1 => vminfo(),
+                _ => fail!("vminfo: too many arguments"),
               }
             }
+            "vmdump" => {
+              match command.args.len() {
+                1 => fail!("vmdump: <pid> argument required"),
+                2 => match command.args[1].parse::<u64>() {
+                  Ok(pid) => vmdump(pid),
+                  Err(e) => fail!("vmdump: error: {:?}", e),
+                }
+                _ => fail!("vmdump: too many arguments"),
+              }
+            }
+            // For debugging purposes
+            //
+            // "atags" => {
+            //   for atag in Atags::get() {
+            //     kprint!("{:#?} ", atag);
+            //   }
+            //   kprintln!();
+            // }
+            // "memmap" => {
+            //   kprintln!("{:#?}", memory_map());
+            // }
+            // "memtest" => {
+            // let mut v = Vec::new();
+            //   for i in 0..50 {
+            //     v.push(i);
+            //   }
+            //   kprintln!("{:?}", v);
+            // }
+            // "fsinit" => {
+            //   unsafe { FILESYSTEM.initialize() };
+            // }
+            // "print_root" => {
+            //   let ent = FILESYSTEM.open(Path::new("/"));
+            //   match ent {
+            //     Ok(root) => {
+            //       if let Some(d) = root.as_dir() {
+            //         match d.entries() {
+            //           Ok(it) => {
+            //             for entry in it {
+            //               kprint!("{}\t", entry.name());
+            //             }
+            //             kprintln!();
+            //           }
+            //           Err(e) => kprintln!("error iterating directory: {:?}", e),
+            //         }
+            //       } else {
+            //         kprintln!("root dir is not dir...");
+            //       }
+            //     }
+            //     Err(e) => kprintln!("error: {:?}", e),
+            //   }
+            // }
+            other => {
+              fail!("unknown command: {}", other);
+            }
           }
         }
       }
-      Err(_) => {}
     }
+    Err(_) => {}
   }
+  Flow::Continue
 }
 
-fn cat(path: PathBuf) {
-  match FILESYSTEM.open(path) {
-    Ok(f) => if let Some(mut file) = f.into_file() {
-      let mut bytes_read = 0;
-      let mut file_contents = Vec::with_capacity(file.size() as usize);
-      file_contents.resize(file.size() as usize, 0);
-      while bytes_read < file.size() as usize {
-        match file.read(&mut file_contents[bytes_read..]) {
-          Ok(n) => bytes_read += n,
-          Err(e) => {
-            kprintln!("cat: error: {:?}", e);
-            break;
-          }
-        };
+/// Runs the commands in the file `script`, one per line, as if typed at the
+/// prompt; they share the shell's working directory. Blank lines and lines
+/// starting with `#` are skipped. The lines `set -e` and `set +e` turn
+/// stopping at the first failed command on and off; `stop_on_error` is its
+/// initial state. `exit` ends the script.
+fn run_script(script: &str, mut stop_on_error: bool, work_dir: &mut PathBuf) {
+  if SCRIPT_DEPTH.load(Ordering::Relaxed) >= MAX_SCRIPT_DEPTH {
+    return fail!("run: {}: scripts nested too deeply", script);
+  }
+  let text = match read_text(canonical::resolve(&*work_dir, script)) {
+    Ok(text) => text,
+    Err(e) => return fail!("run: {}: {:?}", script, e),
+  };
+  SCRIPT_DEPTH.fetch_add(1, Ordering::Relaxed);
+  let mut stopped_at = None;
+  for (number, line) in text.lines().enumerate() {
+    match line.trim() {
+      "" => continue,
+      line if line.starts_with('#') => continue,
+      "set -e" => stop_on_error = true,
+      "set +e" => stop_on_error = false,
+      line => {
+        if execute(line.as_bytes(), work_dir, true) == Flow::Exit {
+          break;
+        }
+        if stop_on_error && FAILED.load(Ordering::Relaxed) {
+          stopped_at = Some(number + 1);
+          break;
+        }
       }
-      kprint!("{}", file_contents.iter().map(|b| char::from(*b)).collect::<String>());
-    } else {
-      kprintln!("cat: not a regular file");
     }
-    Err(e) => kprintln!("cat: error: {:?}", e),
   }
+  SCRIPT_DEPTH.fetch_sub(1, Ordering::Relaxed);
+  if let Some(number) = stopped_at {
+    fail!("run: {}: stopped at line {}", script, number);
+  }
+}
+
+/// Runs the command `args` and pages its output with `pager::page()`. Inside
+/// another `more`, the output is left to that one.
+fn more(args: &[&str], work_dir: &mut PathBuf) -> Flow {
+  let line = command_line(args);
+  if !CONSOLE.lock().start_capture() {
+    return execute(line.as_bytes(), work_dir, true);
+  }
+  let flow = execute(line.as_bytes(), work_dir, true);
+  let (text, truncated) = CONSOLE.lock().end_capture().unwrap_or_default();
+  pager::page(&text);
+  if truncated {
+    kprintln!("more: output too long; the rest was dropped");
+  }
+  flow
+}
+
+/// Runs the command `args` and prints the wall-clock time it took, and the
+/// CPU time processes used meanwhile: both those it started and those running
+/// next to it. Processes that exited before the command ended are not
+/// counted.
+fn time(args: &[&str], work_dir: &mut PathBuf) -> Flow {
+  let line = command_line(args);
+  let before = cpu_times();
+  let start = pi::timer::current_time();
+  let flow = execute(line.as_bytes(), work_dir, true);
+  let real = pi::timer::current_time() - start;
+  let cpu: Duration = cpu_times().iter().map(|&(pid, after)| {
+    match before.iter().find(|&&(other, _)| other == pid) {
+      Some(&(_, before)) => after.checked_sub(before).unwrap_or_default(),
+      None => after,
+    }
+  }).sum();
+  kprintln!("real {}.{:06}s  cpu {}.{:06}s",
+    real.as_secs(), real.subsec_micros(), cpu.as_secs(), cpu.subsec_micros());
+  flow
+}
+
+/// Returns each process's ID and the CPU time it has used.
+fn cpu_times() -> Vec<(u64, Duration)> {
+  let now = pi::timer::current_time();
+  SCHEDULER.critical(|scheduler| {
+    scheduler.processes().map(|p| (p.context.tpidr, p.cpu_time_at(now))).collect()
+  })
+}
+
+/// Returns the command line that runs the command `args`.
+fn command_line(args: &[&str]) -> String {
+  args.iter().map(|arg| quote(arg)).collect::<Vec<_>>().join(" ")
+}
+
+/// Quotes `arg` so that `Command::parse()` turns it back into `arg`.
+fn quote(arg: &str) -> String {
+  format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Reads the UTF-8 text file at `path`.
+fn read_text(path: PathBuf) -> io::Result<String> {
+  let mut file = FILESYSTEM.open_file(path)?;
+  let mut bytes = Vec::new();
+  fs::read_to_end(&mut file, &mut bytes)?;
+  String::from_utf8(bytes).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "not UTF-8"))
+}
+
+/// Parses a number in decimal or, with a `0x` prefix, in hex.
+pub(crate) fn parse_number(arg: &str) -> Option<u64> {
+  if arg.starts_with("0x") {
+    u64::from_str_radix(&arg[2..], 16).ok()
+  } else {
+    arg.parse().ok()
+  }
+}
+
+/// Prints `bytes` as `hexdump -C` does: on each line, the offset of its
+/// first byte counting from `offset`, then 16 bytes in hex and as ASCII.
+pub fn print_hex(offset: u64, bytes: &[u8]) {
+  print_hex_lines(offset, bytes);
+  kprintln!("{:08x}", offset + bytes.len() as u64);
+}
+
+/// Prints the lines of `print_hex()` for `bytes`, without the end offset, so
+/// that a dump can be printed a chunk at a time.
+fn print_hex_lines(offset: u64, bytes: &[u8]) {
+  use core::fmt::Write;
+
+  let mut line = String::with_capacity(80);
+  for (i, chunk) in bytes.chunks(HEXDUMP_WIDTH).enumerate() {
+    line.clear();
+    let _ = write!(line, "{:08x} ", offset + (i * HEXDUMP_WIDTH) as u64);
+    for j in 0..HEXDUMP_WIDTH {
+      if j == HEXDUMP_WIDTH / 2 {
+        line.push(' ');
+      }
+      match chunk.get(j) {
+        Some(byte) => {
+          let _ = write!(line, " {:02x}", byte);
+        }
+        None => line.push_str("   "),
+      }
+    }
+    line.push_str("  |");
+    for &byte in chunk {
+      line.push(if byte == b' ' || byte.is_ascii_graphic() { byte as char } else { '.' });
+    }
+    line.push('|');
+    kprintln!("{}", line);
+  }
+}
+
+/// Dumps up to `len` bytes of the file at `path` from `offset`.
+fn hexdump(path: PathBuf, offset: u64, len: u64) {
+  let mut file = match FILESYSTEM.open_file(path) {
+    Ok(file) => file,
+    Err(e) => return fail!("hexdump: {:?}", e),
+  };
+  let len = len.min(file.size().saturating_sub(offset));
+  if let Err(e) = file.seek(SeekFrom::Start(offset)) {
+    return fail!("hexdump: {:?}", e);
+  }
+  let mut bytes = vec![0; len as usize];
+  let mut read = 0;
+  while read < bytes.len() {
+    match file.read(&mut bytes[read..]) {
+      Ok(0) => break,
+      Ok(n) => read += n,
+      Err(e) => return fail!("hexdump: {:?}", e),
+    }
+  }
+  print_hex(offset, &bytes[..read]);
+}
+
+/// Dumps `count` raw sectors of the SD card starting at sector `n`. The
+/// file system's cache is bypassed, so writes it holds are not shown until
+/// they are synced.
+fn sector(n: u64, count: u64) {
+  let mut device = match FILESYSTEM.device() {
+    Some(device) => device,
+    None => return fail!("sector: no SD card"),
+  };
+  let size = device.sector_size();
+  let mut buf = vec![0; size as usize];
+  for sector in n..n + count {
+    if let Err(e) = device.read_sector(sector, &mut buf) {
+      return fail!("sector: {}: {:?}", sector, e);
+    }
+    kprintln!("sector {}:", sector);
+    print_hex(sector * size, &buf);
+  }
+}
+
+/// Prints the file at `path` as UTF-8 text, with U+FFFD for invalid
+/// sequences. A file that does not look like text is refused, or with
+/// `binary`, dumped as `hexdump` does.
+fn cat(path: PathBuf, name: &str, binary: bool) {
+  let mut file = match FILESYSTEM.open_file(path) {
+    Ok(file) => file,
+    Err(e) => return fail!("cat: {}: {:?}", name, e),
+  };
+  let mut buf = vec![0; CAT_CHUNK_SIZE];
+  let mut len = match fill(&mut file, &mut buf) {
+    Ok(len) => len,
+    Err(e) => return fail!("cat: {}: {:?}", name, e),
+  };
+  if utf8::is_text(&buf[..len]) {
+    let mut text = TextWriter::console();
+    while len > 0 {
+      let _ = io::Write::write_all(&mut text, &buf[..len]);
+      len = match fill(&mut file, &mut buf) {
+        Ok(len) => len,
+        Err(e) => return fail!("cat: {}: {:?}", name, e),
+      };
+    }
+    let _ = text.finish();
+  } else if binary {
+    let mut offset = 0;
+    while len > 0 {
+      print_hex_lines(offset, &buf[..len]);
+      offset += len as u64;
+      len = match fill(&mut file, &mut buf) {
+        Ok(len) => len,
+        Err(e) => return fail!("cat: {}: {:?}", name, e),
+      };
+    }
+    kprintln!("{:08x}", offset);
+  } else {
+    fail!("cat: {}: binary file; use `cat --binary` to dump it", name);
+  }
+}
+
+/// Reads from `file` until `buf` is full or the file ends, and returns the
+/// number of bytes read.
+fn fill<R: Read>(file: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+  let mut len = 0;
+  while len < buf.len() {
+    match file.read(&mut buf[len..]) {
+      Ok(0) => break,
+      Ok(n) => len += n,
+      Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+      Err(e) => return Err(e),
+    }
+  }
+  Ok(len)
 }
 
 fn ls(path: &PathBuf, show_hidden: bool) {
@@ -341,11 +1125,465 @@ fn ls(path: &PathBuf, show_hidden: bool) {
               entry.name());
           }
         }
-        Err(e) => kprintln!("ls: error: {:?}", e),
+        Err(e) => fail!("ls: error: {:?}", e),
       }
     } else {
-      kprintln!("ls: not a directory")
+      fail!("ls: not a directory")
+    }
+    Err(e) => fail!("ls: error: {:?}", e),
+  }
+}
+
+/// Formats `dev`, partition `sd1` to `sd4` of the SD card, with an empty FAT32
+/// file system with clusters of `cluster_size` bytes, or a size chosen for
+/// the partition if 0. Unless `confirmed`, only describes what would be
+/// erased. The partition holding the mounted root file system is refused.
+fn mkfs(dev: &str, cluster_size: u32, confirmed: bool) {
+  let number = if dev.starts_with("sd") { dev[2..].parse::<usize>().ok() } else { None };
+  let index = match number {
+    Some(n) if n >= 1 && n <= 4 => n - 1,
+    _ => return fail!("mkfs: {}: not a partition; use sd1 to sd4", dev),
+  };
+  let sd = match FILESYSTEM.device() {
+    Some(sd) => sd,
+    None => return fail!("mkfs: no SD card"),
+  };
+  let mbr = match MasterBootRecord::from(sd) {
+    Ok(mbr) => mbr,
+    Err(e) => return fail!("mkfs: cannot read the partition table: {:?}", e),
+  };
+  let partition = &mbr.partition_table[index];
+  let (kind, start, sectors) = (partition.partition_type, partition.sector_offset as u64, partition.num_sectors as u64);
+  if kind == 0 || sectors == 0 {
+    return fail!("mkfs: {}: no such partition", dev);
+  }
+  // `VFat::from()` mounts the first FAT32 partition.
+  let root = mbr.partition_table.iter().position(|p| p.partition_type == fat32::TYPE_FAT32 || p.partition_type == fat32::TYPE_FAT32_LBA);
+  if root == Some(index) && FILESYSTEM.options().is_some() {
+    return fail!("mkfs: {} is mounted at /", dev);
+  }
+
+  let options = FormatOptions {
+    cluster_size,
+    volume_id: pi::timer::current_time().as_micros() as u32,
+    ..FormatOptions::default()
+  };
+  let layout = match Layout::new(sd.sector_size(), sectors, &options) {
+    Ok(layout) => layout,
+    Err(e) => return fail!("mkfs: {}: {:?}", dev, e),
+  };
+  let cluster_bytes = layout.sectors_per_cluster as u64 * layout.bytes_per_sector as u64;
+  if !confirmed {
+    kprintln!("mkfs: {} ({} MiB) would be erased for {} clusters of {} bytes; run `mkfs -y {}` to format it",
+      dev, sectors * sd.sector_size() >> 20, layout.clusters, cluster_bytes, dev);
+    return;
+  }
+  if let Err(e) = vfat::format(sd, start, sectors, &options) {
+    return fail!("mkfs: {}: {:?}", dev, e);
+  }
+  kprintln!("{}: {} clusters of {} bytes", dev, layout.clusters, cluster_bytes);
+  if kind != fat32::TYPE_FAT32 && kind != fat32::TYPE_FAT32_LBA {
+    kprintln!("mkfs: note: {} has partition type {:#04x}, not FAT32 (0x0c)", dev, kind);
+  }
+}
+
+/// Loads the disk image at `path` into memory and mounts it at
+/// `fs::RAMDISK_PATH`.
+fn ramdisk(path: PathBuf) -> io::Result<()> {
+  let image = FILESYSTEM.open_file(path)?;
+  let disk = MemDisk::load(image, 512)?;
+  kprintln!("ramdisk: {} KiB at {}", disk.sectors() / 2, fs::RAMDISK_PATH);
+  FILESYSTEM.mount_ramdisk(disk)
+}
+
+fn volinfo() {
+  match FILESYSTEM.metadata("/") {
+    Ok(metadata) => {
+      kprintln!("label:   {}", FILESYSTEM.volume_label().unwrap_or_else(|| String::from("(none)")));
+      let created = metadata.created();
+      kprintln!("created: {:02}/{:02}/{:04} {:02}:{:02}:{:02}",
+        created.month(), created.day(), created.year(),
+        created.hour(), created.minute(), created.second());
+    }
+    Err(e) => fail!("volinfo: {}", e),
+  }
+}
+
+fn vmdump(pid: u64) {
+  SCHEDULER.critical(|scheduler| {
+    match scheduler.find(pid) {
+      Some(process) => {
+        kprintln!("process {} page table at {:?}", pid, process.vmap.get_baddr());
+        for region in process.vmap.regions(Process::get_image_base()) {
+          kprintln!("  {}", region);
+        }
+      }
+      None => fail!("vmdump: no process with pid {}", pid),
+    }
+  });
+}
+
+/// Prints each process's state and the memory mapped in its address space
+/// in KiB: stack, image and heap pages, the total and its peak, and the
+/// process's limit, if any.
+fn ps() {
+  use crate::param::PAGE_SIZE;
+  use crate::process::State;
+
+  let kib = |pages: usize| pages * PAGE_SIZE / 1024;
+  kprintln!("{:<6} {:<6} {:<8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8}",
+    "pid", "group", "state", "stack", "image", "heap", "rss", "peak", "limit");
+  SCHEDULER.critical(|scheduler| {
+    for p in scheduler.processes() {
+      let state = match p.state {
+        State::Ready => "ready",
+        State::Running => "running",
+        State::Waiting(_) => "waiting",
+        State::Dead => "dead",
+      };
+      let limit = match p.mem_limit {
+        Some(pages) => format!("{}", kib(pages)),
+        None => String::from("-"),
+      };
+      kprintln!("{:<6} {:<6} {:<8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8}",
+        p.context.tpidr, p.group, state, kib(p.usage.stack), kib(p.usage.image),
+        kib(p.usage.heap), kib(p.usage.total()), kib(p.peak_pages), limit);
     }
-    Err(e) => kprintln!("ls: error: {:?}", e),
+  });
+}
+
+/// Prints the affinity mask of process `pid`, or sets it to `mask`, a
+/// hexadecimal number with an optional `0x` prefix.
+fn taskset(pid: u64, mask: Option<&str>) {
+  let mask = match mask {
+    None => return match SCHEDULER.affinity(pid) {
+      Some(mask) => kprintln!("pid {} affinity {:#x}", pid, mask),
+      None => fail!("taskset: no process with pid {}", pid),
+    },
+    Some(mask) => mask,
+  };
+  let digits = mask.trim_start_matches("0x");
+  match u64::from_str_radix(digits, 16) {
+    Ok(mask) => if let Err(e) = SCHEDULER.set_affinity(pid, mask) {
+      fail!("taskset: {}", e);
+    }
+    Err(_) => fail!("taskset: invalid mask {}", mask),
+  }
+}
+
+/// Prints how many processes are queued on each core, how many it has
+/// dispatched and how many of those it took from another core's queue, then
+/// each process that has migrated.
+fn schedstat() {
+  let stats = SCHEDULER.stats();
+  SCHEDULER.critical(|scheduler| {
+    kprintln!("{:<6} {:>8} {:>10} {:>10}", "core", "queued", "dispatch", "migrate");
+    for (core, stats) in stats.iter().enumerate() {
+      kprintln!("{:<6} {:>8} {:>10} {:>10}",
+        core, scheduler.queued(core), stats.dispatches, stats.migrations);
+    }
+    for p in scheduler.processes().filter(|p| p.migrations > 0) {
+      kprintln!("pid {:<4} migrated {} times", p.context.tpidr, p.migrations);
+    }
+  });
+}
+
+/// Prints the kernel function containing `addr`, a hexadecimal address with
+/// an optional `0x` prefix, and how far into it `addr` is.
+fn sym(addr: &str) {
+  let value = match u64::from_str_radix(addr.trim_start_matches("0x"), 16) {
+    Ok(value) => value,
+    Err(_) => return fail!("sym: invalid address {}", addr),
+  };
+  match ksyms::lookup(value) {
+    Some(symbol) => kprintln!("{:#018x} {}", value, symbol),
+    None if ksyms::count() == 0 => fail!("sym: no symbol table in this image"),
+    None => kprintln!("{:#018x} ?", value),
+  }
+}
+
+/// Starts streaming telemetry samples every `ms` milliseconds.
+fn telemetry_start(ms: u64) {
+  if let Err(e) = telemetry::start(Duration::from_millis(ms)) {
+    fail!("telemetry: {:?}", e);
+  }
+}
+
+/// Prints whether telemetry is being streamed and the counters it samples.
+fn telemetry_status() {
+  match telemetry::interval() {
+    Some(interval) => kprintln!("streaming every {}ms", interval.as_millis()),
+    None => kprintln!("stopped"),
+  }
+  let s = telemetry::sample();
+  kprintln!("uptime      {}.{:06}s", s.uptime / 1_000_000, s.uptime % 1_000_000);
+  kprintln!("heap        {} of {} bytes free", s.heap_free, s.heap_size);
+  kprintln!("processes   {} ({} ready)", s.processes, s.ready);
+  kprintln!("dispatches  {}", s.dispatches);
+  kprintln!("irqs        {} ({}us handling)", s.irqs, s.irq_time);
+  kprintln!("fs cache    {} sectors ({} dirty), {} hits, {} misses",
+    s.cached_sectors, s.dirty_sectors, s.cache_hits, s.cache_misses);
+}
+
+/// Prints how often the console and scheduler locks have been taken, how
+/// often they were found held by another process, and which process holds
+/// them now along with the priority it has been lent.
+fn lockstat() {
+  use crate::mutex;
+
+  kprintln!("{:<10} {:>10} {:>10} {:>8} {:>8}", "lock", "taken", "contended", "holder", "lent");
+  let locks = [("console", CONSOLE.stats()), ("scheduler", SCHEDULER.lock_stats())];
+  for &(name, stats) in locks.iter() {
+    let (holder, lent) = match stats.holder {
+      Some(pid) => (format!("{}", pid), format!("{:#x}", mutex::inherited(pid))),
+      None => (String::from("-"), String::from("-")),
+    };
+    kprintln!("{:<10} {:>10} {:>10} {:>8} {:>8}",
+      name, stats.acquisitions, stats.contentions, holder, lent);
+  }
+}
+
+/// Prints how often each interrupt has been taken and the time spent in its
+/// handlers. `mean` is the mean time in the IRQ path per call; `thread` is
+/// the time the threaded handler, if any, has run.
+fn irqstat() {
+  use pi::interrupt::Interrupt;
+
+  let stats = IRQ.stats();
+  kprintln!("{:<8} {:>10} {:>10} {:>10} {:>8} {:>10}",
+    "irq", "count", "unhandled", "time(us)", "mean(us)", "thread(us)");
+  for &int in Interrupt::iter() {
+    let s = &stats[Interrupt::to_index(int)];
+    let handled = s.count - s.unhandled;
+    let mean = if handled == 0 { 0 } else { s.time.as_micros() / handled as u128 };
+    kprintln!("{:<8} {:>10} {:>10} {:>10} {:>8} {:>10}",
+      traps::irq::name(int), s.count, s.unhandled, s.time.as_micros(), mean,
+      s.thread_time.as_micros());
+  }
+}
+
+fn systimer() {
+  use pi::timer::Channel;
+  use systimer::Owner;
+
+  for &channel in Channel::ALL.iter() {
+    match systimer::owner(channel) {
+      Owner::Free => kprintln!("channel {}: free", channel as usize),
+      Owner::Claimed(name) => kprintln!("channel {}: claimed by {}", channel as usize, name),
+      Owner::Shared => kprintln!("channel {}: shared", channel as usize),
+    }
+  }
+  match systimer::pending() {
+    (0, _) => kprintln!("no deadlines pending"),
+    (count, Some(next)) => {
+      let until = next.checked_sub(pi::timer::current_time()).unwrap_or_default();
+      kprintln!("{} deadlines pending, the next in {}us", count, until.as_micros());
+    }
+    (count, None) => kprintln!("{} deadlines pending", count),
+  }
+}
+
+fn vminfo() {
+  let (sctlr, ttbr0, ttbr1) = unsafe {
+    use aarch64::{SCTLR_EL1, TTBR0_EL1, TTBR1_EL1};
+    (SCTLR_EL1.get(), TTBR0_EL1.get(), TTBR1_EL1.get())
+  };
+  let on = |bit| if sctlr & bit != 0 { "on" } else { "off" };
+  kprintln!("mmu {}, dcache {}, icache {}",
+    on(aarch64::SCTLR_EL1::M), on(aarch64::SCTLR_EL1::C), on(aarch64::SCTLR_EL1::I));
+  kprintln!("TTBR0    {:#x}", ttbr0);
+  kprintln!("TTBR1    {:#x}", ttbr1);
+  kprintln!("heap     {:#x} past the kernel image", ALLOCATOR.base_offset());
+  match TranslationConfig::current() {
+    Some(config) => {
+      kprintln!("{}", config);
+      if config != VMM.translation_config() {
+        kprintln!("(not the configuration the VMM sets up)");
+      }
+    }
+    None => fail!("vminfo: TCR_EL1 has a reserved granule"),
+  }
+}
+
+fn show_config() {
+  let config = config::get();
+  let (uart, uart_config) = {
+    let mut console = CONSOLE.lock();
+    (console.uart(), console.uart_config())
+  };
+  kprintln!("source          {}", if config.loaded { config::CONFIG_FILE } else { "defaults" });
+  kprintln!("cmdline         {}", cmdline::get().unwrap_or(""));
+  kprintln!("console         {},{}", uart.name(), uart_config.baud);
+  kprintln!("log_level       {}", logger::max_level().name());
+  kprintln!("sched_tick      {:?}", config.sched_tick);
+  kprintln!("init_processes  {}", config.init_processes);
+  kprintln!("core_limit      {}", config.core_limit);
+  kprintln!("init_caps       {}", config::caps_names(config.init_caps));
+  match config.display {
+    Some(ref panel) => kprintln!("display         {}", display::config_name(panel)),
+    None => kprintln!("display         none"),
+  }
+  for (name, on) in config::features() {
+    kprintln!("feature.{:<8} {}", name, if on { "on" } else { "off" });
+  }
+}
+
+#[cfg(feature = "heap-track")]
+fn heapdump(mark: bool) {
+  use crate::allocator::track::TRACKER;
+
+  let mut tracker = TRACKER.lock();
+  if mark {
+    kprintln!("heapdump: started epoch {}", tracker.mark());
+    return;
+  }
+  // Size classes match the bins of the bin allocator: class k holds
+  // allocations in (2^(k+2), 2^(k+3)].
+  let mut counts = [0usize; 30];
+  let mut bytes = [0usize; 30];
+  for a in tracker.live() {
+    let class = (a.size.next_power_of_two().trailing_zeros() as usize).saturating_sub(3).min(29);
+    counts[class] += 1;
+    bytes[class] += a.size;
+  }
+  kprintln!("{: <12} {: >8} {: >12}", "size class", "count", "live bytes");
+  for class in 0..counts.len() {
+    if counts[class] > 0 {
+      kprintln!("{: <12} {: >8} {: >12}", 1usize << (class + 3), counts[class], bytes[class]);
+    }
+  }
+  kprintln!("total: {} bytes in {} allocations ({} untracked)",
+    bytes.iter().sum::<usize>(), counts.iter().sum::<usize>(), tracker.dropped());
+  if tracker.epoch() > 0 {
+    kprintln!("live allocations since epoch {}:", tracker.epoch());
+    for a in tracker.leaks() {
+      kprintln!("  {:#010x} size {: <8} align {: <5} caller {:#010x}", a.addr, a.size, a.align, a.caller);
+    }
+  }
+}
+
+#[cfg(not(feature = "heap-track"))]
+fn heapdump(_mark: bool) {
+  fail!("heapdump: kernel built without the heap-track feature");
+}
+
+/// Prints the temperature measured by each DS18B20 sensor on the 1-Wire bus
+/// on GPIO pin `pin`.
+fn temp(pin: u8) {
+  use pi::ds18b20;
+  use pi::onewire::OneWire;
+
+  let mut bus = OneWire::new(pin);
+  let sensors = match bus.search().collect::<Result<Vec<_>, _>>() {
+    Ok(roms) => roms.into_iter().filter(|rom| rom.family() == ds18b20::FAMILY).collect::<Vec<_>>(),
+    Err(e) => return fail!("temp: bus error: {:?}", e),
+  };
+  if sensors.is_empty() {
+    return fail!("temp: no DS18B20 on GPIO {}", pin);
+  }
+  // All sensors measure at once.
+  if let Err(e) = ds18b20::convert(&mut bus, None) {
+    return fail!("temp: conversion failed: {:?}", e);
+  }
+  for rom in sensors.iter() {
+    match ds18b20::read(&mut bus, rom) {
+      Ok(t) => kprintln!("{}  {} C", rom, t),
+      Err(e) => kprintln!("{}  error: {:?}", rom, e),
+    }
+  }
+}
+
+/// Prints the panel in use and whether the console is mirrored onto it.
+fn display_status() {
+  match display::panel() {
+    Some((panel, width, height)) => {
+      kprintln!("{} {}x{}, console {}", panel.name(), width, height,
+        if display::is_mirroring() { "mirrored" } else { "not mirrored" });
+    }
+    None => kprintln!("no display"),
+  }
+}
+
+/// Draws color bars, a border and the panel's size, to check its wiring and
+/// orientation.
+fn display_test() {
+  use display::{rgb565, Rect};
+
+  const BARS: [(u8, u8, u8); 8] = [
+    (255, 255, 255), (255, 255, 0), (0, 255, 255), (0, 255, 0),
+    (255, 0, 255), (255, 0, 0), (0, 0, 255), (0, 0, 0),
+  ];
+
+  let drawn = display::draw(|fb| {
+    let (width, height) = fb.size();
+    let bar = width / BARS.len();
+    for (i, &(r, g, b)) in BARS.iter().enumerate() {
+      fb.fill_rect(Rect::new(i * bar, 0, bar, height), rgb565(r, g, b));
+    }
+    fb.draw_rect(Rect::new(0, 0, width, height), display::WHITE);
+    let label = format!("{}x{}", width, height);
+    fb.draw_text(8, 8, &label, display::WHITE, display::BLACK);
+  });
+  if drawn.is_none() {
+    fail!("display: no display");
+  }
+}
+
+/// Clears the panel and draws `text`, wrapped at its right edge.
+fn display_text(text: &str) {
+  use crate::console::font;
+
+  let drawn = display::draw(|fb| {
+    fb.clear(display::BLACK);
+    let cols = (fb.size().0 / font::WIDTH).max(1);
+    for (i, c) in text.chars().enumerate() {
+      let byte = if c.is_ascii() { c as u8 } else { b'?' };
+      let (x, y) = (i % cols * font::WIDTH, i / cols * font::HEIGHT);
+      fb.draw_char(x, y, byte, display::WHITE, display::BLACK);
+    }
+  });
+  if drawn.is_none() {
+    fail!("display: no display");
+  }
+}
+
+/// Prints the HDMI console's mode and the modes the display supports, its
+/// preferred one marked with `*`.
+fn vidmode_list() {
+  match video::current_size() {
+    Some((width, height)) => kprintln!("console {}x{}", width, height),
+    None => kprintln!("console off"),
+  }
+  let preferred = video::edid().and_then(|edid| edid.preferred());
+  let modes = video::modes();
+  if modes.is_empty() {
+    kprintln!("no EDID; the display's modes are unknown");
+  }
+  for mode in modes {
+    let mark = match preferred {
+      Some(p) if (p.width, p.height) == (mode.width, mode.height) => '*',
+      _ => ' ',
+    };
+    kprintln!("{} {}", mark, mode);
+  }
+}
+
+/// Plays a square wave of `hz` Hz for `ms` milliseconds and waits for it to
+/// finish.
+fn tone(hz: u32, ms: u32) {
+  const RATE: u32 = 44100;
+  const AMPLITUDE: i16 = 8000;
+
+  if hz == 0 || hz > RATE / 2 {
+    fail!("tone: frequency must be between 1 and {} Hz", RATE / 2);
+    return;
+  }
+  let len = (RATE as u64 * ms as u64 / 1000) as usize;
+  let samples: Vec<i16> = (0..len)
+    .map(|i| if (i as u64 * 2 * hz as u64 / RATE as u64) % 2 == 0 { AMPLITUDE } else { -AMPLITUDE })
+    .collect();
+  match crate::audio::play_pcm(&samples, RATE) {
+    Ok(()) => crate::AUDIO.wait(),
+    Err(e) => fail!("tone: error: {:?}", e),
   }
 }
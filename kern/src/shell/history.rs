@@ -0,0 +1,151 @@
+//! The shell's command history, kept in `HISTORY_FILE` across boots.
+//!
+//! The history is loaded when the first shell starts, and written back
+//! every `SAVE_INTERVAL` new entries and when a shell exits. It keeps the
+//! newest `history.size` entries, `DEFAULT_SIZE` if the setting is unset.
+//! Shells share the history: one started from the debugger recalls the
+//! lines of the shell it interrupted.
+
+use alloc::string::String;
+use core::fmt::Write as _;
+
+use fat32::traits::FileSystem as _;
+use fat32::vfat::Dir;
+use shim::io::{self, Write as _};
+use shim::path::PathBuf;
+
+use crate::console::line::{self, History, LineDiscipline};
+use crate::console::{kprintln, Console};
+use crate::fs::PiVFatHandle;
+use crate::mutex::Mutex;
+use crate::settings;
+use crate::FILESYSTEM;
+
+/// The file holding the history, one entry per line, oldest first.
+pub const HISTORY_FILE: &str = "/.history";
+
+/// The file the history is written to before it replaces `HISTORY_FILE`, so
+/// that a failed write leaves the old history.
+const NEW_HISTORY_FILE: &str = ".history.new";
+
+/// The entries kept while `history.size` is unset.
+const DEFAULT_SIZE: usize = 100;
+
+/// How many new entries are written back at once.
+const SAVE_INTERVAL: usize = 16;
+
+struct State {
+    history: History,
+    /// The entries added since the history was last written.
+    unsaved: usize,
+}
+
+/// The history, once loaded. It is taken out while a line is read, so that
+/// a shell started from the debugger meanwhile does without.
+static HISTORY: Mutex<Option<State>> = Mutex::new(None);
+
+/// Returns the number of entries kept.
+fn size() -> usize {
+    settings::get("history.size").and_then(|size| size.parse().ok()).unwrap_or(DEFAULT_SIZE)
+}
+
+/// Loads the history from `HISTORY_FILE` unless it is loaded already. A
+/// missing or unreadable file starts an empty history.
+pub fn load() {
+    let mut state = HISTORY.lock();
+    if state.is_some() {
+        return;
+    }
+    let mut history = History::new(size());
+    if let Ok(text) = super::read_text(PathBuf::from(HISTORY_FILE)) {
+        for line in text.lines() {
+            history.push(line);
+        }
+    }
+    *state = Some(State { history, unsaved: 0 });
+}
+
+/// Reads a line from `console` with `discipline`, recalling the history's
+/// entries with Up and Down once it is loaded.
+pub fn read_line(discipline: &mut LineDiscipline, console: &mut Console, buf: &mut [u8]) -> Result<usize, line::Error> {
+    let state = HISTORY.lock().take();
+    let result = match state {
+        Some(ref state) => discipline.read_line_with_history(console, buf, &state.history),
+        None => discipline.read_line(console, buf),
+    };
+    if state.is_some() {
+        *HISTORY.lock() = state;
+    }
+    result
+}
+
+/// Adds `line` to the history, writing the history back once enough
+/// entries are new.
+pub fn add(line: &str) {
+    let mut guard = HISTORY.lock();
+    let state = match guard.as_mut() {
+        Some(state) => state,
+        None => return,
+    };
+    state.history.set_max(size());
+    if state.history.push(line) {
+        state.unsaved += 1;
+    }
+    if state.unsaved >= SAVE_INTERVAL {
+        // Failures are retried with the next entry, and reported by
+        // `history -w`.
+        let _ = write(state);
+    }
+}
+
+/// Writes the history to `HISTORY_FILE` if it has unsaved entries.
+pub fn save() -> io::Result<()> {
+    match HISTORY.lock().as_mut() {
+        Some(state) if state.unsaved > 0 => write(state),
+        _ => Ok(()),
+    }
+}
+
+/// Prints the history, numbered from the oldest entry.
+pub fn print() {
+    if let Some(state) = HISTORY.lock().as_ref() {
+        for (i, line) in state.history.iter().enumerate() {
+            kprintln!("{:5}  {}", i + 1, line);
+        }
+    }
+}
+
+/// Forgets every entry, in `HISTORY_FILE` too.
+pub fn clear() -> io::Result<()> {
+    match HISTORY.lock().as_mut() {
+        Some(state) => {
+            state.history.clear();
+            write(state)
+        }
+        None => Ok(()),
+    }
+}
+
+fn write(state: &mut State) -> io::Result<()> {
+    let mut text = String::new();
+    for line in state.history.iter() {
+        let _ = writeln!(text, "{}", line);
+    }
+    let root = FILESYSTEM.open_dir("/")?;
+    remove(&root, NEW_HISTORY_FILE)?;
+    let mut file = root.create_file(NEW_HISTORY_FILE)?;
+    file.write_all(text.as_bytes())?;
+    file.flush()?;
+    remove(&root, &HISTORY_FILE[1..])?;
+    root.rename(NEW_HISTORY_FILE, &HISTORY_FILE[1..])?;
+    state.unsaved = 0;
+    Ok(())
+}
+
+/// Removes the file `name` from `dir` if it exists.
+fn remove(dir: &Dir<PiVFatHandle>, name: &str) -> io::Result<()> {
+    match dir.remove(name) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
@@ -0,0 +1,182 @@
+//! Paging long output a screenful at a time, for `more`.
+//!
+//! The terminal's size is taken from the `console.rows` and `console.cols`
+//! settings. When they are unset, the terminal is asked with a cursor
+//! position report: the cursor is moved as far down and right as it goes and
+//! the terminal replies with where it ended up. Terminals that do not reply
+//! in time are taken to be 24x80.
+
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::console::line::{Key, LineDiscipline};
+use crate::console::{kprint, kprintln, CONSOLE};
+use crate::settings;
+
+/// The size of a terminal that does not report one.
+const DEFAULT_ROWS: usize = 24;
+const DEFAULT_COLS: usize = 80;
+
+/// How long the terminal has to report the cursor position.
+const REPORT_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// The longest cursor position report: `ESC [ rows ; cols R`.
+const MAX_REPORT: usize = 16;
+
+const ESC: char = '\x1b';
+
+/// Prints `text`, pausing after each screenful until a key is pressed:
+///
+///   * space, `f` or Page Down shows the next screenful
+///   * Enter, `j` or Down shows the next line
+///   * `d` shows the next half screenful
+///   * `b` or Page Up shows the screenful before the one shown
+///   * `q`, Ctrl-C or Ctrl-D stops
+///
+/// Text that fits on the screen is printed as is.
+pub fn page(text: &str) {
+    let (rows, cols) = terminal_size();
+    let lines = wrap(text, cols);
+    let page = rows.saturating_sub(1).max(1);
+    if lines.len() <= page {
+        kprint!("{}", text);
+        return;
+    }
+
+    let mut console = CONSOLE.lock();
+    let mut discipline = LineDiscipline::new();
+    // The lines shown end before `shown`.
+    let mut shown = show(&lines, 0, page);
+    while shown < lines.len() {
+        kprint!("\x1b[7m--More-- ({}%)\x1b[0m", shown * 100 / lines.len());
+        let key = discipline.read_key(&mut *console);
+        kprint!("\r\x1b[K");
+        shown = match key {
+            Key::Char(b' ') | Key::Char(b'f') | Key::PageDown => show(&lines, shown, page),
+            Key::Enter | Key::Char(b'j') | Key::Down => show(&lines, shown, 1),
+            Key::Char(b'd') => show(&lines, shown, (page / 2).max(1)),
+            Key::Char(b'b') | Key::PageUp => show(&lines, shown.saturating_sub(2 * page), page),
+            Key::Char(b'q') | Key::Interrupt | Key::EndOfFile => return,
+            _ => shown,
+        };
+    }
+}
+
+/// Prints up to `count` of `lines` from `from`, and returns the index of the
+/// line after the last one printed.
+fn show(lines: &[&str], from: usize, count: usize) -> usize {
+    let to = (from + count).min(lines.len());
+    for line in &lines[from..to] {
+        kprintln!("{}", line);
+    }
+    to
+}
+
+/// Returns the terminal's size as `(rows, cols)`.
+fn terminal_size() -> (usize, usize) {
+    let setting = |key| settings::get(key).and_then(|value| value.parse::<usize>().ok());
+    if let (Some(rows), Some(cols)) = (setting("console.rows"), setting("console.cols")) {
+        return (rows, cols);
+    }
+    query_size().unwrap_or((DEFAULT_ROWS, DEFAULT_COLS))
+}
+
+/// Asks the terminal for its size with a cursor position report, restoring
+/// the cursor after. Returns `None` if no report arrives in time.
+fn query_size() -> Option<(usize, usize)> {
+    let mut console = CONSOLE.lock();
+    kprint!("\x1b7\x1b[999;999H\x1b[6n\x1b8");
+    console.flush();
+    let deadline = pi::timer::current_time() + REPORT_TIMEOUT;
+    let mut report = [0u8; MAX_REPORT];
+    let mut len = 0;
+    while pi::timer::current_time() < deadline {
+        console.poll_input();
+        if !console.has_input() {
+            continue;
+        }
+        // Keys typed before the query come ahead of the report; they are
+        // dropped with it.
+        len += console.take_input(&mut report[len..]);
+        if let Some(size) = parse_report(&report[..len]) {
+            return Some(size);
+        }
+        if len == report.len() {
+            len = 0;
+        }
+    }
+    None
+}
+
+/// Parses the cursor position report `ESC [ rows ; cols R` at the end of
+/// `input`.
+fn parse_report(input: &[u8]) -> Option<(usize, usize)> {
+    let input = core::str::from_utf8(input).ok()?;
+    let report = &input[input.rfind(ESC)?..];
+    if !report.starts_with("\x1b[") || !report.ends_with('R') {
+        return None;
+    }
+    let mut numbers = report[2..report.len() - 1].split(';');
+    let rows = numbers.next()?.parse().ok()?;
+    let cols = numbers.next()?.parse().ok()?;
+    match numbers.next() {
+        None if rows > 0 && cols > 0 => Some((rows, cols)),
+        _ => None,
+    }
+}
+
+/// Splits `text` into the lines it takes on a terminal `cols` columns wide:
+/// at newlines, and where a line is longer than `cols` characters. The
+/// escape sequences of colored output take no columns.
+fn wrap(text: &str, cols: usize) -> Vec<&str> {
+    let cols = cols.max(1);
+    let mut lines = Vec::new();
+    for line in text.trim_end_matches('\n').split('\n') {
+        let line = line.trim_end_matches('\r');
+        let mut start = 0;
+        let mut width = 0;
+        let mut escape = false;
+        for (i, c) in line.char_indices() {
+            if escape {
+                // A sequence ends with a letter, after `[` and parameters.
+                escape = !c.is_ascii_alphabetic();
+                continue;
+            }
+            if c == ESC {
+                escape = true;
+                continue;
+            }
+            if width == cols {
+                lines.push(&line[start..i]);
+                start = i;
+                width = 0;
+            }
+            width += 1;
+        }
+        lines.push(&line[start..]);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_long_lines() {
+        assert_eq!(wrap("abcdef\nxy\n\nz\n", 4), ["abcd", "ef", "xy", "", "z"]);
+        assert_eq!(wrap("abcd\r\n", 4), ["abcd"]);
+        assert_eq!(wrap("\u{e9}\u{e9}\u{e9}", 2), ["\u{e9}\u{e9}", "\u{e9}"]);
+        // Escape sequences take no columns.
+        assert_eq!(wrap("\x1b[31mabc\x1b[0m", 3), ["\x1b[31mabc\x1b[0m"]);
+    }
+
+    #[test]
+    fn parses_cursor_reports() {
+        assert_eq!(parse_report(b"\x1b[48;160R"), Some((48, 160)));
+        assert_eq!(parse_report(b"x\x1b[24;80R"), Some((24, 80)));
+        assert_eq!(parse_report(b"\x1b[24;80"), None);
+        assert_eq!(parse_report(b"\x1b[0;80R"), None);
+        assert_eq!(parse_report(b"\x1b[24R"), None);
+    }
+}
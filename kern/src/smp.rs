@@ -0,0 +1,122 @@
+//! Secondary core bring-up.
+//!
+//! `start_cores()` gives each secondary core a kernel stack and wakes it
+//! through the firmware's spin table. A woken core enters `_start_secondary`
+//! in `init`, drops to EL1 as the boot core did, turns its MMU on with the
+//! kernel page table, starts its scheduler tick and parks in `idle()` until
+//! the scheduler can run on more than one core.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use aarch64::atomic;
+use aarch64::{clean_dcache_range, flush_tlb, wfe, wfi};
+use pi::timer::current_time;
+
+use crate::logger::{info, warn};
+use crate::mutex::Mutex;
+use crate::param::NCORES;
+use crate::process::Stack;
+use crate::traps::ipi;
+use crate::{SCHEDULER, VMM};
+
+/// How long a woken core has to come online.
+const ONLINE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The initial stack pointer of each core, read by `_start_secondary` with
+/// the MMU and caches off.
+pub(crate) static STACK_TOPS: [AtomicU64; NCORES] =
+    [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
+
+/// The stacks of the secondary cores, kept so they are never freed.
+static STACKS: Mutex<Vec<Stack>> = Mutex::new(Vec::new());
+
+/// A mask of the cores that are online. The boot core always is.
+static ONLINE: AtomicU64 = AtomicU64::new(1);
+
+/// Wakes every secondary core and waits for each to come online. Returns
+/// the number of cores online, the boot core included.
+///
+/// The caller should assure that `VMM` has been initialized, and that this
+/// is called once.
+pub fn start_cores() -> usize {
+    extern "C" {
+        fn _start_secondary() -> !;
+    }
+
+    for core in 1..NCORES {
+        let mut stack = match Stack::new() {
+            Some(stack) => stack,
+            None => {
+                warn!("smp: no memory for the stack of core {}", core);
+                break;
+            }
+        };
+        stack.install_guard();
+        let top = stack.top().as_u64();
+        STACKS.lock().push(stack);
+
+        STACK_TOPS[core].store(top, Ordering::Relaxed);
+        unsafe {
+            clean_dcache_range(&STACK_TOPS[core] as *const _ as usize, 8);
+            pi::cores::wake(core, _start_secondary as usize);
+        }
+
+        let start = current_time();
+        while !is_online(core) {
+            if current_time() - start > ONLINE_TIMEOUT {
+                warn!("smp: core {} did not come online", core);
+                break;
+            }
+        }
+    }
+
+    let count = online().count_ones() as usize;
+    info!("smp: {} of {} cores online", count, NCORES);
+    count
+}
+
+/// Returns the mask of cores that are online.
+pub fn online() -> u64 {
+    atomic::load_acquire(&ONLINE)
+}
+
+/// Returns whether core `core` is online.
+pub fn is_online(core: usize) -> bool {
+    online() & (1 << core) != 0
+}
+
+/// Finishes bringing up core `core`, which runs at EL1 on its own stack:
+/// enables its MMU with the kernel page table, starts its tick and marks it
+/// online.
+pub(crate) fn secondary_main(core: usize) -> ! {
+    unsafe {
+        flush_tlb();
+    }
+    VMM.setup();
+    ipi::enable();
+    SCHEDULER.start_tick();
+    atomic::fetch_or(&ONLINE, 1 << core);
+    idle()
+}
+
+/// Where secondary cores wait. They run nothing yet; this is where the
+/// scheduler will dispatch processes on them. IRQs stay masked, but an IPI
+/// or the tick still ends `wfi`, and is handled here.
+fn idle() -> ! {
+    loop {
+        wfi();
+        ipi::poll();
+        SCHEDULER.poll_tick();
+    }
+}
+
+/// Takes core `core`, the calling core, offline for good.
+pub(crate) fn halt(core: usize) -> ! {
+    unsafe { aarch64::cli() };
+    atomic::fetch_and(&ONLINE, !(1 << core));
+    loop {
+        wfe();
+    }
+}
@@ -0,0 +1,211 @@
+//! Paging user memory out to the SD card.
+//!
+//! When free memory drops below `LOW_WATERMARK`, least recently used user
+//! pages are written to `SWAP_FILE` and unmapped until it is back above
+//! `HIGH_WATERMARK`. This happens in the pageout thread, which checks every
+//! `PAGEOUT_INTERVAL`, and in the page fault path before a page is mapped,
+//! so a process allocating faster than the thread runs reclaims for itself.
+//! A swapped out page's entry records its slot in the file, and the next
+//! access to the page faults and reads it back in.
+//!
+//! Recency is tracked with the access flag: each process keeps its pages
+//! on its `lru` list, and a page at the front whose flag is set is passed
+//! over once, with its flag cleared. An access to the page then faults,
+//! which sets the flag again.
+//!
+//! Like crash dumps, swap bypasses the file system: `SWAP_FILE` must be
+//! created with a fixed size before boot, and its sectors are written
+//! directly, so the sector cache never holds swapped pages.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use fat32::traits::{BlockDevice, Entry, FileSystem};
+use shim::io;
+use shim::ioerr;
+
+use crate::console::kprintln;
+use crate::fs::Sd;
+use crate::logger::{info, warn};
+use crate::mutex::Mutex;
+use crate::param::PAGE_SIZE;
+use crate::process::{Id, Process, Scheduler};
+use crate::vm::{self, VirtualAddr};
+use crate::{ALLOCATOR, FILESYSTEM, SCHEDULER};
+
+/// The file pages are swapped out to. Each `PAGE_SIZE` bytes of it hold one
+/// page.
+pub const SWAP_FILE: &str = "/swap.sys";
+
+/// The number of SD card sectors a page takes.
+const SECTORS_PER_PAGE: usize = PAGE_SIZE / 512;
+
+/// Below this many free bytes, pages are swapped out.
+const LOW_WATERMARK: usize = 16 * 1024 * 1024;
+
+/// Pages are swapped out until this many bytes are free.
+const HIGH_WATERMARK: usize = 24 * 1024 * 1024;
+
+/// How often the pageout thread checks free memory.
+const PAGEOUT_INTERVAL: Duration = Duration::from_millis(100);
+
+struct Swap {
+    sd: Sd,
+    /// The sectors of `SWAP_FILE`, in order.
+    sectors: Vec<u64>,
+    /// Whether each slot holds a page.
+    used: Vec<bool>,
+}
+
+impl Swap {
+    /// Returns the first free slot, if any.
+    fn free_slot(&self) -> Option<usize> {
+        self.used.iter().position(|&used| !used)
+    }
+
+    /// Writes the page of `p` mapped at `va` to slot `slot` and unmaps it.
+    fn page_out(&mut self, p: &mut Process, va: VirtualAddr, slot: usize) -> io::Result<()> {
+        let phys = match p.vmap.page_addr(va) {
+            Some(phys) => phys,
+            None => return ioerr!(InvalidInput, "page not mapped"),
+        };
+        // The kernel maps all of physical memory.
+        let page = unsafe { core::slice::from_raw_parts(phys.as_usize() as *const u8, PAGE_SIZE) };
+        for (i, chunk) in page.chunks(512).enumerate() {
+            self.sd.write_sector(self.sectors[slot * SECTORS_PER_PAGE + i], chunk)?;
+        }
+        self.used[slot] = true;
+        p.swap_out(va, slot);
+        Ok(())
+    }
+}
+
+/// Set once swapping is enabled with `enable()`.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static SWAP: Mutex<Option<Swap>> = Mutex::new(None);
+
+/// Swaps user pages out to `SWAP_FILE` under memory pressure from now on,
+/// and starts the pageout thread. Does nothing if the file is missing or
+/// holds less than a page.
+///
+/// The caller should assure that `FILESYSTEM` and `SCHEDULER` have been
+/// initialized. Returns the ID of the pageout thread.
+pub fn enable() -> Option<Id> {
+    let sd = FILESYSTEM.device()?;
+    let sectors = (&FILESYSTEM)
+        .open(SWAP_FILE)
+        .ok()
+        .and_then(|entry| entry.into_file())
+        .and_then(|file| file.sectors().ok())
+        .filter(|sectors| sectors.len() >= SECTORS_PER_PAGE);
+    let sectors = match sectors {
+        Some(sectors) => sectors,
+        None => {
+            warn!("swap: no {} of at least one page; swapping disabled", SWAP_FILE);
+            return None;
+        }
+    };
+    let thread = match Process::kernel_thread(pageout_thread) {
+        Ok(thread) => thread,
+        Err(e) => {
+            kprintln!("swap: cannot start pageout thread: {:?}", e);
+            return None;
+        }
+    };
+
+    let slots = sectors.len() / SECTORS_PER_PAGE;
+    *SWAP.lock() = Some(Swap { sd, sectors, used: vec![false; slots] });
+    ENABLED.store(true, Ordering::Release);
+    // Processes loaded so far have pages that are on no list yet.
+    SCHEDULER.critical(|scheduler| {
+        for p in scheduler.processes_mut() {
+            p.track_pages();
+        }
+    });
+    info!("swap: {} pages in {}", slots, SWAP_FILE);
+    SCHEDULER.add(thread)
+}
+
+/// Returns `true` if swapping is enabled.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Acquire)
+}
+
+/// Swaps pages out with `reclaim()` if free memory is below
+/// `LOW_WATERMARK`.
+pub fn balance(scheduler: &mut Scheduler) {
+    if enabled() && ALLOCATOR.free_bytes() < LOW_WATERMARK {
+        reclaim(scheduler);
+    }
+}
+
+/// Swaps out pages of the processes in `scheduler`, the least recently
+/// used of each process in turn, until free memory is back above
+/// `HIGH_WATERMARK`, the swap file is full or no process has a page left.
+/// Returns the number of pages swapped out.
+pub fn reclaim(scheduler: &mut Scheduler) -> usize {
+    let mut guard = SWAP.lock();
+    let swap = match guard.as_mut() {
+        Some(swap) => swap,
+        None => return 0,
+    };
+    let mut count = 0;
+    let mut progress = true;
+    'reclaim: while progress && ALLOCATOR.free_bytes() < HIGH_WATERMARK {
+        progress = false;
+        for p in scheduler.processes_mut() {
+            let slot = match swap.free_slot() {
+                Some(slot) => slot,
+                None => break 'reclaim,
+            };
+            let va = match p.next_victim() {
+                Some(va) => va,
+                None => continue,
+            };
+            if let Err(e) = swap.page_out(p, va, slot) {
+                p.lru.push_front(va);
+                warn!("swap: cannot write to {}: {:?}", SWAP_FILE, e);
+                break 'reclaim;
+            }
+            count += 1;
+            progress = true;
+            if ALLOCATOR.free_bytes() >= HIGH_WATERMARK {
+                break;
+            }
+        }
+    }
+    // Make the access flags cleared on the way fault again, on every core.
+    vm::shootdown();
+    count
+}
+
+/// Reads the page in slot `slot` into `page` and frees the slot.
+pub fn swap_in(slot: usize, page: &mut [u8]) -> io::Result<()> {
+    let mut guard = SWAP.lock();
+    let swap = match guard.as_mut() {
+        Some(swap) => swap,
+        None => return ioerr!(NotFound, "swap disabled"),
+    };
+    swap.used[slot] = false;
+    for (i, chunk) in page.chunks_mut(512).enumerate() {
+        swap.sd.read_sector(swap.sectors[slot * SECTORS_PER_PAGE + i], chunk)?;
+    }
+    Ok(())
+}
+
+/// Frees slot `slot`, whose page is no longer needed.
+pub fn release(slot: usize) {
+    if let Some(swap) = SWAP.lock().as_mut() {
+        swap.used[slot] = false;
+    }
+}
+
+extern "C" fn pageout_thread() -> ! {
+    loop {
+        let _ = kernel_api::syscall::sleep(PAGEOUT_INTERVAL);
+        SCHEDULER.critical(balance);
+    }
+}
@@ -0,0 +1,286 @@
+//! Sharing the system timer's compare channels, 1 and 3, between the
+//! kernel's users of them.
+//!
+//! A user that needs a channel to itself claims it with `claim()`, which
+//! registers its interrupt handler, and then arms it with
+//! `Timer::with_channel()`. Other users schedule deadlines with
+//! `schedule()`: these are multiplexed over one shared channel, armed for the
+//! earliest of them, and their callbacks run in the IRQ path once they are
+//! due. The shared channel is channel 3 unless it is claimed, and is given up
+//! again once no deadlines are pending.
+//!
+//! A channel is claimed only while it is neither claimed nor shared, so two
+//! users never arm the same compare register; the loser of a conflict gets
+//! `FileExists` instead of missing its interrupts.
+
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use kernel_api::{OsError, OsResult};
+use pi::interrupt::Controller;
+use pi::timer::{current_time, Channel, Timer};
+
+use crate::logger::warn;
+use crate::mutex::Mutex;
+use crate::traps::irq::{without_irqs, IrqHandler};
+use crate::traps::TrapFrame;
+use crate::IRQ;
+
+/// Who uses a channel.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Owner {
+    Free,
+    /// Claimed with `claim()` by the user named.
+    Claimed(&'static str),
+    /// Carrying the deadlines of `schedule()`.
+    Shared,
+}
+
+/// A deadline of `schedule()`: `callback(context)` runs once `at` has come.
+#[derive(Copy, Clone)]
+struct Deadline {
+    id: u64,
+    at: Duration,
+    callback: fn(usize),
+    context: usize,
+}
+
+/// The pending deadlines of `schedule()`.
+struct Deadlines {
+    /// Sorted by `at`; deadlines due at the same time in the order they were
+    /// scheduled.
+    pending: Vec<Deadline>,
+    next_id: u64,
+}
+
+impl Deadlines {
+    const fn new() -> Deadlines {
+        Deadlines { pending: Vec::new(), next_id: 1 }
+    }
+
+    /// Adds a deadline and returns its ID.
+    fn insert(&mut self, at: Duration, callback: fn(usize), context: usize) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        let index = self.pending.iter().position(|d| d.at > at).unwrap_or(self.pending.len());
+        self.pending.insert(index, Deadline { id, at, callback, context });
+        id
+    }
+
+    /// Removes deadline `id`. Returns `false` if it is not pending.
+    fn remove(&mut self, id: u64) -> bool {
+        match self.pending.iter().position(|d| d.id == id) {
+            Some(index) => {
+                self.pending.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns when the earliest deadline is due.
+    fn next(&self) -> Option<Duration> {
+        self.pending.first().map(|d| d.at)
+    }
+
+    /// Removes and returns the earliest deadline if it is due by `now`.
+    fn pop_due(&mut self, now: Duration) -> Option<Deadline> {
+        match self.pending.first() {
+            Some(d) if d.at <= now => Some(self.pending.remove(0)),
+            _ => None,
+        }
+    }
+}
+
+struct State {
+    /// The owner of each channel, indexed by `Channel::index()`.
+    owners: [Owner; 2],
+    deadlines: Deadlines,
+}
+
+/// The channels' owners and the pending deadlines. It is only locked with
+/// IRQs masked, as the IRQ path takes it too.
+static STATE: Mutex<State> = Mutex::new(State { owners: [Owner::Free; 2], deadlines: Deadlines::new() });
+
+/// Gives `channel` to the user `name`, registering `handler` for its
+/// interrupt and enabling the interrupt.
+///
+/// Returns `FileExists` if the channel is claimed or shared already, or its
+/// interrupt has a handler registered without claiming it.
+pub fn claim(channel: Channel, name: &'static str, handler: IrqHandler) -> OsResult<()> {
+    without_irqs(|| {
+        let mut state = STATE.lock();
+        let owner = state.owners[channel.index()];
+        if owner != Owner::Free {
+            warn!("systimer: {} cannot claim channel {}, used by {}", name, channel as usize, describe(owner));
+            return Err(OsError::FileExists);
+        }
+        if let Err(e) = IRQ.register(channel.interrupt(), handler) {
+            warn!("systimer: {} cannot claim channel {}: its interrupt has a handler", name, channel as usize);
+            return Err(e);
+        }
+        state.owners[channel.index()] = Owner::Claimed(name);
+        Controller::new().enable(channel.interrupt());
+        Ok(())
+    })
+}
+
+/// Gives back `channel`, claimed by `name`: disarms it, and disables and
+/// unregisters its interrupt.
+///
+/// Returns `InvalidArgument` if `name` does not hold the channel.
+pub fn release(channel: Channel, name: &'static str) -> OsResult<()> {
+    without_irqs(|| {
+        let mut state = STATE.lock();
+        if state.owners[channel.index()] != Owner::Claimed(name) {
+            return Err(OsError::InvalidArgument);
+        }
+        give_up(&mut state, channel);
+        Ok(())
+    })
+}
+
+/// Returns who uses `channel`.
+pub fn owner(channel: Channel) -> Owner {
+    without_irqs(|| STATE.lock().owners[channel.index()])
+}
+
+/// Runs `callback(context)` in the IRQ path once the time since boot reaches
+/// `at`, and returns an ID to cancel it with. A deadline already past runs
+/// at once. Deadlines due together run in the order they were scheduled.
+///
+/// The callback runs with IRQs masked and must not block.
+///
+/// Returns `FileExists` if no channel can be shared: both are claimed, or
+/// their interrupts have handlers registered without claiming them.
+pub fn schedule(at: Duration, callback: fn(usize), context: usize) -> OsResult<u64> {
+    without_irqs(|| {
+        let mut state = STATE.lock();
+        let channel = match shared(&state) {
+            Some(channel) => channel,
+            None => share(&mut state)?,
+        };
+        let id = state.deadlines.insert(at, callback, context);
+        if state.deadlines.next() == Some(at) {
+            Timer::with_channel(channel).arm_at(at);
+        }
+        Ok(id)
+    })
+}
+
+/// Cancels deadline `id`. Returns `false` if it has run or was cancelled
+/// already.
+pub fn cancel(id: u64) -> bool {
+    without_irqs(|| {
+        let mut state = STATE.lock();
+        let earliest = state.deadlines.next();
+        if !state.deadlines.remove(id) {
+            return false;
+        }
+        if state.deadlines.next() != earliest {
+            rearm(&mut state);
+        }
+        true
+    })
+}
+
+/// Returns the number of pending deadlines and when the earliest is due.
+pub fn pending() -> (usize, Option<Duration>) {
+    without_irqs(|| {
+        let state = STATE.lock();
+        (state.deadlines.pending.len(), state.deadlines.next())
+    })
+}
+
+/// Returns the shared channel, if one is.
+fn shared(state: &State) -> Option<Channel> {
+    Channel::ALL.iter().cloned().find(|c| state.owners[c.index()] == Owner::Shared)
+}
+
+/// Makes a free channel the shared one, channel 3 first so that channel 1 is
+/// left for claims.
+fn share(state: &mut State) -> OsResult<Channel> {
+    for &channel in [Channel::Three, Channel::One].iter() {
+        if state.owners[channel.index()] != Owner::Free {
+            continue;
+        }
+        if IRQ.register(channel.interrupt(), IrqHandler::new(expire, channel.index())).is_ok() {
+            state.owners[channel.index()] = Owner::Shared;
+            Controller::new().enable(channel.interrupt());
+            return Ok(channel);
+        }
+    }
+    warn!("systimer: no channel to schedule deadlines on");
+    Err(OsError::FileExists)
+}
+
+/// Arms the shared channel for the earliest deadline, or gives the channel up
+/// if none is pending.
+fn rearm(state: &mut State) {
+    if let Some(channel) = shared(state) {
+        match state.deadlines.next() {
+            Some(at) => Timer::with_channel(channel).arm_at(at),
+            None => give_up(state, channel),
+        }
+    }
+}
+
+/// Disarms `channel`, disables and unregisters its interrupt and frees it.
+fn give_up(state: &mut State, channel: Channel) {
+    Controller::new().disable(channel.interrupt());
+    Timer::with_channel(channel).disarm();
+    IRQ.unregister(channel.interrupt());
+    state.owners[channel.index()] = Owner::Free;
+}
+
+/// The interrupt handler of the shared channel, whose index is `index`: runs
+/// the callbacks of the deadlines that are due, one at a time so that they
+/// may schedule or cancel others, and arms the channel for the next.
+fn expire(index: usize, _: &mut TrapFrame) {
+    if !Timer::with_channel(Channel::ALL[index]).check_deadline() {
+        return;
+    }
+    let now = current_time();
+    loop {
+        let due = STATE.lock().deadlines.pop_due(now);
+        match due {
+            Some(deadline) => (deadline.callback)(deadline.context),
+            None => break,
+        }
+    }
+    rearm(&mut STATE.lock());
+}
+
+fn describe(owner: Owner) -> &'static str {
+    match owner {
+        Owner::Free => "free",
+        Owner::Claimed(name) => name,
+        Owner::Shared => "shared deadlines",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nothing(_: usize) {}
+
+    #[test]
+    fn orders_deadlines() {
+        let mut deadlines = Deadlines::new();
+        let ms = Duration::from_millis;
+        let late = deadlines.insert(ms(30), nothing, 0);
+        let first = deadlines.insert(ms(10), nothing, 1);
+        let second = deadlines.insert(ms(10), nothing, 2);
+        assert_eq!(deadlines.next(), Some(ms(10)));
+
+        assert_eq!(deadlines.pop_due(ms(5)).map(|d| d.id), None);
+        assert_eq!(deadlines.pop_due(ms(10)).map(|d| d.id), Some(first));
+        assert_eq!(deadlines.pop_due(ms(10)).map(|d| d.id), Some(second));
+        assert_eq!(deadlines.pop_due(ms(10)).map(|d| d.id), None);
+
+        assert!(deadlines.remove(late));
+        assert!(!deadlines.remove(late));
+        assert_eq!(deadlines.next(), None);
+    }
+}
@@ -0,0 +1,133 @@
+//! Live kernel statistics for a host dashboard.
+//!
+//! While the stream is running, a kernel thread samples the memory,
+//! scheduler, interrupt and file system cache counters at a fixed interval
+//! and sends each `Sample` on the UART's control channel; see
+//! `serial_mux::telemetry` for the format. The stream is started and
+//! stopped with `Telemetry` control requests or the `telemetry` shell
+//! command, and needs the UART to be multiplexed (`feature.uart_mux`).
+
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use core::time::Duration;
+
+use pi::timer::current_time;
+use serial_mux::control::Message;
+use serial_mux::telemetry::{Sample, SIZE};
+use shim::io;
+use shim::newioerr;
+
+use crate::allocator::memory_map;
+use crate::console::{mux, CONSOLE};
+use crate::mutex::Mutex;
+use crate::process::{Id, Process, State};
+use crate::{ALLOCATOR, FILESYSTEM, IRQ, SCHEDULER};
+
+/// The shortest interval between samples. Each is about 100 bytes on the
+/// wire, so this leaves most of a 115200 baud UART to the other channels.
+pub const MIN_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often the sampling thread checks for a restarted stream while it is
+/// stopped.
+const IDLE_POLL: Duration = Duration::from_millis(100);
+
+/// The interval between samples in milliseconds, or 0 while the stream is
+/// stopped.
+static INTERVAL: AtomicU64 = AtomicU64::new(0);
+
+/// The sequence number of the next sample.
+static SEQUENCE: AtomicU32 = AtomicU32::new(0);
+
+/// The sampling thread, once started. It is started by the first `start()`
+/// and idles while the stream is stopped.
+static THREAD: Mutex<Option<Id>> = Mutex::new(None);
+
+/// Starts sending a sample every `interval`, numbered from 0, or changes the
+/// interval of the running stream; a new interval takes effect after the
+/// next sample.
+///
+/// Returns `InvalidInput` if `interval` is shorter than `MIN_INTERVAL` and
+/// `Other` if the UART is not multiplexed or the sampling thread cannot be
+/// started.
+pub fn start(interval: Duration) -> io::Result<()> {
+    if interval < MIN_INTERVAL {
+        return Err(newioerr!(InvalidInput, "interval too short"));
+    }
+    if !CONSOLE.lock().is_mux() {
+        return Err(newioerr!(Other, "UART is not multiplexed"));
+    }
+    let mut thread = THREAD.lock();
+    if thread.is_none() {
+        let process = Process::kernel_thread(sampling_thread)
+            .map_err(|_| newioerr!(Other, "cannot start sampling thread"))?;
+        let pid = SCHEDULER.add(process).ok_or_else(|| newioerr!(Other, "no process ID"))?;
+        *thread = Some(pid);
+    }
+    if INTERVAL.swap(interval.as_millis() as u64, Ordering::Relaxed) == 0 {
+        SEQUENCE.store(0, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+/// Stops the stream. Does nothing if it is not running.
+pub fn stop() {
+    INTERVAL.store(0, Ordering::Relaxed);
+}
+
+/// Returns the interval between samples, or `None` if the stream is stopped.
+pub fn interval() -> Option<Duration> {
+    match INTERVAL.load(Ordering::Relaxed) {
+        0 => None,
+        ms => Some(Duration::from_millis(ms)),
+    }
+}
+
+/// Returns the kernel's counters now, as sample 0.
+pub fn sample() -> Sample {
+    let heap_size = memory_map()
+        .map(|(start, end)| end - start - ALLOCATOR.base_offset())
+        .unwrap_or(0);
+    let (processes, ready) = SCHEDULER.critical(|scheduler| {
+        scheduler.processes().fold((0, 0), |(all, ready), p| match p.state {
+            State::Ready => (all + 1, ready + 1),
+            _ => (all + 1, ready),
+        })
+    });
+    let irqs = IRQ.stats();
+    let cache = FILESYSTEM.cache_stats().unwrap_or_default();
+    Sample {
+        sequence: 0,
+        uptime: current_time().as_micros() as u64,
+        heap_size: heap_size as u64,
+        heap_free: ALLOCATOR.free_bytes() as u64,
+        processes,
+        ready,
+        dispatches: SCHEDULER.dispatches(),
+        irqs: irqs.iter().map(|irq| irq.count).sum(),
+        irq_time: irqs.iter().map(|irq| irq.time.as_micros() as u64).sum(),
+        cached_sectors: cache.sectors as u32,
+        dirty_sectors: cache.dirty as u32,
+        cache_hits: cache.hits,
+        cache_misses: cache.misses,
+    }
+}
+
+extern "C" fn sampling_thread() -> ! {
+    loop {
+        let interval = match interval() {
+            Some(interval) => interval,
+            None => {
+                let _ = kernel_api::syscall::sleep(IDLE_POLL);
+                continue;
+            }
+        };
+        let mut payload = [0; SIZE];
+        let mut len = 0;
+        let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+        Sample { sequence, ..sample() }.encode(|byte| {
+            payload[len] = byte;
+            len += 1;
+        });
+        mux::send(Message::Sample(&payload));
+        let _ = kernel_api::syscall::sleep(interval);
+    }
+}
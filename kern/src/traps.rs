@@ -1,14 +1,47 @@
+mod fault;
 mod frame;
 mod syndrome;
 mod syscall;
+mod trace;
 
+pub mod ipi;
 pub mod irq;
+pub mod profile;
+pub use self::fault::{backtrace, dump_registers, frames};
 pub use self::frame::TrapFrame;
 
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 use pi::interrupt::{Controller, Interrupt};
+use pi::local_interrupt::{LocalController, LocalInterrupt};
 
+use self::fault::{handle_kernel_fault, handle_user_fault};
 use self::syndrome::Syndrome;
 use self::syscall::handle_syscall;
+use crate::param::NCORES;
+
+/// How deeply each core is nested in IRQ handlers. Only the core itself
+/// changes its count, so loads and stores suffice.
+static IRQ_DEPTH: [AtomicUsize; NCORES] =
+    [AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0)];
+
+/// Returns `true` if the calling core is running an IRQ handler.
+pub fn in_interrupt() -> bool {
+    IRQ_DEPTH[core()].load(Ordering::Relaxed) != 0
+}
+
+/// Returns the calling core. Host tests run as core 0.
+fn core() -> usize {
+    #[cfg(not(test))]
+    {
+        aarch64::affinity()
+    }
+
+    #[cfg(test)]
+    {
+        0
+    }
+}
 
 #[repr(u16)]
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
@@ -39,29 +72,46 @@ pub struct Info {
 /// specifies the source and kind of exception that has occurred. The `esr` is
 /// the value of the exception syndrome register. Finally, `tf` is a pointer to
 /// the trap frame for the exception.
+///
+/// `brk`, from any exception level, starts a debugger session. Other
+/// synchronous faults taken from EL1 are unrecoverable and panic after
+/// printing diagnostics. Synchronous exceptions other than `svc` taken from
+/// a lower exception level stop a process being debugged on its debug
+/// events, and otherwise kill only the offending process.
 #[no_mangle]
 pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
     // crate::console::kprintln!("{:?}, esr {}, {:?}", info, esr, tf);
     // crate::console::kprintln!("esr {}, {:?}", esr, tf);
     // crate::console::kprintln!("{}", unsafe { aarch64::current_el() });
     if info.kind == Kind::Synchronous {
+        let from_user = match info.source {
+            Source::LowerAArch64 | Source::LowerAArch32 => true,
+            Source::CurrentSpEl0 | Source::CurrentSpElx => false,
+        };
         match Syndrome::from(esr) {
-            Syndrome::Brk(_) => {
-                crate::shell::shell("brk_handler$ ");
-                tf.elr += 4;
-            }
             Syndrome::Svc(x) => handle_syscall(x, tf),
-            other => {
-                crate::console::kprintln!("unhandled exception with syndrome {:?}", other);
-                loop {}
-            }
+            other if from_user => handle_user_fault(other, tf),
+            Syndrome::Brk(comment) => crate::debugger::on_brk(comment, tf, false),
+            other => handle_kernel_fault(info, other, tf),
         }
     } else if info.kind == Kind::Irq {
+        let depth = &IRQ_DEPTH[core()];
+        depth.store(depth.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+        // `Gpu` only says that some interrupt of the global controller is
+        // pending, which the loop below finds.
+        let local = LocalController::current();
+        for i in LocalInterrupt::iter() {
+            if *i != LocalInterrupt::Gpu && local.is_pending(*i) {
+                crate::IRQ.invoke_local(*i, tf);
+            }
+        }
         let controller = Controller::new();
         for i in Interrupt::iter() {
             if controller.is_pending(*i) {
                 crate::IRQ.invoke(*i, tf);
             }
         }
+        depth.store(depth.load(Ordering::Relaxed) - 1, Ordering::Relaxed);
+        crate::console::flush_deferred();
     }
 }
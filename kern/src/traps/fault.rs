@@ -0,0 +1,209 @@
+use alloc::boxed::Box;
+use alloc::format;
+
+use aarch64::FAR_EL1;
+
+use crate::allocator::memory_map;
+use crate::console::kprintln;
+use crate::coredump;
+use crate::debugger;
+use crate::ksyms;
+use crate::process::{Action, Event, Process, State};
+use crate::swap;
+use crate::traps::syndrome::{Fault, Syndrome};
+use crate::traps::{Info, TrapFrame};
+use crate::vm::VirtualAddr;
+use crate::{SCHEDULER, VMM};
+
+/// The maximum number of frames printed by `backtrace()`.
+const MAX_BACKTRACE_DEPTH: usize = 16;
+
+/// Returns the faulting virtual address recorded in `FAR_EL1`.
+///
+/// The value is only meaningful for aborts and alignment faults.
+pub fn fault_address() -> u64 {
+    unsafe { FAR_EL1.get() }
+}
+
+/// Prints the system and general purpose registers saved in `tf`.
+pub fn dump_registers(tf: &TrapFrame) {
+    kprintln!("  elr   {:#018x}  spsr  {:#018x}", tf.elr, tf.spsr);
+    kprintln!("  sp    {:#018x}  tpidr {:#018x}", tf.sp, tf.tpidr);
+    kprintln!("  ttbr0 {:#018x}  ttbr1 {:#018x}", tf.ttbr0, tf.ttbr1);
+    for i in (0..tf.x_registers.len()).step_by(2) {
+        if i + 1 < tf.x_registers.len() {
+            kprintln!("  x{:02}   {:#018x}  x{:02}   {:#018x}",
+                i, tf.x_registers[i], i + 1, tf.x_registers[i + 1]);
+        } else {
+            kprintln!("  x{:02}   {:#018x}", i, tf.x_registers[i]);
+        }
+    }
+}
+
+/// An iterator over the return addresses of a frame pointer chain. See
+/// `frames()`.
+pub struct Frames {
+    fp: u64,
+    mem_end: u64,
+    depth: usize,
+}
+
+/// Returns an iterator over the return addresses found by walking the frame
+/// pointer chain starting at the frame pointer `fp`.
+///
+/// The walk stops at a null or misaligned frame pointer, at a frame pointer
+/// outside of physical memory, or after `MAX_BACKTRACE_DEPTH` frames.
+pub fn frames(fp: u64) -> Frames {
+    let mem_end = memory_map().map(|(_, end)| end as u64).unwrap_or(0);
+    Frames { fp, mem_end, depth: 0 }
+}
+
+impl Iterator for Frames {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        let fp = self.fp;
+        if self.depth == MAX_BACKTRACE_DEPTH || fp == 0 || fp % 16 != 0 || fp + 16 > self.mem_end {
+            return None;
+        }
+        let (next_fp, lr) = unsafe {
+            let frame = fp as *const u64;
+            (*frame, *frame.add(1))
+        };
+        if lr == 0 {
+            return None;
+        }
+        self.depth += 1;
+        // The stack grows downwards, so callers' frames live at higher
+        // addresses. Anything else means the chain is corrupt.
+        self.fp = if next_fp > fp { next_fp } else { 0 };
+        Some(lr)
+    }
+}
+
+/// Prints the return addresses found by walking the frame pointer chain
+/// starting at the frame pointer `fp`, with the functions they are in when
+/// known. See `frames()`.
+pub fn backtrace(fp: u64) {
+    kprintln!("backtrace:");
+    for (depth, lr) in frames(fp).enumerate() {
+        match ksyms::lookup(lr) {
+            Some(symbol) => kprintln!("  #{:<2} {:#018x} {}", depth, lr, symbol),
+            None => kprintln!("  #{:<2} {:#018x}", depth, lr),
+        }
+    }
+}
+
+/// Handles a synchronous exception taken from EL1. Prints the decoded
+/// syndrome, the faulting address, the saved registers, and a backtrace. A
+/// data abort in a guard page is reported as a kernel stack overflow. With
+/// the `debugger` feature on, a debugger session starts before the panic.
+///
+/// # Panics
+///
+/// A fault in the kernel is not recoverable, so this function always panics
+/// after printing its diagnostics.
+pub fn handle_kernel_fault(info: Info, syndrome: Syndrome, tf: &mut TrapFrame) -> ! {
+    let far = fault_address();
+    if let Syndrome::DataAbort { .. } = syndrome {
+        if VMM.is_guard(VirtualAddr::from(far as usize)) {
+            kprintln!("kernel stack overflow: process {} hit a guard page", tf.tpidr);
+            kprintln!("  far   {:#018x}", far);
+            dump_registers(tf);
+            panic!("kernel stack overflow in process {}", tf.tpidr);
+        }
+    }
+    kprintln!("kernel fault: {:?} ({:?})", syndrome, info.source);
+    kprintln!("  far   {:#018x}", far);
+    dump_registers(tf);
+    kprintln!("  #0  {:#018x}", tf.elr);
+    backtrace(tf.x_registers[29]);
+    debugger::on_fault(&syndrome, tf, false);
+    panic!("unrecoverable kernel exception: {:?}", syndrome);
+}
+
+/// Handles a synchronous exception taken from a user process. A debug
+/// exception, or a `brk` of a process being debugged, is handled by the
+/// process's `DebugState`, which may stop it; any other `brk` starts a
+/// debugger session. A translation or access flag fault on a page of the
+/// process makes the page accessible and resumes the process: the page is
+/// read back from swap, or mapped if it is in the heap. Any other exception
+/// kills the offending process and switches to the next one, after a
+/// debugger session if the `debugger` feature is on.
+///
+/// # Panics
+///
+/// Panics if the faulting process is not the running process known to the
+/// scheduler.
+pub fn handle_user_fault(syndrome: Syndrome, tf: &mut TrapFrame) {
+    let event = match syndrome {
+        Syndrome::Breakpoint => Some(Event::Breakpoint),
+        Syndrome::Step => Some(Event::Step),
+        Syndrome::Watchpoint => Some(Event::Watchpoint(fault_address())),
+        Syndrome::Brk(_) => Some(Event::Brk),
+        _ => None,
+    };
+    if let Some(event) = event {
+        if handle_debug_event(event, tf) {
+            return;
+        }
+        if let Syndrome::Brk(comment) = syndrome {
+            return debugger::on_brk(comment, tf, true);
+        }
+    }
+    let fault = match syndrome {
+        Syndrome::DataAbort { kind, .. } | Syndrome::InstructionAbort { kind, .. } => Some(kind),
+        _ => None,
+    };
+    if let Some(Fault::Translation) | Some(Fault::AccessFlag) = fault {
+        let va = VirtualAddr::from(fault_address() as usize);
+        let mapped = SCHEDULER.critical(|scheduler| {
+            swap::balance(scheduler);
+            match scheduler.owner_mut(tf) {
+                Some(p) => p.fault_in(va),
+                None => false,
+            }
+        });
+        if mapped {
+            return;
+        }
+    }
+    debugger::on_fault(&syndrome, tf, true);
+    let pid = tf.tpidr;
+    kprintln!("killing process {}: {:?} at {:#x} (far {:#x})",
+        pid, syndrome, tf.elr, fault_address());
+    if coredump::enabled() {
+        let reason = format!("{:?} elr {:#x} far {:#x}", syndrome, tf.elr, fault_address());
+        SCHEDULER.critical(|scheduler| {
+            if let Some(p) = scheduler.owner_mut(tf) {
+                coredump::capture(p, tf, &reason);
+            }
+        });
+    }
+    if SCHEDULER.kill(tf).is_none() {
+        panic!("could not kill faulting process {}", pid);
+    }
+}
+
+/// Passes `event`, a debug exception taken from the process whose registers
+/// are in `tf`, to its `DebugState`, and switches away from the process if it
+/// stopped. Returns `false` if the process is not being debugged.
+fn handle_debug_event(event: Event, tf: &mut TrapFrame) -> bool {
+    let action = SCHEDULER.critical(|scheduler| match scheduler.current_mut(tf) {
+        Some(p) => {
+            let action = p.debug.on_event(event, tf);
+            p.debug.install();
+            action
+        }
+        None => Action::Ignore,
+    });
+    match action {
+        Action::Resume => true,
+        Action::Stop => {
+            let resumed = Box::new(|p: &mut Process| !p.debug.is_stopped());
+            SCHEDULER.switch(State::Waiting(resumed), tf);
+            true
+        }
+        Action::Ignore => false,
+    }
+}
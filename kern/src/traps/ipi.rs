@@ -0,0 +1,160 @@
+//! Inter-processor interrupts.
+//!
+//! An IPI is a bit set in mailbox `MAILBOX` of the target core, one bit per
+//! message type, so messages of different types sent before the target
+//! takes the interrupt are all delivered, and repeats of one type merge.
+//!
+//! A `TlbFlush` broadcast waits for every target to acknowledge it, so that
+//! once it returns no core can still translate through the old mapping.
+
+use core::sync::atomic::AtomicUsize;
+
+use aarch64::{atomic, flush_tlb};
+use pi::local_interrupt::{LocalController, LocalInterrupt};
+
+use crate::param::NCORES;
+use crate::process::State;
+use crate::traps::irq::IrqHandler;
+use crate::traps::TrapFrame;
+use crate::{smp, IRQ, SCHEDULER};
+
+/// The mailbox IPIs are sent through.
+const MAILBOX: usize = 0;
+
+/// The messages one core can send another.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Ipi {
+    /// Work has arrived: the target should pick a process to run.
+    Reschedule = 0,
+    /// The kernel page table changed: the target should flush its TLB.
+    TlbFlush = 1,
+    /// The target should stop and go offline.
+    Halt = 2,
+    /// The target should call the function left by `call()`.
+    CallFunction = 3,
+}
+
+impl Ipi {
+    const ALL: [Ipi; 4] = [Ipi::Reschedule, Ipi::TlbFlush, Ipi::Halt, Ipi::CallFunction];
+}
+
+/// The function each core is to call on `CallFunction`, as a `fn()`
+/// pointer, or 0 if none.
+static CALLS: [AtomicUsize; NCORES] =
+    [AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0)];
+
+/// The number of TLB shootdowns requested so far. A shootdown is numbered
+/// once the page tables have been changed.
+static SHOOTDOWNS: AtomicUsize = AtomicUsize::new(0);
+
+/// The latest shootdown each core has acknowledged: the value of
+/// `SHOOTDOWNS` read before the core's last TLB flush.
+static TLB_ACKS: [AtomicUsize; NCORES] =
+    [AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0)];
+
+/// Registers the IPI handler and lets the calling core take IPIs.
+pub fn initialize() {
+    IRQ.replace_local(LocalInterrupt::mailbox(MAILBOX), IrqHandler::new(handle, 0));
+    enable();
+}
+
+/// Lets the calling core take IPIs. Each core calls this once.
+pub fn enable() {
+    LocalController::current().enable(LocalInterrupt::mailbox(MAILBOX));
+}
+
+/// Sends `ipi` to core `core`.
+pub fn send(core: usize, ipi: Ipi) {
+    LocalController::current().send(core, MAILBOX, 1 << ipi as u32);
+}
+
+/// Sends `ipi` to every online core but the calling one. For `TlbFlush`,
+/// which the caller sends once it has changed the page tables, waits until
+/// each of them has flushed its TLB.
+pub fn broadcast(ipi: Ipi) {
+    // Until secondary cores are started, the boot core is alone.
+    if smp::online().count_ones() < 2 {
+        return;
+    }
+    let shootdown = match ipi {
+        Ipi::TlbFlush => Some(atomic::fetch_add(&SHOOTDOWNS, 1) + 1),
+        _ => None,
+    };
+    let mut controller = LocalController::current();
+    let me = controller.core();
+    let mut targets = 0u64;
+    for core in (0..NCORES).filter(|&core| core != me && smp::is_online(core)) {
+        controller.send(core, MAILBOX, 1 << ipi as u32);
+        targets |= 1 << core;
+    }
+    if let Some(shootdown) = shootdown {
+        for core in (0..NCORES).filter(|&core| targets & (1 << core) != 0) {
+            while smp::is_online(core) && atomic::load_acquire(&TLB_ACKS[core]) < shootdown {
+                // A core broadcasting a shootdown at the same time waits for
+                // this one's acknowledgement.
+                if controller.take_bits(MAILBOX, 1 << Ipi::TlbFlush as u32) != 0 {
+                    flush(me);
+                }
+            }
+        }
+    }
+}
+
+/// Flushes the TLB of the calling core, `core`, and acknowledges the
+/// shootdowns requested so far.
+fn flush(core: usize) {
+    let shootdown = atomic::load_acquire(&SHOOTDOWNS);
+    unsafe { flush_tlb() };
+    atomic::store_release(&TLB_ACKS[core], shootdown);
+}
+
+/// Makes core `core` call `f` from its IPI handler. Returns `false`, sending
+/// nothing, if `core` has yet to call the last function it was sent.
+pub fn call(core: usize, f: fn()) -> bool {
+    if atomic::compare_exchange(&CALLS[core], 0, f as usize).is_err() {
+        return false;
+    }
+    send(core, Ipi::CallFunction);
+    true
+}
+
+/// The IRQ path of IPIs: handles those pending on the calling core, which
+/// `tf` interrupted.
+fn handle(_: usize, tf: &mut TrapFrame) {
+    deliver(Some(tf));
+}
+
+/// Handles the IPIs pending on the calling core while it idles with IRQs
+/// masked.
+pub(crate) fn poll() {
+    if LocalController::current().is_pending(LocalInterrupt::mailbox(MAILBOX)) {
+        deliver(None);
+    }
+}
+
+/// Handles the IPIs pending on the calling core. `tf` is the context they
+/// interrupted, if any; without one, as when the core idles, there is
+/// nothing to reschedule.
+fn deliver(mut tf: Option<&mut TrapFrame>) {
+    let mut controller = LocalController::current();
+    let core = controller.core();
+    let pending = controller.take(MAILBOX);
+    for &ipi in Ipi::ALL.iter().filter(|&&ipi| pending & (1 << ipi as u32) != 0) {
+        match ipi {
+            Ipi::Reschedule => {
+                if let Some(tf) = tf.as_mut() {
+                    SCHEDULER.switch(State::Ready, tf);
+                }
+            }
+            Ipi::TlbFlush => flush(core),
+            Ipi::Halt => smp::halt(core),
+            Ipi::CallFunction => {
+                let f = atomic::swap(&CALLS[core], 0);
+                if f != 0 {
+                    let f: fn() = unsafe { core::mem::transmute(f) };
+                    f();
+                }
+            }
+        }
+    }
+}
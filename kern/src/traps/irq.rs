@@ -1,38 +1,361 @@
-use alloc::boxed::Box;
-use pi::interrupt::Interrupt;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use aarch64::{cli, DAIF};
+use kernel_api::{OsError, OsResult};
+use pi::interrupt::{Controller, Interrupt};
+use pi::local_interrupt::LocalInterrupt;
+use pi::timer::current_time;
 
 use crate::mutex::Mutex;
+use crate::process::{Id, Policy, Process};
 use crate::traps::TrapFrame;
+use crate::SCHEDULER;
+
+/// An interrupt handler: a function and the context it is called with, such
+/// as the index of the device it serves or the address of a static.
+///
+/// Handlers are plain data, so registering one allocates nothing and the
+/// IRQ path runs a copy of it with the table unlocked. A handler may then
+/// unregister or replace itself.
+#[derive(Copy, Clone)]
+pub struct IrqHandler {
+    func: fn(usize, &mut TrapFrame),
+    context: usize,
+}
+
+impl IrqHandler {
+    /// Returns a handler that calls `func(context, tf)`.
+    pub fn new(func: fn(usize, &mut TrapFrame), context: usize) -> IrqHandler {
+        IrqHandler { func, context }
+    }
+
+    fn call(&self, tf: &mut TrapFrame) {
+        (self.func)(self.context, tf)
+    }
+}
 
-pub type IrqHandler = Box<dyn FnMut(&mut TrapFrame) + Send>;
 pub type IrqHandlers = [Option<IrqHandler>; Interrupt::MAX];
 
-pub struct Irq(Mutex<Option<IrqHandlers>>);
+/// Handlers of the cores' local interrupts. Each core takes its own local
+/// interrupts, but they share one handler for each.
+pub type LocalIrqHandlers = [Option<IrqHandler>; LocalInterrupt::MAX];
+
+/// A handler that runs in a kernel thread instead of in the IRQ path: a
+/// function and the context it is called with.
+#[derive(Copy, Clone)]
+pub struct ThreadedHandler {
+    func: fn(usize),
+    context: usize,
+}
+
+impl ThreadedHandler {
+    /// Returns a handler that calls `func(context)`.
+    pub fn new(func: fn(usize), context: usize) -> ThreadedHandler {
+        ThreadedHandler { func, context }
+    }
+
+    fn call(&self) {
+        (self.func)(self.context)
+    }
+}
+
+/// The FIFO priority of the threads that run threaded handlers, so that they
+/// run ahead of normal processes once woken.
+const IRQ_THREAD_PRIORITY: u8 = 50;
+
+/// The thread that runs an interrupt's threaded handler, and the handler.
+struct IrqThread {
+    pid: Id,
+    handler: ThreadedHandler,
+}
+
+/// What an interrupt has cost since boot or the last `reset_stats()`.
+#[derive(Copy, Clone, Debug)]
+pub struct IrqStats {
+    /// The times the interrupt was taken.
+    pub count: u64,
+    /// The times it was taken with no handler registered.
+    pub unhandled: u64,
+    /// Time spent in its handler in the IRQ path.
+    pub time: Duration,
+    /// Time its threaded handler, if any, spent running.
+    pub thread_time: Duration,
+}
+
+impl IrqStats {
+    const fn new() -> IrqStats {
+        IrqStats {
+            count: 0,
+            unhandled: 0,
+            time: Duration::from_secs(0),
+            thread_time: Duration::from_secs(0),
+        }
+    }
+}
+
+/// The interrupt handlers, by interrupt. The tables are changed with IRQs
+/// masked on the calling core, so that an interrupt it takes meanwhile does
+/// not find them locked.
+pub struct Irq(
+    Mutex<IrqHandlers>,
+    Mutex<[Option<IrqThread>; Interrupt::MAX]>,
+    [AtomicBool; Interrupt::MAX],
+    Mutex<[IrqStats; Interrupt::MAX]>,
+    Mutex<LocalIrqHandlers>,
+);
 
 impl Irq {
     pub const fn uninitialized() -> Irq {
-        Irq(Mutex::new(None))
+        Irq(
+            Mutex::new([None; Interrupt::MAX]),
+            Mutex::new([None, None, None, None, None, None, None, None, None, None]),
+            [
+                AtomicBool::new(false),
+                AtomicBool::new(false),
+                AtomicBool::new(false),
+                AtomicBool::new(false),
+                AtomicBool::new(false),
+                AtomicBool::new(false),
+                AtomicBool::new(false),
+                AtomicBool::new(false),
+                AtomicBool::new(false),
+                AtomicBool::new(false),
+            ],
+            Mutex::new([IrqStats::new(); Interrupt::MAX]),
+            Mutex::new([None; LocalInterrupt::MAX]),
+        )
+    }
+
+    /// Registers `handler` for `int`. Returns `FileExists` if `int` already
+    /// has a handler; see `replace()`.
+    pub fn register(&self, int: Interrupt, handler: IrqHandler) -> OsResult<()> {
+        let index = Interrupt::to_index(int);
+        without_irqs(|| claim(&mut self.0.lock()[index], handler))
+    }
+
+    /// Removes the handler of `int` and returns it. The interrupt is taken as
+    /// unhandled from then on, so its source should be disabled first.
+    ///
+    /// Returns `None`, removing nothing, if `int` has a threaded handler; see
+    /// `unregister_threaded()`.
+    pub fn unregister(&self, int: Interrupt) -> Option<IrqHandler> {
+        let index = Interrupt::to_index(int);
+        without_irqs(|| {
+            if self.1.lock()[index].is_some() {
+                return None;
+            }
+            self.0.lock()[index].take()
+        })
     }
 
-    pub fn initialize(&self) {
-        *self.0.lock() = Some([None, None, None, None, None, None, None, None]);
+    /// Makes `handler` the handler of `int`, and returns the one it replaces.
+    /// Drivers that may be initialized again use this to register.
+    pub fn replace(&self, int: Interrupt, handler: IrqHandler) -> Option<IrqHandler> {
+        let index = Interrupt::to_index(int);
+        without_irqs(|| self.0.lock()[index].replace(handler))
     }
 
-    /// Register an irq handler for an interrupt.
-    /// The caller should assure that `initialize()` has been called before calling this function.
-    pub fn register(&self, int: Interrupt, handler: IrqHandler) {
-        if let Some(ref mut handlers) = *self.0.lock() {
-            handlers[Interrupt::to_index(int)] = Some(handler);
+    /// Registers `handler` for a local interrupt. It runs on whichever core
+    /// takes the interrupt; each core enables the interrupt in its own
+    /// `LocalController`. Returns `FileExists` if `int` already has a
+    /// handler.
+    pub fn register_local(&self, int: LocalInterrupt, handler: IrqHandler) -> OsResult<()> {
+        let index = LocalInterrupt::to_index(int);
+        without_irqs(|| claim(&mut self.4.lock()[index], handler))
+    }
+
+    /// Removes the handler of the local interrupt `int` and returns it.
+    pub fn unregister_local(&self, int: LocalInterrupt) -> Option<IrqHandler> {
+        let index = LocalInterrupt::to_index(int);
+        without_irqs(|| self.4.lock()[index].take())
+    }
+
+    /// Makes `handler` the handler of the local interrupt `int`, and returns
+    /// the one it replaces.
+    pub fn replace_local(&self, int: LocalInterrupt, handler: IrqHandler) -> Option<IrqHandler> {
+        let index = LocalInterrupt::to_index(int);
+        without_irqs(|| self.4.lock()[index].replace(handler))
+    }
+
+    /// Registers a threaded handler for an interrupt and starts the kernel
+    /// thread that runs it. Returns the ID of the thread.
+    ///
+    /// When the interrupt fires, the IRQ path only masks it in the interrupt
+    /// controller and wakes the thread, so a slow handler does not delay
+    /// other interrupts. The thread runs `handler`, which must clear the
+    /// interrupt at its source, and then unmasks the interrupt.
+    ///
+    /// Returns `FileExists` if `int` already has a handler, threaded or
+    /// not. The caller should assure that `SCHEDULER` has been initialized.
+    pub fn register_threaded(&self, int: Interrupt, handler: ThreadedHandler) -> OsResult<Id> {
+        let index = Interrupt::to_index(int);
+        // The thread looks itself up here when it starts, so the table stays
+        // locked until its entry is filled in.
+        let mut threads = self.1.lock();
+        if threads[index].is_some() || self.0.lock()[index].is_some() {
+            return Err(OsError::FileExists);
         }
+        let thread = Process::kernel_thread(irq_thread)?;
+        let pid = SCHEDULER.add(thread).ok_or(OsError::NoMemory)?;
+        let _ = SCHEDULER.set_policy(pid, Policy::Fifo(IRQ_THREAD_PRIORITY));
+        threads[index] = Some(IrqThread { pid, handler });
+        drop(threads);
+
+        self.register(int, IrqHandler::new(wake, index))?;
+        Ok(pid)
     }
 
-    /// Executes an irq handler for the given interrupt.
-    /// The caller should assure that `initialize()` has been called before calling this function.
+    /// Removes the threaded handler of `int` and returns it. Its thread exits
+    /// the next time it is scheduled. As with `unregister()`, the interrupt's
+    /// source should be disabled first.
+    pub fn unregister_threaded(&self, int: Interrupt) -> Option<ThreadedHandler> {
+        let index = Interrupt::to_index(int);
+        without_irqs(|| {
+            let thread = self.1.lock()[index].take()?;
+            self.0.lock()[index] = None;
+            self.2[index].store(false, Ordering::Release);
+            Some(thread.handler)
+        })
+    }
+
+    /// Executes the irq handler for the given interrupt, with the table
+    /// unlocked.
+    ///
+    /// The call is counted in the interrupt's statistics, with the time the
+    /// handler took.
     pub fn invoke(&self, int: Interrupt, tf: &mut TrapFrame) {
-        if let Some(ref mut handlers) = *self.0.lock() {
-            if let Some(ref mut f) = handlers[Interrupt::to_index(int)] {
-                f(tf);
-            }
+        let start = current_time();
+        let handler = self.0.lock()[Interrupt::to_index(int)];
+        if let Some(ref handler) = handler {
+            handler.call(tf);
+        }
+        let handled = handler.is_some();
+        let elapsed = current_time().checked_sub(start).unwrap_or_default();
+        let stats = &mut self.3.lock()[Interrupt::to_index(int)];
+        stats.count += 1;
+        if handled {
+            stats.time += elapsed;
+        } else {
+            stats.unhandled += 1;
+        }
+    }
+
+    /// Executes the irq handler for the local interrupt `int`, taken by the
+    /// calling core, with the table unlocked.
+    pub fn invoke_local(&self, int: LocalInterrupt, tf: &mut TrapFrame) {
+        let handler = self.4.lock()[LocalInterrupt::to_index(int)];
+        if let Some(ref handler) = handler {
+            handler.call(tf);
+        }
+    }
+
+    /// Returns the statistics of every interrupt, indexed by
+    /// `Interrupt::to_index`.
+    pub fn stats(&self) -> [IrqStats; Interrupt::MAX] {
+        *self.3.lock()
+    }
+
+    /// Zeroes the statistics of every interrupt.
+    pub fn reset_stats(&self) {
+        *self.3.lock() = [IrqStats::new(); Interrupt::MAX];
+    }
+
+    /// The IRQ path of a threaded interrupt: masks the interrupt until its
+    /// thread has handled it and marks it pending.
+    fn wake(&self, int: Interrupt) {
+        Controller::new().disable(int);
+        self.2[Interrupt::to_index(int)].store(true, Ordering::Release);
+    }
+
+    /// Returns `true`, and clears the flag, if `int` fired since the last
+    /// call.
+    pub fn take_pending(&self, int: Interrupt) -> bool {
+        self.2[Interrupt::to_index(int)].swap(false, Ordering::Acquire)
+    }
+
+    /// Returns the ID of the thread that runs the threaded handler of `int`.
+    pub fn thread(&self, int: Interrupt) -> Option<Id> {
+        self.1.lock()[Interrupt::to_index(int)].as_ref().map(|t| t.pid)
+    }
+
+    /// Returns the interrupt whose threaded handler thread `pid` runs.
+    fn thread_interrupt(&self, pid: Id) -> Option<Interrupt> {
+        let threads = self.1.lock();
+        let index = threads.iter().position(|t| t.as_ref().map(|t| t.pid) == Some(pid))?;
+        Some(Interrupt::from_index(index))
+    }
+
+    /// Runs the threaded handler of `int` once and unmasks the interrupt,
+    /// unless the handler has been unregistered.
+    fn run_threaded(&self, int: Interrupt) {
+        let index = Interrupt::to_index(int);
+        // The handler is copied out so the table is not locked while it runs.
+        let handler = self.1.lock()[index].as_ref().map(|t| t.handler);
+        if let Some(handler) = handler {
+            let start = current_time();
+            handler.call();
+            let elapsed = current_time().checked_sub(start).unwrap_or_default();
+            self.3.lock()[index].thread_time += elapsed;
+            Controller::new().enable(int);
+        }
+    }
+}
+
+/// Fills `slot` with `handler` unless it holds one.
+fn claim(slot: &mut Option<IrqHandler>, handler: IrqHandler) -> OsResult<()> {
+    match slot {
+        Some(_) => Err(OsError::FileExists),
+        None => {
+            *slot = Some(handler);
+            Ok(())
+        }
+    }
+}
+
+/// Runs `f` with IRQs masked on the calling core, restoring the mask after.
+pub fn without_irqs<R>(f: impl FnOnce() -> R) -> R {
+    let daif = unsafe { DAIF.get() };
+    unsafe { cli() };
+    let result = f();
+    unsafe { DAIF.set(daif) };
+    result
+}
+
+/// The handler of threaded interrupts; `index` is the interrupt's.
+fn wake(index: usize, _: &mut TrapFrame) {
+    crate::IRQ.wake(Interrupt::from_index(index));
+}
+
+/// Returns a name for `int`.
+pub fn name(int: Interrupt) -> &'static str {
+    match int {
+        Interrupt::Timer1 => "timer1",
+        Interrupt::Timer3 => "timer3",
+        Interrupt::Usb => "usb",
+        Interrupt::Dma0 => "dma0",
+        Interrupt::Aux => "aux",
+        Interrupt::Gpio0 => "gpio0",
+        Interrupt::Gpio1 => "gpio1",
+        Interrupt::Gpio2 => "gpio2",
+        Interrupt::Gpio3 => "gpio3",
+        Interrupt::Uart => "uart",
+    }
+}
+
+/// The body of the thread that runs a threaded handler: waits for the
+/// interrupt with `wait_irq` and runs the handler each time it fires, until
+/// the handler is unregistered.
+extern "C" fn irq_thread() -> ! {
+    let pid = kernel_api::syscall::getpid();
+    let int = match crate::IRQ.thread_interrupt(pid) {
+        Some(int) => int,
+        None => kernel_api::syscall::exit(),
+    };
+    loop {
+        match kernel_api::syscall::wait_irq(Interrupt::to_index(int) as u64) {
+            Ok(_) => crate::IRQ.run_threaded(int),
+            Err(_) => kernel_api::syscall::exit(),
         }
     }
 }
@@ -0,0 +1,107 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use pi::timer::current_time;
+
+use crate::console::kprintln;
+use crate::mutex::Mutex;
+
+/// The number of syscall numbers with a histogram of their own; higher
+/// numbers are counted together in the last one.
+const SYSCALLS: usize = 32;
+
+/// The number of histogram buckets. Bucket `i` counts latencies in
+/// `[2^i, 2^(i+1))` microseconds; the first also counts shorter ones and the
+/// last longer ones.
+const BUCKETS: usize = 24;
+
+/// The latencies of one syscall number.
+#[derive(Copy, Clone)]
+struct Histogram {
+    counts: [u64; BUCKETS],
+    total: Duration,
+    max: Duration,
+}
+
+impl Histogram {
+    const fn new() -> Histogram {
+        Histogram {
+            counts: [0; BUCKETS],
+            total: Duration::from_secs(0),
+            max: Duration::from_secs(0),
+        }
+    }
+
+    fn record(&mut self, latency: Duration) {
+        let micros = latency.as_micros() as u64;
+        let bucket = (63 - micros.max(1).leading_zeros()) as usize;
+        self.counts[bucket.min(BUCKETS - 1)] += 1;
+        self.total += latency;
+        self.max = self.max.max(latency);
+    }
+
+    fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+static HISTOGRAMS: Mutex<[Histogram; SYSCALLS]> = Mutex::new([Histogram::new(); SYSCALLS]);
+
+/// Set while syscall latencies are being recorded.
+static PROFILING: AtomicBool = AtomicBool::new(false);
+
+/// Starts or stops recording syscall latencies.
+pub fn set_enabled(enabled: bool) {
+    PROFILING.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    PROFILING.load(Ordering::Relaxed)
+}
+
+/// Returns the time at which a syscall starts, if latencies are being
+/// recorded.
+pub(super) fn start() -> Option<Duration> {
+    if enabled() {
+        Some(current_time())
+    } else {
+        None
+    }
+}
+
+/// Records the latency of syscall `num`, which started at `start`.
+pub(super) fn record(num: u16, start: Duration) {
+    let latency = current_time().checked_sub(start).unwrap_or_default();
+    HISTOGRAMS.lock()[(num as usize).min(SYSCALLS - 1)].record(latency);
+}
+
+/// Discards all recorded latencies.
+pub fn reset() {
+    *HISTOGRAMS.lock() = [Histogram::new(); SYSCALLS];
+}
+
+/// Prints the count, mean and maximum latency and the histogram of each
+/// syscall number that has been recorded.
+pub fn dump() {
+    let histograms = *HISTOGRAMS.lock();
+    kprintln!("syscall latencies ({})", if enabled() { "recording" } else { "stopped" });
+    for (num, histogram) in histograms.iter().enumerate() {
+        let count = histogram.count();
+        if count == 0 {
+            continue;
+        }
+        let mean = Duration::from_nanos((histogram.total.as_nanos() / count as u128) as u64);
+        kprintln!("  #{:<2} {:>8} calls  mean {:>8}us  max {:>8}us",
+            num, count, mean.as_micros(), histogram.max.as_micros());
+        for (bucket, &n) in histogram.counts.iter().enumerate() {
+            if n == 0 {
+                continue;
+            }
+            if bucket == BUCKETS - 1 {
+                kprintln!("     >={:>8}us {:>8}", 1u64 << bucket, n);
+            } else {
+                kprintln!("      <{:>8}us {:>8}", 1u64 << (bucket + 1), n);
+            }
+        }
+    }
+}
@@ -1,12 +1,21 @@
 use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::Ordering;
 use core::time::Duration;
 
 use crate::console::{CONSOLE, kprintln};
-use crate::process::{Process, State};
-use crate::traps::TrapFrame;
-use crate::SCHEDULER;
+use crate::param::{PAGE_SIZE, USER_IMG_BASE};
+use crate::vm::VirtualAddr;
+use crate::process::{Id, Policy, Process, State, Timers};
+use crate::traps::{profile, trace, TrapFrame};
+use crate::settings;
+use crate::version;
+use crate::{FILESYSTEM, IRQ, SCHEDULER};
 use kernel_api::*;
-use pi::timer::Timer;
+use pi::interrupt::Interrupt;
+use pi::timer::current_time;
+use shim::io::{self, Write};
 
 /// Sleep for `ms` milliseconds.
 ///
@@ -16,12 +25,14 @@ use pi::timer::Timer;
 /// parameter: the approximate true elapsed time from when `sleep` was called to
 /// when `sleep` returned.
 pub fn sys_sleep(ms: u32, tf: &mut TrapFrame) {
-    let timer = Timer::new();
-    let start_time = timer.read();
+    let start_time = current_time();
     let end_time = start_time + Duration::from_millis(ms as u64);
+    // The scheduler's timing wheels set the alarm, so sleepers do not each
+    // read the timer on every pass over the queue.
+    let _ = SCHEDULER.sleep_until(tf.tpidr, end_time);
     let has_waited_long_enough = Box::new(move |p: &mut Process| {
-        if timer.read() >= end_time {
-            let elapsed_time = (timer.read() - start_time).as_millis() as u64;
+        if p.alarm {
+            let elapsed_time = (current_time() - start_time).as_millis() as u64;
             p.context.x_registers[0] = elapsed_time;
             p.context.x_registers[7] = 1;
             true
@@ -32,6 +43,52 @@ pub fn sys_sleep(ms: u32, tf: &mut TrapFrame) {
     SCHEDULER.switch(State::Waiting(has_waited_long_enough), tf);
 }
 
+/// Sleep until an absolute time on a clock.
+///
+/// This system call takes three parameters: the `CLOCK_*` clock to read, and
+/// the time to wake at on it as seconds and the fractional part of it in
+/// nanoseconds. Only `CLOCK_MONOTONIC`, the time since boot `sys_time`
+/// returns, is supported. A loop that sleeps until its last deadline plus a
+/// period does not drift by the time it takes to run, as one that sleeps
+/// for the period does.
+///
+/// In addition to the usual status value, this system call returns two
+/// parameters: the time it returned at, as seconds and nanoseconds. It
+/// returns right away if that time has passed. `InvalidArgument` is returned
+/// for an unknown clock or a fractional part of a second or more.
+pub fn sys_sleep_until(clock: u64, secs: u64, nanos: u64, tf: &mut TrapFrame) {
+    if clock != CLOCK_MONOTONIC || nanos >= 1_000_000_000 {
+        tf.x_registers[7] = OsError::InvalidArgument as u64;
+        return;
+    }
+    let deadline = Duration::new(secs, nanos as u32);
+    let _ = SCHEDULER.sleep_until(tf.tpidr, deadline);
+    let has_reached_deadline = Box::new(move |p: &mut Process| {
+        if p.alarm {
+            let now = current_time();
+            p.context.x_registers[0] = now.as_secs();
+            p.context.x_registers[1] = now.subsec_nanos() as u64;
+            p.context.x_registers[7] = 1;
+            true
+        } else {
+            false
+        }
+    });
+    SCHEDULER.switch(State::Waiting(has_reached_deadline), tf);
+}
+
+/// Gives up the rest of the calling process's time slice.
+///
+/// This system call does not take parameter. The process runs again once
+/// the ready processes that rank with it have had their turn, right away if
+/// there are none.
+///
+/// It only returns the usual status value.
+pub fn sys_yield(tf: &mut TrapFrame) {
+    tf.x_registers[7] = 1;
+    SCHEDULER.yield_now(tf);
+}
+
 /// Returns current time.
 ///
 /// This system call does not take parameter.
@@ -41,8 +98,7 @@ pub fn sys_sleep(ms: u32, tf: &mut TrapFrame) {
 ///  - current time as seconds
 ///  - fractional part of the current time, in nanoseconds.
 pub fn sys_time(tf: &mut TrapFrame) {
-    let timer = Timer::new();
-    let now = timer.read();
+    let now = current_time();
     tf.x_registers[0] = now.as_secs();
     tf.x_registers[1] = now.subsec_nanos() as u64;
     tf.x_registers[7] = 1;
@@ -51,6 +107,8 @@ pub fn sys_time(tf: &mut TrapFrame) {
 /// Kills current process.
 ///
 /// This system call does not take paramer and does not return any value.
+/// Called from a thread, it ends only that thread; called from a process
+/// with threads, it ends them too.
 pub fn sys_exit(tf: &mut TrapFrame) {
     SCHEDULER.switch(State::Dead, tf);
 }
@@ -65,6 +123,39 @@ pub fn sys_write(b: u8, tf: &mut TrapFrame) {
     tf.x_registers[7] = 1;
 }
 
+/// Write a string to console.
+///
+/// This system call takes two parameters: the address and the length in bytes
+/// of a UTF-8 string in the current process's memory. The string is copied
+/// into the kernel and written under a single acquisition of the console
+/// lock, so it is not interleaved with other output.
+///
+/// In addition to the usual status value, this system call returns a
+/// parameter: the number of bytes written. `BadAddress` is returned if the
+/// string is not in mapped user memory and `InvalidArgument` if it is not
+/// UTF-8.
+pub fn sys_write_str(va: u64, len: u64, tf: &mut TrapFrame) {
+    let (va, len) = (va as usize, len as usize);
+    let checked = SCHEDULER.critical(|scheduler| match scheduler.owner_mut(tf) {
+        Some(p) => p.check_user_range(VirtualAddr::from(va), len),
+        None => Err(OsError::NoEntry),
+    });
+    if let Err(e) = checked {
+        tf.x_registers[7] = e as u64;
+        return;
+    }
+    // The process's page tables are still active, so the checked range can
+    // be read directly.
+    let bytes = unsafe { core::slice::from_raw_parts(va as *const u8, len) }.to_vec();
+    if core::str::from_utf8(&bytes).is_err() {
+        tf.x_registers[7] = OsError::InvalidArgument as u64;
+        return;
+    }
+    let _ = CONSOLE.lock().write(&bytes);
+    tf.x_registers[0] = len as u64;
+    tf.x_registers[7] = 1;
+}
+
 /// Returns current process's ID.
 ///
 /// This system call does not take parameter.
@@ -76,13 +167,949 @@ pub fn sys_getpid(tf: &mut TrapFrame) {
     tf.x_registers[7] = 1;
 }
 
+/// Sets a resource limit for the current process.
+///
+/// This system call takes two parameters: the resource to limit and the new
+/// limit. The supported resources are `RLIMIT_CPU`, the CPU time the
+/// process may consume in milliseconds before it is killed, and
+/// `RLIMIT_RSS`, the bytes of memory it may have mapped, rounded down to
+/// whole pages. A limit of `RLIM_INFINITY` removes the limit. Called from a
+/// thread, `RLIMIT_CPU` limits that thread and `RLIMIT_RSS` the process.
+///
+/// It only returns the usual status value. `InvalidArgument` is returned for
+/// an unknown resource.
+pub fn sys_setrlimit(resource: u64, limit: u64, tf: &mut TrapFrame) {
+    if resource != RLIMIT_CPU && resource != RLIMIT_RSS {
+        tf.x_registers[7] = OsError::InvalidArgument as u64;
+        return;
+    }
+    let limited = limit != RLIM_INFINITY;
+    // CPU time is counted per thread, memory per address space.
+    let found = SCHEDULER.critical(|scheduler| {
+        if resource == RLIMIT_CPU {
+            match scheduler.current_mut(tf) {
+                Some(p) => {
+                    p.cpu_limit = if limited { Some(Duration::from_millis(limit)) } else { None };
+                    true
+                }
+                None => false,
+            }
+        } else {
+            match scheduler.owner_mut(tf) {
+                Some(p) => {
+                    p.mem_limit = if limited { Some(limit as usize / PAGE_SIZE) } else { None };
+                    true
+                }
+                None => false,
+            }
+        }
+    });
+    tf.x_registers[7] = if found { 1 } else { OsError::NoEntry as u64 };
+}
+
+/// Moves the current process into a process group.
+///
+/// This system call takes one parameter: the ID of the group to join. A group
+/// ID of `0` puts the process in a new group whose ID is its process ID.
+///
+/// It only returns the usual status value.
+pub fn sys_setpgid(pgid: u64, tf: &mut TrapFrame) {
+    let pgid = if pgid == 0 { tf.tpidr } else { pgid };
+    let found = SCHEDULER.critical(|scheduler| {
+        match scheduler.current_mut(tf) {
+            Some(p) => {
+                p.group = pgid;
+                true
+            }
+            None => false,
+        }
+    });
+    tf.x_registers[7] = if found { 1 } else { OsError::NoEntry as u64 };
+}
+
+/// Returns the current process's group ID.
+///
+/// This system call does not take parameter.
+///
+/// In addition to the usual status value, this system call returns a
+/// parameter: the current process's group ID.
+pub fn sys_getpgid(tf: &mut TrapFrame) {
+    let pgid = SCHEDULER.critical(|scheduler| scheduler.current_mut(tf).map(|p| p.group));
+    match pgid {
+        Some(pgid) => {
+            tf.x_registers[0] = pgid;
+            tf.x_registers[7] = 1;
+        }
+        None => tf.x_registers[7] = OsError::NoEntry as u64,
+    }
+}
+
+/// Makes a process group the foreground group of the console.
+///
+/// This system call takes one parameter: the ID of the group that should own
+/// the console. Only the foreground group receives console input and Ctrl-C.
+///
+/// It only returns the usual status value. `NoAccess` is returned if the
+/// group is not the caller's own and the caller lacks `CAP_CONSOLE`, and
+/// `NoEntry` if no live process is in the group.
+pub fn sys_tcsetpgrp(pgid: u64, tf: &mut TrapFrame) {
+    let allowed = SCHEDULER.critical(|scheduler| {
+        let caller = scheduler.current_mut(tf).map(|p| (p.group, p.caps));
+        let live = |p: &Process| match p.state {
+            State::Dead => false,
+            _ => true,
+        };
+        match caller {
+            Some((group, caps)) if group != pgid && caps & CAP_CONSOLE == 0 => {
+                Err(OsError::NoAccess)
+            }
+            Some(_) if scheduler.processes().any(|p| p.group == pgid && live(p)) => Ok(()),
+            _ => Err(OsError::NoEntry),
+        }
+    });
+    if let Err(e) = allowed {
+        tf.x_registers[7] = e as u64;
+        return;
+    }
+    CONSOLE.lock().set_foreground(Some(pgid));
+    tf.x_registers[7] = 1;
+}
+
+/// Returns the foreground process group of the console.
+///
+/// This system call does not take parameter.
+///
+/// In addition to the usual status value, this system call returns a
+/// parameter: the foreground group's ID. `NoEntry` is returned if the console
+/// has no foreground group.
+pub fn sys_tcgetpgrp(tf: &mut TrapFrame) {
+    match CONSOLE.lock().foreground() {
+        Some(pgid) => {
+            tf.x_registers[0] = pgid;
+            tf.x_registers[7] = 1;
+        }
+        None => tf.x_registers[7] = OsError::NoEntry as u64,
+    }
+}
+
+/// Duplicates a file descriptor.
+///
+/// This system call takes one parameter: the descriptor to duplicate. The
+/// new descriptor is the lowest free one and shares the open file, including
+/// its position, with the old one.
+///
+/// In addition to the usual status value, this system call returns a
+/// parameter: the new descriptor. `BadDescriptor` is returned if the old one
+/// is not open and `TooManyFiles` if no descriptor is free.
+pub fn sys_dup(fd: u64, tf: &mut TrapFrame) {
+    let result = SCHEDULER.critical(|scheduler| match scheduler.owner_mut(tf) {
+        Some(p) => p.files.dup(fd as usize),
+        None => Err(OsError::NoEntry),
+    });
+    match result {
+        Ok(new_fd) => {
+            tf.x_registers[0] = new_fd as u64;
+            tf.x_registers[7] = 1;
+        }
+        Err(e) => tf.x_registers[7] = e as u64,
+    }
+}
+
+/// Duplicates a file descriptor onto a given descriptor.
+///
+/// This system call takes two parameters: the descriptor to duplicate and the
+/// descriptor to make refer to the same open file. A file the second one
+/// referred to before is closed, unless the two are equal.
+///
+/// In addition to the usual status value, this system call returns a
+/// parameter: the second descriptor. `BadDescriptor` is returned if the first
+/// one is not open or the second is not below `MAX_FDS`.
+pub fn sys_dup2(old_fd: u64, new_fd: u64, tf: &mut TrapFrame) {
+    let result = SCHEDULER.critical(|scheduler| match scheduler.owner_mut(tf) {
+        Some(p) => p.files.dup2(old_fd as usize, new_fd as usize),
+        None => Err(OsError::NoEntry),
+    });
+    match result {
+        Ok(fd) => {
+            tf.x_registers[0] = fd as u64;
+            tf.x_registers[7] = 1;
+        }
+        Err(e) => tf.x_registers[7] = e as u64,
+    }
+}
+
+/// Moves the end of the current process's heap.
+///
+/// This system call takes one parameter: the signed number of bytes to grow
+/// the heap by. New heap pages are mapped, zeroed, when first touched.
+///
+/// In addition to the usual status value, this system call returns a
+/// parameter: the old end of the heap. `NoVmSpace` is returned if the heap
+/// would reach the stack or shrink below its start, and `NoMemory` if it
+/// would grow past the process's `RLIMIT_RSS`.
+pub fn sys_sbrk(increment: i64, tf: &mut TrapFrame) {
+    let result = SCHEDULER.critical(|scheduler| match scheduler.owner_mut(tf) {
+        Some(p) => p.sbrk(increment),
+        None => Err(OsError::NoEntry),
+    });
+    match result {
+        Ok(old_end) => {
+            tf.x_registers[0] = old_end.as_u64();
+            tf.x_registers[7] = 1;
+        }
+        Err(e) => tf.x_registers[7] = e as u64,
+    }
+}
+
+/// Returns memory usage of the current process.
+///
+/// This system call does not take parameter.
+///
+/// In addition to the usual status value, this system call returns three
+/// parameters:
+///  - the bytes of memory mapped in the process's address space
+///  - the most bytes that have been mapped at once
+///  - the size of the heap in bytes
+pub fn sys_procinfo(tf: &mut TrapFrame) {
+    let info = SCHEDULER.critical(|scheduler| {
+        scheduler.owner_mut(tf).map(|p| {
+            let heap = p.heap_end.as_usize() - p.heap_start.as_usize();
+            (p.usage.total() * PAGE_SIZE, p.peak_pages * PAGE_SIZE, heap)
+        })
+    });
+    match info {
+        Some((rss, peak_rss, heap)) => {
+            tf.x_registers[0] = rss as u64;
+            tf.x_registers[1] = peak_rss as u64;
+            tf.x_registers[2] = heap as u64;
+            tf.x_registers[7] = 1;
+        }
+        None => tf.x_registers[7] = OsError::NoEntry as u64,
+    }
+}
+
+/// Traces or debugs a process.
+///
+/// This system call takes four parameters: the ID of the process, or `0` for
+/// the current process, a `PTRACE_*` request and the request's two
+/// arguments. `PTRACE_SYSCALLS_OFF` and `PTRACE_SYSCALLS` clear and set the
+/// process's trace flag; each syscall a traced process makes is logged with
+/// its arguments and result. The other requests debug a process other than
+/// the caller through its `DebugState`; breakpoints and watchpoints set
+/// while it runs on another core take effect once it is dispatched again.
+///
+/// For `PTRACE_WAIT`, this system call returns the `Stop` fields, `reason`,
+/// `pc` and `addr`, in addition to the usual status value. The other
+/// requests only return the status. `NoEntry` is returned if there is no
+/// such process, `NoAccess` if it is another process and the caller lacks
+/// `CAP_PTRACE`, and `InvalidArgument` for a debug request of the caller
+/// itself, a slot the core does not have, an invalid address or length, or
+/// a `PTRACE_STEP` or `PTRACE_CONT` of a process that is not stopped.
+pub fn sys_ptrace_lite(pid: u64, request: u64, arg0: u64, arg1: u64, tf: &mut TrapFrame) {
+    let pid = if pid == 0 { tf.tpidr } else { pid };
+    if let Err(e) = check_cap(pid, CAP_PTRACE, tf) {
+        tf.x_registers[7] = e as u64;
+        return;
+    }
+    let result = match request {
+        PTRACE_SYSCALLS_OFF | PTRACE_SYSCALLS => {
+            if SCHEDULER.set_traced(pid, request == PTRACE_SYSCALLS) {
+                Ok(())
+            } else {
+                Err(OsError::NoEntry)
+            }
+        }
+        _ if pid == tf.tpidr => Err(OsError::InvalidArgument),
+        PTRACE_WAIT => return ptrace_wait(pid, tf),
+        request => SCHEDULER.critical(|scheduler| {
+            let p = scheduler.find_mut(pid).ok_or(OsError::NoEntry)?;
+            let watch_slot = (arg0 & 0xff) as usize;
+            match request {
+                PTRACE_BREAK if arg0 as usize >= aarch64::debug::breakpoints() => {
+                    Err(OsError::InvalidArgument)
+                }
+                PTRACE_BREAK => p.debug.set_breakpoint(arg0 as usize, arg1),
+                PTRACE_WATCH if watch_slot >= aarch64::debug::watchpoints() => {
+                    Err(OsError::InvalidArgument)
+                }
+                PTRACE_WATCH => {
+                    p.debug.set_watchpoint(watch_slot, arg1, arg0 >> 16, (arg0 >> 8) & 0xff)
+                }
+                PTRACE_STEP | PTRACE_CONT => p.debug.resume(request == PTRACE_STEP, &mut p.context),
+                PTRACE_DETACH => {
+                    p.debug.detach(&mut p.context);
+                    Ok(())
+                }
+                _ => Err(OsError::InvalidArgument),
+            }
+        }),
+    };
+    tf.x_registers[7] = match result {
+        Ok(()) => 1,
+        Err(e) => e as u64,
+    };
+}
+
+/// Returns why process `pid` is stopped, waiting until it stops or exits.
+fn ptrace_wait(pid: Id, tf: &mut TrapFrame) {
+    let found = SCHEDULER.critical(|scheduler| {
+        scheduler.find(pid).map(|p| (p.debug.stop(), p.debug.watch_stop(), p.exit.watch()))
+    });
+    match found {
+        None => tf.x_registers[7] = OsError::NoEntry as u64,
+        Some((Some(stop), _, _)) => {
+            tf.x_registers[0] = stop.reason;
+            tf.x_registers[1] = stop.pc;
+            tf.x_registers[2] = stop.addr;
+            tf.x_registers[7] = 1;
+        }
+        Some((None, stopped, exited)) => {
+            // Return to the `svc` instruction, which is 4 bytes long, once
+            // woken.
+            tf.elr -= 4;
+            let ready = Box::new(move |_: &mut Process| {
+                stopped.load(Ordering::Acquire) || exited.load(Ordering::Acquire)
+            });
+            SCHEDULER.switch(State::Waiting(ready), tf);
+        }
+    }
+}
+
+/// Restricts a process to a set of cores.
+///
+/// This system call takes two parameters: the ID of the process, or `0` for
+/// the current process, and a mask with bit `n` set for each core `n` the
+/// process may run on.
+///
+/// It only returns the usual status value. `InvalidArgument` is returned if
+/// the mask names no core, `NoEntry` if there is no such process and
+/// `NoAccess` if it is another process and the caller lacks `CAP_SCHED`.
+pub fn sys_setaffinity(pid: u64, mask: u64, tf: &mut TrapFrame) {
+    let pid = if pid == 0 { tf.tpidr } else { pid };
+    let result = check_cap(pid, CAP_SCHED, tf).and_then(|()| SCHEDULER.set_affinity(pid, mask));
+    tf.x_registers[7] = match result {
+        Ok(()) => 1,
+        Err(e) => e as u64,
+    };
+}
+
+/// Sets the scheduling policy of a process.
+///
+/// This system call takes three parameters: the ID of the process, or `0`
+/// for the current process, the policy (`SCHED_NORMAL`, `SCHED_FIFO` or
+/// `SCHED_DEADLINE`) and its parameter: the priority, from 1 to 99, for
+/// `SCHED_FIFO` and the relative deadline in milliseconds for
+/// `SCHED_DEADLINE`.
+///
+/// It only returns the usual status value. `InvalidArgument` is returned for
+/// an unknown policy or a parameter out of range and `NoEntry` if there is no
+/// such process. `NoAccess` is returned if the caller lacks `CAP_SCHED` and
+/// the process is another one or the policy is not `SCHED_NORMAL`.
+pub fn sys_setscheduler(pid: u64, policy: u64, param: u64, tf: &mut TrapFrame) {
+    let pid = if pid == 0 { tf.tpidr } else { pid };
+    let result = Policy::from_raw(policy, param).and_then(|policy| {
+        match policy {
+            Policy::Normal => check_cap(pid, CAP_SCHED, tf)?,
+            _ => require_cap(CAP_SCHED, tf)?,
+        }
+        SCHEDULER.set_policy(pid, policy)
+    });
+    tf.x_registers[7] = match result {
+        Ok(()) => 1,
+        Err(e) => e as u64,
+    };
+}
+
+/// Gives up capabilities.
+///
+/// This system call takes one parameter: a mask of the `CAP_*` capabilities
+/// to drop. Dropped capabilities cannot be regained.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the capabilities the process keeps.
+pub fn sys_capdrop(caps: u64, tf: &mut TrapFrame) {
+    let kept = SCHEDULER.critical(|scheduler| {
+        scheduler.current_mut(tf).map(|p| {
+            p.caps &= !caps;
+            p.caps
+        })
+    });
+    match kept {
+        Some(kept) => {
+            tf.x_registers[0] = kept;
+            tf.x_registers[7] = 1;
+        }
+        None => tf.x_registers[7] = OsError::NoEntry as u64,
+    }
+}
+
+/// Mounts the file system, or changes the options it is mounted with.
+///
+/// This system call takes two parameters: the address and length of a
+/// comma-separated list of mount options, such as `ro,noatime`. See
+/// `FileSystem::mount()`. The caller must hold `CAP_MOUNT`.
+///
+/// This system call does not return values. `InvalidArgument` is returned
+/// if the options are not UTF-8 or an option is unknown.
+pub fn sys_mount(va: u64, len: u64, tf: &mut TrapFrame) {
+    let (va, len) = (va as usize, len as usize);
+    let checked = require_cap(CAP_MOUNT, tf).and_then(|_| {
+        SCHEDULER.critical(|scheduler| match scheduler.owner_mut(tf) {
+            Some(p) => p.check_user_range(VirtualAddr::from(va), len),
+            None => Err(OsError::NoEntry),
+        })
+    });
+    if let Err(e) = checked {
+        tf.x_registers[7] = e as u64;
+        return;
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(va as *const u8, len) }.to_vec();
+    let options = match core::str::from_utf8(&bytes) {
+        Ok(options) => options,
+        Err(_) => {
+            tf.x_registers[7] = OsError::InvalidArgument as u64;
+            return;
+        }
+    };
+    tf.x_registers[7] = match FILESYSTEM.mount(options) {
+        Ok(()) => 1,
+        Err(ref e) if e.kind() == io::ErrorKind::InvalidInput => OsError::InvalidArgument as u64,
+        Err(e) => OsError::from(e) as u64,
+    };
+}
+
+/// Writes back and unmounts the file system.
+///
+/// This system call does not take parameters or return values. The caller
+/// must hold `CAP_MOUNT`.
+pub fn sys_umount(tf: &mut TrapFrame) {
+    let result = require_cap(CAP_MOUNT, tf)
+        .and_then(|_| FILESYSTEM.umount().map_err(OsError::from));
+    tf.x_registers[7] = match result {
+        Ok(()) => 1,
+        Err(e) => e as u64,
+    };
+}
+
+/// Copies the system's hostname into the current process's memory.
+///
+/// This system call takes two parameters: the address and length of the
+/// buffer to copy the hostname into. If the hostname is longer than the
+/// buffer, only as much as fits is copied.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the length of the hostname in bytes. `BadAddress` is returned
+/// if the buffer is not in mapped user memory.
+pub fn sys_gethostname(va: u64, len: u64, tf: &mut TrapFrame) {
+    let (va, len) = (va as usize, len as usize);
+    let checked = SCHEDULER.critical(|scheduler| match scheduler.owner_mut(tf) {
+        Some(p) => p.check_user_range(VirtualAddr::from(va), len),
+        None => Err(OsError::NoEntry),
+    });
+    if let Err(e) = checked {
+        tf.x_registers[7] = e as u64;
+        return;
+    }
+    let hostname = settings::hostname();
+    let copied = hostname.len().min(len);
+    let buf = unsafe { core::slice::from_raw_parts_mut(va as *mut u8, copied) };
+    buf.copy_from_slice(&hostname.as_bytes()[..copied]);
+    tf.x_registers[0] = hostname.len() as u64;
+    tf.x_registers[7] = 1;
+}
+
+/// Sets the system's hostname and saves it in the settings store.
+///
+/// This system call takes two parameters: the address and length of the new
+/// hostname, which is one word of at most `HOST_NAME_MAX` bytes. The caller
+/// must hold `CAP_HOSTNAME`.
+///
+/// This system call does not return values. `InvalidArgument` is returned
+/// if the hostname is not UTF-8, is empty, contains whitespace or is too
+/// long, and `NoEntry` if there is no settings store to save it in.
+pub fn sys_sethostname(va: u64, len: u64, tf: &mut TrapFrame) {
+    let (va, len) = (va as usize, len as usize);
+    let checked = require_cap(CAP_HOSTNAME, tf).and_then(|_| {
+        SCHEDULER.critical(|scheduler| match scheduler.owner_mut(tf) {
+            Some(p) => p.check_user_range(VirtualAddr::from(va), len),
+            None => Err(OsError::NoEntry),
+        })
+    });
+    if let Err(e) = checked {
+        tf.x_registers[7] = e as u64;
+        return;
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(va as *const u8, len) }.to_vec();
+    let hostname = match core::str::from_utf8(&bytes) {
+        Ok(hostname) => hostname,
+        Err(_) => {
+            tf.x_registers[7] = OsError::InvalidArgument as u64;
+            return;
+        }
+    };
+    tf.x_registers[7] = match settings::set("hostname", hostname) {
+        Ok(()) => 1,
+        Err(ref e) if e.kind() == io::ErrorKind::InvalidInput => OsError::InvalidArgument as u64,
+        Err(e) => OsError::from(e) as u64,
+    };
+}
+
+/// Copies what the running kernel image was built from into the current
+/// process's memory: the lines of `version::uname()`.
+///
+/// This system call takes two parameters: the address and length of the
+/// buffer to copy the text into. If the text is longer than the buffer,
+/// only as much as fits is copied.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the length of the text in bytes. `BadAddress` is returned if
+/// the buffer is not in mapped user memory.
+pub fn sys_uname(va: u64, len: u64, tf: &mut TrapFrame) {
+    let (va, len) = (va as usize, len as usize);
+    let checked = SCHEDULER.critical(|scheduler| match scheduler.owner_mut(tf) {
+        Some(p) => p.check_user_range(VirtualAddr::from(va), len),
+        None => Err(OsError::NoEntry),
+    });
+    if let Err(e) = checked {
+        tf.x_registers[7] = e as u64;
+        return;
+    }
+    let text = version::uname();
+    let copied = text.len().min(len);
+    let buf = unsafe { core::slice::from_raw_parts_mut(va as *mut u8, copied) };
+    buf.copy_from_slice(&text.as_bytes()[..copied]);
+    tf.x_registers[0] = text.len() as u64;
+    tf.x_registers[7] = 1;
+}
+
+/// Returns `NoAccess` unless the current process holds capability `cap`.
+fn require_cap(cap: u64, tf: &TrapFrame) -> OsResult<()> {
+    SCHEDULER.critical(|scheduler| match scheduler.current_mut(tf) {
+        Some(p) if p.caps & cap == cap => Ok(()),
+        Some(_) => Err(OsError::NoAccess),
+        None => Err(OsError::NoEntry),
+    })
+}
+
+/// Returns `NoAccess` unless process `target` is the current process or the
+/// current process holds capability `cap`.
+fn check_cap(target: Id, cap: u64, tf: &TrapFrame) -> OsResult<()> {
+    if target == tf.tpidr {
+        return Ok(());
+    }
+    require_cap(cap, tf)
+}
+
+/// Waits for an interrupt to fire.
+///
+/// This system call takes one parameter: the index of the interrupt. Only the
+/// kernel thread running the interrupt's threaded handler may call it;
+/// others get `NoAccess`, and `InvalidArgument` is returned for an index out
+/// of range. If the handler is unregistered while the thread waits, it is
+/// woken with `NoEntry`.
+///
+/// It only returns the usual status value.
+pub fn sys_wait_irq(int: u64, tf: &mut TrapFrame) {
+    if int as usize >= Interrupt::MAX {
+        tf.x_registers[7] = OsError::InvalidArgument as u64;
+        return;
+    }
+    let int = Interrupt::from_index(int as usize);
+    if IRQ.thread(int) != Some(tf.tpidr) {
+        tf.x_registers[7] = OsError::NoAccess as u64;
+        return;
+    }
+    let fired = Box::new(move |p: &mut Process| {
+        if IRQ.take_pending(int) {
+            p.context.x_registers[7] = 1;
+            true
+        } else if IRQ.thread(int) != Some(p.context.tpidr) {
+            p.context.x_registers[7] = OsError::NoEntry as u64;
+            true
+        } else {
+            false
+        }
+    });
+    SCHEDULER.switch(State::Waiting(fired), tf);
+}
+
+/// Applies `f` to the current process's interval timers and sets the usual
+/// status value from its result, which it returns.
+fn with_timers<T, F>(tf: &mut TrapFrame, f: F) -> OsResult<T>
+where
+    F: FnOnce(&mut Timers) -> OsResult<T>,
+{
+    let result = SCHEDULER.critical(|scheduler| match scheduler.current_mut(tf) {
+        Some(p) => f(&mut p.timers),
+        None => Err(OsError::NoEntry),
+    });
+    tf.x_registers[7] = match result {
+        Ok(_) => 1,
+        Err(e) => e as u64,
+    };
+    result
+}
+
+/// Creates a disarmed interval timer.
+///
+/// This system call does not take parameter.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the ID of the timer. `NoMemory` is returned if the process has
+/// `MAX_TIMERS` timers already.
+pub fn sys_timer_create(tf: &mut TrapFrame) {
+    if let Ok(id) = with_timers(tf, |timers| timers.create()) {
+        tf.x_registers[0] = id as u64;
+    }
+}
+
+/// Arms an interval timer.
+///
+/// This system call takes three parameters: the ID of the timer, the
+/// milliseconds until it first expires and the milliseconds between later
+/// expirations, or `0` for a timer that expires once. Expirations that were
+/// not yet waited for are discarded.
+///
+/// It only returns the usual status value. `InvalidArgument` is returned if
+/// there is no such timer.
+pub fn sys_timer_arm(id: u64, initial: u64, interval: u64, tf: &mut TrapFrame) {
+    let now = current_time();
+    let initial = Duration::from_millis(initial);
+    let interval = Duration::from_millis(interval);
+    let _ = with_timers(tf, |timers| timers.arm(id as usize, now, initial, interval));
+}
+
+/// Disarms an interval timer.
+///
+/// This system call takes one parameter: the ID of the timer.
+///
+/// It only returns the usual status value. `InvalidArgument` is returned if
+/// there is no such timer.
+pub fn sys_timer_disarm(id: u64, tf: &mut TrapFrame) {
+    let _ = with_timers(tf, |timers| timers.disarm(id as usize));
+}
+
+/// Deletes an interval timer.
+///
+/// This system call takes one parameter: the ID of the timer.
+///
+/// It only returns the usual status value. `InvalidArgument` is returned if
+/// there is no such timer.
+pub fn sys_timer_delete(id: u64, tf: &mut TrapFrame) {
+    let _ = with_timers(tf, |timers| timers.delete(id as usize));
+}
+
+/// Waits for an interval timer to expire.
+///
+/// This system call takes one parameter: the ID of the timer.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of times the timer expired since it was armed or
+/// last waited for. `InvalidArgument` is returned if there is no such timer
+/// or it is disarmed, since it would never expire.
+pub fn sys_timer_wait(id: u64, tf: &mut TrapFrame) {
+    let id = id as usize;
+    match with_timers(tf, |timers| timers.is_armed(id)) {
+        Ok(true) => (),
+        Ok(false) => {
+            tf.x_registers[7] = OsError::InvalidArgument as u64;
+            return;
+        }
+        Err(_) => return,
+    }
+    let expired = Box::new(move |p: &mut Process| match p.timers.expirations(id, current_time()) {
+        Ok(0) => false,
+        Ok(count) => {
+            p.context.x_registers[0] = count;
+            p.context.x_registers[7] = 1;
+            true
+        }
+        Err(e) => {
+            p.context.x_registers[7] = e as u64;
+            true
+        }
+    });
+    SCHEDULER.switch(State::Waiting(expired), tf);
+}
+
+/// Waits for file descriptors to become ready.
+///
+/// This system call takes three parameters: a mask of the descriptors to be
+/// read, with bit `n` naming descriptor `n`, a mask of those to be written,
+/// and a timeout in milliseconds, or `POLL_FOREVER`. A timeout of `0` only
+/// checks the descriptors.
+///
+/// In addition to the usual status value, this system call returns two
+/// parameters: the masks of the descriptors that can be read and written
+/// without blocking, which are both empty if the timeout passed first.
+/// `BadDescriptor` is returned if a mask names a descriptor that is not
+/// open, or is closed while waiting.
+pub fn sys_poll(read: u64, write: u64, timeout: u64, tf: &mut TrapFrame) {
+    let deadline = match timeout {
+        POLL_FOREVER => None,
+        ms => Some(current_time() + Duration::from_millis(ms)),
+    };
+    // A thread polls its leader's descriptors, as they were when it called.
+    let shared = SCHEDULER.critical(|scheduler| {
+        let leader = scheduler.current_mut(tf)?.leader?;
+        scheduler.find(leader).map(|p| p.files.clone())
+    });
+    let ready = Box::new(move |p: &mut Process| {
+        let files = shared.as_ref().unwrap_or(&p.files);
+        match files.poll(read, write) {
+            Ok((0, 0)) if deadline.map_or(true, |d| current_time() < d) => return false,
+            Ok((readable, writable)) => {
+                p.context.x_registers[0] = readable;
+                p.context.x_registers[1] = writable;
+                p.context.x_registers[7] = 1;
+            }
+            Err(e) => p.context.x_registers[7] = e as u64,
+        }
+        true
+    });
+    SCHEDULER.switch(State::Waiting(ready), tf);
+}
+
+/// Starts a thread in the current process.
+///
+/// This system call takes three parameters: the address the thread starts
+/// at, the initial value of its stack pointer and a value passed to it in
+/// `x0`. The thread shares the process's address space and file descriptors
+/// but has registers and a kernel stack of its own. It ends when it calls
+/// `sys_exit` or the process exits.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the ID of the thread. `BadAddress` is returned if the start
+/// address is not in user memory or the stack pointer is not 16-byte aligned
+/// just above mapped user memory.
+pub fn sys_clone(entry: u64, stack: u64, arg: u64, tf: &mut TrapFrame) {
+    let (entry, stack) = (entry as usize, stack as usize);
+    let checked = SCHEDULER.critical(|scheduler| match scheduler.owner_mut(tf) {
+        Some(_) if entry < USER_IMG_BASE || stack % 16 != 0 || stack < 16 => {
+            Err(OsError::BadAddress)
+        }
+        Some(p) => p.check_user_range(VirtualAddr::from(stack - 16), 16),
+        None => Err(OsError::NoEntry),
+    });
+    let result = checked.and_then(|_| {
+        SCHEDULER.spawn_thread(tf, VirtualAddr::from(entry), VirtualAddr::from(stack), arg)
+    });
+    match result {
+        Ok(tid) => {
+            tf.x_registers[0] = tid;
+            tf.x_registers[7] = 1;
+        }
+        Err(e) => tf.x_registers[7] = e as u64,
+    }
+}
+
+/// Waits for a thread of the current process to end.
+///
+/// This system call takes one parameter: the ID of the thread, as returned
+/// by `sys_clone`.
+///
+/// It only returns the usual status value. `NoEntry` is returned if there
+/// is no such thread in the current process, which includes one that has
+/// already ended, and `InvalidArgument` if it is the caller.
+pub fn sys_thread_join(tid: u64, tf: &mut TrapFrame) {
+    let exited = match SCHEDULER.critical(|scheduler| scheduler.watch_thread(tf, tid)) {
+        Ok(exited) => exited,
+        Err(e) => {
+            tf.x_registers[7] = e as u64;
+            return;
+        }
+    };
+    let joined = Box::new(move |p: &mut Process| {
+        if exited.load(Ordering::Acquire) {
+            p.context.x_registers[7] = 1;
+            true
+        } else {
+            false
+        }
+    });
+    SCHEDULER.switch(State::Waiting(joined), tf);
+}
+
+/// Reads console input.
+///
+/// This system call takes two parameters: the address and length of the
+/// buffer to read into. It waits until the console has input and the
+/// caller's process group is the console's foreground group, then moves as
+/// much of the input as fits into the buffer.
+///
+/// A waiting process is not scheduled until the UART's receive interrupt
+/// has read input ahead. It then makes the system call again, so that the
+/// input is copied in its own address space.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes read. `BadAddress` is returned if the
+/// buffer is not in mapped user memory.
+pub fn sys_read_console(va: u64, len: u64, tf: &mut TrapFrame) {
+    let (va, len) = (va as usize, len as usize);
+    let group = SCHEDULER.critical(|scheduler| match scheduler.owner_mut(tf) {
+        Some(p) => p.check_user_range(VirtualAddr::from(va), len).map(|_| p.group),
+        None => Err(OsError::NoEntry),
+    });
+    let group = match group {
+        Ok(group) => group,
+        Err(e) => {
+            tf.x_registers[7] = e as u64;
+            return;
+        }
+    };
+    {
+        let mut console = CONSOLE.lock();
+        if len == 0 || (console.is_foreground(group) && console.has_input()) {
+            let buf = unsafe { core::slice::from_raw_parts_mut(va as *mut u8, len) };
+            tf.x_registers[0] = console.take_input(buf) as u64;
+            tf.x_registers[7] = 1;
+            return;
+        }
+    }
+    // Return to the `svc` instruction, which is 4 bytes long, once woken.
+    tf.elr -= 4;
+    let readable = Box::new(move |p: &mut Process| {
+        let console = CONSOLE.lock();
+        console.is_foreground(p.group) && console.has_input()
+    });
+    SCHEDULER.switch(State::Waiting(readable), tf);
+}
+
+/// Loads a program and starts it as a new process.
+///
+/// This system call takes four parameters: the address and length of the
+/// program's path, and the address and number of its arguments, an array of
+/// up to `SPAWN_MAX_ARGS` pairs of a string's address and length. The program
+/// receives them as `load_with_args()` passes them. It runs in the caller's
+/// process group with the caller's capabilities.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the ID of the new process. `BadAddress` is returned if a
+/// string or the array is not in mapped user memory, `InvalidArgument` if
+/// there are too many arguments or a string is not UTF-8, and `NoVmSpace` if
+/// the arguments do not fit on the new process's stack. Errors loading the
+/// program, such as `NoEntry`, are returned as they are.
+pub fn sys_spawn(path_va: u64, path_len: u64, argv_va: u64, argc: u64, tf: &mut TrapFrame) {
+    let result = spawn_args(path_va, path_len, argv_va, argc, tf).and_then(|(path, args)| {
+        let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+        let mut child = Process::load_with_args(&path, &args)?;
+        SCHEDULER.critical(|scheduler| match scheduler.current_mut(tf) {
+            Some(p) => {
+                child.caps = p.caps;
+                child.group = p.group;
+                Ok(())
+            }
+            None => Err(OsError::NoEntry),
+        })?;
+        SCHEDULER.add(child).ok_or(OsError::NoMemory)
+    });
+    match result {
+        Ok(pid) => {
+            tf.x_registers[0] = pid;
+            tf.x_registers[7] = 1;
+        }
+        Err(e) => tf.x_registers[7] = e as u64,
+    }
+}
+
+/// Copies `sys_spawn`'s path and arguments out of the caller's memory.
+fn spawn_args(
+    path_va: u64,
+    path_len: u64,
+    argv_va: u64,
+    argc: u64,
+    tf: &TrapFrame,
+) -> OsResult<(String, Vec<String>)> {
+    if argc as usize > SPAWN_MAX_ARGS {
+        return Err(OsError::InvalidArgument);
+    }
+    let check = |va: u64, len: usize| {
+        SCHEDULER.critical(|scheduler| match scheduler.owner_mut(tf) {
+            Some(p) => p.check_user_range(VirtualAddr::from(va as usize), len),
+            None => Err(OsError::NoEntry),
+        })
+    };
+    let copy = |va: u64, len: u64| -> OsResult<String> {
+        check(va, len as usize)?;
+        let bytes = unsafe { core::slice::from_raw_parts(va as *const u8, len as usize) };
+        String::from_utf8(bytes.to_vec()).map_err(|_| OsError::InvalidArgument)
+    };
+    let path = copy(path_va, path_len)?;
+    check(argv_va, argc as usize * 16)?;
+    let argv = unsafe { core::slice::from_raw_parts(argv_va as *const [u64; 2], argc as usize) };
+    let args = argv.iter().map(|&[va, len]| copy(va, len)).collect::<OsResult<Vec<_>>>()?;
+    Ok((path, args))
+}
+
+/// Handles syscall `num`, recording its latency if profiling is enabled and
+/// logging it if the calling process is traced.
 pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
+    let traced = trace::traced(tf);
+    if let Some(pid) = traced {
+        trace::enter(pid, num, tf);
+    }
+    let start = profile::start();
+    dispatch(num, tf);
+    if let Some(start) = start {
+        profile::record(num, start);
+    }
+    if let Some(pid) = traced {
+        trace::exit(pid, num, tf);
+    }
+}
+
+fn dispatch(num: u16, tf: &mut TrapFrame) {
     match num as usize {
         NR_EXIT => sys_exit(tf),
         NR_GETPID => sys_getpid(tf),
         NR_SLEEP => sys_sleep(tf.x_registers[0] as u32, tf),
         NR_TIME => sys_time(tf),
+        NR_YIELD => sys_yield(tf),
+        NR_SLEEP_UNTIL => {
+            sys_sleep_until(tf.x_registers[0], tf.x_registers[1], tf.x_registers[2], tf)
+        }
         NR_WRITE => sys_write(tf.x_registers[0] as u8, tf),
+        NR_WRITE_STR => sys_write_str(tf.x_registers[0], tf.x_registers[1], tf),
+        NR_SETRLIMIT => sys_setrlimit(tf.x_registers[0], tf.x_registers[1], tf),
+        NR_SETPGID => sys_setpgid(tf.x_registers[0], tf),
+        NR_GETPGID => sys_getpgid(tf),
+        NR_TCSETPGRP => sys_tcsetpgrp(tf.x_registers[0], tf),
+        NR_TCGETPGRP => sys_tcgetpgrp(tf),
+        NR_DUP => sys_dup(tf.x_registers[0], tf),
+        NR_DUP2 => sys_dup2(tf.x_registers[0], tf.x_registers[1], tf),
+        NR_SBRK => sys_sbrk(tf.x_registers[0] as i64, tf),
+        NR_PROCINFO => sys_procinfo(tf),
+        NR_PTRACE_LITE => {
+            let (pid, request) = (tf.x_registers[0], tf.x_registers[1]);
+            sys_ptrace_lite(pid, request, tf.x_registers[2], tf.x_registers[3], tf)
+        }
+        NR_SETAFFINITY => sys_setaffinity(tf.x_registers[0], tf.x_registers[1], tf),
+        NR_SETSCHEDULER => {
+            sys_setscheduler(tf.x_registers[0], tf.x_registers[1], tf.x_registers[2], tf)
+        }
+        NR_WAIT_IRQ => sys_wait_irq(tf.x_registers[0], tf),
+        NR_TIMER_CREATE => sys_timer_create(tf),
+        NR_TIMER_ARM => {
+            sys_timer_arm(tf.x_registers[0], tf.x_registers[1], tf.x_registers[2], tf)
+        }
+        NR_TIMER_DISARM => sys_timer_disarm(tf.x_registers[0], tf),
+        NR_TIMER_DELETE => sys_timer_delete(tf.x_registers[0], tf),
+        NR_TIMER_WAIT => sys_timer_wait(tf.x_registers[0], tf),
+        NR_POLL => sys_poll(tf.x_registers[0], tf.x_registers[1], tf.x_registers[2], tf),
+        NR_CAPDROP => sys_capdrop(tf.x_registers[0], tf),
+        NR_MOUNT => sys_mount(tf.x_registers[0], tf.x_registers[1], tf),
+        NR_UMOUNT => sys_umount(tf),
+        NR_GETHOSTNAME => sys_gethostname(tf.x_registers[0], tf.x_registers[1], tf),
+        NR_SETHOSTNAME => sys_sethostname(tf.x_registers[0], tf.x_registers[1], tf),
+        NR_UNAME => sys_uname(tf.x_registers[0], tf.x_registers[1], tf),
+        NR_CLONE => sys_clone(tf.x_registers[0], tf.x_registers[1], tf.x_registers[2], tf),
+        NR_THREAD_JOIN => sys_thread_join(tf.x_registers[0], tf),
+        NR_READ_CONSOLE => sys_read_console(tf.x_registers[0], tf.x_registers[1], tf),
+        NR_SPAWN => {
+            let (x0, x1, x2, x3) =
+                (tf.x_registers[0], tf.x_registers[1], tf.x_registers[2], tf.x_registers[3]);
+            sys_spawn(x0, x1, x2, x3, tf)
+        }
         other => kprintln!("unrecognized syscall {}", other),
     }
 }
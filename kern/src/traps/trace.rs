@@ -0,0 +1,85 @@
+use kernel_api::*;
+
+use crate::logger::info;
+use crate::process::Id;
+use crate::traps::TrapFrame;
+use crate::SCHEDULER;
+
+/// Returns the name of syscall `num` and the number of arguments it takes.
+fn describe(num: u16) -> (&'static str, usize) {
+    match num as usize {
+        NR_SLEEP => ("sleep", 1),
+        NR_TIME => ("time", 0),
+        NR_EXIT => ("exit", 0),
+        NR_WRITE => ("write", 1),
+        NR_GETPID => ("getpid", 0),
+        NR_SETRLIMIT => ("setrlimit", 2),
+        NR_SETPGID => ("setpgid", 1),
+        NR_GETPGID => ("getpgid", 0),
+        NR_TCSETPGRP => ("tcsetpgrp", 1),
+        NR_TCGETPGRP => ("tcgetpgrp", 0),
+        NR_DUP => ("dup", 1),
+        NR_DUP2 => ("dup2", 2),
+        NR_SBRK => ("sbrk", 1),
+        NR_PROCINFO => ("procinfo", 0),
+        NR_WRITE_STR => ("write_str", 2),
+        NR_PTRACE_LITE => ("ptrace_lite", 4),
+        NR_SETAFFINITY => ("setaffinity", 2),
+        NR_SETSCHEDULER => ("setscheduler", 3),
+        NR_WAIT_IRQ => ("wait_irq", 1),
+        NR_TIMER_CREATE => ("timer_create", 0),
+        NR_TIMER_ARM => ("timer_arm", 3),
+        NR_TIMER_DISARM => ("timer_disarm", 1),
+        NR_TIMER_DELETE => ("timer_delete", 1),
+        NR_TIMER_WAIT => ("timer_wait", 1),
+        NR_POLL => ("poll", 3),
+        NR_CAPDROP => ("capdrop", 1),
+        NR_MOUNT => ("mount", 2),
+        NR_UMOUNT => ("umount", 0),
+        NR_GETHOSTNAME => ("gethostname", 2),
+        NR_SETHOSTNAME => ("sethostname", 2),
+        NR_CLONE => ("clone", 3),
+        NR_THREAD_JOIN => ("thread_join", 1),
+        NR_SPAWN => ("spawn", 4),
+        NR_READ_CONSOLE => ("read_console", 2),
+        NR_YIELD => ("yield", 0),
+        NR_SLEEP_UNTIL => ("sleep_until", 3),
+        NR_UNAME => ("uname", 2),
+        _ => ("unknown", 0),
+    }
+}
+
+/// Returns the ID of the process making the syscall in `tf` if it is being
+/// traced.
+pub(super) fn traced(tf: &TrapFrame) -> Option<Id> {
+    SCHEDULER.critical(|scheduler| match scheduler.current_mut(tf) {
+        Some(p) if p.traced => Some(p.context.tpidr),
+        _ => None,
+    })
+}
+
+/// Logs the entry of syscall `num` with its arguments, in `tf`.
+pub(super) fn enter(pid: Id, num: u16, tf: &TrapFrame) {
+    let (name, argc) = describe(num);
+    let args = &tf.x_registers[..argc];
+    match args {
+        [] => info!("strace: {} {}()", pid, name),
+        [a] => info!("strace: {} {}({:#x})", pid, name, a),
+        [a, b] => info!("strace: {} {}({:#x}, {:#x})", pid, name, a, b),
+        [a, b, c, ..] => info!("strace: {} {}({:#x}, {:#x}, {:#x})", pid, name, a, b, c),
+    }
+}
+
+/// Logs the result of syscall `num` made by `pid`. If `tf` no longer holds
+/// `pid`'s context, the syscall blocked or ended the process and its result
+/// is not known yet.
+pub(super) fn exit(pid: Id, num: u16, tf: &TrapFrame) {
+    let (name, _) = describe(num);
+    if tf.tpidr != pid {
+        return info!("strace: {} {} = ? (switched away)", pid, name);
+    }
+    match OsError::from(tf.x_registers[7]) {
+        OsError::Ok => info!("strace: {} {} = {:#x}", pid, name, tf.x_registers[0]),
+        e => info!("strace: {} {} = {}", pid, name, e),
+    }
+}
@@ -0,0 +1,45 @@
+//! What the running image was built from, so that testers can tell which
+//! one a board is running. `build.rs` records it when the kernel is built.
+
+use alloc::string::String;
+use core::fmt::Write as _;
+
+use crate::console::kprintln;
+
+/// The system's name, whatever the hostname.
+pub const SYSNAME: &str = "rustos";
+
+/// The kernel crate's version.
+pub const RELEASE: &str = env!("CARGO_PKG_VERSION");
+
+/// The abbreviated git commit the image was built from, with `-dirty` if
+/// tracked files had changes, or `unknown` outside a git checkout.
+pub const GIT_REVISION: &str = env!("RUSTOS_GIT_REVISION");
+
+/// When the image was built, in ISO 8601 UTC.
+pub const BUILD_TIME: &str = env!("RUSTOS_BUILD_TIME");
+
+/// The output of `rustc --version` for the compiler that built the image.
+pub const RUSTC_VERSION: &str = env!("RUSTOS_RUSTC_VERSION");
+
+/// The enabled cargo features, comma-separated and sorted.
+pub const FEATURES: &str = env!("RUSTOS_FEATURES");
+
+/// Returns the build's metadata as `sys_uname` reports it: the system's
+/// name, the release, the git revision, the build time, the features and
+/// the compiler's version, one per line.
+pub fn uname() -> String {
+    let mut text = String::new();
+    for field in [SYSNAME, RELEASE, GIT_REVISION, BUILD_TIME, FEATURES, RUSTC_VERSION].iter() {
+        let _ = writeln!(text, "{}", field);
+    }
+    text
+}
+
+/// Prints the build's metadata, for the `version` command.
+pub fn print() {
+    kprintln!("{} {} ({})", SYSNAME, RELEASE, GIT_REVISION);
+    kprintln!("built:    {}", BUILD_TIME);
+    kprintln!("compiler: {}", RUSTC_VERSION);
+    kprintln!("features: {}", if FEATURES.is_empty() { "none" } else { FEATURES });
+}
@@ -0,0 +1,294 @@
+//! The console on the HDMI display, drawn in the VideoCore's framebuffer.
+//!
+//! It is started with the `video` kernel parameter or the `vidmode` shell
+//! command, in a mode chosen among those the display reports in its EDID.
+//! Like the TFT mirror in `display`, everything the console prints is queued
+//! here and a kernel thread renders it into a `Screen`, redrawing the cells
+//! that changed. Changing the mode reallocates the framebuffer and resizes
+//! the screen, so its scrollback survives; PageUp and PageDown are not wired
+//! to it yet, but `Screen` keeps the lines for them.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use pi::edid::{Edid, Mode};
+use pi::framebuffer::{self, Framebuffer};
+use pi::mailbox;
+use ringbuf::{Mpsc, Slot};
+use shim::io;
+use shim::newioerr;
+
+use crate::cmdline::Param;
+use crate::console::font;
+use crate::console::screen::{Cell, Screen, PALETTE};
+use crate::console::CONSOLE;
+use crate::display::rgb565_of;
+use crate::logger::warn;
+use crate::mutex::Mutex;
+use crate::process::{Id, Process};
+use crate::SCHEDULER;
+
+/// How often the console thread redraws the screen.
+const REFRESH: Duration = Duration::from_millis(50);
+
+/// The most console output queued between redraws.
+const MAX_PENDING: usize = 8192;
+
+/// The lines kept once they scroll off the top of the screen.
+const SCROLLBACK: usize = 500;
+
+/// The mode used when the display reports none.
+const FALLBACK: (u32, u32) = (640, 480);
+
+/// The smallest and largest accepted resolutions.
+const MIN_SIZE: u32 = 64;
+const MAX_SIZE: u32 = 4096;
+
+/// Parses a resolution as `<width>x<height>`, e.g. `1280x720`.
+pub fn parse_size(value: &str) -> Option<(u32, u32)> {
+    let mut parts = value.splitn(2, 'x');
+    let width: u32 = parts.next()?.parse().ok()?;
+    let height: u32 = parts.next()?.parse().ok()?;
+    let valid = |size: u32| size >= MIN_SIZE && size <= MAX_SIZE;
+    if valid(width) && valid(height) {
+        Some((width, height))
+    } else {
+        None
+    }
+}
+
+/// The video console's kernel parameters; see `cmdline`.
+pub static PARAMS: &[Param] = &[Param {
+    name: "video",
+    description: "HDMI console mode as <width>x<height>, or auto for the display's preferred mode",
+    set: set_video,
+}];
+
+/// The resolution asked for by the `video` parameter: `Some(None)` for the
+/// preferred mode.
+static BOOT_SIZE: Mutex<Option<Option<(u32, u32)>>> = Mutex::new(None);
+
+fn set_video(_name: &str, value: &str) -> Result<(), String> {
+    let size = match value {
+        "auto" | "" => None,
+        value => match parse_size(value) {
+            Some(size) => Some(size),
+            None => return Err(String::from("expected <width>x<height> or auto")),
+        },
+    };
+    *BOOT_SIZE.lock() = Some(size);
+    Ok(())
+}
+
+/// Returns the display's EDID, or `None` if there is no display or it
+/// reports an invalid one.
+pub fn edid() -> Option<Edid> {
+    Edid::parse(&mailbox::edid_block(0)?).ok()
+}
+
+/// Returns the modes the display supports, largest first, each resolution
+/// once at its highest refresh rate. Empty if there is no EDID.
+pub fn modes() -> Vec<Mode> {
+    let mut modes: Vec<Mode> = edid().map(|edid| edid.modes().collect()).unwrap_or_default();
+    modes.sort_by(|a, b| b.cmp(a));
+    modes.dedup_by_key(|mode| (mode.width, mode.height));
+    modes
+}
+
+/// Returns the resolution to use when none is asked for: the display's
+/// preferred mode, or else the one the firmware set up at boot.
+fn default_size() -> (u32, u32) {
+    edid()
+        .and_then(|edid| edid.preferred())
+        .map(|mode| (mode.width, mode.height))
+        .or_else(framebuffer::boot_size)
+        .unwrap_or(FALLBACK)
+}
+
+struct Video {
+    framebuffer: Framebuffer,
+    screen: Screen,
+    /// The cells drawn in the framebuffer, row by row.
+    drawn: Vec<Cell>,
+}
+
+impl Video {
+    /// Forgets what was drawn, so that the next redraw draws every cell.
+    fn invalidate(&mut self) {
+        let (cols, rows) = self.screen.size();
+        let unknown = Cell { byte: 0, fg: 0, bg: 0 };
+        self.drawn = vec![unknown; cols * rows];
+    }
+
+    /// Renders the queued output and draws the cells that changed.
+    fn refresh(&mut self) {
+        // Only the console thread calls `refresh()`, so there is one consumer.
+        let mut consumer = unsafe { PENDING.consumer() };
+        while let Some(byte) = consumer.pop() {
+            self.screen.write_byte(byte);
+        }
+        if !self.screen.take_dirty() {
+            return;
+        }
+        let (screen, drawn, framebuffer) = (&self.screen, &mut self.drawn, &mut self.framebuffer);
+        for (row, line) in screen.visible_lines().enumerate() {
+            for (col, &cell) in line.iter().enumerate() {
+                let index = row * line.len() + col;
+                if drawn[index] == cell {
+                    continue;
+                }
+                drawn[index] = cell;
+                draw_cell(framebuffer, col, row, cell);
+            }
+        }
+    }
+}
+
+/// Draws `cell` at column `col` and row `row` of the character grid.
+fn draw_cell(framebuffer: &mut Framebuffer, col: usize, row: usize, cell: Cell) {
+    let fg = rgb565_of(PALETTE[cell.fg as usize]);
+    let bg = rgb565_of(PALETTE[cell.bg as usize]);
+    let (x, y) = (col * font::WIDTH, row * font::HEIGHT);
+    for (i, bits) in font::glyph(cell.byte).iter().enumerate() {
+        let mut pixels = [bg; font::WIDTH];
+        for (col, pixel) in pixels.iter_mut().enumerate() {
+            if bits & (1 << col) != 0 {
+                *pixel = fg;
+            }
+        }
+        framebuffer.write_row(x, y + i, &pixels);
+    }
+}
+
+/// Returns the size of the character grid filling `framebuffer`.
+fn grid(framebuffer: &Framebuffer) -> (usize, usize) {
+    (framebuffer.width() / font::WIDTH, framebuffer.height() / font::HEIGHT)
+}
+
+static VIDEO: Mutex<Option<Video>> = Mutex::new(None);
+
+/// Console output waiting to be rendered by the console thread.
+static PENDING: Mpsc<[Slot<u8>; MAX_PENDING]> = Mpsc::new([Slot::new(0); MAX_PENDING]);
+
+/// Whether console output is queued for the video console.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The console thread, once started.
+static THREAD: Mutex<Option<Id>> = Mutex::new(None);
+
+/// Queues console output for the video console, if it is running. Called by
+/// the console with its lock held for everything it prints.
+pub fn mirror(bytes: &[u8]) {
+    if ENABLED.load(Ordering::Relaxed) {
+        let _ = PENDING.push_slice(bytes);
+    }
+}
+
+/// Starts the video console if the `video` parameter asked for it. Logs a
+/// warning if it cannot be started.
+pub fn initialize() {
+    let size = match *BOOT_SIZE.lock() {
+        Some(size) => size,
+        None => return,
+    };
+    if let Err(e) = set_mode(size) {
+        warn!("video: cannot start the console: {:?}", e);
+    }
+}
+
+/// Returns the current mode's size, or `None` if the video console is not
+/// running.
+pub fn current_size() -> Option<(usize, usize)> {
+    VIDEO.lock().as_ref().map(|video| (video.framebuffer.width(), video.framebuffer.height()))
+}
+
+/// Switches the video console to a `size` framebuffer, or to the display's
+/// preferred mode if `size` is `None`, starting it if it is not running.
+/// The screen keeps its contents and scrollback across the change.
+///
+/// Returns `Other` if the firmware refuses the mode, in which case the
+/// previous mode is restored, or if the console thread cannot be started.
+pub fn set_mode(size: Option<(u32, u32)>) -> io::Result<()> {
+    let (width, height) = size.unwrap_or_else(default_size);
+    start_thread()?;
+    let mut guard = VIDEO.lock();
+    match guard.take() {
+        Some(video) => {
+            let Video { framebuffer, mut screen, .. } = video;
+            let (old_width, old_height) = (framebuffer.width() as u32, framebuffer.height() as u32);
+            framebuffer.release();
+            let (framebuffer, result) = match Framebuffer::allocate(width, height) {
+                Some(framebuffer) => (framebuffer, Ok(())),
+                None => match Framebuffer::allocate(old_width, old_height) {
+                    Some(framebuffer) => (framebuffer, Err(newioerr!(Other, "mode not supported"))),
+                    None => {
+                        ENABLED.store(false, Ordering::Relaxed);
+                        return Err(newioerr!(Other, "cannot restore the previous mode"));
+                    }
+                },
+            };
+            let (cols, rows) = grid(&framebuffer);
+            screen.resize(cols, rows);
+            let mut video = Video { framebuffer, screen, drawn: Vec::new() };
+            video.invalidate();
+            *guard = Some(video);
+            result
+        }
+        None => {
+            let framebuffer = Framebuffer::allocate(width, height)
+                .ok_or_else(|| newioerr!(Other, "mode not supported"))?;
+            let (cols, rows) = grid(&framebuffer);
+            let mut screen = Screen::new(cols, rows, SCROLLBACK);
+            // Output printed from now on is queued; that printed before is
+            // in the console's history.
+            let console = CONSOLE.lock();
+            let (older, newer) = console.history();
+            older.iter().chain(newer).for_each(|&byte| screen.write_byte(byte));
+            ENABLED.store(true, Ordering::Relaxed);
+            drop(console);
+            let mut video = Video { framebuffer, screen, drawn: Vec::new() };
+            video.invalidate();
+            *guard = Some(video);
+            Ok(())
+        }
+    }
+}
+
+/// Starts the console thread unless it is running.
+fn start_thread() -> io::Result<()> {
+    let mut thread = THREAD.lock();
+    if thread.is_none() {
+        let process = Process::kernel_thread(video_thread)
+            .map_err(|_| newioerr!(Other, "cannot start console thread"))?;
+        let pid = SCHEDULER.add(process).ok_or_else(|| newioerr!(Other, "no process ID"))?;
+        *thread = Some(pid);
+    }
+    Ok(())
+}
+
+extern "C" fn video_thread() -> ! {
+    loop {
+        let _ = kernel_api::syscall::sleep(REFRESH);
+        if let Some(video) = VIDEO.lock().as_mut() {
+            video.refresh();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("1280x720"), Some((1280, 720)));
+        assert_eq!(parse_size("1920x1080"), Some((1920, 1080)));
+        assert_eq!(parse_size("1280"), None);
+        assert_eq!(parse_size("1280x"), None);
+        assert_eq!(parse_size("0x0"), None);
+        assert_eq!(parse_size("8192x4096"), None);
+    }
+}
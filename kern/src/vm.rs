@@ -1,16 +1,31 @@
 use crate::mutex::Mutex;
+use crate::traps::ipi::{self, Ipi};
 
 use aarch64::*;
 
 mod address;
+mod config;
 mod pagetable;
 
+#[cfg(test)]
+mod tests;
+
 pub use self::address::{PhysicalAddr, VirtualAddr};
+pub use self::config::{Granule, TranslationConfig};
 pub use self::pagetable::*;
-use crate::param::{KERNEL_MASK_BITS, USER_MASK_BITS};
+use kernel_api::{OsError, OsResult};
+
+/// Flushes the TLB of every online core once a mapping they may have cached
+/// has changed: one of the kernel's, or a user mapping that a process's
+/// threads share. Returns once no core translates through the old one.
+pub fn shootdown() {
+    unsafe { flush_tlb() };
+    ipi::broadcast(Ipi::TlbFlush);
+}
 
-/// Thread-safe (locking) wrapper around a kernel page table.
-pub struct VMManager(Mutex<Option<KernPageTable>>);
+/// Thread-safe (locking) wrapper around a kernel page table and the
+/// translation configuration it is used with.
+pub struct VMManager(Mutex<Option<KernPageTable>>, Mutex<TranslationConfig>);
 
 impl VMManager {
     /// Returns an uninitialized `VMManager`.
@@ -18,7 +33,7 @@ impl VMManager {
     /// The virtual memory manager must be initialized by calling `initialize()` and `setup()`
     /// before the first memory allocation. Failure to do will result in panics.
     pub const fn uninitialized() -> Self {
-        VMManager(Mutex::new(None))
+        VMManager(Mutex::new(None), Mutex::new(TranslationConfig::new()))
     }
 
     /// Initializes the virtual memory manager.
@@ -46,44 +61,91 @@ impl VMManager {
 
             let ips = ID_AA64MMFR0_EL1.get_value(ID_AA64MMFR0_EL1::PARange);
 
-            // (ref. D7.2.70: Memory Attribute Indirection Register)
-            MAIR_EL1.set(
-                (0xFF <<  0) |// AttrIdx=0: normal, IWBWA, OWBWA, NTR
-                (0x04 <<  8) |// AttrIdx=1: device, nGnRE (must be OSH too)
-                (0x44 << 16), // AttrIdx=2: non cacheable
-            );
-            // (ref. D7.2.91: Translation Control Register)
-            TCR_EL1.set(
-                (0b00 << 37) |// TBI=0, no tagging
-                (ips  << 32) |// IPS
-                (0b11 << 30) |// TG1=64k
-                (0b11 << 28) |// SH1=3 inner
-                (0b01 << 26) |// ORGN1=1 write back
-                (0b01 << 24) |// IRGN1=1 write back
-                (0b0  << 23) |// EPD1 enables higher half
-                ((USER_MASK_BITS as u64) << 16) | // T1SZ=34 (1GB)
-                (0b01 << 14) |// TG0=64k
-                (0b11 << 12) |// SH0=3 inner
-                (0b01 << 10) |// ORGN0=1 write back
-                (0b01 <<  8) |// IRGN0=1 write back
-                (0b0  <<  7) |// EPD0 enables lower half
-                ((KERNEL_MASK_BITS as u64) << 0), // T0SZ=32 (4GB)
-            );
+            let config = *self.1.lock();
+            MAIR_EL1.set(config.mair_value());
+            TCR_EL1.set(config.tcr_value(ips));
             isb();
 
             TTBR0_EL1.set(baddr);
             TTBR1_EL1.set(baddr);
 
-            asm!("dsb ish");
+            dsb_ish();
             isb();
 
             SCTLR_EL1.set(SCTLR_EL1.get() | SCTLR_EL1::I | SCTLR_EL1::C | SCTLR_EL1::M);
 
-            asm!("dsb sy");
+            dsb();
             isb();
         }
     }
 
+    /// Returns the translation configuration `setup()` uses.
+    pub fn translation_config(&self) -> TranslationConfig {
+        *self.1.lock()
+    }
+
+    /// Replaces the translation configuration. If the MMU is already on, it
+    /// is applied at once and the TLB is flushed; otherwise `setup()` applies
+    /// it.
+    ///
+    /// Returns `InvalidArgument` if the kernel's page tables cannot be used
+    /// with `config`.
+    pub fn configure(&self, config: TranslationConfig) -> OsResult<()> {
+        config.validate()?;
+        *self.1.lock() = config;
+        if self.0.lock().is_some() {
+            self.setup();
+            unsafe { flush_tlb() };
+        }
+        Ok(())
+    }
+
+    /// Sets the memory attribute that entries with an `ATTR` of `index`
+    /// select to `attr`, one of the `MemAttr` encodings.
+    ///
+    /// Returns `InvalidArgument` if `index` is not a `MAIR_EL1` slot.
+    pub fn set_memory_attribute(&self, index: usize, attr: u8) -> OsResult<()> {
+        let mut config = self.translation_config();
+        *config.mair.get_mut(index).ok_or(OsError::InvalidArgument)? = attr;
+        self.configure(config)
+    }
+
+    /// Turns the RAM page at `page`, which must be page aligned, into a guard
+    /// page: it is unmapped from the kernel's identity map, so that a stack
+    /// growing into it faults instead of corrupting the memory below.
+    pub fn guard(&self, page: PhysicalAddr) {
+        if let Some(kpt) = &mut *self.0.lock() {
+            kpt.unmap_guard(page);
+        }
+        shootdown();
+    }
+
+    /// Maps a page turned into a guard page by `guard()` again. The caller
+    /// should do this before the page is freed.
+    pub fn unguard(&self, page: PhysicalAddr) {
+        if let Some(kpt) = &mut *self.0.lock() {
+            kpt.remap_guard(page);
+        }
+        shootdown();
+    }
+
+    /// Returns `true` if `va` lies in a guard page: RAM that the kernel's
+    /// identity map leaves unmapped.
+    pub fn is_guard(&self, va: VirtualAddr) -> bool {
+        let end = match crate::allocator::memory_map() {
+            Some((_, end)) => end,
+            None => return false,
+        };
+        if va.as_usize() >= end {
+            return false;
+        }
+        let page = VirtualAddr::from(va.as_usize() & crate::param::PAGE_MASK);
+        match &*self.0.lock() {
+            Some(kpt) => kpt.is_invalid(page),
+            None => false,
+        }
+    }
+
     /// Returns the base address of the kernel page table as `PhysicalAddr`.
     pub fn get_baddr(&self) -> PhysicalAddr {
         if let Some(kpt) = &*self.0.lock() {
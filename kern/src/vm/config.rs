@@ -0,0 +1,189 @@
+use core::fmt;
+
+use aarch64::{EntrySh, MemAttr, Tg0, Tg1, WalkCache, MAIR_EL1, TCR_EL1};
+use kernel_api::{OsError, OsResult};
+
+use crate::param::{KERNEL_MASK_BITS, PAGE_SIZE, USER_MASK_BITS};
+
+/// The smallest `T0SZ`: with 64KB pages, a larger region would need a level 1
+/// table, and the page tables start at level 2.
+const MIN_T0SZ: u8 = 22;
+
+/// The largest `T0SZ`: a smaller region would not reach `LOCAL_END`, the
+/// end of the kernel's identity map.
+const MAX_T0SZ: u8 = 33;
+
+/// The size of the pages that translation tables map.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Granule {
+    K4,
+    K16,
+    K64,
+}
+
+impl Granule {
+    pub fn size(self) -> usize {
+        match self {
+            Granule::K4 => 4 * 1024,
+            Granule::K16 => 16 * 1024,
+            Granule::K64 => 64 * 1024,
+        }
+    }
+
+    fn tg0(self) -> u64 {
+        match self {
+            Granule::K4 => Tg0::K4,
+            Granule::K16 => Tg0::K16,
+            Granule::K64 => Tg0::K64,
+        }
+    }
+
+    fn tg1(self) -> u64 {
+        match self {
+            Granule::K4 => Tg1::K4,
+            Granule::K16 => Tg1::K16,
+            Granule::K64 => Tg1::K64,
+        }
+    }
+
+    fn from_tg0(tg0: u64) -> Option<Granule> {
+        match tg0 {
+            Tg0::K4 => Some(Granule::K4),
+            Tg0::K16 => Some(Granule::K16),
+            Tg0::K64 => Some(Granule::K64),
+            _ => None,
+        }
+    }
+}
+
+/// The contents of `MAIR_EL1` and the translation fields of `TCR_EL1`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TranslationConfig {
+    /// The `MemAttr` encoding that each value of an entry's `ATTR` field
+    /// selects.
+    pub mair: [u8; 8],
+    pub granule: Granule,
+    /// The lower, kernel, region spans `2^(64 - t0sz)` bytes from 0.
+    pub t0sz: u8,
+    /// The upper, user, region spans `2^(64 - t1sz)` bytes below 2^64.
+    pub t1sz: u8,
+}
+
+impl TranslationConfig {
+    /// Returns the configuration the kernel boots with: cached normal memory,
+    /// device memory and uncached normal memory in the slots `EntryAttr`
+    /// names, and the page size and address spaces in `param`.
+    pub const fn new() -> TranslationConfig {
+        TranslationConfig {
+            // Indexed by `EntryAttr::{Mem, Dev, Nc}`.
+            mair: [
+                MemAttr::NORMAL_WB,
+                MemAttr::DEVICE_nGnRE,
+                MemAttr::NORMAL_NC,
+                MemAttr::DEVICE_nGnRnE,
+                MemAttr::DEVICE_nGnRnE,
+                MemAttr::DEVICE_nGnRnE,
+                MemAttr::DEVICE_nGnRnE,
+                MemAttr::DEVICE_nGnRnE,
+            ],
+            granule: Granule::K64,
+            t0sz: KERNEL_MASK_BITS as u8,
+            t1sz: USER_MASK_BITS as u8,
+        }
+    }
+
+    /// Returns `InvalidArgument` unless the page tables can be used with
+    /// this configuration: they hold `PAGE_SIZE` pages, start at level 2,
+    /// and user addresses are laid out for `USER_MASK_BITS`.
+    pub fn validate(&self) -> OsResult<()> {
+        if self.granule.size() != PAGE_SIZE
+            || self.t0sz < MIN_T0SZ
+            || self.t0sz > MAX_T0SZ
+            || self.t1sz as usize != USER_MASK_BITS
+        {
+            return Err(OsError::InvalidArgument);
+        }
+        Ok(())
+    }
+
+    /// Returns the `MAIR_EL1` value.
+    pub fn mair_value(&self) -> u64 {
+        self.mair
+            .iter()
+            .enumerate()
+            .fold(0, |mair, (i, &attr)| mair | (attr as u64) << (8 * i))
+    }
+
+    /// Returns the `TCR_EL1` value for a physical address size of `ips`, as
+    /// `ID_AA64MMFR0_EL1.PARange` reports it. Table walks are cached
+    /// write-back and inner shareable in both halves.
+    pub fn tcr_value(&self, ips: u64) -> u64 {
+        field(ips, TCR_EL1::IPS)
+            | field(self.granule.tg1(), TCR_EL1::TG1)
+            | field(EntrySh::ISh, TCR_EL1::SH1)
+            | field(WalkCache::WriteBackAlloc, TCR_EL1::ORGN1)
+            | field(WalkCache::WriteBackAlloc, TCR_EL1::IRGN1)
+            | field(self.t1sz as u64, TCR_EL1::T1SZ)
+            | field(self.granule.tg0(), TCR_EL1::TG0)
+            | field(EntrySh::ISh, TCR_EL1::SH0)
+            | field(WalkCache::WriteBackAlloc, TCR_EL1::ORGN0)
+            | field(WalkCache::WriteBackAlloc, TCR_EL1::IRGN0)
+            | field(self.t0sz as u64, TCR_EL1::T0SZ)
+    }
+
+    /// Decodes register values into a configuration, or returns `None` if
+    /// `tcr` has a reserved granule encoding.
+    pub fn from_registers(mair: u64, tcr: u64) -> Option<TranslationConfig> {
+        let mut attrs = [0; 8];
+        for (i, attr) in attrs.iter_mut().enumerate() {
+            *attr = (mair >> (8 * i)) as u8;
+        }
+        Some(TranslationConfig {
+            mair: attrs,
+            granule: Granule::from_tg0(TCR_EL1::get_value(tcr, TCR_EL1::TG0))?,
+            t0sz: TCR_EL1::get_value(tcr, TCR_EL1::T0SZ) as u8,
+            t1sz: TCR_EL1::get_value(tcr, TCR_EL1::T1SZ) as u8,
+        })
+    }
+
+    /// Reads the configuration the MMU is using.
+    pub fn current() -> Option<TranslationConfig> {
+        unsafe { TranslationConfig::from_registers(MAIR_EL1.get(), TCR_EL1.get()) }
+    }
+}
+
+fn field(value: u64, mask: u64) -> u64 {
+    (value << mask.trailing_zeros()) & mask
+}
+
+/// Returns the size in megabytes of a region with a size offset of `tnsz`.
+fn region_mb(tnsz: u8) -> u64 {
+    1u64.checked_shl(64 - tnsz.min(64) as u32).unwrap_or(0) >> 20
+}
+
+/// Returns a name for a `MemAttr` encoding.
+fn attr_name(attr: u8) -> &'static str {
+    match attr {
+        MemAttr::DEVICE_nGnRnE => "device nGnRnE",
+        MemAttr::DEVICE_nGnRE => "device nGnRE",
+        MemAttr::NORMAL_NC => "normal non-cacheable",
+        MemAttr::NORMAL_WT => "normal write-through",
+        MemAttr::NORMAL_WB => "normal write-back",
+        _ => "other",
+    }
+}
+
+impl fmt::Display for TranslationConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "granule  {}KB", self.granule.size() / 1024)?;
+        writeln!(f, "T0SZ     {:<2} (kernel, {} MB)", self.t0sz, region_mb(self.t0sz))?;
+        writeln!(f, "T1SZ     {:<2} (user, {} MB)", self.t1sz, region_mb(self.t1sz))?;
+        for (i, &attr) in self.mair.iter().enumerate() {
+            write!(f, "Attr{}    {:#04x} {}", i, attr, attr_name(attr))?;
+            if i + 1 < self.mair.len() {
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
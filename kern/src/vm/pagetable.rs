@@ -3,16 +3,18 @@ use core::ops::{Deref, DerefMut};
 use core::slice::Iter;
 use core::fmt::Formatter;
 
+use alloc::alloc::{alloc, dealloc};
 use alloc::boxed::Box;
 use alloc::fmt;
-use core::alloc::{GlobalAlloc, Layout};
+use alloc::vec::Vec;
+use core::alloc::Layout;
 
 use crate::allocator;
 use crate::param::*;
 use crate::vm::{PhysicalAddr, VirtualAddr};
-use crate::ALLOCATOR;
 
 use aarch64::vmsa::*;
+use kernel_api::{OsError, OsResult};
 use shim::const_assert_size;
 
 #[repr(C)]
@@ -80,6 +82,65 @@ impl L3Entry {
     }
 }
 
+/// A contiguous range of virtual memory mapped to contiguous physical memory
+/// with uniform permissions, memory attributes, and shareability.
+#[derive(Debug, Copy, Clone)]
+pub struct Region {
+    /// The first virtual address of the region.
+    pub start: VirtualAddr,
+    /// The virtual address one past the end of the region.
+    pub end: VirtualAddr,
+    /// The physical address `start` translates to.
+    pub phys: PhysicalAddr,
+    /// The `AP` field of the region's entries.
+    pub perm: u64,
+    /// The `ATTR` field of the region's entries.
+    pub attr: u64,
+    /// The `SH` field of the region's entries.
+    pub sh: u64,
+}
+
+impl Region {
+    /// Returns `true` if the page at `va` described by `entry` extends this
+    /// region.
+    fn extends(&self, va: VirtualAddr, entry: &RawL3Entry) -> bool {
+        let offset = self.end.as_usize() - self.start.as_usize();
+        va == self.end
+            && entry.get_masked(RawL3Entry::ADDR) as usize == self.phys.as_usize() + offset
+            && entry.get_value(RawL3Entry::AP) == self.perm
+            && entry.get_value(RawL3Entry::ATTR) == self.attr
+            && entry.get_value(RawL3Entry::SH) == self.sh
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:#018x}-{:#018x} -> {:#010x} {} {} {} ({} pages)",
+            self.start.as_usize(),
+            self.end.as_usize(),
+            self.phys.as_usize(),
+            match self.perm {
+                EntryPerm::KERN_RW => "KERN-RW",
+                EntryPerm::USER_RW => "USER-RW",
+                EntryPerm::KERN_RO => "KERN-RO",
+                EntryPerm::USER_RO => "USER-RO",
+                _ => "????-??",
+            },
+            match self.attr {
+                EntryAttr::Mem => "mem",
+                EntryAttr::Dev => "dev",
+                EntryAttr::Nc => "nc ",
+                _ => "???",
+            },
+            match self.sh {
+                EntrySh::ISh => "ish",
+                EntrySh::OSh => "osh",
+                _ => "nsh",
+            },
+            (self.end.as_usize() - self.start.as_usize()) / PAGE_SIZE)
+    }
+}
+
 impl fmt::Debug for L3Entry {
     fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
         self.0.fmt(fmt)
@@ -175,6 +236,22 @@ impl PageTable {
         !self.is_valid(va)
     }
 
+    /// Returns the L3 entry indicated by the given virtual address.
+    fn entry(&self, va: VirtualAddr) -> &RawL3Entry {
+        let (l2, l3) = PageTable::locate(va);
+        let l3_address = self.l2.entries[l2].get_masked(RawL2Entry::ADDR) as usize;
+        let l3_index = (l3_address - self.l3[0].as_ptr().as_usize()) / PAGE_SIZE;
+        &self.l3[l3_index].entries[l3].0
+    }
+
+    /// Returns the L3 entry indicated by the given virtual address, mutably.
+    fn entry_mut(&mut self, va: VirtualAddr) -> &mut RawL3Entry {
+        let (l2, l3) = PageTable::locate(va);
+        let l3_address = self.l2.entries[l2].get_masked(RawL2Entry::ADDR) as usize;
+        let l3_index = (l3_address - self.l3[0].as_ptr().as_usize()) / PAGE_SIZE;
+        &mut self.l3[l3_index].entries[l3].0
+    }
+
     /// Set the given RawL3Entry `entry` to the L3Entry indicated by the given virtual
     /// address.
     pub fn set_entry(&mut self, va: VirtualAddr, entry: RawL3Entry) -> &mut Self {
@@ -190,6 +267,35 @@ impl PageTable {
     pub fn get_baddr(&self) -> PhysicalAddr {
         self.l2.as_ptr()
     }
+
+    /// Walks the page table and returns its valid mappings coalesced into
+    /// `Region`s, in ascending order of virtual address. `base` is the virtual
+    /// address translated by the first entry of the table.
+    pub fn regions(&self, base: VirtualAddr) -> Vec<Region> {
+        let mut regions: Vec<Region> = Vec::new();
+        for (i, l3_entry) in self.into_iter().enumerate() {
+            if !l3_entry.is_valid() {
+                continue;
+            }
+            let entry = &l3_entry.0;
+            let va = base + VirtualAddr::from(i * PAGE_SIZE);
+            if let Some(last) = regions.last_mut() {
+                if last.extends(va, entry) {
+                    last.end += VirtualAddr::from(PAGE_SIZE);
+                    continue;
+                }
+            }
+            regions.push(Region {
+                start: va,
+                end: va + VirtualAddr::from(PAGE_SIZE),
+                phys: PhysicalAddr::from(entry.get_masked(RawL3Entry::ADDR)),
+                perm: entry.get_value(RawL3Entry::AP),
+                attr: entry.get_value(RawL3Entry::ATTR),
+                sh: entry.get_value(RawL3Entry::SH),
+            });
+        }
+        regions
+    }
 }
 
 impl fmt::Debug for PageTable {
@@ -210,53 +316,112 @@ impl<'a> IntoIterator for &'a PageTable {
     }
 }
 
-pub struct KernPageTable(Box<PageTable>);
+/// The kernel page table, and the table mapping the local peripherals at
+/// `LOCAL_BASE`, which lie beyond the 1GB `PageTable` covers.
+pub struct KernPageTable(Box<PageTable>, Box<L3PageTable>);
 
 impl KernPageTable {
     /// Returns a new `KernPageTable`. `KernPageTable` should have a `Pagetable`
     /// created with `KERN_RW` permission.
     ///
     /// Set L3entry of ARM physical address starting at 0x00000000 for RAM and
-    /// physical address range from `IO_BASE` to `IO_BASE_END` for peripherals.
+    /// device memory from the end of RAM to `IO_BASE_END`, covering the
+    /// VideoCore's memory and the peripherals.
     /// Each L3 entry should have correct value for lower attributes[10:0] as well
     /// as address[47:16]. Refer to the definition of `RawL3Entry` in `vmsa.rs` for
     /// more details.
+    ///
+    /// The local peripherals, from `LOCAL_BASE` to `LOCAL_END`, are mapped
+    /// through a table of their own.
     pub fn new() -> KernPageTable {
-        let mut kpt = KernPageTable(PageTable::new(EntryPerm::KERN_RW));
+        let mut kpt =
+            KernPageTable(PageTable::new(EntryPerm::KERN_RW), Box::new(L3PageTable::new()));
         if let Some((_, end)) = allocator::memory_map() {
             let mut addr = 0;
             while addr < end {
-                let mut entry = RawL3Entry::new(0);
-                entry
-                    .set_value(EntryValid::Valid, RawL3Entry::VALID)
-                    .set_value(PageType::Page, RawL3Entry::TYPE)
-                    .set_value(EntryAttr::Mem, RawL3Entry::ATTR)
-                    .set_value(EntryPerm::KERN_RW, RawL3Entry::AP)
-                    .set_masked(addr as u64, RawL3Entry::ADDR)
-                    .set_value(EntrySh::ISh, RawL3Entry::SH)
-                    .set_bit(RawL3Entry::AF);
-                kpt.set_entry(addr.into(), entry);
+                kpt.set_entry(addr.into(), KernPageTable::ram_entry(addr));
                 addr += PAGE_SIZE;
             }
-            addr = IO_BASE;
+            // The VideoCore's memory, between RAM and the peripherals, holds
+            // the framebuffer. Mapping it as device memory keeps writes to
+            // it out of the data cache, where the GPU would not see them.
             while addr < IO_BASE_END {
-                let mut entry = RawL3Entry::new(0);
-                entry
-                    .set_value(EntryValid::Valid, RawL3Entry::VALID)
-                    .set_value(PageType::Page, RawL3Entry::TYPE)
-                    .set_value(EntryAttr::Dev, RawL3Entry::ATTR)
-                    .set_value(EntryPerm::KERN_RW, RawL3Entry::AP)
-                    .set_masked(addr as u64, RawL3Entry::ADDR)
-                    .set_value(EntrySh::OSh, RawL3Entry::SH)
-                    .set_bit(RawL3Entry::AF);
-                kpt.set_entry(addr.into(), entry);
+                kpt.set_entry(addr.into(), KernPageTable::device_entry(addr));
                 addr += PAGE_SIZE;
             }
+            kpt.map_local_peripherals();
         } else {
             panic!("could not map memory");
         }
         kpt
     }
+
+    /// Identity-maps the local peripherals, as device memory, through the
+    /// second table and points the level 2 entry for `LOCAL_BASE` at it.
+    fn map_local_peripherals(&mut self) {
+        let mut addr = LOCAL_BASE;
+        while addr < LOCAL_END {
+            let mut entry = RawL3Entry::new(0);
+            entry
+                .set_value(EntryValid::Valid, RawL3Entry::VALID)
+                .set_value(PageType::Page, RawL3Entry::TYPE)
+                .set_value(EntryAttr::Dev, RawL3Entry::ATTR)
+                .set_value(EntryPerm::KERN_RW, RawL3Entry::AP)
+                .set_masked(addr as u64, RawL3Entry::ADDR)
+                .set_value(EntrySh::OSh, RawL3Entry::SH)
+                .set_bit(RawL3Entry::AF);
+            self.1.entries[(addr - LOCAL_BASE) / PAGE_SIZE] = L3Entry(entry);
+            addr += PAGE_SIZE;
+        }
+        let l3 = self.1.as_ptr().as_u64();
+        self.0.l2.entries[LOCAL_BASE >> 29]
+            .set_value(EntryValid::Valid, RawL2Entry::VALID)
+            .set_value(EntryType::Table, RawL2Entry::TYPE)
+            .set_value(EntryPerm::KERN_RW, RawL2Entry::AP)
+            .set_value(EntrySh::ISh, RawL2Entry::SH)
+            .set_value(EntryAttr::Mem, RawL2Entry::ATTR)
+            .set_bit(RawL2Entry::AF)
+            .set_masked(l3, RawL2Entry::ADDR);
+    }
+
+    /// Returns the entry identity-mapping the RAM page at `addr`.
+    fn ram_entry(addr: usize) -> RawL3Entry {
+        let mut entry = RawL3Entry::new(0);
+        entry
+            .set_value(EntryValid::Valid, RawL3Entry::VALID)
+            .set_value(PageType::Page, RawL3Entry::TYPE)
+            .set_value(EntryAttr::Mem, RawL3Entry::ATTR)
+            .set_value(EntryPerm::KERN_RW, RawL3Entry::AP)
+            .set_masked(addr as u64, RawL3Entry::ADDR)
+            .set_value(EntrySh::ISh, RawL3Entry::SH)
+            .set_bit(RawL3Entry::AF);
+        entry
+    }
+
+    /// Returns the entry identity-mapping the device memory page at `addr`.
+    fn device_entry(addr: usize) -> RawL3Entry {
+        let mut entry = RawL3Entry::new(0);
+        entry
+            .set_value(EntryValid::Valid, RawL3Entry::VALID)
+            .set_value(PageType::Page, RawL3Entry::TYPE)
+            .set_value(EntryAttr::Dev, RawL3Entry::ATTR)
+            .set_value(EntryPerm::KERN_RW, RawL3Entry::AP)
+            .set_masked(addr as u64, RawL3Entry::ADDR)
+            .set_value(EntrySh::OSh, RawL3Entry::SH)
+            .set_bit(RawL3Entry::AF);
+        entry
+    }
+
+    /// Unmaps the RAM page at `page`, so that any access to it faults.
+    pub fn unmap_guard(&mut self, page: PhysicalAddr) {
+        self.set_entry(page.as_usize().into(), RawL3Entry::new(0));
+    }
+
+    /// Maps the RAM page at `page` again after `unmap_guard()`.
+    pub fn remap_guard(&mut self, page: PhysicalAddr) {
+        let addr = page.as_usize();
+        self.set_entry(addr.into(), KernPageTable::ram_entry(addr));
+    }
 }
 
 pub enum PagePerm {
@@ -267,6 +432,17 @@ pub enum PagePerm {
 
 pub struct UserPageTable(Box<PageTable>);
 
+/// Returns the swap slot recorded in `entry`, if it holds a page that was
+/// swapped out: such an entry is invalid, has its `TYPE` bit set, and holds
+/// the slot in its `ADDR` field.
+fn swap_slot(entry: &RawL3Entry) -> Option<usize> {
+    if entry.get_masked(RawL3Entry::VALID) == 0 && entry.get_masked(RawL3Entry::TYPE) != 0 {
+        Some((entry.get_masked(RawL3Entry::ADDR) >> 16) as usize)
+    } else {
+        None
+    }
+}
+
 impl UserPageTable {
     /// Returns a new `UserPageTable` containing a `PageTable` created with
     /// `USER_RW` permission.
@@ -277,23 +453,36 @@ impl UserPageTable {
     /// Allocates a page and set an L3 entry translates given virtual address to the
     /// physical address of the allocated page. Returns the allocated page.
     ///
-    /// # Panics
-    /// Panics if the virtual address is lower than `USER_IMG_BASE`.
-    /// Panics if the virtual address has already been allocated.
-    /// Panics if allocator fails to allocate a page.
+    /// Returns `BadAddress` if the virtual address is lower than
+    /// `USER_IMG_BASE`, `FileExists` if it has already been allocated, and
+    /// `NoMemory` if the allocator fails to allocate a page. The table is
+    /// left unchanged on failure.
     ///
-    /// TODO. use Result<T> and make it failurable
     /// TODO. use perm properly
-    pub fn alloc(&mut self, va: VirtualAddr, _perm: PagePerm) -> &mut [u8] {
+    pub fn alloc(&mut self, va: VirtualAddr, perm: PagePerm) -> OsResult<&mut [u8]> {
+        self.alloc_with(va, perm, || unsafe { alloc(Page::layout()) })
+    }
+
+    /// Like `alloc()`, but takes the page from `allocate`, which returns null
+    /// if it has none.
+    pub(super) fn alloc_with<F>(
+        &mut self,
+        va: VirtualAddr,
+        _perm: PagePerm,
+        allocate: F,
+    ) -> OsResult<&mut [u8]>
+    where
+        F: FnOnce() -> *mut u8,
+    {
         if va.as_usize() < USER_IMG_BASE {
-            panic!("invalid virtual address {:?}", va);
+            return Err(OsError::BadAddress);
         }
         if self.0.is_valid(va) {
-            panic!("address {:?} already allocated", va);
+            return Err(OsError::FileExists);
         }
-        let ptr = unsafe { ALLOCATOR.alloc(Page::layout()) };
-        if ptr == core::ptr::null_mut() {
-            panic!("could not allocate page");
+        let ptr = allocate();
+        if ptr.is_null() {
+            return Err(OsError::NoMemory);
         }
         let mut entry = RawL3Entry::new(0);
         entry
@@ -306,10 +495,55 @@ impl UserPageTable {
             .set_bit(RawL3Entry::AF);
         self.set_entry(va, entry);
 
+        Ok(unsafe { core::slice::from_raw_parts_mut(ptr, PAGE_SIZE) })
+    }
+
+    /// Returns the physical address of the page mapped at `va`, if any.
+    pub fn page_addr(&self, va: VirtualAddr) -> Option<PhysicalAddr> {
+        L3Entry(*self.0.entry(va)).get_page_addr()
+    }
+
+    /// Returns `true` if the access flag of the page mapped at `va` is set:
+    /// the page has been accessed since the flag was last cleared.
+    pub fn accessed(&self, va: VirtualAddr) -> bool {
+        self.0.entry(va).get_masked(RawL3Entry::AF) != 0
+    }
+
+    /// Sets or clears the access flag of the page mapped at `va`. Once it is
+    /// cleared, and the TLB flushed, the next access to the page faults.
+    pub fn set_accessed(&mut self, va: VirtualAddr, accessed: bool) {
+        let entry = self.0.entry_mut(va);
+        if accessed {
+            entry.set_bit(RawL3Entry::AF);
+        } else {
+            entry.clear_bit(RawL3Entry::AF);
+        }
+    }
+
+    /// Unmaps the page at `va`, whose contents have been saved to swap slot
+    /// `slot`, and frees it. The entry records the slot until the page is
+    /// mapped again.
+    ///
+    /// # Panics
+    /// Panics if no page is mapped at `va`.
+    pub fn swap_out(&mut self, va: VirtualAddr, slot: usize) {
+        let mut phys = self.page_addr(va).expect("swapping out an unmapped page");
+        let mut entry = RawL3Entry::new(0);
+        entry
+            .set_value(PageType::Page, RawL3Entry::TYPE)
+            .set_masked((slot as u64) << 16, RawL3Entry::ADDR);
+        self.set_entry(va, entry);
+        // No thread may still reach the page once it is freed.
+        super::shootdown();
         unsafe {
-            core::slice::from_raw_parts_mut(ptr, PAGE_SIZE)
+            dealloc(phys.as_mut_ptr(), Page::layout());
         }
     }
+
+    /// Returns the swap slot holding the page at `va` if it was swapped out.
+    pub fn swap_slot(&self, va: VirtualAddr) -> Option<usize> {
+        swap_slot(self.0.entry(va))
+    }
 }
 
 impl fmt::Debug for UserPageTable {
@@ -326,8 +560,10 @@ impl Drop for UserPageTable {
         for page_addr in self.into_iter() {
             if let Some(mut phys) = page_addr.get_page_addr() {
                 unsafe {
-                    ALLOCATOR.dealloc(phys.as_mut_ptr(), Page::layout())
+                    dealloc(phys.as_mut_ptr(), Page::layout())
                 };
+            } else if let Some(slot) = swap_slot(&page_addr.0) {
+                crate::swap::release(slot);
             }
         }
     }
@@ -0,0 +1,100 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use kernel_api::OsError;
+
+use crate::param::{PAGE_SIZE, USER_IMG_BASE};
+use crate::vm::{Granule, PagePerm, TranslationConfig, UserPageTable, VirtualAddr};
+
+fn page(n: usize) -> VirtualAddr {
+    VirtualAddr::from(USER_IMG_BASE + n * PAGE_SIZE)
+}
+
+#[test]
+fn alloc_maps_page() {
+    let mut table = UserPageTable::new();
+    assert!(table.is_invalid(page(3)));
+
+    let bytes = table.alloc(page(3), PagePerm::RW).unwrap();
+    assert_eq!(bytes.len(), PAGE_SIZE);
+    bytes[0] = 0xAB;
+    let phys = bytes.as_ptr() as usize;
+
+    assert!(table.is_valid(page(3)));
+    assert!(table.is_invalid(page(2)));
+    assert!(table.is_invalid(page(4)));
+    let regions = table.regions(page(0));
+    assert_eq!(regions.len(), 1);
+    assert_eq!(regions[0].start, page(3));
+    assert_eq!(regions[0].end, page(4));
+    assert_eq!(regions[0].phys.as_usize(), phys);
+}
+
+#[test]
+fn regions_are_split_by_gaps() {
+    let mut table = UserPageTable::new();
+    table.alloc(page(0), PagePerm::RW).unwrap();
+    table.alloc(page(2), PagePerm::RWX).unwrap();
+    // Pages in the second L3 table.
+    table.alloc(page(8192), PagePerm::RW).unwrap();
+
+    let starts: Vec<VirtualAddr> = table.regions(page(0)).iter().map(|r| r.start).collect();
+    assert_eq!(starts, vec![page(0), page(2), page(8192)]);
+    assert!(table.is_valid(page(8192)));
+    assert!(table.is_invalid(page(8193)));
+}
+
+#[test]
+fn alloc_twice_fails() {
+    let mut table = UserPageTable::new();
+    let phys = table.alloc(page(1), PagePerm::RW).unwrap().as_ptr() as usize;
+    assert_eq!(table.alloc(page(1), PagePerm::RW).err(), Some(OsError::FileExists));
+    // The first page stays mapped.
+    assert_eq!(table.regions(page(0))[0].phys.as_usize(), phys);
+}
+
+#[test]
+fn alloc_below_user_space_fails() {
+    let mut table = UserPageTable::new();
+    let va = VirtualAddr::from(0x8_0000usize);
+    assert_eq!(table.alloc(va, PagePerm::RW).err(), Some(OsError::BadAddress));
+}
+
+#[test]
+fn alloc_without_memory_fails() {
+    let mut table = UserPageTable::new();
+    let result = table.alloc_with(page(5), PagePerm::RW, core::ptr::null_mut);
+    assert_eq!(result.err(), Some(OsError::NoMemory));
+    assert!(table.is_invalid(page(5)));
+    assert!(table.regions(page(0)).is_empty());
+}
+
+#[test]
+fn default_translation_config_encoding() {
+    let config = TranslationConfig::new();
+    assert_eq!(config.mair_value(), 0x44_04_FF);
+    let tcr = (0b010 << 32) | (0b11 << 30) | (0b11 << 28) | (0b01 << 26) | (0b01 << 24)
+        | (34 << 16) | (0b01 << 14) | (0b11 << 12) | (0b01 << 10) | (0b01 << 8) | 32;
+    assert_eq!(config.tcr_value(0b010), tcr);
+    assert_eq!(TranslationConfig::from_registers(config.mair_value(), tcr), Some(config));
+    assert!(config.validate().is_ok());
+}
+
+#[test]
+fn translation_config_validation() {
+    let mut config = TranslationConfig::new();
+    config.granule = Granule::K4;
+    assert!(config.validate().is_err());
+
+    let mut config = TranslationConfig::new();
+    config.t0sz = 21;
+    assert!(config.validate().is_err());
+    config.t0sz = 33;
+    assert!(config.validate().is_ok());
+    config.t0sz = 34;
+    assert!(config.validate().is_err());
+
+    let mut config = TranslationConfig::new();
+    config.t1sz = 33;
+    assert!(config.validate().is_err());
+}
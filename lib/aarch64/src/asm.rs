@@ -29,6 +29,43 @@ pub fn isb() {
     unsafe { llvm_asm!("isb" :::: "volatile") };
 }
 
+/// Data Memory Barrier, full system: orders memory accesses before it
+/// against those after it.
+#[inline(always)]
+pub fn dmb() {
+    unsafe { llvm_asm!("dmb sy" ::: "memory" : "volatile") };
+}
+
+/// Data Synchronization Barrier, full system: completes all memory accesses
+/// and cache maintenance before it before any instruction after it runs.
+#[inline(always)]
+pub fn dsb() {
+    unsafe { llvm_asm!("dsb sy" ::: "memory" : "volatile") };
+}
+
+/// Data Synchronization Barrier, inner shareable domain.
+#[inline(always)]
+pub fn dsb_ish() {
+    unsafe { llvm_asm!("dsb ish" ::: "memory" : "volatile") };
+}
+
+/// Data Synchronization Barrier for stores, inner shareable domain. Makes
+/// page table writes visible to the table walker; follow it with `isb()`.
+#[inline(always)]
+pub fn dsb_ishst() {
+    unsafe { llvm_asm!("dsb ishst" ::: "memory" : "volatile") };
+}
+
+/// Invalidates all EL1&0 TLB entries of this core, after completing prior
+/// page table writes.
+#[inline(always)]
+pub unsafe fn flush_tlb() {
+    dsb_ishst();
+    llvm_asm!("tlbi vmalle1" :::: "volatile");
+    dsb_ish();
+    isb();
+}
+
 /// Set Event
 #[inline(always)]
 pub fn sev() {
@@ -70,3 +107,71 @@ macro_rules! svc {
         unsafe { llvm_asm!(concat!("svc ", stringify!($num)) :::: "volatile"); }
     }
 }
+
+/// The size of data and instruction cache lines on the Cortex-A53.
+pub const CACHE_LINE_SIZE: usize = 64;
+
+/// Runs `op` on the address of each cache line covering `len` bytes at
+/// `addr`.
+#[inline(always)]
+fn for_each_line(addr: usize, len: usize, mut op: impl FnMut(usize)) {
+    let mut line = addr & !(CACHE_LINE_SIZE - 1);
+    while line < addr + len {
+        op(line);
+        line += CACHE_LINE_SIZE;
+    }
+}
+
+/// Cleans the data cache lines covering `len` bytes at `addr` to the point of
+/// coherency, so that other bus masters (e.g. DMA) observe prior CPU writes.
+#[inline(always)]
+pub unsafe fn clean_dcache_range(addr: usize, len: usize) {
+    for_each_line(addr, len, |line| llvm_asm!("dc cvac, $0" :: "r"(line) :: "volatile"));
+    dsb();
+}
+
+/// Cleans and invalidates the data cache lines covering `len` bytes at
+/// `addr`, so that later CPU reads observe writes by other bus masters (e.g.
+/// DMA). Call it both before starting the transfer, so that no dirty line is
+/// evicted over the new data, and after it completes.
+#[inline(always)]
+pub unsafe fn clean_invalidate_dcache_range(addr: usize, len: usize) {
+    for_each_line(addr, len, |line| llvm_asm!("dc civac, $0" :: "r"(line) :: "volatile"));
+    dsb();
+}
+
+/// Makes instructions written as data to `len` bytes at `addr` visible to
+/// instruction fetches: cleans the data cache lines to the point of
+/// unification and invalidates the instruction cache lines covering them.
+#[inline(always)]
+pub unsafe fn sync_icache_range(addr: usize, len: usize) {
+    for_each_line(addr, len, |line| llvm_asm!("dc cvau, $0" :: "r"(line) :: "volatile"));
+    dsb_ish();
+    for_each_line(addr, len, |line| llvm_asm!("ic ivau, $0" :: "r"(line) :: "volatile"));
+    dsb_ish();
+    isb();
+}
+
+/// Returns the current frame pointer (`x29`).
+#[inline(always)]
+pub fn frame_pointer() -> u64 {
+    let fp: u64;
+    unsafe { llvm_asm!("mov $0, x29" : "=r"(fp) ::: "volatile") };
+    fp
+}
+
+/// Performs the semihosting operation `op` with the parameter block at
+/// `param` and returns its result.
+///
+/// Requires a debugger or an emulator with semihosting enabled, e.g. QEMU
+/// started with `-semihosting`. Otherwise the `hlt` instruction faults.
+#[inline(always)]
+pub unsafe fn semihosting_call(op: u64, param: u64) -> u64 {
+    let result: u64;
+    llvm_asm!("hlt #0xf000"
+         : "={x0}"(result)
+         : "{x0}"(op), "{x1}"(param)
+         : "memory"
+         : "volatile");
+    result
+}
@@ -0,0 +1,293 @@
+//! Atomic read-modify-write operations and memory barriers.
+//!
+//! The read-modify-write operations act on `AtomicUsize` and `AtomicU64`
+//! and are both acquire and release: no memory access of the calling core
+//! moves across them in either direction. They use the ARMv8.1 LSE
+//! instructions (`LDADDAL`, `CASAL`, ...) if `has_lse()`, and a
+//! `LDAXR`/`STLXR` loop otherwise, as on the Pi 3's Cortex-A53.
+//!
+//! Exclusive and LSE accesses need Normal cacheable memory. Until the MMU and
+//! data cache are on, all memory is Device memory and a store-exclusive
+//! never succeeds on the Pi, so the operations fall back to a plain load and
+//! store. Only the boot core runs then, with IRQs masked, so nothing can
+//! interleave with them.
+
+use core::sync::atomic::{self, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+use crate::regs::*;
+
+/// An atomic integer of 64 bits that this module operates on.
+///
+/// # Safety
+///
+/// Implementors must have the size and alignment of a `u64`.
+pub unsafe trait Word {
+    type Value: Copy;
+
+    fn to_bits(value: Self::Value) -> u64;
+
+    fn from_bits(bits: u64) -> Self::Value;
+}
+
+unsafe impl Word for AtomicU64 {
+    type Value = u64;
+
+    fn to_bits(value: u64) -> u64 {
+        value
+    }
+
+    fn from_bits(bits: u64) -> u64 {
+        bits
+    }
+}
+
+unsafe impl Word for AtomicUsize {
+    type Value = usize;
+
+    fn to_bits(value: usize) -> u64 {
+        value as u64
+    }
+
+    fn from_bits(bits: u64) -> usize {
+        bits as usize
+    }
+}
+
+/// Returns a pointer to the integer in `word`.
+fn bits<W: Word>(word: &W) -> *mut u64 {
+    word as *const W as *mut u64
+}
+
+/// Whether the LSE atomics are implemented: 0 if not checked yet, 1 if not,
+/// 2 if they are.
+static LSE: AtomicU8 = AtomicU8::new(0);
+
+/// Returns `true` if the core implements the ARMv8.1 LSE atomic
+/// instructions. Checked once, at EL1 or above.
+pub fn has_lse() -> bool {
+    match LSE.load(Ordering::Relaxed) {
+        0 => {
+            let field = unsafe { ID_AA64ISAR0_EL1.get_value(ID_AA64ISAR0_EL1::Atomic) };
+            let present = field >= 0b0010;
+            LSE.store(if present { 2 } else { 1 }, Ordering::Relaxed);
+            present
+        }
+        state => state == 2,
+    }
+}
+
+/// Returns `true` once the MMU and data cache are on, so that exclusive and
+/// LSE accesses work.
+#[inline(always)]
+fn exclusives_work() -> bool {
+    let on = SCTLR_EL1::M | SCTLR_EL1::C;
+    unsafe { SCTLR_EL1.get_masked(on) == on }
+}
+
+/// A read-modify-write operation.
+#[derive(Copy, Clone)]
+enum Op {
+    Add,
+    Or,
+    And,
+    Swap,
+}
+
+impl Op {
+    fn apply(self, old: u64, value: u64) -> u64 {
+        match self {
+            Op::Add => old.wrapping_add(value),
+            Op::Or => old | value,
+            Op::And => old & value,
+            Op::Swap => value,
+        }
+    }
+}
+
+/// Atomically replaces the integer at `ptr` with `op` applied to it and
+/// `value`, and returns what it was.
+#[inline(always)]
+unsafe fn rmw(ptr: *mut u64, op: Op, value: u64) -> u64 {
+    if !exclusives_work() {
+        let old = ptr.read_volatile();
+        ptr.write_volatile(op.apply(old, value));
+        return old;
+    }
+
+    let old: u64;
+    if has_lse() {
+        match op {
+            Op::Add => llvm_asm!(".arch_extension lse
+                                  ldaddal $1, $0, [$2]"
+                                 : "=r"(old) : "r"(value), "r"(ptr) : "memory" : "volatile"),
+            Op::Or => llvm_asm!(".arch_extension lse
+                                 ldsetal $1, $0, [$2]"
+                                : "=r"(old) : "r"(value), "r"(ptr) : "memory" : "volatile"),
+            // `LDCLRAL` clears the bits set in its operand.
+            Op::And => llvm_asm!(".arch_extension lse
+                                  ldclral $1, $0, [$2]"
+                                 : "=r"(old) : "r"(!value), "r"(ptr) : "memory" : "volatile"),
+            Op::Swap => llvm_asm!(".arch_extension lse
+                                   swpal $1, $0, [$2]"
+                                  : "=r"(old) : "r"(value), "r"(ptr) : "memory" : "volatile"),
+        }
+        return old;
+    }
+
+    let (_new, _status): (u64, u32);
+    match op {
+        Op::Add => llvm_asm!("1: ldaxr $0, [$3]
+                                 add $1, $0, $4
+                                 stlxr ${2:w}, $1, [$3]
+                                 cbnz ${2:w}, 1b"
+                             : "=&r"(old), "=&r"(_new), "=&r"(_status)
+                             : "r"(ptr), "r"(value)
+                             : "memory"
+                             : "volatile"),
+        Op::Or => llvm_asm!("1: ldaxr $0, [$3]
+                                orr $1, $0, $4
+                                stlxr ${2:w}, $1, [$3]
+                                cbnz ${2:w}, 1b"
+                            : "=&r"(old), "=&r"(_new), "=&r"(_status)
+                            : "r"(ptr), "r"(value)
+                            : "memory"
+                            : "volatile"),
+        Op::And => llvm_asm!("1: ldaxr $0, [$3]
+                                 and $1, $0, $4
+                                 stlxr ${2:w}, $1, [$3]
+                                 cbnz ${2:w}, 1b"
+                             : "=&r"(old), "=&r"(_new), "=&r"(_status)
+                             : "r"(ptr), "r"(value)
+                             : "memory"
+                             : "volatile"),
+        Op::Swap => llvm_asm!("1: ldaxr $0, [$2]
+                                  stlxr ${1:w}, $3, [$2]
+                                  cbnz ${1:w}, 1b"
+                              : "=&r"(old), "=&r"(_status)
+                              : "r"(ptr), "r"(value)
+                              : "memory"
+                              : "volatile"),
+    }
+    old
+}
+
+/// Adds `value` to `word`, wrapping around on overflow, and returns the
+/// previous value.
+#[inline(always)]
+pub fn fetch_add<W: Word>(word: &W, value: W::Value) -> W::Value {
+    W::from_bits(unsafe { rmw(bits(word), Op::Add, W::to_bits(value)) })
+}
+
+/// Sets the bits of `word` that are set in `value` and returns the previous
+/// value.
+#[inline(always)]
+pub fn fetch_or<W: Word>(word: &W, value: W::Value) -> W::Value {
+    W::from_bits(unsafe { rmw(bits(word), Op::Or, W::to_bits(value)) })
+}
+
+/// Clears the bits of `word` that are clear in `value` and returns the
+/// previous value.
+#[inline(always)]
+pub fn fetch_and<W: Word>(word: &W, value: W::Value) -> W::Value {
+    W::from_bits(unsafe { rmw(bits(word), Op::And, W::to_bits(value)) })
+}
+
+/// Stores `value` in `word` and returns the previous value.
+#[inline(always)]
+pub fn swap<W: Word>(word: &W, value: W::Value) -> W::Value {
+    W::from_bits(unsafe { rmw(bits(word), Op::Swap, W::to_bits(value)) })
+}
+
+/// Stores `new` in `word` if it holds `current`. Returns the previous value,
+/// as `Ok` if it was `current` and `new` was stored, and as `Err` otherwise.
+/// A failed exchange is only an acquire.
+#[inline(always)]
+pub fn compare_exchange<W: Word>(word: &W, current: W::Value, new: W::Value)
+    -> Result<W::Value, W::Value>
+{
+    let (ptr, current, new) = (bits(word), W::to_bits(current), W::to_bits(new));
+    let old: u64;
+    unsafe {
+        if !exclusives_work() {
+            old = ptr.read_volatile();
+            if old == current {
+                ptr.write_volatile(new);
+            }
+        } else if has_lse() {
+            llvm_asm!(".arch_extension lse
+                       casal $0, $2, [$1]"
+                      : "=r"(old) : "r"(ptr), "r"(new), "0"(current) : "memory" : "volatile");
+        } else {
+            let _status: u32;
+            llvm_asm!("1: ldaxr $0, [$2]
+                          cmp $0, $3
+                          b.ne 2f
+                          stlxr ${1:w}, $4, [$2]
+                          cbnz ${1:w}, 1b
+                          b 3f
+                       2: clrex
+                       3:"
+                      : "=&r"(old), "=&r"(_status)
+                      : "r"(ptr), "r"(current), "r"(new)
+                      : "cc", "memory"
+                      : "volatile");
+        }
+    }
+    if old == current {
+        Ok(W::from_bits(old))
+    } else {
+        Err(W::from_bits(old))
+    }
+}
+
+/// Loads `word` with `LDAR`: no later memory access of the calling core
+/// moves before it.
+#[inline(always)]
+pub fn load_acquire<W: Word>(word: &W) -> W::Value {
+    let value: u64;
+    unsafe {
+        llvm_asm!("ldar $0, [$1]" : "=r"(value) : "r"(bits(word)) : "memory" : "volatile");
+    }
+    W::from_bits(value)
+}
+
+/// Stores `value` in `word` with `STLR`: no earlier memory access of the
+/// calling core moves after it.
+#[inline(always)]
+pub fn store_release<W: Word>(word: &W, value: W::Value) {
+    unsafe {
+        llvm_asm!("stlr $0, [$1]" :: "r"(W::to_bits(value)), "r"(bits(word)) : "memory"
+                  : "volatile");
+    }
+}
+
+/// Keeps the compiler from moving memory accesses across this point. Emits
+/// no instruction, so it only orders accesses as seen by the calling core,
+/// for example against its own interrupt handlers.
+#[inline(always)]
+pub fn compiler_fence() {
+    atomic::compiler_fence(Ordering::SeqCst);
+}
+
+/// Orders the loads before it against the loads and stores after it, as
+/// observed by the other cores (`DMB ISHLD`).
+#[inline(always)]
+pub fn acquire_fence() {
+    unsafe { llvm_asm!("dmb ishld" ::: "memory" : "volatile") };
+}
+
+/// Orders the loads and stores before it against the stores after it, as
+/// observed by the other cores (`DMB ISH`; AArch64 has no barrier that
+/// orders exactly these).
+#[inline(always)]
+pub fn release_fence() {
+    unsafe { llvm_asm!("dmb ish" ::: "memory" : "volatile") };
+}
+
+/// Orders all loads and stores before it against all those after it, as
+/// observed by the other cores (`DMB ISH`). Unlike `dmb()`, does not order
+/// accesses to devices outside the inner shareable domain.
+#[inline(always)]
+pub fn full_fence() {
+    unsafe { llvm_asm!("dmb ish" ::: "memory" : "volatile") };
+}
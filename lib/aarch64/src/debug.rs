@@ -0,0 +1,114 @@
+// (ref. D2: AArch64 Self-hosted Debug, D13.3: Debug registers)
+
+// (ref. D13.3.20: Monitor Debug System Control Register)
+defreg!(MDSCR_EL1, [
+    MDE  [15-15], // Monitor debug events: breakpoints and watchpoints
+    KDE  [13-13], // Local (kernel) debug enable
+    SS   [00-00], // Software step control
+]);
+
+// (ref. D13.3.26: OS Lock Access Register)
+defreg!(OSLAR_EL1, [
+    OSLK [00-00], // OS Lock; set at cold reset, debug exceptions need it clear
+]);
+
+// (ref. D13.2.51: AArch64 Debug Feature Register 0)
+defreg!(ID_AA64DFR0_EL1, [
+    CTX_CMPs [31-28], // Context-aware breakpoints, minus 1
+    WRPs     [23-20], // Watchpoints, minus 1
+    BRPs     [15-12], // Breakpoints, minus 1
+]);
+
+// (ref. D13.3.3: Debug Breakpoint Value Registers)
+defreg!(DBGBVR0_EL1);
+defreg!(DBGBVR1_EL1);
+defreg!(DBGBVR2_EL1);
+defreg!(DBGBVR3_EL1);
+defreg!(DBGBVR4_EL1);
+defreg!(DBGBVR5_EL1);
+
+// (ref. D13.3.2: Debug Breakpoint Control Registers)
+defreg!(DBGBCR0_EL1, [
+    BT   [23-20], // Breakpoint type; 0 for an unlinked address match
+    BAS  [08-05], // Byte address select; all set for A64 instructions
+    PMC  [02-01], // Privilege mode control; 0b10 for EL0
+    E    [00-00], // Enable
+]);
+defreg!(DBGBCR1_EL1);
+defreg!(DBGBCR2_EL1);
+defreg!(DBGBCR3_EL1);
+defreg!(DBGBCR4_EL1);
+defreg!(DBGBCR5_EL1);
+
+// (ref. D13.3.12: Debug Watchpoint Value Registers)
+defreg!(DBGWVR0_EL1);
+defreg!(DBGWVR1_EL1);
+defreg!(DBGWVR2_EL1);
+defreg!(DBGWVR3_EL1);
+
+// (ref. D13.3.11: Debug Watchpoint Control Registers)
+defreg!(DBGWCR0_EL1, [
+    MASK [28-24], // Address mask; 0 to watch the bytes BAS selects
+    BAS  [12-05], // Byte address select within the doubleword
+    LSC  [04-03], // Load/store control: 0b01 loads, 0b10 stores
+    PAC  [02-01], // Privilege of access control; 0b10 for EL0
+    E    [00-00], // Enable
+]);
+defreg!(DBGWCR1_EL1);
+defreg!(DBGWCR2_EL1);
+defreg!(DBGWCR3_EL1);
+
+/// The most breakpoint and watchpoint register pairs used: the number the
+/// Cortex-A53 implements. The architecture allows up to 16 of each.
+pub const MAX_BREAKPOINTS: usize = 6;
+pub const MAX_WATCHPOINTS: usize = 4;
+
+/// The value of a `DBGBCR<n>_EL1` enabling an unlinked breakpoint on an A64
+/// instruction executed at EL0.
+pub const BREAKPOINT_EL0: u64 = DBGBCR0_EL1::BAS | (0b10 << 1) | DBGBCR0_EL1::E;
+
+/// Returns the number of usable breakpoint register pairs.
+pub fn breakpoints() -> usize {
+    let brps = unsafe { ID_AA64DFR0_EL1.get_value(ID_AA64DFR0_EL1::BRPs) } as usize + 1;
+    brps.min(MAX_BREAKPOINTS)
+}
+
+/// Returns the number of usable watchpoint register pairs.
+pub fn watchpoints() -> usize {
+    let wrps = unsafe { ID_AA64DFR0_EL1.get_value(ID_AA64DFR0_EL1::WRPs) } as usize + 1;
+    wrps.min(MAX_WATCHPOINTS)
+}
+
+/// Sets breakpoint register pair `n` to `value` and `control`.
+///
+/// # Safety
+///
+/// `n` must be less than `breakpoints()`. An enabled breakpoint raises debug
+/// exceptions when `MDSCR_EL1.MDE` is set.
+pub unsafe fn set_breakpoint(n: usize, value: u64, control: u64) {
+    match n {
+        0 => { DBGBVR0_EL1.set(value); DBGBCR0_EL1.set(control) }
+        1 => { DBGBVR1_EL1.set(value); DBGBCR1_EL1.set(control) }
+        2 => { DBGBVR2_EL1.set(value); DBGBCR2_EL1.set(control) }
+        3 => { DBGBVR3_EL1.set(value); DBGBCR3_EL1.set(control) }
+        4 => { DBGBVR4_EL1.set(value); DBGBCR4_EL1.set(control) }
+        5 => { DBGBVR5_EL1.set(value); DBGBCR5_EL1.set(control) }
+        _ => (),
+    }
+}
+
+/// Sets watchpoint register pair `n` to `value` and `control`.
+///
+/// # Safety
+///
+/// `n` must be less than `watchpoints()`. An enabled watchpoint raises debug
+/// exceptions when `MDSCR_EL1.MDE` is set.
+pub unsafe fn set_watchpoint(n: usize, value: u64, control: u64) {
+    match n {
+        0 => { DBGWVR0_EL1.set(value); DBGWCR0_EL1.set(control) }
+        1 => { DBGWVR1_EL1.set(value); DBGWCR1_EL1.set(control) }
+        2 => { DBGWVR2_EL1.set(value); DBGWCR2_EL1.set(control) }
+        3 => { DBGWVR3_EL1.set(value); DBGWCR3_EL1.set(control) }
+        _ => (),
+    }
+}
@@ -9,6 +9,8 @@ pub mod macros;
 
 pub mod sp;
 pub mod asm;
+pub mod atomic;
+pub mod debug;
 pub mod regs;
 pub mod vmsa;
 
@@ -187,6 +187,11 @@ defreg!(SCTLR_EL1, [
     RES1 [29-28|23-22|20-20|11-11],
 ]);
 
+// (ref. D13.2.62 AArch64 Instruction Set Attribute Register 0)
+defreg!(ID_AA64ISAR0_EL1, [
+    Atomic [23-20], // 0b0010 if the LSE atomic instructions are implemented
+]);
+
 defreg!(SP_EL0);
 defreg!(SP_EL1);
 defreg!(SP_EL2);
@@ -219,9 +224,31 @@ defreg!(VBAR_EL1, [
     RES0   [10-0],
 ]);
 
+// With HCR_EL2.E2H clear, as the kernel runs, bits 1 and 0 let EL1 and EL0
+// use the physical timer and read the physical counter (ref: D13.8.2).
 defreg!(CNTHCTL_EL2, [
-    EL0VCTEN [1-1],
-    EL0PCTEN [0-0],
+    EL1PCEN  [1-1],
+    EL1PCTEN [0-0],
 ]);
 
 defreg!(CNTVOFF_EL2);
+
+// (ref. D13.8 Generic Timer registers)
+defreg!(CNTFRQ_EL0); // Counter frequency in Hz
+defreg!(CNTVCT_EL0); // Virtual count
+defreg!(CNTPCT_EL0); // Physical count
+defreg!(CNTP_TVAL_EL0); // Physical timer value, relative to the count
+
+defreg!(CNTP_CTL_EL0, [
+    ISTATUS [02-02], // The timer condition is met
+    IMASK   [01-01], // Masks the timer interrupt
+    ENABLE  [00-00], // Enables the timer
+]);
+
+defreg!(CNTKCTL_EL1, [
+    EVNTI    [07-04], // Counter bit whose transition generates an event
+    EVNTDIR  [03-03], // Event on a 1-to-0 (1) or 0-to-1 (0) transition
+    EVNTEN   [02-02], // Enables the event stream
+    EL0VCTEN [01-01], // EL0 access to the virtual counter
+    EL0PCTEN [00-00], // EL0 access to the physical counter
+]);
@@ -82,7 +82,65 @@ defreg!(MAIR_EL1, [
 ]);
 
 // (ref. D7.2.91: Translation Control Register)
-defreg!(TCR_EL1);
+defreg!(TCR_EL1, [
+    TBI0  [37-37], // Top byte ignored for TTBR0 addresses
+    IPS   [34-32], // Intermediate physical address size
+    TG1   [31-30], // Granule size for TTBR1
+    SH1   [29-28], // Shareability of TTBR1 table walks
+    ORGN1 [27-26], // Outer cacheability of TTBR1 table walks
+    IRGN1 [25-24], // Inner cacheability of TTBR1 table walks
+    EPD1  [23-23], // Disables TTBR1 table walks
+    T1SZ  [21-16], // Size offset of the TTBR1 region
+    TG0   [15-14], // Granule size for TTBR0
+    SH0   [13-12], // Shareability of TTBR0 table walks
+    ORGN0 [11-10], // Outer cacheability of TTBR0 table walks
+    IRGN0 [09-08], // Inner cacheability of TTBR0 table walks
+    EPD0  [07-07], // Disables TTBR0 table walks
+    T0SZ  [05-00], // Size offset of the TTBR0 region
+]);
+
+/// Encodings of `MAIR_EL1` attribute fields.
+#[allow(non_snake_case)]
+#[allow(non_upper_case_globals)]
+pub mod MemAttr {
+    /// Device memory, non-gathering, non-reordering, without and with early
+    /// write acknowledgement.
+    pub const DEVICE_nGnRnE: u8 = 0x00;
+    pub const DEVICE_nGnRE: u8 = 0x04;
+    /// Normal memory, inner and outer non-cacheable.
+    pub const NORMAL_NC: u8 = 0x44;
+    /// Normal memory, inner and outer write-through, read/write-allocate.
+    pub const NORMAL_WT: u8 = 0xBB;
+    /// Normal memory, inner and outer write-back, read/write-allocate.
+    pub const NORMAL_WB: u8 = 0xFF;
+}
+
+/// Encodings of `TCR_EL1.TG0`; `TG1` encodes the same sizes differently.
+#[allow(non_snake_case)]
+#[allow(non_upper_case_globals)]
+pub mod Tg0 {
+    pub const K4: u64 = 0b00;
+    pub const K64: u64 = 0b01;
+    pub const K16: u64 = 0b10;
+}
+
+#[allow(non_snake_case)]
+#[allow(non_upper_case_globals)]
+pub mod Tg1 {
+    pub const K16: u64 = 0b01;
+    pub const K4: u64 = 0b10;
+    pub const K64: u64 = 0b11;
+}
+
+/// Encodings of the `TCR_EL1` table walk cacheability fields.
+#[allow(non_snake_case)]
+#[allow(non_upper_case_globals)]
+pub mod WalkCache {
+    pub const NonCacheable: u64 = 0b00;
+    pub const WriteBackAlloc: u64 = 0b01;
+    pub const WriteThrough: u64 = 0b10;
+    pub const WriteBack: u64 = 0b11;
+}
 
 // (ref. D7.2.99: Translation Table Base Register 0)
 defreg!(TTBR0_EL1, [
@@ -0,0 +1,88 @@
+//! CRC-32 as used by gzip, zip and Ethernet, and CRC-16/CCITT-FALSE as used
+//! by the serial multiplexer.
+
+use crate::tables;
+
+/// An incremental CRC-32 (IEEE 802.3).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    pub const fn new() -> Crc32 {
+        Crc32 { state: !0 }
+    }
+
+    /// Adds `bytes` to the checksummed data.
+    pub fn update(&mut self, bytes: &[u8]) {
+        let mut crc = self.state;
+        for &byte in bytes {
+            crc = tables::CRC32[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+        }
+        self.state = crc;
+    }
+
+    /// Returns the CRC of the data added so far.
+    pub fn finish(&self) -> u32 {
+        !self.state
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Crc32 {
+        Crc32::new()
+    }
+}
+
+/// Returns the CRC-32 (IEEE 802.3) of `bytes`.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(bytes);
+    crc.finish()
+}
+
+/// An incremental CRC-16/CCITT-FALSE: polynomial `0x1021`, starting from
+/// `0xFFFF`, without reflection or final XOR.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Crc16 {
+    state: u16,
+}
+
+impl Crc16 {
+    pub const fn new() -> Crc16 {
+        Crc16 { state: 0xFFFF }
+    }
+
+    /// Returns a CRC continuing from `crc`, the result of earlier data.
+    pub const fn resume(crc: u16) -> Crc16 {
+        Crc16 { state: crc }
+    }
+
+    /// Adds `bytes` to the checksummed data.
+    pub fn update(&mut self, bytes: &[u8]) {
+        let mut crc = self.state;
+        for &byte in bytes {
+            crc = tables::CRC16[((crc >> 8) as u8 ^ byte) as usize] ^ (crc << 8);
+        }
+        self.state = crc;
+    }
+
+    /// Returns the CRC of the data added so far.
+    pub fn finish(&self) -> u16 {
+        self.state
+    }
+}
+
+impl Default for Crc16 {
+    fn default() -> Crc16 {
+        Crc16::new()
+    }
+}
+
+/// Returns the CRC-16/CCITT-FALSE of `bytes`.
+pub fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc = Crc16::new();
+    crc.update(bytes);
+    crc.finish()
+}
@@ -0,0 +1,46 @@
+//! The 64-bit FNV-1a hash, for hash maps keyed by short values.
+
+use core::hash::{BuildHasherDefault, Hasher};
+
+const OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+const PRIME: u64 = 0x0000_0100_0000_01B3;
+
+/// An incremental FNV-1a hash.
+#[derive(Copy, Clone, Debug)]
+pub struct Fnv1a {
+    state: u64,
+}
+
+impl Fnv1a {
+    pub const fn new() -> Fnv1a {
+        Fnv1a { state: OFFSET_BASIS }
+    }
+}
+
+impl Default for Fnv1a {
+    fn default() -> Fnv1a {
+        Fnv1a::new()
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.state = (self.state ^ byte as u64).wrapping_mul(PRIME);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.state
+    }
+}
+
+/// Builds `Fnv1a` hashers, e.g. for `HashMap<K, V, BuildFnv>`.
+pub type BuildFnv = BuildHasherDefault<Fnv1a>;
+
+/// Returns the FNV-1a hash of `bytes`.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hasher = Fnv1a::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
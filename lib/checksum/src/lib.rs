@@ -0,0 +1,22 @@
+#![no_std]
+
+//! Checksums and non-cryptographic hashes shared by the bootloader, the
+//! kernel and its libraries.
+//!
+//! The CRCs are table-driven and incremental: feed a `Crc32` or `Crc16`
+//! data in as many pieces as it arrives, then read the result, or use
+//! `crc32()` and `crc16()` for data at hand. `Fnv1a` is a `Hasher` for hash
+//! maps keyed by short values, and `xxh32()` hashes larger buffers quickly.
+
+#[cfg(test)]
+mod tests;
+
+mod tables;
+
+pub mod crc;
+pub mod fnv;
+pub mod xxhash;
+
+pub use crc::{crc16, crc32, Crc16, Crc32};
+pub use fnv::{fnv1a, BuildFnv, Fnv1a};
+pub use xxhash::xxh32;
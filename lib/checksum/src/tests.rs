@@ -0,0 +1,62 @@
+extern crate std;
+
+use core::hash::Hasher;
+
+use crate::{crc16, crc32, fnv1a, tables, xxh32, Crc16, Crc32, Fnv1a};
+
+const CHECK: &[u8] = b"123456789";
+
+#[test]
+fn tables_match_bitwise_crcs() {
+    for n in 0..256u32 {
+        let mut crc32 = n;
+        let mut crc16 = (n as u16) << 8;
+        for _ in 0..8 {
+            crc32 = if crc32 & 1 != 0 { (crc32 >> 1) ^ 0xEDB8_8320 } else { crc32 >> 1 };
+            crc16 = if crc16 & 0x8000 != 0 { (crc16 << 1) ^ 0x1021 } else { crc16 << 1 };
+        }
+        assert_eq!(tables::CRC32[n as usize], crc32);
+        assert_eq!(tables::CRC16[n as usize], crc16);
+    }
+}
+
+#[test]
+fn crc_check_values() {
+    assert_eq!(crc32(CHECK), 0xCBF4_3926);
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc16(CHECK), 0x29B1);
+    assert_eq!(crc16(b""), 0xFFFF);
+}
+
+#[test]
+fn crcs_are_incremental() {
+    let mut crc = Crc32::new();
+    crc.update(b"1234");
+    crc.update(b"");
+    crc.update(b"56789");
+    assert_eq!(crc.finish(), 0xCBF4_3926);
+
+    let mut crc = Crc16::new();
+    crc.update(b"12");
+    let mut crc = Crc16::resume(crc.finish());
+    crc.update(b"3456789");
+    assert_eq!(crc.finish(), 0x29B1);
+}
+
+#[test]
+fn fnv1a_reference_values() {
+    assert_eq!(fnv1a(b""), 0xCBF2_9CE4_8422_2325);
+    assert_eq!(fnv1a(b"a"), 0xAF63_DC4C_8601_EC8C);
+    assert_eq!(fnv1a(b"foobar"), 0x8594_4171_F739_67E8);
+    let mut hasher = Fnv1a::new();
+    hasher.write(b"foo");
+    hasher.write(b"bar");
+    assert_eq!(hasher.finish(), fnv1a(b"foobar"));
+}
+
+#[test]
+fn xxh32_reference_values() {
+    assert_eq!(xxh32(0, b""), 0x02CC_5D05);
+    assert_eq!(xxh32(0, b"abc"), 0x32D1_53FF);
+    assert_eq!(xxh32(0, b"Nobody inspects the spammish repetition"), 0xE229_3B2F);
+}
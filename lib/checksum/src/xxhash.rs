@@ -0,0 +1,59 @@
+//! The 32-bit xxHash, XXH32, which hashes large buffers several times
+//! faster than a CRC. Its results match the reference implementation.
+
+const PRIME1: u32 = 0x9E37_79B1;
+const PRIME2: u32 = 0x85EB_CA77;
+const PRIME3: u32 = 0xC2B2_AE3D;
+const PRIME4: u32 = 0x27D4_EB2F;
+const PRIME5: u32 = 0x1656_67B1;
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn round(acc: u32, lane: u32) -> u32 {
+    acc.wrapping_add(lane.wrapping_mul(PRIME2)).rotate_left(13).wrapping_mul(PRIME1)
+}
+
+/// Returns the XXH32 hash of `bytes` with `seed`.
+pub fn xxh32(seed: u32, bytes: &[u8]) -> u32 {
+    let mut stripes = bytes.chunks_exact(16);
+    let mut hash = if bytes.len() >= 16 {
+        let mut acc = [
+            seed.wrapping_add(PRIME1).wrapping_add(PRIME2),
+            seed.wrapping_add(PRIME2),
+            seed,
+            seed.wrapping_sub(PRIME1),
+        ];
+        for stripe in &mut stripes {
+            for (i, acc) in acc.iter_mut().enumerate() {
+                *acc = round(*acc, read_u32(&stripe[i * 4..]));
+            }
+        }
+        acc[0]
+            .rotate_left(1)
+            .wrapping_add(acc[1].rotate_left(7))
+            .wrapping_add(acc[2].rotate_left(12))
+            .wrapping_add(acc[3].rotate_left(18))
+    } else {
+        seed.wrapping_add(PRIME5)
+    };
+    hash = hash.wrapping_add(bytes.len() as u32);
+
+    let mut rest = stripes.remainder();
+    while rest.len() >= 4 {
+        hash = hash.wrapping_add(read_u32(rest).wrapping_mul(PRIME3));
+        hash = hash.rotate_left(17).wrapping_mul(PRIME4);
+        rest = &rest[4..];
+    }
+    for &byte in rest {
+        hash = hash.wrapping_add((byte as u32).wrapping_mul(PRIME5));
+        hash = hash.rotate_left(11).wrapping_mul(PRIME1);
+    }
+
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(PRIME2);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(PRIME3);
+    hash ^ (hash >> 16)
+}
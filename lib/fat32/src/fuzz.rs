@@ -0,0 +1,110 @@
+//! Entry points for fuzzing the on-disk parsers, e.g. with the cargo-fuzz
+//! targets in `fuzz/`. Each function accepts arbitrary bytes and returns an
+//! error, never panicking or looping forever, if they are malformed.
+//!
+//! Requires the standard library: enable the `fuzz` feature without
+//! `no_std`.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, Cursor, Read};
+use std::sync::{Arc, Mutex};
+
+use crate::mbr::{self, MasterBootRecord};
+use crate::traits::{Dir as _, Entry as _, FileSystem};
+use crate::vfat::{self, BiosParameterBlock, Entry, VFat, VFatHandle};
+
+/// The most entries `image()` visits, so that huge directories cannot make
+/// a single run take too long.
+const MAX_ENTRIES: usize = 4096;
+
+/// Copies the first sector of `data` into a buffer, zero-padded.
+fn sector(data: &[u8]) -> [u8; 512] {
+    let mut buf = [0; 512];
+    let n = data.len().min(512);
+    buf[..n].copy_from_slice(&data[..n]);
+    buf
+}
+
+/// Parses the first 512 bytes of `data` as a master boot record.
+pub fn mbr(data: &[u8]) -> Result<MasterBootRecord, mbr::Error> {
+    MasterBootRecord::parse(&sector(data))
+}
+
+/// Parses the first 512 bytes of `data` as a FAT32 EBPB.
+pub fn ebpb(data: &[u8]) -> Result<BiosParameterBlock, vfat::Error> {
+    BiosParameterBlock::parse(&sector(data))
+}
+
+#[derive(Clone)]
+struct Handle(Arc<VFat<Handle>>);
+
+impl fmt::Debug for Handle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "fuzz::Handle")
+    }
+}
+
+struct FuzzLock(Mutex<()>);
+
+impl vfat::Lock for FuzzLock {
+    fn new() -> Self {
+        FuzzLock(Mutex::new(()))
+    }
+
+    fn critical<R>(&self, f: impl FnOnce() -> R) -> R {
+        let _guard = self.0.lock().expect("fuzz lock poisoned");
+        f()
+    }
+}
+
+impl VFatHandle for Handle {
+    type Lock = FuzzLock;
+
+    fn new(val: VFat<Handle>) -> Self {
+        Handle(Arc::new(val))
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&VFat<Handle>) -> R) -> R {
+        f(&self.0)
+    }
+}
+
+/// Mounts `data` as a disk image and reads every directory and file on it.
+/// Returns the number of entries visited.
+///
+/// # Errors
+///
+/// Returns the error that mounting the image failed with, or `Io` with the
+/// first error met while reading it.
+pub fn image(data: &[u8]) -> Result<usize, vfat::Error> {
+    let vfat = VFat::<Handle>::from(Cursor::new(data.to_vec()))?;
+    let root = (&vfat).open_dir("/").map_err(io::Error::from)?;
+    let mut visited = HashSet::new();
+    visited.insert(root.first_cluster);
+    let mut pending = vec![root];
+    let mut count = 0;
+    while let Some(dir) = pending.pop() {
+        for entry in dir.entries()? {
+            if entry.name() == "." || entry.name() == ".." {
+                continue;
+            }
+            count += 1;
+            if count > MAX_ENTRIES {
+                return Ok(count);
+            }
+            match entry {
+                Entry::Dir(dir) => {
+                    // Directories may link to their ancestors.
+                    if visited.insert(dir.first_cluster) {
+                        pending.push(dir);
+                    }
+                }
+                Entry::File(mut file) => {
+                    io::copy(&mut file.by_ref().take(1 << 20), &mut io::sink())?;
+                }
+            }
+        }
+    }
+    Ok(count)
+}
@@ -0,0 +1,80 @@
+/// Reads the fields of an on-disk structure in order, as little-endian
+/// integers, from a byte buffer.
+///
+/// Buffers are sized for the structure being read; reading past the end is
+/// a bug and panics.
+pub(crate) struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Reader<'a> {
+        Reader { buf }
+    }
+
+    /// Returns the next `n` bytes and advances past them.
+    fn take(&mut self, n: usize) -> &'a [u8] {
+        let (head, tail) = self.buf.split_at(n);
+        self.buf = tail;
+        head
+    }
+
+    pub fn skip(&mut self, n: usize) {
+        self.take(n);
+    }
+
+    pub fn u8(&mut self) -> u8 {
+        self.take(1)[0]
+    }
+
+    pub fn u16(&mut self) -> u16 {
+        let bytes = self.take(2);
+        u16::from_le_bytes([bytes[0], bytes[1]])
+    }
+
+    pub fn u32(&mut self) -> u32 {
+        let bytes = self.take(4);
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    /// Fills `out` with the next `out.len()` bytes.
+    pub fn bytes(&mut self, out: &mut [u8]) {
+        out.copy_from_slice(self.take(out.len()));
+    }
+}
+
+/// Writes the fields of an on-disk structure in order, as little-endian
+/// integers, to a byte buffer. The counterpart of `Reader`.
+pub(crate) struct Writer<'a> {
+    buf: &'a mut [u8],
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Writer<'a> {
+        Writer { buf }
+    }
+
+    /// Returns the next `n` bytes and advances past them.
+    fn take(&mut self, n: usize) -> &'a mut [u8] {
+        let buf = core::mem::replace(&mut self.buf, &mut []);
+        let (head, tail) = buf.split_at_mut(n);
+        self.buf = tail;
+        head
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.take(1)[0] = value;
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.take(2).copy_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.take(4).copy_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.take(bytes.len()).copy_from_slice(bytes);
+    }
+}
@@ -10,6 +10,7 @@ extern crate alloc;
 #[cfg(not(target_endian = "little"))]
 compile_error!("only little endian platforms supported");
 
+mod le;
 mod mbr;
 #[cfg(test)]
 mod tests;
@@ -17,5 +18,7 @@ mod util;
 
 pub mod traits;
 pub mod vfat;
+#[cfg(any(test, feature = "fuzz"))]
+pub mod fuzz;
 
 pub use crate::mbr::*;
@@ -1,12 +1,21 @@
 use core::fmt;
-use core::mem;
 use shim::const_assert_size;
 use shim::io;
 
+use crate::le::{Reader, Writer};
 use crate::traits::BlockDevice;
 
+/// The disk geometry that CHS addresses are computed with, as other
+/// partitioners use for disks that report none.
+const HEADS: u32 = 255;
+const SECTORS_PER_TRACK: u32 = 63;
+
+/// The partition types of FAT32 volumes, addressed by CHS and by LBA.
+pub const TYPE_FAT32: u8 = 0x0B;
+pub const TYPE_FAT32_LBA: u8 = 0x0C;
+
 #[repr(C)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq)]
 pub struct CHS {
     head: u8,
     sector: u8,
@@ -24,6 +33,32 @@ impl fmt::Debug for CHS {
 }
 const_assert_size!(CHS, 3);
 
+impl CHS {
+    /// Returns the CHS address of sector `lba`. Sectors past the last
+    /// cylinder CHS can address get the largest address, which tells readers
+    /// to use the LBA.
+    pub fn from_lba(lba: u32) -> CHS {
+        let cylinder = lba / (HEADS * SECTORS_PER_TRACK);
+        if cylinder > 1023 {
+            return CHS { head: 254, sector: 0xFF, cylinder: 0xFF };
+        }
+        let head = lba / SECTORS_PER_TRACK % HEADS;
+        let sector = lba % SECTORS_PER_TRACK + 1;
+        // The cylinder's top two bits are in the top of the sector byte.
+        CHS { head: head as u8, sector: sector as u8 | ((cylinder >> 2) & 0xC0) as u8, cylinder: cylinder as u8 }
+    }
+
+    fn read(reader: &mut Reader) -> CHS {
+        CHS { head: reader.u8(), sector: reader.u8(), cylinder: reader.u8() }
+    }
+
+    fn write(&self, writer: &mut Writer) {
+        writer.u8(self.head);
+        writer.u8(self.sector);
+        writer.u8(self.cylinder);
+    }
+}
+
 #[repr(C, packed)]
 pub struct PartitionEntry {
     boot_indicator: u8,
@@ -49,6 +84,71 @@ impl fmt::Debug for PartitionEntry {
 
 const_assert_size!(PartitionEntry, 16);
 
+impl PartitionEntry {
+    /// An unused slot.
+    const UNUSED: PartitionEntry = PartitionEntry {
+        boot_indicator: 0,
+        start: CHS { head: 0, sector: 0, cylinder: 0 },
+        partition_type: 0,
+        end: CHS { head: 0, sector: 0, cylinder: 0 },
+        sector_offset: 0,
+        num_sectors: 0,
+    };
+
+    /// Returns whether the slot holds a partition.
+    pub fn is_used(&self) -> bool {
+        self.partition_type != 0
+    }
+
+    pub fn is_bootable(&self) -> bool {
+        self.boot_indicator == 0x80
+    }
+
+    /// Returns the sector after the partition's last.
+    pub fn end_sector(&self) -> u64 {
+        self.sector_offset as u64 + self.num_sectors as u64
+    }
+
+    /// Returns whether the CHS addresses of the partition's first and last
+    /// sectors are those of its LBA ones, as edits write them. Partitions
+    /// written by tools that use another geometry do not match.
+    pub fn chs_matches_lba(&self) -> bool {
+        let (start, count) = (self.sector_offset, self.num_sectors);
+        match start.checked_add(count.wrapping_sub(1)) {
+            Some(last) if count != 0 => self.start == CHS::from_lba(start) && self.end == CHS::from_lba(last),
+            _ => false,
+        }
+    }
+
+    /// Sets the partition's sectors, with matching CHS addresses.
+    fn set_extent(&mut self, start: u32, count: u32) {
+        self.sector_offset = start;
+        self.num_sectors = count;
+        self.start = CHS::from_lba(start);
+        self.end = CHS::from_lba(start + (count - 1));
+    }
+
+    fn read(reader: &mut Reader) -> PartitionEntry {
+        PartitionEntry {
+            boot_indicator: reader.u8(),
+            start: CHS::read(reader),
+            partition_type: reader.u8(),
+            end: CHS::read(reader),
+            sector_offset: reader.u32(),
+            num_sectors: reader.u32(),
+        }
+    }
+
+    fn write(&self, writer: &mut Writer) {
+        writer.u8(self.boot_indicator);
+        self.start.write(writer);
+        writer.u8(self.partition_type);
+        self.end.write(writer);
+        writer.u32(self.sector_offset);
+        writer.u32(self.num_sectors);
+    }
+}
+
 /// The master boot record (MBR).
 #[repr(C, packed)]
 pub struct MasterBootRecord {
@@ -78,6 +178,20 @@ pub enum Error {
     UnknownBootIndicator(u8),
     /// The MBR magic signature was invalid.
     BadSignature,
+    /// Partition `.0` (0-indexed) is in use but is empty or extends past the
+    /// largest addressable sector.
+    BadPartition(u8),
+    /// Partition slot `.0` (0-indexed) is already in use.
+    InUse(u8),
+    /// Partition slot `.0` (0-indexed) is not in use.
+    Unused(u8),
+    /// Type 0 marks unused slots; partitions need another.
+    BadType,
+    /// The partition would be empty, include the MBR's sector, or end past
+    /// the disk or the largest addressable sector.
+    BadExtent,
+    /// The partition would overlap partition `.0` (0-indexed).
+    Overlaps(u8),
 }
 
 impl MasterBootRecord {
@@ -95,7 +209,30 @@ impl MasterBootRecord {
             Ok(_) => {}
             Err(e) => return Err(Error::Io(e))
         }
-        let mbr: MasterBootRecord = unsafe { mem::transmute(buf) };
+        MasterBootRecord::parse(&buf)
+    }
+
+    /// Parses and validates the master boot record in `sector`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as `from()`, except `Io`. Also returns
+    /// `BadPartition(n)` if partition `n` has a non-zero type but no sectors,
+    /// or ends past sector `u32::MAX`.
+    pub fn parse(sector: &[u8; 512]) -> Result<MasterBootRecord, Error> {
+        let mut reader = Reader::new(sector);
+        let mut bootstrap = [0; 436];
+        reader.bytes(&mut bootstrap);
+        let mut disk_id = [0; 10];
+        reader.bytes(&mut disk_id);
+        let partition_table = [
+            PartitionEntry::read(&mut reader),
+            PartitionEntry::read(&mut reader),
+            PartitionEntry::read(&mut reader),
+            PartitionEntry::read(&mut reader),
+        ];
+        let signature = reader.u16();
+        let mbr = MasterBootRecord { bootstrap, disk_id, partition_table, signature };
         for i in 0..mbr.partition_table.len() {
             if mbr.partition_table[i].boot_indicator & 0x7f != 0 {
                 return Err(Error::UnknownBootIndicator(i as u8));
@@ -104,6 +241,162 @@ impl MasterBootRecord {
         if mbr.signature != 0xaa55 {
             return Err(Error::BadSignature);
         }
+        for (i, partition) in mbr.partition_table.iter().enumerate() {
+            let (offset, count) = (partition.sector_offset, partition.num_sectors);
+            if partition.partition_type != 0 && (count == 0 || offset.checked_add(count).is_none()) {
+                return Err(Error::BadPartition(i as u8));
+            }
+        }
         Ok(mbr)
     }
+
+    /// Returns an MBR with no partitions and no bootstrap code, for a blank
+    /// disk with the 32-bit disk signature `disk_signature`.
+    pub fn new(disk_signature: u32) -> MasterBootRecord {
+        let mut disk_id = [0; 10];
+        disk_id[4..8].copy_from_slice(&disk_signature.to_le_bytes());
+        MasterBootRecord {
+            bootstrap: [0; 436],
+            disk_id,
+            partition_table: [PartitionEntry::UNUSED; 4],
+            signature: 0xaa55,
+        }
+    }
+
+    /// Writes the MBR to `sector`.
+    pub fn write(&self, sector: &mut [u8; 512]) {
+        let mut writer = Writer::new(sector);
+        writer.bytes(&self.bootstrap);
+        writer.bytes(&self.disk_id);
+        for partition in self.partition_table.iter() {
+            partition.write(&mut writer);
+        }
+        writer.u16(self.signature);
+    }
+
+    /// Writes the MBR to sector 0 of `device`, keeping the rest of a sector
+    /// larger than 512 bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Io(err)` if the I/O error `err` occurred reading or writing
+    /// the sector.
+    pub fn write_to<T: BlockDevice>(&self, mut device: T) -> Result<(), Error> {
+        let mut buf = vec![0; device.sector_size() as usize];
+        if buf.len() > 512 {
+            device.read_sector(0, &mut buf).map_err(Error::Io)?;
+        }
+        let mut sector = [0; 512];
+        self.write(&mut sector);
+        buf[..512].copy_from_slice(&sector);
+        device.write_sector(0, &buf).map_err(Error::Io)?;
+        Ok(())
+    }
+
+    /// Creates partition `index` (0-indexed) of type `partition_type` on the
+    /// `count` sectors from `start` of a disk of `disk_sectors` sectors.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InUse` if the slot is taken, `BadType` if `partition_type` is
+    /// 0, `BadExtent` if the sectors are not on the disk past the MBR, and
+    /// `Overlaps(n)` if partition `n` has some of them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not 0 to 3.
+    pub fn create(&mut self, index: usize, partition_type: u8, start: u64, count: u64, disk_sectors: u64) -> Result<(), Error> {
+        if self.partition_table[index].is_used() {
+            return Err(Error::InUse(index as u8));
+        }
+        if partition_type == 0 {
+            return Err(Error::BadType);
+        }
+        self.check_extent(index, start, count, disk_sectors)?;
+        let mut partition = PartitionEntry::UNUSED;
+        partition.partition_type = partition_type;
+        partition.set_extent(start as u32, count as u32);
+        self.partition_table[index] = partition;
+        Ok(())
+    }
+
+    /// Deletes partition `index` (0-indexed). Its sectors are not touched.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Unused` if the slot has no partition.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not 0 to 3.
+    pub fn delete(&mut self, index: usize) -> Result<(), Error> {
+        if !self.partition_table[index].is_used() {
+            return Err(Error::Unused(index as u8));
+        }
+        self.partition_table[index] = PartitionEntry::UNUSED;
+        Ok(())
+    }
+
+    /// Makes partition `index` (0-indexed) `count` sectors long, keeping its
+    /// start, on a disk of `disk_sectors` sectors. The file system in it is
+    /// not resized.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Unused` if the slot has no partition, and the extent errors
+    /// of `create()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not 0 to 3.
+    pub fn resize(&mut self, index: usize, count: u64, disk_sectors: u64) -> Result<(), Error> {
+        if !self.partition_table[index].is_used() {
+            return Err(Error::Unused(index as u8));
+        }
+        let start = self.partition_table[index].sector_offset;
+        self.check_extent(index, start as u64, count, disk_sectors)?;
+        self.partition_table[index].set_extent(start, count as u32);
+        Ok(())
+    }
+
+    /// Returns the start and length of the largest run of sectors of a disk
+    /// of `disk_sectors` sectors that no partition has, starting at a
+    /// multiple of `align` sectors past the MBR, or `None` if there is none.
+    pub fn largest_free(&self, disk_sectors: u64, align: u64) -> Option<(u64, u64)> {
+        let disk_sectors = disk_sectors.min(u32::max_value() as u64);
+        let align = align.max(1);
+        let mut used: [(u64, u64); 4] = [(0, 0); 4];
+        for (extent, partition) in used.iter_mut().zip(self.partition_table.iter()) {
+            if partition.is_used() {
+                *extent = (partition.sector_offset as u64, partition.end_sector());
+            }
+        }
+        used.sort_unstable();
+        let mut best: Option<(u64, u64)> = None;
+        // The gaps between partitions, from after the MBR to the disk's end.
+        let mut from = 1;
+        for (start, end) in used.iter().copied().chain(Some((disk_sectors, disk_sectors))) {
+            let aligned = (from + align - 1) / align * align;
+            if start > aligned && best.map_or(true, |(_, len)| start - aligned > len) {
+                best = Some((aligned, start - aligned));
+            }
+            from = from.max(end);
+        }
+        best
+    }
+
+    /// Checks that the `count` sectors from `start` can be partition `index`
+    /// of a disk of `disk_sectors` sectors.
+    fn check_extent(&self, index: usize, start: u64, count: u64, disk_sectors: u64) -> Result<(), Error> {
+        let end = start.checked_add(count).ok_or(Error::BadExtent)?;
+        if count == 0 || start == 0 || end > disk_sectors || end > u32::max_value() as u64 {
+            return Err(Error::BadExtent);
+        }
+        for (i, other) in self.partition_table.iter().enumerate() {
+            if i != index && other.is_used() && start < other.end_sector() && (other.sector_offset as u64) < end {
+                return Err(Error::Overlaps(i as u8));
+            }
+        }
+        Ok(())
+    }
 }
@@ -12,10 +12,23 @@ use crate::traits::*;
 use crate::vfat;
 
 use mbr::{MasterBootRecord, PartitionEntry, CHS};
-use vfat::{BiosParameterBlock, VFat, VFatHandle};
+use vfat::{BiosParameterBlock, Lock, MountOptions, VFat, VFatHandle};
+
+struct StdLock(Mutex<()>);
+
+impl Lock for StdLock {
+    fn new() -> Self {
+        StdLock(Mutex::new(()))
+    }
+
+    fn critical<R>(&self, f: impl FnOnce() -> R) -> R {
+        let _guard = self.0.lock().expect("all okay");
+        f()
+    }
+}
 
 #[derive(Clone)]
-struct StdVFatHandle(Arc<Mutex<VFat<Self>>>);
+struct StdVFatHandle(Arc<VFat<Self>>);
 
 impl Debug for StdVFatHandle {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
@@ -24,12 +37,14 @@ impl Debug for StdVFatHandle {
 }
 
 impl VFatHandle for StdVFatHandle {
+    type Lock = StdLock;
+
     fn new(val: VFat<StdVFatHandle>) -> Self {
-        StdVFatHandle(Arc::new(Mutex::new(val)))
+        StdVFatHandle(Arc::new(val))
     }
 
-    fn lock<R>(&self, f: impl FnOnce(&mut VFat<StdVFatHandle>) -> R) -> R {
-        f(&mut self.0.lock().expect("all okay"))
+    fn with<R>(&self, f: impl FnOnce(&VFat<StdVFatHandle>) -> R) -> R {
+        f(&self.0)
     }
 }
 
@@ -137,10 +152,73 @@ fn check_ebpb_size() {
     check_size!(BiosParameterBlock, 512);
 }
 
+#[test]
+fn check_mbr_partitions() {
+    let mut data = [0u8; 512];
+    data[510..].copy_from_slice(&[0x55, 0xAA]);
+
+    let entry = 446 + 16;
+    data[entry + 4] = 0x0C;
+    expect_variant!(MasterBootRecord::parse(&data), Err(mbr::Error::BadPartition(1)));
+
+    data[entry + 8..entry + 12].copy_from_slice(&u32::max_value().to_le_bytes());
+    data[entry + 12..entry + 16].copy_from_slice(&2u32.to_le_bytes());
+    expect_variant!(MasterBootRecord::parse(&data), Err(mbr::Error::BadPartition(1)));
+
+    data[entry + 8..entry + 12].copy_from_slice(&1u32.to_le_bytes());
+    MasterBootRecord::parse(&data).unwrap();
+}
+
+#[test]
+fn test_mbr_editing() {
+    const DISK: u64 = 1 << 20;
+    let mut mbr = MasterBootRecord::new(0x1234_5678);
+    assert_eq!(mbr.largest_free(DISK, 2048), Some((2048, DISK - 2048)));
+
+    mbr.create(0, mbr::TYPE_FAT32_LBA, 2048, 1 << 16, DISK).expect("created partition");
+    expect_variant!(mbr.create(0, 0x83, 1 << 17, 100, DISK), Err(mbr::Error::InUse(0)));
+    expect_variant!(mbr.create(1, 0, 1 << 17, 100, DISK), Err(mbr::Error::BadType));
+    expect_variant!(mbr.create(1, 0x83, 0, 100, DISK), Err(mbr::Error::BadExtent));
+    expect_variant!(mbr.create(1, 0x83, DISK - 10, 11, DISK), Err(mbr::Error::BadExtent));
+    expect_variant!(mbr.create(1, 0x83, 4096, 100, DISK), Err(mbr::Error::Overlaps(0)));
+    mbr.create(1, 0x83, DISK / 2, DISK / 2, DISK).expect("created partition");
+    assert_eq!(mbr.largest_free(DISK, 2048), Some((2048 + (1 << 16), DISK / 2 - 2048 - (1 << 16))));
+
+    expect_variant!(mbr.resize(0, DISK / 2, DISK), Err(mbr::Error::Overlaps(1)));
+    mbr.resize(0, DISK / 2 - 2048, DISK).expect("resized partition");
+    expect_variant!(mbr.resize(2, 100, DISK), Err(mbr::Error::Unused(2)));
+    assert_eq!(mbr.largest_free(DISK, 2048), None);
+    mbr.delete(1).expect("deleted partition");
+    expect_variant!(mbr.delete(1), Err(mbr::Error::Unused(1)));
+
+    // What is written reads back the same, with CHS addresses that match.
+    let mut disk = Cursor::new(vec![0u8; 1024]);
+    mbr.write_to(&mut disk).expect("wrote MBR");
+    let mut sector = [0u8; 512];
+    sector.copy_from_slice(&disk.get_ref()[..512]);
+    let read = MasterBootRecord::parse(&sector).expect("valid MBR");
+    assert_eq!(read.disk_id[4..8], 0x1234_5678u32.to_le_bytes());
+    let partition = &read.partition_table[0];
+    assert_eq!((partition.partition_type, { partition.sector_offset }, { partition.num_sectors }),
+               (mbr::TYPE_FAT32_LBA, 2048, DISK as u32 / 2 - 2048));
+    assert!(partition.chs_matches_lba());
+    assert!(!read.partition_table[1].is_used());
+
+    // Sector 2048 is on track 32 of cylinder 0, as head 32, sector 33.
+    assert_eq!(sector[446 + 1..446 + 4], [32, 33, 0]);
+    // Sectors past cylinder 1023 get the largest CHS address.
+    let mut huge = MasterBootRecord::new(0);
+    huge.create(3, 0x83, 1, u32::max_value() as u64 - 1, u64::max_value()).expect("created partition");
+    huge.write(&mut sector);
+    assert!(huge.partition_table[3].chs_matches_lba());
+    assert_eq!(sector[446 + 48 + 5..446 + 48 + 8], [254, 0xFF, 0xFF]);
+    assert!(CHS::from_lba(1024 * 255 * 63) == CHS::from_lba(u32::max_value()));
+}
+
 #[test]
 fn check_ebpb_signature() {
     let mut data = [0u8; 1024];
-    data[510..512].copy_from_slice(&[0x55, 0xAA]);
+    data[..512].copy_from_slice(&blank_ebpb());
 
     let e = BiosParameterBlock::from(Cursor::new(&mut data[..]), 1).unwrap_err();
     expect_variant!(e, vfat::Error::BadSignature);
@@ -148,6 +226,28 @@ fn check_ebpb_signature() {
     BiosParameterBlock::from(Cursor::new(&mut data[..]), 0).unwrap();
 }
 
+#[test]
+fn check_ebpb_fields() {
+    let cases: &[(usize, &[u8], &str)] = &[
+        (11, &[0x00, 0x01], "bytes_per_sector"),
+        (11, &[0x00, 0x03], "bytes_per_sector"),
+        (11, &[0x00, 0x20], "bytes_per_sector"),
+        (13, &[0], "sectors_per_cluster"),
+        (13, &[3], "sectors_per_cluster"),
+        (14, &[0, 0], "reserved_sectors"),
+        (16, &[0], "fats"),
+        (36, &[0, 0, 0, 0], "sectors_per_fat"),
+        (44, &[1, 0, 0, 0], "root_directory_cluster"),
+        (32, &[4, 0, 0, 0], "total_logical_sectors"),
+    ];
+    for &(offset, bytes, field) in cases {
+        let mut ebpb = blank_ebpb();
+        ebpb[offset..offset + bytes.len()].copy_from_slice(bytes);
+        expect_variant!(BiosParameterBlock::parse(&ebpb), Err(vfat::Error::BadField(f)) if f == field);
+    }
+    BiosParameterBlock::parse(&blank_ebpb()).expect("valid EBPB");
+}
+
 #[test]
 fn test_ebpb() {
     let mut ebpb1 = resource!("ebpb1.img");
@@ -168,9 +268,7 @@ fn test_ebpb() {
 #[test]
 fn check_entry_sizes() {
     check_size!(vfat::dir::VFatRegularDirEntry, 32);
-    check_size!(vfat::dir::VFatUnknownDirEntry, 32);
     check_size!(vfat::dir::VFatLfnDirEntry, 32);
-    check_size!(vfat::dir::VFatDirEntry, 32);
 }
 
 #[test]
@@ -471,3 +569,822 @@ fn shuffle_test() {
     let hash = hash_files_recursive_from(vfat, "/");
     assert_hash_eq!("mock 1 file hashes", hash, hash_for!("files-1"));
 }
+
+/// Returns a blank FAT32 image: an MBR with one partition at sector 1 and a
+/// volume of 1-sector clusters with two FATs of one sector each.
+fn blank_image() -> Cursor<Vec<u8>> {
+    const TOTAL_SECTORS: u32 = 130;
+    let mut image = vec![0u8; (1 + TOTAL_SECTORS as usize) * 512];
+    // MBR: partition type 0x0C at sector 1.
+    image[446 + 4] = 0x0C;
+    image[446 + 8..446 + 12].copy_from_slice(&1u32.to_le_bytes());
+    image[446 + 12..446 + 16].copy_from_slice(&TOTAL_SECTORS.to_le_bytes());
+    image[510] = 0x55;
+    image[511] = 0xAA;
+
+    let bpb = &mut image[512..1024];
+    bpb[11..13].copy_from_slice(&512u16.to_le_bytes());
+    bpb[13] = 1;
+    bpb[14..16].copy_from_slice(&2u16.to_le_bytes());
+    bpb[16] = 2;
+    bpb[32..36].copy_from_slice(&TOTAL_SECTORS.to_le_bytes());
+    bpb[36..40].copy_from_slice(&1u32.to_le_bytes());
+    bpb[44..48].copy_from_slice(&2u32.to_le_bytes());
+    bpb[510] = 0x55;
+    bpb[511] = 0xAA;
+
+    // Both FATs: the reserved entries and the root directory's cluster.
+    for fat in 0..2 {
+        let start = (1 + 2 + fat) * 512;
+        image[start..start + 4].copy_from_slice(&0x0FFF_FFF8u32.to_le_bytes());
+        image[start + 4..start + 8].copy_from_slice(&0x0FFF_FFFFu32.to_le_bytes());
+        image[start + 8..start + 12].copy_from_slice(&0x0FFF_FFFFu32.to_le_bytes());
+    }
+    Cursor::new(image)
+}
+
+#[test]
+fn test_write_files() {
+    let vfat = VFat::<StdVFatHandle>::from(blank_image()).expect("valid blank image");
+    let root = (&vfat).open_dir("/").expect("root exists");
+
+    let log = root.create_dir("log").expect("created dir");
+    let mut file = log.create_file("kernel.log").expect("created file");
+    let data: Vec<u8> = (0..2000).map(|i| i as u8).collect();
+    file.write_all(&data).expect("wrote file");
+    file.flush().expect("flushed file");
+    expect_variant!(root.create_dir("LOG"), Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists);
+    expect_variant!(log.create_file("too_long_name"), Err(ref e) if e.kind() == io::ErrorKind::InvalidInput);
+
+    let mut file = (&vfat).open_file("/log/kernel.log").expect("file exists");
+    assert_eq!(file.name, "kernel.log");
+    assert_eq!(file.size(), 2000);
+    let mut read = Vec::new();
+    file.read_to_end(&mut read).expect("read file");
+    assert_eq!(read, data);
+
+    log.rename("kernel.log", "kernel.1").expect("renamed file");
+    expect_variant!((&vfat).open("/log/kernel.log"), Err(ref e) if e.kind() == io::ErrorKind::NotFound);
+    expect_variant!(root.remove("log"), Err(ref e) if e.kind() == io::ErrorKind::Other);
+    log.remove("kernel.1").expect("removed file");
+    root.remove("log").expect("removed dir");
+    let names: Vec<String> = root.entries().unwrap().map(|e| e.name().to_string()).collect();
+    assert!(names.is_empty());
+
+    // The freed clusters are reused.
+    let mut file = root.create_file("A.TXT").expect("created file");
+    file.write_all(&data).expect("wrote file");
+}
+
+#[test]
+fn test_concurrent_file_io() {
+    let vfat = VFat::<StdVFatHandle>::from(blank_image()).expect("valid blank image");
+    let root = (&vfat).open_dir("/").expect("root exists");
+    let data: Vec<u8> = (0..8192).map(|i| (i / 7) as u8).collect();
+    root.create_file("a").expect("created file").write_all(&data).expect("wrote file");
+    root.create_file("b").expect("created file");
+
+    // Readers of one file and a writer of another share the volume, each
+    // taking its locks a cluster at a time.
+    let readers: Vec<_> = (0..3).map(|_| {
+        let (vfat, data) = (vfat.clone(), data.clone());
+        std::thread::spawn(move || {
+            for _ in 0..20 {
+                let mut read = Vec::new();
+                let mut file = (&vfat).open_file("/a").expect("file exists");
+                file.read_to_end(&mut read).expect("read file");
+                assert_eq!(read, data);
+            }
+        })
+    }).collect();
+    let writer = {
+        let (vfat, data) = (vfat.clone(), data.clone());
+        std::thread::spawn(move || {
+            let mut file = (&vfat).open_file("/b").expect("file exists");
+            for chunk in data.chunks(100) {
+                file.write_all(chunk).expect("wrote file");
+            }
+        })
+    };
+    for thread in readers.into_iter().chain(Some(writer)) {
+        thread.join().expect("thread finished");
+    }
+
+    let mut read = Vec::new();
+    (&vfat).open_file("/b").expect("file exists").read_to_end(&mut read).expect("read file");
+    assert_eq!(read, data);
+}
+
+#[test]
+fn test_chain_index() {
+    use vfat::ChainIndex;
+
+    let mut index = ChainIndex::new();
+    index.select(vfat::Cluster::from(5));
+    for &cluster in &[5, 6, 7, 20, 21, 9] {
+        index.push(vfat::Cluster::from(cluster));
+    }
+    assert_eq!(index.len(), 6);
+    assert_eq!(index.extents(), 3);
+    let found: Vec<_> = (0..7).map(|n| index.get(n).map(|c| c.get_value())).collect();
+    assert_eq!(found, vec![Some(5), Some(6), Some(7), Some(20), Some(21), Some(9), None]);
+
+    // Indexing another chain forgets this one.
+    index.select(vfat::Cluster::from(5));
+    assert_eq!(index.len(), 6);
+    index.select(vfat::Cluster::from(30));
+    assert!(index.is_empty());
+}
+
+#[test]
+fn test_seek_reads_use_chain_index() {
+    let vfat = VFat::<StdVFatHandle>::from(blank_image()).expect("valid blank image");
+    let root = (&vfat).open_dir("/").expect("root exists");
+    // 1-sector clusters, so the file's chain is 64 clusters long.
+    let data: Vec<u8> = (0..64 * 512).map(|i| (i % 251) as u8).collect();
+    root.create_file("big").expect("created file").write_all(&data).expect("wrote file");
+
+    let mut file = (&vfat).open_file("/big").expect("file exists");
+    let accesses = || vfat.with(|vfat| {
+        let stats = vfat.cache_stats();
+        stats.hits + stats.misses
+    });
+    let mut byte = [0u8];
+    file.seek(io::SeekFrom::End(-1)).expect("seek");
+    file.read_exact(&mut byte).expect("read file");
+    assert_eq!(byte[0], data[data.len() - 1]);
+
+    // Once the chain has been walked, a read anywhere in it touches only the
+    // sectors it reads, not the FAT.
+    for &offset in &[100, 40 * 512 + 7, 63 * 512, 3] {
+        let before = accesses();
+        file.seek(io::SeekFrom::Start(offset as u64)).expect("seek");
+        file.read_exact(&mut byte).expect("read file");
+        assert_eq!(byte[0], data[offset]);
+        assert_eq!(accesses() - before, 1);
+    }
+
+    // Writes past the clusters found so far extend the chain, which reads
+    // then find.
+    file.seek(io::SeekFrom::End(0)).expect("seek");
+    file.write_all(&data[..1000]).expect("wrote file");
+    file.seek(io::SeekFrom::Start(data.len() as u64 + 600)).expect("seek");
+    file.read_exact(&mut byte).expect("read file");
+    assert_eq!(byte[0], data[600]);
+}
+
+/// A read-only volume synthesized sector by sector, holding one file `BIG.BIN`
+/// of the largest size FAT32 allows, 4GiB less a byte, in 32KiB clusters 3
+/// onwards. Each 8-byte word of the file holds its own offset.
+struct HugeFileDevice;
+
+impl HugeFileDevice {
+    const SECTORS_PER_CLUSTER: u64 = 64;
+    const RESERVED: u64 = 32;
+    const SECTORS_PER_FAT: u64 = 1025;
+    const FILE_SIZE: u64 = u32::max_value() as u64;
+    /// The file's clusters, the last partly used.
+    const FILE_CLUSTERS: u64 = (Self::FILE_SIZE + 64 * 512 - 1) / (64 * 512);
+
+    /// The partition's first sector of the data region, cluster 2.
+    fn data_start() -> u64 {
+        Self::RESERVED + Self::SECTORS_PER_FAT
+    }
+
+    fn total_sectors() -> u64 {
+        Self::data_start() + (1 + Self::FILE_CLUSTERS) * Self::SECTORS_PER_CLUSTER
+    }
+
+    fn fat_entry(cluster: u64) -> u32 {
+        let last = 2 + Self::FILE_CLUSTERS;
+        match cluster {
+            0 => 0x0FFF_FFF8,
+            1 | 2 => 0x0FFF_FFFF,
+            c if c == last => 0x0FFF_FFFF,
+            c if c < last => c as u32 + 1,
+            _ => 0,
+        }
+    }
+}
+
+impl BlockDevice for HugeFileDevice {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let buf = &mut buf[..512];
+        for byte in buf.iter_mut() {
+            *byte = 0;
+        }
+        if n == 0 {
+            // MBR: partition type 0x0C at sector 1.
+            buf[446 + 4] = 0x0C;
+            buf[446 + 8..446 + 12].copy_from_slice(&1u32.to_le_bytes());
+            buf[446 + 12..446 + 16].copy_from_slice(&(Self::total_sectors() as u32).to_le_bytes());
+            buf[510] = 0x55;
+            buf[511] = 0xAA;
+            return Ok(512);
+        }
+        let sector = n - 1;
+        let data_start = Self::data_start();
+        if sector == 0 {
+            buf[11..13].copy_from_slice(&512u16.to_le_bytes());
+            buf[13] = Self::SECTORS_PER_CLUSTER as u8;
+            buf[14..16].copy_from_slice(&(Self::RESERVED as u16).to_le_bytes());
+            buf[16] = 1;
+            buf[32..36].copy_from_slice(&(Self::total_sectors() as u32).to_le_bytes());
+            buf[36..40].copy_from_slice(&(Self::SECTORS_PER_FAT as u32).to_le_bytes());
+            buf[44..48].copy_from_slice(&2u32.to_le_bytes());
+            buf[510] = 0x55;
+            buf[511] = 0xAA;
+        } else if sector >= Self::RESERVED && sector < data_start {
+            let first = (sector - Self::RESERVED) * 128;
+            for (i, entry) in buf.chunks_mut(4).enumerate() {
+                entry.copy_from_slice(&Self::fat_entry(first + i as u64).to_le_bytes());
+            }
+        } else if sector == data_start {
+            buf[..11].copy_from_slice(b"BIG     BIN");
+            buf[11] = 0x20;
+            buf[26..28].copy_from_slice(&3u16.to_le_bytes());
+            buf[28..32].copy_from_slice(&(Self::FILE_SIZE as u32).to_le_bytes());
+        } else if sector >= data_start + Self::SECTORS_PER_CLUSTER && sector < Self::total_sectors() {
+            let offset = (sector - data_start - Self::SECTORS_PER_CLUSTER) * 512;
+            for (i, word) in buf.chunks_mut(8).enumerate() {
+                word.copy_from_slice(&(offset + 8 * i as u64).to_le_bytes());
+            }
+        }
+        Ok(512)
+    }
+
+    fn write_sector(&mut self, _n: u64, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len().min(512))
+    }
+}
+
+#[test]
+fn test_read_near_max_file_size() {
+    let vfat = VFat::<StdVFatHandle>::from(HugeFileDevice).expect("valid synthetic volume");
+    let mut file = (&vfat).open_file("/BIG.BIN").expect("file exists");
+    assert_eq!(file.size(), HugeFileDevice::FILE_SIZE);
+
+    // The last bytes of the file, from 4GiB less 16, are the last two words,
+    // the second cut short by the end of the file.
+    let start = HugeFileDevice::FILE_SIZE - 15;
+    assert_eq!(file.seek(io::SeekFrom::End(-15)).expect("seek"), start);
+    let mut buf = [0u8; 64];
+    let mut read = 0;
+    loop {
+        match file.read(&mut buf[read..]).expect("read file") {
+            0 => break,
+            n => read += n,
+        }
+    }
+    assert_eq!(read, 15);
+    let mut expected = Vec::new();
+    expected.extend_from_slice(&start.to_le_bytes());
+    expected.extend_from_slice(&(start + 8).to_le_bytes()[..7]);
+    assert_eq!(&buf[..read], &expected[..]);
+    assert_eq!(file.seek(io::SeekFrom::Current(0)).expect("seek"), HugeFileDevice::FILE_SIZE);
+
+    // Offsets past the end, or that overflow, are refused and leave the
+    // position alone.
+    for &pos in &[
+        io::SeekFrom::Current(1),
+        io::SeekFrom::Start(u64::max_value()),
+        io::SeekFrom::End(i64::min_value()),
+        io::SeekFrom::Current(i64::max_value()),
+    ] {
+        expect_variant!(file.seek(pos), Err(ref e) if e.kind() == io::ErrorKind::InvalidInput);
+    }
+    assert_eq!(file.seek(io::SeekFrom::Current(0)).expect("seek"), HugeFileDevice::FILE_SIZE);
+
+    // A word in the middle of the file, past 2GiB.
+    let offset = 0x8765_4320;
+    file.seek(io::SeekFrom::Start(offset)).expect("seek");
+    let mut word = [0u8; 8];
+    file.read_exact(&mut word).expect("read file");
+    assert_eq!(u64::from_le_bytes(word), offset);
+
+    // A write that would grow the file past what FAT32 can record fails.
+    file.seek(io::SeekFrom::End(-1)).expect("seek");
+    expect_variant!(file.write(b"ab"), Err(ref e) if e.kind() == io::ErrorKind::InvalidInput);
+}
+
+/// Returns the EBPB of `blank_image()`.
+fn blank_ebpb() -> [u8; 512] {
+    let mut ebpb = [0; 512];
+    ebpb.copy_from_slice(&blank_image().get_ref()[512..1024]);
+    ebpb
+}
+
+/// The offset of the root directory's cluster in `blank_image()`.
+const ROOT: usize = (1 + 4) * 512;
+
+/// Sets the entry of `cluster` in both FATs of `blank_image()`.
+fn set_fat(image: &mut [u8], cluster: usize, value: u32) {
+    for fat in 0..2 {
+        let start = (1 + 2 + fat) * 512 + cluster * 4;
+        image[start..start + 4].copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// Returns `blank_image()` with a 1000-byte file `A.TXT` in clusters 3 and 4
+/// and an empty directory `D` in cluster 5.
+fn populated_image() -> Vec<u8> {
+    let mut image = blank_image().into_inner();
+    image[ROOT..ROOT + 11].copy_from_slice(b"A       TXT");
+    image[ROOT + 11] = 0x20;
+    image[ROOT + 26..ROOT + 28].copy_from_slice(&3u16.to_le_bytes());
+    image[ROOT + 28..ROOT + 32].copy_from_slice(&1000u32.to_le_bytes());
+    image[ROOT + 32..ROOT + 43].copy_from_slice(b"D          ");
+    image[ROOT + 43] = 0x10;
+    image[ROOT + 58..ROOT + 60].copy_from_slice(&5u16.to_le_bytes());
+    set_fat(&mut image, 3, 4);
+    set_fat(&mut image, 4, 0x0FFF_FFFF);
+    set_fat(&mut image, 5, 0x0FFF_FFFF);
+    image
+}
+
+#[test]
+fn test_malformed_images() {
+    use crate::fuzz;
+
+    assert_eq!(fuzz::image(&populated_image()).expect("valid image"), 2);
+
+    let corruptions: &[(&str, fn(&mut Vec<u8>))] = &[
+        ("truncated image", |image| image.truncate(3000)),
+        ("no FAT32 partition", |image| image[446 + 4] = 0x83),
+        ("empty partition", |image| image[446 + 12..446 + 16].copy_from_slice(&[0; 4])),
+        ("partition past the disk", |image| image[446 + 8..446 + 12].copy_from_slice(&[0, 0, 0, 1])),
+        ("zero bytes per sector", |image| image[512 + 11..512 + 13].copy_from_slice(&[0, 0])),
+        ("huge FATs", |image| image[512 + 36..512 + 40].copy_from_slice(&[0, 0, 0, 1])),
+        ("root past the FAT", |image| image[512 + 44..512 + 48].copy_from_slice(&1000u32.to_le_bytes())),
+        ("file in cluster 1", |image| image[ROOT + 26] = 1),
+        ("file past the FAT", |image| image[ROOT + 26..ROOT + 28].copy_from_slice(&[0xFF, 0xFF])),
+        ("chain past the FAT", |image| set_fat(image, 3, 0x0000_FFFF)),
+        ("file chain loops", |image| set_fat(image, 4, 3)),
+        ("directory chain loops", |image| set_fat(image, 5, 5)),
+        ("chain into a reserved cluster", |image| set_fat(image, 3, 0x0FFF_FFF0)),
+        ("huge file size", |image| image[ROOT + 28..ROOT + 32].copy_from_slice(&[0xFF; 4])),
+        ("directory in cluster 0", |image| image[ROOT + 58] = 0),
+        ("directory links to the root", |image| image[ROOT + 58] = 2),
+        ("unpaired surrogate in a long name", |image| {
+            let lfn = &mut image[ROOT + 64..ROOT + 96];
+            lfn[0] = 0x41;
+            lfn[1..3].copy_from_slice(&0xD800u16.to_le_bytes());
+            lfn[11] = 0x0F;
+        }),
+    ];
+    for &(name, corrupt) in corruptions {
+        let mut image = populated_image();
+        corrupt(&mut image);
+        // Whatever the outcome, reading the image must not panic or hang.
+        let _ = fuzz::image(&image).map_err(|e| eprintln!("{}: {:?}", name, e));
+    }
+
+    let mut image = populated_image();
+    image[512 + 44..512 + 48].copy_from_slice(&1000u32.to_le_bytes());
+    expect_variant!(fuzz::image(&image), Err(vfat::Error::BadField("root_directory_cluster")));
+
+    let mut image = populated_image();
+    set_fat(&mut image, 5, 5);
+    expect_variant!(fuzz::image(&image), Err(vfat::Error::Io(ref e)) if e.kind() == io::ErrorKind::InvalidData);
+
+    let mut image = populated_image();
+    image[ROOT + 58] = 2;
+    assert_eq!(fuzz::image(&image).expect("directory cycle is skipped"), 2);
+}
+
+#[test]
+fn test_random_corruption() {
+    use crate::fuzz;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    let original = populated_image();
+    let mut rng = XorShiftRng::from_seed([0x0123_4567, 0x89AB_CDEF, 0xFEDC_BA98, 0x7654_3210]);
+    for _ in 0..500 {
+        let mut image = original.clone();
+        for _ in 0..rng.gen_range(1, 16) {
+            // Corrupt the MBR, the EBPB, the FATs or the root directory.
+            let i = rng.gen_range(0, ROOT + 128);
+            image[i] = rng.gen();
+        }
+        let _ = fuzz::image(&image);
+        let _ = fuzz::mbr(&image);
+        let _ = fuzz::ebpb(&image[512..]);
+    }
+}
+
+/// A device whose asynchronous reads complete on their second poll.
+struct SlowDevice {
+    image: Cursor<Vec<u8>>,
+    polled: std::collections::HashSet<u64>,
+    async_reads: Arc<Mutex<usize>>,
+    completed_reads: Arc<Mutex<usize>>,
+}
+
+impl BlockDevice for SlowDevice {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.image.read_sector(n, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        self.image.write_sector(n, buf)
+    }
+
+    fn read_sector_async(&mut self, n: u64, mut buf: Vec<u8>) -> AsyncRead {
+        *self.async_reads.lock().unwrap() += 1;
+        buf.resize(512, 0);
+        AsyncRead::pending(n, buf)
+    }
+
+    fn poll_read(&mut self, read: &mut AsyncRead) -> bool {
+        if !read.is_complete() && !self.polled.insert(read.sector) {
+            let result = self.image.read_sector(read.sector, read.buffer());
+            read.complete(result);
+            *self.completed_reads.lock().unwrap() += 1;
+        }
+        read.is_complete()
+    }
+}
+
+#[test]
+fn test_prefetch() {
+    let mut image = populated_image();
+    let data: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+    // A.TXT's clusters are data sectors 1 and 2 of the volume.
+    let start = (1 + 5) * 512;
+    image[start..start + 1000].copy_from_slice(&data);
+
+    let async_reads = Arc::new(Mutex::new(0));
+    let device = SlowDevice {
+        image: Cursor::new(image),
+        polled: std::collections::HashSet::new(),
+        async_reads: async_reads.clone(),
+        completed_reads: Arc::new(Mutex::new(0)),
+    };
+    let vfat = VFat::<StdVFatHandle>::from(device).expect("valid image");
+    let mut file = (&vfat).open_file("/A.TXT").expect("file exists");
+    file.prefetch(usize::max_value()).expect("prefetched file");
+    assert_eq!(*async_reads.lock().unwrap(), 2);
+    // Already pending sectors are not read again.
+    file.prefetch(600).expect("prefetched file");
+    assert_eq!(*async_reads.lock().unwrap(), 2);
+
+    let mut read = Vec::new();
+    file.read_to_end(&mut read).expect("read file");
+    assert_eq!(read, data);
+    file.prefetch(1).expect("prefetch at the end of the file");
+    assert_eq!(*async_reads.lock().unwrap(), 2);
+}
+
+#[test]
+fn test_readahead() {
+    let mut image = populated_image();
+    // Grow A.TXT to four clusters: 3, 4, 6 and 7.
+    image[ROOT + 28..ROOT + 32].copy_from_slice(&2048u32.to_le_bytes());
+    set_fat(&mut image, 4, 6);
+    set_fat(&mut image, 6, 7);
+    set_fat(&mut image, 7, 0x0FFF_FFFF);
+    let data: Vec<u8> = (0..2048).map(|i| (i % 251) as u8).collect();
+    for (i, &sector) in [5, 6, 8, 9].iter().enumerate() {
+        let start = (1 + sector) * 512;
+        image[start..start + 512].copy_from_slice(&data[i * 512..(i + 1) * 512]);
+    }
+
+    let async_reads = Arc::new(Mutex::new(0));
+    let device = SlowDevice {
+        image: Cursor::new(image),
+        polled: std::collections::HashSet::new(),
+        async_reads: async_reads.clone(),
+        completed_reads: Arc::new(Mutex::new(0)),
+    };
+    let vfat = VFat::<StdVFatHandle>::from(device).expect("valid image");
+    let mut file = (&vfat).open_file("/A.TXT").expect("file exists");
+
+    // The first read is sequential and reads the rest of the file ahead.
+    let mut buf = [0u8; 512];
+    file.read_exact(&mut buf).expect("read file");
+    assert_eq!(&buf[..], &data[..512]);
+    assert_eq!(*async_reads.lock().unwrap(), 3);
+
+    let mut rest = Vec::new();
+    file.read_to_end(&mut rest).expect("read file");
+    assert_eq!(&rest[..], &data[512..]);
+    assert_eq!(*async_reads.lock().unwrap(), 3);
+
+    // A random read does not read ahead.
+    let mut file = (&vfat).open_file("/A.TXT").expect("file exists");
+    file.seek(io::SeekFrom::Start(100)).expect("seek");
+    file.read_exact(&mut buf[..10]).expect("read file");
+    assert_eq!(*async_reads.lock().unwrap(), 3);
+}
+
+#[test]
+fn test_drop_waits_for_prefetch() {
+    let async_reads = Arc::new(Mutex::new(0));
+    let completed_reads = Arc::new(Mutex::new(0));
+    let device = SlowDevice {
+        image: Cursor::new(populated_image()),
+        polled: std::collections::HashSet::new(),
+        async_reads: async_reads.clone(),
+        completed_reads: completed_reads.clone(),
+    };
+    let vfat = VFat::<StdVFatHandle>::from(device).expect("valid image");
+    let mut file = (&vfat).open_file("/A.TXT").expect("file exists");
+    file.prefetch(usize::max_value()).expect("prefetched file");
+    assert_eq!(*async_reads.lock().unwrap(), 2);
+    assert!(*completed_reads.lock().unwrap() < 2);
+
+    // The reads still in progress are waited for before the device goes.
+    drop(file);
+    drop(vfat);
+    assert_eq!(*completed_reads.lock().unwrap(), 2);
+}
+
+#[test]
+fn test_typed_errors() {
+    let vfat = VFat::<StdVFatHandle>::from(Cursor::new(populated_image())).expect("valid image");
+
+    expect_variant!((&vfat).open_file("/A.TXT"), Ok(_));
+    expect_variant!((&vfat).open_dir("/D"), Ok(_));
+    expect_variant!((&vfat).open_file("/D"), Err(FsError::NotAFile));
+    expect_variant!((&vfat).open_dir("/A.TXT"), Err(FsError::NotADirectory));
+    expect_variant!((&vfat).open_file("/A.TXT/B"), Err(FsError::NotADirectory));
+    expect_variant!((&vfat).open_file("/B.TXT"), Err(FsError::NotFound));
+    expect_variant!((&vfat).open_dir("/D/E"), Err(FsError::NotFound));
+
+    assert_eq!((&vfat).exists("/A.TXT").expect("lookup"), true);
+    assert_eq!((&vfat).exists("/D/E").expect("lookup"), false);
+    expect_variant!((&vfat).exists("/A.TXT/B"), Err(FsError::NotADirectory));
+    assert!(!(&vfat).metadata("/D").expect("metadata").read_only());
+
+    // `open` reports the same failures as `io::Error`s.
+    expect_variant!((&vfat).open("/B.TXT"), Err(ref e) if e.kind() == io::ErrorKind::NotFound);
+    expect_variant!((&vfat).open("/A.TXT/B"), Err(ref e) if e.kind() == io::ErrorKind::InvalidInput);
+    let e = io::Error::from(FsError::NotAFile);
+    assert_eq!(e.kind(), FsError::NotAFile.kind());
+}
+
+#[test]
+fn test_lookup_normalizes() {
+    let vfat = VFat::<StdVFatHandle>::from(Cursor::new(populated_image())).expect("valid image");
+
+    expect_variant!((&vfat).open_dir("/D/../D/."), Ok(_));
+    expect_variant!((&vfat).open_file("//D/..//./A.TXT"), Ok(_));
+    expect_variant!((&vfat).open_dir("/../.."), Ok(_));
+    expect_variant!((&vfat).open("D"), Err(ref e) if e.kind() == io::ErrorKind::InvalidInput);
+    expect_variant!((&vfat).open(""), Err(ref e) if e.kind() == io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_root_metadata() {
+    let vfat = VFat::<StdVFatHandle>::from(Cursor::new(populated_image())).expect("valid image");
+    assert_eq!(vfat.with(|vfat| vfat.volume_label().map(String::from)), None);
+
+    // Without a volume label record, the label comes from the EBPB and the
+    // root's timestamps are zero.
+    let mut image = populated_image();
+    image[512 + 71..512 + 82].copy_from_slice(b"EBPB LABEL ");
+    let vfat = VFat::<StdVFatHandle>::from(Cursor::new(image.clone())).expect("valid image");
+    assert_eq!(vfat.with(|vfat| vfat.volume_label().map(String::from)), Some("EBPB LABEL".to_string()));
+    let metadata = (&vfat).metadata("/").expect("root metadata");
+    assert_eq!(metadata.created().year(), 1980);
+    assert_eq!(metadata.created().day(), 0);
+
+    let record = &mut image[ROOT + 64..ROOT + 96];
+    record[..11].copy_from_slice(b"MY DISK    ");
+    record[11] = 0x08;
+    // 2019-06-02 12:30:10
+    record[14..16].copy_from_slice(&((12 << 11 | 30 << 5 | 5) as u16).to_le_bytes());
+    record[16..18].copy_from_slice(&((39 << 9 | 6 << 5 | 2) as u16).to_le_bytes());
+    let vfat = VFat::<StdVFatHandle>::from(Cursor::new(image)).expect("valid image");
+    assert_eq!(vfat.with(|vfat| vfat.volume_label().map(String::from)), Some("MY DISK".to_string()));
+
+    let root = (&vfat).open("/").expect("root exists");
+    assert!(root.is_dir());
+    let created = root.metadata().created();
+    assert_eq!((created.year(), created.month(), created.day()), (2019, 6, 2));
+    assert_eq!((created.hour(), created.minute(), created.second()), (12, 30, 10));
+    assert!(!root.metadata().read_only() && !root.metadata().hidden());
+}
+
+/// A device that counts the sectors read from and written to it.
+struct CountingDevice {
+    image: Cursor<Vec<u8>>,
+    reads: Arc<Mutex<usize>>,
+    writes: Arc<Mutex<usize>>,
+}
+
+impl BlockDevice for CountingDevice {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        *self.reads.lock().unwrap() += 1;
+        self.image.read_sector(n, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        *self.writes.lock().unwrap() += 1;
+        self.image.write_sector(n, buf)
+    }
+}
+
+#[test]
+fn test_mem_disk() {
+    let mut image = populated_image();
+    // A partial sector at the end is padded when loading.
+    image.extend_from_slice(&[0xAA; 100]);
+    let mut disk = MemDisk::load(Cursor::new(image), 512).expect("loaded image");
+    assert_eq!(disk.sectors(), 132);
+    let tail = &disk.as_bytes()[131 * 512..];
+    assert_eq!(&tail[..100], &[0xAA; 100][..]);
+    assert!(tail[100..].iter().all(|&b| b == 0));
+    let mut sector = [0u8; 512];
+    expect_variant!(disk.read_sector(132, &mut sector), Err(ref e) if e.kind() == io::ErrorKind::InvalidInput);
+
+    let vfat = VFat::<StdVFatHandle>::from(disk).expect("valid image");
+    let mut file = (&vfat).open_file("/A.TXT").expect("file exists");
+    file.write_all(b"in memory").expect("wrote file");
+    file.sync().expect("synced");
+
+    // A static buffer works too.
+    let buffer: &'static mut [u8] = Box::leak(populated_image().into_boxed_slice());
+    let vfat = VFat::<StdVFatHandle>::from(MemDisk::new(buffer, 512)).expect("valid image");
+    let root = (&vfat).open_dir("/").expect("root exists");
+    root.create_file("B.TXT").expect("created file").write_all(b"scratch").expect("wrote file");
+    let mut contents = String::new();
+    (&vfat).open_file("/B.TXT").expect("file exists").read_to_string(&mut contents).expect("read file");
+    assert_eq!(contents, "scratch");
+}
+
+#[test]
+fn test_format() {
+    use vfat::{format, FormatOptions, Layout};
+
+    expect_variant!(Layout::new(512, 34, &FormatOptions::default()), Err(ref e) if e.kind() == io::ErrorKind::InvalidInput);
+    let bad_clusters = FormatOptions { cluster_size: 3 * 512, ..FormatOptions::default() };
+    expect_variant!(Layout::new(512, 1 << 16, &bad_clusters), Err(ref e) if e.kind() == io::ErrorKind::InvalidInput);
+    // 8MiB has too few clusters for FAT32 even at the smallest size.
+    expect_variant!(Layout::new(512, 16 << 10, &FormatOptions::default()), Err(ref e) if e.kind() == io::ErrorKind::InvalidInput);
+    let layout = Layout::new(512, 16 << 20, &FormatOptions::default()).expect("8GiB volume");
+    assert_eq!(layout.sectors_per_cluster, 8);
+    assert!(layout.sectors_per_fat as u64 * 128 >= layout.clusters as u64 + 2);
+
+    // A 33MiB disk, about the smallest FAT32 volume with 512 byte clusters,
+    // with one partition from sector 1 as `blank_image()`.
+    const SECTORS: u64 = 66 << 10;
+    let mut disk = MemDisk::zeroed(SECTORS, 512);
+    let mut mbr = [0u8; 512];
+    mbr[446 + 4] = 0x0C;
+    mbr[446 + 8..446 + 12].copy_from_slice(&1u32.to_le_bytes());
+    mbr[446 + 12..446 + 16].copy_from_slice(&(SECTORS as u32 - 1).to_le_bytes());
+    mbr[510] = 0x55;
+    mbr[511] = 0xAA;
+    disk.write_sector(0, &mbr).unwrap();
+    // Leftovers of an earlier volume are wiped.
+    disk.write_sector(40, &[0xFF; 512]).unwrap();
+
+    let options = FormatOptions { volume_label: *b"SCRATCH    ", ..FormatOptions::default() };
+    let layout = format(&mut disk, 1, SECTORS - 1, &options).expect("formatted");
+    assert_eq!(&disk.as_bytes()[..512], &mbr[..]);
+    assert_eq!(layout.sectors_per_cluster, 1);
+    assert_eq!(layout.total_sectors as u64, SECTORS - 1);
+    assert_eq!(layout.clusters as u64, SECTORS - 1 - layout.data_start());
+
+    let sector = |disk: &MemDisk, n: usize| {
+        let mut buf = [0u8; 512];
+        buf.copy_from_slice(&disk.as_bytes()[n * 512..(n + 1) * 512]);
+        buf
+    };
+    let ebpb = BiosParameterBlock::parse(&sector(&disk, 1)).expect("valid EBPB");
+    assert_eq!({ ebpb.sectors_per_fat }, layout.sectors_per_fat);
+    assert_eq!(&sector(&disk, 1 + 6)[..], &sector(&disk, 1)[..]);
+    let fsinfo = sector(&disk, 1 + 1);
+    assert_eq!(&fsinfo[..4], b"RRaA");
+    assert_eq!(&fsinfo[484..488], b"rrAa");
+    assert_eq!(fsinfo[488..492], (layout.clusters - 1).to_le_bytes());
+    assert_eq!(&sector(&disk, 40)[..], &[0; 512][..]);
+
+    let vfat = VFat::<StdVFatHandle>::from(disk).expect("formatted volume mounts");
+    assert_eq!(vfat.with(|vfat| vfat.volume_label().map(String::from)), Some("SCRATCH".to_string()));
+    let root = (&vfat).open_dir("/").expect("root exists");
+    assert_eq!(root.entries().expect("entries").count(), 0);
+    let data: Vec<u8> = (0..20_000).map(|i| (i % 251) as u8).collect();
+    root.create_file("data.bin").expect("created file").write_all(&data).expect("wrote file");
+    root.create_dir("dir").expect("created directory");
+    let mut read = Vec::new();
+    (&vfat).open_file("/data.bin").expect("file exists").read_to_end(&mut read).expect("read file");
+    assert_eq!(read, data);
+    assert_eq!(root.entries().expect("entries").count(), 2);
+}
+
+#[test]
+fn test_direct_read() {
+    let mut image = populated_image();
+    // Grow A.TXT to eight clusters, 3 through 10, moving D out of the way.
+    image[ROOT + 28..ROOT + 32].copy_from_slice(&4096u32.to_le_bytes());
+    image[ROOT + 58..ROOT + 60].copy_from_slice(&11u16.to_le_bytes());
+    set_fat(&mut image, 11, 0x0FFF_FFFF);
+    for cluster in 3..10 {
+        set_fat(&mut image, cluster, cluster as u32 + 1);
+    }
+    set_fat(&mut image, 10, 0x0FFF_FFFF);
+    let data: Vec<u8> = (0..4096).map(|i| (i % 251) as u8).collect();
+    let start = (3 + 3) * 512;
+    image[start..start + 4096].copy_from_slice(&data);
+
+    let reads = Arc::new(Mutex::new(0));
+    let writes = Arc::new(Mutex::new(0));
+    let device = CountingDevice { image: Cursor::new(image), reads: reads.clone(), writes };
+    let vfat = VFat::<StdVFatHandle>::from(device).expect("valid image");
+    let mut file = (&vfat).open_file("/A.TXT").expect("file exists");
+
+    let mut buf = vec![0u8; 4096];
+    file.read_exact(&mut buf).expect("read file");
+    assert_eq!(buf, data);
+
+    // Large aligned reads leave nothing in the cache, so reading the file
+    // again goes to the device for each of its sectors.
+    let before = *reads.lock().unwrap();
+    file.seek(io::SeekFrom::Start(0)).expect("seek");
+    file.read_exact(&mut buf).expect("read file");
+    assert_eq!(buf, data);
+    assert_eq!(*reads.lock().unwrap() - before, 8);
+
+    // Data written but not yet flushed is read back, not the stale sector.
+    file.seek(io::SeekFrom::Start(0)).expect("seek");
+    file.write_all(&[0xAA; 512]).expect("wrote file");
+    file.seek(io::SeekFrom::Start(0)).expect("seek");
+    file.read_exact(&mut buf).expect("read file");
+    assert_eq!(&buf[..512], &[0xAA; 512][..]);
+    assert_eq!(&buf[512..], &data[512..]);
+}
+
+#[test]
+fn test_cache_stats() {
+    let vfat = VFat::<StdVFatHandle>::from(Cursor::new(populated_image())).expect("valid image");
+    let stats = || vfat.with(|vfat| vfat.cache_stats());
+    let mut file = (&vfat).open_file("/A.TXT").expect("file exists");
+    let mut buf = [0u8; 100];
+    file.read_exact(&mut buf).expect("read file");
+    let first = stats();
+    assert!(first.misses > 0);
+
+    // Reading the same sectors again is served from the cache.
+    file.seek(io::SeekFrom::Start(0)).expect("seek");
+    file.read_exact(&mut buf).expect("read file");
+    let second = stats();
+    assert_eq!(second.misses, first.misses);
+    assert!(second.hits > first.hits);
+    assert_eq!(second.dirty, 0);
+
+    file.write_all(b"dirty").expect("wrote file");
+    assert!(stats().dirty > 0);
+    vfat.with(|vfat| vfat.sync()).expect("synced");
+    assert_eq!(stats().dirty, 0);
+}
+
+#[test]
+fn test_mount_options() {
+    let options = MountOptions::default();
+    assert_eq!(options.to_string(), "rw,codepage=utf8");
+    let options = options.parse("ro, noatime,codepage=437").expect("valid options");
+    assert_eq!(options.to_string(), "ro,noatime,codepage=437");
+    assert_eq!(options.parse("rw").expect("valid options").to_string(), "rw,noatime,codepage=437");
+    expect_variant!(options.parse("sync"), Err(ref e) if e.kind() == io::ErrorKind::InvalidInput);
+
+    let mut image = populated_image();
+    // A short name with code page 437 bytes: "CAFé" ("CAF\x82").
+    image[ROOT + 64..ROOT + 75].copy_from_slice(b"CAF\x82       ");
+    image[ROOT + 75] = 0x20;
+    let writes = Arc::new(Mutex::new(0));
+    let device = CountingDevice {
+        image: Cursor::new(image),
+        reads: Arc::new(Mutex::new(0)),
+        writes: writes.clone(),
+    };
+    let vfat = VFat::<StdVFatHandle>::from(device).expect("valid image");
+    let root = (&vfat).open_dir("/").expect("root exists");
+    let names = |root: &vfat::Dir<StdVFatHandle>| -> Vec<String> {
+        root.entries().unwrap().map(|e| e.name().to_string()).collect()
+    };
+    assert_eq!(names(&root), ["A.TXT", "D"]);
+
+    let mut file = (&vfat).open_file("/A.TXT").expect("file exists");
+    file.write_all(b"cached").expect("wrote file");
+    assert_eq!(*writes.lock().unwrap(), 0);
+
+    // Remounting read-only writes back what is cached, then refuses writes.
+    let read_only = MountOptions::default().parse("ro,codepage=437").unwrap();
+    vfat.with(|vfat| vfat.remount(read_only)).expect("remounted");
+    let flushed = *writes.lock().unwrap();
+    assert!(flushed > 0);
+    assert_eq!(names(&root), ["A.TXT", "D", "CAFé"]);
+    expect_variant!(file.write_all(b"more"), Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied);
+    expect_variant!(root.create_file("B.TXT"), Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied);
+    expect_variant!(root.create_dir("E"), Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied);
+    expect_variant!(root.remove("A.TXT"), Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied);
+    expect_variant!(root.rename("A.TXT", "B.TXT"), Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied);
+    vfat.with(|vfat| vfat.sync()).expect("synced");
+    assert_eq!(*writes.lock().unwrap(), flushed);
+    expect_variant!((&vfat).open_file("/A.TXT"), Ok(_));
+
+    vfat.with(|vfat| vfat.remount(MountOptions::default())).expect("remounted");
+    root.create_file("B.TXT").expect("created file");
+}
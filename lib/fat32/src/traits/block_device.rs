@@ -2,6 +2,66 @@ use alloc::boxed::Box;
 use alloc::vec::Vec;
 use shim::io;
 
+/// A sector read started with `BlockDevice::read_sector_async()`.
+///
+/// The read owns its buffer until it completes, since the device may still
+/// be writing to it. Pass it to `BlockDevice::poll_read()` to make progress
+/// without blocking, or to `BlockDevice::wait_read()` to collect the buffer.
+/// A read must not be dropped before it completes.
+#[derive(Debug)]
+pub struct AsyncRead {
+    /// The sector being read.
+    pub sector: u64,
+    buf: Vec<u8>,
+    /// The number of bytes read or the error, once the read has completed.
+    result: Option<io::Result<usize>>,
+}
+
+impl AsyncRead {
+    /// Returns an in-progress read of sector `sector` into `buf`.
+    pub fn pending(sector: u64, buf: Vec<u8>) -> AsyncRead {
+        AsyncRead { sector, buf, result: None }
+    }
+
+    /// Returns a read of sector `sector` into `buf` that completed with
+    /// `result`.
+    pub fn completed(sector: u64, buf: Vec<u8>, result: io::Result<usize>) -> AsyncRead {
+        AsyncRead { sector, buf, result: Some(result) }
+    }
+
+    /// Records that the read completed with `result`.
+    pub fn complete(&mut self, result: io::Result<usize>) {
+        self.result = Some(result);
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.result.is_some()
+    }
+
+    /// Returns the buffer being read into, e.g. for the device to address
+    /// it. Its contents are undefined until the read completes.
+    pub fn buffer(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+
+    /// Returns the filled buffer, truncated to the bytes read, or the error
+    /// the read failed with.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the read has not completed.
+    pub fn into_result(self) -> io::Result<Vec<u8>> {
+        let mut buf = self.buf;
+        match self.result.expect("AsyncRead::into_result(): read in progress") {
+            Ok(read) => {
+                buf.truncate(read);
+                Ok(buf)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
 /// Trait implemented by devices that can be read/written in sector
 /// granularities.
 pub trait BlockDevice: Send {
@@ -10,6 +70,12 @@ pub trait BlockDevice: Send {
         512
     }
 
+    /// The number of sectors on the device, or `None` if it is not known.
+    /// Defaults to `None`.
+    fn num_sectors(&self) -> Option<u64> {
+        None
+    }
+
     /// Read sector number `n` into `buf`.
     ///
     /// `self.sector_size()` or `buf.len()` bytes, whichever is less, are read
@@ -56,9 +122,48 @@ pub trait BlockDevice: Send {
     /// error of `UnexpectedEof` if the length of `buf` is less than
     /// `self.sector_size()`.
     fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize>;
+
+    /// Starts reading sector `n` into `buf`, which is resized to
+    /// `self.sector_size()` bytes, and returns the read without waiting for
+    /// it to complete.
+    ///
+    /// Errors are reported when the read completes. The default
+    /// implementation reads synchronously and returns a completed read;
+    /// devices that can transfer in the background, e.g. with DMA, override
+    /// it together with `poll_read()`.
+    fn read_sector_async(&mut self, n: u64, mut buf: Vec<u8>) -> AsyncRead {
+        buf.resize(self.sector_size() as usize, 0);
+        let result = self.read_sector(n, &mut buf);
+        AsyncRead::completed(n, buf, result)
+    }
+
+    /// Makes progress on `read`, which must have been started by
+    /// `read_sector_async()` on `self`, without blocking. Returns `true` once
+    /// it has completed.
+    fn poll_read(&mut self, read: &mut AsyncRead) -> bool {
+        read.is_complete()
+    }
+
+    /// Blocks until `read` completes and returns its filled buffer.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error the read failed with.
+    fn wait_read(&mut self, mut read: AsyncRead) -> io::Result<Vec<u8>> {
+        while !self.poll_read(&mut read) {}
+        read.into_result()
+    }
 }
 
 impl<'a, T: BlockDevice> BlockDevice for &'a mut T {
+    fn sector_size(&self) -> u64 {
+        (**self).sector_size()
+    }
+
+    fn num_sectors(&self) -> Option<u64> {
+        (**self).num_sectors()
+    }
+
     fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
         (*self).read_sector(n, buf)
     }
@@ -66,6 +171,14 @@ impl<'a, T: BlockDevice> BlockDevice for &'a mut T {
     fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
         (*self).write_sector(n, buf)
     }
+
+    fn read_sector_async(&mut self, n: u64, buf: Vec<u8>) -> AsyncRead {
+        (*self).read_sector_async(n, buf)
+    }
+
+    fn poll_read(&mut self, read: &mut AsyncRead) -> bool {
+        (*self).poll_read(read)
+    }
 }
 
 macro impl_for_read_write_seek($(<$($gen:tt),*>)* $T:path) {
@@ -0,0 +1,74 @@
+use core::fmt;
+
+use shim::io;
+
+/// The reasons a `FileSystem` path operation can fail.
+///
+/// Each variant maps to an `io::ErrorKind` so that it converts losslessly
+/// into an `io::Error` for callers that only deal in those.
+#[derive(Debug)]
+pub enum FsError {
+    /// There is no entry at the path.
+    NotFound,
+    /// A component of the path that must be a directory is a file.
+    NotADirectory,
+    /// The entry at the path is a directory, not a regular file.
+    NotAFile,
+    /// The on-disk structures are inconsistent.
+    Corrupt,
+    /// Any other error, including from the underlying device.
+    Io(io::Error),
+}
+
+impl FsError {
+    /// Returns the `io::ErrorKind` that `self` converts to.
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            FsError::NotFound => io::ErrorKind::NotFound,
+            FsError::NotADirectory => io::ErrorKind::InvalidInput,
+            FsError::NotAFile => io::ErrorKind::Other,
+            FsError::Corrupt => io::ErrorKind::InvalidData,
+            FsError::Io(e) => e.kind(),
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match self {
+            FsError::NotFound => "no such file or directory",
+            FsError::NotADirectory => "not a directory",
+            FsError::NotAFile => "not a regular file",
+            FsError::Corrupt => "file system is corrupt",
+            FsError::Io(_) => "I/O error",
+        }
+    }
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FsError::Io(e) => write!(f, "{:?}", e),
+            other => write!(f, "{}", other.message()),
+        }
+    }
+}
+
+/// Classifies `NotFound` and `InvalidData` errors; everything else is kept
+/// as `Io`.
+impl From<io::Error> for FsError {
+    fn from(error: io::Error) -> FsError {
+        match error.kind() {
+            io::ErrorKind::NotFound => FsError::NotFound,
+            io::ErrorKind::InvalidData => FsError::Corrupt,
+            _ => FsError::Io(error),
+        }
+    }
+}
+
+impl From<FsError> for io::Error {
+    fn from(error: FsError) -> io::Error {
+        match error {
+            FsError::Io(e) => e,
+            other => io::Error::new(other.kind(), other.message()),
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use shim::{io, path::Path};
 
-use crate::traits::Metadata;
+use crate::traits::{FsError, Metadata};
 
 /// Trait implemented by files in the file system.
 pub trait File: io::Read + io::Write + io::Seek + Sized {
@@ -76,41 +76,74 @@ pub trait FileSystem: Sized {
     /// The type of directory entries in this file system.
     type Entry: Entry<File = Self::File, Dir = Self::Dir>;
 
-    /// Opens the entry at `path`. `path` must be absolute.
+    /// Looks up the entry at `path`. `path` must be absolute.
     ///
     /// # Errors
     ///
-    /// If `path` is not absolute, an error kind of `InvalidInput` is returned.
+    /// If any component but the last in `path` is a file, `NotADirectory` is
+    /// returned.
+    ///
+    /// If there is no entry at `path`, `NotFound` is returned.
     ///
-    /// If any component but the last in `path` does not refer to an existing
-    /// directory, an error kind of `InvalidInput` is returned.
+    /// If `path` is not absolute, `Io` with an error kind of `InvalidInput`
+    /// is returned. All other error values are implementation defined.
+    fn lookup<P: AsRef<Path>>(self, path: P) -> Result<Self::Entry, FsError>;
+
+    /// Opens the entry at `path`. `path` must be absolute.
     ///
-    /// If there is no entry at `path`, an error kind of `NotFound` is returned.
+    /// # Errors
     ///
-    /// All other error values are implementation defined.
-    fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Entry>;
+    /// The errors of `lookup()`, converted to `io::Error`s: `NotFound` has an
+    /// error kind of `NotFound` and `NotADirectory` one of `InvalidInput`.
+    fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Entry> {
+        self.lookup(path).map_err(io::Error::from)
+    }
 
     /// Opens the file at `path`. `path` must be absolute.
     ///
     /// # Errors
     ///
-    /// In addition to the error conditions for `open()`, this method returns an
-    /// error kind of `Other` if the entry at `path` is not a regular file.
-    fn open_file<P: AsRef<Path>>(self, path: P) -> io::Result<Self::File> {
-        self.open(path)?
-            .into_file()
-            .ok_or(io::Error::new(io::ErrorKind::Other, "not a regular file"))
+    /// In addition to the errors of `lookup()`, this method returns
+    /// `NotAFile` if the entry at `path` is a directory.
+    fn open_file<P: AsRef<Path>>(self, path: P) -> Result<Self::File, FsError> {
+        self.lookup(path)?.into_file().ok_or(FsError::NotAFile)
     }
 
     /// Opens the directory at `path`. `path` must be absolute.
     ///
     /// # Errors
     ///
-    /// In addition to the error conditions for `open()`, this method returns an
-    /// error kind of `Other` if the entry at `path` is not a directory.
-    fn open_dir<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Dir> {
-        self.open(path)?
-            .into_dir()
-            .ok_or(io::Error::new(io::ErrorKind::Other, "not a directory"))
+    /// In addition to the errors of `lookup()`, this method returns
+    /// `NotADirectory` if the entry at `path` is a file.
+    fn open_dir<P: AsRef<Path>>(self, path: P) -> Result<Self::Dir, FsError> {
+        self.lookup(path)?.into_dir().ok_or(FsError::NotADirectory)
+    }
+
+    /// Returns the metadata of the entry at `path`.
+    ///
+    /// # Errors
+    ///
+    /// The errors of `lookup()`.
+    fn metadata<P: AsRef<Path>>(
+        self,
+        path: P,
+    ) -> Result<<Self::Entry as Entry>::Metadata, FsError>
+    where
+        <Self::Entry as Entry>::Metadata: Clone,
+    {
+        Ok(self.lookup(path)?.metadata().clone())
+    }
+
+    /// Returns whether there is an entry at `path`.
+    ///
+    /// # Errors
+    ///
+    /// The errors of `lookup()` other than `NotFound`.
+    fn exists<P: AsRef<Path>>(self, path: P) -> Result<bool, FsError> {
+        match self.lookup(path) {
+            Ok(_) => Ok(true),
+            Err(FsError::NotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
     }
 }
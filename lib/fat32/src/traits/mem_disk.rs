@@ -0,0 +1,113 @@
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::ops::Range;
+
+use shim::io;
+use shim::ioerr;
+
+use crate::traits::BlockDevice;
+
+/// A block device held in memory: a RAM disk, or a disk image for tests.
+///
+/// The device's sectors are the whole sectors of a buffer, which may be a
+/// `Vec<u8>` or a static `&mut [u8]`. Writes change the buffer directly.
+#[derive(Debug)]
+pub struct MemDisk<B = Vec<u8>> {
+    buf: B,
+    sector_size: u64,
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> MemDisk<B> {
+    /// Returns a device whose sectors are `buf`, `sector_size` bytes each. A
+    /// partial sector at the end of `buf` is not part of the device.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sector_size` is not a multiple of 512.
+    pub fn new(buf: B, sector_size: u64) -> MemDisk<B> {
+        assert!(sector_size >= 512 && sector_size % 512 == 0, "invalid sector size");
+        MemDisk { buf, sector_size }
+    }
+
+    /// Returns the number of sectors on the device.
+    pub fn sectors(&self) -> u64 {
+        self.buf.as_ref().len() as u64 / self.sector_size
+    }
+
+    /// Returns the contents of the device.
+    pub fn as_bytes(&self) -> &[u8] {
+        let len = (self.sectors() * self.sector_size) as usize;
+        &self.buf.as_ref()[..len]
+    }
+
+    /// Returns the buffer holding the device's sectors.
+    pub fn into_inner(self) -> B {
+        self.buf
+    }
+
+    /// Returns the range of `buf` holding sector `n`.
+    fn sector(&self, n: u64) -> io::Result<Range<usize>> {
+        if n >= self.sectors() {
+            return ioerr!(InvalidInput, "sector out of range");
+        }
+        let start = (n * self.sector_size) as usize;
+        Ok(start..start + self.sector_size as usize)
+    }
+}
+
+impl MemDisk<Vec<u8>> {
+    /// Returns a device of `sectors` zeroed sectors of `sector_size` bytes.
+    pub fn zeroed(sectors: u64, sector_size: u64) -> MemDisk {
+        MemDisk::new(vec![0; (sectors * sector_size) as usize], sector_size)
+    }
+
+    /// Returns a device holding the disk image read from `image` to its end,
+    /// with the last sector padded with zeros.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading `image` fails.
+    pub fn load<R: io::Read>(mut image: R, sector_size: u64) -> io::Result<MemDisk> {
+        let mut buf = Vec::new();
+        let mut len = 0;
+        loop {
+            if len == buf.len() {
+                buf.resize(len + sector_size as usize, 0);
+            }
+            match image.read(&mut buf[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        let sector = sector_size as usize;
+        buf.truncate((len + sector - 1) / sector * sector);
+        Ok(MemDisk::new(buf, sector_size))
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]> + Send> BlockDevice for MemDisk<B> {
+    fn sector_size(&self) -> u64 {
+        self.sector_size
+    }
+
+    fn num_sectors(&self) -> Option<u64> {
+        Some(self.sectors())
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let sector = &self.buf.as_ref()[self.sector(n)?];
+        let len = min(sector.len(), buf.len());
+        buf[..len].copy_from_slice(&sector[..len]);
+        Ok(len)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let range = self.sector(n)?;
+        let sector = &mut self.buf.as_mut()[range];
+        let len = min(sector.len(), buf.len());
+        sector[..len].copy_from_slice(&buf[..len]);
+        Ok(len)
+    }
+}
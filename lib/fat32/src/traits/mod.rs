@@ -1,9 +1,13 @@
 mod block_device;
 mod dummy;
+mod error;
 mod fs;
+mod mem_disk;
 mod metadata;
 
-pub use self::block_device::BlockDevice;
+pub use self::block_device::{AsyncRead, BlockDevice};
 pub use self::dummy::Dummy;
+pub use self::error::FsError;
 pub use self::fs::{Dir, Entry, File, FileSystem};
+pub use self::mem_disk::MemDisk;
 pub use self::metadata::{Metadata, Timestamp};
@@ -1,7 +1,23 @@
 use alloc::vec::Vec;
+use core::convert::TryFrom;
 use core::mem::{align_of, forget, size_of};
 use core::slice::{from_raw_parts, from_raw_parts_mut};
 
+use shim::io;
+use shim::newioerr;
+
+/// Returns the file offset or size `n` as a `usize`, or an `InvalidInput`
+/// error if it does not fit, as on a 32-bit target.
+pub fn checked_usize(n: u64) -> io::Result<usize> {
+    usize::try_from(n).map_err(|_| newioerr!(InvalidInput, "offset does not fit in memory"))
+}
+
+/// Returns `n` as a `usize`, or `usize::max_value()` if it does not fit: for
+/// limiting a buffer's length by a file size.
+pub fn saturating_usize(n: u64) -> usize {
+    usize::try_from(n).unwrap_or(usize::max_value())
+}
+
 pub trait VecExt {
     /// Casts a `Vec<T>` into a `Vec<U>`.
     ///
@@ -1,10 +1,11 @@
 use alloc::boxed::Box;
 use alloc::vec::Vec;
 use core::fmt;
+use core::ops::Range;
 use hashbrown::HashMap;
 use shim::io;
 
-use crate::traits::BlockDevice;
+use crate::traits::{AsyncRead, BlockDevice};
 
 #[derive(Debug)]
 struct CacheEntry {
@@ -12,6 +13,19 @@ struct CacheEntry {
     dirty: bool,
 }
 
+/// How the sector cache has been used since the partition was opened.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// The sectors cached, and those of them modified since they were last
+    /// written back.
+    pub sectors: usize,
+    pub dirty: usize,
+    /// The sector accesses served from the cache, including sectors that
+    /// were being prefetched, and those that had to read the disk.
+    pub hits: u64,
+    pub misses: u64,
+}
+
 pub struct Partition {
     /// The physical sector where the partition begins.
     pub start: u64,
@@ -24,7 +38,11 @@ pub struct Partition {
 pub struct CachedPartition {
     device: Box<dyn BlockDevice>,
     cache: HashMap<u64, CacheEntry>,
+    /// Sectors being prefetched: the reads of each one's physical sectors.
+    pending: HashMap<u64, Vec<AsyncRead>>,
     partition: Partition,
+    hits: u64,
+    misses: u64,
 }
 
 impl CachedPartition {
@@ -53,7 +71,10 @@ impl CachedPartition {
         CachedPartition {
             device: Box::new(device),
             cache: HashMap::new(),
+            pending: HashMap::new(),
             partition: partition,
+            hits: 0,
+            misses: 0,
         }
     }
 
@@ -76,6 +97,13 @@ impl CachedPartition {
         Some(physical_sector)
     }
 
+    /// Returns the physical sectors that make up the logical sector `virt`, or
+    /// `None` if the virtual sector number is out of range.
+    pub fn physical_sectors(&self, virt: u64) -> Option<Range<u64>> {
+        let start = self.virtual_to_physical(virt)?;
+        Some(start..start + self.factor())
+    }
+
     /// Returns a mutable reference to the cached sector `sector`. If the sector
     /// is not already cached, the sector is first read from the disk.
     ///
@@ -93,8 +121,76 @@ impl CachedPartition {
         return Ok(&mut cache_ent.data)
     }
 
+    /// Starts reading the sectors in `sectors` that are neither cached nor
+    /// already being read, without waiting for them. Later accesses to them
+    /// wait only for the reads still in progress.
+    ///
+    /// Out-of-range sectors are ignored. Read errors are reported when the
+    /// sector is accessed.
+    pub fn prefetch(&mut self, sectors: Range<u64>) {
+        let device_sector_size = self.device.sector_size() as usize;
+        for sector in sectors {
+            if self.cache.contains_key(&sector) || self.pending.contains_key(&sector) {
+                continue;
+            }
+            let physical = match self.physical_sectors(sector) {
+                Some(physical) => physical,
+                None => break,
+            };
+            let reads = physical
+                .map(|n| self.device.read_sector_async(n, Vec::with_capacity(device_sector_size)))
+                .collect();
+            self.pending.insert(sector, reads);
+        }
+        self.poll_pending();
+    }
+
+    /// Moves prefetched sectors whose reads have all completed into the
+    /// cache. Never blocks.
+    fn poll_pending(&mut self) {
+        let device = &mut self.device;
+        let mut complete = Vec::new();
+        for (&sector, reads) in self.pending.iter_mut() {
+            if reads.iter_mut().all(|read| device.poll_read(read)) {
+                complete.push(sector);
+            }
+        }
+        for sector in complete {
+            // Failed reads are retried, and their errors reported, when the
+            // sector is accessed.
+            let _ = self.finish_pending(sector);
+        }
+    }
+
+    /// Waits for the reads of the prefetched sector `sector` and caches it.
+    fn finish_pending(&mut self, sector: u64) -> io::Result<()> {
+        let reads = match self.pending.remove(&sector) {
+            Some(reads) => reads,
+            None => return Ok(()),
+        };
+        let mut data = Vec::new();
+        let mut result = Ok(());
+        for read in reads {
+            // Every read must be waited for, even after an error, since the
+            // device owns their buffers until they complete.
+            match self.device.wait_read(read) {
+                Ok(buf) => data.extend_from_slice(&buf),
+                Err(e) => result = result.and(Err(e)),
+            }
+        }
+        result?;
+        self.cache.insert(sector, CacheEntry { data, dirty: false });
+        Ok(())
+    }
+
     fn read_into_cache(&mut self, sector: u64) -> io::Result<()> {
-        if !self.cache.contains_key(&sector) {
+        if self.pending.contains_key(&sector) {
+            self.finish_pending(sector)?;
+        }
+        if self.cache.contains_key(&sector) {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
             let mut v = Vec::new();
             self.read_all_sector(sector, &mut v)?;
             self.cache.insert(sector, CacheEntry {
@@ -105,6 +201,57 @@ impl CachedPartition {
         Ok(())
     }
 
+    /// Returns how the cache has been used.
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            sectors: self.cache.len(),
+            dirty: self.cache.values().filter(|entry| entry.dirty).count(),
+            hits: self.hits,
+            misses: self.misses,
+        }
+    }
+
+    /// Writes every dirty cached sector back to the disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is an error writing a sector to the disk.
+    /// Sectors that were not written stay dirty.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let (start, factor) = (self.partition.start, self.factor());
+        let device_sector_size = self.device.sector_size() as usize;
+        for (&sector, entry) in self.cache.iter_mut() {
+            if !entry.dirty {
+                continue;
+            }
+            for i in 0..factor {
+                let offset = i as usize * device_sector_size;
+                let data = &entry.data[offset..offset + device_sector_size];
+                self.device.write_sector(start + sector * factor + i, data)?;
+            }
+            entry.dirty = false;
+        }
+        Ok(())
+    }
+
+    /// Reads sector `sector` into `buf` without caching it. The sector comes
+    /// straight from the device unless it is cached or being prefetched, in
+    /// which case it is copied from the cache, which may be newer than the
+    /// disk. Returns the number of bytes read.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is an error reading the sector from the disk.
+    pub fn read_direct(&mut self, sector: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.cache.contains_key(&sector) && !self.pending.contains_key(&sector) {
+            return self.read_sector(sector, buf);
+        }
+        let cached = self.get(sector)?;
+        let n = cached.len().min(buf.len());
+        buf[..n].copy_from_slice(&cached[..n]);
+        Ok(n)
+    }
+
     /// Returns a reference to the cached sector `sector`. If the sector is not
     /// already cached, the sector is first read from the disk.
     ///
@@ -135,17 +282,24 @@ impl BlockDevice for CachedPartition {
     }
 
     fn write_sector(&mut self, sector: u64, buf: &[u8]) -> io::Result<usize> {
-        let mut ctr = 0;
-        if let Some(ps) = self.virtual_to_physical(sector) {
-            for i in 0..self.factor() {
-                let physical_sector = self.get_mut(ps + i)?;
-                for j in 0..physical_sector.len() {
-                    physical_sector[j] = buf[ctr];
-                    ctr += 1;
-                }
+        if self.virtual_to_physical(sector).is_none() {
+            return Ok(0);
+        }
+        let cached = self.get_mut(sector)?;
+        let n = cached.len().min(buf.len());
+        cached[..n].copy_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl Drop for CachedPartition {
+    fn drop(&mut self) {
+        // The device owns the buffers of prefetch reads until they complete.
+        for (_, reads) in self.pending.drain() {
+            for read in reads {
+                let _ = self.device.wait_read(read);
             }
         }
-        Ok(ctr)
     }
 }
 
@@ -0,0 +1,93 @@
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+
+use crate::vfat::Cluster;
+
+/// A run of consecutive clusters in a chain.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Extent {
+    /// The position in the chain of the run's first cluster.
+    index: usize,
+    first: Cluster,
+    len: usize,
+}
+
+/// The clusters of a chain found so far, so that reaching a cluster far into
+/// a chain does not take a walk of the FAT from its start every time. Runs of
+/// consecutive clusters, as a file written to a fresh volume has, take one
+/// entry each.
+///
+/// The index only grows as the FAT is walked further. Clusters appended to
+/// the chain are found when they are reached; if the chain is cut short, the
+/// index must be cleared.
+#[derive(Clone, Debug, Default)]
+pub struct ChainIndex {
+    /// The chain's first cluster.
+    start: Option<Cluster>,
+    extents: Vec<Extent>,
+}
+
+impl ChainIndex {
+    pub fn new() -> ChainIndex {
+        ChainIndex::default()
+    }
+
+    /// Makes this the index of the chain starting at `start`, forgetting what
+    /// was found of any other chain.
+    pub fn select(&mut self, start: Cluster) {
+        if self.start != Some(start) {
+            self.clear();
+            self.start = Some(start);
+        }
+    }
+
+    /// Forgets the clusters found so far.
+    pub fn clear(&mut self) {
+        self.start = None;
+        self.extents.clear();
+    }
+
+    /// Returns the number of clusters found so far, all from the chain's
+    /// start.
+    pub fn len(&self) -> usize {
+        self.extents.last().map_or(0, |e| e.index + e.len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.extents.is_empty()
+    }
+
+    /// Returns the cluster at position `index` in the chain, if it has been
+    /// found.
+    pub fn get(&self, index: usize) -> Option<Cluster> {
+        let found = self.extents.binary_search_by(|e| {
+            if e.index + e.len <= index {
+                Ordering::Less
+            } else if e.index > index {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        });
+        let extent = self.extents[found.ok()?];
+        Some(Cluster::from(extent.first.get_value() + (index - extent.index) as u32))
+    }
+
+    /// Records `cluster` as the next cluster of the chain, after the `len()`
+    /// found so far.
+    pub fn push(&mut self, cluster: Cluster) {
+        let index = self.len();
+        if let Some(last) = self.extents.last_mut() {
+            if last.first.get_value() as usize + last.len == cluster.get_value() as usize {
+                last.len += 1;
+                return;
+            }
+        }
+        self.extents.push(Extent { index, first: cluster, len: 1 });
+    }
+
+    /// Returns the number of runs of consecutive clusters found.
+    pub fn extents(&self) -> usize {
+        self.extents.len()
+    }
+}
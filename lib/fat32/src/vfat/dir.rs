@@ -1,4 +1,4 @@
-use alloc::string::String;
+use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 
 use core::char::{decode_utf16, REPLACEMENT_CHARACTER};
@@ -8,10 +8,37 @@ use shim::ffi::OsStr;
 use shim::io;
 use shim::newioerr;
 
+use crate::le::{Reader, Writer};
 use crate::traits;
-use crate::util::VecExt;
-use crate::vfat::{Attributes, Metadata};
-use crate::vfat::{Cluster, Entry, File, VFatHandle};
+use crate::vfat::metadata::{case_flags, ATTR_ARCHIVE, ATTR_DIRECTORY, ATTR_VOLUME_ID};
+use crate::vfat::{Attributes, Codepage, Metadata};
+use crate::vfat::{ChainIndex, Cluster, Entry, File, VFat, VFatHandle};
+
+/// The size of an on-disk directory record.
+const RECORD_SIZE: usize = 32;
+
+/// The first byte of a deleted directory record.
+const DELETED: u8 = 0xE5;
+
+/// Returns the name and metadata of the volume label record among the root
+/// directory records `records`, if there is one.
+pub(crate) fn volume_label_record(records: &[u8]) -> Option<([u8; 11], Metadata)> {
+    for record in records.chunks_exact(RECORD_SIZE) {
+        if record[0] == 0 {
+            break;
+        }
+        // Long file name records also have the volume label bit set.
+        if record[0] == DELETED || record[11] == 0xf || record[11] & ATTR_VOLUME_ID == 0 {
+            continue;
+        }
+        let entry = VFatRegularDirEntry::parse(record);
+        let mut label = [0; 11];
+        label[..8].copy_from_slice(&entry.file_name);
+        label[8..].copy_from_slice(&entry.file_extension);
+        return Some((label, entry.metadata));
+    }
+    None
+}
 
 #[derive(Debug)]
 pub struct Dir<HANDLE: VFatHandle> {
@@ -19,6 +46,120 @@ pub struct Dir<HANDLE: VFatHandle> {
     pub first_cluster: Cluster,
     pub name: String,
     pub metadata: Metadata,
+    /// Where the directory's records are in its parent; `None` for the root.
+    pub location: Option<EntryLocation>,
+}
+
+/// The location of an entry's records in its parent directory: records
+/// `start..end` of the directory starting at cluster `dir`, which are the
+/// entry's long file name records followed by its regular record.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct EntryLocation {
+    pub dir: Cluster,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl EntryLocation {
+    /// Returns the byte offset of the regular record in the directory.
+    fn regular_offset(&self) -> usize {
+        (self.end - 1) * RECORD_SIZE
+    }
+
+    /// Records `cluster` and `size` as the entry's first cluster and size.
+    pub fn set_cluster_and_size<HANDLE: VFatHandle>(
+        &self,
+        vfat: &VFat<HANDLE>,
+        cluster: Cluster,
+        size: u32
+    ) -> io::Result<()> {
+        let mut dir = self.dir;
+        let offset = self.regular_offset();
+        let cluster = cluster.get_value();
+        vfat.write_chain(&mut dir, offset + 20, &((cluster >> 16) as u16).to_le_bytes())?;
+        let mut tail = [0; 6];
+        tail[..2].copy_from_slice(&(cluster as u16).to_le_bytes());
+        tail[2..].copy_from_slice(&size.to_le_bytes());
+        vfat.write_chain(&mut dir, offset + 26, &tail)?;
+        Ok(())
+    }
+
+    /// Replaces the entry's name with the short name `name` and the case
+    /// flags `case`, dropping any long file name records.
+    fn rename<HANDLE: VFatHandle>(
+        &self,
+        vfat: &VFat<HANDLE>,
+        name: [u8; 11],
+        case: u8
+    ) -> io::Result<()> {
+        let mut dir = self.dir;
+        for i in self.start..self.end - 1 {
+            vfat.write_chain(&mut dir, i * RECORD_SIZE, &[DELETED])?;
+        }
+        vfat.write_chain(&mut dir, self.regular_offset(), &name)?;
+        vfat.write_chain(&mut dir, self.regular_offset() + 12, &[case])?;
+        Ok(())
+    }
+
+    /// Marks all of the entry's records as deleted.
+    fn delete<HANDLE: VFatHandle>(&self, vfat: &VFat<HANDLE>) -> io::Result<()> {
+        let mut dir = self.dir;
+        for i in self.start..self.end {
+            vfat.write_chain(&mut dir, i * RECORD_SIZE, &[DELETED])?;
+        }
+        Ok(())
+    }
+}
+
+/// Converts one part of an 8.3 name into `out`, uppercased. Returns whether
+/// the part is lowercase, or `None` if it has invalid characters or mixes
+/// cases, which a short name cannot represent.
+fn short_name_part(part: &str, out: &mut [u8]) -> Option<bool> {
+    let (mut upper, mut lower) = (false, false);
+    for (i, byte) in part.bytes().enumerate() {
+        match byte {
+            b'a'..=b'z' => lower = true,
+            b'A'..=b'Z' => upper = true,
+            b'0'..=b'9' | b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'(' | b')'
+            | b'-' | b'@' | b'^' | b'_' | b'`' | b'{' | b'}' | b'~' => {}
+            _ => return None,
+        }
+        out[i] = byte.to_ascii_uppercase();
+    }
+    if upper && lower {
+        None
+    } else {
+        Some(lower)
+    }
+}
+
+/// Converts `name` to the space-padded short name of a regular record and
+/// whether its base name and extension are lowercase. Returns `None` if
+/// `name` cannot be stored without long file name records.
+fn short_name(name: &str) -> Option<([u8; 11], bool, bool)> {
+    let (base, ext) = match name.rfind('.') {
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None => (name, ""),
+    };
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 || name.ends_with('.') {
+        return None;
+    }
+    let mut short = [b' '; 11];
+    let lower_base = short_name_part(base, &mut short[..8])?;
+    let lower_ext = short_name_part(ext, &mut short[8..])?;
+    Some((short, lower_base, lower_ext))
+}
+
+/// Returns the on-disk regular record for an entry with the short name
+/// `name`.
+fn regular_record(name: [u8; 11], metadata: Metadata, file_size: u32) -> [u8; RECORD_SIZE] {
+    let mut file_name = [0; 8];
+    let mut file_extension = [0; 3];
+    file_name.copy_from_slice(&name[..8]);
+    file_extension.copy_from_slice(&name[8..]);
+    let mut record = [0; RECORD_SIZE];
+    VFatRegularDirEntry { file_name, file_extension, metadata, file_size }.write(&mut record);
+    record
 }
 
 #[repr(C, packed)]
@@ -32,6 +173,31 @@ pub struct VFatRegularDirEntry {
 
 const_assert_size!(VFatRegularDirEntry, 32);
 
+impl VFatRegularDirEntry {
+    fn parse(record: &[u8]) -> VFatRegularDirEntry {
+        let mut reader = Reader::new(record);
+        let mut entry = VFatRegularDirEntry {
+            file_name: [0; 8],
+            file_extension: [0; 3],
+            metadata: Metadata::default(),
+            file_size: 0,
+        };
+        reader.bytes(&mut entry.file_name);
+        reader.bytes(&mut entry.file_extension);
+        entry.metadata = Metadata::read(&mut reader);
+        entry.file_size = reader.u32();
+        entry
+    }
+
+    fn write(&self, record: &mut [u8]) {
+        let mut writer = Writer::new(record);
+        writer.bytes(&self.file_name);
+        writer.bytes(&self.file_extension);
+        self.metadata.write(&mut writer);
+        writer.u32(self.file_size);
+    }
+}
+
 #[repr(C, packed)]
 #[derive(Copy, Clone, Debug)]
 pub struct VFatLfnDirEntry {
@@ -47,16 +213,37 @@ pub struct VFatLfnDirEntry {
 
 const_assert_size!(VFatLfnDirEntry, 32);
 
-#[repr(C, packed)]
-#[derive(Copy, Clone)]
-pub struct VFatUnknownDirEntry([u8; 32]);
-
-const_assert_size!(VFatUnknownDirEntry, 32);
-
-pub union VFatDirEntry {
-    unknown: VFatUnknownDirEntry,
-    regular: VFatRegularDirEntry,
-    long_filename: VFatLfnDirEntry,
+impl VFatLfnDirEntry {
+    fn parse(record: &[u8]) -> VFatLfnDirEntry {
+        let mut reader = Reader::new(record);
+        let sequence_number = reader.u8();
+        let mut first_name_chars = [0; 5];
+        for c in first_name_chars.iter_mut() {
+            *c = reader.u16();
+        }
+        let attributes = Attributes::from(reader.u8());
+        let lfn_type = reader.u8();
+        let checksum = reader.u8();
+        let mut second_name_chars = [0; 6];
+        for c in second_name_chars.iter_mut() {
+            *c = reader.u16();
+        }
+        let always_zero = reader.u16();
+        let mut third_name_chars = [0; 2];
+        for c in third_name_chars.iter_mut() {
+            *c = reader.u16();
+        }
+        VFatLfnDirEntry {
+            sequence_number,
+            first_name_chars,
+            attributes,
+            lfn_type,
+            checksum,
+            second_name_chars,
+            always_zero,
+            third_name_chars,
+        }
+    }
 }
 
 impl<HANDLE: VFatHandle> Dir<HANDLE> {
@@ -83,12 +270,153 @@ impl<HANDLE: VFatHandle> Dir<HANDLE> {
         }
         Err(newioerr!(NotFound, "file not found"))
     }
+
+    /// Writes `record` to the first free record of `self`, extending the
+    /// directory if there is none, and returns its location.
+    fn insert(&self, record: [u8; RECORD_SIZE]) -> io::Result<EntryLocation> {
+        // Find the free record and fill it under one lock, so that two
+        // inserts cannot pick the same one.
+        let index = self.vfat.with(|vfat| {
+            let mut records = Vec::new();
+            vfat.read_chain(self.first_cluster, &mut records)?;
+            let index = records
+                .chunks(RECORD_SIZE)
+                .position(|r| r[0] == 0 || r[0] == DELETED)
+                .unwrap_or(records.len() / RECORD_SIZE);
+            let mut first = self.first_cluster;
+            vfat.write_chain(&mut first, index * RECORD_SIZE, &record).map(|_| index)
+        })?;
+        Ok(EntryLocation { dir: self.first_cluster, start: index, end: index + 1 })
+    }
+
+    /// Returns the short name for a new entry named `name`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AlreadyExists` if `self` has an entry named `name`, and
+    /// `InvalidInput` if `name` is not a valid 8.3 name.
+    fn new_name(&self, name: &str) -> io::Result<([u8; 11], bool, bool)> {
+        if self.find(name).is_ok() {
+            return Err(newioerr!(AlreadyExists, "entry already exists"));
+        }
+        match short_name(name) {
+            Some(short) => Ok(short),
+            None => Err(newioerr!(InvalidInput, "not a valid 8.3 name")),
+        }
+    }
+
+    /// Creates an empty file named `name` in `self` and returns it. Only 8.3
+    /// names are supported; their case is preserved if each part is in a
+    /// single case.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AlreadyExists` if `self` has an entry named `name`, and
+    /// `InvalidInput` if `name` is not a valid 8.3 name.
+    pub fn create_file(&self, name: &str) -> io::Result<File<HANDLE>> {
+        let (short, lower_base, lower_ext) = self.new_name(name)?;
+        let metadata = Metadata::new(ATTR_ARCHIVE, 0, lower_base, lower_ext);
+        let location = self.insert(regular_record(short, metadata, 0))?;
+        Ok(File {
+            vfat: self.vfat.clone(),
+            first_cluster: Cluster::from(0),
+            name: name.to_string(),
+            metadata,
+            seek_offset: 0,
+            file_size: 0,
+            location: Some(location),
+            chain: ChainIndex::new(),
+        })
+    }
+
+    /// Creates an empty directory named `name` in `self` and returns it. See
+    /// `create_file()` for the supported names and errors.
+    pub fn create_dir(&self, name: &str) -> io::Result<Dir<HANDLE>> {
+        let (short, lower_base, lower_ext) = self.new_name(name)?;
+        let (cluster, root) = self.vfat.with(|vfat| (vfat.alloc_cluster(None), vfat.root_cluster()));
+        let cluster = cluster?;
+        // A ".." record refers to the root directory as cluster 0.
+        let parent = if self.first_cluster == root { 0 } else { self.first_cluster.get_value() };
+        let mut dots = [0; 2 * RECORD_SIZE];
+        dots[..RECORD_SIZE].copy_from_slice(&regular_record(
+            *b".          ", Metadata::new(ATTR_DIRECTORY, cluster.get_value(), false, false), 0));
+        dots[RECORD_SIZE..].copy_from_slice(&regular_record(
+            *b"..         ", Metadata::new(ATTR_DIRECTORY, parent, false, false), 0));
+        let metadata = Metadata::new(ATTR_DIRECTORY, cluster.get_value(), lower_base, lower_ext);
+        let mut first = cluster;
+        let location = self.vfat.with(|vfat| vfat.write_chain(&mut first, 0, &dots))
+            .and_then(|_| self.insert(regular_record(short, metadata, 0)));
+        match location {
+            Ok(location) => Ok(Dir {
+                vfat: self.vfat.clone(),
+                first_cluster: cluster,
+                name: name.to_string(),
+                metadata,
+                location: Some(location),
+            }),
+            Err(e) => {
+                self.vfat.with(|vfat| vfat.free_chain(cluster))?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Removes the entry named `name` from `self` and frees its clusters.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if there is no such entry and `Other` if it is a
+    /// directory that is not empty.
+    pub fn remove(&self, name: &str) -> io::Result<()> {
+        use crate::traits::{Dir, Entry};
+        let (first_cluster, location) = match self.find(name)? {
+            self::Entry::File(file) => (file.first_cluster, file.location),
+            self::Entry::Dir(dir) => {
+                if dir.entries()?.any(|e| e.name() != "." && e.name() != "..") {
+                    return Err(newioerr!(Other, "directory not empty"));
+                }
+                (dir.first_cluster, dir.location)
+            }
+        };
+        let location = match location {
+            Some(location) => location,
+            None => return Err(newioerr!(InvalidInput, "cannot remove the root directory")),
+        };
+        self.vfat.with(|vfat| {
+            location.delete(vfat)?;
+            vfat.free_chain(first_cluster)
+        })
+    }
+
+    /// Renames the entry named `from` in `self` to `to`. See `create_file()`
+    /// for the supported names.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if there is no entry named `from`, and the errors
+    /// of `create_file()` for `to`.
+    pub fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        let location = match self.find(from)? {
+            Entry::File(file) => file.location,
+            Entry::Dir(dir) => dir.location,
+        };
+        let location = match location {
+            Some(location) => location,
+            None => return Err(newioerr!(InvalidInput, "cannot rename the root directory")),
+        };
+        let (short, lower_base, lower_ext) = self.new_name(to)?;
+        self.vfat.with(|vfat| location.rename(vfat, short, case_flags(lower_base, lower_ext)))
+    }
 }
 
 pub struct EntryIterator<HANDLE: VFatHandle> {
     vfat: HANDLE,
-    entries: Vec<VFatDirEntry>,
+    dir: Cluster,
+    /// The directory's raw records, `RECORD_SIZE` bytes each.
+    records: Vec<u8>,
     curr: usize,
+    /// The character set of short names.
+    codepage: Codepage,
 }
 
 impl<HANDLE: VFatHandle> Iterator for EntryIterator<HANDLE> {
@@ -97,22 +425,26 @@ impl<HANDLE: VFatHandle> Iterator for EntryIterator<HANDLE> {
         let mut is_lfn = true;
         let mut long_file_name = Vec::new();
         let mut long_file_pieces = Vec::new();
+        let mut start = None;
         while is_lfn {
-            if self.curr >= self.entries.len() {
+            let offset = self.curr * RECORD_SIZE;
+            let record = match self.records.get(offset..offset + RECORD_SIZE) {
+                Some(record) => record,
+                None => return None,
+            };
+            if record[0] == 0 {
                 return None;
             }
-            let unknown_entry = unsafe { self.entries[self.curr].unknown };
-            if unknown_entry.0[0] == 0 {
-                return None;
-            }
-            if unknown_entry.0[0] == 0xe5 {
+            if record[0] == DELETED {
                 self.curr += 1;
+                start = None;
                 continue;
             }
-            is_lfn = unknown_entry.0[11] == 0xf;
+            start = start.or(Some(self.curr));
+            is_lfn = record[11] == 0xf;
             if is_lfn {
                 let mut utf16 = Vec::new();
-                let lfn_entry = unsafe { self.entries[self.curr].long_filename };
+                let lfn_entry = VFatLfnDirEntry::parse(record);
                 for ucs in { lfn_entry.first_name_chars }.iter() {
                     let ucs_char = *ucs;
                     if ucs_char != 0 && ucs_char != 0xffff {
@@ -143,7 +475,13 @@ impl<HANDLE: VFatHandle> Iterator for EntryIterator<HANDLE> {
                 self.curr += 1;
             }
         }
-        let regular_entry = unsafe { self.entries[self.curr].regular };
+        let offset = self.curr * RECORD_SIZE;
+        let regular_entry = VFatRegularDirEntry::parse(&self.records[offset..offset + RECORD_SIZE]);
+        let location = EntryLocation {
+            dir: self.dir,
+            start: start.unwrap_or(self.curr),
+            end: self.curr + 1,
+        };
         self.curr += 1;
         let cluster_num = regular_entry.metadata.first_cluster();
         let entry_name = if long_file_name.len() > 0 {
@@ -154,12 +492,13 @@ impl<HANDLE: VFatHandle> Iterator for EntryIterator<HANDLE> {
             decode_utf16(lfn).map(|r| r.unwrap_or(REPLACEMENT_CHARACTER)).collect::<String>()
         } else {
             let mut filename = Vec::new();
+            let metadata = regular_entry.metadata;
             for b in regular_entry.file_name.iter() {
                 let byte = *b;
                 if byte == 0 || byte == 0x20 {
                     break;
                 }
-                filename.push(byte);
+                filename.push(if metadata.lowercase_base() { byte.to_ascii_lowercase() } else { byte });
             }
             if regular_entry.file_extension[0] != 0 && regular_entry.file_extension[0] != 0x20 {
                 filename.push('.' as u8);
@@ -168,12 +507,12 @@ impl<HANDLE: VFatHandle> Iterator for EntryIterator<HANDLE> {
                     if byte == 0 || byte == 0x20 {
                         break;
                     }
-                    filename.push(byte);
+                    filename.push(if metadata.lowercase_ext() { byte.to_ascii_lowercase() } else { byte });
                 }
             }
-            match String::from_utf8(filename) {
-                Ok(s) => s,
-                Err(_) => {
+            match self.codepage.decode(&filename) {
+                Some(s) => s,
+                None => {
                     return None;
                 }
             }
@@ -184,6 +523,7 @@ impl<HANDLE: VFatHandle> Iterator for EntryIterator<HANDLE> {
                 first_cluster: Cluster::from(cluster_num),
                 metadata: regular_entry.metadata,
                 name: entry_name,
+                location: Some(location),
             }))
         } else {
             Some(Entry::File(File {
@@ -192,7 +532,9 @@ impl<HANDLE: VFatHandle> Iterator for EntryIterator<HANDLE> {
                 name: entry_name,
                 first_cluster: Cluster::from(cluster_num),
                 seek_offset: 0,
-                file_size: regular_entry.file_size as usize,
+                file_size: regular_entry.file_size as u64,
+                location: Some(location),
+                chain: ChainIndex::new(),
             }))
         }
     }
@@ -202,12 +544,16 @@ impl<HANDLE: VFatHandle> traits::Dir for Dir<HANDLE> {
     type Entry = Entry<HANDLE>;
     type Iter = EntryIterator<HANDLE>;
     fn entries(&self) -> io::Result<Self::Iter> {
-        let mut entry_vec = Vec::new();
-        self.vfat.lock(|vfat| vfat.read_chain(self.first_cluster, &mut entry_vec))?;
+        let mut records = Vec::new();
+        let codepage = self.vfat.with(|vfat| {
+            vfat.read_chain(self.first_cluster, &mut records).map(|_| vfat.options().codepage)
+        })?;
         Ok(EntryIterator {
             vfat: self.vfat.clone(),
-            entries: unsafe { entry_vec.cast::<VFatDirEntry>() },
+            dir: self.first_cluster,
+            records,
             curr: 0,
+            codepage,
         })
     }
 }
@@ -1,9 +1,9 @@
 use core::fmt;
-use core::mem;
 use shim::const_assert_size;
 
+use crate::le::{Reader, Writer};
 use crate::traits::BlockDevice;
-use crate::vfat::Error;
+use crate::vfat::{Error, FormatOptions, Layout};
 
 #[repr(C, packed)]
 pub struct BiosParameterBlock {
@@ -32,7 +32,7 @@ pub struct BiosParameterBlock {
     reserved_flags: u8,
     signature: u8,
     volume_id: u32,
-    volume_label: [u8; 11],
+    pub volume_label: [u8; 11],
     system_id: [u8; 8],
     boot_code: [u8; 420],
     bootable_partition_signature: u16,
@@ -47,17 +47,200 @@ impl BiosParameterBlock {
     /// # Errors
     ///
     /// If the EBPB signature is invalid, returns an error of `BadSignature`.
+    /// If a field is out of range, returns an error of `BadField`; see
+    /// `parse()`.
     pub fn from<T: BlockDevice>(mut device: T, sector: u64) -> Result<BiosParameterBlock, Error> {
         let mut buf = [0; 512];
         device.read_sector(sector, &mut buf)?;
-        let ebpb: BiosParameterBlock = unsafe { mem::transmute(buf) };
+        BiosParameterBlock::parse(&buf)
+    }
+
+    /// Parses and validates the FAT32 extended BIOS parameter block in
+    /// `sector`.
+    ///
+    /// # Errors
+    ///
+    /// If the EBPB signature is invalid, returns an error of `BadSignature`.
+    /// Returns an error of `BadField(name)` if:
+    ///
+    ///   * the sector size is not a power of two from 512 to 4096
+    ///   * the sectors per cluster are not a power of two
+    ///   * there are no reserved sectors, no FATs or no sectors per FAT
+    ///   * the root directory cluster is below 2
+    ///   * the FATs leave no room for data in the volume
+    pub fn parse(sector: &[u8; 512]) -> Result<BiosParameterBlock, Error> {
+        let ebpb = BiosParameterBlock::read(&mut Reader::new(sector));
         if ebpb.bootable_partition_signature != 0xaa55 {
             return Err(Error::BadSignature);
         }
+        let bytes_per_sector = ebpb.bytes_per_sector;
+        if !bytes_per_sector.is_power_of_two() || bytes_per_sector < 512 || bytes_per_sector > 4096 {
+            return Err(Error::BadField("bytes_per_sector"));
+        }
+        if !ebpb.sectors_per_cluster.is_power_of_two() {
+            return Err(Error::BadField("sectors_per_cluster"));
+        }
+        if ebpb.reserved_sectors == 0 {
+            return Err(Error::BadField("reserved_sectors"));
+        }
+        if ebpb.fats == 0 {
+            return Err(Error::BadField("fats"));
+        }
+        if ebpb.sectors_per_fat == 0 {
+            return Err(Error::BadField("sectors_per_fat"));
+        }
+        if ebpb.root_directory_cluster & 0x0FFF_FFFF < 2 {
+            return Err(Error::BadField("root_directory_cluster"));
+        }
+        let data_start = ebpb.reserved_sectors as u64 + ebpb.fats as u64 * ebpb.sectors_per_fat as u64;
+        if data_start + ebpb.sectors_per_cluster as u64 > ebpb.total_logical_sectors as u64 {
+            return Err(Error::BadField("total_logical_sectors"));
+        }
         Ok(ebpb)
     }
 }
 
+impl BiosParameterBlock {
+    /// Returns the EBPB of a new volume laid out as `layout`, starting at
+    /// sector `hidden_sectors` of its device. See `vfat::format()`.
+    pub(crate) fn new(layout: &Layout, hidden_sectors: u32, options: &FormatOptions) -> BiosParameterBlock {
+        BiosParameterBlock {
+            jmp_short_noop: [0xEB, 0x58, 0x90],
+            oem_identifier: *b"RUSTOS  ",
+            bytes_per_sector: layout.bytes_per_sector,
+            sectors_per_cluster: layout.sectors_per_cluster,
+            reserved_sectors: layout.reserved_sectors,
+            fats: layout.fats,
+            max_directory_entries: 0,
+            total_logical_sectors_smol: 0,
+            fat_id: 0xF8,
+            sectors_per_fat_smol: 0,
+            sectors_per_track: 32,
+            heads: 64,
+            hidden_sectors,
+            total_logical_sectors: layout.total_sectors,
+            sectors_per_fat: layout.sectors_per_fat,
+            flags: 0,
+            version_number: 0,
+            root_directory_cluster: 2,
+            fsinfo_sector: layout.fsinfo_sector,
+            backup_boot_sector: layout.backup_boot_sector,
+            reserved: [0; 12],
+            drive_number: 0x80,
+            reserved_flags: 0,
+            signature: 0x29,
+            volume_id: options.volume_id,
+            volume_label: options.volume_label,
+            system_id: *b"FAT32   ",
+            boot_code: [0; 420],
+            bootable_partition_signature: 0xaa55,
+        }
+    }
+
+    /// Writes the EBPB to the first 512 bytes of `sector`.
+    pub(crate) fn write(&self, sector: &mut [u8]) {
+        let mut writer = Writer::new(&mut sector[..512]);
+        writer.bytes(&self.jmp_short_noop);
+        writer.bytes(&self.oem_identifier);
+        writer.u16(self.bytes_per_sector);
+        writer.u8(self.sectors_per_cluster);
+        writer.u16(self.reserved_sectors);
+        writer.u8(self.fats);
+        writer.u16(self.max_directory_entries);
+        writer.u16(self.total_logical_sectors_smol);
+        writer.u8(self.fat_id);
+        writer.u16(self.sectors_per_fat_smol);
+        writer.u16(self.sectors_per_track);
+        writer.u16(self.heads);
+        writer.u32(self.hidden_sectors);
+        writer.u32(self.total_logical_sectors);
+        writer.u32(self.sectors_per_fat);
+        writer.u16(self.flags);
+        writer.u16(self.version_number);
+        writer.u32(self.root_directory_cluster);
+        writer.u16(self.fsinfo_sector);
+        writer.u16(self.backup_boot_sector);
+        writer.bytes(&self.reserved);
+        writer.u8(self.drive_number);
+        writer.u8(self.reserved_flags);
+        writer.u8(self.signature);
+        writer.u32(self.volume_id);
+        writer.bytes(&self.volume_label);
+        writer.bytes(&self.system_id);
+        writer.bytes(&self.boot_code);
+        writer.u16(self.bootable_partition_signature);
+    }
+
+    fn read(reader: &mut Reader) -> BiosParameterBlock {
+        let mut jmp_short_noop = [0; 3];
+        reader.bytes(&mut jmp_short_noop);
+        let mut oem_identifier = [0; 8];
+        reader.bytes(&mut oem_identifier);
+        let bytes_per_sector = reader.u16();
+        let sectors_per_cluster = reader.u8();
+        let reserved_sectors = reader.u16();
+        let fats = reader.u8();
+        let max_directory_entries = reader.u16();
+        let total_logical_sectors_smol = reader.u16();
+        let fat_id = reader.u8();
+        let sectors_per_fat_smol = reader.u16();
+        let sectors_per_track = reader.u16();
+        let heads = reader.u16();
+        let hidden_sectors = reader.u32();
+        let total_logical_sectors = reader.u32();
+        let sectors_per_fat = reader.u32();
+        let flags = reader.u16();
+        let version_number = reader.u16();
+        let root_directory_cluster = reader.u32();
+        let fsinfo_sector = reader.u16();
+        let backup_boot_sector = reader.u16();
+        let mut reserved = [0; 12];
+        reader.bytes(&mut reserved);
+        let drive_number = reader.u8();
+        let reserved_flags = reader.u8();
+        let signature = reader.u8();
+        let volume_id = reader.u32();
+        let mut volume_label = [0; 11];
+        reader.bytes(&mut volume_label);
+        let mut system_id = [0; 8];
+        reader.bytes(&mut system_id);
+        let mut boot_code = [0; 420];
+        reader.bytes(&mut boot_code);
+        let bootable_partition_signature = reader.u16();
+        BiosParameterBlock {
+            jmp_short_noop,
+            oem_identifier,
+            bytes_per_sector,
+            sectors_per_cluster,
+            reserved_sectors,
+            fats,
+            max_directory_entries,
+            total_logical_sectors_smol,
+            fat_id,
+            sectors_per_fat_smol,
+            sectors_per_track,
+            heads,
+            hidden_sectors,
+            total_logical_sectors,
+            sectors_per_fat,
+            flags,
+            version_number,
+            root_directory_cluster,
+            fsinfo_sector,
+            backup_boot_sector,
+            reserved,
+            drive_number,
+            reserved_flags,
+            signature,
+            volume_id,
+            volume_label,
+            system_id,
+            boot_code,
+            bootable_partition_signature,
+        }
+    }
+}
+
 impl fmt::Debug for BiosParameterBlock {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         fmt.debug_struct("BiosParameterBlock")
@@ -7,6 +7,8 @@ pub enum Error {
     Mbr(mbr::Error),
     Io(io::Error),
     BadSignature,
+    /// A field of the EBPB is out of range; `.0` names it.
+    BadField(&'static str),
     NotFound,
 }
 
@@ -1,10 +1,15 @@
 use alloc::string::String;
+use alloc::vec::Vec;
 
 use shim::io::{self, SeekFrom};
-use shim::newioerr;
+use shim::{ioerr, newioerr};
 
 use crate::traits;
-use crate::vfat::{Cluster, Metadata, VFatHandle};
+use crate::util::saturating_usize;
+use crate::vfat::{ChainIndex, Cluster, EntryLocation, Metadata, VFatHandle};
+
+/// The largest size a FAT32 directory entry can record: 4GiB less a byte.
+const MAX_FILE_SIZE: u64 = u32::max_value() as u64;
 
 #[derive(Debug)]
 pub struct File<HANDLE: VFatHandle> {
@@ -12,16 +17,39 @@ pub struct File<HANDLE: VFatHandle> {
     pub first_cluster: Cluster,
     pub name: String,
     pub metadata: Metadata,
-    pub seek_offset: usize,
-    pub file_size: usize,
+    pub seek_offset: u64,
+    pub file_size: u64,
+    /// Where the file's records are in its parent directory.
+    pub location: Option<EntryLocation>,
+    /// The clusters of the file found by earlier reads and writes, so that
+    /// one far into the file does not take a walk of the whole chain.
+    pub chain: ChainIndex,
+}
+
+impl<HANDLE: VFatHandle> File<HANDLE> {
+    /// Returns the physical sectors of the underlying block device that hold
+    /// the file's contents, in order. See `VFat::chain_sectors()`.
+    pub fn sectors(&self) -> io::Result<Vec<u64>> {
+        self.vfat.with(|vfat| vfat.chain_sectors(self.first_cluster, self.file_size))
+    }
+
+    /// Starts reading the next `len` bytes of the file, from the current
+    /// offset, into the sector cache without waiting for them, so that a
+    /// later `read()` of them overlaps less with the device. See
+    /// `VFat::prefetch()`.
+    pub fn prefetch(&mut self, len: usize) -> io::Result<()> {
+        let len = len.min(saturating_usize(self.file_size.saturating_sub(self.seek_offset)));
+        let (first_cluster, offset, chain) = (self.first_cluster, self.seek_offset, &mut self.chain);
+        self.vfat.with(|vfat| vfat.prefetch(first_cluster, chain, offset, len))
+    }
 }
 
 impl<HANDLE: VFatHandle> traits::File for File<HANDLE> {
     fn sync(&mut self) -> io::Result<()> {
-        unimplemented!("filesystem is read only")
+        self.vfat.with(|vfat| vfat.sync())
     }
     fn size(&self) -> u64 {
-        self.file_size as u64
+        self.file_size
     }
 }
 
@@ -31,19 +59,47 @@ impl<HANDLE: VFatHandle> io::Read for File<HANDLE> {
             // EOF
             return Ok(0);
         }
-        let bytes_read = self.vfat.lock(|vfat| vfat.read_file(self.first_cluster, self.seek_offset, self.file_size, buf))?;
-        self.seek_offset += bytes_read;
+        // The chain may be longer than the file; never read past its end.
+        let len = buf.len().min(saturating_usize(self.file_size - self.seek_offset));
+        let buf = &mut buf[..len];
+        let (first_cluster, offset, size, chain) = (self.first_cluster, self.seek_offset, self.file_size, &mut self.chain);
+        let bytes_read = self.vfat.with(|vfat| vfat.read_file(first_cluster, chain, offset, size, buf))?;
+        self.seek_offset += bytes_read as u64;
         Ok(bytes_read)
     }
 }
 
 impl<HANDLE: VFatHandle> io::Write for File<HANDLE> {
-    fn write(&mut self, _buf: &[u8]) -> Result<usize, io::Error> {
-        unimplemented!("filesystem is read only")
+    /// Writes `buf` at the current offset, growing the file if the write
+    /// extends past its end. Changes are cached until the file is synced.
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        let location = match self.location {
+            Some(location) => location,
+            None => return ioerr!(PermissionDenied, "file has no directory entry"),
+        };
+        match self.seek_offset.checked_add(buf.len() as u64) {
+            Some(end) if end <= MAX_FILE_SIZE => (),
+            _ => return ioerr!(InvalidInput, "write exceeds the maximum file size"),
+        }
+        let (old_cluster, offset, old_size) = (self.first_cluster, self.seek_offset, self.file_size);
+        let mut first_cluster = old_cluster;
+        let chain = &mut self.chain;
+        let written = self.vfat.with(|vfat| -> io::Result<usize> {
+            let written = vfat.write_file(&mut first_cluster, chain, offset, buf)?;
+            let size = old_size.max(offset + written as u64);
+            if first_cluster != old_cluster || size != old_size {
+                location.set_cluster_and_size(vfat, first_cluster, size as u32)?;
+            }
+            Ok(written)
+        })?;
+        self.first_cluster = first_cluster;
+        self.seek_offset += written as u64;
+        self.file_size = self.file_size.max(self.seek_offset);
+        Ok(written)
     }
 
     fn flush(&mut self) -> Result<(), io::Error> {
-        unimplemented!("filesystem is read only")
+        traits::File::sync(self)
     }
 }
 
@@ -62,33 +118,27 @@ impl<HANDLE: VFatHandle> io::Seek for File<HANDLE> {
     /// Seeking before the start of a file or beyond the end of the file results
     /// in an `InvalidInput` error.
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
-        match pos {
-            SeekFrom::Start(p) => {
-                if p > self.file_size as u64 {
-                    Err(newioerr!(InvalidInput, "Attempt to seek outside file"))
-                } else {
-                    self.seek_offset = p as usize;
-                    Ok(p)
-                }
-            },
-            SeekFrom::End(p) => {
-                let offset = p + self.file_size as i64;
-                if offset < 0 || offset > self.file_size as i64 {
-                    Err(newioerr!(InvalidInput, "Attempt to seek outside file"))
-                } else {
-                    self.seek_offset = offset as usize;
-                    Ok(offset as u64)
-                }
-            },
-            SeekFrom::Current(p) => {
-                let offset = p + self.seek_offset as i64;
-                if offset < 0 || offset > self.file_size as i64 {
-                    Err(newioerr!(InvalidInput, "Attempt to seek outside file"))
-                } else {
-                    self.seek_offset = offset as usize;
-                    Ok(offset as u64)
-                }
-            },
+        let offset = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(p) => offset_by(self.file_size, p),
+            SeekFrom::Current(p) => offset_by(self.seek_offset, p),
+        };
+        match offset {
+            Some(offset) if offset <= self.file_size => {
+                self.seek_offset = offset;
+                Ok(offset)
+            }
+            _ => Err(newioerr!(InvalidInput, "Attempt to seek outside file")),
         }
     }
 }
+
+/// Returns `base` moved by `delta`, or `None` if that is before 0 or
+/// overflows.
+fn offset_by(base: u64, delta: i64) -> Option<u64> {
+    if delta < 0 {
+        base.checked_sub(delta.wrapping_neg() as u64)
+    } else {
+        base.checked_add(delta as u64)
+    }
+}
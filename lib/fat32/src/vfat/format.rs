@@ -0,0 +1,215 @@
+use shim::io;
+use shim::ioerr;
+
+use crate::le::Writer;
+use crate::traits::BlockDevice;
+use crate::vfat::BiosParameterBlock;
+
+/// The sectors before the first FAT, as other formatters reserve.
+const RESERVED_SECTORS: u16 = 32;
+const FSINFO_SECTOR: u16 = 1;
+/// Where the copies of the boot sector and the FSInfo sector start.
+const BACKUP_BOOT_SECTOR: u16 = 6;
+
+/// The fewest data clusters a FAT32 volume can have: with fewer, other
+/// implementations take it for FAT16 whatever its boot sector says.
+const MIN_CLUSTERS: u64 = 65525;
+
+/// The most data clusters a FAT32 volume can have: entries from 0x0FFF_FFF7
+/// up mark bad clusters and the ends of chains.
+const MAX_CLUSTERS: u64 = 0x0FFF_FFF7 - 2;
+
+/// The FAT entry of the root directory, the end of its one-cluster chain.
+const END_OF_CHAIN: u32 = 0x0FFF_FFFF;
+
+/// How `format()` sets up a new volume.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FormatOptions {
+    /// Bytes per cluster: a power of two multiple of the sector size, at most
+    /// 128 sectors. 0 picks a size from the volume's size, as other
+    /// formatters do.
+    pub cluster_size: u32,
+    /// The number of copies of the FAT.
+    pub fats: u8,
+    /// The volume label, padded with spaces.
+    pub volume_label: [u8; 11],
+    /// The volume serial number.
+    pub volume_id: u32,
+}
+
+impl Default for FormatOptions {
+    fn default() -> FormatOptions {
+        FormatOptions { cluster_size: 0, fats: 2, volume_label: *b"NO NAME    ", volume_id: 0 }
+    }
+}
+
+/// Where the structures of a new volume go, in its sectors.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Layout {
+    pub bytes_per_sector: u16,
+    pub sectors_per_cluster: u8,
+    pub reserved_sectors: u16,
+    pub fsinfo_sector: u16,
+    pub backup_boot_sector: u16,
+    pub fats: u8,
+    pub sectors_per_fat: u32,
+    pub total_sectors: u32,
+    /// The number of data clusters, from cluster 2. The root directory takes
+    /// the first.
+    pub clusters: u32,
+}
+
+impl Layout {
+    /// Returns the layout of a volume of `sectors` sectors of `sector_size`
+    /// bytes, formatted with `options`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidInput` if the sector size, cluster size or number of
+    /// FATs is not supported, or if the volume has too few or too many
+    /// clusters for FAT32 with that cluster size.
+    pub fn new(sector_size: u64, sectors: u64, options: &FormatOptions) -> io::Result<Layout> {
+        if !sector_size.is_power_of_two() || sector_size < 512 || sector_size > 4096 {
+            return ioerr!(InvalidInput, "unsupported sector size");
+        }
+        if sectors > u32::max_value() as u64 {
+            return ioerr!(InvalidInput, "volume too large");
+        }
+        let cluster_size = match options.cluster_size {
+            0 => default_cluster_size(sector_size * sectors).max(sector_size),
+            size => size as u64,
+        };
+        let sectors_per_cluster = cluster_size / sector_size;
+        if cluster_size % sector_size != 0 || !sectors_per_cluster.is_power_of_two() || sectors_per_cluster > 128 {
+            return ioerr!(InvalidInput, "unsupported cluster size");
+        }
+        if options.fats == 0 {
+            return ioerr!(InvalidInput, "a volume needs a FAT");
+        }
+
+        // Each FAT takes sectors from the data region, so needs fewer entries
+        // as it grows; this settles in a few rounds.
+        let fixed = RESERVED_SECTORS as u64;
+        let entries_per_sector = sector_size / 4;
+        let mut sectors_per_fat = 1;
+        let clusters = loop {
+            let fats = options.fats as u64 * sectors_per_fat;
+            let data = match sectors.checked_sub(fixed + fats) {
+                Some(data) => data,
+                None => return ioerr!(InvalidInput, "volume too small"),
+            };
+            let clusters = data / sectors_per_cluster;
+            let needed = (clusters + 2 + entries_per_sector - 1) / entries_per_sector;
+            if needed <= sectors_per_fat {
+                break clusters;
+            }
+            sectors_per_fat = needed;
+        };
+        if clusters < MIN_CLUSTERS {
+            return ioerr!(InvalidInput, "volume too small for FAT32");
+        }
+        if clusters > MAX_CLUSTERS {
+            return ioerr!(InvalidInput, "too many clusters for FAT32; use larger clusters");
+        }
+        Ok(Layout {
+            bytes_per_sector: sector_size as u16,
+            sectors_per_cluster: sectors_per_cluster as u8,
+            reserved_sectors: RESERVED_SECTORS,
+            fsinfo_sector: FSINFO_SECTOR,
+            backup_boot_sector: BACKUP_BOOT_SECTOR,
+            fats: options.fats,
+            sectors_per_fat: sectors_per_fat as u32,
+            total_sectors: sectors as u32,
+            clusters: clusters as u32,
+        })
+    }
+
+    /// Returns the first sector of the data region, cluster 2's.
+    pub fn data_start(&self) -> u64 {
+        self.reserved_sectors as u64 + self.fats as u64 * self.sectors_per_fat as u64
+    }
+}
+
+/// Returns the cluster size for a volume of `bytes` bytes, as chosen by
+/// Microsoft's formatter.
+fn default_cluster_size(bytes: u64) -> u64 {
+    const MIB: u64 = 1 << 20;
+    match bytes {
+        b if b <= 260 * MIB => 512,
+        b if b <= 8 * 1024 * MIB => 4096,
+        b if b <= 16 * 1024 * MIB => 8192,
+        b if b <= 32 * 1024 * MIB => 16384,
+        _ => 32768,
+    }
+}
+
+/// Writes an empty FAT32 file system with `options` onto the `sectors`
+/// sectors of `device` from sector `start`, and returns its layout:
+///
+///   * the EBPB, FSInfo sector and their copies in the reserved sectors
+///   * FATs with only the root directory's cluster in use
+///   * an empty root directory in cluster 2
+///
+/// Whatever the sectors held is lost. The rest of the device, including a
+/// partition table at sector 0 when `start` is past it, is not touched.
+///
+/// # Errors
+///
+/// Returns `InvalidInput` if the volume cannot be laid out; see
+/// `Layout::new()`. Returns the error of a failed write, after which the
+/// volume is not usable.
+pub fn format<T: BlockDevice>(mut device: T, start: u64, sectors: u64, options: &FormatOptions) -> io::Result<Layout> {
+    let layout = Layout::new(device.sector_size(), sectors, options)?;
+    if start + sectors > u32::max_value() as u64 + 1 {
+        return ioerr!(InvalidInput, "volume ends past the largest partition sector");
+    }
+    let sector_size = layout.bytes_per_sector as usize;
+    let zeros = vec![0u8; sector_size];
+
+    let mut boot = vec![0u8; sector_size];
+    BiosParameterBlock::new(&layout, start as u32, options).write(&mut boot);
+    let mut fsinfo = vec![0u8; sector_size];
+    write_fsinfo(&layout, &mut fsinfo);
+    for n in 0..layout.reserved_sectors {
+        let sector = match n {
+            n if n == 0 || n == layout.backup_boot_sector => &boot,
+            n if n == layout.fsinfo_sector || n == layout.backup_boot_sector + layout.fsinfo_sector => &fsinfo,
+            _ => &zeros,
+        };
+        device.write_sector(start + n as u64, sector)?;
+    }
+
+    let mut first_fat_sector = vec![0u8; sector_size];
+    let mut writer = Writer::new(&mut first_fat_sector);
+    // Entry 0 holds the media descriptor, entry 1 the clean shutdown flags.
+    writer.u32(0x0FFF_FFF8);
+    writer.u32(0x0FFF_FFFF);
+    writer.u32(END_OF_CHAIN);
+    for fat in 0..layout.fats as u64 {
+        let fat_start = start + layout.reserved_sectors as u64 + fat * layout.sectors_per_fat as u64;
+        for n in 0..layout.sectors_per_fat as u64 {
+            let sector = if n == 0 { &first_fat_sector } else { &zeros };
+            device.write_sector(fat_start + n, sector)?;
+        }
+    }
+
+    let root = start + layout.data_start();
+    for n in 0..layout.sectors_per_cluster as u64 {
+        device.write_sector(root + n, &zeros)?;
+    }
+    Ok(layout)
+}
+
+/// Writes the FSInfo sector of a new volume laid out as `layout` to `sector`:
+/// all clusters but the root directory's are free, and the next free one is
+/// cluster 3.
+fn write_fsinfo(layout: &Layout, sector: &mut [u8]) {
+    let mut writer = Writer::new(&mut sector[..512]);
+    writer.u32(0x4161_5252);
+    writer.bytes(&[0; 480]);
+    writer.u32(0x6141_7272);
+    writer.u32(layout.clusters - 1);
+    writer.u32(3);
+    writer.bytes(&[0; 12]);
+    writer.u32(0xAA55_0000);
+}
@@ -0,0 +1,38 @@
+use core::cell::UnsafeCell;
+use core::fmt;
+
+/// A lock that handles a critical section as a closure. The file system has
+/// no locks of its own; `VFatHandle::Lock` supplies them.
+pub trait Lock: Send + Sync {
+    fn new() -> Self;
+
+    /// Runs `f` while holding the lock. Other callers wait until it returns.
+    fn critical<R>(&self, f: impl FnOnce() -> R) -> R;
+}
+
+/// A value that is only accessed while holding a `Lock`.
+pub struct Locked<L: Lock, T> {
+    lock: L,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<L: Lock, T: Send> Send for Locked<L, T> {}
+unsafe impl<L: Lock, T: Send> Sync for Locked<L, T> {}
+
+impl<L: Lock, T> Locked<L, T> {
+    pub fn new(value: T) -> Locked<L, T> {
+        Locked { lock: L::new(), value: UnsafeCell::new(value) }
+    }
+
+    /// Runs `f` with the value while holding the lock. `f` must not lock the
+    /// value again: a lock need not be reentrant.
+    pub fn lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        self.lock.critical(|| f(unsafe { &mut *self.value.get() }))
+    }
+}
+
+impl<L: Lock, T> fmt::Debug for Locked<L, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Locked").finish()
+    }
+}
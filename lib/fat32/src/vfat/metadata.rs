@@ -2,6 +2,7 @@ use core::fmt;
 
 use shim::const_assert_size;
 
+use crate::le::{Reader, Writer};
 use crate::traits;
 
 /// A date as represented in FAT32 on-disk structures.
@@ -44,7 +45,109 @@ pub struct Metadata {
 
 const_assert_size!(Metadata, 17);
 
+/// `Attributes` bit of the volume label record.
+pub(crate) const ATTR_VOLUME_ID: u8 = 0x08;
+/// `Attributes` bit of directories.
+pub(crate) const ATTR_DIRECTORY: u8 = 0x10;
+/// `Attributes` bit of files changed since the last backup.
+pub(crate) const ATTR_ARCHIVE: u8 = 0x20;
+
+/// Bit of the reserved byte set when the base name is lowercase.
+const CASE_LOWER_BASE: u8 = 0x08;
+/// Bit of the reserved byte set when the extension is lowercase.
+const CASE_LOWER_EXT: u8 = 0x10;
+
+/// Returns the reserved byte of a regular record whose short name has a
+/// lowercase base name and/or extension.
+pub(crate) fn case_flags(lower_base: bool, lower_ext: bool) -> u8 {
+    let mut flags = 0;
+    if lower_base {
+        flags |= CASE_LOWER_BASE;
+    }
+    if lower_ext {
+        flags |= CASE_LOWER_EXT;
+    }
+    flags
+}
+
+impl From<u8> for Attributes {
+    fn from(raw: u8) -> Attributes {
+        Attributes(raw)
+    }
+}
+
 impl Metadata {
+    /// Returns metadata for a new entry with the attribute bits `attributes`
+    /// whose data starts at `first_cluster`. `lower_base` and `lower_ext`
+    /// record that the short name is to be shown in lowercase.
+    pub(crate) fn new(attributes: u8, first_cluster: u32, lower_base: bool, lower_ext: bool) -> Metadata {
+        Metadata {
+            attributes: Attributes(attributes),
+            reserved: case_flags(lower_base, lower_ext),
+            first_cluster_high: (first_cluster >> 16) as u16,
+            first_cluster_low: first_cluster as u16,
+            ..Default::default()
+        }
+    }
+
+    /// Returns metadata for the root directory starting at `first_cluster`.
+    /// The root has no record of its own, so its timestamps are taken from
+    /// the volume label record `volume`, or are zero if there is none.
+    pub(crate) fn root(first_cluster: u32, volume: Option<&Metadata>) -> Metadata {
+        let mut metadata = Metadata::new(ATTR_DIRECTORY, first_cluster, false, false);
+        if let Some(volume) = volume {
+            metadata.creation_time_tenths_s = volume.creation_time_tenths_s;
+            metadata.created_time = volume.created_time;
+            metadata.created_date = volume.created_date;
+            metadata.accessed_date = volume.accessed_date;
+            metadata.modified_time = volume.modified_time;
+            metadata.modified_date = volume.modified_date;
+        }
+        metadata
+    }
+
+    /// Reads the metadata fields of a regular directory record.
+    pub(crate) fn read(reader: &mut Reader) -> Metadata {
+        Metadata {
+            attributes: Attributes(reader.u8()),
+            reserved: reader.u8(),
+            creation_time_tenths_s: reader.u8(),
+            created_time: Time(reader.u16()),
+            created_date: Date(reader.u16()),
+            accessed_date: Date(reader.u16()),
+            first_cluster_high: reader.u16(),
+            modified_time: Time(reader.u16()),
+            modified_date: Date(reader.u16()),
+            first_cluster_low: reader.u16(),
+        }
+    }
+
+    /// Writes the metadata fields of a regular directory record.
+    pub(crate) fn write(&self, writer: &mut Writer) {
+        writer.u8(self.attributes.0);
+        writer.u8(self.reserved);
+        writer.u8(self.creation_time_tenths_s);
+        writer.u16(self.created_time.0);
+        writer.u16(self.created_date.0);
+        writer.u16(self.accessed_date.0);
+        writer.u16(self.first_cluster_high);
+        writer.u16(self.modified_time.0);
+        writer.u16(self.modified_date.0);
+        writer.u16(self.first_cluster_low);
+    }
+
+    /// Returns `true` if the base name of the short name is shown in
+    /// lowercase.
+    pub(crate) fn lowercase_base(&self) -> bool {
+        self.reserved & CASE_LOWER_BASE != 0
+    }
+
+    /// Returns `true` if the extension of the short name is shown in
+    /// lowercase.
+    pub(crate) fn lowercase_ext(&self) -> bool {
+        self.reserved & CASE_LOWER_EXT != 0
+    }
+
     pub fn first_cluster(&self) -> u32 {
         self.first_cluster_low as u32 | (self.first_cluster_high as u32) << 16
     }
@@ -58,7 +161,7 @@ impl Metadata {
     }
 
     pub fn is_volume_id(&self) -> bool {
-        self.attributes.0 & 0x8 != 0
+        self.attributes.0 & ATTR_VOLUME_ID != 0
     }
 
     pub fn is_archive(&self) -> bool {
@@ -0,0 +1,99 @@
+use alloc::string::String;
+use core::fmt;
+
+use shim::io;
+use shim::newioerr;
+
+/// The character set of 8.3 short names.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Codepage {
+    /// Short names are UTF-8. Entries whose short names are not are skipped.
+    Utf8,
+    /// IBM code page 437, the character set of DOS and of most FAT volumes
+    /// written by other systems.
+    Cp437,
+}
+
+/// The characters of code page 437 bytes 0x80 to 0xFF. Bytes below 0x80 are
+/// ASCII.
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+impl Codepage {
+    /// Decodes the short name bytes `bytes`, or returns `None` if they are
+    /// not valid in this code page.
+    pub fn decode(self, bytes: &[u8]) -> Option<String> {
+        match self {
+            Codepage::Utf8 => core::str::from_utf8(bytes).ok().map(String::from),
+            Codepage::Cp437 => Some(bytes.iter().map(|&byte| match byte {
+                0..=0x7F => byte as char,
+                _ => CP437_HIGH[byte as usize - 0x80],
+            }).collect()),
+        }
+    }
+}
+
+/// Options a volume is mounted with.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MountOptions {
+    /// Writes fail with `PermissionDenied`.
+    pub read_only: bool,
+    /// Access dates are not updated. This driver has no clock and never
+    /// updates them, so the option is only recorded.
+    pub noatime: bool,
+    /// The character set of short names.
+    pub codepage: Codepage,
+}
+
+impl Default for MountOptions {
+    fn default() -> MountOptions {
+        MountOptions { read_only: false, noatime: false, codepage: Codepage::Utf8 }
+    }
+}
+
+impl MountOptions {
+    /// Returns `self` with the comma-separated options `options` applied:
+    /// `ro` or `rw`, `atime` or `noatime`, and `codepage=utf8` or
+    /// `codepage=437`. Options that are not mentioned keep their values.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidInput` if an option is unknown.
+    pub fn parse(self, options: &str) -> io::Result<MountOptions> {
+        let mut parsed = self;
+        for option in options.split(',').map(str::trim).filter(|option| !option.is_empty()) {
+            match option {
+                "ro" => parsed.read_only = true,
+                "rw" => parsed.read_only = false,
+                "atime" => parsed.noatime = false,
+                "noatime" => parsed.noatime = true,
+                "codepage=utf8" => parsed.codepage = Codepage::Utf8,
+                "codepage=437" => parsed.codepage = Codepage::Cp437,
+                _ => return Err(newioerr!(InvalidInput, "unknown mount option")),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+impl fmt::Display for MountOptions {
+    /// Formats the options the way `parse()` accepts them.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(if self.read_only { "ro" } else { "rw" })?;
+        if self.noatime {
+            f.write_str(",noatime")?;
+        }
+        match self.codepage {
+            Codepage::Utf8 => f.write_str(",codepage=utf8"),
+            Codepage::Cp437 => f.write_str(",codepage=437"),
+        }
+    }
+}
@@ -1,37 +1,84 @@
 use core::fmt::Debug;
 use core::marker::PhantomData;
 
+use alloc::string::String;
 use alloc::vec::Vec;
 
 use shim::io;
+use shim::canonical;
 use shim::newioerr;
 use shim::path::{Path,Component};
 
 use crate::alloc::string::ToString;
 use crate::mbr::MasterBootRecord;
-use crate::traits::{BlockDevice, FileSystem};
-use crate::util::SliceExt;
-use crate::vfat::{BiosParameterBlock, CachedPartition, Partition};
-use crate::vfat::{Cluster, Dir, Entry, Error, FatEntry, File, Status};
+use crate::traits::{BlockDevice, FileSystem, FsError};
+use crate::util::{checked_usize, saturating_usize, SliceExt};
+use crate::vfat::{BiosParameterBlock, CacheStats, CachedPartition, ChainIndex, Partition};
+use crate::vfat::dir::volume_label_record;
+use crate::vfat::{Cluster, Dir, Entry, Error, FatEntry, File, Lock, Locked, Metadata, MountOptions, Status};
 
-/// A generic trait that handles a critical section as a closure
+/// A generic trait that shares a volume between the users of its files.
 pub trait VFatHandle: Clone + Debug + Send + Sync {
+    /// The lock the volume takes around each of its sector cache, its FAT
+    /// and its options.
+    type Lock: Lock;
+
     fn new(val: VFat<Self>) -> Self;
-    fn lock<R>(&self, f: impl FnOnce(&mut VFat<Self>) -> R) -> R;
+
+    /// Runs `f` with the volume. The volume locks what it uses for no longer
+    /// than a cluster at a time, so `f` need not run alone: one user's long
+    /// read does not hold up the others.
+    fn with<R>(&self, f: impl FnOnce(&VFat<Self>) -> R) -> R;
+}
+
+/// A volume's FAT. Its entries are in the sector cache, which is all it
+/// takes to read one; changing them, or deciding what to change from what
+/// was read, takes the FAT's lock, `&mut Fat`. The FAT is locked before the
+/// sector cache, never after.
+#[derive(Debug)]
+struct Fat {
+    /// Where the search for a free cluster starts.
+    next_free: u32,
+}
+
+/// What else of a volume changes once it is mounted.
+#[derive(Debug)]
+struct State {
+    options: MountOptions,
+    /// The chain and offset where the last `read_file()` ended, to detect
+    /// sequential reads.
+    last_read: Option<(Cluster, u64)>,
 }
 
 #[derive(Debug)]
 pub struct VFat<HANDLE: VFatHandle> {
     phantom: PhantomData<HANDLE>,
-    device: CachedPartition,
+    device: Locked<HANDLE::Lock, CachedPartition>,
+    fat: Locked<HANDLE::Lock, Fat>,
+    state: Locked<HANDLE::Lock, State>,
     bytes_per_sector: u16,
     sectors_per_cluster: u8,
     sectors_per_fat: u32,
+    fats: u8,
     fat_start_sector: u64,
     data_start_sector: u64,
     rootdir_cluster: Cluster,
+    /// One past the highest valid cluster number.
+    end_cluster: u32,
+    /// The volume label, without trailing spaces.
+    volume_label: Option<String>,
+    /// Metadata for the root directory, which has no record of its own.
+    root_metadata: Metadata,
 }
 
+/// How far past a sequential read `read_file()` reads ahead.
+const READAHEAD: usize = 32 * 1024;
+
+/// `read_file()` reads of at least this many bytes that start on a sector
+/// boundary bypass the sector cache: whole sectors go from the device
+/// straight into the caller's buffer, and are not read ahead.
+const DIRECT_READ: usize = 4 * 1024;
+
 impl<HANDLE: VFatHandle> VFat<HANDLE> {
     pub fn from<T>(mut device: T) -> Result<HANDLE, Error>
     where
@@ -53,20 +100,34 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
         let bpb_sector = mbr.partition_table[which_partition].sector_offset as u64;
         let bpb = BiosParameterBlock::from(&mut device, bpb_sector)?;
         let data_start = bpb.reserved_sectors as u64 + (bpb.fats as u64 * bpb.sectors_per_fat as u64);
-        let fat = VFat {
+        let data_clusters = (bpb.total_logical_sectors as u64).saturating_sub(data_start) / bpb.sectors_per_cluster as u64;
+        // Clusters are also limited by the number of FAT entries.
+        let fat_entries = bpb.sectors_per_fat as u64 * bpb.bytes_per_sector as u64 / 4;
+        let end_cluster = (data_clusters + 2).min(fat_entries) as u32;
+        if bpb.root_directory_cluster & 0x0FFF_FFFF >= end_cluster {
+            return Err(Error::BadField("root_directory_cluster"));
+        }
+        let mut fat = VFat {
             phantom: PhantomData,
-            device: CachedPartition::new(device, Partition {
+            device: Locked::new(CachedPartition::new(device, Partition {
                 start: bpb_sector,
                 num_sectors: bpb.total_logical_sectors as u64,
                 sector_size: bpb.bytes_per_sector as u64,
-            }),
+            })),
+            fat: Locked::new(Fat { next_free: 2 }),
+            state: Locked::new(State { options: Default::default(), last_read: None }),
             bytes_per_sector: bpb.bytes_per_sector,
             sectors_per_cluster: bpb.sectors_per_cluster,
             sectors_per_fat: bpb.sectors_per_fat,
+            fats: bpb.fats,
             fat_start_sector: bpb.reserved_sectors as u64,
             data_start_sector: data_start,
             rootdir_cluster: Cluster::from(bpb.root_directory_cluster),
+            end_cluster,
+            volume_label: None,
+            root_metadata: Default::default(),
         };
+        fat.load_root_metadata(&bpb.volume_label);
         Ok(HANDLE::new(fat))
     }
 
@@ -74,77 +135,236 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
         self.bytes_per_sector as usize * self.sectors_per_cluster as usize
     }
 
+    pub fn root_cluster(&self) -> Cluster {
+        self.rootdir_cluster
+    }
+
+    /// Returns the volume label, or `None` if the volume has none.
+    pub fn volume_label(&self) -> Option<&str> {
+        self.volume_label.as_ref().map(|label| label.as_str())
+    }
+
+    /// Returns the options the volume is mounted with.
+    pub fn options(&self) -> MountOptions {
+        self.state.lock(|state| state.options)
+    }
+
+    /// Changes the options the volume is mounted with. Once the volume is
+    /// read-only, modified sectors are written back, since they could not be
+    /// afterwards; if that fails, the options are left as they were.
+    pub fn remount(&self, options: MountOptions) -> io::Result<()> {
+        let old = self.state.lock(|state| core::mem::replace(&mut state.options, options));
+        if options.read_only && !old.read_only {
+            if let Err(e) = self.sync() {
+                self.state.lock(|state| state.options = old);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns a `PermissionDenied` error if the volume is mounted read-only.
+    fn check_writable(&self) -> io::Result<()> {
+        if self.options().read_only {
+            return Err(newioerr!(PermissionDenied, "read-only file system"));
+        }
+        Ok(())
+    }
+
+    /// Sets `volume_label` and `root_metadata` from the volume label record
+    /// in the root directory. Without one, the label is `ebpb_label` and the
+    /// root's timestamps are zero. Errors reading the root directory are
+    /// ignored here; the first lookup in it reports them.
+    fn load_root_metadata(&mut self, ebpb_label: &[u8; 11]) {
+        let mut records = Vec::new();
+        let volume = match self.read_chain(self.rootdir_cluster, &mut records) {
+            Ok(_) => volume_label_record(&records),
+            Err(_) => None,
+        };
+        let label = volume.map_or(*ebpb_label, |(label, _)| label);
+        let label = String::from_utf8_lossy(&label);
+        let label = label.trim_end_matches(|c| c == ' ' || c == '\0');
+        self.volume_label = match label {
+            "" | "NO NAME" => None,
+            label => Some(label.to_string()),
+        };
+        let volume = volume.map(|(_, metadata)| metadata);
+        self.root_metadata = Metadata::root(self.rootdir_cluster.get_value(), volume.as_ref());
+    }
+
+    /// Returns an `InvalidData` error unless `cluster` is a data cluster of
+    /// the volume. Cluster numbers read from disk must be checked before
+    /// they are used to address sectors.
+    fn check_cluster(&self, cluster: Cluster) -> io::Result<()> {
+        if cluster.get_value() < 2 || cluster.get_value() >= self.end_cluster {
+            return Err(newioerr!(InvalidData, "cluster out of range"));
+        }
+        Ok(())
+    }
+
+    /// Returns an `InvalidData` error if a chain has visited more than
+    /// `visited` clusters, which means that it loops.
+    fn check_chain_length(&self, visited: u32) -> io::Result<()> {
+        if visited > self.end_cluster {
+            return Err(newioerr!(InvalidData, "cluster chain loops"));
+        }
+        Ok(())
+    }
+
+    /// Returns the first logical sector of `cluster`.
+    fn cluster_sector(&self, cluster: Cluster) -> u64 {
+        self.sectors_per_cluster as u64 * (cluster.get_value() - 2) as u64 + self.data_start_sector
+    }
+
+    /// Writes all modified sectors back to the disk.
+    pub fn sync(&self) -> io::Result<()> {
+        self.device.lock(|device| device.flush())
+    }
+
+    /// Returns how the sector cache has been used.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.device.lock(|device| device.stats())
+    }
+
     //
     //  * A method to read from an offset of a cluster into a buffer.
     //
     pub fn read_cluster(
-        &mut self,
+        &self,
         cluster: Cluster,
         offset: usize,
         buf: &mut [u8]
     ) -> io::Result<usize> {
-        let mut ctr = 0;
-        let start_sector = offset / self.bytes_per_sector as usize;
-        let mut sector_start_index = offset % self.bytes_per_sector as usize;
-        for i in start_sector..self.sectors_per_cluster as usize {
-            let sector_num = self.sectors_per_cluster as u64 * (cluster.get_value() - 2) as u64 + self.data_start_sector + i as u64;
-            let sector = self.device.get(sector_num)?;
-            for j in sector_start_index..sector.len() {
-                if ctr >= buf.len() {
-                    return Ok(ctr)
+        self.read_cluster_from(cluster, offset, buf, false)
+    }
+
+    /// Like `read_cluster()`, but if `direct` is set, whole sectors that fit
+    /// in `buf` are read with `CachedPartition::read_direct()`. The sector
+    /// cache is locked for the one cluster.
+    fn read_cluster_from(
+        &self,
+        cluster: Cluster,
+        offset: usize,
+        buf: &mut [u8],
+        direct: bool,
+    ) -> io::Result<usize> {
+        self.check_cluster(cluster)?;
+        self.device.lock(|device| {
+            let mut ctr = 0;
+            let sector_size = self.bytes_per_sector as usize;
+            let start_sector = offset / self.bytes_per_sector as usize;
+            let mut sector_start_index = offset % self.bytes_per_sector as usize;
+            for i in start_sector..self.sectors_per_cluster as usize {
+                let sector_num = self.cluster_sector(cluster) + i as u64;
+                if direct && sector_start_index == 0 && buf.len() - ctr >= sector_size {
+                    ctr += device.read_direct(sector_num, &mut buf[ctr..ctr + sector_size])?;
+                    if ctr == buf.len() {
+                        return Ok(ctr);
+                    }
+                    continue;
+                }
+                let sector = device.get(sector_num)?;
+                for j in sector_start_index..sector.len() {
+                    if ctr >= buf.len() {
+                        return Ok(ctr)
+                    }
+                    buf[ctr] = sector[j];
+                    ctr += 1;
                 }
-                buf[ctr] = sector[j];
-                ctr += 1;
+                sector_start_index = 0;
             }
-            sector_start_index = 0;
-        }
-        Ok(ctr)
+            Ok(ctr)
+        })
     }
 
+    //
+    //  * A method to read from `offset` of a file of `file_size` bytes whose
+    //    chain starts at `chain_start`, with `index` holding the clusters of
+    //    the chain found by earlier reads. Reads that start at the beginning
+    //    of the file or where the previous read ended are sequential; they
+    //    start reading the next `READAHEAD` bytes into the cache in the
+    //    background. No lock is held from one cluster to the next, so other
+    //    users of the volume go on during a long read.
+    //
     pub fn read_file(
-        &mut self,
+        &self,
         chain_start: Cluster,
-        offset: usize,
-        file_size: usize,
+        index: &mut ChainIndex,
+        offset: u64,
+        file_size: u64,
         buf: &mut [u8]
     ) -> io::Result<usize> {
-        let mut bytes_to_skip = offset;
-        let mut curr = chain_start;
-        let mut chain_complete = false;
+        let last_read = self.state.lock(|state| state.last_read);
+        let sequential = offset == 0 || last_read == Some((chain_start, offset));
+        let direct = buf.len() >= DIRECT_READ && offset % self.bytes_per_sector as u64 == 0;
+        let read = self.read_file_at(chain_start, index, offset, file_size, buf, direct)?;
+        let end = offset + read as u64;
+        self.state.lock(|state| state.last_read = Some((chain_start, end)));
+        if sequential && !direct && end < file_size {
+            // Readahead is only a hint; errors surface when the data is read.
+            let _ = self.prefetch(chain_start, index, end, READAHEAD.min(saturating_usize(file_size - end)));
+        }
+        Ok(read)
+    }
+
+    fn read_file_at(
+        &self,
+        chain_start: Cluster,
+        index: &mut ChainIndex,
+        offset: u64,
+        file_size: u64,
+        buf: &mut [u8],
+        direct: bool,
+    ) -> io::Result<usize> {
+        let cluster_size = self.get_cluster_size() as u64;
+        let end = buf.len().min(saturating_usize(file_size.saturating_sub(offset)));
         let mut bytes_read = 0;
-        let mut bytes_skipped = 0;
-        while !chain_complete {
-            match self.fat_entry(curr)?.status() {
-                Status::Free => chain_complete = true,
-                Status::Reserved => chain_complete = true,
-                Status::Bad => chain_complete = true,
-                Status::Eoc(_) => {
-                    if bytes_to_skip < self.get_cluster_size() {
-                        bytes_read += self.read_cluster(curr, bytes_to_skip, &mut buf[bytes_read..])?;
-                        bytes_skipped += bytes_to_skip;
-                        if bytes_read + bytes_skipped > file_size {
-                            bytes_read -= bytes_read + bytes_skipped - file_size;
-                        }
-                    }
-                    chain_complete = true;
-                }
-                Status::Data(next) => {
-                    if bytes_to_skip < self.get_cluster_size() {
-                        bytes_read += self.read_cluster(curr, bytes_to_skip, &mut buf[bytes_read..])?;
-                        if bytes_read >= buf.len() {
-                            return Ok(bytes_read);
-                        }
-                        bytes_skipped += bytes_to_skip;
-                        bytes_to_skip = 0;
-                    } else {
-                        bytes_to_skip -= self.get_cluster_size();
-                        bytes_skipped += self.get_cluster_size();
-                    }
+        while bytes_read < end {
+            let position = offset + bytes_read as u64;
+            let n = checked_usize(position / cluster_size)?;
+            let cluster = match self.chain_cluster(chain_start, index, n)? {
+                Some(cluster) => cluster,
+                None => break,
+            };
+            let within = (position % cluster_size) as usize;
+            let read = self.read_cluster_from(cluster, within, &mut buf[bytes_read..end], direct)?;
+            if read == 0 {
+                break;
+            }
+            bytes_read += read;
+        }
+        Ok(bytes_read)
+    }
+
+    /// Returns the cluster at position `n` in the chain starting at `start`,
+    /// or `None` if the chain is shorter. `index` holds the clusters of the
+    /// chain found so far; only the FAT entries past them are read, and the
+    /// clusters they lead to are added.
+    fn chain_cluster(&self, start: Cluster, index: &mut ChainIndex, n: usize) -> io::Result<Option<Cluster>> {
+        index.select(start);
+        if let Some(cluster) = index.get(n) {
+            return Ok(Some(cluster));
+        }
+        if index.is_empty() {
+            if start.get_value() < 2 {
+                return Ok(None);
+            }
+            self.check_cluster(start)?;
+            index.push(start);
+        }
+        let mut curr = index.get(index.len() - 1).expect("index is not empty");
+        while index.len() <= n {
+            self.check_chain_length(index.len() as u32)?;
+            match self.next_cluster(curr)? {
+                Some(next) => {
+                    self.check_cluster(next)?;
+                    index.push(next);
                     curr = next;
                 }
+                None => return Ok(None),
             }
         }
-        Ok(bytes_read)
+        Ok(Some(curr))
     }
 
     //
@@ -152,14 +372,17 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
     //    into a vector.
     //
     pub fn read_chain(
-        &mut self,
+        &self,
         start: Cluster,
         buf: &mut Vec<u8>
     ) -> io::Result<usize> {
         let mut curr = start;
         let mut chain_complete = false;
         let mut bytes_read = 0;
+        let mut visited = 0;
         while !chain_complete {
+            visited += 1;
+            self.check_chain_length(visited)?;
             let f = self.fat_entry(curr)?;
             match f.status() {
                 Status::Free => chain_complete = true,
@@ -183,15 +406,285 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
         }
         Ok(bytes_read)
     }
+
     //
-    //  * A method to return a reference to a `FatEntry` for a cluster where the
-    //    reference points directly into a cached sector.
+    //  * A method to list the physical device sectors holding the first `size`
+    //    bytes of the chain starting at `start`, in order. Writing to these
+    //    sectors directly changes file contents without touching any file
+    //    system metadata.
     //
-    fn fat_entry(&mut self, cluster: Cluster) -> io::Result<&FatEntry> {
+    pub fn chain_sectors(
+        &self,
+        start: Cluster,
+        size: u64
+    ) -> io::Result<Vec<u64>> {
+        let bytes_per_sector = self.bytes_per_sector as u64;
+        let needed = (size + bytes_per_sector - 1) / bytes_per_sector;
+        let mut sectors = Vec::new();
+        let mut curr = start;
+        let mut logical = 0;
+        let mut visited = 0;
+        loop {
+            visited += 1;
+            self.check_chain_length(visited)?;
+            if logical < needed {
+                self.check_cluster(curr)?;
+            }
+            for i in 0..self.sectors_per_cluster as u64 {
+                if logical == needed {
+                    return Ok(sectors);
+                }
+                match self.device.lock(|device| device.physical_sectors(self.cluster_sector(curr) + i)) {
+                    Some(physical) => sectors.extend(physical),
+                    None => return Err(newioerr!(InvalidData, "cluster out of range")),
+                }
+                logical += 1;
+            }
+            if logical == needed {
+                return Ok(sectors);
+            }
+            match self.fat_entry(curr)?.status() {
+                Status::Data(next) => curr = next,
+                _ => return Err(newioerr!(InvalidData, "cluster chain shorter than file")),
+            }
+        }
+    }
+
+    //
+    //  * A method to start reading the sectors holding bytes
+    //    `offset..offset + len` of the chain starting at `start`, whose
+    //    clusters found so far are in `index`, into the cache, without
+    //    waiting for them. FAT sectors needed to follow the chain further are
+    //    read synchronously.
+    //
+    pub fn prefetch(
+        &self,
+        start: Cluster,
+        index: &mut ChainIndex,
+        offset: u64,
+        len: usize
+    ) -> io::Result<()> {
+        if len == 0 {
+            return Ok(());
+        }
+        let bytes_per_sector = self.bytes_per_sector as u64;
+        let cluster_size = self.get_cluster_size() as u64;
+        let end = offset + len as u64;
+        for n in offset / cluster_size..(end + cluster_size - 1) / cluster_size {
+            let cluster = match self.chain_cluster(start, index, checked_usize(n)?)? {
+                Some(cluster) => cluster,
+                None => return Ok(()),
+            };
+            let cluster_start = n * cluster_size;
+            let first = offset.saturating_sub(cluster_start) / bytes_per_sector;
+            let last = ((end - cluster_start).min(cluster_size) + bytes_per_sector - 1) / bytes_per_sector;
+            let sector = self.cluster_sector(cluster);
+            self.device.lock(|device| device.prefetch(sector + first..sector + last));
+        }
+        Ok(())
+    }
+
+    //
+    //  * A method to write `buf` at `offset` of the chain starting at `start`,
+    //    extending the chain as needed. A `start` of cluster 0 denotes an
+    //    empty chain and is replaced by the first allocated cluster. Fails
+    //    with `PermissionDenied` if the volume is read-only.
+    //
+    pub fn write_chain(
+        &self,
+        start: &mut Cluster,
+        offset: usize,
+        buf: &[u8]
+    ) -> io::Result<usize> {
+        self.check_writable()?;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if start.get_value() < 2 {
+            *start = self.alloc_cluster(None)?;
+        }
+        let cluster_size = self.get_cluster_size();
+        let mut curr = *start;
+        let mut offset = offset;
+        while offset >= cluster_size {
+            curr = self.next_or_alloc(curr)?;
+            offset -= cluster_size;
+        }
+        let mut written = 0;
+        loop {
+            written += self.write_cluster(curr, offset, &buf[written..])?;
+            if written == buf.len() {
+                return Ok(written);
+            }
+            offset = 0;
+            curr = self.next_or_alloc(curr)?;
+        }
+    }
+
+    //
+    //  * A method to write `buf` at `offset` of a file whose chain starts at
+    //    `start`, like `write_chain()`, with `index` holding the clusters of
+    //    the chain found so far: the write starts from the last of them
+    //    before `offset` rather than from `start`.
+    //
+    pub fn write_file(
+        &self,
+        start: &mut Cluster,
+        index: &mut ChainIndex,
+        offset: u64,
+        buf: &[u8]
+    ) -> io::Result<usize> {
+        let cluster_size = self.get_cluster_size() as u64;
+        let n = checked_usize(offset / cluster_size)?;
+        // This finds cluster `n`, or every cluster if the chain ends first.
+        self.chain_cluster(*start, index, n)?;
+        if index.is_empty() {
+            return self.write_chain(start, checked_usize(offset)?, buf);
+        }
+        let n = n.min(index.len() - 1);
+        let mut cluster = index.get(n).expect("cluster was found");
+        self.write_chain(&mut cluster, checked_usize(offset - n as u64 * cluster_size)?, buf)
+    }
+
+    //
+    //  * A method to write from a buffer into an offset of a cluster.
+    //
+    fn write_cluster(
+        &self,
+        cluster: Cluster,
+        offset: usize,
+        buf: &[u8]
+    ) -> io::Result<usize> {
+        self.check_cluster(cluster)?;
+        let bytes_per_sector = self.bytes_per_sector as usize;
+        let first_sector = self.cluster_sector(cluster);
+        self.device.lock(|device| {
+            let mut start = offset % bytes_per_sector;
+            let mut written = 0;
+            for i in offset / bytes_per_sector..self.sectors_per_cluster as usize {
+                let sector = device.get_mut(first_sector + i as u64)?;
+                let n = (bytes_per_sector - start).min(buf.len() - written);
+                sector[start..start + n].copy_from_slice(&buf[written..written + n]);
+                written += n;
+                if written == buf.len() {
+                    break;
+                }
+                start = 0;
+            }
+            Ok(written)
+        })
+    }
+
+    /// Returns the cluster after `cluster` in its chain, if any.
+    fn next_cluster(&self, cluster: Cluster) -> io::Result<Option<Cluster>> {
+        match self.fat_entry(cluster)?.status() {
+            Status::Data(next) => Ok(Some(next)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the cluster after `cluster` in its chain, appending a new
+    /// cluster if `cluster` is the last.
+    fn next_or_alloc(&self, cluster: Cluster) -> io::Result<Cluster> {
+        self.fat.lock(|fat| match self.next_cluster(cluster)? {
+            Some(next) => Ok(next),
+            None => self.alloc_in(fat, Some(cluster)),
+        })
+    }
+
+    /// Allocates a zeroed cluster, marks it as the end of its chain, and links
+    /// it after `prev` if given.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `Other` if there are no free clusters and
+    /// `PermissionDenied` if the volume is read-only.
+    pub fn alloc_cluster(&self, prev: Option<Cluster>) -> io::Result<Cluster> {
+        self.check_writable()?;
+        self.fat.lock(|fat| self.alloc_in(fat, prev))
+    }
+
+    /// Like `alloc_cluster()`, with the FAT locked.
+    fn alloc_in(&self, fat: &mut Fat, prev: Option<Cluster>) -> io::Result<Cluster> {
+        let count = self.end_cluster.saturating_sub(2);
+        let mut found = None;
+        for i in 0..count {
+            let candidate = Cluster::from(2 + (fat.next_free - 2 + i) % count);
+            if self.fat_entry(candidate)?.status() == Status::Free {
+                found = Some(candidate);
+                break;
+            }
+        }
+        let cluster = match found {
+            Some(cluster) => cluster,
+            None => return Err(newioerr!(Other, "no free clusters")),
+        };
+        self.set_fat_entry(fat, cluster, 0x0FFF_FFFF)?;
+        if let Some(prev) = prev {
+            self.set_fat_entry(fat, prev, cluster.get_value())?;
+        }
+        let first_sector = self.cluster_sector(cluster);
+        self.device.lock(|device| -> io::Result<()> {
+            for i in 0..self.sectors_per_cluster as u64 {
+                for byte in device.get_mut(first_sector + i)?.iter_mut() {
+                    *byte = 0;
+                }
+            }
+            Ok(())
+        })?;
+        fat.next_free = cluster.get_value() + 1;
+        if fat.next_free >= self.end_cluster {
+            fat.next_free = 2;
+        }
+        Ok(cluster)
+    }
+
+    /// Marks every cluster of the chain starting at `start` as free.
+    pub fn free_chain(&self, start: Cluster) -> io::Result<()> {
+        self.check_writable()?;
+        self.fat.lock(|fat| {
+            let mut curr = Some(start);
+            while let Some(cluster) = curr {
+                if cluster.get_value() < 2 || cluster.get_value() >= self.end_cluster {
+                    break;
+                }
+                curr = self.next_cluster(cluster)?;
+                self.set_fat_entry(fat, cluster, 0)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Sets the FAT entry of `cluster` to `value` in every copy of the FAT,
+    /// preserving the entry's reserved high bits.
+    fn set_fat_entry(&self, _fat: &mut Fat, cluster: Cluster, value: u32) -> io::Result<()> {
+        self.device.lock(|device| {
+            for fat in 0..self.fats as u64 {
+                let fat_start = self.fat_start_sector + fat * self.sectors_per_fat as u64;
+                let sector_number = cluster.fat_table_sector(fat_start, self.bytes_per_sector);
+                let sector = device.get_mut(sector_number)?;
+                let entries = unsafe { sector.cast_mut::<FatEntry>() };
+                let entry = &mut entries[cluster.fat_sector_index(entries.len())];
+                entry.0 = (entry.0 & 0xF000_0000) | (value & 0x0FFF_FFFF);
+            }
+            Ok(())
+        })
+    }
+
+    //
+    //  * A method to return a copy of the `FatEntry` for a cluster, read from
+    //    a cached sector.
+    //
+    fn fat_entry(&self, cluster: Cluster) -> io::Result<FatEntry> {
+        if cluster.get_value() >= self.end_cluster {
+            return Err(newioerr!(InvalidData, "cluster out of range"));
+        }
         let fat_sector_number = cluster.fat_table_sector(self.fat_start_sector, self.bytes_per_sector);
-        let fat_sector = self.device.get(fat_sector_number)?;
-        let fat_entries = unsafe { fat_sector.cast::<FatEntry>() };
-        Ok(&fat_entries[cluster.fat_sector_index(fat_entries.len())])
+        self.device.lock(|device| {
+            let fat_sector = device.get(fat_sector_number)?;
+            let fat_entries = unsafe { fat_sector.cast::<FatEntry>() };
+            Ok(FatEntry(fat_entries[cluster.fat_sector_index(fat_entries.len())].0))
+        })
     }
 }
 
@@ -200,49 +693,27 @@ impl<'a, HANDLE: VFatHandle> FileSystem for &'a HANDLE {
     type Dir = Dir<HANDLE>;
     type Entry = Entry<HANDLE>;
 
-    fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Entry> {
-        let mut dir_stack = Vec::new();
-        let mut a_file = None;
-        for component in path.as_ref().components() {
-            if let Some(_) = a_file {
-                return Err(newioerr!(InvalidInput, "was not directory"));
-            }
-            match component {
-                Component::RootDir => {
-                    dir_stack.push(Dir {
-                        vfat: self.clone(),
-                        first_cluster: self.lock(|vfat| vfat.rootdir_cluster),
-                        name: "".to_string(),
-                        metadata: Default::default(),
-                    });
-                }
-                Component::ParentDir => {
-                    match dir_stack.pop() {
-                        None => return Err(newioerr!(InvalidInput, "no such directory")),
-                        _ => {}
-                    }
-                }
-                Component::Normal(name) => {
-                    if let Some(dir) = dir_stack.last() {
-                        match dir.find(name)? {
-                            Entry::File(f) => a_file = Some(f),
-                            Entry::Dir(d) => {
-                                dir_stack.push(d);
-                            }
-                        }
-                    } else {
-                        return Err(newioerr!(InvalidInput, "path is not absolute"));
-                    }
-                }
-                _ => {}
-            }
+    fn lookup<P: AsRef<Path>>(self, path: P) -> Result<Self::Entry, FsError> {
+        let path = canonical::normalize(path);
+        if !path.has_root() {
+            return Err(FsError::Io(newioerr!(InvalidInput, "path is not absolute")));
         }
-        if let Some(file) = a_file {
-            Ok(Entry::File(file))
-        } else if let Some(dir) = dir_stack.pop() {
-            Ok(Entry::Dir(dir))
-        } else {
-            Err(newioerr!(InvalidInput, "empty path"))
+        let (first_cluster, metadata) = self.with(|vfat| (vfat.rootdir_cluster, vfat.root_metadata));
+        let mut entry = Entry::Dir(Dir {
+            vfat: self.clone(),
+            first_cluster,
+            name: "".to_string(),
+            metadata,
+            location: None,
+        });
+        for component in path.components() {
+            if let Component::Normal(name) = component {
+                entry = match entry {
+                    Entry::Dir(dir) => dir.find(name)?,
+                    Entry::File(_) => return Err(FsError::NotADirectory),
+                };
+            }
         }
+        Ok(entry)
     }
 }
@@ -0,0 +1,84 @@
+//! The gzip container: a header, which may carry a file name and other
+//! optional fields, a DEFLATE stream and a trailer with the CRC-32 and the
+//! size of the data. Only the first member of a multi-member file is read.
+
+use checksum::crc32;
+use crate::{inflate_stream, Error, Result};
+
+const MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+/// The compression method of DEFLATE.
+const DEFLATE: u8 = 8;
+
+/// Header flags announcing optional fields, in the order the fields appear.
+const FHCRC: u8 = 1 << 1;
+const FEXTRA: u8 = 1 << 2;
+const FNAME: u8 = 1 << 3;
+const FCOMMENT: u8 = 1 << 4;
+
+/// The size of the fixed part of the header.
+const HEADER_SIZE: usize = 10;
+
+/// Returns `true` if `data` starts with the gzip magic number.
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// Decodes the gzip file `input` into `output` and checks its trailer.
+/// Returns the number of bytes written.
+pub fn gunzip(input: &[u8], output: &mut [u8]) -> Result<usize> {
+    let start = data_start(input)?;
+    let (read, written) = inflate_stream(&input[start..], output)?;
+    let trailer = input.get(start + read..start + read + 8).ok_or(Error::Truncated)?;
+    let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+    if size != written as u32 {
+        return Err(Error::BadLength);
+    }
+    if crc != crc32(&output[..written]) {
+        return Err(Error::BadChecksum);
+    }
+    Ok(written)
+}
+
+/// Returns the decoded size recorded in the trailer of the gzip file
+/// `input`, modulo 2^32, to size the output of `gunzip()`. `input` must
+/// end with the trailer: it is wrong for a file padded by XMODEM.
+pub fn decoded_size(input: &[u8]) -> Option<usize> {
+    if !is_gzip(input) || input.len() < HEADER_SIZE + 8 {
+        return None;
+    }
+    let trailer = &input[input.len() - 4..];
+    Some(u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]) as usize)
+}
+
+/// Checks the header of the gzip file `input` and returns the offset of its
+/// DEFLATE stream.
+fn data_start(input: &[u8]) -> Result<usize> {
+    if !is_gzip(input) {
+        return Err(Error::NotGzip);
+    }
+    let header = input.get(..HEADER_SIZE).ok_or(Error::Truncated)?;
+    if header[2] != DEFLATE {
+        return Err(Error::UnsupportedMethod);
+    }
+    let flags = header[3];
+    let mut pos = HEADER_SIZE;
+    if flags & FEXTRA != 0 {
+        let len = input.get(pos..pos + 2).ok_or(Error::Truncated)?;
+        pos += 2 + u16::from_le_bytes([len[0], len[1]]) as usize;
+    }
+    for &flag in &[FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let rest = input.get(pos..).ok_or(Error::Truncated)?;
+            pos += rest.iter().position(|&byte| byte == 0).ok_or(Error::Truncated)? + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+    if pos > input.len() {
+        return Err(Error::Truncated);
+    }
+    Ok(pos)
+}
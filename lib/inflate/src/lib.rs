@@ -0,0 +1,319 @@
+#![no_std]
+
+//! A DEFLATE (RFC 1951) and gzip (RFC 1952) decoder that needs no heap.
+//!
+//! The whole input is given at once and the output is written to a slice
+//! the caller sizes, which also serves as the window for back-references,
+//! so the decoder keeps only its Huffman tables, a few hundred bytes, on the
+//! stack. That suits the bootloader, which unpacks a kernel straight to its
+//! load address. The decoder follows zlib's `puff`: it favors size and
+//! simplicity over speed.
+
+#[cfg(test)]
+mod tests;
+
+pub mod gzip;
+
+pub use gzip::{decoded_size, gunzip, is_gzip};
+
+/// An error decoding a stream.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The input ended in the middle of the stream.
+    Truncated,
+    /// The output slice is too small for the decoded data.
+    OutputFull,
+    /// A block has the reserved block type 3.
+    BadBlockType,
+    /// A stored block's length does not match its complement.
+    BadStoredLength,
+    /// A Huffman code is invalid, or the data uses a code it does not define.
+    BadCode,
+    /// A back-reference points before the start of the output.
+    BadDistance,
+    /// The input does not start with the gzip magic number.
+    NotGzip,
+    /// The gzip member uses a compression method other than DEFLATE.
+    UnsupportedMethod,
+    /// The CRC-32 of the decoded data does not match the gzip trailer.
+    BadChecksum,
+    /// The size of the decoded data does not match the gzip trailer.
+    BadLength,
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// The longest Huffman code.
+const MAX_BITS: usize = 15;
+
+/// The most literal/length and distance codes a dynamic block defines.
+const MAX_LITLEN_CODES: usize = 286;
+const MAX_DIST_CODES: usize = 30;
+
+/// The symbols of the fixed literal/length code, including two unused ones.
+/// No code has more.
+const FIXED_LITLEN_CODES: usize = 288;
+
+/// The base lengths and extra bits of length symbols 257-285.
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] =
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+
+/// The base distances and extra bits of distance symbols 0-29.
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] =
+    [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// The order in which a dynamic block lists the code length code's lengths.
+const CODE_LENGTH_ORDER: [usize; 19] =
+    [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// Decodes the raw DEFLATE stream at the start of `input` into `output`.
+/// Returns the number of bytes written.
+pub fn inflate(input: &[u8], output: &mut [u8]) -> Result<usize> {
+    inflate_stream(input, output).map(|(_, written)| written)
+}
+
+/// Decodes the DEFLATE stream at the start of `input` into `output`.
+/// Returns the number of input bytes the stream took, up to its last whole
+/// byte, and the number of bytes written.
+fn inflate_stream(input: &[u8], output: &mut [u8]) -> Result<(usize, usize)> {
+    let mut decoder = Decoder { bits: Bits::new(input), output, written: 0 };
+    loop {
+        let last = decoder.bits.read(1)? == 1;
+        match decoder.bits.read(2)? {
+            0 => decoder.stored()?,
+            1 => decoder.fixed()?,
+            2 => decoder.dynamic()?,
+            _ => return Err(Error::BadBlockType),
+        }
+        if last {
+            return Ok((decoder.bits.pos, decoder.written));
+        }
+    }
+}
+
+/// Reads an input stream bit by bit, least significant bit first.
+struct Bits<'a> {
+    input: &'a [u8],
+    /// The next input byte.
+    pos: usize,
+    buf: u32,
+    count: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn new(input: &'a [u8]) -> Bits<'a> {
+        Bits { input, pos: 0, buf: 0, count: 0 }
+    }
+
+    /// Reads `n` bits, at most 16, as a number.
+    fn read(&mut self, n: u32) -> Result<u32> {
+        while self.count < n {
+            let byte = *self.input.get(self.pos).ok_or(Error::Truncated)?;
+            self.buf |= (byte as u32) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+        let value = self.buf & ((1 << n) - 1);
+        self.buf >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    /// Drops the bits left in the current byte.
+    fn align(&mut self) {
+        self.buf = 0;
+        self.count = 0;
+    }
+
+    /// Reads `n` whole bytes; the stream must be aligned.
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        let bytes = self.input.get(self.pos..self.pos + n).ok_or(Error::Truncated)?;
+        self.pos += n;
+        Ok(bytes)
+    }
+}
+
+/// A canonical Huffman code, as the number of codes of each length and the
+/// symbols ordered by code.
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: [u16; FIXED_LITLEN_CODES],
+}
+
+impl Huffman {
+    /// Builds the code in which symbol `i` has a code `lengths[i]` bits long,
+    /// or none if it is 0. Incomplete codes are accepted: a stream may have a
+    /// single distance code, and decoding fails on a missing code.
+    fn new(lengths: &[u8]) -> Result<Huffman> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(Error::BadCode);
+            }
+        }
+        let mut offsets = [0u16; MAX_BITS + 1];
+        for length in 1..MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = [0u16; FIXED_LITLEN_CODES];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    /// Reads one symbol from `bits`.
+    fn decode(&self, bits: &mut Bits) -> Result<u16> {
+        // `first` is the first code of each length and `index` the position
+        // of its symbol; codes are read most significant bit first.
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= bits.read(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(Error::BadCode)
+    }
+}
+
+struct Decoder<'a, 'b> {
+    bits: Bits<'a>,
+    output: &'b mut [u8],
+    written: usize,
+}
+
+impl<'a, 'b> Decoder<'a, 'b> {
+    fn push(&mut self, byte: u8) -> Result<()> {
+        *self.output.get_mut(self.written).ok_or(Error::OutputFull)? = byte;
+        self.written += 1;
+        Ok(())
+    }
+
+    fn stored(&mut self) -> Result<()> {
+        self.bits.align();
+        let header = self.bits.bytes(4)?;
+        let len = u16::from_le_bytes([header[0], header[1]]);
+        let nlen = u16::from_le_bytes([header[2], header[3]]);
+        if len != !nlen {
+            return Err(Error::BadStoredLength);
+        }
+        let bytes = self.bits.bytes(len as usize)?;
+        let end = self.written + bytes.len();
+        self.output.get_mut(self.written..end).ok_or(Error::OutputFull)?.copy_from_slice(bytes);
+        self.written = end;
+        Ok(())
+    }
+
+    fn fixed(&mut self) -> Result<()> {
+        let mut lengths = [0u8; FIXED_LITLEN_CODES];
+        for (symbol, length) in lengths.iter_mut().enumerate() {
+            *length = match symbol {
+                0..=143 => 8,
+                144..=255 => 9,
+                256..=279 => 7,
+                _ => 8,
+            };
+        }
+        let litlen = Huffman::new(&lengths)?;
+        let dist = Huffman::new(&[5; MAX_DIST_CODES])?;
+        self.codes(&litlen, &dist)
+    }
+
+    fn dynamic(&mut self) -> Result<()> {
+        let nlitlen = self.bits.read(5)? as usize + 257;
+        let ndist = self.bits.read(5)? as usize + 1;
+        let ncode = self.bits.read(4)? as usize + 4;
+        if nlitlen > MAX_LITLEN_CODES || ndist > MAX_DIST_CODES {
+            return Err(Error::BadCode);
+        }
+
+        let mut code_lengths = [0u8; 19];
+        for &symbol in &CODE_LENGTH_ORDER[..ncode] {
+            code_lengths[symbol] = self.bits.read(3)? as u8;
+        }
+        let code = Huffman::new(&code_lengths)?;
+
+        // The literal/length and distance code lengths form one sequence, so
+        // a run may cross from one to the other.
+        let mut lengths = [0u8; MAX_LITLEN_CODES + MAX_DIST_CODES];
+        let mut i = 0;
+        while i < nlitlen + ndist {
+            let symbol = code.decode(&mut self.bits)?;
+            let (length, repeat) = match symbol {
+                0..=15 => (symbol as u8, 1),
+                16 if i == 0 => return Err(Error::BadCode),
+                16 => (lengths[i - 1], 3 + self.bits.read(2)? as usize),
+                17 => (0, 3 + self.bits.read(3)? as usize),
+                _ => (0, 11 + self.bits.read(7)? as usize),
+            };
+            if i + repeat > nlitlen + ndist {
+                return Err(Error::BadCode);
+            }
+            lengths[i..i + repeat].iter_mut().for_each(|l| *l = length);
+            i += repeat;
+        }
+        // Without an end-of-block code the block cannot end.
+        if lengths[256] == 0 {
+            return Err(Error::BadCode);
+        }
+
+        let litlen = Huffman::new(&lengths[..nlitlen])?;
+        let dist = Huffman::new(&lengths[nlitlen..nlitlen + ndist])?;
+        self.codes(&litlen, &dist)
+    }
+
+    /// Decodes a compressed block's data until its end-of-block code.
+    fn codes(&mut self, litlen: &Huffman, dist: &Huffman) -> Result<()> {
+        loop {
+            let symbol = litlen.decode(&mut self.bits)? as usize;
+            if symbol < 256 {
+                self.push(symbol as u8)?;
+                continue;
+            }
+            if symbol == 256 {
+                return Ok(());
+            }
+            let symbol = symbol - 257;
+            if symbol >= LENGTH_BASE.len() {
+                return Err(Error::BadCode);
+            }
+            let length = LENGTH_BASE[symbol] as usize
+                + self.bits.read(LENGTH_EXTRA[symbol] as u32)? as usize;
+            let symbol = dist.decode(&mut self.bits)? as usize;
+            if symbol >= DIST_BASE.len() {
+                return Err(Error::BadCode);
+            }
+            let distance =
+                DIST_BASE[symbol] as usize + self.bits.read(DIST_EXTRA[symbol] as u32)? as usize;
+            if distance > self.written {
+                return Err(Error::BadDistance);
+            }
+            // The copy may overlap what it writes, so it goes byte by byte.
+            for _ in 0..length {
+                let byte = self.output[self.written - distance];
+                self.push(byte)?;
+            }
+        }
+    }
+}
@@ -0,0 +1,61 @@
+extern crate std;
+
+use std::vec;
+
+use crate::gzip::decoded_size;
+use crate::{gunzip, inflate, is_gzip, Error};
+
+/// A gzip file named `words.txt`, compressed with a dynamic Huffman block.
+static WORDS_GZ: &[u8] = include_bytes!("../testdata/words.txt.gz");
+static WORDS: &[u8] = include_bytes!("../testdata/words.txt");
+
+#[test]
+fn inflates_fixed_block() {
+    let input = [0xCB, 0x48, 0xCD, 0xC9, 0xC9, 0x57, 0xC8, 0x40, 0x22, 0xCB, 0xF3, 0x8B, 0x72, 0x52, 0x00];
+    let mut output = [0; 64];
+    let len = inflate(&input, &mut output).expect("valid stream");
+    assert_eq!(&output[..len], b"hello hello hello world");
+}
+
+#[test]
+fn inflates_stored_block() {
+    let input = [0x01, 0x06, 0x00, 0xF9, 0xFF, b's', b't', b'o', b'r', b'e', b'd'];
+    let mut output = [0; 6];
+    assert_eq!(inflate(&input, &mut output), Ok(6));
+    assert_eq!(&output, b"stored");
+
+    let mut bad = input;
+    bad[3] ^= 1;
+    assert_eq!(inflate(&bad, &mut output), Err(Error::BadStoredLength));
+    assert_eq!(inflate(&input[..8], &mut output), Err(Error::Truncated));
+    assert_eq!(inflate(&input, &mut output[..5]), Err(Error::OutputFull));
+}
+
+#[test]
+fn gunzips_dynamic_block() {
+    assert!(is_gzip(WORDS_GZ));
+    assert_eq!(decoded_size(WORDS_GZ), Some(WORDS.len()));
+    let mut output = vec![0; WORDS.len()];
+    assert_eq!(gunzip(WORDS_GZ, &mut output), Ok(WORDS.len()));
+    assert_eq!(&output[..], WORDS);
+    let mut short = vec![0; WORDS.len() - 1];
+    assert_eq!(gunzip(WORDS_GZ, &mut short), Err(Error::OutputFull));
+}
+
+#[test]
+fn rejects_corrupt_gzip() {
+    let mut output = vec![0; WORDS.len()];
+    assert_eq!(gunzip(WORDS, &mut output), Err(Error::NotGzip));
+    assert!(!is_gzip(WORDS));
+
+    let mut bad_crc = WORDS_GZ.to_vec();
+    let len = bad_crc.len();
+    bad_crc[len - 8] ^= 1;
+    assert_eq!(gunzip(&bad_crc, &mut output), Err(Error::BadChecksum));
+
+    let mut bad_method = WORDS_GZ.to_vec();
+    bad_method[2] = 7;
+    assert_eq!(gunzip(&bad_method, &mut output), Err(Error::UnsupportedMethod));
+
+    assert_eq!(gunzip(&WORDS_GZ[..len - 4], &mut output), Err(Error::Truncated));
+}
@@ -0,0 +1,129 @@
+//! A memory allocator for user programs that takes memory from the kernel
+//! with `sbrk`. Install it with
+//!
+//! ```ignore
+//! #[global_allocator]
+//! static HEAP: kernel_api::heap::Heap = kernel_api::heap::Heap::new();
+//! ```
+//!
+//! Blocks are rounded up to a power of two and freed blocks are kept on one
+//! free list per size for reuse; memory is never returned to the kernel.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::ptr;
+
+use crate::syscall::sbrk;
+
+/// The size of the smallest block, which must hold a free list link.
+const MIN_BLOCK: usize = 16;
+
+/// The number of block sizes: `MIN_BLOCK << 0` to `MIN_BLOCK << (BINS - 1)`.
+const BINS: usize = 32;
+
+/// The heap grows by multiples of this many bytes.
+const GROWTH: usize = 64 * 1024;
+
+struct State {
+    /// The first free block of each size; each free block starts with a
+    /// pointer to the next.
+    bins: [*mut usize; BINS],
+    /// The unused part of the memory taken from `sbrk`.
+    next: usize,
+    end: usize,
+}
+
+pub struct Heap(UnsafeCell<State>);
+
+// The state is not locked: a program that starts threads must not allocate
+// from two of them at once.
+unsafe impl Sync for Heap {}
+
+impl Heap {
+    pub const fn new() -> Heap {
+        Heap(UnsafeCell::new(State {
+            bins: [ptr::null_mut(); BINS],
+            next: 0,
+            end: 0,
+        }))
+    }
+}
+
+fn align_up(addr: usize, align: usize) -> Option<usize> {
+    Some(addr.checked_add(align - 1)? & !(align - 1))
+}
+
+/// Returns the bin of blocks that can hold `layout`, if there is one.
+fn bin(layout: &Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align()).max(MIN_BLOCK).checked_next_power_of_two()?;
+    let bin = (size.trailing_zeros() - MIN_BLOCK.trailing_zeros()) as usize;
+    if bin < BINS {
+        Some(bin)
+    } else {
+        None
+    }
+}
+
+impl State {
+    /// Returns `size` unused bytes aligned to `align`, growing the heap if
+    /// there are not enough, or null if the heap cannot grow.
+    unsafe fn carve(&mut self, size: usize, align: usize) -> *mut u8 {
+        let fits = |next: usize, end: usize| match align_up(next, align) {
+            Some(start) if start <= end && end - start >= size => Some(start),
+            _ => None,
+        };
+        let start = match fits(self.next, self.end) {
+            Some(start) => start,
+            None => {
+                let grow = match size.checked_add(align).and_then(|n| align_up(n, GROWTH)) {
+                    Some(grow) => grow,
+                    None => return ptr::null_mut(),
+                };
+                let old_end = match sbrk(grow as isize) {
+                    Ok(old_end) => old_end,
+                    Err(_) => return ptr::null_mut(),
+                };
+                // Something else moved the end of the heap: start over at
+                // the new memory.
+                if old_end != self.end {
+                    self.next = old_end;
+                }
+                self.end = old_end + grow;
+                match fits(self.next, self.end) {
+                    Some(start) => start,
+                    None => return ptr::null_mut(),
+                }
+            }
+        };
+        self.next = start + size;
+        start as *mut u8
+    }
+}
+
+unsafe impl GlobalAlloc for Heap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let state = &mut *self.0.get();
+        let bin = match bin(&layout) {
+            Some(bin) => bin,
+            None => return ptr::null_mut(),
+        };
+        let align = layout.align().max(MIN_BLOCK);
+        let head = state.bins[bin];
+        // A free block is aligned for the layout it was carved for, which
+        // may be less than this one asks for.
+        if !head.is_null() && head as usize % align == 0 {
+            state.bins[bin] = *head as *mut usize;
+            return head as *mut u8;
+        }
+        state.carve(MIN_BLOCK << bin, align)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let state = &mut *self.0.get();
+        if let Some(bin) = bin(&layout) {
+            let block = ptr as *mut usize;
+            *block = state.bins[bin] as usize;
+            state.bins[bin] = block;
+        }
+    }
+}
@@ -6,23 +6,36 @@ use core::fmt;
 
 use shim::io;
 
+#[cfg(feature = "user-space")]
+pub mod heap;
 #[cfg(feature = "user-space")]
 pub mod syscall;
+#[cfg(feature = "user-space")]
+pub mod thread;
 
 pub type OsResult<T> = core::result::Result<T, OsError>;
 
+/// An error returned by a system call. The kernel reports it in `x7` as its
+/// numeric value, which is stable: variants may be added but never
+/// renumbered. `x7` holds `OsError::Ok` when a call succeeds.
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum OsError {
     Unknown = 0,
     Ok = 1,
 
     NoEntry = 10,
+    NotDirectory = 11,
+    IsDirectory = 12,
     NoMemory = 20,
     NoVmSpace = 30,
     NoAccess = 40,
     BadAddress = 50,
     FileExists = 60,
     InvalidArgument = 70,
+    NotSupported = 71,
+    BadDescriptor = 80,
+    TooManyFiles = 90,
+    Interrupted = 100,
 
     IoError = 101,
     IoErrorEof = 102,
@@ -35,23 +48,100 @@ pub enum OsError {
     InvalidPort = 202,
 }
 
+impl OsError {
+    /// Returns the value the kernel places in `x7` for this error.
+    pub fn code(self) -> u64 {
+        self as u64
+    }
+
+    /// Returns the conventional errno name of this error, such as `ENOENT`.
+    pub fn name(self) -> &'static str {
+        match self {
+            OsError::Unknown => "EUNKNOWN",
+            OsError::Ok => "OK",
+            OsError::NoEntry => "ENOENT",
+            OsError::NotDirectory => "ENOTDIR",
+            OsError::IsDirectory => "EISDIR",
+            OsError::NoMemory => "ENOMEM",
+            OsError::NoVmSpace => "ENOSPC",
+            OsError::NoAccess => "EACCES",
+            OsError::BadAddress => "EFAULT",
+            OsError::FileExists => "EEXIST",
+            OsError::InvalidArgument => "EINVAL",
+            OsError::NotSupported => "ENOTSUP",
+            OsError::BadDescriptor => "EBADF",
+            OsError::TooManyFiles => "EMFILE",
+            OsError::Interrupted => "EINTR",
+            OsError::IoError => "EIO",
+            OsError::IoErrorEof => "EIO",
+            OsError::IoErrorInvalidData => "EIO",
+            OsError::IoErrorInvalidInput => "EINVAL",
+            OsError::IoErrorTimedOut => "ETIMEDOUT",
+            OsError::InvalidSocket => "ENOTSOCK",
+            OsError::SocketAlreadyOpen => "EISCONN",
+            OsError::InvalidPort => "EADDRNOTAVAIL",
+        }
+    }
+
+    fn message(self) -> &'static str {
+        match self {
+            OsError::Unknown => "unknown error",
+            OsError::Ok => "success",
+            OsError::NoEntry => "no such file, directory or process",
+            OsError::NotDirectory => "not a directory",
+            OsError::IsDirectory => "is a directory",
+            OsError::NoMemory => "out of memory",
+            OsError::NoVmSpace => "out of virtual address space",
+            OsError::NoAccess => "permission denied",
+            OsError::BadAddress => "bad address",
+            OsError::FileExists => "file exists",
+            OsError::InvalidArgument => "invalid argument",
+            OsError::NotSupported => "operation not supported",
+            OsError::BadDescriptor => "bad file descriptor",
+            OsError::TooManyFiles => "too many open files",
+            OsError::Interrupted => "interrupted",
+            OsError::IoError => "I/O error",
+            OsError::IoErrorEof => "unexpected end of file",
+            OsError::IoErrorInvalidData => "invalid data",
+            OsError::IoErrorInvalidInput => "invalid input",
+            OsError::IoErrorTimedOut => "timed out",
+            OsError::InvalidSocket => "invalid socket",
+            OsError::SocketAlreadyOpen => "socket already open",
+            OsError::InvalidPort => "invalid port",
+        }
+    }
+}
+
+impl fmt::Display for OsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.message(), self.name())
+    }
+}
+
 impl core::convert::From<u64> for OsError {
     fn from(e: u64) -> Self {
         match e {
             1 => OsError::Ok,
 
             10 => OsError::NoEntry,
+            11 => OsError::NotDirectory,
+            12 => OsError::IsDirectory,
             20 => OsError::NoMemory,
             30 => OsError::NoVmSpace,
             40 => OsError::NoAccess,
             50 => OsError::BadAddress,
             60 => OsError::FileExists,
             70 => OsError::InvalidArgument,
+            71 => OsError::NotSupported,
+            80 => OsError::BadDescriptor,
+            90 => OsError::TooManyFiles,
+            100 => OsError::Interrupted,
 
             101 => OsError::IoError,
             102 => OsError::IoErrorEof,
             103 => OsError::IoErrorInvalidData,
             104 => OsError::IoErrorInvalidInput,
+            105 => OsError::IoErrorTimedOut,
 
             200 => OsError::InvalidSocket,
             201 => OsError::SocketAlreadyOpen,
@@ -70,13 +160,177 @@ impl core::convert::From<io::Error> for OsError {
             io::ErrorKind::InvalidInput => OsError::IoErrorInvalidInput,
             io::ErrorKind::TimedOut => OsError::IoErrorTimedOut,
             io::ErrorKind::NotFound => OsError::NoEntry,
+            io::ErrorKind::PermissionDenied => OsError::NoAccess,
+            io::ErrorKind::AlreadyExists => OsError::FileExists,
+            io::ErrorKind::Interrupted => OsError::Interrupted,
             _ => OsError::IoError,
         }
     }
 }
 
+#[cfg(feature = "fat32")]
+impl core::convert::From<fat32::traits::FsError> for OsError {
+    fn from(e: fat32::traits::FsError) -> Self {
+        use fat32::traits::FsError;
+
+        match e {
+            FsError::NotFound => OsError::NoEntry,
+            FsError::NotADirectory => OsError::NotDirectory,
+            FsError::NotAFile => OsError::IsDirectory,
+            FsError::Corrupt => OsError::IoErrorInvalidData,
+            FsError::Io(e) => OsError::from(e),
+        }
+    }
+}
+
 pub const NR_SLEEP: usize = 1;
 pub const NR_TIME: usize = 2;
 pub const NR_EXIT: usize = 3;
 pub const NR_WRITE: usize = 4;
 pub const NR_GETPID: usize = 5;
+pub const NR_SETRLIMIT: usize = 6;
+pub const NR_SETPGID: usize = 7;
+pub const NR_GETPGID: usize = 8;
+pub const NR_TCSETPGRP: usize = 9;
+pub const NR_TCGETPGRP: usize = 10;
+pub const NR_DUP: usize = 11;
+pub const NR_DUP2: usize = 12;
+pub const NR_SBRK: usize = 13;
+pub const NR_PROCINFO: usize = 14;
+pub const NR_WRITE_STR: usize = 15;
+pub const NR_PTRACE_LITE: usize = 16;
+pub const NR_SETAFFINITY: usize = 17;
+pub const NR_SETSCHEDULER: usize = 18;
+pub const NR_WAIT_IRQ: usize = 19;
+pub const NR_TIMER_CREATE: usize = 20;
+pub const NR_TIMER_ARM: usize = 21;
+pub const NR_TIMER_DISARM: usize = 22;
+pub const NR_TIMER_DELETE: usize = 23;
+pub const NR_TIMER_WAIT: usize = 24;
+pub const NR_POLL: usize = 25;
+pub const NR_CAPDROP: usize = 26;
+pub const NR_MOUNT: usize = 27;
+pub const NR_UMOUNT: usize = 28;
+pub const NR_GETHOSTNAME: usize = 29;
+pub const NR_SETHOSTNAME: usize = 30;
+pub const NR_CLONE: usize = 31;
+pub const NR_THREAD_JOIN: usize = 32;
+pub const NR_SPAWN: usize = 33;
+pub const NR_READ_CONSOLE: usize = 34;
+pub const NR_YIELD: usize = 35;
+pub const NR_SLEEP_UNTIL: usize = 36;
+pub const NR_UNAME: usize = 37;
+
+/// The time since boot, the clock `sys_time` reads.
+pub const CLOCK_MONOTONIC: u64 = 0;
+
+/// The most arguments `sys_spawn` passes to a program.
+pub const SPAWN_MAX_ARGS: usize = 16;
+
+/// Memory usage of a process, as returned by `sys_procinfo`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ProcInfo {
+    /// Bytes of memory mapped in the process's address space.
+    pub rss: u64,
+    /// The most bytes that have been mapped at once.
+    pub peak_rss: u64,
+    /// The size of the heap in bytes.
+    pub heap: u64,
+}
+
+/// `sys_setscheduler` policies: round-robin time slicing, fixed priorities
+/// from 1 to 99, and earliest deadline first with a relative deadline in
+/// milliseconds.
+pub const SCHED_NORMAL: u64 = 0;
+pub const SCHED_FIFO: u64 = 1;
+pub const SCHED_DEADLINE: u64 = 2;
+
+/// `sys_ptrace_lite` requests. The first two stop and start logging a
+/// process's syscalls. The others debug a process other than the caller
+/// with the core's debug registers; the process only stops on its own
+/// breakpoints, watchpoints, steps and `brk` instructions:
+///
+///   * `PTRACE_BREAK`: sets breakpoint `arg0` on the instruction at `arg1`,
+///     or clears it if `arg1` is `0`
+///   * `PTRACE_WATCH`: sets watchpoint `arg0` on the `arg0 >> 16` bytes at
+///     `arg1`, which may not cross a multiple of 8, for the accesses whose
+///     `WATCH_*` bits are set in `(arg0 >> 8) & 0xff`, or clears it if `arg1`
+///     is `0`
+///   * `PTRACE_STEP`: resumes the stopped process for one instruction
+///   * `PTRACE_CONT`: resumes the stopped process
+///   * `PTRACE_WAIT`: waits until the process stops and returns a `Stop`
+///   * `PTRACE_DETACH`: clears the breakpoints and watchpoints, and resumes
+///     the process if it is stopped
+pub const PTRACE_SYSCALLS_OFF: u64 = 0;
+pub const PTRACE_SYSCALLS: u64 = 1;
+pub const PTRACE_BREAK: u64 = 2;
+pub const PTRACE_WATCH: u64 = 3;
+pub const PTRACE_STEP: u64 = 4;
+pub const PTRACE_CONT: u64 = 5;
+pub const PTRACE_WAIT: u64 = 6;
+pub const PTRACE_DETACH: u64 = 7;
+
+/// `PTRACE_WATCH` accesses: loads and stores.
+pub const WATCH_LOAD: u64 = 1 << 0;
+pub const WATCH_STORE: u64 = 1 << 1;
+
+/// Why a debugged process stopped, as returned by `PTRACE_WAIT`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Stop {
+    /// A `STOP_*` value.
+    pub reason: u64,
+    /// The address of the next instruction to run, or of the `brk`.
+    pub pc: u64,
+    /// The address accessed, for a watchpoint.
+    pub addr: u64,
+}
+
+/// `Stop` reasons: a breakpoint, the end of a step, a watchpoint and a `brk`
+/// instruction.
+pub const STOP_BREAKPOINT: u64 = 1;
+pub const STOP_STEP: u64 = 2;
+pub const STOP_WATCHPOINT: u64 = 3;
+pub const STOP_BRK: u64 = 4;
+
+/// Capabilities: privileged operations a process may perform on other
+/// processes. Any process may perform them on itself, except where noted.
+///
+/// `CAP_SCHED`: change another process's affinity or scheduling policy, and
+/// give any process, itself included, a `SCHED_FIFO` or `SCHED_DEADLINE`
+/// policy.
+pub const CAP_SCHED: u64 = 1 << 0;
+/// `CAP_PTRACE`: trace another process's syscalls, or debug it.
+pub const CAP_PTRACE: u64 = 1 << 1;
+/// `CAP_CONSOLE`: give the console to a process group other than its own.
+pub const CAP_CONSOLE: u64 = 1 << 2;
+/// `CAP_MOUNT`: mount, remount and unmount the file system.
+pub const CAP_MOUNT: u64 = 1 << 3;
+/// `CAP_HOSTNAME`: change the system's hostname.
+pub const CAP_HOSTNAME: u64 = 1 << 4;
+pub const CAP_ALL: u64 = CAP_SCHED | CAP_PTRACE | CAP_CONSOLE | CAP_MOUNT | CAP_HOSTNAME;
+
+/// The name of each capability.
+pub const CAP_NAMES: &[(&str, u64)] = &[
+    ("sched", CAP_SCHED),
+    ("ptrace", CAP_PTRACE),
+    ("console", CAP_CONSOLE),
+    ("mount", CAP_MOUNT),
+    ("hostname", CAP_HOSTNAME),
+];
+
+/// The longest hostname, in bytes.
+pub const HOST_NAME_MAX: usize = 64;
+
+/// `sys_poll` timeout that waits until a descriptor is ready, however long
+/// that takes.
+pub const POLL_FOREVER: u64 = core::u64::MAX;
+
+/// `sys_setrlimit` resource: CPU time a process may consume, in milliseconds.
+pub const RLIMIT_CPU: u64 = 0;
+
+/// `sys_setrlimit` resource: memory a process may have mapped, in bytes.
+/// Growing the heap past it fails with `NoMemory`.
+pub const RLIMIT_RSS: u64 = 1;
+
+/// `sys_setrlimit` limit value that removes any existing limit.
+pub const RLIM_INFINITY: u64 = core::u64::MAX;
@@ -37,6 +37,33 @@ pub fn sleep(span: Duration) -> OsResult<Duration> {
     err_or!(ecode, Duration::from_millis(elapsed_ms))
 }
 
+/// Sleeps until `deadline`, a time since boot as `time()` returns, and
+/// returns the time it woke at. Returns right away if `deadline` has passed.
+///
+/// Sleeping until the last deadline plus a period keeps a periodic loop in
+/// step, where `sleep(period)` falls behind by the time each pass takes.
+pub fn sleep_until(deadline: Duration) -> OsResult<Duration> {
+    let mut ecode: u64;
+    let mut seconds: u64;
+    let mut nanos: u64;
+
+    unsafe {
+        llvm_asm!("mov x0, $3
+              mov x1, $4
+              mov x2, $5
+              svc $6
+              mov $0, x0
+              mov $1, x1
+              mov $2, x7"
+             : "=r"(seconds), "=r"(nanos), "=r"(ecode)
+             : "r"(CLOCK_MONOTONIC), "r"(deadline.as_secs()), "r"(deadline.subsec_nanos() as u64),
+               "i"(NR_SLEEP_UNTIL)
+             : "x0", "x1", "x2", "x7"
+             : "volatile");
+    }
+    err_or!(ecode, Duration::from_secs(seconds) + Duration::from_nanos(nanos))
+}
+
 pub fn time() -> Duration {
     let mut seconds: u64;
     let mut nanos: u64;
@@ -52,6 +79,18 @@ pub fn time() -> Duration {
     Duration::from_secs(seconds) + Duration::from_nanos(nanos)
 }
 
+/// Gives up the rest of the time slice to the processes ready to run, if
+/// any, for spin loops that wait on another process.
+pub fn yield_now() {
+    unsafe {
+        llvm_asm!("svc $0"
+            :
+            : "i"(NR_YIELD)
+            : "x7"
+            : "volatile");
+    }
+}
+
 pub fn exit() -> ! {
     unsafe {
         llvm_asm!("svc $0"
@@ -74,6 +113,23 @@ pub fn write(b: u8) {
     }
 }
 
+pub fn write_str(s: &str) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut written: u64;
+    unsafe {
+        llvm_asm!("mov x0, $2
+              mov x1, $3
+              svc $4
+              mov $0, x0
+              mov $1, x7"
+            : "=r"(written), "=r"(ecode)
+            : "r"(s.as_ptr()), "r"(s.len()), "i"(NR_WRITE_STR)
+            : "x0", "x1", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, written as usize)
+}
+
 pub fn getpid() -> u64 {
     let mut pid: u64;
     unsafe {
@@ -87,15 +143,595 @@ pub fn getpid() -> u64 {
     pid
 }
 
+pub fn setrlimit(resource: u64, limit: u64) -> OsResult<()> {
+    let mut ecode: u64;
+    unsafe {
+        llvm_asm!("mov x0, $1
+              mov x1, $2
+              svc $3
+              mov $0, x7"
+            : "=r"(ecode)
+            : "r"(resource), "r"(limit), "i"(NR_SETRLIMIT)
+            : "x0", "x1", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, ())
+}
+pub fn setpgid(pgid: u64) -> OsResult<()> {
+    let mut ecode: u64;
+    unsafe {
+        llvm_asm!("mov x0, $1
+              svc $2
+              mov $0, x7"
+            : "=r"(ecode)
+            : "r"(pgid), "i"(NR_SETPGID)
+            : "x0", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, ())
+}
+
+pub fn getpgid() -> OsResult<u64> {
+    let mut ecode: u64;
+    let mut pgid: u64;
+    unsafe {
+        llvm_asm!("svc $2
+              mov $0, x0
+              mov $1, x7"
+            : "=r"(pgid), "=r"(ecode)
+            : "i"(NR_GETPGID)
+            : "x0", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, pgid)
+}
+
+pub fn tcsetpgrp(pgid: u64) -> OsResult<()> {
+    let mut ecode: u64;
+    unsafe {
+        llvm_asm!("mov x0, $1
+              svc $2
+              mov $0, x7"
+            : "=r"(ecode)
+            : "r"(pgid), "i"(NR_TCSETPGRP)
+            : "x0", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, ())
+}
+
+pub fn tcgetpgrp() -> OsResult<u64> {
+    let mut ecode: u64;
+    let mut pgid: u64;
+    unsafe {
+        llvm_asm!("svc $2
+              mov $0, x0
+              mov $1, x7"
+            : "=r"(pgid), "=r"(ecode)
+            : "i"(NR_TCGETPGRP)
+            : "x0", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, pgid)
+}
+
+pub fn dup(fd: u64) -> OsResult<u64> {
+    let mut ecode: u64;
+    let mut new_fd: u64;
+    unsafe {
+        llvm_asm!("mov x0, $2
+              svc $3
+              mov $0, x0
+              mov $1, x7"
+            : "=r"(new_fd), "=r"(ecode)
+            : "r"(fd), "i"(NR_DUP)
+            : "x0", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, new_fd)
+}
+
+pub fn dup2(old_fd: u64, new_fd: u64) -> OsResult<u64> {
+    let mut ecode: u64;
+    let mut fd: u64;
+    unsafe {
+        llvm_asm!("mov x0, $2
+              mov x1, $3
+              svc $4
+              mov $0, x0
+              mov $1, x7"
+            : "=r"(fd), "=r"(ecode)
+            : "r"(old_fd), "r"(new_fd), "i"(NR_DUP2)
+            : "x0", "x1", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, fd)
+}
+
+pub fn sbrk(increment: isize) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut old_end: u64;
+    unsafe {
+        llvm_asm!("mov x0, $2
+              svc $3
+              mov $0, x0
+              mov $1, x7"
+            : "=r"(old_end), "=r"(ecode)
+            : "r"(increment as i64), "i"(NR_SBRK)
+            : "x0", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, old_end as usize)
+}
+
+pub fn procinfo() -> OsResult<ProcInfo> {
+    let mut ecode: u64;
+    let mut rss: u64;
+    let mut peak_rss: u64;
+    let mut heap: u64;
+    unsafe {
+        llvm_asm!("svc $4
+              mov $0, x0
+              mov $1, x1
+              mov $2, x2
+              mov $3, x7"
+            : "=r"(rss), "=r"(peak_rss), "=r"(heap), "=r"(ecode)
+            : "i"(NR_PROCINFO)
+            : "x0", "x1", "x2", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, ProcInfo { rss, peak_rss, heap })
+}
+
+/// Starts or stops logging the syscalls of process `pid`, or of the calling
+/// process if `pid` is `0`, to the kernel log.
+pub fn ptrace_lite(pid: u64, enable: bool) -> OsResult<()> {
+    let mut ecode: u64;
+    unsafe {
+        llvm_asm!("mov x0, $1
+              mov x1, $2
+              svc $3
+              mov $0, x7"
+            : "=r"(ecode)
+            : "r"(pid), "r"(enable as u64), "i"(NR_PTRACE_LITE)
+            : "x0", "x1", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, ())
+}
+
+/// Makes the `PTRACE_*` `request` of process `pid` with arguments `arg0`
+/// and `arg1`. Returns `PTRACE_WAIT`'s `Stop`; for the other requests its
+/// fields are meaningless.
+pub fn ptrace(pid: u64, request: u64, arg0: u64, arg1: u64) -> OsResult<Stop> {
+    let mut ecode: u64;
+    let mut reason: u64;
+    let mut pc: u64;
+    let mut addr: u64;
+    unsafe {
+        llvm_asm!("mov x0, $4
+              mov x1, $5
+              mov x2, $6
+              mov x3, $7
+              svc $8
+              mov $0, x0
+              mov $1, x1
+              mov $2, x2
+              mov $3, x7"
+            : "=r"(reason), "=r"(pc), "=r"(addr), "=r"(ecode)
+            : "r"(pid), "r"(request), "r"(arg0), "r"(arg1), "i"(NR_PTRACE_LITE)
+            : "x0", "x1", "x2", "x3", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, Stop { reason, pc, addr })
+}
+
+/// Sets breakpoint `slot` of process `pid` on the instruction at `addr`, or
+/// clears it if `addr` is `0`.
+pub fn set_breakpoint(pid: u64, slot: u64, addr: u64) -> OsResult<()> {
+    ptrace(pid, PTRACE_BREAK, slot, addr).map(|_| ())
+}
+
+/// Sets watchpoint `slot` of process `pid` on the `len` bytes at `addr`, for
+/// the `WATCH_*` `accesses`, or clears it if `addr` is `0`.
+pub fn set_watchpoint(pid: u64, slot: u64, addr: u64, len: u64, accesses: u64) -> OsResult<()> {
+    ptrace(pid, PTRACE_WATCH, slot | (accesses << 8) | (len << 16), addr).map(|_| ())
+}
+
+/// Waits until process `pid` stops, and returns why.
+pub fn wait_stop(pid: u64) -> OsResult<Stop> {
+    ptrace(pid, PTRACE_WAIT, 0, 0)
+}
+
+/// Resumes the stopped process `pid`, for one instruction if `step`.
+pub fn resume(pid: u64, step: bool) -> OsResult<()> {
+    let request = if step { PTRACE_STEP } else { PTRACE_CONT };
+    ptrace(pid, request, 0, 0).map(|_| ())
+}
+
+/// Restricts process `pid`, or the calling process if `pid` is `0`, to the
+/// cores whose bits are set in `mask`.
+pub fn setaffinity(pid: u64, mask: u64) -> OsResult<()> {
+    let mut ecode: u64;
+    unsafe {
+        llvm_asm!("mov x0, $1
+              mov x1, $2
+              svc $3
+              mov $0, x7"
+            : "=r"(ecode)
+            : "r"(pid), "r"(mask), "i"(NR_SETAFFINITY)
+            : "x0", "x1", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, ())
+}
+
+/// Sets the scheduling policy of process `pid`, or of the calling process if
+/// `pid` is `0`. `param` is the priority for `SCHED_FIFO` and the relative
+/// deadline in milliseconds for `SCHED_DEADLINE`.
+pub fn setscheduler(pid: u64, policy: u64, param: u64) -> OsResult<()> {
+    let mut ecode: u64;
+    unsafe {
+        llvm_asm!("mov x0, $1
+              mov x1, $2
+              mov x2, $3
+              svc $4
+              mov $0, x7"
+            : "=r"(ecode)
+            : "r"(pid), "r"(policy), "r"(param), "i"(NR_SETSCHEDULER)
+            : "x0", "x1", "x2", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, ())
+}
+
+/// Blocks until interrupt number `int`, as `Interrupt::to_index` numbers it,
+/// fires. Only the kernel thread that runs the interrupt's threaded handler
+/// may wait for it; it gets `NoEntry` once the handler is unregistered.
+pub fn wait_irq(int: u64) -> OsResult<()> {
+    let mut ecode: u64;
+    unsafe {
+        llvm_asm!("mov x0, $1
+              svc $2
+              mov $0, x7"
+            : "=r"(ecode)
+            : "r"(int), "i"(NR_WAIT_IRQ)
+            : "x0", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, ())
+}
+
+/// Creates a disarmed interval timer and returns its ID.
+pub fn timer_create() -> OsResult<u64> {
+    let mut ecode: u64;
+    let mut id: u64;
+    unsafe {
+        llvm_asm!("svc $2
+              mov $0, x0
+              mov $1, x7"
+            : "=r"(id), "=r"(ecode)
+            : "i"(NR_TIMER_CREATE)
+            : "x0", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, id)
+}
+
+/// Arms timer `id` to expire after `initial` and then every `interval`, or
+/// only once if `interval` is zero.
+pub fn timer_arm(id: u64, initial: Duration, interval: Duration) -> OsResult<()> {
+    let mut ecode: u64;
+    let initial = initial.as_millis() as u64;
+    let interval = interval.as_millis() as u64;
+    unsafe {
+        llvm_asm!("mov x0, $1
+              mov x1, $2
+              mov x2, $3
+              svc $4
+              mov $0, x7"
+            : "=r"(ecode)
+            : "r"(id), "r"(initial), "r"(interval), "i"(NR_TIMER_ARM)
+            : "x0", "x1", "x2", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, ())
+}
+
+/// Disarms timer `id`.
+pub fn timer_disarm(id: u64) -> OsResult<()> {
+    let mut ecode: u64;
+    unsafe {
+        llvm_asm!("mov x0, $1
+              svc $2
+              mov $0, x7"
+            : "=r"(ecode)
+            : "r"(id), "i"(NR_TIMER_DISARM)
+            : "x0", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, ())
+}
+
+/// Deletes timer `id`, freeing its ID.
+pub fn timer_delete(id: u64) -> OsResult<()> {
+    let mut ecode: u64;
+    unsafe {
+        llvm_asm!("mov x0, $1
+              svc $2
+              mov $0, x7"
+            : "=r"(ecode)
+            : "r"(id), "i"(NR_TIMER_DELETE)
+            : "x0", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, ())
+}
+
+/// Blocks until timer `id` expires and returns the number of expirations
+/// since it was armed or last waited for.
+pub fn timer_wait(id: u64) -> OsResult<u64> {
+    let mut ecode: u64;
+    let mut count: u64;
+    unsafe {
+        llvm_asm!("mov x0, $2
+              svc $3
+              mov $0, x0
+              mov $1, x7"
+            : "=r"(count), "=r"(ecode)
+            : "r"(id), "i"(NR_TIMER_WAIT)
+            : "x0", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, count)
+}
+
+/// Waits until one of the file descriptors whose bits are set in `read` can
+/// be read or one of those in `write` can be written, or `timeout` passes.
+/// Without a timeout, waits for as long as it takes.
+///
+/// Returns the masks of the descriptors that are ready, which are both empty
+/// if the timeout passed first.
+pub fn poll(read: u64, write: u64, timeout: Option<Duration>) -> OsResult<(u64, u64)> {
+    let mut ecode: u64;
+    let mut readable: u64;
+    let mut writable: u64;
+    let timeout = timeout.map_or(POLL_FOREVER, |t| t.as_millis() as u64);
+    unsafe {
+        llvm_asm!("mov x0, $3
+              mov x1, $4
+              mov x2, $5
+              svc $6
+              mov $0, x0
+              mov $1, x1
+              mov $2, x7"
+            : "=r"(readable), "=r"(writable), "=r"(ecode)
+            : "r"(read), "r"(write), "r"(timeout), "i"(NR_POLL)
+            : "x0", "x1", "x2", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, (readable, writable))
+}
+
+/// Gives up the capabilities whose bits are set in `caps` for good, and
+/// returns the capabilities the calling process keeps.
+pub fn capdrop(caps: u64) -> OsResult<u64> {
+    let mut ecode: u64;
+    let mut kept: u64;
+    unsafe {
+        llvm_asm!("mov x0, $2
+              svc $3
+              mov $0, x0
+              mov $1, x7"
+            : "=r"(kept), "=r"(ecode)
+            : "r"(caps), "i"(NR_CAPDROP)
+            : "x0", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, kept)
+}
+
+pub fn mount(options: &str) -> OsResult<()> {
+    let mut ecode: u64;
+    unsafe {
+        llvm_asm!("mov x0, $1
+              mov x1, $2
+              svc $3
+              mov $0, x7"
+            : "=r"(ecode)
+            : "r"(options.as_ptr()), "r"(options.len()), "i"(NR_MOUNT)
+            : "x0", "x1", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, ())
+}
+
+pub fn umount() -> OsResult<()> {
+    let mut ecode: u64;
+    unsafe {
+        llvm_asm!("svc $1
+              mov $0, x7"
+            : "=r"(ecode)
+            : "i"(NR_UMOUNT)
+            : "x7"
+            : "volatile");
+    }
+    err_or!(ecode, ())
+}
+
+/// Copies the system's hostname into `buf` and returns its length. If the
+/// hostname is longer than `buf`, only its first `buf.len()` bytes are
+/// copied.
+pub fn gethostname(buf: &mut [u8]) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut len: u64;
+    unsafe {
+        llvm_asm!("mov x0, $2
+              mov x1, $3
+              svc $4
+              mov $0, x0
+              mov $1, x7"
+            : "=r"(len), "=r"(ecode)
+            : "r"(buf.as_mut_ptr()), "r"(buf.len()), "i"(NR_GETHOSTNAME)
+            : "x0", "x1", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, len as usize)
+}
+
+pub fn sethostname(name: &str) -> OsResult<()> {
+    let mut ecode: u64;
+    unsafe {
+        llvm_asm!("mov x0, $1
+              mov x1, $2
+              svc $3
+              mov $0, x7"
+            : "=r"(ecode)
+            : "r"(name.as_ptr()), "r"(name.len()), "i"(NR_SETHOSTNAME)
+            : "x0", "x1", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, ())
+}
+
+/// Copies what the running kernel was built from into `buf` and returns its
+/// length: its name, release, git revision, build time, cargo features and
+/// compiler version, one per line. If the text is longer than `buf`, only
+/// its first `buf.len()` bytes are copied.
+pub fn uname(buf: &mut [u8]) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut len: u64;
+    unsafe {
+        llvm_asm!("mov x0, $2
+              mov x1, $3
+              svc $4
+              mov $0, x0
+              mov $1, x7"
+            : "=r"(len), "=r"(ecode)
+            : "r"(buf.as_mut_ptr()), "r"(buf.len()), "i"(NR_UNAME)
+            : "x0", "x1", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, len as usize)
+}
+
+/// Starts a thread at `entry` with its stack pointer at `stack` and `arg` in
+/// `x0`, and returns its ID. See `thread::spawn()` for a safe interface.
+pub fn clone(entry: u64, stack: u64, arg: u64) -> OsResult<u64> {
+    let mut ecode: u64;
+    let mut tid: u64;
+    unsafe {
+        llvm_asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+            : "=r"(tid), "=r"(ecode)
+            : "r"(entry), "r"(stack), "r"(arg), "i"(NR_CLONE)
+            : "x0", "x1", "x2", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, tid)
+}
+
+pub fn thread_join(tid: u64) -> OsResult<()> {
+    let mut ecode: u64;
+    unsafe {
+        llvm_asm!("mov x0, $1
+              svc $2
+              mov $0, x7"
+            : "=r"(ecode)
+            : "r"(tid), "i"(NR_THREAD_JOIN)
+            : "x0", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, ())
+}
+
+/// Loads the program at `path`, starts it with the arguments `args` and
+/// returns its process ID. By convention `args[0]` is the program's name.
+/// Returns `InvalidArgument` if there are more than `SPAWN_MAX_ARGS`.
+pub fn spawn(path: &str, args: &[&str]) -> OsResult<u64> {
+    if args.len() > SPAWN_MAX_ARGS {
+        return Err(OsError::InvalidArgument);
+    }
+    let mut argv = [[0u64; 2]; SPAWN_MAX_ARGS];
+    for (slot, arg) in argv.iter_mut().zip(args) {
+        *slot = [arg.as_ptr() as u64, arg.len() as u64];
+    }
+    let mut ecode: u64;
+    let mut pid: u64;
+    unsafe {
+        llvm_asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              mov x3, $5
+              svc $6
+              mov $0, x0
+              mov $1, x7"
+            : "=r"(pid), "=r"(ecode)
+            : "r"(path.as_ptr()), "r"(path.len()), "r"(argv.as_ptr()), "r"(args.len()),
+              "i"(NR_SPAWN)
+            : "x0", "x1", "x2", "x3", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, pid)
+}
+
+/// Reads console input into `buf`, waiting until there is some, and
+/// returns the number of bytes read. Only the console's foreground process
+/// group may read; see `tcsetpgrp()`.
+pub fn read_console(buf: &mut [u8]) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut len: u64;
+    unsafe {
+        llvm_asm!("mov x0, $2
+              mov x1, $3
+              svc $4
+              mov $0, x0
+              mov $1, x7"
+            : "=r"(len), "=r"(ecode)
+            : "r"(buf.as_mut_ptr()), "r"(buf.len()), "i"(NR_READ_CONSOLE)
+            : "x0", "x1", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, len as usize)
+}
+
+/// Reads a line of console input into `buf`, echoing it, and returns its
+/// length without the line ending. Backspace erases the last byte. A line
+/// longer than `buf` is cut short when `buf` fills up.
+pub fn read_line(buf: &mut [u8]) -> OsResult<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        let mut byte = [0];
+        read_console(&mut byte)?;
+        match byte[0] {
+            b'\r' | b'\n' => {
+                write_str("\n")?;
+                break;
+            }
+            8 | 127 if len > 0 => {
+                len -= 1;
+                write_str("\x08 \x08")?;
+            }
+            8 | 127 => (),
+            byte => {
+                buf[len] = byte;
+                len += 1;
+                write(byte);
+            }
+        }
+    }
+    Ok(len)
+}
 
 struct Console;
 
 impl fmt::Write for Console {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for b in s.bytes() {
-            write(b);
-        }
-        Ok(())
+        write_str(s).map(|_| ()).map_err(|_| fmt::Error)
     }
 }
 
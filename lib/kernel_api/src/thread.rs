@@ -0,0 +1,55 @@
+//! Threads: flows of control that share the calling process's memory and
+//! file descriptors.
+//!
+//! ```ignore
+//! static mut STACK: [u8; 4096] = [0; 4096];
+//!
+//! extern "C" fn worker(arg: u64) -> ! {
+//!     // ...
+//!     kernel_api::thread::exit()
+//! }
+//!
+//! let thread = thread::spawn(worker, unsafe { &mut STACK }, 42)?;
+//! thread.join()?;
+//! ```
+//!
+//! A thread ends when it calls `exit()` and must not return from its entry
+//! function. All threads end when the process's first thread exits.
+
+use crate::syscall;
+use crate::OsResult;
+
+/// A running thread.
+#[derive(Debug)]
+pub struct Thread {
+    id: u64,
+}
+
+impl Thread {
+    /// Returns the thread's ID.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Waits for the thread to end.
+    pub fn join(self) -> OsResult<()> {
+        syscall::thread_join(self.id)
+    }
+}
+
+/// Starts a thread that calls `entry` with `arg`, running on `stack`.
+pub fn spawn(
+    entry: extern "C" fn(u64) -> !,
+    stack: &'static mut [u8],
+    arg: u64,
+) -> OsResult<Thread> {
+    // The stack grows down from its end, which must be 16-byte aligned.
+    let top = (stack.as_mut_ptr() as u64 + stack.len() as u64) & !15;
+    let id = syscall::clone(entry as u64, top, arg)?;
+    Ok(Thread { id })
+}
+
+/// Ends the calling thread.
+pub fn exit() -> ! {
+    syscall::exit()
+}
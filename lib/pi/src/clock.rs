@@ -0,0 +1,79 @@
+use volatile::prelude::*;
+use volatile::Volatile;
+
+use crate::common::IO_BASE;
+
+/// The base address for the clock manager registers.
+const CM_REG_BASE: usize = IO_BASE + 0x101000;
+
+/// Must be written to the top byte of every clock manager register write.
+const PASSWORD: u32 = 0x5A << 24;
+
+/// The frequency of the crystal oscillator clock source.
+const OSCILLATOR_HZ: u32 = 19_200_000;
+
+/// Enum representing bit fields of a `CTL` register.
+#[repr(u32)]
+enum Control {
+    Enable = 1 << 4,
+    Busy = 1 << 7,
+}
+
+/// The clock source selecting the crystal oscillator.
+const SOURCE_OSCILLATOR: u32 = 1;
+
+/// The MASH noise-shaping stage 1, needed for fractional divisors.
+const MASH_1: u32 = 1 << 9;
+
+/// A general purpose clock generator feeding a peripheral.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Generator {
+    Pcm,
+    Pwm,
+}
+
+impl Generator {
+    /// Returns the `CTL` and `DIV` registers of this generator.
+    fn registers(self) -> (&'static mut Volatile<u32>, &'static mut Volatile<u32>) {
+        let offset = match self {
+            Generator::Pcm => 0x98,
+            Generator::Pwm => 0xA0,
+        };
+        unsafe {
+            (
+                &mut *((CM_REG_BASE + offset) as *mut Volatile<u32>),
+                &mut *((CM_REG_BASE + offset + 4) as *mut Volatile<u32>),
+            )
+        }
+    }
+
+    /// Stops the clock, waiting for the generator to go idle.
+    pub fn stop(self) {
+        let (ctl, _) = self.registers();
+        ctl.write(PASSWORD | (ctl.read() & !(Control::Enable as u32) & 0xFFFFFF));
+        while ctl.has_mask(Control::Busy as u32) {}
+    }
+
+    /// Runs the clock at `hz`, derived from the 19.2 MHz oscillator with a
+    /// fractional divisor. Returns the rate actually produced, or `None` if
+    /// `hz` cannot be reached (the integer part of the divisor must be at
+    /// least 2 and fit in 12 bits).
+    pub fn start(self, hz: u32) -> Option<u32> {
+        if hz == 0 {
+            return None;
+        }
+        // The divisor in 12.12 fixed point.
+        let divisor = (((OSCILLATOR_HZ as u64) << 12) + hz as u64 / 2) / hz as u64;
+        let integer = divisor >> 12;
+        if integer < 2 || integer > 0xFFF {
+            return None;
+        }
+
+        self.stop();
+        let (ctl, div) = self.registers();
+        div.write(PASSWORD | divisor as u32);
+        ctl.write(PASSWORD | MASH_1 | SOURCE_OSCILLATOR);
+        ctl.write(PASSWORD | MASH_1 | SOURCE_OSCILLATOR | Control::Enable as u32);
+        Some((((OSCILLATOR_HZ as u64) << 12) / divisor) as u32)
+    }
+}
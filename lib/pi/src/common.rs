@@ -2,6 +2,11 @@
 pub const IO_BASE: usize = 0x3F000000;
 pub const IO_BASE_END: usize = 0x40000000;
 
+/// The address where the BCM2836/7 local peripherals (the per-core interrupt
+/// controller, timers and mailboxes) are mapped to.
+pub const LOCAL_BASE: usize = 0x40000000;
+pub const LOCAL_END: usize = 0x40040000;
+
 /// The base address of the `GPIO` registers
 pub const GPIO_BASE: usize = IO_BASE + 0x200000;
 
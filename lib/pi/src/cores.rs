@@ -0,0 +1,42 @@
+//! Waking the secondary cores.
+//!
+//! The firmware parks cores 1 to 3 in a loop that waits, with `wfe`, for an
+//! entry address to appear in their slot of the spin table at
+//! `SPINNING_BASE`: core `n` polls `SPINNING_BASE + 8 * n`, so cores 1 to 3
+//! poll `0xe0`, `0xe8` and `0xf0`. The Pi 3 firmware offers no PSCI, so the
+//! spin table is the only way to start them.
+//!
+//! A woken core starts at the entry address at EL2 (or EL3 with an older
+//! armstub) with its MMU and caches off and no stack.
+
+use aarch64::{clean_dcache_range, sev};
+
+use crate::common::{NCORES, SPINNING_BASE};
+
+/// Makes core `core` jump to `entry`. Returns `false`, doing nothing, for the
+/// boot core or a core that does not exist.
+///
+/// # Safety
+///
+/// `entry` must be code that can run with the MMU off and without a stack,
+/// and each core may only be woken once.
+pub unsafe fn wake(core: usize, entry: usize) -> bool {
+    if core == 0 || core >= NCORES {
+        return false;
+    }
+    let slot = SPINNING_BASE.add(core);
+    slot.write_volatile(entry);
+    // The parked core reads the slot with its caches off.
+    clean_dcache_range(slot as usize, core::mem::size_of::<usize>());
+    sev();
+    true
+}
+
+/// Returns the entry address written to core `core`'s slot, which the
+/// firmware leaves at zero until the core is woken.
+pub fn entry(core: usize) -> Option<usize> {
+    if core >= NCORES {
+        return None;
+    }
+    Some(unsafe { SPINNING_BASE.add(core).read_volatile() })
+}
@@ -0,0 +1,102 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use aarch64::{isb, wfe, CNTFRQ_EL0, CNTKCTL_EL1, CNTVCT_EL0};
+
+use crate::timer;
+
+/// Delays up to this many microseconds are busy-waited on the counter. Longer
+/// delays sleep in `wfe` between event stream ticks.
+const SPIN_THRESHOLD_MICROS: u64 = 100;
+
+/// The counter bit whose transitions generate event stream ticks. At the
+/// Raspberry Pi 3's 19.2 MHz counter, bit 7 ticks roughly every 13us.
+const EVENT_STREAM_BIT: u64 = 7;
+
+/// The calibrated counter frequency in Hz, or 0 if not yet determined.
+static FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the current value of the generic timer's virtual counter.
+#[inline(always)]
+fn counter() -> u64 {
+    // Keep the read from being hoisted above preceding instructions.
+    isb();
+    unsafe { CNTVCT_EL0.get() }
+}
+
+/// Returns the frequency of the generic timer counter in Hz.
+///
+/// The value comes from `CNTFRQ_EL0`, which the firmware sets at boot. If the
+/// firmware left it unset, the counter is calibrated once against the system
+/// timer.
+pub fn frequency() -> u64 {
+    match FREQUENCY.load(Ordering::Relaxed) {
+        0 => {
+            let freq = match unsafe { CNTFRQ_EL0.get() } {
+                0 => calibrate(),
+                freq => freq,
+            };
+            FREQUENCY.store(freq, Ordering::Relaxed);
+            freq
+        }
+        freq => freq,
+    }
+}
+
+/// Measures the counter frequency by counting ticks over one millisecond of
+/// the system timer.
+fn calibrate() -> u64 {
+    let start = timer::current_time();
+    let mut now = start;
+    // Align to a system timer tick so the window is a full millisecond.
+    while now == start {
+        now = timer::current_time();
+    }
+    let (begin, end_time) = (counter(), now + Duration::from_millis(1));
+    while timer::current_time() < end_time {}
+    (counter() - begin) * 1000
+}
+
+/// Enables the event stream so that `wfe` wakes up periodically.
+///
+/// # Safety
+///
+/// Must be called at EL1 or higher.
+unsafe fn enable_event_stream() {
+    let mask = CNTKCTL_EL1::EVNTI | CNTKCTL_EL1::EVNTDIR | CNTKCTL_EL1::EVNTEN;
+    let ctl = CNTKCTL_EL1.get() & !mask;
+    CNTKCTL_EL1.set(ctl | (EVENT_STREAM_BIT << 4) | CNTKCTL_EL1::EVNTEN);
+    isb();
+}
+
+/// Waits for at least `us` microseconds.
+///
+/// Short delays are busy-waited on the CPU's generic timer counter, which
+/// does not touch the peripheral bus. Delays longer than 100us put the core
+/// to sleep with `wfe` between event stream ticks, so the wait may overshoot
+/// by up to one tick (~13us).
+///
+/// Must be called at EL1 or higher.
+pub fn delay_us(us: u64) {
+    let freq = frequency();
+    let ticks = ((us as u128 * freq as u128 + 999_999) / 1_000_000) as u64;
+    let end = counter() + ticks;
+    if us > SPIN_THRESHOLD_MICROS {
+        unsafe { enable_event_stream() };
+        while counter() < end {
+            wfe();
+        }
+    } else {
+        while counter() < end {}
+    }
+}
+
+/// Waits for at least `ms` milliseconds. See `delay_us()`.
+pub fn delay_ms(ms: u64) {
+    delay_us(ms.saturating_mul(1000))
+}
+
+/// Waits for at least `t`. See `delay_us()`.
+pub fn delay(t: Duration) {
+    delay_us(t.as_micros() as u64)
+}
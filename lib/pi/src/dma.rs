@@ -0,0 +1,209 @@
+use volatile::prelude::*;
+use volatile::{Reserved, Volatile};
+
+use crate::common::IO_BASE;
+
+/// The base address for the registers of DMA channel 0. Channel `n` is at
+/// `DMA_REG_BASE + n * 0x100`.
+const DMA_REG_BASE: usize = IO_BASE + 0x7000;
+
+/// The global `ENABLE` register, with one bit per channel.
+const DMA_ENABLE: *mut Volatile<u32> = (IO_BASE + 0x7FF0) as *mut Volatile<u32>;
+
+/// The number of DMA channels that can be used with `Channel`. Channel 15 is
+/// in a separate register block and is not supported.
+pub const NUM_CHANNELS: u8 = 15;
+
+/// Base of the uncached alias of SDRAM as seen from the VideoCore bus.
+const BUS_RAM_BASE: u32 = 0xC000_0000;
+
+/// Base of the peripherals as seen from the VideoCore bus.
+const BUS_IO_BASE: u32 = 0x7E00_0000;
+
+/// Transfer information (`TI`) bits of a control block.
+pub mod ti {
+    /// Raise an interrupt when the transfer completes.
+    pub const INTEN: u32 = 1 << 0;
+    /// Wait for a write response before the next write.
+    pub const WAIT_RESP: u32 = 1 << 3;
+    /// Increment the destination address after each write.
+    pub const DEST_INC: u32 = 1 << 4;
+    /// Pace writes with the peripheral's DREQ signal.
+    pub const DEST_DREQ: u32 = 1 << 6;
+    /// Increment the source address after each read.
+    pub const SRC_INC: u32 = 1 << 8;
+    /// Pace reads with the peripheral's DREQ signal.
+    pub const SRC_DREQ: u32 = 1 << 10;
+
+    /// Returns the `PERMAP` bits selecting peripheral `dreq` for pacing.
+    pub const fn permap(dreq: u32) -> u32 {
+        (dreq & 0x1F) << 16
+    }
+}
+
+/// DREQ peripheral numbers for `ti::permap()`.
+pub mod dreq {
+    pub const PCM_TX: u32 = 2;
+    pub const PCM_RX: u32 = 3;
+    pub const PWM: u32 = 5;
+    pub const EMMC: u32 = 11;
+}
+
+/// Enum representing bit fields of the channel `CS` register.
+#[repr(u32)]
+enum Status {
+    Active = 1 << 0,
+    End = 1 << 1,
+    Int = 1 << 2,
+    WaitForWrites = 1 << 28,
+    Abort = 1 << 30,
+    Reset = 1 << 31,
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    CS: Volatile<u32>,
+    CONBLK_AD: Volatile<u32>,
+    TI: Volatile<u32>,
+    SOURCE_AD: Volatile<u32>,
+    DEST_AD: Volatile<u32>,
+    TXFR_LEN: Volatile<u32>,
+    STRIDE: Volatile<u32>,
+    NEXTCONBK: Volatile<u32>,
+    DEBUG: Volatile<u32>,
+    __r0: [Reserved<u32>; 55],
+}
+
+/// A DMA control block describing one transfer. The DMA engine reads control
+/// blocks directly from memory, so they must be 32-byte aligned and must not
+/// move while a transfer that references them is active.
+#[repr(C, align(32))]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ControlBlock {
+    /// Transfer information; see `ti`.
+    pub ti: u32,
+    /// Bus address of the source.
+    pub source: u32,
+    /// Bus address of the destination.
+    pub dest: u32,
+    /// Transfer length in bytes.
+    pub length: u32,
+    /// 2D stride; unused for linear transfers.
+    pub stride: u32,
+    /// Bus address of the next control block, or 0 to stop.
+    pub next: u32,
+    __reserved: [u32; 2],
+}
+
+impl ControlBlock {
+    /// Returns an empty control block.
+    pub const fn new() -> ControlBlock {
+        ControlBlock {
+            ti: 0,
+            source: 0,
+            dest: 0,
+            length: 0,
+            stride: 0,
+            next: 0,
+            __reserved: [0; 2],
+        }
+    }
+
+    /// Returns a control block that transfers `length` bytes from bus address
+    /// `source` to `dest` as `ti` describes, and stops after.
+    pub const fn transfer(ti: u32, source: u32, dest: u32, length: u32) -> ControlBlock {
+        ControlBlock { ti, source, dest, length, stride: 0, next: 0, __reserved: [0; 2] }
+    }
+}
+
+/// Returns the bus address through which the DMA engine accesses the memory
+/// at physical address `ptr`, bypassing the VideoCore L2 cache.
+pub fn bus_address<T>(ptr: *const T) -> u32 {
+    ptr as usize as u32 | BUS_RAM_BASE
+}
+
+/// Returns the bus address of the peripheral register at physical address
+/// `addr`.
+pub fn peripheral_bus_address(addr: usize) -> u32 {
+    (addr - IO_BASE) as u32 + BUS_IO_BASE
+}
+
+/// A DMA channel.
+pub struct Channel {
+    index: u8,
+    registers: &'static mut Registers,
+}
+
+impl Channel {
+    /// Returns a handle to DMA channel `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` >= `NUM_CHANNELS`.
+    pub fn new(index: u8) -> Channel {
+        if index >= NUM_CHANNELS {
+            panic!("Channel::new(): channel {} exceeds maximum of {}", index, NUM_CHANNELS - 1);
+        }
+        let base = DMA_REG_BASE + index as usize * 0x100;
+        Channel {
+            index,
+            registers: unsafe { &mut *(base as *mut Registers) },
+        }
+    }
+
+    /// Returns this channel's number.
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    /// Enables the channel and resets it, aborting any active transfer.
+    pub fn reset(&mut self) {
+        unsafe { (*DMA_ENABLE).or_mask(1 << self.index) };
+        self.registers.CS.write(Status::Reset as u32);
+        while self.registers.CS.has_mask(Status::Reset as u32) {}
+    }
+
+    /// Starts executing the chain of control blocks beginning at `block`.
+    ///
+    /// # Safety
+    ///
+    /// `block`, every block chained from it, and every buffer they reference
+    /// must stay valid and in place until the transfer completes or the
+    /// channel is stopped. Their contents must have been cleaned from the
+    /// CPU's data cache.
+    pub unsafe fn start(&mut self, block: &ControlBlock) {
+        self.registers.CS.write(Status::End as u32 | Status::Int as u32);
+        self.registers.CONBLK_AD.write(bus_address(block));
+        self.registers.CS.write(Status::Active as u32 | Status::WaitForWrites as u32);
+    }
+
+    /// Aborts the active transfer, if any, and stops the channel.
+    pub fn stop(&mut self) {
+        self.registers.CS.and_mask(!(Status::Active as u32));
+        self.registers.CS.or_mask(Status::Abort as u32);
+        self.reset();
+    }
+
+    /// Returns `true` if the channel is executing a transfer.
+    pub fn is_active(&self) -> bool {
+        self.registers.CS.has_mask(Status::Active as u32)
+    }
+
+    /// Returns the bus address of the control block being executed.
+    pub fn control_block(&self) -> u32 {
+        self.registers.CONBLK_AD.read()
+    }
+
+    /// Clears the channel's interrupt flag, returning `true` if it was set.
+    pub fn take_interrupt(&mut self) -> bool {
+        let cs = self.registers.CS.read();
+        if cs & Status::Int as u32 != 0 {
+            // Writing 0 to ACTIVE would pause the channel, so keep its value.
+            self.registers.CS.write(Status::Int as u32 | (cs & Status::Active as u32));
+            true
+        } else {
+            false
+        }
+    }
+}
@@ -0,0 +1,115 @@
+//! The Maxim DS18B20 temperature sensor, on a 1-Wire bus.
+//!
+//! A reading takes two steps: `convert()` makes the sensors measure, which
+//! takes up to 750ms at the default 12-bit resolution, and `read()` fetches
+//! a sensor's result from its scratchpad. Converting with every sensor
+//! addressed at once measures them all in the time of one.
+
+use core::fmt;
+
+use crate::delay::delay_ms;
+use crate::onewire::{crc8, Error, OneWire, Rom};
+
+/// The family code of the DS18B20.
+pub const FAMILY: u8 = 0x28;
+
+/// Function commands, sent after a ROM command.
+const CONVERT_T: u8 = 0x44;
+const READ_SCRATCHPAD: u8 = 0xBE;
+
+/// How often and how many times `convert()` checks whether the sensors are
+/// done: a conversion takes at most 750ms.
+const POLL_INTERVAL_MS: u64 = 10;
+const MAX_POLLS: usize = 100;
+
+/// A temperature in units of 1/16 degree Celsius, as the sensor reports it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Temperature(pub i16);
+
+impl Temperature {
+    /// Returns the temperature in thousandths of a degree Celsius, rounded
+    /// toward zero.
+    pub fn millicelsius(&self) -> i32 {
+        self.0 as i32 * 1000 / 16
+    }
+}
+
+impl fmt::Display for Temperature {
+    /// Formats the temperature in degrees Celsius, exactly.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = (self.0 as i32).abs();
+        write!(f, "{}{}.{:04}", sign, abs / 16, (abs % 16) * 625)
+    }
+}
+
+/// Returns the temperature in a scratchpad, whose CRC has been checked. Bits
+/// below the configured resolution are undefined and cleared.
+pub fn temperature(scratchpad: &[u8; 9]) -> Temperature {
+    let raw = i16::from_le_bytes([scratchpad[0], scratchpad[1]]);
+    // Bits 6 and 5 of the configuration register select 9 to 12 bits.
+    let undefined = (1 << (3 - ((scratchpad[4] >> 5) & 0b11))) - 1;
+    Temperature(raw & !undefined)
+}
+
+/// Makes the sensor `rom`, or every sensor if `rom` is `None`, measure the
+/// temperature, and waits until they are done.
+///
+/// The sensors must be powered through their VDD pin: parasite-powered ones
+/// need the bus held high during the conversion, which this does not do.
+pub fn convert(bus: &mut OneWire, rom: Option<&Rom>) -> Result<(), Error> {
+    bus.select(rom)?;
+    bus.write_byte(CONVERT_T);
+    // Sensors answer read slots with 0 until they are done.
+    for _ in 0..MAX_POLLS {
+        delay_ms(POLL_INTERVAL_MS);
+        if bus.read_bit() {
+            return Ok(());
+        }
+    }
+    Err(Error::Timeout)
+}
+
+/// Reads the scratchpad of the sensor `rom` and checks its CRC.
+pub fn read_scratchpad(bus: &mut OneWire, rom: &Rom) -> Result<[u8; 9], Error> {
+    bus.select(Some(rom))?;
+    bus.write_byte(READ_SCRATCHPAD);
+    let mut scratchpad = [0; 9];
+    bus.read_bytes(&mut scratchpad);
+    // A sensor that went away reads as all ones, whose CRC is not 0.
+    if crc8(&scratchpad) != 0 {
+        return Err(Error::Crc);
+    }
+    Ok(scratchpad)
+}
+
+/// Returns the temperature the sensor `rom` last measured with `convert()`.
+pub fn read(bus: &mut OneWire, rom: &Rom) -> Result<Temperature, Error> {
+    read_scratchpad(bus, rom).map(|scratchpad| temperature(&scratchpad))
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use std::format;
+
+    use super::{temperature, Temperature};
+
+    #[test]
+    fn temperatures_from_the_datasheet() {
+        let reading = |raw: u16, config: u8| {
+            let [low, high] = raw.to_le_bytes();
+            temperature(&[low, high, 0x4B, 0x46, config, 0xFF, 0x0C, 0x10, 0])
+        };
+        let t = reading(0x0191, 0x7F);
+        assert_eq!((t.millicelsius(), format!("{}", t)), (25062, "25.0625".into()));
+        let t = reading(0xFF5E, 0x7F);
+        assert_eq!((t.millicelsius(), format!("{}", t)), (-10125, "-10.1250".into()));
+        assert_eq!(format!("{}", Temperature(-8)), "-0.5000");
+        assert_eq!(reading(0xFC90, 0x7F).millicelsius(), -55000);
+
+        // At 9 bits, only half degrees count.
+        assert_eq!(reading(0x0197, 0x1F), Temperature(0x0190));
+    }
+}
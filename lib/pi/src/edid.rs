@@ -0,0 +1,234 @@
+//! Parsing of the EDID a display reports over HDMI: the video modes it
+//! supports and which of them it prefers.
+//!
+//! Only the 128-byte base block is read. Its modes come from three tables:
+//! up to four detailed timings, the first of which is the display's native
+//! mode; up to eight standard timings, given as a width, an aspect ratio and
+//! a refresh rate; and a bitmap of established VESA modes.
+
+use core::fmt;
+
+/// The size of an EDID block.
+pub const BLOCK_SIZE: usize = 128;
+
+const HEADER: [u8; 8] = [0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00];
+
+/// The offsets of the established timings bitmap, the standard timings and
+/// the four 18-byte descriptors.
+const ESTABLISHED: usize = 35;
+const STANDARD: usize = 38;
+const DESCRIPTORS: usize = 54;
+const DESCRIPTOR_SIZE: usize = 18;
+
+/// The tag of a display descriptor holding the monitor's name.
+const MONITOR_NAME: u8 = 0xFC;
+
+/// The modes of the established timings bitmap, most significant bit of
+/// its first byte first. Interlaced 1024x768 at 87Hz is left out.
+const ESTABLISHED_MODES: [Option<(u32, u32, u32)>; 17] = [
+    Some((720, 400, 70)),
+    Some((720, 400, 88)),
+    Some((640, 480, 60)),
+    Some((640, 480, 67)),
+    Some((640, 480, 72)),
+    Some((640, 480, 75)),
+    Some((800, 600, 56)),
+    Some((800, 600, 60)),
+    Some((800, 600, 72)),
+    Some((800, 600, 75)),
+    Some((832, 624, 75)),
+    None,
+    Some((1024, 768, 60)),
+    Some((1024, 768, 70)),
+    Some((1024, 768, 75)),
+    Some((1280, 1024, 75)),
+    Some((1152, 870, 75)),
+];
+
+/// An error parsing an EDID block.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The block does not start with the EDID header.
+    BadHeader,
+    /// The bytes of the block do not sum to 0.
+    BadChecksum,
+}
+
+/// A video mode: a resolution in pixels and a refresh rate in Hz.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Mode {
+    pub width: u32,
+    pub height: u32,
+    pub refresh: u32,
+}
+
+impl Mode {
+    pub fn new(width: u32, height: u32, refresh: u32) -> Mode {
+        Mode { width, height, refresh }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}@{}", self.width, self.height, self.refresh)
+    }
+}
+
+/// A parsed EDID base block.
+#[derive(Clone)]
+pub struct Edid {
+    block: [u8; BLOCK_SIZE],
+}
+
+impl Edid {
+    /// Checks the header and checksum of `block`.
+    pub fn parse(block: &[u8; BLOCK_SIZE]) -> Result<Edid, Error> {
+        if block[..8] != HEADER {
+            return Err(Error::BadHeader);
+        }
+        if block.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0 {
+            return Err(Error::BadChecksum);
+        }
+        Ok(Edid { block: *block })
+    }
+
+    /// Returns the three-letter PNP ID of the display's manufacturer.
+    pub fn manufacturer(&self) -> [u8; 3] {
+        let id = u16::from_be_bytes([self.block[8], self.block[9]]);
+        let letter = |shift: u16| b'A' - 1 + ((id >> shift) & 0x1F) as u8;
+        [letter(10), letter(5), letter(0)]
+    }
+
+    /// Returns the monitor's name, if it reports one.
+    pub fn name(&self) -> Option<&str> {
+        let descriptor = self.descriptors().find(|d| d[..3] == [0; 3] && d[3] == MONITOR_NAME)?;
+        let text = &descriptor[5..];
+        let end = text.iter().position(|&byte| byte == b'\n').unwrap_or(text.len());
+        core::str::from_utf8(&text[..end]).ok().map(str::trim_end)
+    }
+
+    fn descriptors(&self) -> impl Iterator<Item = &[u8]> {
+        self.block[DESCRIPTORS..DESCRIPTORS + 4 * DESCRIPTOR_SIZE].chunks(DESCRIPTOR_SIZE)
+    }
+
+    /// Returns the display's preferred mode: its first detailed timing.
+    pub fn preferred(&self) -> Option<Mode> {
+        self.descriptors().next().and_then(detailed_timing)
+    }
+
+    /// Returns every mode the block lists, the preferred one first.
+    /// Modes listed in more than one table are returned more than once.
+    pub fn modes(&self) -> impl Iterator<Item = Mode> + '_ {
+        let detailed = self.descriptors().filter_map(detailed_timing);
+        let standard = self.block[STANDARD..DESCRIPTORS].chunks(2).filter_map(standard_timing);
+        let established = ESTABLISHED_MODES.iter().enumerate().filter_map(move |(i, mode)| {
+            let byte = self.block[ESTABLISHED + i / 8];
+            match mode {
+                Some((width, height, refresh)) if byte & (0x80 >> (i % 8)) != 0 => {
+                    Some(Mode::new(*width, *height, *refresh))
+                }
+                _ => None,
+            }
+        });
+        detailed.chain(standard).chain(established)
+    }
+}
+
+impl fmt::Debug for Edid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Edid")
+            .field("manufacturer", &core::str::from_utf8(&self.manufacturer()))
+            .field("name", &self.name())
+            .field("preferred", &self.preferred())
+            .finish()
+    }
+}
+
+/// Returns the mode of a detailed timing descriptor, or `None` if it is a
+/// display descriptor.
+fn detailed_timing(d: &[u8]) -> Option<Mode> {
+    let clock = u16::from_le_bytes([d[0], d[1]]) as u64 * 10_000;
+    if clock == 0 {
+        return None;
+    }
+    let width = d[2] as u32 | ((d[4] as u32 & 0xF0) << 4);
+    let h_blank = d[3] as u32 | ((d[4] as u32 & 0x0F) << 8);
+    let height = d[5] as u32 | ((d[7] as u32 & 0xF0) << 4);
+    let v_blank = d[6] as u32 | ((d[7] as u32 & 0x0F) << 8);
+    let total = (width + h_blank) as u64 * (height + v_blank) as u64;
+    if total == 0 {
+        return None;
+    }
+    let refresh = (clock + total / 2) / total;
+    Some(Mode::new(width, height, refresh as u32))
+}
+
+/// Returns the mode of a standard timing, or `None` if the entry is unused.
+fn standard_timing(t: &[u8]) -> Option<Mode> {
+    if t[0] == 0x00 || t[0] == 0x01 && t[1] == 0x01 {
+        return None;
+    }
+    let width = (t[0] as u32 + 31) * 8;
+    let height = match t[1] >> 6 {
+        0 => width * 10 / 16,
+        1 => width * 3 / 4,
+        2 => width * 4 / 5,
+        _ => width * 9 / 16,
+    };
+    Some(Mode::new(width, height, (t[1] & 0x3F) as u32 + 60))
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+
+    /// Returns a block from "DEL" named "TEST" that prefers 1920x1200 at
+    /// 60Hz and also lists 1280x1024 and 1600x1200 at 60Hz, 640x480 at 60Hz
+    /// and 800x600 at 60Hz, with a valid checksum.
+    fn block() -> [u8; BLOCK_SIZE] {
+        let mut block = [0; BLOCK_SIZE];
+        block[..8].copy_from_slice(&HEADER);
+        block[8..10].copy_from_slice(&[0x10, 0xAC]);
+        block[ESTABLISHED] = 0x21;
+        let standard = [0x81, 0x80, 0xA9, 0x40, 0x01, 0x01, 0x01, 0x01];
+        block[STANDARD..STANDARD + 8].copy_from_slice(&standard);
+        block[STANDARD + 8..DESCRIPTORS].iter_mut().for_each(|byte| *byte = 0x01);
+        // 154MHz, 1920+160 by 1200+35.
+        let timing = [0x28, 0x3C, 0x80, 0xA0, 0x70, 0xB0, 0x23, 0x40];
+        block[DESCRIPTORS..DESCRIPTORS + 8].copy_from_slice(&timing);
+        let name = &mut block[DESCRIPTORS + DESCRIPTOR_SIZE..][..DESCRIPTOR_SIZE];
+        name[3] = MONITOR_NAME;
+        name[5..].copy_from_slice(b"TEST\n        ");
+        let sum = block.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        block[BLOCK_SIZE - 1] = 0u8.wrapping_sub(sum);
+        block
+    }
+
+    #[test]
+    fn parses_a_monitor() {
+        let edid = Edid::parse(&block()).expect("valid EDID");
+        assert_eq!(&edid.manufacturer(), b"DEL");
+        assert_eq!(edid.name(), Some("TEST"));
+        assert_eq!(edid.preferred(), Some(Mode::new(1920, 1200, 60)));
+        let modes: Vec<Mode> = edid.modes().collect();
+        assert_eq!(modes[0], Mode::new(1920, 1200, 60));
+        assert!(modes.contains(&Mode::new(1280, 1024, 60)));
+        assert!(modes.contains(&Mode::new(1600, 1200, 60)));
+        assert!(modes.contains(&Mode::new(640, 480, 60)));
+        assert!(modes.contains(&Mode::new(800, 600, 60)));
+        assert_eq!(modes.len(), 5);
+    }
+
+    #[test]
+    fn rejects_corrupt_blocks() {
+        let mut block = block();
+        block[20] ^= 1;
+        assert_eq!(Edid::parse(&block).err(), Some(Error::BadChecksum));
+        block[0] = 1;
+        assert_eq!(Edid::parse(&block).err(), Some(Error::BadHeader));
+    }
+}
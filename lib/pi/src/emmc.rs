@@ -0,0 +1,496 @@
+use core::time::Duration;
+
+use volatile::prelude::*;
+use volatile::{ReadVolatile, Reserved, Volatile};
+
+use crate::common::IO_BASE;
+use crate::delay::delay_ms;
+use crate::dma::{self, dreq, ti, Channel, ControlBlock};
+use crate::gpio::{Event, Function, Gpio, Pull};
+use crate::mailbox::{self, Clock};
+use crate::timer::current_time;
+
+/// The base address for the EMMC (SD host) controller registers.
+const EMMC_REG_BASE: usize = IO_BASE + 0x300000;
+
+/// The size of a block transferred by `read_block()` and `write_block()`.
+pub const BLOCK_SIZE: usize = 512;
+
+/// The EMMC base clock used when the VideoCore cannot be asked for it.
+const DEFAULT_BASE_CLOCK: u32 = 41_666_666;
+
+/// The SD clock during card identification.
+const IDENTIFICATION_CLOCK: u32 = 400_000;
+
+/// The SD clock in data transfer mode.
+const TRANSFER_CLOCK: u32 = 25_000_000;
+
+/// How long to wait for a command or data transfer to complete.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    ARG2: Volatile<u32>,
+    BLKSIZECNT: Volatile<u32>,
+    ARG1: Volatile<u32>,
+    CMDTM: Volatile<u32>,
+    RESP: [ReadVolatile<u32>; 4],
+    DATA: Volatile<u32>,
+    STATUS: ReadVolatile<u32>,
+    CONTROL0: Volatile<u32>,
+    CONTROL1: Volatile<u32>,
+    INTERRUPT: Volatile<u32>,
+    IRPT_MASK: Volatile<u32>,
+    IRPT_EN: Volatile<u32>,
+    CONTROL2: Volatile<u32>,
+    __r0: [Reserved<u32>; 47],
+    SLOTISR_VER: ReadVolatile<u32>,
+}
+
+/// Enum representing bit fields of the `STATUS` register.
+#[repr(u32)]
+enum Status {
+    CmdInhibit = 1 << 0,
+    DatInhibit = 1 << 1,
+}
+
+/// Enum representing bit fields of the `INTERRUPT` register.
+#[repr(u32)]
+enum Int {
+    CmdDone = 1 << 0,
+    DataDone = 1 << 1,
+    WriteReady = 1 << 4,
+    ReadReady = 1 << 5,
+    CmdTimeout = 1 << 16,
+    DataTimeout = 1 << 20,
+}
+
+/// All error bits of the `INTERRUPT` register.
+const INT_ERROR_MASK: u32 = 0x017E_8000;
+
+/// Enum representing bit fields of the `CONTROL0` register.
+#[repr(u32)]
+enum Control0 {
+    /// 4-bit data bus.
+    DataWidth4 = 1 << 1,
+}
+
+/// Enum representing bit fields of the `CONTROL1` register.
+#[repr(u32)]
+enum Control1 {
+    ClockInternal = 1 << 0,
+    ClockStable = 1 << 1,
+    ClockEnable = 1 << 2,
+    /// Maximum data timeout.
+    TimeoutMax = 0xE << 16,
+    ResetHost = 1 << 24,
+    ResetCmd = 1 << 25,
+}
+
+/// The host controller specification version, from `SLOTISR_VER`. Version 3
+/// controllers support a 10-bit clock divisor.
+const HOST_SPEC_V3: u32 = 2;
+
+/// Encodings of the `CMDTM` register for the commands used.
+mod cmd {
+    const RESPONSE_48: u32 = 0b10 << 16;
+    const RESPONSE_48_BUSY: u32 = 0b11 << 16;
+    const RESPONSE_136: u32 = 0b01 << 16;
+    const CRC_CHECK: u32 = 1 << 19;
+    const DATA: u32 = 1 << 21;
+    const READ: u32 = 1 << 4;
+
+    pub const GO_IDLE: u32 = 0;
+    pub const ALL_SEND_CID: u32 = (2 << 24) | RESPONSE_136;
+    pub const SEND_RELATIVE_ADDR: u32 = (3 << 24) | RESPONSE_48 | CRC_CHECK;
+    pub const SELECT_CARD: u32 = (7 << 24) | RESPONSE_48_BUSY | CRC_CHECK;
+    pub const SEND_IF_COND: u32 = (8 << 24) | RESPONSE_48 | CRC_CHECK;
+    pub const SEND_CSD: u32 = (9 << 24) | RESPONSE_136;
+    pub const SET_BLOCKLEN: u32 = (16 << 24) | RESPONSE_48 | CRC_CHECK;
+    pub const READ_SINGLE_BLOCK: u32 = (17 << 24) | RESPONSE_48 | CRC_CHECK | DATA | READ;
+    pub const WRITE_SINGLE_BLOCK: u32 = (24 << 24) | RESPONSE_48 | CRC_CHECK | DATA;
+    pub const APP_CMD: u32 = 55 << 24;
+    pub const APP_CMD_RCA: u32 = (55 << 24) | RESPONSE_48 | CRC_CHECK;
+
+    pub const SET_BUS_WIDTH: u32 = (6 << 24) | RESPONSE_48 | CRC_CHECK;
+    pub const SD_SEND_OP_COND: u32 = (41 << 24) | RESPONSE_48;
+    pub const SEND_SCR: u32 = (51 << 24) | RESPONSE_48 | CRC_CHECK | DATA | READ;
+}
+
+/// Error bits of a card status (R1) response.
+const R1_ERRORS_MASK: u32 = 0xFFF9_C004;
+
+/// `SEND_IF_COND` argument: 2.7-3.6V and check pattern 0xAA.
+const IF_COND: u32 = 0x1AA;
+
+/// `SD_SEND_OP_COND` argument requesting high capacity support, for cards
+/// that answer `SEND_IF_COND`.
+const OP_COND_HIGH_CAPACITY: u32 = 0x51FF_8000;
+/// `SD_SEND_OP_COND` argument for version 1.x cards, which are all standard
+/// capacity: the 2.7-3.6V window only.
+const OP_COND_V1: u32 = 0x00FF_8000;
+const OCR_READY: u32 = 1 << 31;
+const OCR_HIGH_CAPACITY: u32 = 1 << 30;
+const OCR_VOLTAGE_MASK: u32 = 0x00FF_8000;
+
+/// Bit set in the first word read of the big-endian `SCR` if the card
+/// supports a 4-bit data bus.
+const SCR_BUS_WIDTH_4: u32 = 1 << 10;
+
+/// Error type for SD card operations.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The controller or the card did not respond in time.
+    Timeout,
+    /// The controller reported an error, or the card rejected a command.
+    Command,
+    /// The card does not support the host's voltage range.
+    UnsupportedCard,
+}
+
+/// The EMMC controller with an initialized SD card, in data transfer mode.
+///
+/// All card state lives in the controller, so `Emmc` is `Copy`: a copy can be
+/// used to reach the card from a context that cannot take the lock of the
+/// original, such as a panic handler.
+#[derive(Copy, Clone, Debug)]
+pub struct Emmc {
+    /// Whether the card is addressed in blocks (SDHC/SDXC) rather than bytes.
+    high_capacity: bool,
+    /// The card's capacity in blocks.
+    blocks: u64,
+}
+
+fn registers() -> &'static mut Registers {
+    unsafe { &mut *(EMMC_REG_BASE as *mut Registers) }
+}
+
+/// Waits until `done` returns `true` or `TIMEOUT` elapses.
+fn wait_until<F: FnMut() -> bool>(mut done: F) -> Result<(), Error> {
+    let start = current_time();
+    while !done() {
+        if current_time() - start > TIMEOUT {
+            return Err(Error::Timeout);
+        }
+    }
+    Ok(())
+}
+
+impl Emmc {
+    /// Routes the SD card pins to the controller, resets it, and brings the
+    /// card through identification into data transfer mode.
+    ///
+    /// The caller should assure that the method is invoked only once during
+    /// kernel initialization.
+    pub fn new() -> Result<Emmc, Error> {
+        // Card detect.
+        let mut detect = Gpio::new(47).into_input();
+        detect.set_pull(Pull::Up);
+        detect.enable_detect(Event::High);
+        // Clock, command, and data 0-3.
+        for pin in 48..54 {
+            let mut gpio = Gpio::new(pin).into_alt(Function::Alt3);
+            gpio.set_pull(Pull::Up);
+        }
+
+        let regs = registers();
+        regs.CONTROL0.write(0);
+        regs.CONTROL1.or_mask(Control1::ResetHost as u32);
+        wait_until(|| !regs.CONTROL1.has_mask(Control1::ResetHost as u32))?;
+        regs.CONTROL1.or_mask(Control1::ClockInternal as u32 | Control1::TimeoutMax as u32);
+        delay_ms(10);
+        set_clock(IDENTIFICATION_CLOCK)?;
+        regs.IRPT_EN.write(0xFFFF_FFFF);
+        regs.IRPT_MASK.write(0xFFFF_FFFF);
+
+        command(cmd::GO_IDLE, 0)?;
+        // Version 1.x cards do not know SEND_IF_COND and leave it unanswered.
+        let op_cond = match command(cmd::SEND_IF_COND, IF_COND) {
+            Ok(IF_COND) => OP_COND_HIGH_CAPACITY,
+            Ok(_) => return Err(Error::UnsupportedCard),
+            Err(Error::Timeout) => {
+                regs.CONTROL1.or_mask(Control1::ResetCmd as u32);
+                wait_until(|| !regs.CONTROL1.has_mask(Control1::ResetCmd as u32))?;
+                OP_COND_V1
+            }
+            Err(e) => return Err(e),
+        };
+
+        let start = current_time();
+        let ocr = loop {
+            command(cmd::APP_CMD, 0)?;
+            let ocr = command(cmd::SD_SEND_OP_COND, op_cond)?;
+            if ocr & OCR_READY != 0 {
+                break ocr;
+            }
+            if current_time() - start > TIMEOUT {
+                return Err(Error::Timeout);
+            }
+            delay_ms(10);
+        };
+        if ocr & OCR_VOLTAGE_MASK == 0 {
+            return Err(Error::UnsupportedCard);
+        }
+
+        command(cmd::ALL_SEND_CID, 0)?;
+        let rca = command(cmd::SEND_RELATIVE_ADDR, 0)? & 0xFFFF_0000;
+        command(cmd::SEND_CSD, rca)?;
+        let blocks = csd_blocks(long_response());
+        set_clock(TRANSFER_CLOCK)?;
+        check_r1(command(cmd::SELECT_CARD, rca)?)?;
+        let high_capacity = ocr & OCR_HIGH_CAPACITY != 0;
+        if !high_capacity {
+            // Standard capacity cards may default to their READ_BL_LEN.
+            check_r1(command(cmd::SET_BLOCKLEN, BLOCK_SIZE as u32)?)?;
+        }
+
+        // The SCR is sent as an 8-byte data block.
+        wait_until(|| !regs.STATUS.has_mask(Status::DatInhibit as u32))?;
+        regs.BLKSIZECNT.write((1 << 16) | 8);
+        check_r1(command(cmd::APP_CMD_RCA, rca)?)?;
+        check_r1(command(cmd::SEND_SCR, 0)?)?;
+        wait_for(Int::ReadReady as u32)?;
+        let scr = regs.DATA.read();
+        let _ = regs.DATA.read();
+        wait_for(Int::DataDone as u32)?;
+
+        if scr & SCR_BUS_WIDTH_4 != 0 {
+            check_r1(command(cmd::APP_CMD_RCA, rca)?)?;
+            check_r1(command(cmd::SET_BUS_WIDTH, 2)?)?;
+            regs.CONTROL0.or_mask(Control0::DataWidth4 as u32);
+        }
+
+        Ok(Emmc { high_capacity, blocks })
+    }
+
+    /// Returns the card's capacity in `BLOCK_SIZE` blocks.
+    pub fn blocks(&self) -> u64 {
+        self.blocks
+    }
+
+    /// Returns the command argument addressing block `n`.
+    fn address(&self, n: u32) -> Result<u32, Error> {
+        if self.high_capacity {
+            Ok(n)
+        } else {
+            n.checked_mul(BLOCK_SIZE as u32).ok_or(Error::Command)
+        }
+    }
+
+    /// Reads block `n` into `buf`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buf.len() < BLOCK_SIZE`.
+    pub fn read_block(&mut self, n: u32, buf: &mut [u8]) -> Result<(), Error> {
+        let buf = &mut buf[..BLOCK_SIZE];
+        let regs = registers();
+        wait_until(|| !regs.STATUS.has_mask(Status::DatInhibit as u32))?;
+        regs.BLKSIZECNT.write((1 << 16) | BLOCK_SIZE as u32);
+        check_r1(command(cmd::READ_SINGLE_BLOCK, self.address(n)?)?)?;
+        wait_for(Int::ReadReady as u32)?;
+        for word in buf.chunks_exact_mut(4) {
+            word.copy_from_slice(&regs.DATA.read().to_le_bytes());
+        }
+        wait_for(Int::DataDone as u32)
+    }
+
+    /// Starts reading block `n` into `buf` through the DMA channel `dma`,
+    /// whose transfer is described by `block`, and returns without waiting
+    /// for the data. Call `poll_transfer()` until it reports the outcome
+    /// before issuing another command.
+    ///
+    /// # Safety
+    ///
+    /// `buf` and `block` must stay valid and in place until the transfer
+    /// completes, and `buf` must not be accessed until then. `buf` must have
+    /// been invalidated from the CPU's data cache, and must be invalidated
+    /// again before it is read.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buf.len() < BLOCK_SIZE`.
+    pub unsafe fn start_read_dma(
+        &mut self,
+        n: u32,
+        buf: &mut [u8],
+        dma: &mut Channel,
+        block: &mut ControlBlock,
+    ) -> Result<(), Error> {
+        let buf = &mut buf[..BLOCK_SIZE];
+        let regs = registers();
+        wait_until(|| !regs.STATUS.has_mask(Status::DatInhibit as u32))?;
+        *block = ControlBlock::transfer(
+            ti::WAIT_RESP | ti::DEST_INC | ti::SRC_DREQ | ti::permap(dreq::EMMC),
+            dma::peripheral_bus_address(&regs.DATA as *const _ as usize),
+            dma::bus_address(buf.as_ptr()),
+            BLOCK_SIZE as u32,
+        );
+        aarch64::clean_dcache_range(block as *const _ as usize, core::mem::size_of::<ControlBlock>());
+        dma.reset();
+        dma.start(block);
+        regs.BLKSIZECNT.write((1 << 16) | BLOCK_SIZE as u32);
+        match command(cmd::READ_SINGLE_BLOCK, self.address(n)?).and_then(check_r1) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                dma.stop();
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the outcome of the transfer started by `start_read_dma()`
+    /// with `dma`, or `None` while it is in progress.
+    pub fn poll_transfer(&mut self, dma: &mut Channel) -> Option<Result<(), Error>> {
+        let regs = registers();
+        if regs.INTERRUPT.read() & (Int::DataDone as u32 | INT_ERROR_MASK) == 0 {
+            return None;
+        }
+        let result = wait_for(Int::DataDone as u32);
+        if result.is_err() {
+            dma.stop();
+            return Some(result);
+        }
+        // The card is done once the FIFO is drained; the last words may still
+        // be on their way to memory.
+        Some(wait_until(|| !dma.is_active()))
+    }
+
+    /// Writes the first `BLOCK_SIZE` bytes of `buf` to block `n`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buf.len() < BLOCK_SIZE`.
+    pub fn write_block(&mut self, n: u32, buf: &[u8]) -> Result<(), Error> {
+        let buf = &buf[..BLOCK_SIZE];
+        let regs = registers();
+        wait_until(|| !regs.STATUS.has_mask(Status::DatInhibit as u32))?;
+        regs.BLKSIZECNT.write((1 << 16) | BLOCK_SIZE as u32);
+        check_r1(command(cmd::WRITE_SINGLE_BLOCK, self.address(n)?)?)?;
+        wait_for(Int::WriteReady as u32)?;
+        for word in buf.chunks_exact(4) {
+            regs.DATA.write(u32::from_le_bytes([word[0], word[1], word[2], word[3]]));
+        }
+        // Completes once the card has programmed the block.
+        wait_for(Int::DataDone as u32)
+    }
+}
+
+/// Returns an error if the card status `r1` has any error bits set.
+fn check_r1(r1: u32) -> Result<(), Error> {
+    if r1 & R1_ERRORS_MASK != 0 {
+        Err(Error::Command)
+    } else {
+        Ok(())
+    }
+}
+
+/// Waits for any of the `INTERRUPT` bits in `mask` or an error, then
+/// acknowledges them.
+fn wait_for(mask: u32) -> Result<(), Error> {
+    let regs = registers();
+    let result = wait_until(|| regs.INTERRUPT.read() & (mask | INT_ERROR_MASK) != 0);
+    let int = regs.INTERRUPT.read();
+    if result.is_err() || int & (Int::CmdTimeout as u32 | Int::DataTimeout as u32) != 0 {
+        regs.INTERRUPT.write(int);
+        Err(Error::Timeout)
+    } else if int & INT_ERROR_MASK != 0 {
+        regs.INTERRUPT.write(int);
+        Err(Error::Command)
+    } else {
+        regs.INTERRUPT.write(mask);
+        Ok(())
+    }
+}
+
+/// Sends the command `code` with argument `arg` and returns the first word of
+/// the response.
+fn command(code: u32, arg: u32) -> Result<u32, Error> {
+    let regs = registers();
+    wait_until(|| !regs.STATUS.has_mask(Status::CmdInhibit as u32))?;
+    regs.INTERRUPT.write(regs.INTERRUPT.read());
+    regs.ARG1.write(arg);
+    regs.CMDTM.write(code);
+    wait_for(Int::CmdDone as u32)?;
+    Ok(regs.RESP[0].read())
+}
+
+/// Returns the 136-bit response to the last command. The controller drops
+/// the response's last byte, its CRC, so bit `n` here is bit `n + 8` of the
+/// response.
+fn long_response() -> u128 {
+    let regs = registers();
+    (0..4).fold(0, |response, i| response | (regs.RESP[i].read() as u128) << (32 * i))
+}
+
+/// Returns the capacity in `BLOCK_SIZE` blocks of a card with the CSD
+/// register `csd`, as returned by `long_response()`.
+fn csd_blocks(csd: u128) -> u64 {
+    // Bit positions are 8 below those in the CSD.
+    match (csd >> 118) & 0b11 {
+        // Version 1.0: (C_SIZE + 1) * 2^(C_SIZE_MULT + 2) blocks of
+        // 2^READ_BL_LEN bytes.
+        0 => {
+            let read_bl_len = (csd >> 72) & 0xF;
+            let c_size = (csd >> 54) & 0xFFF;
+            let c_size_mult = (csd >> 39) & 0x7;
+            (((c_size + 1) << (c_size_mult + 2 + read_bl_len)) / BLOCK_SIZE as u128) as u64
+        }
+        // Version 2.0, for SDHC and SDXC: (C_SIZE + 1) * 512KiB.
+        _ => ((((csd >> 40) & 0x3F_FFFF) + 1) * 1024) as u64,
+    }
+}
+
+/// Sets the SD clock to at most `hz`, dividing down the EMMC base clock.
+fn set_clock(hz: u32) -> Result<(), Error> {
+    let regs = registers();
+    let inhibit = Status::CmdInhibit as u32 | Status::DatInhibit as u32;
+    wait_until(|| regs.STATUS.read() & inhibit == 0)?;
+
+    let base = mailbox::clock_rate(Clock::Emmc).unwrap_or(DEFAULT_BASE_CLOCK);
+    // The SD clock is base / (2 * divisor), with divisor 0 meaning 1.
+    let divisor = (base + 2 * hz - 1) / (2 * hz);
+    let version = (regs.SLOTISR_VER.read() >> 16) & 0xFF;
+    let bits = if version >= HOST_SPEC_V3 {
+        // 10-bit divided clock mode.
+        let divisor = divisor.min(0x3FF);
+        ((divisor & 0xFF) << 8) | ((divisor & 0x300) >> 2)
+    } else {
+        // 8-bit power-of-two divisor.
+        let divisor = divisor.next_power_of_two().min(0x80);
+        divisor << 8
+    };
+
+    regs.CONTROL1.and_mask(!(Control1::ClockEnable as u32));
+    delay_ms(10);
+    regs.CONTROL1.write((regs.CONTROL1.read() & 0xFFFF_003F) | bits);
+    delay_ms(10);
+    regs.CONTROL1.or_mask(Control1::ClockEnable as u32);
+    wait_until(|| regs.CONTROL1.has_mask(Control1::ClockStable as u32))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn csd_capacity() {
+        // A 32GB SDHC card: version 2.0 with C_SIZE 0xEDC7.
+        let csd = (1u128 << 118) | (0xEDC7 << 40);
+        assert_eq!(csd_blocks(csd), (0xEDC7 + 1) * 1024);
+        // A 1GB version 1.0 card: C_SIZE 0xF13, C_SIZE_MULT 7, 1KiB blocks.
+        let csd = (10u128 << 72) | (0xF13 << 54) | (7 << 39);
+        assert_eq!(csd_blocks(csd), (0xF13 + 1) * 512 * 2);
+    }
+
+    #[test]
+    fn block_addresses() {
+        let sdhc = Emmc { high_capacity: true, blocks: 1 << 26 };
+        assert_eq!(sdhc.address(3), Ok(3));
+        // Standard capacity cards are addressed in bytes.
+        let sdsc = Emmc { high_capacity: false, blocks: 1 << 21 };
+        assert_eq!(sdsc.address(3), Ok(3 * 512));
+        assert_eq!(sdsc.address(1 << 23), Err(Error::Command));
+    }
+}
@@ -0,0 +1,118 @@
+//! The framebuffer the VideoCore scans out over HDMI.
+//!
+//! The firmware allocates it in its own memory, at the end of RAM, with the
+//! size asked for, and drives the display in the closest mode it supports,
+//! scaling the image if they differ. Pixels are 16-bit RGB565.
+//!
+//! The memory is shared with the GPU, so it must be mapped uncached or
+//! cleaned from the data cache after writing; `write_row()` uses aligned
+//! volatile stores so that a device memory mapping works.
+
+use core::ptr;
+
+use crate::mailbox::Mailbox;
+
+/// Framebuffer property tags.
+const TAG_ALLOCATE: u32 = 0x0004_0001;
+const TAG_GET_PHYSICAL_SIZE: u32 = 0x0004_0003;
+const TAG_GET_PITCH: u32 = 0x0004_0008;
+const TAG_RELEASE: u32 = 0x0004_8001;
+const TAG_SET_PHYSICAL_SIZE: u32 = 0x0004_8003;
+const TAG_SET_VIRTUAL_SIZE: u32 = 0x0004_8004;
+const TAG_SET_DEPTH: u32 = 0x0004_8005;
+
+/// The bits per pixel.
+const DEPTH: u32 = 16;
+
+/// The alignment asked of the framebuffer's address.
+const ALIGNMENT: u32 = 16;
+
+/// Clears the bits that select the VideoCore's cache alias of a bus
+/// address, leaving the ARM physical address.
+const BUS_ADDRESS_MASK: u32 = 0x3FFF_FFFF;
+
+/// A framebuffer allocated by the firmware.
+#[derive(Debug)]
+pub struct Framebuffer {
+    base: usize,
+    size: usize,
+    width: usize,
+    height: usize,
+    /// The distance between rows, in bytes.
+    pitch: usize,
+}
+
+impl Framebuffer {
+    /// Asks the firmware for a `width` x `height` framebuffer and shows it.
+    /// Returns `None` if the firmware refuses; the previous framebuffer, if
+    /// any, should be released first.
+    pub fn allocate(width: u32, height: u32) -> Option<Framebuffer> {
+        let mut physical = [width, height];
+        let mut virtual_size = [width, height];
+        let mut depth = [DEPTH];
+        let mut buffer = [ALIGNMENT, 0];
+        let mut pitch = [0];
+        Mailbox::new()
+            .properties(&mut [
+                (TAG_SET_PHYSICAL_SIZE, &mut physical[..]),
+                (TAG_SET_VIRTUAL_SIZE, &mut virtual_size[..]),
+                (TAG_SET_DEPTH, &mut depth[..]),
+                (TAG_ALLOCATE, &mut buffer[..]),
+                (TAG_GET_PITCH, &mut pitch[..]),
+            ])
+            .ok()?;
+        if buffer[0] == 0 || depth[0] != DEPTH || physical != [width, height] {
+            return None;
+        }
+        Some(Framebuffer {
+            base: (buffer[0] & BUS_ADDRESS_MASK) as usize,
+            size: buffer[1] as usize,
+            width: width as usize,
+            height: height as usize,
+            pitch: pitch[0] as usize,
+        })
+    }
+
+    /// Hands the framebuffer back to the firmware.
+    pub fn release(self) {
+        let _ = Mailbox::new().property(TAG_RELEASE, &mut []);
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the physical address and size of the framebuffer's memory.
+    pub fn memory(&self) -> (usize, usize) {
+        (self.base, self.size)
+    }
+
+    /// Writes `pixels` to row `y` from column `x`, clipped to the
+    /// framebuffer.
+    pub fn write_row(&mut self, x: usize, y: usize, pixels: &[u16]) {
+        if y >= self.height || x >= self.width {
+            return;
+        }
+        let len = pixels.len().min(self.width - x);
+        let row = (self.base + y * self.pitch) as *mut u16;
+        for (i, &pixel) in pixels[..len].iter().enumerate() {
+            unsafe { ptr::write_volatile(row.add(x + i), pixel) };
+        }
+    }
+}
+
+/// Returns the size of the framebuffer the firmware set up at boot, which is
+/// that of the display's preferred mode unless `config.txt` says otherwise,
+/// or `None` if the firmware could not be queried.
+pub fn boot_size() -> Option<(u32, u32)> {
+    let mut size = [0, 0];
+    Mailbox::new().property(TAG_GET_PHYSICAL_SIZE, &mut size).ok()?;
+    match size {
+        [0, _] | [_, 0] => None,
+        [width, height] => Some((width, height)),
+    }
+}
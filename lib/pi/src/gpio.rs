@@ -1,5 +1,7 @@
 use core::marker::PhantomData;
 
+use aarch64::nop;
+
 use crate::common::{states, GPIO_BASE};
 use volatile::prelude::*;
 use volatile::{ReadVolatile, Reserved, Volatile, WriteVolatile};
@@ -17,6 +19,32 @@ pub enum Function {
     Alt5 = 0b010,
 }
 
+/// The pull resistor configuration of a pin.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Pull {
+    Off = 0b00,
+    Down = 0b01,
+    Up = 0b10,
+}
+
+/// A condition that sets a pin's bit in the event detect status register.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A synchronously sampled low-to-high transition.
+    RisingEdge,
+    /// A synchronously sampled high-to-low transition.
+    FallingEdge,
+    /// The pin is high.
+    High,
+    /// The pin is low.
+    Low,
+    /// A low-to-high transition, detected without sampling.
+    AsyncRisingEdge,
+    /// A high-to-low transition, detected without sampling.
+    AsyncFallingEdge,
+}
+
 #[repr(C)]
 #[allow(non_snake_case)]
 struct Registers {
@@ -49,7 +77,7 @@ struct Registers {
 /// Possible states for a GPIO pin.
 #[allow(unused_doc_comments)]
 states! {
-    Uninitialized, Input, Output, Alt
+    Uninitialized, Input, Output, Alt, OpenDrain
 }
 
 /// A GPIO pin in state `State`.
@@ -57,8 +85,9 @@ states! {
 /// The `State` generic always corresponds to an uninstantiatable type that is
 /// use solely to mark and track the state of a given GPIO pin. A `Gpio`
 /// structure starts in the `Uninitialized` state and must be transitions into
-/// one of `Input`, `Output`, or `Alt` via the `into_input`, `into_output`, and
-/// `into_alt` methods before it can be used.
+/// one of `Input`, `Output`, `Alt` or `OpenDrain` via the `into_input`,
+/// `into_output`, `into_alt` and `into_open_drain` methods before it can be
+/// used.
 pub struct Gpio<State> {
     pin: u8,
     registers: &'static mut Registers,
@@ -66,6 +95,16 @@ pub struct Gpio<State> {
 }
 
 impl<T> Gpio<T> {
+    /// Selects `function` for this pin.
+    #[inline(always)]
+    fn select(&mut self, function: Function) {
+        let fsel_no = (self.pin / 10) as usize;
+        let fsel_shift = (self.pin % 10) * 3;
+        let reg = &mut self.registers.FSEL[fsel_no];
+        let value = reg.read() & !(0b111 << fsel_shift);
+        reg.write(value | ((function as u32) << fsel_shift));
+    }
+
     /// Transitions `self` to state `S`, consuming `self` and returning a new
     /// `Gpio` instance in state `S`. This method should _never_ be exposed to
     /// the public!
@@ -77,6 +116,51 @@ impl<T> Gpio<T> {
             _state: PhantomData,
         }
     }
+
+    /// Returns this pin's number.
+    pub fn pin(&self) -> u8 {
+        self.pin
+    }
+
+    /// Returns the register index and bit mask of this pin in the two-word
+    /// banked registers (`SET`, `CLR`, `LEV`, ...).
+    #[inline(always)]
+    fn bank(&self) -> (usize, u32) {
+        ((self.pin / 32) as usize, 1 << (self.pin % 32))
+    }
+
+    /// Configures the pull resistor of this pin using the `GPPUD` /
+    /// `GPPUDCLK` sequence. The setting is retained across function changes
+    /// and is not readable back from the hardware.
+    pub fn set_pull(&mut self, pull: Pull) {
+        let (index, mask) = self.bank();
+        self.registers.PUD.write(pull as u32);
+        // The control signal needs 150 cycles of set-up time, and the clock
+        // 150 cycles of hold time.
+        wait_cycles(150);
+        self.registers.PUDCLK[index].write(mask);
+        wait_cycles(150);
+        self.registers.PUD.write(Pull::Off as u32);
+        self.registers.PUDCLK[index].write(0);
+    }
+}
+
+/// Waits for at least `n` CPU cycles.
+#[inline(always)]
+fn wait_cycles(n: usize) {
+    for _ in 0..n {
+        nop();
+    }
+}
+
+/// Splits the pin numbers of `pins` into masks for the two register banks.
+fn bank_masks<T>(pins: &[Gpio<T>]) -> [u32; 2] {
+    let mut masks = [0; 2];
+    for pin in pins {
+        let (index, mask) = pin.bank();
+        masks[index] |= mask;
+    }
+    masks
 }
 
 impl Gpio<Uninitialized> {
@@ -99,11 +183,8 @@ impl Gpio<Uninitialized> {
 
     /// Enables the alternative function `function` for `self`. Consumes self
     /// and returns a `Gpio` structure in the `Alt` state.
-    pub fn into_alt(self, function: Function) -> Gpio<Alt> {
-        let fsel_no = (self.pin / 10) as usize;
-        let fsel_shift = (self.pin % 10) * 3;
-        let reg = &mut self.registers.FSEL[fsel_no];
-        reg.or_mask((function as u32) << fsel_shift);
+    pub fn into_alt(mut self, function: Function) -> Gpio<Alt> {
+        self.select(function);
         self.transition::<Alt>()
     }
 
@@ -118,6 +199,19 @@ impl Gpio<Uninitialized> {
     pub fn into_input(self) -> Gpio<Input> {
         self.into_alt(Function::Input).transition()
     }
+
+    /// Sets this pin up to share a wire with other open-drain outputs, as on
+    /// a 1-Wire or I2C bus: it either pulls the line low or lets it float,
+    /// to be pulled up by a resistor. Consumes self and returns a `Gpio`
+    /// structure in the `OpenDrain` state, with the line released.
+    pub fn into_open_drain(self) -> Gpio<OpenDrain> {
+        let mut pin = self.into_input().transition::<OpenDrain>();
+        // The pin drives its output latch whenever it is an output, so the
+        // latch stays low and only the function changes.
+        let (index, mask) = pin.bank();
+        pin.registers.CLR[index].write(mask);
+        pin
+    }
 }
 
 impl Gpio<Output> {
@@ -136,6 +230,30 @@ impl Gpio<Output> {
         let reg = &mut self.registers.CLR[clr_no];
         reg.write(1 << clr_shift);
     }
+
+    /// Sets all of `pins` with one register write per bank.
+    pub fn set_all(pins: &mut [Gpio<Output>]) {
+        let masks = bank_masks(pins);
+        if let Some(pin) = pins.first_mut() {
+            for (index, &mask) in masks.iter().enumerate() {
+                if mask != 0 {
+                    pin.registers.SET[index].write(mask);
+                }
+            }
+        }
+    }
+
+    /// Clears all of `pins` with one register write per bank.
+    pub fn clear_all(pins: &mut [Gpio<Output>]) {
+        let masks = bank_masks(pins);
+        if let Some(pin) = pins.first_mut() {
+            for (index, &mask) in masks.iter().enumerate() {
+                if mask != 0 {
+                    pin.registers.CLR[index].write(mask);
+                }
+            }
+        }
+    }
 }
 
 impl Gpio<Input> {
@@ -147,4 +265,88 @@ impl Gpio<Input> {
         let reg = &mut self.registers.LEV[lev_no];
         return reg.read() & (1 << lev_shift) != 0;
     }
+
+    /// Reads the levels of all of `pins` with one register read per bank.
+    /// Bit `i` of the result is set if `pins[i]` is high.
+    ///
+    /// # Panics
+    ///
+    /// Panics if more than 64 pins are given.
+    pub fn levels(pins: &[Gpio<Input>]) -> u64 {
+        assert!(pins.len() <= 64, "Gpio::levels(): too many pins");
+        let first = match pins.first() {
+            Some(pin) => pin,
+            None => return 0,
+        };
+        let banks = [first.registers.LEV[0].read(), first.registers.LEV[1].read()];
+        let mut levels = 0;
+        for (i, pin) in pins.iter().enumerate() {
+            let (index, mask) = pin.bank();
+            if banks[index] & mask != 0 {
+                levels |= 1 << i;
+            }
+        }
+        levels
+    }
+
+    /// Returns the enable register for `event` detection.
+    fn detect_register(&mut self, event: Event, index: usize) -> &mut Volatile<u32> {
+        match event {
+            Event::RisingEdge => &mut self.registers.REN[index],
+            Event::FallingEdge => &mut self.registers.FEN[index],
+            Event::High => &mut self.registers.HEN[index],
+            Event::Low => &mut self.registers.LEN[index],
+            Event::AsyncRisingEdge => &mut self.registers.AREN[index],
+            Event::AsyncFallingEdge => &mut self.registers.AFEN[index],
+        }
+    }
+
+    /// Enables detection of `event` on this pin. Detected events are latched
+    /// until cleared with `clear_event()` and raise the corresponding `GpioN`
+    /// interrupt if it is enabled.
+    pub fn enable_detect(&mut self, event: Event) {
+        let (index, mask) = self.bank();
+        self.detect_register(event, index).or_mask(mask);
+    }
+
+    /// Disables detection of `event` on this pin.
+    pub fn disable_detect(&mut self, event: Event) {
+        let (index, mask) = self.bank();
+        self.detect_register(event, index).and_mask(!mask);
+    }
+
+    /// Returns `true` if an enabled event has been detected on this pin since
+    /// it was last cleared.
+    pub fn has_event(&self) -> bool {
+        let (index, mask) = self.bank();
+        self.registers.EDS[index].read() & mask != 0
+    }
+
+    /// Clears this pin's latched event.
+    pub fn clear_event(&mut self) {
+        let (index, mask) = self.bank();
+        self.registers.EDS[index].write(mask);
+    }
+}
+
+impl Gpio<OpenDrain> {
+    /// Pulls the line low.
+    #[inline(always)]
+    pub fn drive_low(&mut self) {
+        self.select(Function::Output);
+    }
+
+    /// Stops pulling the line low, so that it rises unless another device
+    /// holds it down.
+    #[inline(always)]
+    pub fn release(&mut self) {
+        self.select(Function::Input);
+    }
+
+    /// Reads the level of the line. Returns `true` if it is high.
+    #[inline(always)]
+    pub fn level(&self) -> bool {
+        let (index, mask) = self.bank();
+        self.registers.LEV[index].read() & mask != 0
+    }
 }
@@ -6,8 +6,24 @@
 #![no_std]
 
 pub mod atags;
+pub mod clock;
 pub mod common;
+pub mod cores;
+pub mod delay;
+pub mod dma;
+pub mod ds18b20;
+pub mod edid;
+pub mod emmc;
+pub mod framebuffer;
 pub mod gpio;
 pub mod interrupt;
+pub mod local_interrupt;
+pub mod mailbox;
+pub mod onewire;
+pub mod pcm;
+pub mod pl011;
+pub mod rng;
+pub mod spi;
+pub mod tft;
 pub mod timer;
 pub mod uart;
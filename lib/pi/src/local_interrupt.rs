@@ -0,0 +1,236 @@
+//! The BCM2836/7 local interrupt controller.
+//!
+//! Each core has its own view of the interrupts it can take: its four
+//! generic timer interrupts, its four mailboxes, and the GPU interrupt (the
+//! global `interrupt::Controller`) if that is routed to it. The GPU
+//! interrupt goes to core 0 unless routed elsewhere.
+
+use core::time::Duration;
+
+use aarch64::{CNTFRQ_EL0, CNTPCT_EL0, CNTP_CTL_EL0, CNTP_TVAL_EL0, MPIDR_EL1};
+use volatile::prelude::*;
+use volatile::{ReadVolatile, Reserved, Volatile, WriteVolatile};
+
+use crate::common::{LOCAL_BASE, NCORES};
+
+/// The number of mailboxes each core has.
+pub const MAILBOXES: usize = 4;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LocalInterrupt {
+    /// The secure physical timer.
+    CntPs = 0,
+    /// The non-secure physical timer, which EL1 programs through `CNTP_*`.
+    CntPns = 1,
+    /// The hypervisor physical timer.
+    CntHp = 2,
+    /// The virtual timer.
+    CntV = 3,
+    Mailbox0 = 4,
+    Mailbox1 = 5,
+    Mailbox2 = 6,
+    Mailbox3 = 7,
+    /// Some interrupt of the global controller is pending.
+    Gpu = 8,
+    Pmu = 9,
+}
+
+impl LocalInterrupt {
+    pub const MAX: usize = 10;
+
+    pub fn iter() -> core::slice::Iter<'static, LocalInterrupt> {
+        use LocalInterrupt::*;
+        [CntPs, CntPns, CntHp, CntV, Mailbox0, Mailbox1, Mailbox2, Mailbox3, Gpu, Pmu].iter()
+    }
+
+    pub fn to_index(i: LocalInterrupt) -> usize {
+        i as usize
+    }
+
+    pub fn from_index(i: usize) -> LocalInterrupt {
+        use LocalInterrupt::*;
+        match i {
+            0 => CntPs,
+            1 => CntPns,
+            2 => CntHp,
+            3 => CntV,
+            4 => Mailbox0,
+            5 => Mailbox1,
+            6 => Mailbox2,
+            7 => Mailbox3,
+            8 => Gpu,
+            9 => Pmu,
+            _ => panic!("Unknown local interrupt: {}", i),
+        }
+    }
+
+    /// Returns the interrupt of mailbox `mailbox`.
+    pub fn mailbox(mailbox: usize) -> LocalInterrupt {
+        assert!(mailbox < MAILBOXES);
+        LocalInterrupt::from_index(LocalInterrupt::Mailbox0 as usize + mailbox)
+    }
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    Control: Volatile<u32>,
+    __r0: Reserved<u32>,
+    CoreTimerPrescaler: Volatile<u32>,
+    GpuRouting: Volatile<u32>,
+    PmuRoutingSet: WriteVolatile<u32>,
+    PmuRoutingClear: WriteVolatile<u32>,
+    __r1: Reserved<u32>,
+    CoreTimerLow: Volatile<u32>,
+    CoreTimerHigh: Volatile<u32>,
+    LocalRouting: Volatile<u32>,
+    __r2: Reserved<u32>,
+    AxiCounters: Volatile<u32>,
+    AxiIrq: Volatile<u32>,
+    LocalTimerControl: Volatile<u32>,
+    LocalTimerFlags: WriteVolatile<u32>,
+    __r3: Reserved<u32>,
+    TimerControl: [Volatile<u32>; NCORES],
+    MailboxControl: [Volatile<u32>; NCORES],
+    IrqSource: [ReadVolatile<u32>; NCORES],
+    FiqSource: [ReadVolatile<u32>; NCORES],
+    MailboxSet: [[WriteVolatile<u32>; MAILBOXES]; NCORES],
+    MailboxClear: [[Volatile<u32>; MAILBOXES]; NCORES],
+}
+
+/// A core's local interrupt controller. Used to enable and disable the
+/// core's interrupts, to check if one is pending, and to use mailboxes.
+pub struct LocalController {
+    core: usize,
+    registers: &'static mut Registers,
+}
+
+impl LocalController {
+    /// Returns a new handle to the local interrupt controller of `core`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `core` is not less than `NCORES`.
+    pub fn new(core: usize) -> LocalController {
+        assert!(core < NCORES);
+        LocalController {
+            core,
+            registers: unsafe { &mut *(LOCAL_BASE as *mut Registers) },
+        }
+    }
+
+    /// Returns a new handle to the local interrupt controller of the calling
+    /// core.
+    pub fn current() -> LocalController {
+        LocalController::new(unsafe { MPIDR_EL1.get_value(MPIDR_EL1::Aff0) } as usize)
+    }
+
+    /// Returns the core this controller belongs to.
+    pub fn core(&self) -> usize {
+        self.core
+    }
+
+    /// Enables the interrupt `int`. Enabling `Gpu` routes the global
+    /// controller's interrupts to this core, away from any other.
+    pub fn enable(&mut self, int: LocalInterrupt) {
+        use LocalInterrupt::*;
+        match int {
+            CntPs | CntPns | CntHp | CntV => {
+                self.registers.TimerControl[self.core].or_mask(1 << int as u32)
+            }
+            Mailbox0 | Mailbox1 | Mailbox2 | Mailbox3 => self.registers.MailboxControl[self.core]
+                .or_mask(1 << (int as u32 - Mailbox0 as u32)),
+            Gpu => self.registers.GpuRouting.write(self.core as u32),
+            Pmu => self.registers.PmuRoutingSet.write(1 << self.core),
+        }
+    }
+
+    /// Disables the interrupt `int`. `Gpu` is always routed to some core and
+    /// cannot be disabled here; disable its sources in the global controller.
+    pub fn disable(&mut self, int: LocalInterrupt) {
+        use LocalInterrupt::*;
+        match int {
+            CntPs | CntPns | CntHp | CntV => {
+                self.registers.TimerControl[self.core].and_mask(!(1 << int as u32))
+            }
+            Mailbox0 | Mailbox1 | Mailbox2 | Mailbox3 => self.registers.MailboxControl[self.core]
+                .and_mask(!(1 << (int as u32 - Mailbox0 as u32))),
+            Gpu => (),
+            Pmu => self.registers.PmuRoutingClear.write(1 << self.core),
+        }
+    }
+
+    /// Returns `true` if `int` is pending on this core. Otherwise, returns
+    /// `false`.
+    pub fn is_pending(&self, int: LocalInterrupt) -> bool {
+        self.registers.IrqSource[self.core].has_mask(1 << int as u32)
+    }
+
+    /// Sets the bits `bits` in mailbox `mailbox` of core `core`, raising
+    /// the mailbox's interrupt there if it is enabled.
+    pub fn send(&mut self, core: usize, mailbox: usize, bits: u32) {
+        self.registers.MailboxSet[core][mailbox].write(bits);
+    }
+
+    /// Returns the bits set in this core's mailbox `mailbox` and clears them,
+    /// which clears the mailbox's interrupt.
+    pub fn take(&mut self, mailbox: usize) -> u32 {
+        let bits = self.registers.MailboxClear[self.core][mailbox].read();
+        self.registers.MailboxClear[self.core][mailbox].write(bits);
+        bits
+    }
+
+    /// Returns which of the bits `bits` are set in this core's mailbox
+    /// `mailbox` and clears them, leaving the other bits set.
+    pub fn take_bits(&mut self, mailbox: usize, bits: u32) -> u32 {
+        let bits = self.registers.MailboxClear[self.core][mailbox].read() & bits;
+        self.registers.MailboxClear[self.core][mailbox].write(bits);
+        bits
+    }
+}
+
+/// The calling core's non-secure physical timer, which raises `CntPns`.
+///
+/// Each core has its own, so each can keep its own tick, unlike the system
+/// timer's compare channel that all cores share.
+pub struct CoreTimer(());
+
+impl CoreTimer {
+    /// Returns a handle to the calling core's timer.
+    pub fn new() -> CoreTimer {
+        CoreTimer(())
+    }
+
+    /// Returns the time since the counter, which all cores share, started.
+    pub fn read(&self) -> Duration {
+        unsafe {
+            let freq = CNTFRQ_EL0.get();
+            let count = CNTPCT_EL0.get();
+            Duration::from_nanos((count as u128 * 1_000_000_000 / freq as u128) as u64)
+        }
+    }
+
+    /// Sets up the timer to fire `t` duration from now. If `CntPns` is
+    /// enabled in this core's `LocalController` and IRQs are unmasked, an
+    /// interrupt is issued in `t` duration.
+    pub fn tick_in(&mut self, t: Duration) {
+        unsafe {
+            let freq = CNTFRQ_EL0.get() as u128;
+            let ticks = (t.as_nanos() * freq / 1_000_000_000).max(1).min(u32::MAX as u128);
+            CNTP_TVAL_EL0.set(ticks as u64);
+            CNTP_CTL_EL0.set(CNTP_CTL_EL0::ENABLE);
+        }
+    }
+
+    /// Returns `true`, and stops the timer so it no longer interrupts, if the
+    /// time set by `tick_in()` has come. Otherwise, returns `false`.
+    pub fn check_deadline(&mut self) -> bool {
+        unsafe {
+            if CNTP_CTL_EL0.get() & CNTP_CTL_EL0::ISTATUS == 0 {
+                return false;
+            }
+            CNTP_CTL_EL0.set(CNTP_CTL_EL0::IMASK);
+        }
+        true
+    }
+}
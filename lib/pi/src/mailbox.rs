@@ -0,0 +1,177 @@
+use crate::common::IO_BASE;
+use crate::dma;
+
+use volatile::prelude::*;
+use volatile::{ReadVolatile, Reserved, Volatile};
+
+/// The base address for the VideoCore mailbox 0 registers.
+const MAILBOX_REG_BASE: usize = IO_BASE + 0xB880;
+
+/// The mailbox channel for property tags from the ARM to the VideoCore.
+const PROPERTY_CHANNEL: u8 = 8;
+
+/// `STATUS` bit set when the write mailbox is full.
+const STATUS_FULL: u32 = 1 << 31;
+/// `STATUS` bit set when the read mailbox is empty.
+const STATUS_EMPTY: u32 = 1 << 30;
+
+/// Buffer code of a property request.
+const REQUEST: u32 = 0;
+/// Buffer code of a successful property response.
+const RESPONSE_SUCCESS: u32 = 0x8000_0000;
+/// Bit set in a tag's value length once the VideoCore has answered it.
+const TAG_RESPONSE: u32 = 1 << 31;
+
+/// The "get clock rate" property tag.
+const TAG_GET_CLOCK_RATE: u32 = 0x0003_0002;
+
+/// The "get EDID block" property tag.
+const TAG_GET_EDID_BLOCK: u32 = 0x0003_0020;
+
+/// The size of a property message in words: a header of two, three words
+/// ahead of each tag's values and an end tag.
+pub const MESSAGE_WORDS: usize = 64;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    READ: ReadVolatile<u32>,
+    __r0: [Reserved<u32>; 3],
+    PEEK: ReadVolatile<u32>,
+    SENDER: ReadVolatile<u32>,
+    STATUS: ReadVolatile<u32>,
+    CONFIG: Volatile<u32>,
+    WRITE: Volatile<u32>,
+}
+
+/// A clock managed by the VideoCore firmware.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Clock {
+    Emmc = 1,
+    Uart = 2,
+    Arm = 3,
+    Core = 4,
+}
+
+/// A property message. The VideoCore requires the buffer to be 16-byte
+/// aligned since the low four bits of its address carry the channel.
+#[repr(C, align(16))]
+struct Message {
+    words: [u32; MESSAGE_WORDS],
+}
+
+/// The VideoCore mailbox used to talk to the GPU firmware.
+pub struct Mailbox {
+    registers: &'static mut Registers,
+}
+
+impl Mailbox {
+    /// Returns a new instance of `Mailbox`.
+    pub fn new() -> Mailbox {
+        Mailbox {
+            registers: unsafe { &mut *(MAILBOX_REG_BASE as *mut Registers) },
+        }
+    }
+
+    /// Sends `data`, the bus address of a 16-byte aligned buffer, on
+    /// `channel` and blocks until the firmware responds on the same channel.
+    fn call(&mut self, channel: u8, data: u32) {
+        while self.registers.STATUS.has_mask(STATUS_FULL) {}
+        self.registers.WRITE.write((data & !0xF) | channel as u32);
+        loop {
+            while self.registers.STATUS.has_mask(STATUS_EMPTY) {}
+            if self.registers.READ.read() & 0xF == channel as u32 {
+                return;
+            }
+        }
+    }
+
+    /// Sends the property tag `tag` with the request values in `values` and
+    /// overwrites `values` with the response.
+    ///
+    /// Returns `Err(())` if the firmware rejects the request or does not
+    /// answer the tag.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `values` does not fit in a message.
+    pub fn property(&mut self, tag: u32, values: &mut [u32]) -> Result<(), ()> {
+        self.properties(&mut [(tag, values)])
+    }
+
+    /// Sends the property tags `tags` in one message, each with its request
+    /// values, and overwrites the values with the responses. Settings that
+    /// depend on each other, such as a framebuffer's size and its
+    /// allocation, must be sent together.
+    ///
+    /// Returns `Err(())` if the firmware rejects the request or does not
+    /// answer every tag.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the tags do not fit in `MESSAGE_WORDS`.
+    pub fn properties(&mut self, tags: &mut [(u32, &mut [u32])]) -> Result<(), ()> {
+        let len = 3 + tags.iter().map(|(_, values)| 3 + values.len()).sum::<usize>();
+        assert!(len <= MESSAGE_WORDS, "too many property values");
+        let mut message = Message { words: [0; MESSAGE_WORDS] };
+        let words = &mut message.words;
+        words[0] = (len * 4) as u32;
+        words[1] = REQUEST;
+        let mut i = 2;
+        for (tag, values) in tags.iter() {
+            words[i] = *tag;
+            words[i + 1] = (values.len() * 4) as u32;
+            words[i + 2] = 0;
+            words[i + 3..i + 3 + values.len()].copy_from_slice(values);
+            i += 3 + values.len();
+        }
+        words[i] = 0;
+
+        // The VideoCore reads and writes the message in memory, past the
+        // ARM's data cache, at its bus address.
+        let size = core::mem::size_of::<Message>();
+        unsafe { aarch64::clean_dcache_range(words.as_ptr() as usize, size) };
+        self.call(PROPERTY_CHANNEL, dma::bus_address(words.as_ptr()));
+        unsafe { aarch64::clean_invalidate_dcache_range(words.as_ptr() as usize, size) };
+
+        let words = unsafe { core::ptr::read_volatile(&message.words) };
+        if words[1] != RESPONSE_SUCCESS {
+            return Err(());
+        }
+        let mut i = 2;
+        for (_, values) in tags.iter_mut() {
+            if words[i + 2] & TAG_RESPONSE == 0 {
+                return Err(());
+            }
+            values.copy_from_slice(&words[i + 3..i + 3 + values.len()]);
+            i += 3 + values.len();
+        }
+        Ok(())
+    }
+}
+
+/// Returns the current rate of `clock` in Hz, or `None` if the firmware could
+/// not be queried.
+pub fn clock_rate(clock: Clock) -> Option<u32> {
+    let mut values = [clock as u32, 0];
+    Mailbox::new().property(TAG_GET_CLOCK_RATE, &mut values).ok()?;
+    Some(values[1])
+}
+
+/// Returns EDID block `block` of the display attached over HDMI, or `None`
+/// if there is none or the firmware could not be queried.
+pub fn edid_block(block: u32) -> Option<[u8; 128]> {
+    // The block number and a status, 0 on success, then the block.
+    let mut values = [0; 2 + 128 / 4];
+    values[0] = block;
+    Mailbox::new().property(TAG_GET_EDID_BLOCK, &mut values).ok()?;
+    if values[1] != 0 {
+        return None;
+    }
+    let mut bytes = [0; 128];
+    for (chunk, word) in bytes.chunks_mut(4).zip(values[2..].iter()) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    Some(bytes)
+}
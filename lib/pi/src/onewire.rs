@@ -0,0 +1,334 @@
+//! A bit-banged 1-Wire bus master on a GPIO pin.
+//!
+//! The bus is one data line, held high by a pull-up resistor (4.7k to 3.3V;
+//! the pin's internal pull-up is also enabled but is too weak for more than
+//! a short wire). The master and each device pull it low to signal. Every
+//! exchange starts with a reset, after which the master addresses one device
+//! by its 64-bit `Rom` code, or all of them, and then sends a command.
+//!
+//! The time slots follow the standard speed timings of Maxim's application
+//! note 126 and are measured with `delay::delay_us()`. IRQs are masked for
+//! the length of each slot, at most 70us, since a late release or sample
+//! reads the wrong bit; between slots the bus may wait indefinitely.
+
+use core::fmt;
+
+use aarch64::{cli, sti, DAIF};
+
+use crate::delay::delay_us;
+use crate::gpio::{Gpio, OpenDrain, Pull};
+
+/// ROM commands, sent after a reset.
+const SEARCH_ROM: u8 = 0xF0;
+const READ_ROM: u8 = 0x33;
+const MATCH_ROM: u8 = 0x55;
+const SKIP_ROM: u8 = 0xCC;
+
+/// An error on the bus.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// No device answered a reset with a presence pulse.
+    NoPresence,
+    /// The line was low before a reset: it is shorted or lacks a pull-up.
+    BusLow,
+    /// Data failed its CRC.
+    Crc,
+    /// No device answered during a ROM search, which means the devices that
+    /// answered the reset went away.
+    NoResponse,
+    /// A device took too long to finish an operation.
+    Timeout,
+}
+
+/// Returns the Dallas/Maxim CRC-8 (polynomial x^8 + x^5 + x^4 + 1) of
+/// `bytes`. The CRC of data followed by its CRC is 0.
+pub fn crc8(bytes: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in bytes {
+        let mut byte = byte;
+        for _ in 0..8 {
+            let mix = (crc ^ byte) & 1;
+            crc >>= 1;
+            if mix != 0 {
+                crc ^= 0x8C;
+            }
+            byte >>= 1;
+        }
+    }
+    crc
+}
+
+/// The 64-bit ROM code of a device: a family code, a 48-bit serial number
+/// and a CRC of both, in the order they are sent on the bus.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rom(pub [u8; 8]);
+
+impl Rom {
+    /// Returns the family code, which names the kind of device.
+    pub fn family(&self) -> u8 {
+        self.0[0]
+    }
+
+    /// Returns `true` if the code passes its CRC.
+    pub fn is_valid(&self) -> bool {
+        crc8(&self.0) == 0
+    }
+}
+
+impl fmt::Display for Rom {
+    /// Formats the code as Linux's `w1` driver names devices: the family
+    /// code, a dash and the serial number, in hexadecimal.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x}-", self.family())?;
+        for byte in self.0[1..7].iter().rev() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs `f` with IRQs masked, then restores the mask as it was.
+#[inline(always)]
+fn masked<R>(f: impl FnOnce() -> R) -> R {
+    let daif = unsafe { DAIF.get() };
+    unsafe { cli() };
+    let result = f();
+    if daif & DAIF::I == 0 {
+        unsafe { sti() };
+    }
+    result
+}
+
+/// A 1-Wire bus master.
+pub struct OneWire {
+    pin: Gpio<OpenDrain>,
+}
+
+impl OneWire {
+    /// Returns a bus master on GPIO pin `pin`, which is made an open-drain
+    /// pin with its pull-up enabled.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pin` > `53`.
+    pub fn new(pin: u8) -> OneWire {
+        let mut pin = Gpio::new(pin).into_open_drain();
+        pin.set_pull(Pull::Up);
+        OneWire { pin }
+    }
+
+    /// Resets the bus and waits for the devices' presence pulse.
+    ///
+    /// Returns `BusLow` if the line is held low and `NoPresence` if no
+    /// device is present.
+    pub fn reset(&mut self) -> Result<(), Error> {
+        let pin = &mut self.pin;
+        if !pin.level() {
+            return Err(Error::BusLow);
+        }
+        pin.drive_low();
+        delay_us(480);
+        let present = masked(|| {
+            pin.release();
+            delay_us(70);
+            !pin.level()
+        });
+        // Devices hold the line for up to 240us.
+        delay_us(410);
+        if present {
+            Ok(())
+        } else {
+            Err(Error::NoPresence)
+        }
+    }
+
+    /// Sends one bit.
+    pub fn write_bit(&mut self, bit: bool) {
+        let pin = &mut self.pin;
+        masked(|| {
+            pin.drive_low();
+            if bit {
+                delay_us(6);
+                pin.release();
+                delay_us(64);
+            } else {
+                delay_us(60);
+                pin.release();
+                delay_us(10);
+            }
+        })
+    }
+
+    /// Receives one bit.
+    pub fn read_bit(&mut self) -> bool {
+        let pin = &mut self.pin;
+        let bit = masked(|| {
+            pin.drive_low();
+            delay_us(6);
+            pin.release();
+            delay_us(9);
+            pin.level()
+        });
+        delay_us(55);
+        bit
+    }
+
+    /// Sends `byte`, least significant bit first.
+    pub fn write_byte(&mut self, byte: u8) {
+        for i in 0..8 {
+            self.write_bit(byte & (1 << i) != 0);
+        }
+    }
+
+    /// Receives a byte, least significant bit first.
+    pub fn read_byte(&mut self) -> u8 {
+        (0..8).fold(0, |byte, i| byte | (self.read_bit() as u8) << i)
+    }
+
+    /// Sends each byte of `bytes`.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_byte(byte);
+        }
+    }
+
+    /// Fills `buf` with received bytes.
+    pub fn read_bytes(&mut self, buf: &mut [u8]) {
+        for byte in buf.iter_mut() {
+            *byte = self.read_byte();
+        }
+    }
+
+    /// Resets the bus and addresses the device `rom`, or every device if
+    /// `rom` is `None`, so that they act on the command that follows.
+    pub fn select(&mut self, rom: Option<&Rom>) -> Result<(), Error> {
+        self.reset()?;
+        match rom {
+            Some(rom) => {
+                self.write_byte(MATCH_ROM);
+                self.write_bytes(&rom.0);
+            }
+            None => self.write_byte(SKIP_ROM),
+        }
+        Ok(())
+    }
+
+    /// Reads the ROM code of the only device on the bus. With more than one,
+    /// their answers collide and the result fails its CRC.
+    pub fn read_rom(&mut self) -> Result<Rom, Error> {
+        self.reset()?;
+        self.write_byte(READ_ROM);
+        let mut rom = Rom([0; 8]);
+        self.read_bytes(&mut rom.0);
+        if rom.is_valid() {
+            Ok(rom)
+        } else {
+            Err(Error::Crc)
+        }
+    }
+
+    /// Returns an iterator over the ROM codes of the devices on the bus, in
+    /// increasing order of their bits as sent. A bus without devices yields
+    /// nothing; the iterator ends after an error.
+    pub fn search(&mut self) -> Search<'_> {
+        Search { bus: self, rom: [0; 8], last_discrepancy: 0, passes: 0, done: false }
+    }
+}
+
+/// An iterator over the devices on a bus; see `OneWire::search()`.
+///
+/// Each pass of the search walks the binary tree of ROM codes: at every bit
+/// the devices still addressed send it and then its complement, and two 0s
+/// mean some have a 0 there and others a 1. The master takes the 0 branch
+/// at new discrepancies and the 1 branch at the last one it took a 0 at, as
+/// described in Maxim's application note 187.
+pub struct Search<'a> {
+    bus: &'a mut OneWire,
+    /// The ROM code found by the last pass.
+    rom: [u8; 8],
+    /// The last bit, from 1, at which the last pass took the 0 branch of a
+    /// discrepancy, or 0 if it took none.
+    last_discrepancy: usize,
+    /// The passes run so far.
+    passes: usize,
+    done: bool,
+}
+
+impl Search<'_> {
+    fn bit(&self, n: usize) -> bool {
+        self.rom[(n - 1) / 8] & (1 << ((n - 1) % 8)) != 0
+    }
+
+    fn set_bit(&mut self, n: usize, value: bool) {
+        let mask = 1 << ((n - 1) % 8);
+        if value {
+            self.rom[(n - 1) / 8] |= mask;
+        } else {
+            self.rom[(n - 1) / 8] &= !mask;
+        }
+    }
+
+    /// Runs one pass of the search.
+    fn pass(&mut self) -> Result<Rom, Error> {
+        self.bus.reset()?;
+        self.bus.write_byte(SEARCH_ROM);
+        let mut last_zero = 0;
+        for n in 1..=64 {
+            let (bit, complement) = (self.bus.read_bit(), self.bus.read_bit());
+            let direction = match (bit, complement) {
+                (true, true) => return Err(Error::NoResponse),
+                (bit, complement) if bit != complement => bit,
+                _ => {
+                    let direction = match n {
+                        n if n < self.last_discrepancy => self.bit(n),
+                        n => n == self.last_discrepancy,
+                    };
+                    if !direction {
+                        last_zero = n;
+                    }
+                    direction
+                }
+            };
+            self.set_bit(n, direction);
+            self.bus.write_bit(direction);
+        }
+        self.last_discrepancy = last_zero;
+        let rom = Rom(self.rom);
+        if rom.is_valid() {
+            Ok(rom)
+        } else {
+            Err(Error::Crc)
+        }
+    }
+}
+
+impl Iterator for Search<'_> {
+    type Item = Result<Rom, Error>;
+
+    fn next(&mut self) -> Option<Result<Rom, Error>> {
+        if self.done {
+            return None;
+        }
+        let result = self.pass();
+        self.passes += 1;
+        self.done = result.is_err() || self.last_discrepancy == 0;
+        match result {
+            Err(Error::NoPresence) if self.passes == 1 => None,
+            result => Some(result),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{crc8, Rom};
+
+    #[test]
+    fn crc8_of_a_rom_code() {
+        // The example of Maxim's application note 27.
+        let rom = Rom([0x02, 0x1C, 0xB8, 0x01, 0x00, 0x00, 0x00, 0xA2]);
+        assert_eq!(crc8(&rom.0[..7]), 0xA2);
+        assert!(rom.is_valid());
+        assert!(!Rom([0x02, 0x1C, 0xB8, 0x01, 0x00, 0x00, 0x01, 0xA2]).is_valid());
+    }
+}
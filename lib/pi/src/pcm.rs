@@ -0,0 +1,152 @@
+use volatile::prelude::*;
+use volatile::{Volatile, WriteVolatile};
+
+use crate::clock::Generator;
+use crate::common::IO_BASE;
+use crate::dma;
+use crate::gpio::{Function, Gpio};
+use crate::timer;
+
+/// The base address for the `PCM` (I2S) registers.
+const PCM_REG_BASE: usize = IO_BASE + 0x203000;
+
+/// The number of bit clocks in each frame: two 16-bit channels.
+const FRAME_BITS: u32 = 32;
+
+/// Enum representing bit fields of the `CS_A` register.
+#[repr(u32)]
+enum Control {
+    Enable = 1 << 0,
+    TxOn = 1 << 2,
+    TxClear = 1 << 3,
+    DmaEnable = 1 << 9,
+    Sync = 1 << 24,
+    RamStandbyOff = 1 << 25,
+}
+
+/// Enum representing bit fields of the `MODE_A` register.
+#[repr(u32)]
+enum Mode {
+    /// Frame sync inverted, so that the left channel is sent while FS is low.
+    FrameSyncInvert = 1 << 20,
+    /// Bit clock inverted, so that data changes on the falling edge.
+    ClockInvert = 1 << 22,
+    /// Both channels of a frame are packed into one FIFO word.
+    TxPacked = 1 << 24,
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    CS_A: Volatile<u32>,
+    FIFO_A: WriteVolatile<u32>,
+    MODE_A: Volatile<u32>,
+    RXC_A: Volatile<u32>,
+    TXC_A: Volatile<u32>,
+    DREQ_A: Volatile<u32>,
+    INTEN_A: Volatile<u32>,
+    INTSTC_A: Volatile<u32>,
+    GRAY: Volatile<u32>,
+}
+
+/// Error returned when the PCM interface cannot be configured.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The sample rate cannot be derived from the oscillator.
+    UnsupportedRate(u32),
+}
+
+/// Returns the `TXC_A` channel configuration for a 16-bit channel starting at
+/// bit clock `position` of the frame.
+fn channel_config(position: u32) -> u32 {
+    const ENABLE: u32 = 1 << 14;
+    // Width is 8 + WID bits.
+    const WIDTH_16: u32 = 8;
+    ENABLE | (position << 4) | WIDTH_16
+}
+
+/// The PCM/I2S audio interface, configured as an I2S master transmitting
+/// 16-bit stereo frames on GPIO pins 18 (PCM_CLK), 19 (PCM_FS) and 21
+/// (PCM_DOUT).
+///
+/// Each word written to the transmit FIFO is one frame, with the left sample
+/// in the low half and the right sample in the high half.
+pub struct Pcm {
+    registers: &'static mut Registers,
+    rate: u32,
+}
+
+impl Pcm {
+    /// Returns a new, stopped instance of `Pcm`.
+    pub fn new() -> Pcm {
+        Gpio::new(18).into_alt(Function::Alt0);
+        Gpio::new(19).into_alt(Function::Alt0);
+        Gpio::new(21).into_alt(Function::Alt0);
+        Pcm {
+            registers: unsafe { &mut *(PCM_REG_BASE as *mut Registers) },
+            rate: 0,
+        }
+    }
+
+    /// Returns the configured sample rate in Hz, or 0 if unconfigured.
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    /// Returns the bus address of the transmit FIFO, as a DMA destination.
+    pub fn fifo_bus_address(&self) -> u32 {
+        dma::peripheral_bus_address(&self.registers.FIFO_A as *const _ as usize)
+    }
+
+    /// Stops transmission and configures the interface for `rate` samples per
+    /// second per channel.
+    pub fn configure(&mut self, rate: u32) -> Result<(), Error> {
+        self.stop();
+        Generator::Pcm
+            .start(rate.saturating_mul(FRAME_BITS))
+            .ok_or(Error::UnsupportedRate(rate))?;
+
+        self.registers.CS_A.write(Control::Enable as u32 | Control::RamStandbyOff as u32);
+        // FLEN is the frame length minus one; FSLEN is the frame sync length.
+        self.registers.MODE_A.write(
+            ((FRAME_BITS - 1) << 10)
+                | (FRAME_BITS / 2)
+                | Mode::FrameSyncInvert as u32
+                | Mode::ClockInvert as u32
+                | Mode::TxPacked as u32,
+        );
+        // I2S delays data by one bit clock from the frame sync edge.
+        self.registers.TXC_A.write((channel_config(1) << 16) | channel_config(FRAME_BITS / 2 + 1));
+        self.rate = rate;
+        Ok(())
+    }
+
+    /// Clears the transmit FIFO and starts transmitting, with the FIFO fed by
+    /// DMA through the `dma::dreq::PCM_TX` request line.
+    pub fn start_dma(&mut self) {
+        self.clear_fifo();
+        // Request data when fewer than 32 words are queued; panic below 16.
+        self.registers.DREQ_A.write((16 << 24) | (32 << 8));
+        self.registers.CS_A.or_mask(Control::DmaEnable as u32);
+        self.registers.CS_A.or_mask(Control::TxOn as u32);
+    }
+
+    /// Stops transmitting.
+    pub fn stop(&mut self) {
+        self.registers.CS_A.and_mask(!(Control::TxOn as u32 | Control::DmaEnable as u32));
+    }
+
+    /// Clears the transmit FIFO. The clear takes effect after two PCM clocks,
+    /// which is detected with the `SYNC` bit.
+    fn clear_fifo(&mut self) {
+        self.registers.CS_A.or_mask(Control::TxClear as u32);
+        self.registers.CS_A.or_mask(Control::Sync as u32);
+        let start = timer::current_time();
+        while !self.registers.CS_A.has_mask(Control::Sync as u32) {
+            // Without a running clock SYNC never echoes back.
+            if timer::current_time() - start > core::time::Duration::from_millis(1) {
+                break;
+            }
+        }
+    }
+}
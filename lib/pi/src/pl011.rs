@@ -0,0 +1,296 @@
+use core::fmt;
+use core::time::Duration;
+
+use shim::io;
+use shim::{const_assert_size, ioerr};
+
+use volatile::prelude::*;
+use volatile::{ReadVolatile, Reserved, Volatile, WriteVolatile};
+
+use crate::common::IO_BASE;
+use crate::gpio::{Function, Gpio};
+use crate::mailbox::{self, Clock};
+use crate::timer;
+use crate::uart::{Config, DataBits, Error};
+
+/// The base address for the `UART0` registers.
+const PL011_REG_BASE: usize = IO_BASE + 0x201000;
+
+/// The UART reference clock rate assumed when the firmware cannot be queried.
+const DEFAULT_UART_CLOCK: u32 = 48_000_000;
+
+/// Enum representing bit fields of the `FR` register.
+#[repr(u32)]
+enum Flag {
+    Busy = 1 << 3,
+    RxFifoEmpty = 1 << 4,
+    TxFifoFull = 1 << 5,
+}
+
+/// Enum representing bit fields of the `LCRH` register.
+#[repr(u32)]
+enum LineControl {
+    FifoEnable = 1 << 4,
+    WordLen7 = 0b10 << 5,
+    WordLen8 = 0b11 << 5,
+}
+
+/// Enum representing bit fields of the `CR` register.
+#[repr(u32)]
+enum Control {
+    Enable = 1,
+    TxEnable = 1 << 8,
+    RxEnable = 1 << 9,
+    RtsFlow = 1 << 14,
+    CtsFlow = 1 << 15,
+}
+
+/// A PL011 interrupt source, as used in the `IMSC`, `MIS` and `ICR`
+/// registers.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Interrupt {
+    /// The receive FIFO reached its trigger level.
+    Rx = 1 << 4,
+    /// The transmit FIFO drained to its trigger level.
+    Tx = 1 << 5,
+    /// The receive FIFO is non-empty and no data arrived for 32 bit periods.
+    RxTimeout = 1 << 6,
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    DR: Volatile<u32>,
+    RSRECR: Volatile<u32>,
+    __r0: [Reserved<u32>; 4],
+    FR: ReadVolatile<u32>,
+    __r1: Reserved<u32>,
+    ILPR: Volatile<u32>,
+    IBRD: Volatile<u32>,
+    FBRD: Volatile<u32>,
+    LCRH: Volatile<u32>,
+    CR: Volatile<u32>,
+    IFLS: Volatile<u32>,
+    IMSC: Volatile<u32>,
+    RIS: ReadVolatile<u32>,
+    MIS: ReadVolatile<u32>,
+    ICR: WriteVolatile<u32>,
+    DMACR: Volatile<u32>,
+}
+
+const_assert_size!(Registers, 0x7E20104C - 0x7E201000);
+
+/// Returns the integer and 6-bit fractional baud rate divisors (`IBRD`,
+/// `FBRD`) that most closely yield `baud` bps from a reference clock of
+/// `uart_clock` Hz, or `None` if `baud` is out of range.
+///
+/// The PL011 runs at `uart_clock / (16 * (IBRD + FBRD / 64))` bps.
+pub fn baud_divisor(uart_clock: u32, baud: u32) -> Option<(u16, u8)> {
+    if baud == 0 {
+        return None;
+    }
+    // The divisor in units of 1/64, rounded to nearest.
+    let step = 16 * baud as u64;
+    let scaled = (uart_clock as u64 * 64 + step / 2) / step;
+    let (int, frac) = (scaled / 64, scaled % 64);
+    if int == 0 || int > u16::MAX as u64 {
+        return None;
+    }
+    Some((int as u16, frac as u8))
+}
+
+/// The Raspberry Pi's PL011 UART (`UART0`).
+///
+/// Compared to the mini UART, the PL011 has 16-entry FIFOs, a baud rate
+/// independent of the core clock, and per-source interrupts. On the
+/// Raspberry Pi 3 it is wired to Bluetooth unless `config.txt` contains
+/// `dtoverlay=disable-bt` (or `miniuart-bt`).
+pub struct Pl011 {
+    registers: &'static mut Registers,
+    timeout: Option<Duration>,
+    config: Config,
+}
+
+impl Pl011 {
+    /// Initializes the PL011 with the default `Config` (115200 bps, 8 data
+    /// bits, no flow control). See `with_config()`.
+    pub fn new() -> Pl011 {
+        Pl011::with_config(Config::default())
+            .expect("default UART configuration is supported")
+    }
+
+    /// Initializes the PL011 by setting GPIO pins 14 and 15 to alternative
+    /// function 0 (TXD0/RXD0), applying `config`, and enabling the UART
+    /// with its FIFOs. All interrupts start masked.
+    ///
+    /// By default, reads will never time out. To set a read timeout, use
+    /// `set_read_timeout()`.
+    pub fn with_config(config: Config) -> Result<Pl011, Error> {
+        let registers = unsafe { &mut *(PL011_REG_BASE as *mut Registers) };
+        Gpio::new(14).into_alt(Function::Alt0);
+        Gpio::new(15).into_alt(Function::Alt0);
+        let mut uart = Pl011 {
+            registers,
+            timeout: None,
+            config,
+        };
+        uart.configure(config)?;
+        uart.registers.IMSC.write(0);
+        uart.registers.ICR.write(0x7FF);
+        Ok(uart)
+    }
+
+    /// Returns the current line settings.
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    /// Applies `config`, waiting for pending output to drain first. The UART
+    /// is disabled while the settings change, so input arriving during
+    /// reconfiguration is lost.
+    ///
+    /// On error, the current settings are left untouched.
+    pub fn configure(&mut self, config: Config) -> Result<(), Error> {
+        let uart_clock = mailbox::clock_rate(Clock::Uart).unwrap_or(DEFAULT_UART_CLOCK);
+        let (int, frac) = baud_divisor(uart_clock, config.baud)
+            .ok_or(Error::UnsupportedBaudRate(config.baud))?;
+
+        while self.registers.FR.has_mask(Flag::Busy as u32) {}
+        self.registers.CR.write(0);
+        // Disabling the FIFOs flushes them.
+        self.registers.LCRH.write(0);
+        self.registers.IBRD.write(int as u32);
+        self.registers.FBRD.write(frac as u32);
+        // The divisors only take effect on a write to LCRH.
+        let word_len = match config.data_bits {
+            DataBits::Seven => LineControl::WordLen7,
+            DataBits::Eight => LineControl::WordLen8,
+        };
+        self.registers.LCRH.write(word_len as u32 | LineControl::FifoEnable as u32);
+
+        let mut cr = Control::Enable as u32 | Control::TxEnable as u32 | Control::RxEnable as u32;
+        if config.flow_control {
+            Gpio::new(16).into_alt(Function::Alt3);
+            Gpio::new(17).into_alt(Function::Alt3);
+            cr |= Control::RtsFlow as u32 | Control::CtsFlow as u32;
+        }
+        self.registers.CR.write(cr);
+        self.config = config;
+        Ok(())
+    }
+
+    /// Changes the baud rate to `baud` bps, keeping the other settings.
+    pub fn set_baud_rate(&mut self, baud: u32) -> Result<(), Error> {
+        let config = Config { baud, ..self.config };
+        self.configure(config)
+    }
+
+    /// Set the read timeout to `t` duration.
+    pub fn set_read_timeout(&mut self, t: Duration) {
+        self.timeout = Some(t);
+    }
+
+    /// Unmasks (`enable == true`) or masks the interrupt `int`.
+    pub fn enable_interrupt(&mut self, int: Interrupt, enable: bool) {
+        if enable {
+            self.registers.IMSC.or_mask(int as u32);
+        } else {
+            self.registers.IMSC.and_mask(!(int as u32));
+        }
+    }
+
+    /// Returns `true` if the unmasked interrupt `int` is pending.
+    pub fn is_pending(&self, int: Interrupt) -> bool {
+        self.registers.MIS.has_mask(int as u32)
+    }
+
+    /// Clears the pending interrupt `int`.
+    pub fn clear_interrupt(&mut self, int: Interrupt) {
+        self.registers.ICR.write(int as u32);
+    }
+
+    /// Write the byte `byte`. This method blocks until there is space available
+    /// in the output FIFO.
+    pub fn write_byte(&mut self, byte: u8) {
+        while self.registers.FR.has_mask(Flag::TxFifoFull as u32) {}
+        self.registers.DR.write(byte as u32);
+    }
+
+    /// Returns `true` if a byte can be written without blocking.
+    pub fn can_write(&self) -> bool {
+        !self.registers.FR.has_mask(Flag::TxFifoFull as u32)
+    }
+
+    /// Returns `true` if there is at least one byte ready to be read. This
+    /// method does not block.
+    pub fn has_byte(&self) -> bool {
+        !self.registers.FR.has_mask(Flag::RxFifoEmpty as u32)
+    }
+
+    /// Blocks until there is a byte ready to read, or until the read timeout
+    /// expires if one is set. Returns `Err(())` on timeout.
+    pub fn wait_for_byte(&self) -> Result<(), ()> {
+        let start = timer::current_time();
+        while !self.has_byte() {
+            if let Some(d) = self.timeout {
+                if start + d <= timer::current_time() {
+                    return Err(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a byte. Blocks indefinitely until a byte is ready to be read.
+    pub fn read_byte(&mut self) -> u8 {
+        while !self.has_byte() {}
+        self.registers.DR.read() as u8
+    }
+
+    /// Blocks until all pending output has been transmitted.
+    pub fn flush(&mut self) {
+        while self.registers.FR.has_mask(Flag::Busy as u32) {}
+    }
+}
+
+impl fmt::Write for Pl011 {
+    fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                self.write_byte(b'\r');
+            }
+            self.write_byte(byte);
+        }
+        Ok(())
+    }
+}
+
+impl io::Read for Pl011 {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        if self.wait_for_byte().is_ok() {
+            let mut i = 0;
+            while i < buf.len() && self.has_byte() {
+                buf[i] = self.read_byte();
+                i += 1;
+            }
+            Ok(i)
+        } else {
+            ioerr!(TimedOut, "time out exceeded")
+        }
+    }
+}
+
+impl io::Write for Pl011 {
+    fn write(&mut self, data: &[u8]) -> Result<usize, io::Error> {
+        for &byte in data {
+            self.write_byte(byte);
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        Pl011::flush(self);
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+use crate::common::IO_BASE;
+
+use volatile::prelude::*;
+use volatile::{ReadVolatile, Volatile};
+
+/// The base address of the hardware random number generator.
+const RNG_REG_BASE: usize = IO_BASE + 0x104000;
+
+/// `CTRL` bit that starts the generator.
+const CTRL_ENABLE: u32 = 1;
+
+/// `INT_MASK` bit that keeps the generator from raising interrupts.
+const INT_MASK_OFF: u32 = 1;
+
+/// The number of initial values the generator discards, written to `STATUS`
+/// before it is enabled: early values are not random enough.
+const WARMUP_COUNT: u32 = 0x40000;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    CTRL: Volatile<u32>,
+    STATUS: Volatile<u32>,
+    DATA: ReadVolatile<u32>,
+    _FF_THRESHOLD: Volatile<u32>,
+    INT_MASK: Volatile<u32>,
+}
+
+/// The Raspberry Pi hardware random number generator.
+pub struct Rng {
+    registers: &'static mut Registers,
+}
+
+impl Rng {
+    /// Returns a handle to the generator, starting it if it is not running.
+    pub fn new() -> Rng {
+        let registers = unsafe { &mut *(RNG_REG_BASE as *mut Registers) };
+        if !registers.CTRL.has_mask(CTRL_ENABLE) {
+            registers.STATUS.write(WARMUP_COUNT);
+            registers.INT_MASK.or_mask(INT_MASK_OFF);
+            registers.CTRL.or_mask(CTRL_ENABLE);
+        }
+        Rng { registers }
+    }
+
+    /// Waits for a random word and returns it.
+    pub fn next_u32(&mut self) -> u32 {
+        // The top byte of `STATUS` counts the words ready to be read.
+        while self.registers.STATUS.read() >> 24 == 0 {}
+        self.registers.DATA.read()
+    }
+
+    /// Returns two random words as one.
+    pub fn next_u64(&mut self) -> u64 {
+        ((self.next_u32() as u64) << 32) | self.next_u32() as u64
+    }
+}
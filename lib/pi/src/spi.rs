@@ -0,0 +1,140 @@
+use volatile::prelude::*;
+use volatile::Volatile;
+
+use crate::common::IO_BASE;
+use crate::gpio::{Function, Gpio};
+use crate::mailbox::{self, Clock};
+
+/// The base address for the `SPI0` registers.
+const SPI0_REG_BASE: usize = IO_BASE + 0x204000;
+
+/// The core clock frequency assumed when the firmware cannot be queried.
+const DEFAULT_CORE_CLOCK: u32 = 250_000_000;
+
+/// The GPIO pins of the bus's signals, all on `Alt0`.
+const MISO_PIN: u8 = 9;
+const MOSI_PIN: u8 = 10;
+const SCLK_PIN: u8 = 11;
+
+/// Enum representing bit fields of the `CS` register.
+#[repr(u32)]
+enum Control {
+    Cpha = 1 << 2,
+    Cpol = 1 << 3,
+    ClearTx = 1 << 4,
+    ClearRx = 1 << 5,
+    Active = 1 << 7,
+    Done = 1 << 16,
+    RxData = 1 << 17,
+    TxSpace = 1 << 18,
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    CS: Volatile<u32>,
+    FIFO: Volatile<u32>,
+    CLK: Volatile<u32>,
+    DLEN: Volatile<u32>,
+    LTOH: Volatile<u32>,
+    DC: Volatile<u32>,
+}
+
+/// The chip select line asserted during transfers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChipSelect {
+    /// `CE0`, on GPIO 8.
+    Ce0 = 0,
+    /// `CE1`, on GPIO 7.
+    Ce1 = 1,
+}
+
+impl ChipSelect {
+    fn pin(self) -> u8 {
+        match self {
+            ChipSelect::Ce0 => 8,
+            ChipSelect::Ce1 => 7,
+        }
+    }
+}
+
+/// The clock polarity and phase: in mode 0 the clock idles low and data is
+/// sampled on its rising edge, as most devices expect.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Mode0,
+    Mode1,
+    Mode2,
+    Mode3,
+}
+
+/// The `SPI0` master, polled.
+///
+/// Each transfer asserts the chip select line, clocks out the bytes given
+/// while clocking in as many, and releases the line.
+pub struct Spi {
+    registers: &'static mut Registers,
+    /// The `CS` register bits kept between transfers: the chip select and
+    /// the mode.
+    control: u32,
+}
+
+impl Spi {
+    /// Returns the `SPI0` master, with its pins set up, transferring to the
+    /// device on `chip_select` in `mode` at the fastest clock rate not above
+    /// `hz`.
+    pub fn new(chip_select: ChipSelect, mode: Mode, hz: u32) -> Spi {
+        for &pin in [MISO_PIN, MOSI_PIN, SCLK_PIN, chip_select.pin()].iter() {
+            Gpio::new(pin).into_alt(Function::Alt0);
+        }
+        let mut control = chip_select as u32;
+        if let Mode::Mode1 | Mode::Mode3 = mode {
+            control |= Control::Cpha as u32;
+        }
+        if let Mode::Mode2 | Mode::Mode3 = mode {
+            control |= Control::Cpol as u32;
+        }
+        let registers = unsafe { &mut *(SPI0_REG_BASE as *mut Registers) };
+        registers.CS.write(control | Control::ClearTx as u32 | Control::ClearRx as u32);
+        let mut spi = Spi { registers, control };
+        spi.set_clock(hz);
+        spi
+    }
+
+    /// Sets the clock rate to the fastest not above `hz`: the core clock
+    /// divided by an even number from 2 to 65534.
+    pub fn set_clock(&mut self, hz: u32) {
+        let core_clock = mailbox::clock_rate(Clock::Core).unwrap_or(DEFAULT_CORE_CLOCK);
+        let divisor = (core_clock + hz.max(1) - 1) / hz.max(1);
+        let divisor = ((divisor + 1) & !1).max(2).min(65534);
+        self.registers.CLK.write(divisor);
+    }
+
+    /// Sends `tx` and stores the bytes received meanwhile in `rx`. Received
+    /// bytes beyond the length of `rx` are dropped.
+    pub fn transfer(&mut self, tx: &[u8], rx: &mut [u8]) {
+        let control = self.control | Control::ClearTx as u32 | Control::ClearRx as u32;
+        self.registers.CS.write(control | Control::Active as u32);
+        let (mut sent, mut received) = (0, 0);
+        while received < tx.len() {
+            while sent < tx.len() && self.registers.CS.has_mask(Control::TxSpace as u32) {
+                self.registers.FIFO.write(tx[sent] as u32);
+                sent += 1;
+            }
+            while received < sent && self.registers.CS.has_mask(Control::RxData as u32) {
+                let byte = self.registers.FIFO.read() as u8;
+                if let Some(slot) = rx.get_mut(received) {
+                    *slot = byte;
+                }
+                received += 1;
+            }
+        }
+        while !self.registers.CS.has_mask(Control::Done as u32) {}
+        self.registers.CS.write(self.control);
+    }
+
+    /// Sends `bytes`, ignoring what is received.
+    pub fn write(&mut self, bytes: &[u8]) {
+        self.transfer(bytes, &mut []);
+    }
+}
@@ -0,0 +1,226 @@
+//! Small TFT panels driven by an ST7735 or ILI9341 controller over SPI.
+//!
+//! Besides the SPI signals, the controller has a data/command line (D/C),
+//! low while a command byte is sent and high for its parameters and for
+//! pixel data, and optionally a reset line. Pixels are sent as 16-bit RGB565
+//! values, most significant byte first, into a window set beforehand; the
+//! controller keeps the image, so only the parts that change are sent.
+
+use crate::delay::delay_ms;
+use crate::gpio::{Gpio, Output};
+use crate::spi::{ChipSelect, Mode, Spi};
+
+/// Controller commands.
+const SWRESET: u8 = 0x01;
+const SLPOUT: u8 = 0x11;
+const NORON: u8 = 0x13;
+const DISPON: u8 = 0x29;
+const CASET: u8 = 0x2A;
+const RASET: u8 = 0x2B;
+const RAMWR: u8 = 0x2C;
+const MADCTL: u8 = 0x36;
+const COLMOD: u8 = 0x3A;
+
+/// The number of pixels converted to bytes at a time by `write_pixels()`.
+const CHUNK_PIXELS: usize = 64;
+
+/// A supported panel.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Panel {
+    /// A 128x160 ST7735 panel.
+    St7735,
+    /// A 240x320 ILI9341 panel.
+    Ili9341,
+}
+
+impl Panel {
+    /// Returns the panel named `name`, as returned by `name()`.
+    pub fn from_name(name: &str) -> Option<Panel> {
+        match name {
+            "st7735" => Some(Panel::St7735),
+            "ili9341" => Some(Panel::Ili9341),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Panel::St7735 => "st7735",
+            Panel::Ili9341 => "ili9341",
+        }
+    }
+
+    /// Returns the panel's width and height in pixels, in portrait
+    /// orientation.
+    pub fn size(&self) -> (usize, usize) {
+        match self {
+            Panel::St7735 => (128, 160),
+            Panel::Ili9341 => (240, 320),
+        }
+    }
+
+    /// Returns the fastest SPI clock rate the controller accepts for writes.
+    fn max_hz(&self) -> u32 {
+        match self {
+            Panel::St7735 => 15_000_000,
+            Panel::Ili9341 => 10_000_000,
+        }
+    }
+
+    /// Returns the `MADCTL` value that shows the image upright with RGB
+    /// pixel order on common modules.
+    fn orientation(&self) -> u8 {
+        match self {
+            Panel::St7735 => 0xC8,
+            Panel::Ili9341 => 0x48,
+        }
+    }
+
+    /// Returns the `COLMOD` value selecting 16 bits per pixel.
+    fn pixel_format(&self) -> u8 {
+        match self {
+            Panel::St7735 => 0x05,
+            Panel::Ili9341 => 0x55,
+        }
+    }
+}
+
+/// How a panel is wired.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub panel: Panel,
+    /// The GPIO pin wired to D/C.
+    pub dc: u8,
+    /// The GPIO pin wired to the reset line, if any. Without one the panel is
+    /// reset with a command.
+    pub reset: Option<u8>,
+    pub chip_select: ChipSelect,
+}
+
+impl Config {
+    /// Returns the usual wiring of `panel`: D/C on GPIO 24, reset on GPIO 25
+    /// and chip select on `CE0`.
+    pub fn new(panel: Panel) -> Config {
+        Config { panel, dc: 24, reset: Some(25), chip_select: ChipSelect::Ce0 }
+    }
+}
+
+/// A TFT panel.
+pub struct Tft {
+    spi: Spi,
+    dc: Gpio<Output>,
+    panel: Panel,
+}
+
+impl Tft {
+    /// Resets and initializes the panel wired as in `config` and turns it
+    /// on. Its contents are undefined until drawn.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the D/C or reset pin is > `53`.
+    pub fn new(config: &Config) -> Tft {
+        let panel = config.panel;
+        let spi = Spi::new(config.chip_select, Mode::Mode0, panel.max_hz());
+        let dc = Gpio::new(config.dc).into_output();
+        let mut tft = Tft { spi, dc, panel };
+        match config.reset {
+            Some(pin) => {
+                let mut reset = Gpio::new(pin).into_output();
+                reset.clear();
+                delay_ms(10);
+                reset.set();
+            }
+            None => tft.command(SWRESET, &[]),
+        }
+        delay_ms(150);
+        tft.command(SLPOUT, &[]);
+        delay_ms(120);
+        tft.command(COLMOD, &[panel.pixel_format()]);
+        tft.command(MADCTL, &[panel.orientation()]);
+        tft.command(NORON, &[]);
+        tft.command(DISPON, &[]);
+        tft
+    }
+
+    pub fn panel(&self) -> Panel {
+        self.panel
+    }
+
+    /// Returns the panel's width and height in pixels.
+    pub fn size(&self) -> (usize, usize) {
+        self.panel.size()
+    }
+
+    /// Sends the command `cmd` followed by its parameters `params`.
+    pub fn command(&mut self, cmd: u8, params: &[u8]) {
+        self.dc.clear();
+        self.spi.write(&[cmd]);
+        self.dc.set();
+        if !params.is_empty() {
+            self.spi.write(params);
+        }
+    }
+
+    /// Sets the window the following pixels fill, row by row, and starts
+    /// writing to it. The window is clipped to the panel.
+    fn set_window(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        let (panel_width, panel_height) = self.size();
+        let x_end = (x + width).min(panel_width).saturating_sub(1) as u16;
+        let y_end = (y + height).min(panel_height).saturating_sub(1) as u16;
+        let range = |start: u16, end: u16| {
+            let ([s0, s1], [e0, e1]) = (start.to_be_bytes(), end.to_be_bytes());
+            [s0, s1, e0, e1]
+        };
+        self.command(CASET, &range(x as u16, x_end));
+        self.command(RASET, &range(y as u16, y_end));
+        self.command(RAMWR, &[]);
+    }
+
+    /// Sends `pixels` into the window set last.
+    fn write_pixels(&mut self, pixels: &[u16]) {
+        let mut bytes = [0; CHUNK_PIXELS * 2];
+        for chunk in pixels.chunks(CHUNK_PIXELS) {
+            for (i, pixel) in chunk.iter().enumerate() {
+                bytes[2 * i..2 * i + 2].copy_from_slice(&pixel.to_be_bytes());
+            }
+            self.spi.write(&bytes[..chunk.len() * 2]);
+        }
+    }
+
+    /// Draws the `width` by `height` rectangle of RGB565 `pixels`, given row
+    /// by row, with its top left corner at (`x`, `y`).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pixels` is shorter than `width * height` or the rectangle
+    /// does not fit on the panel.
+    pub fn draw(&mut self, x: usize, y: usize, width: usize, height: usize, pixels: &[u16]) {
+        let (panel_width, panel_height) = self.size();
+        assert!(x + width <= panel_width && y + height <= panel_height);
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.set_window(x, y, width, height);
+        self.write_pixels(&pixels[..width * height]);
+    }
+
+    /// Fills the `width` by `height` rectangle at (`x`, `y`) with `color`,
+    /// clipped to the panel.
+    pub fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, color: u16) {
+        let (panel_width, panel_height) = self.size();
+        let width = width.min(panel_width.saturating_sub(x));
+        let height = height.min(panel_height.saturating_sub(y));
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.set_window(x, y, width, height);
+        let row = [color; CHUNK_PIXELS];
+        let mut left = width * height;
+        while left > 0 {
+            let n = left.min(CHUNK_PIXELS);
+            self.write_pixels(&row[..n]);
+            left -= n;
+        }
+    }
+}
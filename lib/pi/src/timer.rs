@@ -1,4 +1,7 @@
 use crate::common::IO_BASE;
+use crate::interrupt::Interrupt;
+use core::cmp::{max, min};
+use core::sync::atomic::{AtomicU64, Ordering};
 use core::time::Duration;
 
 use volatile::prelude::*;
@@ -7,6 +10,52 @@ use volatile::{ReadVolatile, Volatile};
 /// The base address for the ARM system timer registers.
 const TIMER_REG_BASE: usize = IO_BASE + 0x3000;
 
+/// A compare channel of the system timer that the ARM may use. Channels 0
+/// and 2 are used by the GPU.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Channel {
+    One = 1,
+    Three = 3,
+}
+
+impl Channel {
+    /// Every channel, in order.
+    pub const ALL: [Channel; 2] = [Channel::One, Channel::Three];
+
+    /// Returns the interrupt a match on the channel raises.
+    pub fn interrupt(self) -> Interrupt {
+        match self {
+            Channel::One => Interrupt::Timer1,
+            Channel::Three => Interrupt::Timer3,
+        }
+    }
+
+    /// Returns the channel's index into `Channel::ALL`.
+    pub fn index(self) -> usize {
+        match self {
+            Channel::One => 0,
+            Channel::Three => 1,
+        }
+    }
+}
+
+/// The longest interval, in microseconds, programmed into the 32-bit compare
+/// register at once. Staying well below 2^32 keeps the match unambiguous;
+/// longer deadlines are reached through a chain of intermediate matches.
+const MAX_STEP_MICROS: u64 = 1 << 31;
+
+/// The minimum distance, in microseconds, between the counter and a newly
+/// written compare value. A compare value the counter has already passed
+/// would only match after the counter wraps, ~71 minutes later.
+const MIN_LEAD_MICROS: u64 = 2;
+
+/// Marker for "no deadline armed" in `DEADLINES`.
+const NO_DEADLINE: u64 = u64::MAX;
+
+/// The absolute deadline, in microseconds since boot, armed on each channel,
+/// indexed by `Channel::index()`.
+static DEADLINES: [AtomicU64; 2] = [AtomicU64::new(NO_DEADLINE), AtomicU64::new(NO_DEADLINE)];
+
 #[repr(C)]
 #[allow(non_snake_case)]
 struct Registers {
@@ -16,53 +65,137 @@ struct Registers {
     COMPARE: [Volatile<u32>; 4],
 }
 
-/// The Raspberry Pi ARM system timer.
+/// The Raspberry Pi ARM system timer, armed through one of its compare
+/// channels.
+///
+/// Two users arming the same channel would overwrite each other's deadlines,
+/// so a channel must only be armed by the user it has been given to; the
+/// kernel hands channels out in its `systimer` module. Reading the time needs
+/// no channel: see `current_time()`.
 pub struct Timer {
     registers: &'static mut Registers,
+    channel: Channel,
 }
 
 impl Timer {
-    /// Returns a new instance of `Timer`.
-    pub fn new() -> Timer {
+    /// Returns a new instance of `Timer` that arms `channel`.
+    pub fn with_channel(channel: Channel) -> Timer {
         Timer {
             registers: unsafe { &mut *(TIMER_REG_BASE as *mut Registers) },
+            channel,
         }
     }
 
+    /// Returns the channel this timer arms.
+    pub fn channel(&self) -> Channel {
+        self.channel
+    }
+
     /// Reads the system timer's counter and returns Duration.
     /// `CLO` and `CHI` together can represent the number of elapsed microseconds.
     pub fn read(&self) -> Duration {
-        let timer = Timer::new();
-        let micros: u64 = (timer.registers.CLO.read() as u64) + ((timer.registers.CHI.read() as u64) << 32);
-        Duration::from_micros(micros)
+        Duration::from_micros(self.micros())
     }
 
-    /// Sets up a match in timer 1 to occur `t` duration from now. If
-    /// interrupts for timer 1 are enabled and IRQs are unmasked, then a timer
+    fn micros(&self) -> u64 {
+        counter(self.registers)
+    }
+
+    /// Sets up a match on the channel to occur `t` duration from now. If
+    /// the channel's interrupt is enabled and IRQs are unmasked, then a timer
     /// interrupt will be issued in `t` duration.
+    ///
+    /// Equivalent to `arm_at(self.read() + t)`.
     pub fn tick_in(&mut self, t: Duration) {
-        self.registers.CS.or_mask(1 << 1);
-        self.registers.COMPARE[1].write(self.registers.CLO.read() + (t.as_micros() as u32));
+        let now = self.read();
+        self.arm_at(now + t);
+    }
+
+    /// Arms the channel to interrupt at the absolute time `deadline`, replacing
+    /// any previously armed deadline. A deadline in the past interrupts
+    /// immediately.
+    ///
+    /// Deadlines too far away for the 32-bit compare register are reached
+    /// through intermediate interrupts; the interrupt handler must call
+    /// `check_deadline()` to tell these apart from the real deadline.
+    pub fn arm_at(&mut self, deadline: Duration) {
+        let deadline = min(deadline.as_micros(), (NO_DEADLINE - 1) as u128) as u64;
+        self.armed().store(deadline, Ordering::SeqCst);
+        self.registers.CS.write(1 << self.channel as usize);
+        self.program(deadline);
+    }
+
+    /// Cancels the armed deadline, if any. A match that is already pending is
+    /// reported as spurious by `check_deadline()`.
+    pub fn disarm(&mut self) {
+        self.armed().store(NO_DEADLINE, Ordering::SeqCst);
+    }
+
+    /// Returns the armed deadline, if any.
+    pub fn deadline(&self) -> Option<Duration> {
+        match self.armed().load(Ordering::SeqCst) {
+            NO_DEADLINE => None,
+            micros => Some(Duration::from_micros(micros)),
+        }
+    }
+
+    /// Acknowledges a match on the channel. Returns `true` if the armed deadline has
+    /// been reached, in which case it is disarmed. Otherwise, re-arms the next
+    /// leg of a long deadline (if any) and returns `false`.
+    pub fn check_deadline(&mut self) -> bool {
+        self.registers.CS.write(1 << self.channel as usize);
+        let deadline = self.armed().load(Ordering::SeqCst);
+        if deadline == NO_DEADLINE {
+            return false;
+        }
+        if self.micros() >= deadline {
+            self.armed().store(NO_DEADLINE, Ordering::SeqCst);
+            return true;
+        }
+        self.program(deadline);
+        false
+    }
+
+    /// Writes the compare value for the next match on the way to `deadline`.
+    fn program(&mut self, deadline: u64) {
+        loop {
+            let now = self.micros();
+            let target = max(now + MIN_LEAD_MICROS, min(deadline, now + MAX_STEP_MICROS));
+            self.registers.COMPARE[self.channel as usize].write(target as u32);
+            // If the counter overtook `target` while it was being written,
+            // the match was missed; try again from the new counter value.
+            if self.micros() < target {
+                return;
+            }
+        }
+    }
+
+    /// Returns the deadline armed on the channel.
+    fn armed(&self) -> &'static AtomicU64 {
+        &DEADLINES[self.channel.index()]
+    }
+}
+
+/// Returns the 64-bit counter value. `CHI` is read on both sides of `CLO` so
+/// that a carry out of `CLO` between the two reads is not missed.
+fn counter(registers: &Registers) -> u64 {
+    loop {
+        let hi = registers.CHI.read();
+        let lo = registers.CLO.read();
+        if registers.CHI.read() == hi {
+            return ((hi as u64) << 32) | lo as u64;
+        }
     }
 }
 
 /// Returns current time.
 pub fn current_time() -> Duration {
-    Timer::new().read()
+    let registers = unsafe { &*(TIMER_REG_BASE as *const Registers) };
+    Duration::from_micros(counter(registers))
 }
 
-/// Spins until `t` duration have passed.
+/// Waits until `t` duration have passed. Equivalent to `delay::delay(t)`,
+/// which waits on the CPU's counter instead of polling the system timer.
 pub fn spin_sleep(t: Duration) {
-    let mut now = current_time();
-    let end_time = now + t;
-    while now < end_time {
-        now = current_time();
-    }
-}
-
-/// Sets up a match in timer 1 to occur `t` duration from now. If
-/// interrupts for timer 1 are enabled and IRQs are unmasked, then a timer
-/// interrupt will be issued in `t` duration.
-pub fn tick_in(t: Duration) {
-    Timer::new().tick_in(t)
+    crate::delay::delay(t)
 }
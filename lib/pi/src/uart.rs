@@ -9,6 +9,7 @@ use volatile::{ReadVolatile, Reserved, Volatile};
 
 use crate::common::IO_BASE;
 use crate::gpio::{Function, Gpio};
+use crate::mailbox::{self, Clock};
 use crate::timer;
 
 /// The base address for the `MU` registers.
@@ -17,11 +18,77 @@ const MU_REG_BASE: usize = IO_BASE + 0x215040;
 /// The `AUXENB` register from page 9 of the BCM2837 documentation.
 const AUX_ENABLES: *mut Volatile<u8> = (IO_BASE + 0x215004) as *mut Volatile<u8>;
 
+/// The core clock rate assumed when the firmware cannot be queried.
+const DEFAULT_CORE_CLOCK: u32 = 250_000_000;
+
 /// Enum representing bit fields of the `AUX_MU_LSR_REG` register.
 #[repr(u8)]
 enum LsrStatus {
     DataReady = 1,
     TxAvailable = 1 << 5,
+    TxIdle = 1 << 6,
+}
+
+/// Enum representing bit fields of the `AUX_MU_CNTL_REG` register.
+#[repr(u8)]
+enum Cntl {
+    RxEnable = 1,
+    TxEnable = 1 << 1,
+    RtsFlow = 1 << 2,
+    CtsFlow = 1 << 3,
+}
+
+/// The number of data bits in each character.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DataBits {
+    Seven,
+    Eight,
+}
+
+/// Line settings for the mini UART.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// The requested baud rate in bits per second.
+    pub baud: u32,
+    /// The number of data bits per character.
+    pub data_bits: DataBits,
+    /// Whether RTS/CTS hardware flow control is enabled. Uses GPIO pins 16
+    /// (CTS1) and 17 (RTS1).
+    pub flow_control: bool,
+}
+
+impl Default for Config {
+    /// 115200 bps, 8 data bits, no flow control.
+    fn default() -> Config {
+        Config {
+            baud: 115200,
+            data_bits: DataBits::Eight,
+            flow_control: false,
+        }
+    }
+}
+
+/// Error returned when a `Config` cannot be applied.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The baud rate cannot be derived from the core clock.
+    UnsupportedBaudRate(u32),
+}
+
+/// Returns the `AUX_MU_BAUD_REG` divisor that most closely yields `baud` bps
+/// from a core clock of `core_clock` Hz, or `None` if `baud` is out of range.
+///
+/// The mini UART runs at `core_clock / (8 * (divisor + 1))` bps.
+pub fn baud_divisor(core_clock: u32, baud: u32) -> Option<u16> {
+    if baud == 0 {
+        return None;
+    }
+    let step = 8 * baud as u64;
+    let scaled = (core_clock as u64 + step / 2) / step;
+    if scaled == 0 || scaled - 1 > u16::MAX as u64 {
+        return None;
+    }
+    Some((scaled - 1) as u16)
 }
 
 #[repr(C)]
@@ -55,31 +122,84 @@ const_assert_size!(Registers, 0x7E21506C - 0x7E215040);
 pub struct MiniUart {
     registers: &'static mut Registers,
     timeout: Option<Duration>,
+    config: Config,
 }
 
 impl MiniUart {
+    /// Initializes the mini UART with the default `Config` (115200 bps, 8
+    /// data bits, no flow control). See `with_config()`.
+    pub fn new() -> MiniUart {
+        MiniUart::with_config(Config::default())
+            .expect("default UART configuration is supported")
+    }
+
     /// Initializes the mini UART by enabling it as an auxiliary peripheral,
-    /// setting the data size to 8 bits, setting the BAUD rate to ~115200 (baud
-    /// divider of 270), setting GPIO pins 14 and 15 to alternative function 5
-    /// (TXD1/RDXD1), and finally enabling the UART transmitter and receiver.
+    /// setting GPIO pins 14 and 15 to alternative function 5 (TXD1/RDXD1),
+    /// applying `config`, and finally enabling the UART transmitter and
+    /// receiver.
+    ///
+    /// The baud rate divisor is derived from the core clock reported by the
+    /// firmware. The core clock must not be scaled while the UART is in use
+    /// (e.g. `core_freq` should be fixed in `config.txt`).
     ///
     /// By default, reads will never time out. To set a read timeout, use
     /// `set_read_timeout()`.
-    pub fn new() -> MiniUart {
+    pub fn with_config(config: Config) -> Result<MiniUart, Error> {
         let registers = unsafe {
             // Enable the mini UART as an auxiliary device.
             (*AUX_ENABLES).or_mask(1);
             &mut *(MU_REG_BASE as *mut Registers)
         };
-        registers.LCR.or_mask(0b11);
-        registers.BAUD.write(270);
         Gpio::new(14).into_alt(Function::Alt5);
         Gpio::new(15).into_alt(Function::Alt5);
-        registers.CNTL.or_mask(0b11);
-        MiniUart {
+        let mut uart = MiniUart {
             registers: registers,
-            timeout: None
+            timeout: None,
+            config,
+        };
+        uart.configure(config)?;
+        Ok(uart)
+    }
+
+    /// Returns the current line settings.
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    /// Applies `config`, waiting for pending output to drain first. The
+    /// transmitter and receiver are disabled while the settings change, so
+    /// input arriving during reconfiguration is lost.
+    ///
+    /// On error, the current settings are left untouched.
+    pub fn configure(&mut self, config: Config) -> Result<(), Error> {
+        let core_clock = mailbox::clock_rate(Clock::Core).unwrap_or(DEFAULT_CORE_CLOCK);
+        let divisor = baud_divisor(core_clock, config.baud)
+            .ok_or(Error::UnsupportedBaudRate(config.baud))?;
+
+        while !self.registers.LSR.has_mask(LsrStatus::TxIdle as u8) {}
+        self.registers.CNTL.write(0);
+        // Bit 1 of LCR is undocumented but required for 8-bit mode.
+        self.registers.LCR.write(match config.data_bits {
+            DataBits::Seven => 0b00,
+            DataBits::Eight => 0b11,
+        });
+        self.registers.BAUD.write(divisor);
+
+        let mut cntl = Cntl::RxEnable as u8 | Cntl::TxEnable as u8;
+        if config.flow_control {
+            Gpio::new(16).into_alt(Function::Alt5);
+            Gpio::new(17).into_alt(Function::Alt5);
+            cntl |= Cntl::RtsFlow as u8 | Cntl::CtsFlow as u8;
         }
+        self.registers.CNTL.write(cntl);
+        self.config = config;
+        Ok(())
+    }
+
+    /// Changes the baud rate to `baud` bps, keeping the other settings.
+    pub fn set_baud_rate(&mut self, baud: u32) -> Result<(), Error> {
+        let config = Config { baud, ..self.config };
+        self.configure(config)
     }
 
     /// Set the read timeout to `t` duration.
@@ -97,6 +217,35 @@ impl MiniUart {
         self.registers.IO.write(byte);
     }
 
+    /// Returns `true` if a byte can be written without blocking.
+    pub fn can_write(&self) -> bool {
+        self.registers.LSR.has_mask(LsrStatus::TxAvailable as u8)
+    }
+
+    /// Enables or disables the transmit interrupt, which is asserted on the
+    /// `Aux` interrupt line while the transmit FIFO is empty.
+    pub fn enable_tx_interrupt(&mut self, enable: bool) {
+        // Bits 0 and 1 of IER are swapped relative to the documentation; bit 1
+        // enables the transmit interrupt.
+        if enable {
+            self.registers.IER.or_mask(1 << 1);
+        } else {
+            self.registers.IER.and_mask(!(1 << 1));
+        }
+    }
+
+    /// Enables or disables the receive interrupt, which is asserted on the
+    /// `Aux` interrupt line while the receive FIFO holds a byte.
+    pub fn enable_rx_interrupt(&mut self, enable: bool) {
+        // Bit 0 of IER enables the receive interrupt; see
+        // `enable_tx_interrupt()`.
+        if enable {
+            self.registers.IER.or_mask(1 << 0);
+        } else {
+            self.registers.IER.and_mask(!(1 << 0));
+        }
+    }
+
     /// Returns `true` if there is at least one byte ready to be read. If this
     /// method returns `true`, a subsequent call to `read_byte` is guaranteed to
     /// return immediately. This method does not block.
@@ -189,9 +338,9 @@ mod uart_io {
         }
 
         fn flush(&mut self) -> Result<(), io::Error> {
-            let mut done = self.registers.LSR.has_mask(1 << 6);
+            let mut done = self.registers.LSR.has_mask(super::LsrStatus::TxIdle as u8);
             while !done {
-                done = self.registers.LSR.has_mask(1 << 6);
+                done = self.registers.LSR.has_mask(super::LsrStatus::TxIdle as u8);
             }
             Ok(())
         }
@@ -0,0 +1,28 @@
+//! The atomic operations the ring buffers are built on: those of
+//! `aarch64::atomic` in the kernel and their `core` equivalents elsewhere,
+//! as in host tests.
+
+#[cfg(all(target_arch = "aarch64", target_os = "none"))]
+pub use aarch64::atomic::{compare_exchange, load_acquire, store_release};
+
+#[cfg(not(all(target_arch = "aarch64", target_os = "none")))]
+pub use self::host::*;
+
+#[cfg(not(all(target_arch = "aarch64", target_os = "none")))]
+mod host {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    pub fn load_acquire(word: &AtomicUsize) -> usize {
+        word.load(Ordering::Acquire)
+    }
+
+    pub fn store_release(word: &AtomicUsize, value: usize) {
+        word.store(value, Ordering::Release)
+    }
+
+    pub fn compare_exchange(word: &AtomicUsize, current: usize, new: usize)
+        -> Result<usize, usize>
+    {
+        word.compare_exchange(current, new, Ordering::AcqRel, Ordering::Acquire)
+    }
+}
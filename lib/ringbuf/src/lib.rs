@@ -0,0 +1,59 @@
+#![no_std]
+
+//! Fixed-size, lock-free ring buffers for passing values between interrupt
+//! handlers, kernel threads and cores without taking a lock.
+//!
+//! `Spsc` connects one producer to one consumer. `Mpsc` accepts values from
+//! any number of producers, including interrupt handlers that preempt
+//! another producer mid-push, and hands them to one consumer. Neither
+//! allocates: values live in an array given to the `const fn` constructor,
+//! so either can be a `static`.
+//!
+//! Positions are free-running counters and an array of `CAPACITY` values is
+//! indexed by position modulo its length, so capacities are powers of two.
+//! `Array` is only implemented for such arrays.
+
+#[cfg(test)]
+mod tests;
+
+mod atomic;
+
+pub mod mpsc;
+pub mod spsc;
+
+pub use mpsc::{Mpsc, Slot};
+pub use spsc::Spsc;
+
+/// The error returned when items do not fit in a ring buffer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Full;
+
+/// Storage for a ring buffer.
+///
+/// # Safety
+///
+/// Implementors must be laid out as `CAPACITY` contiguous `Item`s, and
+/// `CAPACITY` must be a power of two.
+pub unsafe trait Array {
+    type Item;
+
+    /// The number of items the storage holds.
+    const CAPACITY: usize;
+}
+
+macro_rules! impl_array {
+    ($($n:expr),*) => {
+        $(unsafe impl<T> Array for [T; $n] {
+            type Item = T;
+            const CAPACITY: usize = $n;
+        })*
+    };
+}
+
+impl_array!(1, 2, 4, 8, 16, 32, 64, 128, 256, 512, 1024, 2048, 4096, 8192, 16384, 32768, 65536);
+
+/// Returns a pointer to the item of `storage` at `position`, modulo the
+/// capacity.
+unsafe fn slot<A: Array>(storage: *mut A, position: usize) -> *mut A::Item {
+    (storage as *mut A::Item).add(position & (A::CAPACITY - 1))
+}
@@ -0,0 +1,197 @@
+//! A ring buffer with any number of producers and a single consumer.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{atomic, slot, Array, Full};
+
+/// An item of an `Mpsc` and the stamp that says whose turn it is.
+///
+/// The stamp of the slot for position `p` is `lap(p)` while it is free for
+/// the producer of `p`, `lap(p) + 1` once that producer has written it, and
+/// `lap(p) + CAPACITY` once the consumer has read it, which is the lap of the
+/// next position using the slot. `lap(p)` is `p` rounded down to a multiple
+/// of the capacity, so zeroed stamps start every slot free for the first lap.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Slot<T> {
+    /// Only accessed atomically. First, so that a pointer to the slot is a
+    /// pointer to it.
+    stamp: usize,
+    value: T,
+}
+
+impl<T> Slot<T> {
+    /// Returns an empty slot. `value` is never read.
+    pub const fn new(value: T) -> Slot<T> {
+        Slot { stamp: 0, value }
+    }
+}
+
+/// A ring buffer that any number of contexts push to, including interrupt
+/// handlers that preempt a push on the same core, and one context pops
+/// from.
+///
+/// Producers claim positions with a compare-and-swap and never wait for one
+/// another. A producer that is preempted between claiming a position and
+/// writing it holds up the consumer, which sees the buffer as empty at that
+/// position until it is written, but not other producers.
+///
+/// The storage is an array of `Slot`s: `Mpsc::new([Slot::new(0u8); 1024])`.
+pub struct Mpsc<A> {
+    storage: UnsafeCell<A>,
+    /// The position of the next item to pop. Only the consumer writes it.
+    head: AtomicUsize,
+    /// The next position to be claimed by a producer.
+    tail: AtomicUsize,
+}
+
+unsafe impl<A: Array<Item = Slot<T>>, T: Send> Sync for Mpsc<A> { }
+
+/// The popping half of an `Mpsc`.
+pub struct Consumer<'a, A> {
+    ring: &'a Mpsc<A>,
+}
+
+impl<A> Mpsc<A> {
+    /// Returns an empty ring buffer that keeps its items in `storage`.
+    pub const fn new(storage: A) -> Mpsc<A> {
+        Mpsc {
+            storage: UnsafeCell::new(storage),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl<A: Array<Item = Slot<T>>, T: Copy> Mpsc<A> {
+    /// Returns the most items the buffer holds.
+    pub fn capacity(&self) -> usize {
+        A::CAPACITY
+    }
+
+    /// Returns the number of positions claimed but not yet popped, including
+    /// any whose producer has not finished writing them. The result may be
+    /// stale when it returns.
+    pub fn len(&self) -> usize {
+        let head = atomic::load_acquire(&self.head);
+        atomic::load_acquire(&self.tail).wrapping_sub(head)
+    }
+
+    /// Returns `true` if no positions are claimed.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the stamp of the slot for `position`.
+    fn stamp(&self, position: usize) -> &AtomicUsize {
+        // `Slot` is `repr(C)` with the stamp first, and `AtomicUsize` has the
+        // layout of `usize`.
+        unsafe { &*(slot(self.storage.get(), position) as *const AtomicUsize) }
+    }
+
+    /// Returns `position` rounded down to a multiple of the capacity.
+    fn lap(position: usize) -> usize {
+        position & !(A::CAPACITY - 1)
+    }
+
+    /// Pushes `item`, returning it back if the buffer is full.
+    pub fn push(&self, item: T) -> Result<(), T> {
+        self.push_slice(&[item]).map_err(|_| item)
+    }
+
+    /// Pushes all of `items` at consecutive positions, so that the consumer
+    /// pops them in order without items of other producers in between.
+    /// Returns `Err(Full)` without pushing any if they do not all fit.
+    pub fn push_slice(&self, items: &[T]) -> Result<(), Full> {
+        if items.is_empty() {
+            return Ok(());
+        }
+        if items.len() > A::CAPACITY {
+            return Err(Full);
+        }
+
+        // The consumer frees slots in order, so if the slot for the last
+        // position is free, so are those before it.
+        let mut tail = self.tail.load(Ordering::Relaxed);
+        loop {
+            let last = tail.wrapping_add(items.len() - 1);
+            let stamp = atomic::load_acquire(self.stamp(last));
+            match stamp.wrapping_sub(Self::lap(last)) as isize {
+                0 => {
+                    let claimed = tail.wrapping_add(items.len());
+                    match atomic::compare_exchange(&self.tail, tail, claimed) {
+                        Ok(_) => break,
+                        Err(current) => tail = current,
+                    }
+                }
+                // The slot still holds an item from the previous lap.
+                n if n < 0 => return Err(Full),
+                // Another producer claimed `last` since `tail` was read.
+                _ => tail = self.tail.load(Ordering::Relaxed),
+            }
+        }
+
+        for (i, &item) in items.iter().enumerate() {
+            let position = tail.wrapping_add(i);
+            unsafe {
+                let slot = slot(self.storage.get(), position);
+                (*slot).value = item;
+            }
+            let written = Self::lap(position).wrapping_add(1);
+            atomic::store_release(self.stamp(position), written);
+        }
+        Ok(())
+    }
+
+    /// Returns the buffer's consumer.
+    ///
+    /// # Safety
+    ///
+    /// No other `Consumer` for this buffer may be in use while the returned
+    /// one is.
+    pub unsafe fn consumer(&self) -> Consumer<'_, A> {
+        Consumer { ring: self }
+    }
+
+    /// Removes and returns the oldest item, if it has been written.
+    pub fn pop(&mut self) -> Option<T> {
+        Consumer { ring: &*self }.pop()
+    }
+}
+
+impl<'a, A: Array<Item = Slot<T>>, T: Copy> Consumer<'a, A> {
+    /// Removes and returns the oldest item, if it has been written.
+    pub fn pop(&mut self) -> Option<T> {
+        let ring = self.ring;
+        let head = ring.head.load(Ordering::Relaxed);
+        let lap = Mpsc::<A>::lap(head);
+        // Pairs with the producer's release once the item is written.
+        if atomic::load_acquire(ring.stamp(head)) != lap.wrapping_add(1) {
+            return None;
+        }
+        let item = unsafe { (*slot(ring.storage.get(), head)).value };
+        atomic::store_release(ring.stamp(head), lap.wrapping_add(A::CAPACITY));
+        atomic::store_release(&ring.head, head.wrapping_add(1));
+        Some(item)
+    }
+
+    /// Moves the oldest written items into `buf`, as many as are available
+    /// and fit, and returns how many were moved.
+    pub fn pop_slice(&mut self, buf: &mut [T]) -> usize {
+        let mut count = 0;
+        while count < buf.len() {
+            match self.pop() {
+                Some(item) => buf[count] = item,
+                None => break,
+            }
+            count += 1;
+        }
+        count
+    }
+
+    /// Returns `true` if no positions are claimed.
+    pub fn is_empty(&self) -> bool {
+        self.ring.is_empty()
+    }
+}
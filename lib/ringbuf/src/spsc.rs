@@ -0,0 +1,179 @@
+//! A ring buffer with a single producer and a single consumer.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{atomic, slot, Array};
+
+/// A ring buffer that one context pushes to and one context pops from,
+/// possibly on different cores or with one of them an interrupt handler.
+///
+/// The owner of a `&mut Spsc` can `push()` and `pop()` directly or `split()`
+/// the buffer into a `Producer` and a `Consumer`. A shared `Spsc`, such as a
+/// `static`, hands them out with the unsafe `producer()` and `consumer()`,
+/// whose callers promise there is only one of each at a time.
+pub struct Spsc<A> {
+    storage: UnsafeCell<A>,
+    /// The position of the next item to pop. Only the consumer writes it.
+    head: AtomicUsize,
+    /// The position of the next item to push. Only the producer writes it.
+    tail: AtomicUsize,
+}
+
+unsafe impl<A: Array> Sync for Spsc<A> where A::Item: Send { }
+
+/// The pushing half of an `Spsc`.
+pub struct Producer<'a, A> {
+    ring: &'a Spsc<A>,
+}
+
+/// The popping half of an `Spsc`.
+pub struct Consumer<'a, A> {
+    ring: &'a Spsc<A>,
+}
+
+impl<A> Spsc<A> {
+    /// Returns an empty ring buffer that keeps its items in `storage`. The
+    /// initial contents of `storage` are never read.
+    pub const fn new(storage: A) -> Spsc<A> {
+        Spsc {
+            storage: UnsafeCell::new(storage),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl<A: Array> Spsc<A> {
+    /// Returns the most items the buffer holds.
+    pub fn capacity(&self) -> usize {
+        A::CAPACITY
+    }
+
+    /// Returns the number of items in the buffer. With a producer or
+    /// consumer active elsewhere, the result may be stale when it returns.
+    pub fn len(&self) -> usize {
+        let head = atomic::load_acquire(&self.head);
+        atomic::load_acquire(&self.tail).wrapping_sub(head)
+    }
+
+    /// Returns `true` if the buffer holds no items.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns `true` if the buffer cannot take another item.
+    pub fn is_full(&self) -> bool {
+        self.len() == A::CAPACITY
+    }
+
+    /// Splits the buffer into its producer and consumer.
+    pub fn split(&mut self) -> (Producer<'_, A>, Consumer<'_, A>) {
+        let ring = &*self;
+        (Producer { ring }, Consumer { ring })
+    }
+
+    /// Returns the buffer's producer.
+    ///
+    /// # Safety
+    ///
+    /// No other `Producer` for this buffer may be in use while the returned
+    /// one is.
+    pub unsafe fn producer(&self) -> Producer<'_, A> {
+        Producer { ring: self }
+    }
+
+    /// Returns the buffer's consumer.
+    ///
+    /// # Safety
+    ///
+    /// No other `Consumer` for this buffer may be in use while the returned
+    /// one is.
+    pub unsafe fn consumer(&self) -> Consumer<'_, A> {
+        Consumer { ring: self }
+    }
+}
+
+impl<A: Array> Spsc<A> where A::Item: Copy {
+    /// Pushes `item`, returning it back if the buffer is full.
+    pub fn push(&mut self, item: A::Item) -> Result<(), A::Item> {
+        self.split().0.push(item)
+    }
+
+    /// Removes and returns the oldest item, if any.
+    pub fn pop(&mut self) -> Option<A::Item> {
+        self.split().1.pop()
+    }
+}
+
+impl<'a, A: Array> Producer<'a, A> where A::Item: Copy {
+    /// Pushes `item`, returning it back if the buffer is full.
+    pub fn push(&mut self, item: A::Item) -> Result<(), A::Item> {
+        let tail = self.ring.tail.load(Ordering::Relaxed);
+        // Pairs with the consumer's release of the slot it popped last.
+        let head = atomic::load_acquire(&self.ring.head);
+        if tail.wrapping_sub(head) == A::CAPACITY {
+            return Err(item);
+        }
+        unsafe { slot(self.ring.storage.get(), tail).write(item) };
+        atomic::store_release(&self.ring.tail, tail.wrapping_add(1));
+        Ok(())
+    }
+
+    /// Pushes as many of `items` as fit, in order, and returns how many
+    /// were pushed.
+    pub fn push_slice(&mut self, items: &[A::Item]) -> usize {
+        let tail = self.ring.tail.load(Ordering::Relaxed);
+        let head = atomic::load_acquire(&self.ring.head);
+        let count = items.len().min(A::CAPACITY - tail.wrapping_sub(head));
+        for (i, &item) in items[..count].iter().enumerate() {
+            unsafe { slot(self.ring.storage.get(), tail.wrapping_add(i)).write(item) };
+        }
+        atomic::store_release(&self.ring.tail, tail.wrapping_add(count));
+        count
+    }
+
+    /// Returns `true` if the buffer cannot take another item.
+    pub fn is_full(&self) -> bool {
+        self.ring.is_full()
+    }
+}
+
+impl<'a, A: Array> Consumer<'a, A> where A::Item: Copy {
+    /// Removes and returns the oldest item, if any.
+    pub fn pop(&mut self) -> Option<A::Item> {
+        let item = self.peek()?;
+        let head = self.ring.head.load(Ordering::Relaxed);
+        // Hands the slot back to the producer once it has been read.
+        atomic::store_release(&self.ring.head, head.wrapping_add(1));
+        Some(item)
+    }
+
+    /// Returns the oldest item without removing it, if any.
+    pub fn peek(&self) -> Option<A::Item> {
+        let head = self.ring.head.load(Ordering::Relaxed);
+        // Pairs with the producer's release of the item it pushed last.
+        if atomic::load_acquire(&self.ring.tail) == head {
+            return None;
+        }
+        Some(unsafe { slot(self.ring.storage.get(), head).read() })
+    }
+
+    /// Moves the oldest items into `buf`, as many as are available and fit,
+    /// and returns how many were moved.
+    pub fn pop_slice(&mut self, buf: &mut [A::Item]) -> usize {
+        let head = self.ring.head.load(Ordering::Relaxed);
+        let tail = atomic::load_acquire(&self.ring.tail);
+        let count = buf.len().min(tail.wrapping_sub(head));
+        for (i, item) in buf[..count].iter_mut().enumerate() {
+            *item = unsafe { slot(self.ring.storage.get(), head.wrapping_add(i)).read() };
+        }
+        atomic::store_release(&self.ring.head, head.wrapping_add(count));
+        count
+    }
+
+    /// Returns `true` if the buffer holds no items.
+    pub fn is_empty(&self) -> bool {
+        self.ring.is_empty()
+    }
+}
@@ -0,0 +1,160 @@
+extern crate std;
+
+use std::sync::Arc;
+use std::thread;
+use std::vec::Vec;
+
+use crate::{Full, Mpsc, Slot, Spsc};
+
+#[test]
+fn spsc_is_fifo_and_bounded() {
+    let mut ring = Spsc::new([0u8; 4]);
+    assert_eq!(ring.capacity(), 4);
+    assert!(ring.is_empty());
+    assert_eq!(ring.pop(), None);
+
+    for byte in 1..=4 {
+        assert_eq!(ring.push(byte), Ok(()));
+    }
+    assert!(ring.is_full());
+    assert_eq!(ring.push(5), Err(5));
+
+    assert_eq!(ring.pop(), Some(1));
+    assert_eq!(ring.push(5), Ok(()));
+    assert_eq!(ring.len(), 4);
+    for byte in 2..=5 {
+        assert_eq!(ring.pop(), Some(byte));
+    }
+    assert_eq!(ring.pop(), None);
+}
+
+#[test]
+fn spsc_wraps_around_its_storage() {
+    let mut ring = Spsc::new([0u32; 8]);
+    let (mut producer, mut consumer) = ring.split();
+    for round in 0..100u32 {
+        assert_eq!(producer.push_slice(&[round, round + 1, round + 2]), 3);
+        assert_eq!(consumer.peek(), Some(round));
+        let mut buf = [0; 2];
+        assert_eq!(consumer.pop_slice(&mut buf), 2);
+        assert_eq!(buf, [round, round + 1]);
+        assert_eq!(consumer.pop(), Some(round + 2));
+    }
+    assert!(consumer.is_empty());
+
+    assert_eq!(producer.push_slice(&[7; 10]), 8);
+    assert!(producer.is_full());
+}
+
+#[test]
+fn spsc_passes_items_between_threads() {
+    static RING: Spsc<[u64; 64]> = Spsc::new([0; 64]);
+    const COUNT: u64 = 100_000;
+
+    let producer = thread::spawn(|| {
+        let mut producer = unsafe { RING.producer() };
+        for i in 0..COUNT {
+            while producer.push(i).is_err() {
+                thread::yield_now();
+            }
+        }
+    });
+
+    let mut consumer = unsafe { RING.consumer() };
+    let mut expected = 0;
+    while expected < COUNT {
+        match consumer.pop() {
+            Some(i) => {
+                assert_eq!(i, expected);
+                expected += 1;
+            }
+            None => thread::yield_now(),
+        }
+    }
+    producer.join().unwrap();
+    assert!(RING.is_empty());
+}
+
+#[test]
+fn mpsc_is_fifo_and_bounded() {
+    let mut ring = Mpsc::new([Slot::new(0u8); 4]);
+    assert_eq!(ring.pop(), None);
+    for byte in 1..=4 {
+        assert_eq!(ring.push(byte), Ok(()));
+    }
+    assert_eq!(ring.push(5), Err(5));
+    assert_eq!(ring.pop(), Some(1));
+    assert_eq!(ring.push(5), Ok(()));
+    for byte in 2..=5 {
+        assert_eq!(ring.pop(), Some(byte));
+    }
+    assert!(ring.is_empty());
+}
+
+#[test]
+fn mpsc_pushes_slices_whole_or_not_at_all() {
+    let ring = Mpsc::new([Slot::new(0u8); 8]);
+    assert_eq!(ring.push_slice(b"hello"), Ok(()));
+    assert_eq!(ring.push_slice(b"world"), Err(Full));
+    assert_eq!(ring.push_slice(b"!!!"), Ok(()));
+    assert_eq!(ring.push_slice(&[0; 9]), Err(Full));
+
+    let mut buf = [0; 8];
+    let mut consumer = unsafe { ring.consumer() };
+    assert_eq!(consumer.pop_slice(&mut buf), 8);
+    assert_eq!(&buf, b"hello!!!");
+
+    // Wrapping around the end of the storage keeps the slice together.
+    assert_eq!(ring.push_slice(b"abcdef"), Ok(()));
+    let mut consumer = unsafe { ring.consumer() };
+    assert_eq!(consumer.pop_slice(&mut buf), 6);
+    assert_eq!(&buf[..6], b"abcdef");
+    assert!(consumer.is_empty());
+}
+
+#[test]
+fn mpsc_keeps_each_producers_records_whole() {
+    const PRODUCERS: u32 = 4;
+    const RECORDS: u32 = 10_000;
+    let ring = Arc::new(Mpsc::new([Slot::new(0u32); 256]));
+
+    let producers: Vec<_> = (0..PRODUCERS)
+        .map(|p| {
+            let ring = ring.clone();
+            thread::spawn(move || {
+                for i in 0..RECORDS {
+                    let record = [p, i, p ^ i];
+                    while ring.push_slice(&record).is_err() {
+                        thread::yield_now();
+                    }
+                }
+            })
+        })
+        .collect();
+
+    let mut consumer = unsafe { ring.consumer() };
+    let mut next = [0; PRODUCERS as usize];
+    let mut received = 0;
+    while received < PRODUCERS * RECORDS {
+        let mut record = [0; 3];
+        let mut len = 0;
+        while len < 3 {
+            match consumer.pop() {
+                Some(word) => {
+                    record[len] = word;
+                    len += 1;
+                }
+                None => thread::yield_now(),
+            }
+        }
+        let (p, i) = (record[0], record[1]);
+        assert_eq!(record[2], p ^ i);
+        assert_eq!(next[p as usize], i);
+        next[p as usize] += 1;
+        received += 1;
+    }
+    for producer in producers {
+        producer.join().unwrap();
+    }
+    assert!(ring.is_empty());
+}
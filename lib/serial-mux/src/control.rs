@@ -0,0 +1,100 @@
+//! Messages on `Channel::Control`, one per frame: an opcode byte followed
+//! by the message's data.
+//!
+//! The host sends requests and the kernel answers each one:
+//!
+//!   * `Ping` is answered with `Pong`.
+//!   * `Get(path)` is answered with the file's contents in `Data` messages
+//!     and then `Done`.
+//!   * `Put(path)` starts replacing the file with the contents of the `Data`
+//!     messages that follow, up to `Done`. Each of these messages is
+//!     answered with `Ack` once it is handled, and the host waits for it
+//!     before sending the next, so the kernel is never sent more than one
+//!     frame ahead.
+//!
+//!   * `Telemetry(interval)` starts sending a `Sample` message every
+//!     `interval` milliseconds, or stops if it is 0, and is answered with
+//!     `Ack`. Samples are sent unasked until the stream is stopped; see
+//!     `telemetry`.
+//!
+//! Any request may instead be answered with `Error`, which ends a transfer.
+
+use crate::MAX_PAYLOAD;
+
+const PING: u8 = 0x01;
+const PONG: u8 = 0x02;
+const GET: u8 = 0x03;
+const PUT: u8 = 0x04;
+const DATA: u8 = 0x05;
+const DONE: u8 = 0x06;
+const ACK: u8 = 0x07;
+const ERROR: u8 = 0x08;
+const TELEMETRY: u8 = 0x09;
+const SAMPLE: u8 = 0x0A;
+
+/// The most data a message carries: a frame's payload less the opcode.
+/// Paths and error messages must also fit.
+pub const MAX_DATA: usize = MAX_PAYLOAD - 1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Message<'a> {
+    Ping,
+    Pong,
+    Get(&'a str),
+    Put(&'a str),
+    Data(&'a [u8]),
+    Done,
+    Ack,
+    Error(&'a str),
+    Telemetry(u32),
+    Sample(&'a [u8]),
+}
+
+impl<'a> Message<'a> {
+    /// Parses the payload of a control frame. Returns `None` if the opcode is
+    /// unknown or a path or error message is not UTF-8.
+    pub fn parse(payload: &'a [u8]) -> Option<Message<'a>> {
+        let (&opcode, data) = payload.split_first()?;
+        let text = || core::str::from_utf8(data).ok();
+        Some(match opcode {
+            PING => Message::Ping,
+            PONG => Message::Pong,
+            GET => Message::Get(text()?),
+            PUT => Message::Put(text()?),
+            DATA => Message::Data(data),
+            DONE => Message::Done,
+            ACK => Message::Ack,
+            ERROR => Message::Error(text()?),
+            TELEMETRY if data.len() == 4 => {
+                Message::Telemetry(u32::from_le_bytes([data[0], data[1], data[2], data[3]]))
+            }
+            SAMPLE => Message::Sample(data),
+            _ => return None,
+        })
+    }
+
+    /// Passes the payload of the message's frame to `out`. Data beyond
+    /// `MAX_DATA` bytes is cut off, so that the message fits in one frame.
+    pub fn encode(&self, mut out: impl FnMut(u8)) {
+        let interval;
+        let (opcode, data): (u8, &[u8]) = match *self {
+            Message::Ping => (PING, &[]),
+            Message::Pong => (PONG, &[]),
+            Message::Get(path) => (GET, path.as_bytes()),
+            Message::Put(path) => (PUT, path.as_bytes()),
+            Message::Data(data) => (DATA, data),
+            Message::Done => (DONE, &[]),
+            Message::Ack => (ACK, &[]),
+            Message::Error(message) => (ERROR, message.as_bytes()),
+            Message::Telemetry(ms) => {
+                interval = ms.to_le_bytes();
+                (TELEMETRY, &interval)
+            }
+            Message::Sample(sample) => (SAMPLE, sample),
+        };
+        out(opcode);
+        for &byte in &data[..data.len().min(MAX_DATA)] {
+            out(byte);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+#![no_std]
+
+//! Multiplexes several byte streams over one serial line, so that the
+//! console, file transfers and log streaming can share a single cable.
+//!
+//! Each write on a `Channel` is sent as one or more frames. A frame is the
+//! channel number, up to `MAX_PAYLOAD` bytes of payload and a CRC-16 of both,
+//! followed by an `END` byte. `END` and `ESC` bytes inside a frame are
+//! escaped as in SLIP, so a receiver that loses bytes or starts listening in
+//! the middle of a frame resynchronizes at the next `END`. Frames that fail
+//! their CRC are dropped.
+//!
+//! The kernel and host tools speak the same protocol: both sides use
+//! `encode()` to send and a `Decoder` to receive. The messages carried on
+//! `Channel::Control` are defined in `control`, and the telemetry samples
+//! the kernel streams there in `telemetry`.
+
+#[cfg(test)]
+mod tests;
+
+pub mod control;
+pub mod telemetry;
+
+use checksum::Crc16;
+
+/// Ends a frame.
+const END: u8 = 0xC0;
+/// Starts an escape sequence: `ESC_END` for an `END` byte in the frame,
+/// `ESC_ESC` for an `ESC` byte.
+const ESC: u8 = 0xDB;
+const ESC_END: u8 = 0xDC;
+const ESC_ESC: u8 = 0xDD;
+
+/// The most payload bytes a frame carries. Longer writes are split.
+pub const MAX_PAYLOAD: usize = 256;
+
+/// The size of a frame before escaping: a channel byte, the payload and a
+/// two-byte CRC.
+const MAX_FRAME: usize = 1 + MAX_PAYLOAD + 2;
+
+/// A stream carried on the serial line.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Channel {
+    /// Console input and output.
+    Console = 0,
+    /// Control requests and replies; see `control`.
+    Control = 1,
+    /// Kernel log records.
+    Log = 2,
+}
+
+impl Channel {
+    /// Returns the channel numbered `n`, if there is one.
+    pub fn from_u8(n: u8) -> Option<Channel> {
+        match n {
+            0 => Some(Channel::Console),
+            1 => Some(Channel::Control),
+            2 => Some(Channel::Log),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the CRC-16/CCITT-FALSE of `bytes` continuing from `crc`, which
+/// is `0xFFFF` at the start.
+pub fn crc16(crc: u16, bytes: &[u8]) -> u16 {
+    let mut crc = Crc16::resume(crc);
+    crc.update(bytes);
+    crc.finish()
+}
+
+/// Passes `byte` to `out`, escaped if needed.
+fn put_escaped(byte: u8, out: &mut impl FnMut(u8)) {
+    match byte {
+        END => {
+            out(ESC);
+            out(ESC_END);
+        }
+        ESC => {
+            out(ESC);
+            out(ESC_ESC);
+        }
+        _ => out(byte),
+    }
+}
+
+/// Encodes `payload` as frames on `channel` and passes the bytes to send to
+/// `out`. An empty payload is sent as one empty frame.
+pub fn encode(channel: Channel, payload: &[u8], mut out: impl FnMut(u8)) {
+    let mut chunks = payload.chunks(MAX_PAYLOAD);
+    let first = chunks.next().unwrap_or(&[]);
+    for chunk in core::iter::once(first).chain(chunks) {
+        let crc = crc16(crc16(0xFFFF, &[channel as u8]), chunk);
+        put_escaped(channel as u8, &mut out);
+        for &byte in chunk {
+            put_escaped(byte, &mut out);
+        }
+        for &byte in crc.to_be_bytes().iter() {
+            put_escaped(byte, &mut out);
+        }
+        out(END);
+    }
+}
+
+/// Reassembles frames from the bytes received on the serial line.
+pub struct Decoder {
+    frame: [u8; MAX_FRAME],
+    len: usize,
+    /// Whether the last byte was `ESC`.
+    escaped: bool,
+    /// Whether the frame being received is malformed and is to be dropped
+    /// at its `END`.
+    invalid: bool,
+    /// The number of frames dropped.
+    dropped: usize,
+}
+
+impl Decoder {
+    pub const fn new() -> Decoder {
+        Decoder { frame: [0; MAX_FRAME], len: 0, escaped: false, invalid: false, dropped: 0 }
+    }
+
+    /// Returns the number of frames dropped for being malformed, too long,
+    /// failing their CRC or naming an unknown channel.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Feeds in `byte`, received from the line. Returns the channel and
+    /// payload of the frame it completes, if any.
+    pub fn push(&mut self, byte: u8) -> Option<(Channel, &[u8])> {
+        if byte == END {
+            let (len, invalid) = (self.len, self.invalid || self.escaped);
+            self.len = 0;
+            self.escaped = false;
+            self.invalid = false;
+            // Consecutive `END`s delimit nothing.
+            if len == 0 && !invalid {
+                return None;
+            }
+            return match self.check(len, invalid) {
+                Some(channel) => Some((channel, &self.frame[1..len - 2])),
+                None => {
+                    self.dropped += 1;
+                    None
+                }
+            };
+        }
+        if byte == ESC && !self.escaped {
+            self.escaped = true;
+            return None;
+        }
+        let byte = match (self.escaped, byte) {
+            (false, byte) => byte,
+            (true, ESC_END) => END,
+            (true, ESC_ESC) => ESC,
+            (true, byte) => {
+                self.invalid = true;
+                byte
+            }
+        };
+        self.escaped = false;
+        if self.len == MAX_FRAME {
+            self.invalid = true;
+        } else {
+            self.frame[self.len] = byte;
+            self.len += 1;
+        }
+        None
+    }
+
+    /// Returns the channel of the `len`-byte frame in `self.frame`, or `None`
+    /// if it is to be dropped.
+    fn check(&self, len: usize, invalid: bool) -> Option<Channel> {
+        if invalid || len < 3 {
+            return None;
+        }
+        let crc = u16::from_be_bytes([self.frame[len - 2], self.frame[len - 1]]);
+        if crc16(0xFFFF, &self.frame[..len - 2]) != crc {
+            return None;
+        }
+        Channel::from_u8(self.frame[0])
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Decoder {
+        Decoder::new()
+    }
+}
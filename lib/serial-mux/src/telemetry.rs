@@ -0,0 +1,94 @@
+//! Telemetry samples: snapshots of the kernel's counters, streamed to the
+//! host in `Sample` control messages so that it can graph them live.
+//!
+//! A sample is `SIZE` bytes: `VERSION`, then each field of `Sample` in the
+//! order declared, little endian. Counters are totals since boot; the host
+//! takes the difference between consecutive samples for rates. Payloads of
+//! another version are rejected, and bytes past `SIZE` are ignored.
+
+/// The version of the sample layout, bumped whenever it changes.
+pub const VERSION: u8 = 2;
+
+/// The size of an encoded sample.
+pub const SIZE: usize = 81;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Sample {
+    /// Counts the samples sent since the stream was started, from 0, so that
+    /// the host can tell when some were lost.
+    pub sequence: u32,
+    /// Time since boot, in microseconds.
+    pub uptime: u64,
+    /// The size of the kernel heap and the part of it not handed out, in
+    /// bytes.
+    pub heap_size: u64,
+    pub heap_free: u64,
+    /// The processes in the scheduler's queue and those of them ready to
+    /// run.
+    pub processes: u16,
+    pub ready: u16,
+    /// The processes dispatched by all cores.
+    pub dispatches: u64,
+    /// The interrupts taken, and the time spent handling them in the IRQ
+    /// path, in microseconds.
+    pub irqs: u64,
+    pub irq_time: u64,
+    /// The sectors in the file system's cache and those of them modified.
+    pub cached_sectors: u32,
+    pub dirty_sectors: u32,
+    /// The sector accesses served from the cache and those that went to the
+    /// disk.
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+}
+
+impl Sample {
+    /// Passes the encoded sample, `SIZE` bytes, to `out`.
+    pub fn encode(&self, mut out: impl FnMut(u8)) {
+        let mut put = |bytes: &[u8]| bytes.iter().for_each(|&byte| out(byte));
+        put(&[VERSION]);
+        put(&self.sequence.to_le_bytes());
+        put(&self.uptime.to_le_bytes());
+        put(&self.heap_size.to_le_bytes());
+        put(&self.heap_free.to_le_bytes());
+        put(&self.processes.to_le_bytes());
+        put(&self.ready.to_le_bytes());
+        put(&self.dispatches.to_le_bytes());
+        put(&self.irqs.to_le_bytes());
+        put(&self.irq_time.to_le_bytes());
+        put(&self.cached_sectors.to_le_bytes());
+        put(&self.dirty_sectors.to_le_bytes());
+        put(&self.cache_hits.to_le_bytes());
+        put(&self.cache_misses.to_le_bytes());
+    }
+
+    /// Decodes a sample. Returns `None` if `bytes` is too short or of another
+    /// `VERSION`.
+    pub fn parse(bytes: &[u8]) -> Option<Sample> {
+        if bytes.len() < SIZE || bytes[0] != VERSION {
+            return None;
+        }
+        let mut rest = &bytes[1..];
+        let mut take = |n: usize| {
+            let mut word = [0; 8];
+            word[..n].copy_from_slice(&rest[..n]);
+            rest = &rest[n..];
+            u64::from_le_bytes(word)
+        };
+        Some(Sample {
+            sequence: take(4) as u32,
+            uptime: take(8),
+            heap_size: take(8),
+            heap_free: take(8),
+            processes: take(2) as u16,
+            ready: take(2) as u16,
+            dispatches: take(8),
+            irqs: take(8),
+            irq_time: take(8),
+            cached_sectors: take(4) as u32,
+            dirty_sectors: take(4) as u32,
+            cache_hits: take(8),
+            cache_misses: take(8),
+        })
+    }
+}
@@ -0,0 +1,148 @@
+extern crate std;
+
+use std::vec::Vec;
+
+use crate::control::{Message, MAX_DATA};
+use crate::telemetry::{self, Sample};
+use crate::{crc16, encode, Channel, Decoder, MAX_PAYLOAD};
+
+/// Returns the frames `encode()` produces for `payload` on `channel`.
+fn encoded(channel: Channel, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    encode(channel, payload, |byte| bytes.push(byte));
+    bytes
+}
+
+/// Feeds `bytes` to `decoder` and returns the frames it completes.
+fn decode_all(decoder: &mut Decoder, bytes: &[u8]) -> Vec<(Channel, Vec<u8>)> {
+    let mut frames = Vec::new();
+    for &byte in bytes {
+        if let Some((channel, payload)) = decoder.push(byte) {
+            frames.push((channel, payload.to_vec()));
+        }
+    }
+    frames
+}
+
+#[test]
+fn crc16_check_value() {
+    assert_eq!(crc16(0xFFFF, b"123456789"), 0x29B1);
+}
+
+#[test]
+fn round_trips_frames() {
+    let mut decoder = Decoder::new();
+    let mut line = encoded(Channel::Console, b"hello\n");
+    line.extend(encoded(Channel::Log, &[0xC0, 0xDB, 0xDC, 0xDD]));
+    line.extend(encoded(Channel::Control, &[]));
+    let frames = decode_all(&mut decoder, &line);
+    assert_eq!(frames, [
+        (Channel::Console, b"hello\n".to_vec()),
+        (Channel::Log, [0xC0, 0xDB, 0xDC, 0xDD].to_vec()),
+        (Channel::Control, Vec::new()),
+    ]);
+    assert_eq!(decoder.dropped(), 0);
+}
+
+#[test]
+fn splits_long_payloads() {
+    let payload: Vec<u8> = (0..MAX_PAYLOAD * 2 + 10).map(|i| i as u8).collect();
+    let frames = decode_all(&mut Decoder::new(), &encoded(Channel::Control, &payload));
+    let lengths: Vec<usize> = frames.iter().map(|(_, payload)| payload.len()).collect();
+    assert_eq!(lengths, [MAX_PAYLOAD, MAX_PAYLOAD, 10]);
+    let joined: Vec<u8> = frames.into_iter().flat_map(|(_, payload)| payload).collect();
+    assert_eq!(joined, payload);
+}
+
+#[test]
+fn resynchronizes_after_noise() {
+    let mut decoder = Decoder::new();
+    let mut line = b"garbage before the first frame".to_vec();
+    line.push(0xC0);
+    line.extend(encoded(Channel::Console, b"ok"));
+
+    // A corrupted frame is dropped without losing the one after it.
+    let mut corrupted = encoded(Channel::Console, b"lost");
+    corrupted[2] ^= 0x01;
+    line.extend(corrupted);
+    line.extend(encoded(Channel::Log, b"kept"));
+
+    // So is a frame naming an unknown channel, and a bad escape.
+    let mut unknown = encoded(Channel::Console, b"x");
+    unknown[0] = 7;
+    line.extend(unknown);
+    line.extend(&[0x41, 0xDB, 0x41, 0xC0]);
+
+    let frames = decode_all(&mut decoder, &line);
+    assert_eq!(frames, [(Channel::Console, b"ok".to_vec()), (Channel::Log, b"kept".to_vec())]);
+    assert_eq!(decoder.dropped(), 4);
+}
+
+#[test]
+fn drops_overlong_frames() {
+    let mut decoder = Decoder::new();
+    let mut line = std::vec![0x00; MAX_PAYLOAD + 10];
+    line.push(0xC0);
+    line.extend(encoded(Channel::Console, b"after"));
+    let frames = decode_all(&mut decoder, &line);
+    assert_eq!(frames, [(Channel::Console, b"after".to_vec())]);
+    assert_eq!(decoder.dropped(), 1);
+}
+
+#[test]
+fn control_messages_round_trip() {
+    let data = [1, 2, 3];
+    let messages = [
+        Message::Ping,
+        Message::Pong,
+        Message::Get("/config.txt"),
+        Message::Put("/kernel8.img"),
+        Message::Data(&data),
+        Message::Done,
+        Message::Ack,
+        Message::Error("not found"),
+        Message::Telemetry(250),
+        Message::Sample(&data),
+    ];
+    for message in messages.iter() {
+        let mut payload = Vec::new();
+        message.encode(|byte| payload.push(byte));
+        assert_eq!(Message::parse(&payload).as_ref(), Some(message));
+    }
+    assert_eq!(Message::parse(&[]), None);
+    assert_eq!(Message::parse(&[0xFF]), None);
+    assert_eq!(Message::parse(&[0x03, 0xFF]), None);
+    assert_eq!(Message::parse(&[0x09, 0x01]), None);
+
+    let long = [0xAA; MAX_DATA + 10];
+    let mut payload = Vec::new();
+    Message::Data(&long).encode(|byte| payload.push(byte));
+    assert_eq!(payload.len(), MAX_PAYLOAD);
+}
+
+#[test]
+fn telemetry_samples_round_trip() {
+    let sample = Sample {
+        sequence: 7,
+        uptime: 12_345_678,
+        heap_size: 0x3000_0000,
+        heap_free: 0x2000_0000,
+        processes: 9,
+        ready: 2,
+        dispatches: 1 << 40,
+        irqs: 100_000,
+        irq_time: 4_000,
+        cached_sectors: 512,
+        dirty_sectors: 4,
+        cache_hits: 90,
+        cache_misses: 10,
+    };
+    let mut payload = Vec::new();
+    sample.encode(|byte| payload.push(byte));
+    assert_eq!(payload.len(), telemetry::SIZE);
+    assert_eq!(Sample::parse(&payload), Some(sample));
+
+    assert_eq!(Sample::parse(&payload[..telemetry::SIZE - 1]), None);
+    payload[0] = telemetry::VERSION + 1;
+    assert_eq!(Sample::parse(&payload), None);
+}
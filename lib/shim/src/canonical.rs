@@ -0,0 +1,45 @@
+//! Lexical path canonicalization.
+//!
+//! These functions only look at the path's text: they never consult a file
+//! system, so `a/..` becomes `.` even if `a` does not exist or is a file.
+
+use crate::path::{Component, Path, PathBuf};
+
+/// Returns `path` with repeated separators collapsed, `.` components removed
+/// and each `..` component applied to the component before it.
+///
+/// `..` at the root stays at the root. Leading `..` components of a relative
+/// path are kept, since there is nothing to apply them to. An empty result is
+/// returned as `.` for relative paths.
+pub fn normalize<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut out = PathBuf::new();
+    // The number of `Normal` components at the end of `out` that a `..` may
+    // remove.
+    let mut depth = 0;
+    for component in path.as_ref().components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => out.push(component.as_os_str()),
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => {
+                out.pop();
+                depth -= 1;
+            }
+            Component::ParentDir if out.has_root() => {}
+            Component::ParentDir => out.push(".."),
+            Component::Normal(name) => {
+                out.push(name);
+                depth += 1;
+            }
+        }
+    }
+    if out.as_os_str().is_empty() {
+        out.push(".");
+    }
+    out
+}
+
+/// Resolves `path` relative to the directory `base` and normalizes the
+/// result. An absolute `path` ignores `base`.
+pub fn resolve<B: AsRef<Path>, P: AsRef<Path>>(base: B, path: P) -> PathBuf {
+    normalize(base.as_ref().join(path))
+}
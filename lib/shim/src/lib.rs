@@ -19,5 +19,8 @@ cfg_if::cfg_if! {
 #[macro_use]
 pub mod macros;
 
+#[cfg(feature = "alloc")]
+pub mod canonical;
+
 #[cfg(test)]
 mod tests;
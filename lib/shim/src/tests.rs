@@ -9,4 +9,71 @@ mod assert {
         const_assert_size!(S2, 2+2);
         S2(2, 2);
     }
-}
\ No newline at end of file
+}
+#[cfg(feature = "alloc")]
+mod canonical {
+    use crate::canonical::{normalize, resolve};
+    use crate::path::PathBuf;
+
+    fn check(cases: &[(&str, &str)], f: impl Fn(&str) -> PathBuf) {
+        for &(input, expected) in cases {
+            assert_eq!(f(input), PathBuf::from(expected), "input {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_normalize_absolute() {
+        check(&[
+            ("/", "/"),
+            ("//", "/"),
+            ("/a", "/a"),
+            ("/a/", "/a"),
+            ("//a///b//", "/a/b"),
+            ("/./a/./b/.", "/a/b"),
+            ("/a/..", "/"),
+            ("/a/b/../c", "/a/c"),
+            ("/a/b/../../c/", "/c"),
+            ("/..", "/"),
+            ("/../../a", "/a"),
+            ("/a/../../b", "/b"),
+            ("/a/.../b", "/a/.../b"),
+            ("/a/..b/c", "/a/..b/c"),
+        ], |path| normalize(path));
+    }
+
+    #[test]
+    fn test_normalize_relative() {
+        check(&[
+            ("", "."),
+            (".", "."),
+            ("./", "."),
+            ("a", "a"),
+            ("./a/./b", "a/b"),
+            ("a//b/", "a/b"),
+            ("a/..", "."),
+            ("a/b/..", "a"),
+            ("..", ".."),
+            ("../a", "../a"),
+            ("../../a/..", "../.."),
+            ("a/../../b", "../b"),
+        ], |path| normalize(path));
+    }
+
+    #[test]
+    fn test_resolve() {
+        check(&[
+            ("a", "/home/a"),
+            ("./a/", "/home/a"),
+            (".", "/home"),
+            ("..", "/"),
+            ("../..", "/"),
+            ("../etc//x", "/etc/x"),
+            ("/", "/"),
+            ("/a/../b", "/b"),
+        ], |path| resolve("/home", path));
+
+        assert_eq!(resolve("/a/./b/", "../c"), PathBuf::from("/a/c"));
+        assert_eq!(resolve("rel", "../../x"), PathBuf::from("../x"));
+        assert_eq!(resolve("/", ""), PathBuf::from("/"));
+    }
+}
@@ -0,0 +1,127 @@
+#![feature(alloc_error_handler)]
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+mod cr0;
+
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use kernel_api::heap::Heap;
+use kernel_api::println;
+use kernel_api::syscall::{exit, procinfo};
+
+#[global_allocator]
+static HEAP: Heap = Heap::new();
+
+#[alloc_error_handler]
+fn out_of_memory(layout: Layout) -> ! {
+    println!("memtest: out of memory allocating {} bytes", layout.size());
+    println!("memtest: FAIL");
+    exit();
+}
+
+/// The number of allocations or frees in the random phase.
+const STEPS: usize = 20_000;
+
+/// The most allocations alive at once in the random phase.
+const MAX_LIVE: usize = 256;
+
+/// The largest allocation in the random phase, in bytes.
+const MAX_SIZE: usize = 256 * 1024;
+
+/// The number of elements pushed to a single growing vector.
+const GROWN_LEN: usize = 256 * 1024;
+
+/// A xorshift64 generator; the same seed gives the same run every time.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Returns a number in `0..n`.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// An allocation filled with a pattern that depends on `seed`.
+struct Block {
+    data: Vec<u8>,
+    seed: u8,
+}
+
+fn pattern(seed: u8, i: usize) -> u8 {
+    seed ^ (i as u8).wrapping_mul(31) ^ (i >> 8) as u8
+}
+
+impl Block {
+    fn new(size: usize, seed: u8) -> Block {
+        let data = (0..size).map(|i| pattern(seed, i)).collect();
+        Block { data, seed }
+    }
+
+    fn is_intact(&self) -> bool {
+        self.data.iter().enumerate().all(|(i, &b)| b == pattern(self.seed, i))
+    }
+}
+
+/// Allocates and frees blocks of random sizes, mostly small, and checks that
+/// no block is overwritten while it is alive. Returns the number of corrupted
+/// blocks.
+fn random_phase(rng: &mut Rng) -> usize {
+    let mut live: Vec<Block> = Vec::with_capacity(MAX_LIVE);
+    let mut corrupted = 0;
+    for step in 0..STEPS {
+        if live.is_empty() || (live.len() < MAX_LIVE && rng.below(3) != 0) {
+            let size = if rng.below(16) == 0 {
+                rng.below(MAX_SIZE) + 1
+            } else {
+                rng.below(512) + 1
+            };
+            live.push(Block::new(size, step as u8));
+        } else {
+            let block = live.swap_remove(rng.below(live.len()));
+            if !block.is_intact() {
+                println!("memtest: block of {} bytes corrupted", block.data.len());
+                corrupted += 1;
+            }
+        }
+    }
+    corrupted + live.iter().filter(|block| !block.is_intact()).count()
+}
+
+/// Grows a vector one element at a time, so that it is reallocated many
+/// times, and checks its contents. Returns `true` if they are intact.
+fn growth_phase() -> bool {
+    let mut grown = Vec::new();
+    for i in 0..GROWN_LEN as u64 {
+        grown.push(i.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    }
+    grown.iter().enumerate().all(|(i, &x)| x == (i as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15))
+}
+
+fn main() {
+    let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+    let corrupted = random_phase(&mut rng);
+    println!("memtest: {} random steps, {} corrupted blocks", STEPS, corrupted);
+    let grown = growth_phase();
+    println!("memtest: grown vector of {} elements {}", GROWN_LEN, if grown { "intact" } else { "corrupted" });
+
+    match procinfo() {
+        Ok(info) => println!(
+            "memtest: rss {} KiB, peak rss {} KiB, heap {} KiB",
+            info.rss / 1024,
+            info.peak_rss / 1024,
+            info.heap / 1024
+        ),
+        Err(e) => println!("memtest: procinfo failed: {}", e),
+    }
+    println!("memtest: {}", if corrupted == 0 && grown { "PASS" } else { "FAIL" });
+}